@@ -0,0 +1,211 @@
+//! Load generator for capacity testing of policy-engine deployments.
+//!
+//! Concurrent workers send graph requests drawn from a query mix until the
+//! configured duration elapsed or number of requests has been sent, and the
+//! latency percentiles and error rates of the responses are reported.
+
+#[macro_use]
+extern crate serde;
+
+mod mix;
+mod report;
+
+use crate::mix::{QueryMix, Sampler};
+use crate::report::{Outcome, Report, Samples};
+use cincinnati_client::{Client, Error, Fetched, RetryPolicy};
+use commons::prelude_errors::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+/// Default policy-engine graph endpoint.
+static DEFAULT_UPSTREAM: &str = "http://localhost:8081/api/upgrades_info/v1/graph";
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Generate graph request load and report latencies and error rates")]
+struct Options {
+    /// URL of the graph endpoint
+    #[structopt(long = "upstream", default_value = DEFAULT_UPSTREAM)]
+    upstream: String,
+
+    /// TOML file describing the query mix
+    #[structopt(long = "mix", parse(from_os_str))]
+    mix: Option<PathBuf>,
+
+    /// Number of concurrent clients
+    #[structopt(short = "c", long = "concurrency", default_value = "10")]
+    concurrency: u64,
+
+    /// Duration of the run in seconds
+    #[structopt(short = "d", long = "duration", default_value = "30")]
+    duration: u64,
+
+    /// Stop after sending this many requests
+    #[structopt(short = "n", long = "requests")]
+    requests: Option<u64>,
+
+    /// Request timeout in seconds
+    #[structopt(long = "timeout", default_value = "10")]
+    timeout: u64,
+
+    /// Seed for drawing queries, for reproducible runs
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+
+    /// Fail if the error rate exceeds this fraction
+    #[structopt(long = "max-error-rate")]
+    max_error_rate: Option<f64>,
+
+    /// Fail if the 99th percentile latency exceeds this many milliseconds
+    #[structopt(long = "max-p99-ms")]
+    max_p99_ms: Option<f64>,
+
+    /// Print the report as JSON
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+/// Entity tags of previous responses, by query parameters.
+type Etags = Arc<Mutex<HashMap<Vec<(String, String)>, String>>>;
+
+fn main() -> Fallible<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let options = Options::from_args();
+    ensure!(options.concurrency > 0, "concurrency must be positive");
+
+    let mix = match &options.mix {
+        Some(path) => QueryMix::read(path)?,
+        None => QueryMix::default(),
+    };
+    let sampler = mix.sampler()?;
+
+    let runtime = tokio::runtime::Runtime::new().context("building runtime")?;
+    let report = runtime.block_on(run(&options, sampler))?;
+
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{}", report);
+    }
+
+    if let Some(max_error_rate) = options.max_error_rate {
+        ensure!(
+            report.error_rate <= max_error_rate,
+            "error rate {:.4} exceeds {}",
+            report.error_rate,
+            max_error_rate
+        );
+    }
+    if let Some(max_p99_ms) = options.max_p99_ms {
+        ensure!(
+            report.latency_ms.p99 <= max_p99_ms,
+            "p99 latency {:.1}ms exceeds {}ms",
+            report.latency_ms.p99,
+            max_p99_ms
+        );
+    }
+    Ok(())
+}
+
+async fn run(options: &Options, sampler: Sampler) -> Fallible<Report> {
+    let client = Client::try_new(Duration::from_secs(options.timeout))?
+        .with_retry_policy(RetryPolicy::none());
+    let upstream = Arc::new(options.upstream.clone());
+    let etags = Etags::default();
+    let remaining = Arc::new(AtomicU64::new(options.requests.unwrap_or(u64::MAX)));
+
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(options.duration);
+    let workers: Vec<_> = (0..options.concurrency)
+        .map(|i| {
+            let rng = match options.seed {
+                Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(i)),
+                None => StdRng::from_entropy(),
+            };
+            tokio::spawn(worker(
+                client.clone(),
+                upstream.clone(),
+                sampler.clone(),
+                rng,
+                etags.clone(),
+                remaining.clone(),
+                deadline,
+            ))
+        })
+        .collect();
+
+    let mut samples = Samples::default();
+    for worker in workers {
+        samples.merge(worker.await.context("joining worker")?);
+    }
+    Ok(samples.report(start.elapsed()))
+}
+
+async fn worker(
+    client: Client,
+    upstream: Arc<String>,
+    sampler: Sampler,
+    mut rng: StdRng,
+    etags: Etags,
+    remaining: Arc<AtomicU64>,
+    deadline: Instant,
+) -> Samples {
+    let mut samples = Samples::default();
+
+    while Instant::now() < deadline
+        && remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+    {
+        let (query, conditional) = sampler.sample(&mut rng);
+        let key: Vec<(String, String)> = query
+            .pairs()
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let etag = if conditional {
+            etags.lock().ok().and_then(|cache| cache.get(&key).cloned())
+        } else {
+            None
+        };
+
+        let sent = Instant::now();
+        let result = client
+            .fetch_graph_if_changed::<serde::de::IgnoredAny>(&upstream, &query, etag.as_deref())
+            .await;
+        let latency = sent.elapsed();
+
+        let outcome = match result {
+            Ok(Fetched::Modified { etag, .. }) => {
+                if let (Some(etag), Ok(mut cache)) = (etag, etags.lock()) {
+                    cache.insert(key, etag);
+                }
+                Outcome::Ok
+            }
+            Ok(Fetched::NotModified) => Outcome::NotModified,
+            Err(e) => {
+                log::debug!("request to {} failed: {}", upstream, e);
+                Outcome::Error(error_kind(&e))
+            }
+        };
+        samples.record(outcome, latency);
+    }
+
+    samples
+}
+
+/// Classify errors for the report.
+fn error_kind(error: &Error) -> String {
+    match error {
+        Error::Status(status) => format!("status {}", status.as_u16()),
+        Error::Request(e) if e.is_timeout() => "timeout".to_string(),
+        Error::Request(_) => "request failed".to_string(),
+        Error::Json(_) => "invalid graph".to_string(),
+        Error::InvalidUrl(..) | Error::InvalidEtag(_) => "invalid request".to_string(),
+    }
+}
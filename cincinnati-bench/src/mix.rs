@@ -0,0 +1,198 @@
+//! Query mixes, describing the distribution of generated graph requests.
+
+use cincinnati_client::GraphQuery;
+use commons::prelude_errors::*;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use std::path::Path;
+
+/// A parameter value, drawn with probability proportional to its weight.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Weighted {
+    /// Parameter value.
+    pub value: String,
+    /// Relative weight of the value.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// Distribution of the generated graph requests.
+///
+/// An empty list of values means the corresponding parameter is not sent.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryMix {
+    /// Channels to query.
+    pub channels: Vec<Weighted>,
+    /// Architectures to query.
+    pub arches: Vec<Weighted>,
+    /// Cluster versions to report.
+    pub versions: Vec<Weighted>,
+    /// Fraction of requests sent with the entity tag of a previous response,
+    /// as clusters polling for updates do.
+    pub conditional_ratio: f64,
+}
+
+impl Default for QueryMix {
+    fn default() -> Self {
+        Self {
+            channels: vec![Weighted {
+                value: "stable-4.14".to_string(),
+                weight: 1,
+            }],
+            arches: vec![Weighted {
+                value: "amd64".to_string(),
+                weight: 1,
+            }],
+            versions: vec![],
+            conditional_ratio: 0.0,
+        }
+    }
+}
+
+impl QueryMix {
+    /// Read a query mix from a TOML file.
+    pub fn read(path: &Path) -> Fallible<Self> {
+        let content =
+            std::fs::read_to_string(path).context(format!("reading {}", path.display()))?;
+        let mix: Self = toml::from_str(&content).context(format!("parsing {}", path.display()))?;
+        mix.try_validate()?;
+        Ok(mix)
+    }
+
+    /// Validate the mix.
+    pub fn try_validate(&self) -> Fallible<()> {
+        ensure!(
+            (0.0..=1.0).contains(&self.conditional_ratio),
+            "conditional_ratio must be between 0 and 1, got {}",
+            self.conditional_ratio
+        );
+        for (name, values) in &[
+            ("channels", &self.channels),
+            ("arches", &self.arches),
+            ("versions", &self.versions),
+        ] {
+            ensure!(
+                values.is_empty() || values.iter().any(|v| v.weight > 0),
+                "all weights of {} are zero",
+                name
+            );
+        }
+        Ok(())
+    }
+
+    /// Prepare the mix for sampling.
+    pub fn sampler(&self) -> Fallible<Sampler> {
+        self.try_validate()?;
+        Ok(Sampler {
+            channels: ParamSampler::try_new(&self.channels)?,
+            arches: ParamSampler::try_new(&self.arches)?,
+            versions: ParamSampler::try_new(&self.versions)?,
+            conditional_ratio: self.conditional_ratio,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ParamSampler {
+    values: Vec<String>,
+    index: Option<WeightedIndex<u32>>,
+}
+
+impl ParamSampler {
+    fn try_new(values: &[Weighted]) -> Fallible<Self> {
+        let index = if values.is_empty() {
+            None
+        } else {
+            Some(WeightedIndex::new(values.iter().map(|v| v.weight))?)
+        };
+        Ok(Self {
+            values: values.iter().map(|v| v.value.clone()).collect(),
+            index,
+        })
+    }
+
+    fn sample<R: Rng>(&self, rng: &mut R) -> Option<String> {
+        self.index
+            .as_ref()
+            .map(|index| self.values[index.sample(rng)].clone())
+    }
+}
+
+/// Draws queries from a [`QueryMix`].
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    channels: ParamSampler,
+    arches: ParamSampler,
+    versions: ParamSampler,
+    conditional_ratio: f64,
+}
+
+impl Sampler {
+    /// Draw a query, and whether it should be sent conditionally.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> (GraphQuery, bool) {
+        let query = GraphQuery {
+            channel: self.channels.sample(rng),
+            arch: self.arches.sample(rng),
+            version: self.versions.sample(rng),
+            ..Default::default()
+        };
+        (query, rng.gen_bool(self.conditional_ratio))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn parse_and_sample_mix() -> Fallible<()> {
+        let mix: QueryMix = toml::from_str(
+            r#"
+                conditional_ratio = 1.0
+                channels = [
+                    { value = "stable-4.14", weight = 3 },
+                    { value = "fast-4.14", weight = 0 },
+                ]
+                versions = [{ value = "4.14.1" }]
+            "#,
+        )?;
+        assert!(mix.arches.is_empty());
+        assert_eq!(mix.versions[0].weight, 1);
+
+        let sampler = mix.sampler()?;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let (query, conditional) = sampler.sample(&mut rng);
+            assert_eq!(query.channel.as_deref(), Some("stable-4.14"));
+            assert_eq!(query.arch, None);
+            assert_eq!(query.version.as_deref(), Some("4.14.1"));
+            assert!(conditional);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_mix() {
+        let mix = QueryMix {
+            conditional_ratio: 1.5,
+            ..Default::default()
+        };
+        assert!(mix.try_validate().is_err());
+
+        let mix = QueryMix {
+            arches: vec![Weighted {
+                value: "amd64".to_string(),
+                weight: 0,
+            }],
+            ..Default::default()
+        };
+        assert!(mix.sampler().is_err());
+    }
+}
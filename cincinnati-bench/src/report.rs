@@ -0,0 +1,209 @@
+//! Aggregation of request outcomes into latency and error statistics.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Outcome of a single request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// A graph was returned.
+    Ok,
+    /// The graph did not change since the sent entity tag.
+    NotModified,
+    /// The request failed, with the given kind of error.
+    Error(String),
+}
+
+/// Samples collected while running the benchmark.
+#[derive(Debug, Default, Clone)]
+pub struct Samples {
+    latencies: Vec<Duration>,
+    not_modified: u64,
+    errors: BTreeMap<String, u64>,
+}
+
+impl Samples {
+    /// Record the outcome and latency of a request.
+    pub fn record(&mut self, outcome: Outcome, latency: Duration) {
+        match outcome {
+            Outcome::Ok => {}
+            Outcome::NotModified => self.not_modified += 1,
+            Outcome::Error(kind) => *self.errors.entry(kind).or_default() += 1,
+        }
+        self.latencies.push(latency);
+    }
+
+    /// Merge samples collected by another worker.
+    pub fn merge(&mut self, other: Samples) {
+        self.latencies.extend(other.latencies);
+        self.not_modified += other.not_modified;
+        for (kind, count) in other.errors {
+            *self.errors.entry(kind).or_default() += count;
+        }
+    }
+
+    /// Summarize the samples, collected over `elapsed`.
+    pub fn report(mut self, elapsed: Duration) -> Report {
+        self.latencies.sort();
+        let requests = self.latencies.len() as u64;
+        let errors: u64 = self.errors.values().sum();
+        let ratio = |count: u64| {
+            if requests == 0 {
+                0.0
+            } else {
+                count as f64 / requests as f64
+            }
+        };
+        let millis = |latency: Option<Duration>| {
+            latency.map_or(0.0, |latency| latency.as_secs_f64() * 1000.0)
+        };
+
+        Report {
+            requests,
+            elapsed_seconds: elapsed.as_secs_f64(),
+            throughput: if elapsed.as_secs_f64() > 0.0 {
+                requests as f64 / elapsed.as_secs_f64()
+            } else {
+                0.0
+            },
+            not_modified: self.not_modified,
+            errors,
+            error_rate: ratio(errors),
+            errors_by_kind: self.errors,
+            latency_ms: Latencies {
+                p50: millis(percentile(&self.latencies, 50.0)),
+                p90: millis(percentile(&self.latencies, 90.0)),
+                p95: millis(percentile(&self.latencies, 95.0)),
+                p99: millis(percentile(&self.latencies, 99.0)),
+                max: millis(self.latencies.last().cloned()),
+            },
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted latencies.
+fn percentile(sorted: &[Duration], percentile: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.max(1) - 1).cloned()
+}
+
+/// Latency percentiles, in milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Latencies {
+    /// Median latency.
+    pub p50: f64,
+    /// 90th percentile.
+    pub p90: f64,
+    /// 95th percentile.
+    pub p95: f64,
+    /// 99th percentile.
+    pub p99: f64,
+    /// Maximum latency.
+    pub max: f64,
+}
+
+/// Summary of a benchmark run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    /// Number of requests sent.
+    pub requests: u64,
+    /// Duration of the run.
+    pub elapsed_seconds: f64,
+    /// Requests per second.
+    pub throughput: f64,
+    /// Number of requests answered with `304 Not Modified`.
+    pub not_modified: u64,
+    /// Number of failed requests.
+    pub errors: u64,
+    /// Fraction of failed requests.
+    pub error_rate: f64,
+    /// Number of failed requests, by kind of error.
+    pub errors_by_kind: BTreeMap<String, u64>,
+    /// Latency percentiles, including failed requests.
+    pub latency_ms: Latencies,
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "requests:     {} in {:.1}s ({:.1} req/s)",
+            self.requests, self.elapsed_seconds, self.throughput
+        )?;
+        writeln!(f, "not modified: {}", self.not_modified)?;
+        writeln!(
+            f,
+            "errors:       {} ({:.2}%)",
+            self.errors,
+            self.error_rate * 100.0
+        )?;
+        for (kind, count) in &self.errors_by_kind {
+            writeln!(f, "  {}: {}", kind, count)?;
+        }
+        write!(
+            f,
+            "latency (ms): p50 {:.1}, p90 {:.1}, p95 {:.1}, p99 {:.1}, max {:.1}",
+            self.latency_ms.p50,
+            self.latency_ms.p90,
+            self.latency_ms.p95,
+            self.latency_ms.p99,
+            self.latency_ms.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let latencies: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&[], 50.0), None);
+        assert_eq!(percentile(&latencies, 0.0), Some(Duration::from_millis(1)));
+        assert_eq!(
+            percentile(&latencies, 50.0),
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(
+            percentile(&latencies, 99.0),
+            Some(Duration::from_millis(99))
+        );
+        assert_eq!(
+            percentile(&latencies, 100.0),
+            Some(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn summarize_samples() {
+        let mut samples = Samples::default();
+        samples.record(Outcome::Ok, Duration::from_millis(10));
+        samples.record(Outcome::NotModified, Duration::from_millis(20));
+
+        let mut other = Samples::default();
+        other.record(
+            Outcome::Error("status 503".to_string()),
+            Duration::from_millis(30),
+        );
+        other.record(
+            Outcome::Error("status 503".to_string()),
+            Duration::from_millis(40),
+        );
+        samples.merge(other);
+
+        let report = samples.report(Duration::from_secs(2));
+        assert_eq!(report.requests, 4);
+        assert_eq!(report.throughput, 2.0);
+        assert_eq!(report.not_modified, 1);
+        assert_eq!(report.errors, 2);
+        assert_eq!(report.error_rate, 0.5);
+        assert_eq!(report.errors_by_kind["status 503"], 2);
+        assert_eq!(report.latency_ms.p50, 20.0);
+        assert_eq!(report.latency_ms.max, 40.0);
+    }
+}
@@ -0,0 +1,296 @@
+//! Implementation of the subcommands, rendering their output as text or JSON.
+
+use cincinnati::GraphDiff;
+use cincinnati_client::{Graph, Release};
+use commons::prelude_errors::*;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+/// Compare versions semantically, falling back to lexical order for invalid ones.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    match (semver::Version::parse(a), semver::Version::parse(b)) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Fallible<String> {
+    serde_json::to_string_pretty(value).context("serializing output")
+}
+
+/// List the channels releases belong to, read from the metadata under `key_prefix`.
+pub fn channels(graph: &Graph, key_prefix: &str, json: bool) -> Fallible<String> {
+    let channels: BTreeSet<&str> = graph
+        .nodes
+        .iter()
+        .flat_map(|r| r.channels(key_prefix))
+        .collect();
+
+    if json {
+        return to_json(&channels);
+    }
+    Ok(channels.into_iter().collect::<Vec<_>>().join("\n"))
+}
+
+/// List releases, optionally restricted to a channel, sorted by version.
+pub fn releases(
+    graph: &Graph,
+    channel: Option<&str>,
+    key_prefix: &str,
+    json: bool,
+) -> Fallible<String> {
+    let mut releases: Vec<&Release> = graph
+        .nodes
+        .iter()
+        .filter(|r| channel.map_or(true, |c| r.channels(key_prefix).contains(&c)))
+        .collect();
+    releases.sort_by(|a, b| compare_versions(&a.version, &b.version));
+
+    if json {
+        return to_json(&releases);
+    }
+    Ok(releases
+        .iter()
+        .map(|r| format!("{}\t{}", r.version, r.payload))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NextUpdates<'a> {
+    from: &'a str,
+    updates: Vec<&'a str>,
+    conditional_updates: Vec<ConditionalUpdate<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ConditionalUpdate<'a> {
+    to: &'a str,
+    risks: Vec<&'a str>,
+}
+
+/// List the updates available from release `from`.
+pub fn next(graph: &Graph, from: &str, json: bool) -> Fallible<String> {
+    ensure!(
+        graph.release(from).is_some(),
+        "release '{}' not found in graph",
+        from
+    );
+
+    let mut updates: Vec<&str> = graph
+        .next_releases(from)
+        .into_iter()
+        .map(|r| r.version.as_str())
+        .collect();
+    updates.sort_by(|a, b| compare_versions(a, b));
+
+    let mut conditional_updates: Vec<ConditionalUpdate> = graph
+        .next_conditional_updates(from)
+        .into_iter()
+        .map(|(edge, risks)| ConditionalUpdate {
+            to: &edge.to,
+            risks: risks.iter().map(|r| r.name.as_str()).collect(),
+        })
+        .collect();
+    conditional_updates.sort_by(|a, b| compare_versions(a.to, b.to));
+
+    if json {
+        return to_json(&NextUpdates {
+            from,
+            updates,
+            conditional_updates,
+        });
+    }
+    Ok(updates
+        .iter()
+        .map(|u| u.to_string())
+        .chain(
+            conditional_updates
+                .iter()
+                .map(|u| format!("{} (conditional: {})", u.to, u.risks.join(", "))),
+        )
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// Compute the shortest update path from `from` to `to`, by version.
+fn shortest_path(graph: &Graph, from: &str, to: &str, conditional: bool) -> Option<Vec<String>> {
+    let mut successors: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (source, target) in &graph.edges {
+        if let (Some(source), Some(target)) = (graph.nodes.get(*source), graph.nodes.get(*target)) {
+            successors
+                .entry(source.version.as_str())
+                .or_default()
+                .push(target.version.as_str());
+        }
+    }
+    if conditional {
+        for edge in graph
+            .conditional_edges
+            .iter()
+            .flat_map(|ce| ce.edges.iter())
+        {
+            successors
+                .entry(edge.from.as_str())
+                .or_default()
+                .push(edge.to.as_str());
+        }
+    }
+
+    // Breadth-first search, remembering the predecessor of each visited release.
+    let mut predecessors: HashMap<&str, &str> = HashMap::new();
+    let mut queue: VecDeque<&str> = vec![from].into();
+    while let Some(current) = queue.pop_front() {
+        if current == to {
+            let mut path = vec![to.to_string()];
+            let mut node = to;
+            while let Some(&previous) = predecessors.get(node) {
+                path.push(previous.to_string());
+                node = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for &next in successors.get(current).into_iter().flatten() {
+            if next != from && !predecessors.contains_key(next) {
+                predecessors.insert(next, current);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    None
+}
+
+/// Find the shortest update path from release `from` to release `to`.
+pub fn path(
+    graph: &Graph,
+    from: &str,
+    to: &str,
+    conditional: bool,
+    json: bool,
+) -> Fallible<String> {
+    for version in &[from, to] {
+        ensure!(
+            graph.release(version).is_some(),
+            "release '{}' not found in graph",
+            version
+        );
+    }
+
+    let path = shortest_path(graph, from, to, conditional)
+        .ok_or_else(|| format_err!("no update path from '{}' to '{}'", from, to))?;
+
+    if json {
+        return to_json(&path);
+    }
+    Ok(path.join(" -> "))
+}
+
+/// Render the differences between two graphs.
+pub fn diff(diff: &GraphDiff, json: bool) -> Fallible<String> {
+    if json {
+        return to_json(diff);
+    }
+
+    let lines: Vec<String> = diff
+        .removed_releases
+        .iter()
+        .map(|r| format!("- {}", r))
+        .chain(diff.added_releases.iter().map(|r| format!("+ {}", r)))
+        .chain(
+            diff.removed_edges
+                .iter()
+                .map(|(from, to)| format!("- {} -> {}", from, to)),
+        )
+        .chain(
+            diff.added_edges
+                .iter()
+                .map(|(from, to)| format!("+ {} -> {}", from, to)),
+        )
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_graph() -> Graph {
+        serde_json::from_str(
+            r#"{
+                "nodes": [
+                    {"version": "4.14.10", "payload": "image:4.14.10", "metadata": {
+                        "io.openshift.upgrades.graph.release.channels": "fast-4.14,stable-4.14"
+                    }},
+                    {"version": "4.14.9", "payload": "image:4.14.9", "metadata": {
+                        "io.openshift.upgrades.graph.release.channels": "stable-4.14"
+                    }},
+                    {"version": "4.14.11", "payload": "image:4.14.11", "metadata": {
+                        "io.openshift.upgrades.graph.release.channels": "fast-4.14"
+                    }},
+                    {"version": "4.15.0", "payload": "image:4.15.0", "metadata": {
+                        "io.openshift.upgrades.graph.release.channels": "fast-4.15"
+                    }}
+                ],
+                "edges": [[1, 0], [0, 2]],
+                "conditionalEdges": [{
+                    "edges": [{"from": "4.14.11", "to": "4.15.0"}],
+                    "risks": [{"url": "https://example.com", "name": "Risk", "message": "", "matchingRules": []}]
+                }]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn list_channels_and_releases() -> Fallible<()> {
+        let graph = test_graph();
+
+        let key_prefix = cincinnati_client::DEFAULT_KEY_PREFIX;
+        assert_eq!(
+            channels(&graph, key_prefix, false)?,
+            "fast-4.14\nfast-4.15\nstable-4.14"
+        );
+        assert_eq!(
+            releases(&graph, Some("stable-4.14"), key_prefix, false)?,
+            "4.14.9\timage:4.14.9\n4.14.10\timage:4.14.10"
+        );
+        assert_eq!(channels(&graph, "io.okd.upgrades.graph", false)?, "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_next_updates() -> Fallible<()> {
+        let graph = test_graph();
+
+        assert_eq!(next(&graph, "4.14.10", false)?, "4.14.11");
+        assert_eq!(
+            next(&graph, "4.14.11", false)?,
+            "4.15.0 (conditional: Risk)"
+        );
+        assert!(next(&graph, "4.13.0", false).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn find_update_path() -> Fallible<()> {
+        let graph = test_graph();
+
+        assert_eq!(
+            path(&graph, "4.14.9", "4.14.11", false, false)?,
+            "4.14.9 -> 4.14.10 -> 4.14.11"
+        );
+        assert!(path(&graph, "4.14.9", "4.15.0", false, false).is_err());
+        assert_eq!(
+            path(&graph, "4.14.9", "4.15.0", true, false)?,
+            "4.14.9 -> 4.14.10 -> 4.14.11 -> 4.15.0"
+        );
+        assert_eq!(path(&graph, "4.14.9", "4.14.9", false, false)?, "4.14.9");
+
+        Ok(())
+    }
+}
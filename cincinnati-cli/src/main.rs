@@ -0,0 +1,168 @@
+//! Command-line tool to query and analyze Cincinnati update graphs.
+//!
+//! Graphs are either fetched from a `/graph` endpoint or read from a file
+//! containing a graph in its JSON representation.
+
+#[macro_use]
+extern crate serde;
+
+mod commands;
+
+use cincinnati_client::{Client, Graph, GraphQuery};
+use commons::prelude_errors::*;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Default upstream to fetch graphs from.
+static DEFAULT_UPSTREAM: &str = "https://api.openshift.com/api/upgrades_info/v1/graph";
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Query and analyze Cincinnati update graphs")]
+struct Options {
+    /// URL of the graph endpoint
+    #[structopt(long = "upstream", default_value = DEFAULT_UPSTREAM)]
+    upstream: String,
+
+    /// Read the graph from a file instead of fetching it from upstream
+    #[structopt(long = "file", parse(from_os_str))]
+    file: Option<PathBuf>,
+
+    /// Query parameter for the graph request, as `key=value` (e.g. `channel=stable-4.14`)
+    #[structopt(short = "p", long = "param", parse(try_from_str = parse_param))]
+    params: Vec<(String, String)>,
+
+    /// Request timeout in seconds
+    #[structopt(long = "timeout", default_value = "30")]
+    timeout: u64,
+
+    /// Prefix of the release metadata keys, e.g. `io.okd.upgrades.graph` for OKD
+    #[structopt(long = "key-prefix", default_value = cincinnati_client::DEFAULT_KEY_PREFIX)]
+    key_prefix: String,
+
+    /// Print results as JSON
+    #[structopt(long = "json")]
+    json: bool,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// List the channels releases belong to
+    Channels,
+
+    /// List releases, sorted by version
+    Releases {
+        /// Only list releases in this channel
+        #[structopt(long = "channel")]
+        channel: Option<String>,
+    },
+
+    /// List the updates available from a release
+    Next {
+        /// Version of the release to update from
+        #[structopt(long = "from")]
+        from: String,
+    },
+
+    /// Find the shortest update path between two releases
+    Path {
+        /// Version of the release to update from
+        #[structopt(long = "from")]
+        from: String,
+
+        /// Version of the release to update to
+        #[structopt(long = "to")]
+        to: String,
+
+        /// Also take conditional updates
+        #[structopt(long = "conditional")]
+        conditional: bool,
+    },
+
+    /// Show the differences between two graph files
+    Diff {
+        /// Graph before the changes
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+
+        /// Graph after the changes
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+    },
+}
+
+fn parse_param(param: &str) -> Fallible<(String, String)> {
+    let (key, value) = param
+        .split_once('=')
+        .ok_or_else(|| format_err!("parameter '{}' is not of the form key=value", param))?;
+    ensure!(!key.is_empty(), "empty key in parameter '{}'", param);
+    Ok((key.to_string(), value.to_string()))
+}
+
+fn main() -> Fallible<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let options = Options::from_args();
+
+    // All commands but `diff` query the graph of the options.
+    let graph = || -> Fallible<Graph> {
+        match &options.file {
+            Some(path) => read_file(path),
+            None => fetch(&options),
+        }
+    };
+
+    let output = match &options.command {
+        Command::Channels => commands::channels(&graph()?, &options.key_prefix, options.json)?,
+        Command::Releases { channel } => commands::releases(
+            &graph()?,
+            channel.as_deref(),
+            &options.key_prefix,
+            options.json,
+        )?,
+        Command::Next { from } => commands::next(&graph()?, from, options.json)?,
+        Command::Path {
+            from,
+            to,
+            conditional,
+        } => commands::path(&graph()?, from, to, *conditional, options.json)?,
+        Command::Diff { old, new } => {
+            let diff = cincinnati::GraphDiff::new(&read_file(old)?, &read_file(new)?);
+            commands::diff(&diff, options.json)?
+        }
+    };
+
+    println!("{}", output);
+    Ok(())
+}
+
+fn read_file<T: serde::de::DeserializeOwned>(path: &Path) -> Fallible<T> {
+    let file = std::fs::File::open(path).context(format!("opening {}", path.display()))?;
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .context(format!("parsing graph in {}", path.display()))
+}
+
+fn fetch(options: &Options) -> Fallible<Graph> {
+    let mut query = GraphQuery::default();
+    for (key, value) in &options.params {
+        let field = match key.as_str() {
+            "channel" => &mut query.channel,
+            "arch" => &mut query.arch,
+            "version" => &mut query.version,
+            "id" => &mut query.id,
+            _ => {
+                query.extra.insert(key.clone(), value.clone());
+                continue;
+            }
+        };
+        *field = Some(value.clone());
+    }
+
+    let client = Client::try_new(Duration::from_secs(options.timeout))?;
+    let runtime = tokio::runtime::Runtime::new().context("building runtime")?;
+    runtime
+        .block_on(client.fetch_graph(&options.upstream, &query))
+        .context(format!("fetching graph from {}", options.upstream))
+}
@@ -0,0 +1,441 @@
+//! Typed client for the Cincinnati update graph API.
+//!
+//! This is the client policy-engine uses to fetch graphs from graph-builder,
+//! and it works equally against policy-engine itself. It supports conditional
+//! requests based on the `ETag` of the previous response and retries of
+//! transient failures.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), cincinnati_client::Error> {
+//! use cincinnati_client::{Client, GraphQuery};
+//!
+//! let client = Client::try_new(std::time::Duration::from_secs(30))?;
+//! let query = GraphQuery::channel("stable-4.14").with_arch("amd64");
+//! let graph = client
+//!     .fetch_graph("https://api.openshift.com/api/upgrades_info/graph", &query)
+//!     .await?;
+//! println!("{} releases", graph.nodes.len());
+//! # Ok(())
+//! # }
+//! ```
+
+#![deny(missing_docs)]
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde;
+
+mod limits;
+mod model;
+mod query;
+mod retry;
+
+pub use crate::limits::ResponseLimits;
+pub use crate::model::*;
+pub use crate::query::GraphQuery;
+pub use crate::retry::RetryPolicy;
+
+use crate::limits::DeadlineReader;
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+/// Media type of graph responses.
+pub static CONTENT_TYPE: &str = "application/json";
+
+/// Error fetching a graph.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The upstream URL could not be parsed.
+    #[error("invalid upstream URL '{0}': {1}")]
+    InvalidUrl(String, String),
+
+    /// The request could not be sent, or the response could not be read.
+    #[error("request to upstream failed: {0}")]
+    Request(#[from] reqwest::Error),
+
+    /// The entity tag can not be sent as a header.
+    #[error("invalid entity tag '{0}'")]
+    InvalidEtag(String),
+
+    /// Upstream responded with an unsuccessful status.
+    #[error("upstream responded with status {0}")]
+    Status(StatusCode),
+
+    /// The response body is not a valid graph.
+    #[error("failed to deserialize graph: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// The response body exceeds the maximum size, in bytes.
+    #[error("response exceeds the limit of {0} bytes")]
+    TooLarge(u64),
+
+    /// Decoding the response body exceeded the maximum time.
+    #[error("decoding the graph exceeded the limit of {0:?}")]
+    DecodeTimeout(Duration),
+}
+
+impl Error {
+    /// Whether the failure may be resolved by retrying the request.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Request(_) => true,
+            Error::Status(status) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Error::InvalidUrl(..)
+            | Error::InvalidEtag(_)
+            | Error::Json(_)
+            | Error::TooLarge(_)
+            | Error::DecodeTimeout(_) => false,
+        }
+    }
+}
+
+/// Outcome of a conditional graph request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fetched<T> {
+    /// The graph changed; `etag` identifies the new version, if upstream sent one.
+    Modified {
+        /// The fetched graph.
+        graph: T,
+        /// Entity tag of the response.
+        etag: Option<String>,
+    },
+
+    /// The graph did not change since the given entity tag.
+    NotModified,
+}
+
+/// Client for `/graph` endpoints.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    retry_policy: RetryPolicy,
+    limits: ResponseLimits,
+}
+
+impl Client {
+    /// Create a client with the given request timeout and the default retry policy.
+    pub fn try_new(timeout: Duration) -> Result<Self, Error> {
+        let http = reqwest::ClientBuilder::new()
+            .gzip(true)
+            .timeout(timeout)
+            .build()?;
+        Ok(Self::from_http_client(http))
+    }
+
+    /// Create a client on top of an existing HTTP client.
+    pub fn from_http_client(http: reqwest::Client) -> Self {
+        Self {
+            http,
+            retry_policy: RetryPolicy::default(),
+            limits: ResponseLimits::default(),
+        }
+    }
+
+    /// Use the given retry policy.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Use the given limits on responses.
+    pub fn with_limits(mut self, limits: ResponseLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Fetch the graph for `query` from `upstream`.
+    pub async fn fetch_graph(&self, upstream: &str, query: &GraphQuery) -> Result<Graph, Error> {
+        self.fetch_graph_as(upstream, query, HeaderMap::new()).await
+    }
+
+    /// Fetch the graph for `query` from `upstream`, sending additional headers
+    /// and deserializing it into a custom type.
+    pub async fn fetch_graph_as<T: DeserializeOwned>(
+        &self,
+        upstream: &str,
+        query: &GraphQuery,
+        headers: HeaderMap,
+    ) -> Result<T, Error> {
+        match self.fetch(upstream, query, None, headers).await? {
+            Fetched::Modified { graph, .. } => Ok(graph),
+            Fetched::NotModified => Err(Error::Status(StatusCode::NOT_MODIFIED)),
+        }
+    }
+
+    /// Fetch the graph for `query` from `upstream`, unless it still matches `etag`.
+    pub async fn fetch_graph_if_changed<T: DeserializeOwned>(
+        &self,
+        upstream: &str,
+        query: &GraphQuery,
+        etag: Option<&str>,
+    ) -> Result<Fetched<T>, Error> {
+        self.fetch(upstream, query, etag, HeaderMap::new()).await
+    }
+
+    async fn fetch<T: DeserializeOwned>(
+        &self,
+        upstream: &str,
+        query: &GraphQuery,
+        etag: Option<&str>,
+        mut headers: HeaderMap,
+    ) -> Result<Fetched<T>, Error> {
+        let mut url = reqwest::Url::parse(upstream)
+            .map_err(|e| Error::InvalidUrl(upstream.to_string(), e.to_string()))?;
+        let pairs = query.pairs();
+        if !pairs.is_empty() {
+            url.query_pairs_mut().extend_pairs(pairs);
+        }
+
+        headers.insert(ACCEPT, HeaderValue::from_static(CONTENT_TYPE));
+        if let Some(etag) = etag {
+            let value =
+                HeaderValue::from_str(etag).map_err(|_| Error::InvalidEtag(etag.to_string()))?;
+            headers.insert(IF_NONE_MATCH, value);
+        }
+
+        let mut retry = 0;
+        loop {
+            match self.attempt(url.clone(), headers.clone()).await {
+                Err(e) if e.is_transient() && retry < self.retry_policy.max_retries => {
+                    let backoff = self.retry_policy.backoff(retry);
+                    warn!("fetching graph from {} failed, retrying: {}", url, e);
+                    tokio::time::sleep(backoff).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn attempt<T: DeserializeOwned>(
+        &self,
+        url: reqwest::Url,
+        headers: HeaderMap,
+    ) -> Result<Fetched<T>, Error> {
+        trace!("getting graph from upstream at {}", url);
+        let res = self.http.get(url).headers(headers).send().await?;
+
+        if res.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
+        if !res.status().is_success() {
+            return Err(Error::Status(res.status()));
+        }
+
+        let etag = res
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let body = self.read_body(res).await?;
+        let graph = self.decode(&body)?;
+
+        Ok(Fetched::Modified { graph, etag })
+    }
+
+    /// Read the body of `res`, failing as soon as it exceeds the maximum size.
+    async fn read_body(&self, mut res: reqwest::Response) -> Result<Vec<u8>, Error> {
+        let max_bytes = match self.limits.max_bytes {
+            Some(max_bytes) => max_bytes,
+            None => return Ok(res.bytes().await?.to_vec()),
+        };
+        if res
+            .content_length()
+            .map_or(false, |length| length > max_bytes)
+        {
+            return Err(Error::TooLarge(max_bytes));
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = res.chunk().await? {
+            if (body.len() + chunk.len()) as u64 > max_bytes {
+                return Err(Error::TooLarge(max_bytes));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+
+    /// Decode `body`, failing once the maximum decode time is exceeded.
+    fn decode<T: DeserializeOwned>(&self, body: &[u8]) -> Result<T, Error> {
+        let timeout = match self.limits.max_decode_time {
+            Some(timeout) => timeout,
+            None => return Ok(serde_json::from_slice(body)?),
+        };
+
+        let mut reader = DeadlineReader::new(body, timeout);
+        let result = serde_json::from_reader(&mut reader);
+        result.map_err(|e| {
+            if e.is_io() && reader.is_expired() {
+                Error::DecodeTimeout(timeout)
+            } else {
+                Error::Json(e)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+
+    static GRAPH_JSON: &str =
+        r#"{"version":1,"nodes":[{"version":"4.1.0","payload":"image:4.1.0"}],"edges":[]}"#;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Runtime::new().unwrap()
+    }
+
+    fn client(max_retries: u32) -> Client {
+        Client::try_new(Duration::from_secs(5))
+            .unwrap()
+            .with_retry_policy(RetryPolicy {
+                max_retries,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+            })
+    }
+
+    #[test]
+    fn fetch_graph_with_query() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/graph")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("channel".into(), "stable-4.1".into()),
+                Matcher::UrlEncoded("arch".into(), "amd64".into()),
+            ]))
+            .match_header("accept", CONTENT_TYPE)
+            .with_status(200)
+            .with_body(GRAPH_JSON)
+            .create();
+
+        let query = GraphQuery::channel("stable-4.1").with_arch("amd64");
+        let graph = runtime()
+            .block_on(client(0).fetch_graph(&format!("{}/graph", server.url()), &query))
+            .unwrap();
+
+        assert_eq!(graph.nodes[0].version, "4.1.0");
+    }
+
+    #[test]
+    fn fetch_graph_conditional() {
+        let mut server = mockito::Server::new();
+        let _modified = server
+            .mock("GET", "/graph")
+            .match_header("if-none-match", Matcher::Missing)
+            .with_status(200)
+            .with_header("etag", "\"abc\"")
+            .with_body(GRAPH_JSON)
+            .create();
+        let _not_modified = server
+            .mock("GET", "/graph")
+            .match_header("if-none-match", "\"abc\"")
+            .with_status(304)
+            .create();
+
+        let rt = runtime();
+        let client = client(0);
+        let upstream = format!("{}/graph", server.url());
+
+        let fetched: Fetched<Graph> = rt
+            .block_on(client.fetch_graph_if_changed(&upstream, &GraphQuery::default(), None))
+            .unwrap();
+        let etag = match fetched {
+            Fetched::Modified { etag, .. } => etag,
+            Fetched::NotModified => panic!("expected a graph"),
+        };
+        assert_eq!(etag.as_deref(), Some("\"abc\""));
+
+        let fetched: Fetched<Graph> = rt
+            .block_on(client.fetch_graph_if_changed(
+                &upstream,
+                &GraphQuery::default(),
+                etag.as_deref(),
+            ))
+            .unwrap();
+        assert_eq!(fetched, Fetched::NotModified);
+    }
+
+    #[test]
+    fn fetch_graph_retries_server_errors() {
+        let mut server = mockito::Server::new();
+        let failing = server
+            .mock("GET", "/graph")
+            .with_status(503)
+            .expect(3)
+            .create();
+
+        let result = runtime().block_on(
+            client(2).fetch_graph(&format!("{}/graph", server.url()), &GraphQuery::default()),
+        );
+
+        assert!(matches!(
+            result,
+            Err(Error::Status(StatusCode::SERVICE_UNAVAILABLE))
+        ));
+        failing.assert();
+    }
+
+    #[test]
+    fn fetch_graph_does_not_retry_client_errors() {
+        let mut server = mockito::Server::new();
+        let failing = server
+            .mock("GET", "/graph")
+            .with_status(404)
+            .expect(1)
+            .create();
+
+        let result = runtime().block_on(
+            client(2).fetch_graph(&format!("{}/graph", server.url()), &GraphQuery::default()),
+        );
+
+        assert!(matches!(result, Err(Error::Status(StatusCode::NOT_FOUND))));
+        failing.assert();
+    }
+
+    #[test]
+    fn fetch_graph_within_limits() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/graph")
+            .with_status(200)
+            .with_body(GRAPH_JSON)
+            .expect_at_least(1)
+            .create();
+        let upstream = format!("{}/graph", server.url());
+
+        let fetch = |limits: ResponseLimits| {
+            runtime().block_on(
+                client(2)
+                    .with_limits(limits)
+                    .fetch_graph(&upstream, &GraphQuery::default()),
+            )
+        };
+
+        let graph = fetch(ResponseLimits {
+            max_bytes: Some(GRAPH_JSON.len() as u64),
+            max_decode_time: Some(Duration::from_secs(5)),
+        })
+        .unwrap();
+        assert_eq!(graph.nodes.len(), 1);
+
+        let result = fetch(ResponseLimits {
+            max_bytes: Some(GRAPH_JSON.len() as u64 - 1),
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(Error::TooLarge(_))));
+
+        let result = fetch(ResponseLimits {
+            max_decode_time: Some(Duration::ZERO),
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(Error::DecodeTimeout(_))));
+    }
+}
@@ -0,0 +1,57 @@
+//! Limits on graph responses.
+
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+/// Limits on the size and decoding of graph responses, so that an
+/// unexpectedly huge or malicious response fails fast.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResponseLimits {
+    /// Maximum size of a response body, in bytes. Unlimited if unset.
+    pub max_bytes: Option<u64>,
+
+    /// Maximum time spent decoding a response body. Unlimited if unset.
+    pub max_decode_time: Option<Duration>,
+}
+
+/// Number of bytes read between two checks of the decode deadline.
+const DEADLINE_CHECK_INTERVAL: u64 = 64 * 1024;
+
+/// Reader failing once its deadline is exceeded.
+pub(crate) struct DeadlineReader<R> {
+    inner: R,
+    deadline: Instant,
+    unchecked: u64,
+}
+
+impl<R: Read> DeadlineReader<R> {
+    pub(crate) fn new(inner: R, timeout: Duration) -> Self {
+        Self {
+            inner,
+            deadline: Instant::now() + timeout,
+            unchecked: DEADLINE_CHECK_INTERVAL,
+        }
+    }
+
+    /// Whether reading stopped because the deadline was exceeded.
+    pub(crate) fn is_expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+}
+
+impl<R: Read> Read for DeadlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.unchecked >= DEADLINE_CHECK_INTERVAL {
+            if self.is_expired() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "decode deadline exceeded",
+                ));
+            }
+            self.unchecked = 0;
+        }
+        let read = self.inner.read(buf)?;
+        self.unchecked += read as u64;
+        Ok(read)
+    }
+}
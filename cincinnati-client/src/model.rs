@@ -0,0 +1,225 @@
+//! Response model of the `/graph` endpoint.
+
+use std::collections::HashMap;
+
+/// Prefix of the metadata keys of OpenShift releases.
+pub static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+
+/// Return the metadata key listing the channels of a release, for the
+/// metadata keys under `key_prefix`, e.g. `io.openshift.upgrades.graph`.
+pub fn channels_key(key_prefix: &str) -> String {
+    format!("{}.release.channels", key_prefix)
+}
+
+/// An update graph, as returned by graph-builder and policy-engine.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Graph {
+    /// Version of the graph format.
+    #[serde(default)]
+    pub version: u64,
+
+    /// Releases in the graph.
+    pub nodes: Vec<Release>,
+
+    /// Unconditional update edges, as indices into `nodes`.
+    pub edges: Vec<(usize, usize)>,
+
+    /// Updates which are only recommended under certain conditions.
+    #[serde(
+        default,
+        rename = "conditionalEdges",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub conditional_edges: Vec<ConditionalEdge>,
+}
+
+/// A release in the graph.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Release {
+    /// Version of the release.
+    pub version: String,
+
+    /// Pullspec of the release payload.
+    pub payload: String,
+
+    /// Release metadata.
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+/// A set of conditional updates sharing the same risks.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConditionalEdge {
+    /// Updates affected by the risks.
+    pub edges: Vec<ConditionalUpdateEdge>,
+
+    /// Risks of taking the updates.
+    pub risks: Vec<ConditionalUpdateRisk>,
+}
+
+/// A conditional update, by release versions.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConditionalUpdateEdge {
+    /// Version of the source release.
+    pub from: String,
+
+    /// Version of the target release.
+    pub to: String,
+}
+
+/// A risk affecting conditional updates.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConditionalUpdateRisk {
+    /// URL to more details on the risk.
+    pub url: String,
+
+    /// Name of the risk.
+    pub name: String,
+
+    /// Human-readable description of the risk.
+    pub message: String,
+
+    /// Conditions identifying the clusters exposed to the risk.
+    #[serde(rename = "matchingRules")]
+    pub matching_rules: Vec<ClusterCondition>,
+}
+
+/// A condition identifying the clusters exposed to a risk.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClusterCondition {
+    /// Type of the condition, e.g. `PromQL` or `Always`.
+    #[serde(rename = "type")]
+    pub condition_type: String,
+
+    /// PromQL query, for conditions of type `PromQL`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub promql: Option<PromQLClusterCondition>,
+}
+
+/// A PromQL query matching exposed clusters.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PromQLClusterCondition {
+    /// The PromQL query.
+    pub promql: String,
+}
+
+impl Release {
+    /// Channels the release belongs to, listed in the metadata under `key_prefix`.
+    pub fn channels(&self, key_prefix: &str) -> Vec<&str> {
+        self.metadata
+            .get(&channels_key(key_prefix))
+            .map(|channels| {
+                channels
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|c| !c.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Graph {
+    /// Find a release by version.
+    pub fn release(&self, version: &str) -> Option<&Release> {
+        self.nodes.iter().find(|n| n.version == version)
+    }
+
+    /// Releases reachable from `version` through an unconditional update.
+    pub fn next_releases(&self, version: &str) -> Vec<&Release> {
+        let source = match self.nodes.iter().position(|n| n.version == version) {
+            Some(source) => source,
+            None => return vec![],
+        };
+
+        self.edges
+            .iter()
+            .filter(|(from, _)| *from == source)
+            .filter_map(|(_, to)| self.nodes.get(*to))
+            .collect()
+    }
+
+    /// Conditional updates from `version`, together with their risks.
+    pub fn next_conditional_updates(
+        &self,
+        version: &str,
+    ) -> Vec<(&ConditionalUpdateEdge, &[ConditionalUpdateRisk])> {
+        self.conditional_edges
+            .iter()
+            .flat_map(|ce| {
+                ce.edges
+                    .iter()
+                    .filter(move |e| e.from == version)
+                    .map(move |e| (e, ce.risks.as_slice()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static GRAPH_JSON: &str = r#"{
+        "version": 1,
+        "nodes": [
+            {"version": "4.1.0", "payload": "image:4.1.0", "metadata": {
+                "io.openshift.upgrades.graph.release.channels": "fast-4.1, stable-4.1"
+            }},
+            {"version": "4.1.1", "payload": "image:4.1.1"},
+            {"version": "4.1.2", "payload": "image:4.1.2", "metadata": {}}
+        ],
+        "edges": [[0, 1], [1, 2]],
+        "conditionalEdges": [{
+            "edges": [{"from": "4.1.0", "to": "4.1.2"}],
+            "risks": [{
+                "url": "https://example.com",
+                "name": "Risk",
+                "message": "message",
+                "matchingRules": [{"type": "Always"}]
+            }]
+        }]
+    }"#;
+
+    #[test]
+    fn deserialize_graph() {
+        let graph: Graph = serde_json::from_str(GRAPH_JSON).unwrap();
+
+        assert_eq!(graph.version, 1);
+        assert_eq!(
+            graph.nodes[0].channels(DEFAULT_KEY_PREFIX),
+            vec!["fast-4.1", "stable-4.1"]
+        );
+        assert!(graph.nodes[1].channels(DEFAULT_KEY_PREFIX).is_empty());
+        assert!(graph.nodes[0].channels("io.okd.upgrades.graph").is_empty());
+        assert_eq!(
+            graph.conditional_edges[0].risks[0].matching_rules[0].promql,
+            None
+        );
+
+        let versions: Vec<&str> = graph
+            .next_releases("4.1.0")
+            .iter()
+            .map(|r| r.version.as_str())
+            .collect();
+        assert_eq!(versions, vec!["4.1.1"]);
+        assert!(graph.next_releases("4.0.0").is_empty());
+
+        let conditional = graph.next_conditional_updates("4.1.0");
+        assert_eq!(conditional.len(), 1);
+        assert_eq!(conditional[0].0.to, "4.1.2");
+        assert_eq!(conditional[0].1[0].name, "Risk");
+    }
+
+    #[test]
+    fn roundtrip_graph() {
+        let graph: Graph = serde_json::from_str(GRAPH_JSON).unwrap();
+        let json = serde_json::to_string(&graph).unwrap();
+        assert_eq!(serde_json::from_str::<Graph>(&json).unwrap(), graph);
+    }
+}
@@ -0,0 +1,97 @@
+//! Client parameters of graph requests.
+
+use std::collections::BTreeMap;
+
+/// Query parameters of a graph request.
+///
+/// Parameters are only sent when set; graph-builder ignores all of them,
+/// while policy-engine requires at least `channel`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GraphQuery {
+    /// Channel to restrict the graph to.
+    pub channel: Option<String>,
+
+    /// Architecture of the releases.
+    pub arch: Option<String>,
+
+    /// Version the client is currently running.
+    pub version: Option<String>,
+
+    /// Unique identifier of the client.
+    pub id: Option<String>,
+
+    /// Additional parameters, for custom plugins.
+    pub extra: BTreeMap<String, String>,
+}
+
+impl GraphQuery {
+    /// Query for the given channel.
+    pub fn channel(channel: impl Into<String>) -> Self {
+        Self {
+            channel: Some(channel.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Set the architecture.
+    pub fn with_arch(mut self, arch: impl Into<String>) -> Self {
+        self.arch = Some(arch.into());
+        self
+    }
+
+    /// Set the current client version.
+    pub fn with_version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Set the client identifier.
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Set an additional parameter.
+    pub fn with_param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra.insert(key.into(), value.into());
+        self
+    }
+
+    /// All set parameters, as key-value pairs.
+    pub fn pairs(&self) -> Vec<(&str, &str)> {
+        let known = [
+            ("channel", &self.channel),
+            ("arch", &self.arch),
+            ("version", &self.version),
+            ("id", &self.id),
+        ];
+
+        known
+            .iter()
+            .filter_map(|(key, value)| value.as_deref().map(|v| (*key, v)))
+            .chain(self.extra.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_pairs() {
+        assert!(GraphQuery::default().pairs().is_empty());
+
+        let query = GraphQuery::channel("stable-4.14")
+            .with_arch("amd64")
+            .with_param("custom", "value");
+        assert_eq!(
+            query.pairs(),
+            vec![
+                ("channel", "stable-4.14"),
+                ("arch", "amd64"),
+                ("custom", "value")
+            ]
+        );
+    }
+}
@@ -0,0 +1,14 @@
+//! Deserialize arbitrary input as a graph, and check accepted graphs round-trip.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(graph) = serde_json::from_slice::<cincinnati::Graph>(data) {
+        let json = serde_json::to_vec(&graph).expect("serializing a deserialized graph");
+        let roundtrip: cincinnati::Graph =
+            serde_json::from_slice(&json).expect("deserializing a serialized graph");
+        assert_eq!(graph, roundtrip);
+    }
+});
@@ -0,0 +1,10 @@
+//! Decode arbitrary input as a release image layer carrying release metadata.
+
+#![no_main]
+
+use cincinnati::plugins::internal::graph_builder::release_scrape_dockerv2::registry::assemble_metadata;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = assemble_metadata(data, "release-manifests/release-metadata");
+});
@@ -0,0 +1,61 @@
+//! Run the secondary metadata parser on arbitrary graph-data files.
+//!
+//! The input is split on NUL bytes into a channel file, a blocked edge file
+//! and the raw metadata file of an otherwise valid graph-data directory.
+
+#![no_main]
+
+use cincinnati::plugins::internal::graph_builder::openshift_secondary_metadata_parser::plugin::{
+    BLOCKED_EDGES_DIR, CHANNELS_DIR,
+};
+use cincinnati::plugins::internal::graph_builder::openshift_secondary_metadata_parser::{
+    OpenshiftSecondaryMetadataParserPlugin, OpenshiftSecondaryMetadataParserSettings,
+};
+use cincinnati::plugins::{InternalIO, InternalPlugin};
+use commons::GRAPH_DATA_DIR_PARAM_KEY;
+use libfuzzer_sys::fuzz_target;
+
+static GRAPH: &str = r#"{
+    "nodes": [
+        {"version": "4.0.0", "payload": "image/4.0.0", "metadata": {}},
+        {"version": "4.1.0", "payload": "image/4.1.0", "metadata": {}}
+    ],
+    "edges": [[0, 1]]
+}"#;
+
+fuzz_target!(|data: &[u8]| {
+    let mut parts = data.splitn(3, |b| *b == 0);
+    let channel = parts.next().unwrap_or_default();
+    let blocked_edge = parts.next().unwrap_or_default();
+    let raw_metadata = parts.next().unwrap_or(b"{}");
+
+    let dir = tempfile::tempdir().expect("creating data directory");
+    let write = |path: &str, content: &[u8]| {
+        let path = dir.path().join(path);
+        std::fs::create_dir_all(path.parent().expect("parent")).expect("creating directory");
+        std::fs::write(path, content).expect("writing file");
+    };
+    write("version", b"1.2.0");
+    write(&format!("{}/fuzz.yaml", CHANNELS_DIR), channel);
+    write(&format!("{}/fuzz.yaml", BLOCKED_EDGES_DIR), blocked_edge);
+    write("raw/metadata.json", raw_metadata);
+
+    let plugin = OpenshiftSecondaryMetadataParserPlugin::new(
+        OpenshiftSecondaryMetadataParserSettings::default(),
+    );
+    let io = InternalIO {
+        graph: serde_json::from_str(GRAPH).expect("parsing graph"),
+        parameters: [(
+            GRAPH_DATA_DIR_PARAM_KEY.to_string(),
+            dir.path().to_string_lossy().to_string(),
+        )]
+        .iter()
+        .cloned()
+        .collect(),
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("building runtime");
+    let _ = runtime.block_on(plugin.run_internal(io));
+});
@@ -0,0 +1,229 @@
+//! Groups of channels, e.g. the stable channels of all minor versions.
+//!
+//! A channel belongs to the group set by graph-data, or else to the group
+//! derived from its name: "stable-4.14" belongs to "stable". Groups can be
+//! recorded in release metadata, one key per group listing the channels of
+//! the release in that group, so that they are served along with the graph.
+
+use crate::{Graph, MapImpl};
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Key suffix of the release metadata listing the channels of a group.
+///
+/// The full key is `<key_prefix>.release.channel_group.<group>`.
+pub static CHANNEL_GROUP_KEY_SUFFIX: &str = "release.channel_group";
+
+lazy_static! {
+    static ref CHANNEL_NAME_RE: regex::Regex =
+        regex::Regex::new(r"^(?P<group>[a-z]+(?:-[a-z]+)*)-(?P<minor>[0-9]+\.[0-9]+)$")
+            .expect("could not create regex");
+}
+
+/// Return the group of `channel` derived from its name, if it follows the
+/// `<group>-<major>.<minor>` convention.
+pub fn derive_group(channel: &str) -> Option<&str> {
+    CHANNEL_NAME_RE
+        .captures(channel)
+        .and_then(|captures| captures.name("group"))
+        .map(|group| group.as_str())
+}
+
+/// A group of channels.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ChannelGroup {
+    /// Name of the group, e.g. "stable".
+    pub name: String,
+    /// Channels of the group, sorted by version.
+    pub channels: Vec<String>,
+}
+
+/// Record the channel groups of every release in its metadata.
+///
+/// Channels are read from `<key_prefix>.release.channels`; `groups` maps
+/// channels to their group, overriding the groups derived from their names.
+pub fn set_group_metadata(graph: &mut Graph, key_prefix: &str, groups: &MapImpl<String, String>) {
+    let channels_key = crate::channels_key(key_prefix);
+    let group_key_prefix = format!("{}.{}.", key_prefix, CHANNEL_GROUP_KEY_SUFFIX);
+
+    graph.find_by_fn_mut(|release| {
+        if let Some(metadata) = release.get_metadata_mut() {
+            let mut release_groups: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+            if let Some(channels) = metadata.get(&channels_key) {
+                for channel in channels.split(',').map(str::trim) {
+                    let group = groups
+                        .get(channel)
+                        .map(String::as_str)
+                        .or_else(|| derive_group(channel));
+                    if let Some(group) = group {
+                        release_groups.entry(group).or_default().push(channel);
+                    }
+                }
+            }
+
+            let entries: Vec<(String, String)> = release_groups
+                .into_iter()
+                .map(|(group, channels)| {
+                    (format!("{}{}", group_key_prefix, group), channels.join(","))
+                })
+                .collect();
+            metadata.extend(entries);
+        }
+
+        // we don't care about this result
+        false
+    });
+}
+
+/// Collect the channel groups of the releases of `graph`.
+///
+/// Groups recorded in release metadata are used if present, and derived
+/// from the channel names otherwise.
+pub fn channel_groups(graph: &mut Graph, key_prefix: &str) -> Vec<ChannelGroup> {
+    let channels_key = crate::channels_key(key_prefix);
+    let group_key_prefix = format!("{}.{}.", key_prefix, CHANNEL_GROUP_KEY_SUFFIX);
+    let mut groups: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+
+    graph.find_by_fn_mut(|release| {
+        if let Some(metadata) = release.get_metadata_mut() {
+            let mut recorded = false;
+            for (key, channels) in metadata.iter() {
+                if let Some(group) = key.strip_prefix(&group_key_prefix) {
+                    recorded = true;
+                    groups
+                        .entry(group.to_string())
+                        .or_default()
+                        .extend(channels.split(',').map(|c| c.trim().to_string()));
+                }
+            }
+
+            if !recorded {
+                if let Some(channels) = metadata.get(&channels_key) {
+                    for channel in channels.split(',').map(str::trim) {
+                        if let Some(group) = derive_group(channel) {
+                            groups
+                                .entry(group.to_string())
+                                .or_default()
+                                .insert(channel.to_string());
+                        }
+                    }
+                }
+            }
+        }
+
+        // we don't care about this result
+        false
+    });
+
+    groups
+        .into_iter()
+        .map(|(name, channels)| {
+            let mut channels: Vec<String> = channels.into_iter().collect();
+            channels.sort_by_key(|channel| channel_sort_key(channel));
+            ChannelGroup { name, channels }
+        })
+        .collect()
+}
+
+/// Sort channels by their numeric version, then by name.
+fn channel_sort_key(channel: &str) -> (Vec<u64>, String) {
+    let version = CHANNEL_NAME_RE
+        .captures(channel)
+        .and_then(|captures| captures.name("minor"))
+        .map(|minor| {
+            minor
+                .as_str()
+                .split('.')
+                .filter_map(|n| n.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    (version, channel.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::generate_custom_graph;
+
+    #[test]
+    fn derive_channel_groups() {
+        assert_eq!(derive_group("stable-4.14"), Some("stable"));
+        assert_eq!(derive_group("eus-4.12"), Some("eus"));
+        assert_eq!(derive_group("fast-stable-4.2"), Some("fast-stable"));
+        assert_eq!(derive_group("stable"), None);
+        assert_eq!(derive_group("stable-4"), None);
+    }
+
+    #[test]
+    fn derived_channel_groups() {
+        let channels_key = "io.openshift.upgrades.graph.release.channels".to_string();
+        let metadata = vec![(
+            0,
+            [(channels_key, "candidate-4.2,stable-4.1".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+        )];
+        let mut graph = generate_custom_graph("image", metadata, None);
+
+        let names: Vec<String> = channel_groups(&mut graph, "io.openshift.upgrades.graph")
+            .into_iter()
+            .map(|group| group.name)
+            .collect();
+        assert_eq!(names, vec!["candidate", "stable"]);
+    }
+
+    #[test]
+    fn group_metadata_roundtrip() {
+        let key_prefix = "io.openshift.upgrades.graph";
+        let channels_key = crate::channels_key(key_prefix);
+        let metadata = ["stable-4.9,fast-4.9", "stable-4.10,eus-4.10,custom"]
+            .iter()
+            .enumerate()
+            .map(|(i, channels)| {
+                (
+                    i,
+                    [(channels_key.clone(), channels.to_string())]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                )
+            })
+            .collect();
+        let mut graph = generate_custom_graph("image", metadata, None);
+
+        let overrides = [("eus-4.10".to_string(), "stable".to_string())]
+            .iter()
+            .cloned()
+            .collect();
+        set_group_metadata(&mut graph, key_prefix, &overrides);
+
+        let release = graph.find_by_version("1.0.0").unwrap();
+        let metadata = graph.get_metadata_as_ref_mut(&release).unwrap();
+        assert_eq!(
+            metadata
+                .get("io.openshift.upgrades.graph.release.channel_group.stable")
+                .map(String::as_str),
+            Some("stable-4.10,eus-4.10")
+        );
+
+        assert_eq!(
+            channel_groups(&mut graph, key_prefix),
+            vec![
+                ChannelGroup {
+                    name: "fast".to_string(),
+                    channels: vec!["fast-4.9".to_string()],
+                },
+                ChannelGroup {
+                    name: "stable".to_string(),
+                    channels: vec![
+                        "stable-4.9".to_string(),
+                        "eus-4.10".to_string(),
+                        "stable-4.10".to_string(),
+                    ],
+                },
+            ]
+        );
+    }
+}
@@ -1,17 +1,22 @@
+use chrono::{DateTime, Utc};
+use commons::prelude_errors::*;
 use smart_default::SmartDefault;
 
 /// ConditionalEdge stores the conditional edges
-#[derive(Debug, Serialize, Deserialize, SmartDefault, Clone)]
+#[derive(Debug, Serialize, Deserialize, SmartDefault, schemars::JsonSchema, Clone)]
 #[serde(default)]
 pub struct ConditionalEdge {
     #[serde(skip_serializing)]
+    #[schemars(skip)]
     pub edge_regex: ConditionalUpdateEdge,
     pub edges: Vec<ConditionalUpdateEdge>,
     pub risks: Vec<ConditionalUpdateRisk>,
 }
 
 /// Stores an instance of the Edge
-#[derive(Debug, Serialize, Deserialize, SmartDefault, Clone, Eq, PartialEq, Hash)]
+#[derive(
+    Debug, Serialize, Deserialize, SmartDefault, schemars::JsonSchema, Clone, Eq, PartialEq, Hash,
+)]
 #[serde(default)]
 pub struct ConditionalUpdateEdge {
     pub from: String,
@@ -19,7 +24,9 @@ pub struct ConditionalUpdateEdge {
 }
 
 /// Stores the Risk and its matching rules
-#[derive(Debug, Serialize, Deserialize, SmartDefault, Clone, Eq, PartialEq, Hash)]
+#[derive(
+    Debug, Serialize, Deserialize, SmartDefault, schemars::JsonSchema, Clone, Eq, PartialEq, Hash,
+)]
 #[serde(default)]
 pub struct ConditionalUpdateRisk {
     pub url: String,
@@ -27,10 +34,32 @@ pub struct ConditionalUpdateRisk {
     pub message: String,
     #[serde(rename = "matchingRules")]
     pub matching_rules: Vec<ClusterCondition>,
+    /// When the risk applies, always if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<ActivationSchedule>,
+}
+
+/// Time window in which a blocked edge or a risk applies.
+///
+/// Both bounds are RFC 3339 timestamps, e.g. `2024-05-01T12:00:00Z`; the
+/// window starts at `from`, included, and ends at `until`, excluded.
+#[derive(
+    Debug, Serialize, Deserialize, Default, schemars::JsonSchema, Clone, Eq, PartialEq, Hash,
+)]
+#[serde(deny_unknown_fields)]
+pub struct ActivationSchedule {
+    /// Start of the window, unbounded if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// End of the window, unbounded if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<String>,
 }
 
 /// ClusterCondition has the Type and PromQL query used to identify the blocked clusters
-#[derive(Debug, Serialize, Deserialize, SmartDefault, Clone, Eq, PartialEq, Hash)]
+#[derive(
+    Debug, Serialize, Deserialize, SmartDefault, schemars::JsonSchema, Clone, Eq, PartialEq, Hash,
+)]
 #[serde(default)]
 pub struct ClusterCondition {
     #[serde(rename = "type")]
@@ -40,7 +69,9 @@ pub struct ClusterCondition {
 }
 
 /// Contains the PromQL string
-#[derive(Debug, Serialize, Deserialize, SmartDefault, Clone, Eq, PartialEq, Hash)]
+#[derive(
+    Debug, Serialize, Deserialize, SmartDefault, schemars::JsonSchema, Clone, Eq, PartialEq, Hash,
+)]
 #[serde(default)]
 pub struct PromQLClusterCondition {
     pub promql: String,
@@ -53,6 +84,55 @@ impl ConditionalEdge {
     }
 }
 
+impl ActivationSchedule {
+    fn parse_bound(bound: &Option<String>) -> Fallible<Option<DateTime<Utc>>> {
+        bound
+            .as_ref()
+            .map(|timestamp| {
+                DateTime::parse_from_rfc3339(timestamp)
+                    .map(|timestamp| timestamp.with_timezone(&Utc))
+                    .context(format!("invalid RFC 3339 timestamp '{}'", timestamp))
+            })
+            .transpose()
+    }
+
+    /// Parse the bounds of the window.
+    pub fn bounds(&self) -> Fallible<(Option<DateTime<Utc>>, Option<DateTime<Utc>>)> {
+        let from = Self::parse_bound(&self.from)?;
+        let until = Self::parse_bound(&self.until)?;
+        if let (Some(from), Some(until)) = (from, until) {
+            ensure!(
+                from < until,
+                "schedule ends at {} before it starts at {}",
+                until,
+                from
+            );
+        }
+        Ok((from, until))
+    }
+
+    /// Check that the bounds are valid timestamps, in order.
+    pub fn validate(&self) -> Fallible<()> {
+        self.bounds().map(|_| ())
+    }
+
+    /// Whether the window contains `now`.
+    ///
+    /// Invalid schedules are always active, so that a malformed schedule
+    /// never lifts a block.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        match self.bounds() {
+            Ok((from, until)) => {
+                from.map_or(true, |from| from <= now) && until.map_or(true, |until| now < until)
+            }
+            Err(e) => {
+                log::warn!("considering invalid schedule active: {:#}", e);
+                true
+            }
+        }
+    }
+}
+
 impl PromQLClusterCondition {
     /// returns true if there is no PromQL condition to serialize.
     pub fn is_empty(&self) -> bool {
@@ -0,0 +1,223 @@
+//! Computation of differences between two graphs.
+
+use crate::Graph;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Differences between two graphs, expressed by release versions.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GraphDiff {
+    /// Versions of the releases which are only present in the new graph.
+    pub added_releases: Vec<String>,
+    /// Versions of the releases which are only present in the old graph.
+    pub removed_releases: Vec<String>,
+    /// Edges (as `(from, to)` versions) which are only present in the new graph.
+    pub added_edges: Vec<(String, String)>,
+    /// Edges (as `(from, to)` versions) which are only present in the old graph.
+    pub removed_edges: Vec<(String, String)>,
+}
+
+impl GraphDiff {
+    /// Compute the differences going from `old` to `new`.
+    pub fn new(old: &Graph, new: &Graph) -> Self {
+        let old_releases = release_versions(old);
+        let new_releases = release_versions(new);
+        let old_edges = edge_versions(old);
+        let new_edges = edge_versions(new);
+
+        GraphDiff {
+            added_releases: new_releases.difference(&old_releases).cloned().collect(),
+            removed_releases: old_releases.difference(&new_releases).cloned().collect(),
+            added_edges: new_edges.difference(&old_edges).cloned().collect(),
+            removed_edges: old_edges.difference(&new_edges).cloned().collect(),
+        }
+    }
+
+    /// Return true if both graphs have the same releases and edges.
+    pub fn is_empty(&self) -> bool {
+        self.added_releases.is_empty()
+            && self.removed_releases.is_empty()
+            && self.added_edges.is_empty()
+            && self.removed_edges.is_empty()
+    }
+}
+
+/// Blocked edge, i.e. conditional edge, between two versions.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BlockedEdge {
+    /// Version of the origin release.
+    pub from: String,
+    /// Version of the target release.
+    pub to: String,
+    /// Names of the risks blocking the edge, sorted.
+    pub risks: Vec<String>,
+}
+
+/// Differences between the blocked edges of two graphs.
+///
+/// An edge whose risks changed is both removed and added.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BlockedEdgesDiff {
+    /// Blocked edges which are only present in the new graph.
+    pub added_blocked_edges: Vec<BlockedEdge>,
+    /// Blocked edges which are only present in the old graph.
+    pub removed_blocked_edges: Vec<BlockedEdge>,
+}
+
+impl BlockedEdgesDiff {
+    /// Compute the differences going from `old` to `new`.
+    pub fn new(old: &Graph, new: &Graph) -> Self {
+        let old_edges = blocked_edges(old);
+        let new_edges = blocked_edges(new);
+
+        BlockedEdgesDiff {
+            added_blocked_edges: new_edges.difference(&old_edges).cloned().collect(),
+            removed_blocked_edges: old_edges.difference(&new_edges).cloned().collect(),
+        }
+    }
+
+    /// Return true if both graphs have the same blocked edges.
+    pub fn is_empty(&self) -> bool {
+        self.added_blocked_edges.is_empty() && self.removed_blocked_edges.is_empty()
+    }
+}
+
+fn release_versions(graph: &Graph) -> BTreeSet<String> {
+    graph
+        .dag
+        .raw_nodes()
+        .iter()
+        .map(|node| node.weight.version().to_string())
+        .collect()
+}
+
+fn edge_versions(graph: &Graph) -> BTreeSet<(String, String)> {
+    graph
+        .dag
+        .raw_edges()
+        .iter()
+        .filter_map(|edge| {
+            let source = graph.dag.node_weight(edge.source())?;
+            let target = graph.dag.node_weight(edge.target())?;
+            Some((source.version().to_string(), target.version().to_string()))
+        })
+        .collect()
+}
+
+fn blocked_edges(graph: &Graph) -> BTreeSet<BlockedEdge> {
+    let mut risks: BTreeMap<(&str, &str), BTreeSet<&str>> = BTreeMap::new();
+    for conditional_edge in graph.conditional_edges.iter().flatten() {
+        for edge in &conditional_edge.edges {
+            risks
+                .entry((edge.from.as_str(), edge.to.as_str()))
+                .or_default()
+                .extend(conditional_edge.risks.iter().map(|risk| risk.name.as_str()));
+        }
+    }
+
+    risks
+        .into_iter()
+        .map(|((from, to), risks)| BlockedEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+            risks: risks.into_iter().map(str::to_string).collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::generate_custom_graph;
+    use crate::{ConditionalEdge, ConditionalUpdateEdge, ConditionalUpdateRisk};
+
+    #[test]
+    fn diff_identical_graphs() {
+        let graph = generate_custom_graph(
+            "image",
+            (0..3).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (1, 2)]),
+        );
+
+        assert!(GraphDiff::new(&graph, &graph).is_empty());
+    }
+
+    #[test]
+    fn diff_added_and_removed() {
+        let old = generate_custom_graph(
+            "image",
+            (0..3).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (1, 2)]),
+        );
+        let new = generate_custom_graph(
+            "image",
+            (1..4).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (0, 2)]),
+        );
+
+        let diff = GraphDiff::new(&old, &new);
+
+        assert_eq!(diff.added_releases, vec!["3.0.0".to_string()]);
+        assert_eq!(diff.removed_releases, vec!["0.0.0".to_string()]);
+        assert_eq!(
+            diff.added_edges,
+            vec![("1.0.0".to_string(), "3.0.0".to_string())]
+        );
+        assert_eq!(
+            diff.removed_edges,
+            vec![("0.0.0".to_string(), "1.0.0".to_string())]
+        );
+    }
+
+    #[test]
+    fn diff_blocked_edges() {
+        let conditional_edge = |edges: &[(&str, &str)], risk: &str| ConditionalEdge {
+            edges: edges
+                .iter()
+                .map(|(from, to)| ConditionalUpdateEdge {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                })
+                .collect(),
+            risks: vec![ConditionalUpdateRisk {
+                name: risk.to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let blocked_edge = |from: &str, to: &str, risks: &[&str]| BlockedEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+            risks: risks.iter().map(|risk| risk.to_string()).collect(),
+        };
+
+        let old = Graph {
+            conditional_edges: Some(vec![
+                conditional_edge(&[("1.0.0", "2.0.0"), ("1.0.0", "3.0.0")], "A"),
+                conditional_edge(&[("1.0.0", "3.0.0")], "B"),
+            ]),
+            ..Default::default()
+        };
+        let new = Graph {
+            conditional_edges: Some(vec![
+                conditional_edge(&[("1.0.0", "3.0.0")], "B"),
+                conditional_edge(&[("2.0.0", "3.0.0")], "A"),
+            ]),
+            ..Default::default()
+        };
+
+        assert!(BlockedEdgesDiff::new(&old, &old).is_empty());
+        assert_eq!(
+            BlockedEdgesDiff::new(&old, &new),
+            BlockedEdgesDiff {
+                added_blocked_edges: vec![
+                    blocked_edge("1.0.0", "3.0.0", &["B"]),
+                    blocked_edge("2.0.0", "3.0.0", &["A"]),
+                ],
+                removed_blocked_edges: vec![
+                    blocked_edge("1.0.0", "2.0.0", &["A"]),
+                    blocked_edge("1.0.0", "3.0.0", &["A", "B"]),
+                ],
+            }
+        );
+    }
+}
@@ -17,19 +17,34 @@ extern crate serde_derive;
 
 #[macro_use]
 pub mod plugins;
+pub mod channel_groups;
 mod conditional_edges;
+mod diff;
+mod metadata_namespaces;
+mod product_profile;
+pub mod schema;
+mod version_scheme;
 
 use crate::conditional_edges::*;
+pub use crate::diff::{BlockedEdge, BlockedEdgesDiff, GraphDiff};
+pub use crate::metadata_namespaces::{is_reverse_dns, MetadataNamespaces};
+pub use crate::product_profile::{ProductProfile, OCP_PROFILE, OKD_PROFILE, OKD_SCOS_PROFILE};
+pub use crate::version_scheme::{ComponentOrdering, ReleaseVersion, VersionKey, VersionScheme};
+pub use cincinnati_client::channels_key;
 use commons::prelude_errors::*;
 use daggy::petgraph::visit::{IntoNodeReferences, NodeRef};
 use daggy::{Dag, EdgeIndex, Walker};
 use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
-use serde::ser::{Serialize, SerializeStruct, Serializer};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use smart_default::SmartDefault;
 use std::{collections, fmt};
 
 pub use daggy::{self, WouldCycle};
 
 pub const CONTENT_TYPE: &str = "application/json";
+
+/// Architecture of manifest-list payloads, which support heterogeneous clusters.
+pub const MULTI_ARCH: &str = "multi";
 const EXPECT_NODE_WEIGHT: &str = "all exisitng nodes to have a weight (release)";
 
 #[cfg(not(any(test, feature = "test")))]
@@ -49,10 +64,12 @@ pub use std::collections::BTreeSet as SetImpl;
 pub struct Graph {
     dag: Dag<Release, Empty>,
     conditional_edges: Option<Vec<ConditionalEdge>>,
+    // top-level fields of the document which are not known, passed through as they are
+    unknown_fields: serde_json::Map<String, serde_json::Value>,
 }
 
 /// Wrapper enum for the concrete and abstract release types.
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, schemars::JsonSchema)]
 #[serde(untagged)]
 pub enum Release {
     Concrete(ConcreteRelease),
@@ -104,7 +121,7 @@ impl Release {
 }
 
 /// Type to represent a Release with all its information.
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, schemars::JsonSchema)]
 pub struct ConcreteRelease {
     pub version: String,
     pub payload: String,
@@ -116,7 +133,7 @@ pub struct ConcreteRelease {
 /// It can be used for adding an edge between an existing and a non-existing
 /// release, and is expected to later be filled up with a `ConcreteRelease` once
 /// the graph is completed.
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, schemars::JsonSchema)]
 pub struct AbstractRelease {
     pub version: String,
 }
@@ -177,6 +194,17 @@ impl<'a> Iterator for PreviousReleases<'a> {
 #[derive(Debug, Clone)]
 pub struct Empty;
 
+/// Release kept when both merged graphs contain the same version.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, SmartDefault)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergePrecedence {
+    /// Keep the release of the graph being merged into.
+    #[default]
+    Existing,
+    /// Replace it with the release of the incoming graph.
+    Incoming,
+}
+
 /// Errors that can be returned by the methods in this library
 pub mod errors {
     use commons::prelude_errors::*;
@@ -208,6 +236,7 @@ impl Default for Graph {
         Graph {
             dag: Default::default(),
             conditional_edges: Some(vec![]),
+            unknown_fields: Default::default(),
         }
     }
 }
@@ -239,7 +268,7 @@ impl Graph {
                                 node.manifestref().unwrap_or(&missing_manifest_ref)
                             )
                         }
-                        if release_arch == "multi" {
+                        if release_arch == MULTI_ARCH {
                             return Ok(id);
                         }
                     }
@@ -276,6 +305,93 @@ impl Graph {
             .try_fold((), |_, (from, to)| self.add_edge(from, to).map(|_| ()))
     }
 
+    /// Merge the releases and edges of `other` into this graph.
+    ///
+    /// Releases are matched by version. If both graphs contain a concrete
+    /// release for a version, `precedence` decides which one is kept, while
+    /// abstract releases always give way to concrete ones. The edges and
+    /// conditional edges of both graphs are kept.
+    ///
+    /// Fails with the `WouldCycle` error if an edge of `other` would lead to a cycle.
+    pub fn merge(&mut self, other: Graph, precedence: MergePrecedence) -> Result<(), Error> {
+        let Graph {
+            dag,
+            conditional_edges,
+            unknown_fields,
+        } = other;
+        let (nodes, edges) = dag.into_graph().into_nodes_edges();
+
+        let mut ids = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            let release = node.weight;
+            let id = match self.find_by_version(release.version()) {
+                Some(id) => {
+                    let existing = self.dag.node_weight_mut(id.0).expect(EXPECT_NODE_WEIGHT);
+                    let replace = match (&*existing, &release) {
+                        (_, Release::Abstract(_)) => false,
+                        (Release::Abstract(_), Release::Concrete(_)) => true,
+                        (Release::Concrete(_), Release::Concrete(_)) => {
+                            precedence == MergePrecedence::Incoming
+                        }
+                    };
+                    if replace {
+                        *existing = release;
+                    }
+                    id
+                }
+                None => ReleaseId(self.dag.add_node(release)),
+            };
+            ids.push(id);
+        }
+
+        for edge in edges {
+            let (from, to) = (&ids[edge.source().index()], &ids[edge.target().index()]);
+            if self.dag.find_edge(from.0, to.0).is_none() {
+                self.dag.add_edge(from.0, to.0, Empty {})?;
+            }
+        }
+
+        if let Some(other_edges) = conditional_edges {
+            self.conditional_edges
+                .get_or_insert_with(Vec::new)
+                .extend(other_edges);
+        }
+
+        for (key, value) in unknown_fields {
+            match self.unknown_fields.entry(key) {
+                serde_json::map::Entry::Occupied(mut entry) => {
+                    if precedence == MergePrecedence::Incoming {
+                        entry.insert(value);
+                    }
+                }
+                serde_json::map::Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the top-level fields of the deserialized document which are not known.
+    ///
+    /// They are typically added by newer versions of the serving side, and are
+    /// passed through as they are when the graph is serialized.
+    pub fn unknown_fields(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.unknown_fields
+    }
+
+    /// Returns whether the conditional edges are serialized with the graph.
+    pub fn has_conditional_edges(&self) -> bool {
+        self.conditional_edges.is_some()
+    }
+
+    /// Removes the conditional edges, so that the graph is serialized without
+    /// its `conditionalEdges` field, for clients which do not support it.
+    pub fn remove_conditional_edges(&mut self) {
+        self.conditional_edges = None;
+    }
+
     /// Returns a Some(ReleaseId) if the version exists in the graph, None otherwise.
     pub fn find_by_version(&self, version: &str) -> Option<ReleaseId> {
         self.dag
@@ -452,6 +568,11 @@ impl Graph {
         self.dag.node_count() as u64
     }
 
+    /// Return the number of edges in the graph.
+    pub fn edges_count(&self) -> u64 {
+        self.dag.edge_count() as u64
+    }
+
     /// Removes the nodes with the given ReleaseIds and returns the number of
     /// removed releases.
     ///
@@ -506,6 +627,14 @@ impl Graph {
         self.dag.node_weights_mut().try_for_each(f)
     }
 
+    /// Iterates over the concrete releases
+    pub fn concrete_releases(&self) -> impl Iterator<Item = &ConcreteRelease> {
+        self.dag.node_weights().filter_map(|release| match release {
+            Release::Concrete(release) => Some(release),
+            Release::Abstract(_) => None,
+        })
+    }
+
     /// Get the edges expressed as version -> versions; optionally include edges from/to `Release::Abstract`.
     #[cfg(any(test, feature = "test"))]
     pub fn get_edges(
@@ -557,15 +686,6 @@ impl<'a> Deserialize<'a> for Graph {
     where
         D: Deserializer<'a>,
     {
-        #[derive(Deserialize)]
-        #[serde(field_identifier, rename_all = "lowercase")]
-        enum Field {
-            Edges,
-            Nodes,
-            #[serde(rename = "conditionalEdges")]
-            ConditionalEdges,
-        }
-
         struct GraphVisitor;
 
         impl<'de> Visitor<'de> for GraphVisitor {
@@ -582,26 +702,36 @@ impl<'a> Deserialize<'a> for Graph {
                 let mut edges: Option<Vec<(daggy::NodeIndex, daggy::NodeIndex)>> = None;
                 let mut nodes: Option<Vec<Release>> = None;
                 let mut conditional_edges: Option<Vec<ConditionalEdge>> = None;
-                while let Some(key) = map.next_key()? {
-                    match key {
-                        Field::Edges => {
+                let mut unknown_fields = serde_json::Map::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "edges" => {
                             if edges.is_some() {
                                 return Err(de::Error::duplicate_field("edges"));
                             }
                             edges = Some(map.next_value()?);
                         }
-                        Field::Nodes => {
+                        "nodes" => {
                             if nodes.is_some() {
                                 return Err(de::Error::duplicate_field("nodes"));
                             }
                             nodes = Some(map.next_value()?);
                         }
-                        Field::ConditionalEdges => {
+                        "conditionalEdges" => {
                             if conditional_edges.is_some() {
                                 return Err(de::Error::duplicate_field("conditionalEdges"));
                             }
                             conditional_edges = Some(map.next_value()?);
                         }
+                        // The version of the document is decided by the serving side
+                        // for each response, so that newer versions are accepted too.
+                        "version" => {
+                            map.next_value::<de::IgnoredAny>()?;
+                        }
+                        _ => {
+                            let value = map.next_value()?;
+                            unknown_fields.insert(key, value);
+                        }
                     }
                 }
                 let edges = edges.ok_or_else(|| de::Error::missing_field("edges"))?;
@@ -610,6 +740,7 @@ impl<'a> Deserialize<'a> for Graph {
                 let mut graph = Graph {
                     dag: Dag::with_capacity(nodes.len(), edges.len()),
                     conditional_edges: Some(Vec::with_capacity(conditional_edges.len())),
+                    unknown_fields,
                 };
                 let mut versions = collections::HashSet::with_capacity(nodes.len());
                 for node in nodes {
@@ -629,6 +760,17 @@ impl<'a> Deserialize<'a> for Graph {
                     }
                     graph.dag.add_node(node);
                 }
+                // Validate edges reference existing nodes, as adding them would panic otherwise.
+                let node_count = graph.dag.node_count();
+                if let Some((s, t)) = edges
+                    .iter()
+                    .find(|(s, t)| s.index() >= node_count || t.index() >= node_count)
+                {
+                    return Err(de::Error::invalid_value(
+                        de::Unexpected::Unsigned(s.index().max(t.index()) as u64),
+                        &"an index of an existing node",
+                    ));
+                }
                 graph
                     .dag
                     .add_edges(edges.into_iter().map(|(s, t)| (s, t, Empty {})))
@@ -638,18 +780,15 @@ impl<'a> Deserialize<'a> for Graph {
 
                 graph
                     .conditional_edges
-                    .as_mut()
-                    .unwrap()
+                    .get_or_insert_with(Vec::new)
                     .extend(conditional_edges);
                 Ok(graph)
             }
         }
 
-        deserializer.deserialize_struct(
-            "Graph",
-            &["nodes", "edges", "conditional_edges"],
-            GraphVisitor,
-        )
+        // Deserialized as a map rather than a struct, so that unknown fields
+        // also reach the visitor when the graph is flattened into another document.
+        deserializer.deserialize_map(GraphVisitor)
     }
 }
 
@@ -679,16 +818,42 @@ impl Serialize for Graph {
             }
         }
 
-        let mut state = serializer.serialize_struct("Graph", 2)?;
-        state.serialize_field("nodes", &Nodes(self.dag.raw_nodes()))?;
-        state.serialize_field("edges", &Edges(self.dag.raw_edges()))?;
+        let mut state = serializer.serialize_map(None)?;
+        state.serialize_entry("nodes", &Nodes(self.dag.raw_nodes()))?;
+        state.serialize_entry("edges", &Edges(self.dag.raw_edges()))?;
         if self.conditional_edges.is_some() {
-            state.serialize_field("conditionalEdges", &self.conditional_edges)?;
+            state.serialize_entry("conditionalEdges", &self.conditional_edges)?;
+        }
+        for (key, value) in &self.unknown_fields {
+            state.serialize_entry(key, value)?;
         }
         state.end()
     }
 }
 
+impl schemars::JsonSchema for Graph {
+    fn schema_name() -> String {
+        "Graph".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        /// Serialized form of a `Graph`.
+        #[derive(schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct GraphDocument {
+            /// Releases, referenced by their index in `edges`.
+            nodes: Vec<Release>,
+            /// Update edges, as pairs of source and target node indices.
+            edges: Vec<[u32; 2]>,
+            /// Conditional update edges, along with their risks.
+            #[serde(rename = "conditionalEdges", skip_serializing_if = "Option::is_none")]
+            conditional_edges: Option<Vec<ConditionalEdge>>,
+        }
+
+        GraphDocument::json_schema(gen)
+    }
+}
+
 #[cfg(any(test, feature = "test"))]
 impl PartialEq for Graph {
     fn eq(&self, other: &Graph) -> bool {
@@ -842,6 +1007,7 @@ pub mod testing {
                                 .to_string(),
                         },
                     }],
+                    schedule: None,
                 }],
             };
             if include_always_condition {
@@ -853,6 +1019,7 @@ pub mod testing {
                         condition_type: "Always".to_string(),
                         promql: Default::default(),
                     }],
+                    schedule: None,
                 }]
             }
             graph.conditional_edges = Some(vec![ce]);
@@ -1233,6 +1400,50 @@ mod tests {
         assert_eq!(ser, json);
     }
 
+    #[test]
+    fn deserialize_graph_passes_unknown_fields() -> Fallible<()> {
+        let json = r#"{"version":2,"nodes":[{"version":"1.0.0","payload":"image/1.0.0","metadata":{},"architecture":"amd64"}],"edges":[],"conditionalEdges":[],"signatures":{"1.0.0":["sig"]}}"#;
+
+        let de: Graph = serde_json::from_str(json)?;
+        assert_eq!(de.releases_count(), 1);
+        assert_eq!(
+            de.unknown_fields().keys().collect::<Vec<_>>(),
+            vec!["signatures"]
+        );
+
+        let ser = serde_json::to_string(&de)?;
+        assert_eq!(
+            ser,
+            r#"{"nodes":[{"version":"1.0.0","payload":"image/1.0.0","metadata":{}}],"edges":[],"conditionalEdges":[],"signatures":{"1.0.0":["sig"]}}"#
+        );
+
+        // Unknown fields and conditional edges also pass through a versioned document.
+        let versioned: plugins::internal::versioned_graph::VersionedGraph =
+            serde_json::from_str(json)?;
+        assert_eq!(versioned.version, 2);
+        assert_eq!(
+            serde_json::to_string(&versioned)?,
+            r#"{"version":2,"nodes":[{"version":"1.0.0","payload":"image/1.0.0","metadata":{}}],"edges":[],"conditionalEdges":[],"signatures":{"1.0.0":["sig"]}}"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_graph_rejects_invalid_edges() {
+        for edges in &["[[0,3]]", "[[7,0]]", "[[0,0]]", "[[0,1],[1,0]]"] {
+            let json = format!(
+                r#"{{"nodes":[{{"version":"1.0.0","payload":"image/1.0.0","metadata":{{}}}},{{"version":"2.0.0","payload":"image/2.0.0","metadata":{{}}}}],"edges":{}}}"#,
+                edges
+            );
+            assert!(
+                serde_json::from_str::<Graph>(&json).is_err(),
+                "edges {} were accepted",
+                edges
+            );
+        }
+    }
+
     #[test]
     fn test_graph_eq_false_for_unequal_graphs() {
         let graph1 = {
@@ -1471,6 +1682,73 @@ mod tests {
         });
     }
 
+    #[test]
+    fn merge_graphs() -> TestResult<()> {
+        let mut graph = generate_custom_graph(
+            "stable",
+            (0..3).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (1, 2)]),
+        );
+        let other = generate_custom_graph(
+            "nightly",
+            (1..5).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (1, 2), (2, 3)]),
+        );
+
+        graph.merge(other.clone(), MergePrecedence::Existing)?;
+        assert_eq!(graph.releases_count(), 5);
+
+        let payload = |graph: &Graph, version: &str| -> TestResult<String> {
+            let id = graph
+                .find_by_version(version)
+                .ok_or_else(|| format!("couldn't find version {}", version))?;
+            match graph.find_by_releaseid(&id)? {
+                Release::Concrete(release) => Ok(release.payload.clone()),
+                Release::Abstract(_) => Err("unexpected abstract release".into()),
+            }
+        };
+        assert_eq!(payload(&graph, "2.0.0")?, "stable:2.0.0");
+        assert_eq!(payload(&graph, "4.0.0")?, "nightly:4.0.0");
+
+        let edges = graph.get_edges(true)?;
+        assert_eq!(edges["1.0.0"].len(), 1);
+        assert_eq!(edges["3.0.0"].iter().collect::<Vec<_>>(), vec!["4.0.0"]);
+
+        graph.merge(other, MergePrecedence::Incoming)?;
+        assert_eq!(graph.releases_count(), 5);
+        assert_eq!(payload(&graph, "2.0.0")?, "nightly:2.0.0");
+        assert_eq!(payload(&graph, "0.0.0")?, "stable:0.0.0");
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_graphs_rejects_cycles() {
+        let mut graph = generate_custom_graph(
+            "image",
+            (0..2).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1)]),
+        );
+        let mut other = Graph::default();
+        let from = other
+            .add_release(Release::Concrete(ConcreteRelease {
+                version: "1.0.0".to_string(),
+                payload: "image/1.0.0".to_string(),
+                metadata: Default::default(),
+            }))
+            .unwrap();
+        let to = other
+            .add_release(Release::Concrete(ConcreteRelease {
+                version: "0.0.0".to_string(),
+                payload: "image/0.0.0".to_string(),
+                metadata: Default::default(),
+            }))
+            .unwrap();
+        other.add_edge(&from, &to).unwrap();
+
+        assert!(graph.merge(other, MergePrecedence::Existing).is_err());
+    }
+
     #[test]
     fn next_releases_yields_all_direct_children() -> TestResult<()> {
         use std::collections::HashSet;
@@ -0,0 +1,185 @@
+//! Namespacing of release metadata keys.
+//!
+//! Metadata keys are expected to be namespaced in reverse-DNS notation, e.g.
+//! `io.openshift.upgrades.graph.release.channels` in the
+//! `io.openshift.upgrades.graph` namespace. Keys outside of the allowed
+//! namespaces are only served if they are explicitly allowed, e.g. `url`.
+
+use crate::{Graph, Release};
+use commons::prelude_errors::*;
+
+/// Allowed namespaces of release metadata keys.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetadataNamespaces {
+    /// Reverse-DNS namespaces, e.g. `io.openshift.upgrades.graph`.
+    pub namespaces: Vec<String>,
+    /// Keys allowed outside of the namespaces, e.g. `url`.
+    pub keys: Vec<String>,
+}
+
+impl MetadataNamespaces {
+    /// Check that namespaces are in reverse-DNS notation.
+    pub fn validate(&self) -> Fallible<()> {
+        ensure!(
+            !self.namespaces.is_empty() || !self.keys.is_empty(),
+            "no metadata namespace or key allowed"
+        );
+        for namespace in &self.namespaces {
+            ensure!(
+                is_reverse_dns(namespace),
+                "metadata namespace '{}' is not in reverse-DNS notation",
+                namespace
+            );
+        }
+        for key in &self.keys {
+            ensure!(!key.is_empty(), "empty allowed metadata key");
+        }
+        Ok(())
+    }
+
+    /// Check whether `key` is allowed.
+    pub fn allows(&self, key: &str) -> bool {
+        self.keys.iter().any(|allowed| allowed == key)
+            || self.namespaces.iter().any(|namespace| {
+                key.len() > namespace.len()
+                    && key.starts_with(namespace.as_str())
+                    && key[namespace.len()..].starts_with('.')
+            })
+    }
+}
+
+/// Check whether `name` is made of at least two dot-separated DNS labels.
+pub fn is_reverse_dns(name: &str) -> bool {
+    let labels: Vec<&str> = name.split('.').collect();
+    labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        })
+}
+
+impl Graph {
+    /// Return the metadata keys which are not allowed by `namespaces`, as
+    /// `(version, key)` pairs sorted by version and key.
+    pub fn metadata_namespace_violations(
+        &self,
+        namespaces: &MetadataNamespaces,
+    ) -> Vec<(String, String)> {
+        let mut violations: Vec<(String, String)> = self
+            .dag
+            .node_weights()
+            .filter_map(|release| match release {
+                Release::Concrete(release) => Some(release),
+                Release::Abstract(_) => None,
+            })
+            .flat_map(|release| {
+                release
+                    .metadata
+                    .keys()
+                    .filter(|key| !namespaces.allows(key))
+                    .map(move |key| (release.version.clone(), key.clone()))
+            })
+            .collect();
+        violations.sort();
+        violations
+    }
+
+    /// Remove the metadata keys which are not allowed by `namespaces`,
+    /// returning them as `(version, key)` pairs sorted by version and key.
+    pub fn strip_metadata_namespace_violations(
+        &mut self,
+        namespaces: &MetadataNamespaces,
+    ) -> Vec<(String, String)> {
+        let mut stripped = vec![];
+        for release in self.dag.node_weights_mut() {
+            if let Release::Concrete(release) = release {
+                let version = &release.version;
+                release.metadata.retain(|key, _| {
+                    let allowed = namespaces.allows(key);
+                    if !allowed {
+                        stripped.push((version.clone(), key.clone()));
+                    }
+                    allowed
+                });
+            }
+        }
+        stripped.sort();
+        stripped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::generate_custom_graph;
+
+    fn namespaces() -> MetadataNamespaces {
+        MetadataNamespaces {
+            namespaces: vec!["io.openshift.upgrades.graph".to_string()],
+            keys: vec!["url".to_string()],
+        }
+    }
+
+    #[test]
+    fn allowed_keys() {
+        let namespaces = namespaces();
+        assert!(namespaces.allows("io.openshift.upgrades.graph.release.channels"));
+        assert!(namespaces.allows("url"));
+        assert!(!namespaces.allows("io.openshift.upgrades.graph"));
+        assert!(!namespaces.allows("io.openshift.upgrades.graphs.release"));
+        assert!(!namespaces.allows("com.example.ticket"));
+        assert!(!namespaces.allows("ticket"));
+    }
+
+    #[test]
+    fn validate_namespaces() {
+        namespaces().validate().unwrap();
+
+        for invalid in &["example", "io..openshift", "io.OpenShift", "io.-openshift"] {
+            let namespaces = MetadataNamespaces {
+                namespaces: vec![invalid.to_string()],
+                keys: vec![],
+            };
+            assert!(namespaces.validate().is_err(), "{}", invalid);
+        }
+        assert!(MetadataNamespaces::default().validate().is_err());
+    }
+
+    #[test]
+    fn strip_violations() {
+        let metadata = |keys: &[&str]| -> crate::MapImpl<String, String> {
+            keys.iter()
+                .map(|key| (key.to_string(), String::new()))
+                .collect()
+        };
+        let mut graph = generate_custom_graph(
+            "image",
+            vec![
+                (
+                    0,
+                    metadata(&["io.openshift.upgrades.graph.release.channels", "url"]),
+                ),
+                (1, metadata(&["com.example.ticket", "ticket"])),
+            ],
+            None,
+        );
+
+        let expected = vec![
+            ("1.0.0".to_string(), "com.example.ticket".to_string()),
+            ("1.0.0".to_string(), "ticket".to_string()),
+        ];
+        assert_eq!(graph.metadata_namespace_violations(&namespaces()), expected);
+        assert_eq!(
+            graph.strip_metadata_namespace_violations(&namespaces()),
+            expected
+        );
+        assert!(graph
+            .metadata_namespace_violations(&namespaces())
+            .is_empty());
+        assert_eq!(graph.releases_count(), 2);
+    }
+}
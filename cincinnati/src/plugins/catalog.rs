@@ -7,24 +7,34 @@ use crate as cincinnati;
 
 use self::cincinnati::plugins::BoxedPlugin;
 
+use super::internal::accepted_risks::AcceptedRisksPlugin;
 use super::internal::arch_filter::ArchFilterPlugin;
+use super::internal::channel_derive::ChannelDerivePlugin;
 use super::internal::channel_filter::ChannelFilterPlugin;
 use super::internal::cincinnati_graph_fetch::CincinnatiGraphFetchPlugin;
 use super::internal::dkrv2_openshift_secondary_metadata_scraper::{
     DkrV2OpenshiftSecondaryMetadataScraperPlugin, DkrV2OpenshiftSecondaryMetadataScraperSettings,
 };
 use super::internal::edge_add_remove::EdgeAddRemovePlugin;
+use super::internal::edge_min_origin::EdgeMinOriginPlugin;
+use super::internal::edge_schedule::EdgeSchedulePlugin;
 use super::internal::github_openshift_secondary_metadata_scraper::{
     GithubOpenshiftSecondaryMetadataScraperPlugin, GithubOpenshiftSecondaryMetadataScraperSettings,
 };
+use super::internal::metadata_enrich_http::MetadataEnrichHttpPlugin;
 use super::internal::metadata_fetch_quay::QuayMetadataFetchPlugin;
+use super::internal::metadata_namespace_validate::MetadataNamespaceValidatePlugin;
+use super::internal::metadata_redact::MetadataRedactPlugin;
 use super::internal::node_remove::NodeRemovePlugin;
 use super::internal::openshift_secondary_metadata_parser::{
     OpenshiftSecondaryMetadataParserPlugin, OpenshiftSecondaryMetadataParserSettings,
 };
+use super::internal::release_scrape::{ReleaseScrapePlugin, ReleaseScrapeSettings};
 use super::internal::release_scrape_dockerv2::{
     ReleaseScrapeDockerv2Plugin, ReleaseScrapeDockerv2Settings,
 };
+use super::internal::risk_message_template::RiskMessageTemplatePlugin;
+use super::internal::upgrade_impact::UpgradeImpactPlugin;
 use commons::prelude_errors::*;
 use std::fmt::Debug;
 
@@ -35,6 +45,14 @@ static CONFIG_PLUGIN_NAME_KEY: &str = "name";
 pub trait PluginSettings: Debug + Send {
     /// Build the corresponding plugin for this configuration.
     fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin>;
+
+    /// Check the settings, as when they are deserialized from a configuration.
+    ///
+    /// Settings built in code, e.g. for a `PluginChain`, are checked before
+    /// their plugin is built.
+    fn validate(&self) -> Fallible<()> {
+        Ok(())
+    }
 }
 
 /// Validate configuration for a plugin and fill in defaults.
@@ -48,7 +66,9 @@ pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>>
 
     match name.as_str() {
         ChannelFilterPlugin::PLUGIN_NAME => ChannelFilterPlugin::deserialize_config(cfg),
+        ChannelDerivePlugin::PLUGIN_NAME => ChannelDerivePlugin::deserialize_config(cfg),
         EdgeAddRemovePlugin::PLUGIN_NAME => EdgeAddRemovePlugin::deserialize_config(cfg),
+        EdgeMinOriginPlugin::PLUGIN_NAME => EdgeMinOriginPlugin::deserialize_config(cfg),
         NodeRemovePlugin::PLUGIN_NAME => NodeRemovePlugin::deserialize_config(cfg),
         QuayMetadataFetchPlugin::PLUGIN_NAME => QuayMetadataFetchPlugin::deserialize_config(cfg),
         CincinnatiGraphFetchPlugin::PLUGIN_NAME => {
@@ -58,6 +78,7 @@ pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>>
         ReleaseScrapeDockerv2Plugin::PLUGIN_NAME => {
             ReleaseScrapeDockerv2Settings::deserialize_config(cfg)
         }
+        ReleaseScrapePlugin::PLUGIN_NAME => ReleaseScrapeSettings::deserialize_config(cfg),
         GithubOpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME => {
             GithubOpenshiftSecondaryMetadataScraperSettings::deserialize_config(cfg)
         }
@@ -67,6 +88,17 @@ pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>>
         DkrV2OpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME => {
             DkrV2OpenshiftSecondaryMetadataScraperSettings::deserialize_config(cfg)
         }
+        RiskMessageTemplatePlugin::PLUGIN_NAME => {
+            RiskMessageTemplatePlugin::deserialize_config(cfg)
+        }
+        MetadataEnrichHttpPlugin::PLUGIN_NAME => MetadataEnrichHttpPlugin::deserialize_config(cfg),
+        MetadataNamespaceValidatePlugin::PLUGIN_NAME => {
+            MetadataNamespaceValidatePlugin::deserialize_config(cfg)
+        }
+        MetadataRedactPlugin::PLUGIN_NAME => MetadataRedactPlugin::deserialize_config(cfg),
+        AcceptedRisksPlugin::PLUGIN_NAME => AcceptedRisksPlugin::deserialize_config(cfg),
+        EdgeSchedulePlugin::PLUGIN_NAME => EdgeSchedulePlugin::deserialize_config(cfg),
+        UpgradeImpactPlugin::PLUGIN_NAME => UpgradeImpactPlugin::deserialize_config(cfg),
         x => bail!("unknown plugin '{}'", x),
     }
 }
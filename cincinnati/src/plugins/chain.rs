@@ -0,0 +1,161 @@
+//! Plugin chains built in code.
+//!
+//! Projects embedding Cincinnati as a library can assemble a chain from
+//! typed plugin settings, instead of a TOML configuration:
+//!
+//! ```
+//! # use cincinnati::plugins::prelude::*;
+//! # fn main() -> commons::Fallible<()> {
+//! let chain = PluginChain::builder()
+//!     .with(CincinnatiGraphFetchSettings::new("http://graph-builder:8080/graph"))
+//!     .with(ChannelFilterPlugin {
+//!         allowed_channels: vec!["stable-4.*".to_string()],
+//!         ..Default::default()
+//!     })
+//!     .with(ArchFilterPlugin::default())
+//!     .build(None)?;
+//! # assert_eq!(chain.plugins().len(), 3);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Typed settings are checked like deserialized ones, and both can be mixed
+//! within a chain.
+
+use super::catalog::{self, PluginSettings};
+use super::BoxedPlugin;
+use commons::prelude_errors::*;
+
+/// Sequence of plugins, processed in order.
+#[derive(Debug, Default)]
+pub struct PluginChain {
+    plugins: Vec<BoxedPlugin>,
+}
+
+impl PluginChain {
+    /// Return a builder for a chain.
+    pub fn builder() -> PluginChainBuilder {
+        PluginChainBuilder::default()
+    }
+
+    /// Return the plugins of the chain, in order.
+    pub fn plugins(&self) -> &[BoxedPlugin] {
+        &self.plugins
+    }
+
+    /// Return the plugins of the chain, in order.
+    pub fn into_plugins(self) -> Vec<BoxedPlugin> {
+        self.plugins
+    }
+
+    /// Leak the chain, for processing it with `plugins::process` and its
+    /// variants, which require plugins living as long as the process.
+    pub fn leak(self) -> &'static [BoxedPlugin] {
+        Box::leak(self.plugins.into_boxed_slice())
+    }
+}
+
+/// Builder of a `PluginChain`.
+#[derive(Debug, Default)]
+pub struct PluginChainBuilder {
+    /// Settings of the plugins, or the error deserializing them.
+    settings: Vec<Fallible<Box<dyn PluginSettings>>>,
+}
+
+impl PluginChainBuilder {
+    /// Append the plugin with typed `settings`.
+    pub fn with<S: PluginSettings + 'static>(mut self, settings: S) -> Self {
+        self.settings.push(Ok(Box::new(settings)));
+        self
+    }
+
+    /// Append the plugin configured by `cfg`, a plugin configuration as in
+    /// TOML files, naming the plugin in its `name` key.
+    pub fn with_config(mut self, cfg: toml::Value) -> Self {
+        self.settings.push(catalog::deserialize_config(cfg));
+        self
+    }
+
+    /// Check the settings and build the plugins, registering their metrics
+    /// to `registry`.
+    pub fn build(self, registry: Option<&prometheus::Registry>) -> Fallible<PluginChain> {
+        let mut plugins = Vec::with_capacity(self.settings.len());
+        for (i, settings) in self.settings.into_iter().enumerate() {
+            let plugin = settings
+                .and_then(|settings| {
+                    settings.validate()?;
+                    settings.build_plugin(registry)
+                })
+                .context(format!("building plugin #{} of the chain", i))?;
+            plugins.push(plugin);
+        }
+        Ok(PluginChain { plugins })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::prelude::*;
+    use crate::plugins::Plugin;
+
+    #[test]
+    fn build_typed_chain() -> Fallible<()> {
+        let chain = PluginChain::builder()
+            .with(ChannelFilterPlugin {
+                allowed_channels: vec!["stable-4.*".to_string()],
+                ..Default::default()
+            })
+            .with_config(toml::from_str("name = 'arch-filter'")?)
+            .build(None)?;
+
+        // Typed settings build the same plugins as their configuration.
+        let configured = catalog::build_plugins(
+            &[
+                catalog::deserialize_config(toml::from_str(
+                    "name = 'channel-filter'\nallowed_channels = ['stable-4.*']",
+                )?)?,
+                catalog::deserialize_config(toml::from_str("name = 'arch-filter'")?)?,
+            ],
+            None,
+        )?;
+        assert_eq!(chain.plugins(), configured.as_slice());
+        assert_eq!(chain.into_plugins().len(), 2);
+
+        let chain = PluginChain::builder()
+            .with(
+                CincinnatiGraphFetchSettings::new("http://graph-builder:8080/graph")
+                    .timeout(10)
+                    .serve_stale(false),
+            )
+            .build(None)?;
+        assert_eq!(
+            chain.plugins()[0].get_name(),
+            CincinnatiGraphFetchPlugin::PLUGIN_NAME
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_typed_settings() {
+        let error = PluginChain::builder()
+            .with(ArchFilterPlugin::default())
+            .with(ChannelFilterPlugin {
+                allowed_channels: vec!["regex:(".to_string()],
+                ..Default::default()
+            })
+            .build(None)
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("building plugin #1"));
+
+        assert!(PluginChain::builder()
+            .with(CincinnatiGraphFetchSettings::new(""))
+            .build(None)
+            .is_err());
+        assert!(PluginChain::builder()
+            .with_config(toml::from_str("name = 'unknown'").unwrap())
+            .build(None)
+            .is_err());
+    }
+}
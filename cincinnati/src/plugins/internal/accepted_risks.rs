@@ -0,0 +1,220 @@
+//! This plugin turns the conditional edges whose risks were all accepted by
+//! the client into regular edges.
+//!
+//! Clients list the names of the risks they accept in a query parameter,
+//! e.g. `accepted_risks=RiskA,RiskB`, as admins do when acknowledging risks
+//! on their cluster. Conditional edges with at least one risk which was not
+//! accepted are kept as they are.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use std::collections::HashSet;
+
+/// Default query parameter listing the accepted risks.
+pub static DEFAULT_PARAMETER: &str = "accepted_risks";
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct AcceptedRisksPlugin {
+    /// Query parameter listing the names of the accepted risks, comma-separated.
+    #[default(DEFAULT_PARAMETER.to_string())]
+    pub parameter: String,
+}
+
+impl PluginSettings for AcceptedRisksPlugin {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.parameter.is_empty(), "empty parameter");
+
+        Ok(())
+    }
+}
+
+impl AcceptedRisksPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "accepted-risks";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = cfg.try_into()?;
+        plugin.validate()?;
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Turn the conditional edges whose risks are all `accepted` into regular edges.
+    fn materialize(&self, graph: &mut cincinnati::Graph, accepted: &HashSet<&str>) {
+        let conditional_edges = match graph.conditional_edges.take() {
+            Some(conditional_edges) => conditional_edges,
+            None => return,
+        };
+
+        let mut kept = Vec::with_capacity(conditional_edges.len());
+        for conditional_edge in conditional_edges {
+            let all_accepted = !conditional_edge.risks.is_empty()
+                && conditional_edge
+                    .risks
+                    .iter()
+                    .all(|risk| accepted.contains(risk.name.as_str()));
+            if !all_accepted {
+                kept.push(conditional_edge);
+                continue;
+            }
+
+            for edge in &conditional_edge.edges {
+                let (from, to) = match (
+                    graph.find_by_version(&edge.from),
+                    graph.find_by_version(&edge.to),
+                ) {
+                    (Some(from), Some(to)) => (from, to),
+                    _ => continue,
+                };
+                if graph
+                    .next_releases(&from)
+                    .any(|(_, _, next)| next.version() == edge.to)
+                {
+                    continue;
+                }
+                if let Err(e) = graph.add_edge(&from, &to) {
+                    warn!(
+                        "could not add accepted edge from {} to {}: {}",
+                        edge.from, edge.to, e
+                    );
+                }
+            }
+        }
+        graph.conditional_edges = Some(kept);
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for AcceptedRisksPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        if let Some(value) = io.parameters.get(&self.parameter) {
+            let accepted: HashSet<&str> = value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .collect();
+            if !accepted.is_empty() {
+                trace!(
+                    "materializing conditional edges of accepted risks {:?}",
+                    accepted
+                );
+                self.materialize(&mut graph, &accepted);
+            }
+        }
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use cincinnati::{ConditionalEdge, ConditionalUpdateEdge, ConditionalUpdateRisk};
+    use commons::testing::init_runtime;
+    use std::collections::HashMap;
+
+    fn graph() -> cincinnati::Graph {
+        let conditional_edge = |from: &str, risks: &[&str]| ConditionalEdge {
+            edges: vec![ConditionalUpdateEdge {
+                from: from.to_string(),
+                to: "2.0.0".to_string(),
+            }],
+            risks: risks
+                .iter()
+                .map(|name| ConditionalUpdateRisk {
+                    name: name.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let mut graph = generate_custom_graph(
+            "image",
+            (0..3).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1)]),
+        );
+        graph.conditional_edges = Some(vec![
+            conditional_edge("0.0.0", &["RiskA"]),
+            conditional_edge("1.0.0", &["RiskA", "RiskB"]),
+        ]);
+        graph
+    }
+
+    fn run(accepted_risks: Option<&str>) -> Fallible<cincinnati::Graph> {
+        let runtime = init_runtime()?;
+        let parameters: HashMap<String, String> = accepted_risks
+            .map(|value| (DEFAULT_PARAMETER.to_string(), value.to_string()))
+            .into_iter()
+            .collect();
+
+        Ok(runtime
+            .block_on(AcceptedRisksPlugin::default().run_internal(InternalIO {
+                graph: graph(),
+                parameters,
+            }))?
+            .graph)
+    }
+
+    fn conditional_origins(graph: &cincinnati::Graph) -> Vec<&str> {
+        graph
+            .conditional_edges
+            .iter()
+            .flatten()
+            .flat_map(|ce| ce.edges.iter().map(|e| e.from.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn materialize_accepted_edges() -> Fallible<()> {
+        let graph = run(Some("RiskA"))?;
+        assert_eq!(
+            graph,
+            generate_custom_graph(
+                "image",
+                (0..3).map(|i| (i, Default::default())).collect(),
+                Some(vec![(0, 1), (0, 2)]),
+            )
+        );
+        assert_eq!(conditional_origins(&graph), vec!["1.0.0"]);
+
+        let graph = run(Some(" RiskB, RiskA,"))?;
+        assert_eq!(graph.edges_count(), 3);
+        assert!(conditional_origins(&graph).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn keep_unaccepted_edges() -> Fallible<()> {
+        for accepted_risks in &[None, Some(""), Some("RiskB"), Some("riska")] {
+            let graph = run(*accepted_risks)?;
+            assert_eq!(graph.edges_count(), 1, "{:?}", accepted_risks);
+            assert_eq!(
+                conditional_origins(&graph),
+                vec!["0.0.0", "1.0.0"],
+                "{:?}",
+                accepted_risks
+            );
+        }
+
+        Ok(())
+    }
+}
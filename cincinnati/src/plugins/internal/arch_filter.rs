@@ -2,14 +2,23 @@
 //! It reads the requested architecture from the parameters value at key "arch",
 //! and the value must match the regex specified at ARCH_VALIDATION_REGEX_STR
 //!
+//! The configured default architecture can be overridden per request via the
+//! `plugin.arch-filter.default_arch` parameter.
+//!
 //! The filtering also removes any architecture suffixes from the version strings
 //! if they are present. The assumption for this is that the architecture would
-//! be encoded as part of the _build_ information according to the SemVer specification.
+//! be encoded as part of the _build_ information according to the SemVer specification,
+//! i.e. after a `+`, which also holds for versions which are not semantic versions.
+//!
+//! Multi-arch payloads are served as their own graph for the `multi` architecture,
+//! which is never mixed with the graphs of the architectures they contain.
 
 use crate as cincinnati;
 
+use self::cincinnati::plugins::get_plugin_parameter;
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::ReleaseVersion;
 
 use commons::GraphError;
 use lazy_static::lazy_static;
@@ -36,6 +45,13 @@ impl PluginSettings for ArchFilterPlugin {
     fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
         Ok(new_plugin!(InternalPluginWrapper(self.clone())))
     }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.key_prefix.is_empty(), "empty arch-key prefix");
+        ensure!(!self.key_suffix.is_empty(), "empty arch-key suffix");
+
+        Ok(())
+    }
 }
 
 impl ArchFilterPlugin {
@@ -45,15 +61,13 @@ impl ArchFilterPlugin {
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
         let plugin: Self = cfg.try_into()?;
-
-        ensure!(!plugin.key_prefix.is_empty(), "empty arch-key prefix");
-        ensure!(!plugin.key_suffix.is_empty(), "empty arch-key suffix");
+        plugin.validate()?;
 
         Ok(Box::new(plugin))
     }
 
-    /// Takes the arch identifier and removes all the conditional edges that does not contain
-    /// the provided arch identifier.
+    /// Takes the arch identifier and removes all the conditional edges whose releases are not
+    /// of the provided arch identifier.
     pub fn remove_conditional_edges(
         &self,
         ce: &mut Vec<cincinnati::ConditionalEdge>,
@@ -64,7 +78,7 @@ impl ArchFilterPlugin {
             .for_each(|ce: &mut cincinnati::ConditionalEdge| {
                 let total_edges = ce.edges.len();
                 ce.edges
-                    .retain(|e| e.from.contains(&arch) && e.to.contains(&arch));
+                    .retain(|e| is_of_arch(&e.from, &arch) && is_of_arch(&e.to, &arch));
                 edges_removed += total_edges - ce.edges.len();
             });
         ce.retain(|ce| !ce.edges.is_empty());
@@ -90,16 +104,23 @@ impl ArchFilterPlugin {
 
     /// gets the string and arch identifier and removes the arch identifier from the string
     fn remove_arch_info(&self, v: &str, arch: String) -> Result<String, Error> {
-        semver::Version::parse(v)
+        v.parse::<ReleaseVersion>()
             .context(v.to_string())
             .map(|mut version| {
-                version.build.retain(|elem| elem.to_string() != arch);
+                version.remove_build(&arch);
                 trace!("CE: rewriting version {} -> {}", v, version);
                 version.to_string()
             })
     }
 }
 
+/// Check whether the architecture encoded in the build information of `version` is `arch`.
+fn is_of_arch(version: &str, arch: &str) -> bool {
+    version
+        .parse::<ReleaseVersion>()
+        .map_or(false, |version| version.build().as_deref() == Some(arch))
+}
+
 /// Evaluate an architecture from the given "arch" parameters.
 fn infer_arch(arch: Option<String>, default_arch: String) -> Result<String, GraphError> {
     match arch {
@@ -135,10 +156,20 @@ lazy_static! {
 impl InternalPlugin for ArchFilterPlugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
 
+    const PLUGIN_PARAMETERS: &'static [&'static str] = &["default_arch"];
+
     async fn run_internal(&self, internal_io: InternalIO) -> Fallible<InternalIO> {
+        let default_arch = match get_plugin_parameter(
+            &internal_io.parameters,
+            Self::PLUGIN_NAME,
+            "default_arch",
+        ) {
+            Some(arch) => infer_arch(Some(arch.to_string()), self.default_arch.clone())?,
+            None => self.default_arch.clone(),
+        };
         let arch = infer_arch(
             internal_io.parameters.get("arch").map(|s| s.to_string()),
-            self.default_arch.clone(),
+            default_arch,
         )?;
 
         let mut graph = internal_io.graph;
@@ -185,10 +216,11 @@ impl InternalPlugin for ArchFilterPlugin {
                 let version = {
                     let release_version = release.version().to_owned();
 
-                    semver::Version::parse(&release_version)
+                    release_version
+                        .parse::<ReleaseVersion>()
                         .context(release_version.clone())
                         .map(|mut version| {
-                            version.build.retain(|elem| elem.to_string() != arch);
+                            version.remove_build(&arch);
                             trace!("rewriting version {} ->  {}", release_version, version);
                             version.to_string()
                         })?
@@ -300,6 +332,122 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn plugin_parameter_overrides_default_arch() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let input_metadata: TestMetadata = vec![
+            (
+                0,
+                [
+                    (String::from("version_suffix"), String::from("+amd64")),
+                    (String::from("release.arch"), String::from("amd64")),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            ),
+            (
+                1,
+                [
+                    (String::from("version_suffix"), String::from("+arm64")),
+                    (String::from("release.arch"), String::from("arm64")),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            ),
+        ];
+        let input_graph: cincinnati::Graph = generate_custom_graph("image", input_metadata, None);
+
+        let expected_metadata: TestMetadata = vec![(1, [].iter().cloned().collect())];
+        let expected_graph: cincinnati::Graph =
+            generate_custom_graph("image", expected_metadata, None);
+
+        let plugin = Box::new(ArchFilterPlugin {
+            key_prefix: "release".to_string(),
+            key_suffix: "arch".to_string(),
+            default_arch: "amd64".to_string(),
+        });
+        let future_processed_graph = plugin.run_internal(InternalIO {
+            graph: input_graph,
+            parameters: [("plugin.arch-filter.default_arch", "arm64")]
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        });
+
+        let processed_graph = runtime.block_on(future_processed_graph)?.graph;
+
+        assert_eq!(expected_graph, processed_graph);
+
+        Ok(())
+    }
+
+    #[test]
+    fn plugin_serves_multi_arch_graph() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let release = |arch: &str| -> cincinnati::MapImpl<String, String> {
+            [
+                (String::from("version_suffix"), format!("+{}", arch)),
+                (String::from("release.arch"), arch.to_string()),
+            ]
+            .iter()
+            .cloned()
+            .collect()
+        };
+        let input_metadata: TestMetadata = vec![
+            (0, release("amd64")),
+            (1, release("amd64")),
+            (0, release(cincinnati::MULTI_ARCH)),
+            (1, release(cincinnati::MULTI_ARCH)),
+        ];
+        let mut input_graph: cincinnati::Graph =
+            generate_custom_graph("image", input_metadata, Some(vec![(0, 1), (2, 3)]));
+        let edge = |from: &str, to: &str| cincinnati::ConditionalUpdateEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        input_graph.conditional_edges = Some(vec![cincinnati::ConditionalEdge {
+            edges: vec![
+                edge("0.0.0+amd64", "1.0.0+amd64"),
+                edge("0.0.0+multi", "1.0.0+multi"),
+            ],
+            ..Default::default()
+        }]);
+
+        let expected_graph: cincinnati::Graph = generate_custom_graph(
+            "image",
+            vec![(0, Default::default()), (1, Default::default())],
+            Some(vec![(0, 1)]),
+        );
+
+        let plugin = Box::new(ArchFilterPlugin {
+            key_prefix: "release".to_string(),
+            key_suffix: "arch".to_string(),
+            default_arch: "amd64".to_string(),
+        });
+        let processed_graph = runtime
+            .block_on(
+                plugin.run_internal(InternalIO {
+                    graph: input_graph,
+                    parameters: [("arch", cincinnati::MULTI_ARCH)]
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                }),
+            )?
+            .graph;
+
+        assert_eq!(expected_graph, processed_graph);
+        let conditional_edges = processed_graph.conditional_edges.unwrap_or_default();
+        assert_eq!(conditional_edges.len(), 1);
+        assert_eq!(conditional_edges[0].edges, vec![edge("0.0.0", "1.0.0")]);
+
+        Ok(())
+    }
+
     #[test]
     fn ensure_infer_arch() -> Fallible<()> {
         // (arch, default_arch), expecteded_arch
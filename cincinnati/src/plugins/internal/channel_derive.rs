@@ -0,0 +1,384 @@
+//! This plugin synthesizes channels from rules, instead of listing their
+//! releases in the graph-data.
+//!
+//! A `per-minor` rule derives one channel per minor version, e.g.
+//! `stable-{major}.{minor}`, with all the releases of that minor. A `latest`
+//! rule derives a channel with the newest release and the releases which
+//! can be updated to it directly. Pre-releases are left out unless the rule
+//! includes them.
+//!
+//! Derived channels are appended to the `<key_prefix>.release.channels`
+//! metadata of their releases. Channels which are already present in the
+//! graph, e.g. listed in the graph-data, take precedence and are not derived.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use prometheus::IntGauge;
+use std::collections::{BTreeMap, HashSet};
+
+pub static DEFAULT_KEY_FILTER: &str = "io.openshift.upgrades.graph";
+
+/// Kind of derived channels.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChannelRuleKind {
+    /// One channel per minor version, with all its releases.
+    PerMinor,
+    /// One channel with the newest release and its direct predecessors.
+    Latest,
+}
+
+/// Rule deriving channels.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelRule {
+    /// Name of the derived channels, where `{major}` and `{minor}` stand for
+    /// the version of `per-minor` channels.
+    pub name: String,
+
+    /// Kind of derived channels.
+    pub kind: ChannelRuleKind,
+
+    /// Whether pre-releases are included.
+    #[serde(default)]
+    pub prereleases: bool,
+}
+
+impl ChannelRule {
+    /// Whether releases of `version` are candidates of the rule.
+    fn includes(&self, version: &semver::Version) -> bool {
+        self.prereleases || !version.is_prerelease()
+    }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.name.is_empty(), "empty channel name");
+        ensure!(
+            !self.name.contains(','),
+            "channel name '{}' contains a comma",
+            self.name
+        );
+        match self.kind {
+            ChannelRuleKind::PerMinor => ensure!(
+                self.name.contains("{minor}"),
+                "per-minor channel name '{}' lacks '{{minor}}'",
+                self.name
+            ),
+            ChannelRuleKind::Latest => ensure!(
+                !self.name.contains('{'),
+                "latest channel name '{}' contains a placeholder",
+                self.name
+            ),
+        }
+        Ok(())
+    }
+
+    /// Name of the channel derived for `version`.
+    fn channel(&self, version: &semver::Version) -> String {
+        self.name
+            .replace("{major}", &version.major.to_string())
+            .replace("{minor}", &version.minor.to_string())
+    }
+}
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ChannelDeriveSettings {
+    #[default(DEFAULT_KEY_FILTER.to_string())]
+    pub key_prefix: String,
+
+    /// Rules deriving channels.
+    pub rules: Vec<ChannelRule>,
+}
+
+impl PluginSettings for ChannelDeriveSettings {
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = ChannelDerivePlugin::try_new(self.clone(), registry)?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.key_prefix.is_empty(), "empty prefix");
+        for rule in &self.rules {
+            rule.validate()
+                .context(format!("invalid channel rule '{}'", rule.name))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Generator of the channels derived from rules.
+#[derive(CustomDebug)]
+pub struct ChannelDerivePlugin {
+    channels_key: String,
+    rules: Vec<ChannelRule>,
+
+    #[debug(skip)]
+    derived_channels: IntGauge,
+}
+
+impl ChannelDerivePlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "channel-derive";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: ChannelDeriveSettings = cfg.try_into()?;
+        settings.validate()?;
+
+        Ok(Box::new(settings))
+    }
+
+    fn try_new(
+        settings: ChannelDeriveSettings,
+        registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let derived_channels = IntGauge::new(
+            "derived_channels",
+            "Number of channels derived from rules by the latest run",
+        )?;
+        if let Some(registry) = registry {
+            registry.register(Box::new(derived_channels.clone()))?;
+        }
+
+        Ok(Self {
+            channels_key: crate::channels_key(&settings.key_prefix),
+            rules: settings.rules,
+            derived_channels,
+        })
+    }
+
+    /// Derive the channels of `graph`, returning their number.
+    fn derive(&self, graph: &mut cincinnati::Graph) -> Fallible<usize> {
+        let releases: Vec<(ReleaseId, semver::Version)> = graph
+            .find_by_fn_mut(|release| matches!(release, cincinnati::Release::Concrete(_)))
+            .into_iter()
+            .filter_map(|(id, version)| match semver::Version::parse(&version) {
+                Ok(version) => Some((id, version)),
+                Err(e) => {
+                    debug!("not deriving channels of {}: {}", version, e);
+                    None
+                }
+            })
+            .collect();
+
+        let present: HashSet<String> = graph
+            .find_by_metadata_key(&self.channels_key)
+            .into_iter()
+            .flat_map(|(_, _, channels)| {
+                channels.split(',').map(str::to_string).collect::<Vec<_>>()
+            })
+            .collect();
+
+        let mut derived: BTreeMap<String, HashSet<ReleaseId>> = BTreeMap::new();
+        for rule in &self.rules {
+            let candidates = releases
+                .iter()
+                .filter(|(_, version)| rule.includes(version));
+            match rule.kind {
+                ChannelRuleKind::PerMinor => {
+                    for (id, version) in candidates {
+                        derived
+                            .entry(rule.channel(version))
+                            .or_default()
+                            .insert(id.clone());
+                    }
+                }
+                ChannelRuleKind::Latest => {
+                    let candidates: Vec<&(ReleaseId, semver::Version)> = candidates.collect();
+                    let newest = match candidates.iter().map(|(_, version)| version).max() {
+                        Some(newest) => newest.clone(),
+                        None => continue,
+                    };
+                    let channel = derived.entry(rule.channel(&newest)).or_default();
+                    // Releases of each architecture share the version.
+                    for (id, _) in candidates.iter().filter(|(_, version)| *version == newest) {
+                        channel.insert(id.clone());
+                        channel.extend(
+                            graph
+                                .previous_releases(id)
+                                .filter(|(_, _, release)| {
+                                    semver::Version::parse(release.version())
+                                        .map_or(false, |version| rule.includes(&version))
+                                })
+                                .map(|(_, node, _)| ReleaseId(node)),
+                        );
+                    }
+                }
+            }
+        }
+
+        // Channels are appended in the order of their names.
+        let mut count = 0;
+        for (channel, ids) in derived {
+            if present.contains(&channel) {
+                debug!("not deriving channel {}, which is already present", channel);
+                continue;
+            }
+            for id in ids {
+                graph
+                    .get_metadata_as_ref_mut(&id)
+                    .context(format!("deriving channel {}", channel))?
+                    .entry(self.channels_key.clone())
+                    .and_modify(|channels| {
+                        channels.push(',');
+                        channels.push_str(&channel);
+                    })
+                    .or_insert_with(|| channel.clone());
+            }
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for ChannelDerivePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        let count = self.derive(&mut graph)?;
+        self.derived_channels.set(count as i64);
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing::init_runtime;
+
+    static CHANNELS_KEY: &str = "io.openshift.upgrades.graph.release.channels";
+
+    fn cfg(extra: &str) -> Fallible<toml::Value> {
+        Ok(toml::from_str(&format!(
+            "name = {:?}\n{}",
+            ChannelDerivePlugin::PLUGIN_NAME,
+            extra
+        ))?)
+    }
+
+    /// Graph of 4.13.9, 4.14.0-rc.1, 4.14.0 and 4.14.1, each updating to the next.
+    fn graph() -> Fallible<cincinnati::Graph> {
+        let mut graph = cincinnati::Graph::default();
+        let mut previous = None;
+        for version in &["4.13.9", "4.14.0-rc.1", "4.14.0", "4.14.1"] {
+            let mut metadata = cincinnati::MapImpl::new();
+            if version.starts_with("4.13") {
+                metadata.insert(CHANNELS_KEY.to_string(), "stable-4.13".to_string());
+            }
+            let id =
+                graph.add_release(cincinnati::Release::Concrete(cincinnati::ConcreteRelease {
+                    version: version.to_string(),
+                    payload: format!("image:{}", version),
+                    metadata,
+                }))?;
+            if let Some(previous) = &previous {
+                graph.add_edge(previous, &id)?;
+            }
+            previous = Some(id);
+        }
+        Ok(graph)
+    }
+
+    fn channels(graph: &cincinnati::Graph) -> BTreeMap<String, String> {
+        graph
+            .find_by_metadata_key(CHANNELS_KEY)
+            .into_iter()
+            .map(|(_, version, channels)| (version, channels))
+            .collect()
+    }
+
+    fn plugin(rules: &str) -> Fallible<ChannelDerivePlugin> {
+        let settings: ChannelDeriveSettings = cfg(rules)?.try_into()?;
+        ChannelDerivePlugin::try_new(settings, None)
+    }
+
+    #[test]
+    fn validate_settings() -> Fallible<()> {
+        ChannelDerivePlugin::deserialize_config(cfg(
+            "[[rules]]\nname = \"stable-{major}.{minor}\"\nkind = \"per-minor\"",
+        )?)?;
+
+        for invalid in &[
+            "[[rules]]\nname = \"stable\"\nkind = \"per-minor\"",
+            "[[rules]]\nname = \"latest-{minor}\"\nkind = \"latest\"",
+            "[[rules]]\nname = \"stable,fast\"\nkind = \"latest\"",
+            "[[rules]]\nname = \"stable\"\nkind = \"newest\"",
+            "key_prefix = \"\"",
+        ] {
+            assert!(
+                ChannelDerivePlugin::deserialize_config(cfg(invalid)?).is_err(),
+                "{}",
+                invalid
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn derive_channels() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let plugin = plugin(
+            "[[rules]]\nname = \"stable-{major}.{minor}\"\nkind = \"per-minor\"\n\
+             [[rules]]\nname = \"latest\"\nkind = \"latest\"",
+        )?;
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: graph()?,
+            parameters: Default::default(),
+        }))?;
+
+        // The channel of 4.13 is already listed in the graph-data.
+        assert_eq!(
+            channels(&io.graph),
+            vec![
+                ("4.13.9", "stable-4.13"),
+                ("4.14.0", "latest,stable-4.14"),
+                ("4.14.1", "latest,stable-4.14"),
+            ]
+            .into_iter()
+            .map(|(version, channels)| (version.to_string(), channels.to_string()))
+            .collect()
+        );
+        assert_eq!(plugin.derived_channels.get(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn derive_prerelease_channels() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let plugin = plugin(
+            "[[rules]]\nname = \"candidate-{major}.{minor}\"\nkind = \"per-minor\"\nprereleases = true",
+        )?;
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: graph()?,
+            parameters: Default::default(),
+        }))?;
+
+        assert_eq!(
+            channels(&io.graph).get("4.14.0-rc.1").map(String::as_str),
+            Some("candidate-4.14")
+        );
+        assert_eq!(
+            channels(&io.graph).get("4.13.9").map(String::as_str),
+            Some("stable-4.13,candidate-4.13")
+        );
+
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
 //! This plugin can be used to filter a graph by a specific channel.
 //! It reads the requested channel from the parameters value at key "channel",
 //! and the value must match the regex specified at CHANNEL_VALIDATION_REGEX_STR
+//!
+//! A family of channels can be requested instead with a glob, e.g. `stable-4.*`,
+//! or with a regular expression prefixed by CHANNEL_REGEX_PREFIX, e.g.
+//! `regex:stable-4\.1[2-4]`, which must match whole channel names.
 
 use crate as cincinnati;
 use std::collections::HashSet;
@@ -22,12 +26,24 @@ pub struct ChannelFilterPlugin {
 
     #[default(DEFAULT_CHANNEL_KEY.to_string())]
     pub key_suffix: String,
+
+    /// Channels, globs or regular expressions of the channels which can be
+    /// served; all channels are served if empty.
+    pub allowed_channels: Vec<String>,
 }
 
 impl PluginSettings for ChannelFilterPlugin {
     fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
         Ok(new_plugin!(InternalPluginWrapper(self.clone())))
     }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.key_prefix.is_empty(), "empty channel-key prefix");
+        ensure!(!self.key_suffix.is_empty(), "empty channel-key suffix");
+        self.allowed_patterns()?;
+
+        Ok(())
+    }
 }
 
 impl ChannelFilterPlugin {
@@ -36,13 +52,56 @@ impl ChannelFilterPlugin {
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
         let plugin: Self = cfg.try_into()?;
-
-        ensure!(!plugin.key_prefix.is_empty(), "empty channel-key prefix");
-        ensure!(!plugin.key_suffix.is_empty(), "empty channel-key suffix");
+        plugin.validate()?;
 
         Ok(Box::new(plugin))
     }
 
+    fn allowed_patterns(&self) -> Fallible<Vec<ChannelPattern>> {
+        self.allowed_channels
+            .iter()
+            .map(|channel| {
+                ChannelPattern::parse(channel)
+                    .context(format!("invalid allowed channel '{}'", channel))
+            })
+            .collect()
+    }
+
+    /// Return the channels present in `graph` which match `requested` and
+    /// are allowed.
+    fn resolve_channels(
+        &self,
+        graph: &mut cincinnati::Graph,
+        requested: &ChannelPattern,
+        allowed: &[ChannelPattern],
+    ) -> HashSet<String> {
+        let channel_key = format!("{}.{}", self.key_prefix, self.key_suffix);
+        let mut channels = HashSet::new();
+
+        graph.find_by_fn_mut(|release| {
+            if let Some(values) = release
+                .get_metadata_mut()
+                .and_then(|metadata| metadata.get(&channel_key))
+            {
+                channels.extend(
+                    values
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|channel| requested.matches(channel))
+                        .filter(|channel| {
+                            allowed.is_empty() || allowed.iter().any(|p| p.matches(channel))
+                        })
+                        .map(str::to_string),
+                );
+            }
+
+            // we don't care about this result
+            false
+        });
+
+        channels
+    }
+
     /// Takes a HashSet of release names and removes the corresponding conditional edges
     /// containing the releases.  
     pub fn remove_conditional_edges(
@@ -66,9 +125,85 @@ impl ChannelFilterPlugin {
 /// Regex for channel label validation.
 static CHANNEL_VALIDATION_REGEX_STR: &str = r"^[0-9a-z\-\.]+$";
 
+/// Regex for channel glob validation.
+static CHANNEL_GLOB_VALIDATION_REGEX_STR: &str = r"^[0-9a-z\-\.\*\?]+$";
+
+/// Prefix of the channel patterns given as regular expressions.
+pub static CHANNEL_REGEX_PREFIX: &str = "regex:";
+
+/// Maximum length of a channel pattern.
+static CHANNEL_PATTERN_MAX_LEN: usize = 256;
+
+/// Maximum size of a compiled channel pattern, in bytes.
+static CHANNEL_PATTERN_SIZE_LIMIT: usize = 1 << 16;
+
 lazy_static! {
     static ref CHANNEL_VALIDATION_REGEX_RE: regex::Regex =
         regex::Regex::new(CHANNEL_VALIDATION_REGEX_STR).expect("could not create regex");
+    static ref CHANNEL_GLOB_VALIDATION_REGEX_RE: regex::Regex =
+        regex::Regex::new(CHANNEL_GLOB_VALIDATION_REGEX_STR).expect("could not create regex");
+}
+
+/// A channel, or a family of channels.
+#[derive(Clone, Debug)]
+pub enum ChannelPattern {
+    /// A single channel.
+    Channel(String),
+    /// Channels matching a glob or a regular expression.
+    Pattern(regex::Regex),
+}
+
+impl ChannelPattern {
+    /// Parse a channel, a glob or a prefixed regular expression.
+    ///
+    /// Patterns are length and size limited, so that they are cheap to
+    /// compile and match even when supplied by clients.
+    pub fn parse(pattern: &str) -> Fallible<Self> {
+        ensure!(
+            pattern.len() <= CHANNEL_PATTERN_MAX_LEN,
+            "longer than {} characters",
+            CHANNEL_PATTERN_MAX_LEN
+        );
+
+        let regex = if let Some(regex) = pattern.strip_prefix(CHANNEL_REGEX_PREFIX) {
+            regex.to_string()
+        } else if pattern.contains(|c: char| c == '*' || c == '?') {
+            ensure!(
+                CHANNEL_GLOB_VALIDATION_REGEX_RE.is_match(pattern),
+                "does not match regex '{}'",
+                CHANNEL_GLOB_VALIDATION_REGEX_STR
+            );
+            pattern
+                .chars()
+                .map(|c| match c {
+                    '*' => r"[0-9a-z\-\.]*".to_string(),
+                    '?' => r"[0-9a-z\-\.]".to_string(),
+                    c => regex::escape(&c.to_string()),
+                })
+                .collect()
+        } else {
+            ensure!(
+                CHANNEL_VALIDATION_REGEX_RE.is_match(pattern),
+                "does not match regex '{}'",
+                CHANNEL_VALIDATION_REGEX_STR
+            );
+            return Ok(ChannelPattern::Channel(pattern.to_string()));
+        };
+
+        let regex = regex::RegexBuilder::new(&format!("^(?:{})$", regex))
+            .size_limit(CHANNEL_PATTERN_SIZE_LIMIT)
+            .dfa_size_limit(CHANNEL_PATTERN_SIZE_LIMIT)
+            .build()?;
+        Ok(ChannelPattern::Pattern(regex))
+    }
+
+    /// Whether `channel` is part of the pattern.
+    pub fn matches(&self, channel: &str) -> bool {
+        match self {
+            ChannelPattern::Channel(name) => name == channel,
+            ChannelPattern::Pattern(regex) => regex.is_match(channel),
+        }
+    }
 }
 
 #[async_trait]
@@ -80,15 +215,14 @@ impl InternalPlugin for ChannelFilterPlugin {
             .map_err(|e| GraphError::MissingParams(vec![e.to_string()]))?
             .clone();
 
-        if !CHANNEL_VALIDATION_REGEX_RE.is_match(&channel) {
-            return Err(GraphError::InvalidParams(format!(
-                "channel '{}' does not match regex '{}'",
-                channel, CHANNEL_VALIDATION_REGEX_STR
-            ))
-            .into());
-        };
+        let requested = ChannelPattern::parse(&channel)
+            .map_err(|e| GraphError::InvalidParams(format!("channel '{}' {}", channel, e)))?;
+        let allowed = self.allowed_patterns()?;
 
         let mut graph = internal_io.graph;
+        let channels = self.resolve_channels(&mut graph, &requested, &allowed);
+        trace!("channel '{}' resolved to {:?}", channel, channels);
+
         let mut releases_version: HashSet<String> = HashSet::new();
 
         let to_remove: Vec<ReleaseId> = {
@@ -99,7 +233,9 @@ impl InternalPlugin for ChannelFilterPlugin {
                             .metadata
                             .get_mut(&format!("{}.{}", self.key_prefix, self.key_suffix))
                             .map_or(true, |values| {
-                                !values.split(',').any(|value| value.trim() == channel)
+                                !values
+                                    .split(',')
+                                    .any(|value| channels.contains(value.trim()))
                             }),
                         // remove if it's not a ConcreteRelease
                         _ => true,
@@ -149,6 +285,7 @@ mod tests {
         let plugin = Box::new(ChannelFilterPlugin {
             key_prefix: "".to_string(),
             key_suffix: "".to_string(),
+            ..Default::default()
         });
 
         struct Datum {
@@ -195,6 +332,7 @@ mod tests {
         let plugin = Box::new(ChannelFilterPlugin {
             key_prefix: key_prefix.clone(),
             key_suffix: key_suffix.clone(),
+            ..Default::default()
         });
 
         fn generate_test_metadata(
@@ -410,4 +548,87 @@ mod tests {
             assert_eq!(datum.expected_graph, processed_graph);
         }
     }
+
+    #[test]
+    fn channel_patterns() -> Fallible<()> {
+        let glob = ChannelPattern::parse("stable-4.*")?;
+        assert!(glob.matches("stable-4.14"));
+        assert!(!glob.matches("stable-4"));
+        assert!(!glob.matches("stable-4x14"));
+        assert!(!glob.matches("eus-stable-4.14"));
+
+        let regex = ChannelPattern::parse(r"regex:(fast|stable)-4\.1[2-4]")?;
+        assert!(regex.matches("fast-4.12"));
+        assert!(regex.matches("stable-4.14"));
+        assert!(!regex.matches("stable-4.15"));
+        assert!(!regex.matches("stable-4.140"));
+
+        assert!(ChannelPattern::parse("stable_4.*").is_err());
+        assert!(ChannelPattern::parse("regex:(").is_err());
+        assert!(ChannelPattern::parse(r"regex:(?:\w{100}){100}").is_err());
+        assert!(ChannelPattern::parse(&"a".repeat(CHANNEL_PATTERN_MAX_LEN + 1)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn ensure_channel_pattern_filter() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let channel_key = format!("{}.{}", DEFAULT_KEY_FILTER, DEFAULT_CHANNEL_KEY);
+        let metadata: Vec<(usize, MapImpl<String, String>)> =
+            ["stable-4.13", "stable-4.14", "fast-4.14,stable-4.15"]
+                .iter()
+                .enumerate()
+                .map(|(i, channels)| {
+                    (
+                        i,
+                        [(channel_key.clone(), channels.to_string())]
+                            .iter()
+                            .cloned()
+                            .collect(),
+                    )
+                })
+                .collect();
+        let graph = generate_custom_graph("image", metadata, None);
+
+        let versions = |plugin: &ChannelFilterPlugin, channel: &str| -> Fallible<Vec<String>> {
+            let io = runtime.block_on(
+                plugin.run_internal(InternalIO {
+                    graph: graph.clone(),
+                    parameters: [("channel".to_string(), channel.to_string())]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                }),
+            )?;
+            let mut versions: Vec<String> = io
+                .graph
+                .find_by_fn_mut(|_| true)
+                .into_iter()
+                .map(|(_, version)| version)
+                .collect();
+            versions.sort();
+            Ok(versions)
+        };
+
+        let plugin = ChannelFilterPlugin::default();
+        assert_eq!(
+            versions(&plugin, "stable-4.*")?,
+            vec!["0.0.0", "1.0.0", "2.0.0"]
+        );
+        assert_eq!(
+            versions(&plugin, r"regex:.*-4\.14")?,
+            vec!["1.0.0", "2.0.0"]
+        );
+        assert_eq!(versions(&plugin, "candidate-*")?, Vec::<String>::new());
+
+        let plugin = ChannelFilterPlugin {
+            allowed_channels: vec!["stable-*".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(versions(&plugin, "*-4.14")?, vec!["1.0.0"]);
+        assert_eq!(versions(&plugin, "fast-4.14")?, Vec::<String>::new());
+
+        Ok(())
+    }
 }
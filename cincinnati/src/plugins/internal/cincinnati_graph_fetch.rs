@@ -2,12 +2,29 @@
 //!
 //! Instead of processing the input graph, this plugin fetches a graph from a
 //! remote endpoint, which makes it effectively discard any given input graph.
+//!
+//! When the upstream cannot be reached, the last successfully fetched graph is
+//! served instead and the `io.openshift.upgrades.graph.stale_since` parameter
+//! is set to the HTTP-date since which the upstream has been failing.
+//!
+//! The digest of the fetched graph is set in the
+//! `io.openshift.upgrades.graph.upstream_digest` parameter, so that graphs
+//! rendered from it can be addressed by their content.
+//!
+//! The upstream endpoints can be discovered through DNS, see `discovery`.
+//!
+//! Graphs of additional upstreams, e.g. per-architecture graph-builders, can
+//! be fetched as well and merged into the graph of the main upstream.
+//!
+//! Upstream responses larger than `max_response_bytes`, or taking longer than
+//! `max_decode_secs` to decode, are rejected without being fully buffered.
+
+mod discovery;
 
 use crate as cincinnati;
 
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
-use self::cincinnati::CONTENT_TYPE;
 
 use commons::prelude_errors::*;
 use commons::tracing::{get_tracer, set_context};
@@ -17,12 +34,19 @@ use opentelemetry::{
 };
 
 use cached::{proc_macro::cached, Return};
+use cincinnati_client::{Client, GraphQuery, ResponseLimits, RetryPolicy};
 use commons::prelude_errors::Context;
-use commons::GraphError;
-use prometheus::Counter;
-use reqwest;
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
-use std::time::Duration;
+use commons::{
+    GraphError, UpstreamError, GRAPH_STALE_SINCE_PARAM_KEY, GRAPH_UPSTREAM_DIGEST_PARAM_KEY,
+};
+use discovery::{DiscoverySettings, UpstreamPool};
+use prometheus::{Counter, IntGauge};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
 
 /// Default URL to upstream graph provider.
 pub static DEFAULT_UPSTREAM_URL: &str = "http://localhost:8080/graph";
@@ -30,15 +54,149 @@ pub static DEFAULT_UPSTREAM_URL: &str = "http://localhost:8080/graph";
 /// Default graph-builder connection timeout in seconds.
 pub static DEFAULT_TIMEOUT_SECS: u64 = 30;
 
+/// Default number of retries for transient graph-builder failures.
+pub static DEFAULT_MAX_RETRIES: u32 = 0;
+
+/// Default maximum size of upstream responses, in bytes.
+pub static DEFAULT_MAX_RESPONSE_BYTES: u64 = 256 * 1024 * 1024;
+
 /// Plugin settings.
+///
+/// Besides deserialization, settings can be built in code from the URL of the
+/// upstream; authentication and discovery are only configurable by the former.
 #[derive(Clone, CustomDebug, Deserialize, SmartDefault)]
 #[serde(default)]
-struct CincinnatiGraphFetchSettings {
+pub struct CincinnatiGraphFetchSettings {
     #[default(DEFAULT_UPSTREAM_URL.to_string())]
     upstream: String,
 
     #[default(DEFAULT_TIMEOUT_SECS)]
     timeout: u64,
+
+    #[default(DEFAULT_MAX_RETRIES)]
+    max_retries: u32,
+
+    #[default(true)]
+    serve_stale: bool,
+
+    /// Maximum size of upstream responses, in bytes; 0 for unlimited.
+    #[default(DEFAULT_MAX_RESPONSE_BYTES)]
+    max_response_bytes: u64,
+
+    /// Maximum time spent decoding an upstream response, in seconds.
+    max_decode_secs: Option<u64>,
+
+    #[serde(flatten)]
+    auth: UpstreamAuth,
+
+    #[serde(flatten)]
+    discovery: DiscoverySettings,
+
+    #[serde(flatten)]
+    aggregation: UpstreamAggregation,
+}
+
+/// Additional upstreams whose graphs are merged into the one of the main upstream.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+struct UpstreamAggregation {
+    /// URLs of the additional upstreams, merged in order.
+    ///
+    /// They are fetched with the credentials of the main upstream, without discovery.
+    additional_upstreams: Vec<String>,
+
+    /// Release kept when several upstreams serve the same version.
+    merge_precedence: MergePrecedence,
+}
+
+impl UpstreamAggregation {
+    /// Check that the additional upstreams are distinct from each other and from `upstream`.
+    fn validate(&self, upstream: &str) -> Fallible<()> {
+        for (i, additional) in self.additional_upstreams.iter().enumerate() {
+            ensure!(!additional.is_empty(), "empty additional upstream");
+            ensure!(
+                additional != upstream && !self.additional_upstreams[..i].contains(additional),
+                "duplicate upstream '{}'",
+                additional
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Credentials presented to the upstream, read from files.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+struct UpstreamAuth {
+    /// File containing a bearer token, sent in the `Authorization` header.
+    ///
+    /// The file is read on each upstream request, so rotated tokens are picked up.
+    bearer_token_path: Option<PathBuf>,
+
+    /// PEM file containing the client certificate.
+    client_cert_path: Option<PathBuf>,
+
+    /// PEM file containing the PKCS#8 private key of the client certificate.
+    client_key_path: Option<PathBuf>,
+
+    /// PEM file containing additional CA certificates to trust for the upstream.
+    ca_cert_path: Option<PathBuf>,
+}
+
+impl UpstreamAuth {
+    /// Build an HTTP client presenting the configured client certificate.
+    fn http_client(&self, timeout: Duration) -> Fallible<reqwest::Client> {
+        let identity = match (&self.client_cert_path, &self.client_key_path) {
+            (Some(cert_path), Some(key_path)) => Some((cert_path.clone(), key_path.clone())),
+            _ => None,
+        };
+
+        commons::http::HttpClientBuilder::new()
+            .timeout(timeout)
+            .identity(identity)
+            .ca_bundle(self.ca_cert_path.clone())
+            .build()
+    }
+
+    /// Value of the `Authorization` header, if a bearer token is configured.
+    fn authorization(&self) -> Fallible<Option<HeaderValue>> {
+        let token_path = match &self.bearer_token_path {
+            Some(token_path) => token_path,
+            None => return Ok(None),
+        };
+
+        let token = fs::read_to_string(token_path)
+            .context(format!("reading bearer token {}", token_path.display()))?;
+        let token = token.trim();
+        ensure!(
+            !token.is_empty(),
+            "empty bearer token in {}",
+            token_path.display()
+        );
+
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", token))
+            .context(format!("invalid bearer token in {}", token_path.display()))?;
+        value.set_sensitive(true);
+        Ok(Some(value))
+    }
+}
+
+/// Last graph successfully fetched from the upstream.
+#[derive(Debug)]
+struct LastGoodGraph {
+    graph: crate::Graph,
+
+    /// Time of the first upstream failure since the graph was fetched.
+    stale_since: Option<SystemTime>,
+
+    /// Digest of the serialized graph.
+    digest: String,
+}
+
+/// Compute the digest of a graph, as hex-encoded SHA-256 of its serialization.
+fn graph_digest(graph: &crate::Graph) -> Fallible<String> {
+    let json = serde_json::to_vec(graph).context("serializing fetched graph")?;
+    Ok(hex::encode(Sha256::digest(&json)))
 }
 
 /// Graph fetcher for Cincinnati `/graph` endpoints.
@@ -55,16 +213,119 @@ pub struct CincinnatiGraphFetchPlugin {
     #[debug(skip)]
     pub http_upstream_errors_total: Counter,
 
+    /// The optional metric flagging whether a stale graph is being served
+    #[debug(skip)]
+    pub http_upstream_stale: IntGauge,
+
+    /// The optional metric for counting unknown fields in fetched graphs
+    #[debug(skip)]
+    pub upstream_unknown_fields_total: Counter,
+
+    /// Whether to serve the last fetched graph while the upstream fails
+    pub serve_stale: bool,
+
+    // last graph successfully fetched from the upstream
+    #[debug(skip)]
+    last_good: Mutex<Option<LastGoodGraph>>,
+
     // graph-builder connection client
-    client: reqwest::Client,
+    client: Client,
+
+    // credentials presented to the upstream
+    auth: UpstreamAuth,
+
+    // endpoints of the upstream
+    pool: UpstreamPool,
+
+    // additional upstreams merged into the graph
+    aggregation: UpstreamAggregation,
+}
+
+impl CincinnatiGraphFetchSettings {
+    /// Settings fetching the graph from `upstream`, with defaults otherwise.
+    pub fn new(upstream: impl Into<String>) -> Self {
+        Self {
+            upstream: upstream.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the timeout of upstream requests, in seconds.
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the number of retries of failed upstream requests.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Set whether the last fetched graph is served while the upstream fails.
+    pub fn serve_stale(mut self, serve_stale: bool) -> Self {
+        self.serve_stale = serve_stale;
+        self
+    }
+
+    /// Set the additional upstreams whose graphs are merged, in order.
+    pub fn additional_upstreams(mut self, additional_upstreams: Vec<String>) -> Self {
+        self.aggregation.additional_upstreams = additional_upstreams;
+        self
+    }
+
+    /// Set the maximum size of upstream responses, in bytes; 0 for unlimited.
+    pub fn max_response_bytes(mut self, max_response_bytes: u64) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    /// Set the maximum time spent decoding an upstream response, in seconds.
+    pub fn max_decode_secs(mut self, max_decode_secs: u64) -> Self {
+        self.max_decode_secs = Some(max_decode_secs);
+        self
+    }
+
+    fn response_limits(&self) -> ResponseLimits {
+        ResponseLimits {
+            max_bytes: Some(self.max_response_bytes).filter(|max_bytes| *max_bytes > 0),
+            max_decode_time: self.max_decode_secs.map(Duration::from_secs),
+        }
+    }
 }
 
 impl PluginSettings for CincinnatiGraphFetchSettings {
     fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
         let cfg = self.clone();
-        let plugin = CincinnatiGraphFetchPlugin::try_new(cfg.upstream, cfg.timeout, registry)?;
+        let plugin = CincinnatiGraphFetchPlugin::try_new(
+            cfg.upstream,
+            cfg.timeout,
+            cfg.max_retries,
+            cfg.serve_stale,
+            cfg.auth,
+            cfg.discovery,
+            cfg.aggregation,
+            registry,
+        )?
+        .with_limits(self.response_limits());
         Ok(new_plugin!(InternalPluginWrapper(plugin)))
     }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.upstream.is_empty(), "empty upstream");
+        ensure!(
+            self.auth.client_cert_path.is_some() == self.auth.client_key_path.is_some(),
+            "client_cert_path and client_key_path must be set together"
+        );
+        self.discovery.validate(&self.upstream)?;
+        self.aggregation.validate(&self.upstream)?;
+        ensure!(
+            self.max_decode_secs != Some(0),
+            "max_decode_secs must be greater than 0"
+        );
+
+        Ok(())
+    }
 }
 
 impl CincinnatiGraphFetchPlugin {
@@ -74,15 +335,20 @@ impl CincinnatiGraphFetchPlugin {
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
         let settings: CincinnatiGraphFetchSettings = cfg.try_into()?;
-
-        ensure!(!settings.upstream.is_empty(), "empty upstream");
+        settings.validate()?;
 
         Ok(Box::new(settings))
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn try_new(
         upstream: String,
         timeout: u64,
+        max_retries: u32,
+        serve_stale: bool,
+        auth: UpstreamAuth,
+        discovery: DiscoverySettings,
+        aggregation: UpstreamAggregation,
         prometheus_registry: Option<&prometheus::Registry>,
     ) -> Fallible<Self> {
         let http_upstream_reqs = Counter::new(
@@ -95,64 +361,174 @@ impl CincinnatiGraphFetchPlugin {
             "Total number of HTTP upstream unreachable errors",
         )?;
 
+        let http_upstream_stale = IntGauge::new(
+            "http_upstream_stale",
+            "Whether the last fetched graph is served because the upstream fails",
+        )?;
+
+        let upstream_unknown_fields_total = Counter::new(
+            "graph_upstream_unknown_fields_total",
+            "Total number of unknown top-level fields in graphs fetched from upstreams",
+        )?;
+
         if let Some(registry) = &prometheus_registry {
             registry.register(Box::new(http_upstream_reqs.clone()))?;
             registry.register(Box::new(http_upstream_errors_total.clone()))?;
+            registry.register(Box::new(http_upstream_stale.clone()))?;
+            registry.register(Box::new(upstream_unknown_fields_total.clone()))?;
         };
 
-        let client = reqwest::ClientBuilder::new()
-            .gzip(true)
-            .timeout(Duration::from_secs(timeout))
-            .build()
-            .context("Building reqwest client")?;
+        let http = auth
+            .http_client(Duration::from_secs(timeout))
+            .context("Building graph-builder client")?;
+        let client = Client::from_http_client(http).with_retry_policy(RetryPolicy {
+            max_retries,
+            ..Default::default()
+        });
+        let pool = UpstreamPool::try_new(upstream.clone(), discovery)?;
 
         Ok(Self {
             upstream,
             http_upstream_reqs,
             http_upstream_errors_total,
+            http_upstream_stale,
+            upstream_unknown_fields_total,
+            serve_stale,
+            last_good: Mutex::new(None),
             client,
+            auth,
+            pool,
+            aggregation,
+        })
+    }
+
+    /// Limit the size and decode time of upstream responses.
+    fn with_limits(mut self, limits: ResponseLimits) -> Self {
+        self.client = self.client.clone().with_limits(limits);
+        self
+    }
+
+    /// Count the unknown fields of a graph freshly fetched from `upstream`.
+    ///
+    /// They are passed through to clients, but usually mean that the upstream
+    /// runs a newer version.
+    fn record_unknown_fields(&self, upstream: &str, graph: &crate::Graph) {
+        let unknown_fields = graph.unknown_fields();
+        if unknown_fields.is_empty() {
+            return;
+        }
+        debug!(
+            "passing through unknown fields {:?} of the graph from upstream {}",
+            unknown_fields.keys().collect::<Vec<_>>(),
+            upstream
+        );
+        self.upstream_unknown_fields_total
+            .inc_by(unknown_fields.len() as f64);
+    }
+
+    /// Remember a successfully fetched graph and leave the stale state,
+    /// returning the digest of the graph.
+    fn record_fresh(&self, graph: &crate::Graph, was_cached: bool) -> Fallible<String> {
+        let mut last_good = self
+            .last_good
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Cached responses have already been recorded when they were fetched.
+        if !was_cached || last_good.is_none() {
+            *last_good = Some(LastGoodGraph {
+                graph: graph.clone(),
+                stale_since: None,
+                digest: graph_digest(graph)?,
+            });
+        }
+        self.http_upstream_stale.set(0);
+        Ok(last_good
+            .as_ref()
+            .map(|last_good| last_good.digest.clone())
+            .unwrap_or_default())
+    }
+
+    /// Build the output from the last fetched graph, if stale graphs are served.
+    fn stale_output(&self, mut parameters: HashMap<String, String>) -> Option<InternalIO> {
+        if !self.serve_stale {
+            return None;
+        }
+
+        let mut last_good = self
+            .last_good
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let last_good = last_good.as_mut()?;
+        let stale_since = *last_good.stale_since.get_or_insert_with(SystemTime::now);
+        self.http_upstream_stale.set(1);
+
+        parameters.insert(
+            GRAPH_STALE_SINCE_PARAM_KEY.to_string(),
+            commons::http_date(stale_since),
+        );
+        parameters.insert(
+            GRAPH_UPSTREAM_DIGEST_PARAM_KEY.to_string(),
+            last_good.digest.clone(),
+        );
+        Some(InternalIO {
+            graph: last_good.graph.clone(),
+            parameters,
         })
     }
 }
 
-// Cache successful responses, ignoring input, invalidating after 60 seconds
+// Cache successful responses by upstream, invalidating after 60 seconds
 #[cached(
-    size = 1,
+    size = 16,
     time = 60,
-    key = "bool",
-    convert = r#"{ true }"#,
+    key = "String",
+    convert = r#"{ upstream.to_string() }"#,
     sync_writes = true,
     with_cached_flag = true,
     result = true
 )]
 async fn cached_graph(
-    client: &reqwest::Client,
+    client: &Client,
     upstream: &str,
-    headers: HeaderMap,
+    auth: &UpstreamAuth,
+    mut headers: HeaderMap,
 ) -> Fallible<Return<crate::Graph>, GraphError> {
-    let res = client
-        .get(upstream)
-        .headers(headers)
-        .send()
-        .map_err(|e| GraphError::FailedUpstreamFetch(e.to_string()))
-        .await?;
-
-    if !res.status().is_success() {
-        return Err(GraphError::FailedUpstreamFetch(res.status().to_string()));
+    let authorization = auth
+        .authorization()
+        .map_err(|e| GraphError::Upstream(UpstreamError::Unreachable(format!("{:#}", e))))?;
+    if let Some(authorization) = authorization {
+        headers.insert(AUTHORIZATION, authorization);
     }
-    let graph = res
-        .json()
-        .map_err(|e| GraphError::FailedJsonIn(e.to_string()))
+
+    let graph = client
+        .fetch_graph_as(upstream, &GraphQuery::default(), headers)
+        .map_err(|e| {
+            // Upstream failures which may heal are reported as unavailability,
+            // while a response which will not become usable is a bad gateway.
+            let error = if e.is_transient() {
+                UpstreamError::Unreachable(e.to_string())
+            } else {
+                UpstreamError::InvalidResponse(e.to_string())
+            };
+            GraphError::Upstream(error)
+        })
         .await?;
     Ok(Return::new(graph))
 }
 
+/// Drop the cached upstream graphs, so that tests can observe upstream changes.
+#[cfg(any(test, feature = "test"))]
+pub async fn clear_cached_graphs() {
+    use cached::Cached;
+
+    CACHED_GRAPH.lock().await.cache_clear();
+}
+
 impl CincinnatiGraphFetchPlugin {
     async fn do_run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
         // extract current trace ID from headers
         // this is required to make graph-builder trace a child of police-engine request
         let mut headers = HeaderMap::new();
-        headers.insert(ACCEPT, HeaderValue::from_static(CONTENT_TYPE));
         {
             let span = get_tracer().start("");
             let _active_span = mark_span_as_active(span);
@@ -160,19 +536,54 @@ impl CincinnatiGraphFetchPlugin {
             set_context(cx, &mut headers).context("failed to set the tracing context")?;
         }
 
-        trace!("getting graph from upstream at {}", self.upstream);
-        let call_result = cached_graph(&self.client, &self.upstream, headers).await?;
+        let upstream = self.pool.select().await?;
+        trace!("getting graph from upstream at {}", upstream);
+        let start = std::time::Instant::now();
+        let call_result = cached_graph(&self.client, &upstream, &self.auth, headers.clone()).await;
+        if !matches!(&call_result, Ok(call_result) if call_result.was_cached) {
+            self.pool
+                .report(&upstream, call_result.is_ok(), start.elapsed());
+        }
+        let call_result = call_result?;
         // Increase request counter only if actual call was made
         if !call_result.was_cached {
             self.http_upstream_reqs.inc();
+            self.record_unknown_fields(&upstream, &call_result.value);
         }
+        let mut was_cached = call_result.was_cached;
+        let mut graph = call_result.value;
+
+        let additional_results =
+            futures::future::try_join_all(self.aggregation.additional_upstreams.iter().map(
+                |upstream| {
+                    trace!("getting graph from additional upstream at {}", upstream);
+                    cached_graph(&self.client, upstream, &self.auth, headers.clone())
+                },
+            ))
+            .await?;
+        for (upstream, call_result) in self
+            .aggregation
+            .additional_upstreams
+            .iter()
+            .zip(additional_results)
+        {
+            if !call_result.was_cached {
+                self.http_upstream_reqs.inc();
+                self.record_unknown_fields(upstream, &call_result.value);
+                was_cached = false;
+            }
+            graph
+                .merge(call_result.value, self.aggregation.merge_precedence)
+                .context(format!("merging graph of upstream {}", upstream))?;
+        }
+
+        let digest = self.record_fresh(&graph, was_cached)?;
         get_active_span(|span| {
-            span.set_attribute(Key::new("cached").bool(call_result.was_cached));
+            span.set_attribute(Key::new("cached").bool(was_cached));
         });
-        Ok(InternalIO {
-            graph: call_result.value,
-            parameters: io.parameters,
-        })
+        let mut parameters = io.parameters;
+        parameters.insert(GRAPH_UPSTREAM_DIGEST_PARAM_KEY.to_string(), digest);
+        Ok(InternalIO { graph, parameters })
     }
 }
 
@@ -181,13 +592,21 @@ impl InternalPlugin for CincinnatiGraphFetchPlugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
 
     async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
-        self.do_run_internal(io)
-            .map_err(move |e| {
+        let parameters = io.parameters.clone();
+        match self.do_run_internal(io).await {
+            Ok(io) => Ok(io),
+            Err(e) => {
                 error!("error fetching graph: {}", e);
                 self.http_upstream_errors_total.inc();
-                e
-            })
-            .await
+                match self.stale_output(parameters) {
+                    Some(io) => {
+                        warn!("serving stale graph");
+                        Ok(io)
+                    }
+                    None => Err(e),
+                }
+            }
+        }
     }
 }
 
@@ -220,8 +639,16 @@ mod tests {
                     .create();
 
                 let timeout: u64 = 30;
-                let plugin =
-                    CincinnatiGraphFetchPlugin::try_new(mockito::server_url(), timeout, None)?;
+                let plugin = CincinnatiGraphFetchPlugin::try_new(
+                    mockito::server_url(),
+                    timeout,
+                    0,
+                    true,
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    None,
+                )?;
                 let http_upstream_reqs = plugin.http_upstream_reqs.clone();
                 let http_upstream_errors_total = plugin.http_upstream_errors_total.clone();
 
@@ -268,6 +695,168 @@ mod tests {
         ),
     );
 
+    #[test]
+    fn fetch_merge_additional_upstreams() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let graph = |versions: std::ops::Range<usize>| -> Fallible<String> {
+            Ok(serde_json::to_string(&generate_custom_graph(
+                "image",
+                versions.map(|i| (i, Default::default())).collect(),
+                None,
+            ))?)
+        };
+        let _amd64 = mockito::mock("GET", "/amd64")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(graph(0..2)?)
+            .create();
+        let _arm64 = mockito::mock("GET", "/arm64")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(graph(2..4)?)
+            .create();
+
+        let plugin = CincinnatiGraphFetchPlugin::try_new(
+            format!("{}/amd64", mockito::server_url()),
+            30,
+            0,
+            true,
+            Default::default(),
+            Default::default(),
+            UpstreamAggregation {
+                additional_upstreams: vec![format!("{}/arm64", mockito::server_url())],
+                ..Default::default()
+            },
+            None,
+        )?;
+
+        let processed_graph = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+            }))?
+            .graph;
+
+        assert_eq!(processed_graph.releases_count(), 4);
+        let edges = processed_graph.get_edges(true)?;
+        assert_eq!(edges["0.0.0"].iter().collect::<Vec<_>>(), vec!["1.0.0"]);
+        assert_eq!(edges["2.0.0"].iter().collect::<Vec<_>>(), vec!["3.0.0"]);
+        assert_eq!(2, plugin.http_upstream_reqs.get() as u64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fetch_rejects_large_responses() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let _m = mockito::mock("GET", "/large")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(serde_json::to_string(&generate_custom_graph(
+                "image",
+                (0..4).map(|i| (i, Default::default())).collect(),
+                None,
+            ))?)
+            .create();
+
+        let plugin = CincinnatiGraphFetchPlugin::try_new(
+            format!("{}/large", mockito::server_url()),
+            30,
+            0,
+            false,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            None,
+        )?
+        .with_limits(
+            CincinnatiGraphFetchSettings::default()
+                .max_response_bytes(64)
+                .response_limits(),
+        );
+
+        let error = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+            }))
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("exceeds the limit of 64 bytes"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fetch_passes_unknown_fields() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let _m = mockito::mock("GET", "/newer")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"version":2,"nodes":[{"version":"1.0.0","payload":"image/1.0.0","metadata":{}}],"edges":[],"signatures":{},"channels":[]}"#,
+            )
+            .create();
+
+        let plugin = CincinnatiGraphFetchPlugin::try_new(
+            format!("{}/newer", mockito::server_url()),
+            30,
+            0,
+            true,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            None,
+        )?;
+
+        let processed_graph = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+            }))?
+            .graph;
+
+        assert_eq!(processed_graph.releases_count(), 1);
+        assert_eq!(
+            processed_graph.unknown_fields().keys().collect::<Vec<_>>(),
+            vec!["channels", "signatures"]
+        );
+        assert_eq!(2, plugin.upstream_unknown_fields_total.get() as u64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_additional_upstreams() -> Fallible<()> {
+        let cfg = |extra: &str| -> Fallible<toml::Value> {
+            Ok(toml::from_str(&format!(
+                "name = {:?}\nupstream = \"http://amd64.example.com/graph\"\n{}",
+                CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+                extra
+            ))?)
+        };
+
+        CincinnatiGraphFetchPlugin::deserialize_config(cfg(
+            "additional_upstreams = [\"http://arm64.example.com/graph\"]\nmerge_precedence = \"incoming\"",
+        )?)?;
+        for invalid in &[
+            r#"additional_upstreams = [""]"#,
+            r#"additional_upstreams = ["http://amd64.example.com/graph"]"#,
+            r#"additional_upstreams = ["http://arm64.example.com/graph", "http://arm64.example.com/graph"]"#,
+            r#"merge_precedence = "newest""#,
+        ] {
+            assert!(
+                CincinnatiGraphFetchPlugin::deserialize_config(cfg(invalid)?).is_err(),
+                "{}",
+                invalid
+            );
+        }
+
+        Ok(())
+    }
+
     macro_rules! fetch_upstream_failure_test {
         (
             name: $name:ident,
@@ -286,7 +875,16 @@ mod tests {
                     .with_body($mock_body.to_string())
                     .create();
 
-                let plugin = CincinnatiGraphFetchPlugin::try_new($upstream.to_string(), 30, None)?;
+                let plugin = CincinnatiGraphFetchPlugin::try_new(
+                    $upstream.to_string(),
+                    30,
+                    0,
+                    true,
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    None,
+                )?;
                 let http_upstream_reqs = plugin.http_upstream_reqs.clone();
                 let http_upstream_errors_total = plugin.http_upstream_errors_total.clone();
 
@@ -351,8 +949,16 @@ mod tests {
 
         let timeout: u64 = 30;
 
-        let _ =
-            CincinnatiGraphFetchPlugin::try_new(mockito::server_url(), timeout, Some(registry))?;
+        let _ = CincinnatiGraphFetchPlugin::try_new(
+            mockito::server_url(),
+            timeout,
+            0,
+            true,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Some(registry),
+        )?;
 
         let metrics_call = metrics::serve::<metrics::RegistryWrapper>(actix_web::web::Data::new(
             RegistryWrapper(registry),
@@ -375,10 +981,131 @@ mod tests {
             )
             .next()
             .is_some());
+            assert!(memmem::find_iter(
+                bytes.as_ref(),
+                format!("{}_http_upstream_stale 0\n", &metrics_prefix).as_bytes(),
+            )
+            .next()
+            .is_some());
         } else {
             bail!("expected bytes in body")
         };
 
         Ok(())
     }
+
+    #[test]
+    fn bearer_token_from_file() -> Fallible<()> {
+        use std::io::Write;
+
+        let mut token_file = tempfile::NamedTempFile::new()?;
+        writeln!(token_file, "secret")?;
+        let auth = UpstreamAuth {
+            bearer_token_path: Some(token_file.path().to_path_buf()),
+            ..Default::default()
+        };
+
+        let authorization = auth.authorization()?.expect("missing authorization");
+        assert_eq!(authorization, "Bearer secret");
+        assert!(authorization.is_sensitive());
+
+        assert!(UpstreamAuth::default().authorization()?.is_none());
+
+        let empty_file = tempfile::NamedTempFile::new()?;
+        let auth = UpstreamAuth {
+            bearer_token_path: Some(empty_file.path().to_path_buf()),
+            ..Default::default()
+        };
+        assert!(auth.authorization().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn client_certificate_settings() -> Fallible<()> {
+        let cfg = |extra: &str| -> Fallible<toml::Value> {
+            Ok(toml::from_str(&format!(
+                "name = {:?}\n{}",
+                CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+                extra
+            ))?)
+        };
+
+        assert!(CincinnatiGraphFetchPlugin::deserialize_config(cfg(
+            r#"client_cert_path = "/etc/tls/tls.crt""#
+        )?)
+        .is_err());
+
+        let settings = CincinnatiGraphFetchPlugin::deserialize_config(cfg(
+            "client_cert_path = \"/nonexistent/tls.crt\"\nclient_key_path = \"/nonexistent/tls.key\"",
+        )?)?;
+        assert!(settings.build_plugin(None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_last_good_graph_when_stale() -> Fallible<()> {
+        let graph = generate_custom_graph(
+            "image",
+            (0..2)
+                .into_iter()
+                .map(|i| (i, Default::default()))
+                .collect(),
+            Some(vec![(0, 1)]),
+        );
+        let mut parameters = HashMap::new();
+        parameters.insert("channel".to_string(), "stable".to_string());
+
+        let plugin = CincinnatiGraphFetchPlugin::try_new(
+            "http://not.reachable.test".to_string(),
+            30,
+            0,
+            true,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            None,
+        )?;
+
+        // Nothing to fall back to before the first successful fetch.
+        assert!(plugin.stale_output(parameters.clone()).is_none());
+        assert_eq!(0, plugin.http_upstream_stale.get());
+
+        let digest = plugin.record_fresh(&graph, false)?;
+        let stale = plugin
+            .stale_output(parameters.clone())
+            .expect("missing stale graph");
+        assert_eq!(graph, stale.graph);
+        assert_eq!(stale.parameters["channel"], "stable");
+        assert_eq!(stale.parameters[GRAPH_UPSTREAM_DIGEST_PARAM_KEY], digest);
+        let stale_since = stale.parameters[GRAPH_STALE_SINCE_PARAM_KEY].clone();
+        assert_eq!(1, plugin.http_upstream_stale.get());
+
+        // The graph stays stale since the first failure.
+        let stale = plugin
+            .stale_output(parameters.clone())
+            .expect("missing stale graph");
+        assert_eq!(stale.parameters[GRAPH_STALE_SINCE_PARAM_KEY], stale_since);
+
+        // Identical graphs have the same digest.
+        assert_eq!(plugin.record_fresh(&graph, true)?, digest);
+        assert_eq!(plugin.record_fresh(&graph, false)?, digest);
+        assert_eq!(0, plugin.http_upstream_stale.get());
+
+        let plugin = CincinnatiGraphFetchPlugin::try_new(
+            "http://not.reachable.test".to_string(),
+            30,
+            0,
+            false,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            None,
+        )?;
+        plugin.record_fresh(&graph, false)?;
+        assert!(plugin.stale_output(parameters).is_none());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,596 @@
+//! Discovery of the upstream endpoints through DNS.
+//!
+//! With `srv` discovery, the host of the upstream URL is a DNS SRV name,
+//! e.g. `_http._tcp.graph-builder.cincinnati.svc.cluster.local`, and each
+//! target of the lowest priority records is an endpoint. With `dns`
+//! discovery, the host of the upstream URL is resolved to all its addresses,
+//! e.g. those of the pods behind a headless Kubernetes service.
+//!
+//! Requests are balanced round-robin across the endpoints. Endpoints whose
+//! requests fail are skipped for a cooldown period, unless all of them are.
+//!
+//! With `weighted` balancing, requests are instead balanced in proportion to
+//! the passive health of the endpoints, derived from the success rate and
+//! latency of their requests. Endpoints which recover from a failure, or are
+//! newly discovered, start with a fraction of their weight which grows over
+//! `slow_start_secs`, so that they aren't flooded before proving themselves.
+
+use commons::prelude_errors::*;
+use log::{debug, warn};
+use serde::Deserialize;
+use smart_default::SmartDefault;
+use std::net::IpAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use trust_dns_resolver::TokioAsyncResolver;
+use url::Url;
+
+/// Default interval between resolutions of the upstream endpoints, in seconds.
+pub static DEFAULT_DISCOVERY_REFRESH_SECS: u64 = 30;
+
+/// Default time for which failed endpoints are skipped, in seconds.
+pub static DEFAULT_UNHEALTHY_COOLDOWN_SECS: u64 = 30;
+
+/// Default time over which recovering endpoints reach their full weight, in seconds.
+pub static DEFAULT_SLOW_START_SECS: u64 = 60;
+
+/// Weight of the latest request in the moving averages of the endpoint health.
+const HEALTH_SMOOTHING: f64 = 0.2;
+
+/// Minimum weight of an endpoint, so that its health keeps being checked.
+const MIN_WEIGHT: f64 = 0.01;
+
+/// Share of its weight with which a recovering endpoint starts.
+const SLOW_START_SHARE: f64 = 0.1;
+
+/// How the upstream endpoints are discovered.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Discovery {
+    /// The upstream URL is used as is.
+    #[default]
+    Static,
+    /// The upstream host is a DNS SRV name.
+    Srv,
+    /// The upstream host is resolved to all its addresses.
+    Dns,
+}
+
+/// How requests are balanced across the upstream endpoints.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Balancing {
+    /// Requests are sent to the endpoints in turn.
+    #[default]
+    RoundRobin,
+    /// Requests are sent to the endpoints in proportion to their health.
+    Weighted,
+}
+
+/// Settings of the upstream discovery.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct DiscoverySettings {
+    /// How the upstream endpoints are discovered.
+    pub discovery: Discovery,
+
+    /// Interval between resolutions of the upstream endpoints, in seconds.
+    #[default(DEFAULT_DISCOVERY_REFRESH_SECS)]
+    pub discovery_refresh_secs: u64,
+
+    /// Time for which failed endpoints are skipped, in seconds.
+    #[default(DEFAULT_UNHEALTHY_COOLDOWN_SECS)]
+    pub unhealthy_cooldown_secs: u64,
+
+    /// How requests are balanced across the endpoints.
+    pub balancing: Balancing,
+
+    /// Time over which recovering endpoints reach their full weight, in
+    /// seconds, with `weighted` balancing. 0 disables slow start.
+    #[default(DEFAULT_SLOW_START_SECS)]
+    pub slow_start_secs: u64,
+}
+
+impl DiscoverySettings {
+    /// Validate the settings against the `upstream` URL.
+    pub fn validate(&self, upstream: &str) -> Fallible<()> {
+        if self.discovery == Discovery::Static {
+            return Ok(());
+        }
+
+        let url = Url::parse(upstream).context(format!("invalid upstream URL {}", upstream))?;
+        ensure!(
+            url.host_str().map_or(false, |host| !host.is_empty()),
+            "upstream URL {} has no host to discover",
+            upstream
+        );
+        ensure!(
+            self.discovery_refresh_secs > 0,
+            "discovery_refresh_secs must be positive"
+        );
+        Ok(())
+    }
+}
+
+/// An upstream endpoint.
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+
+    /// Time until which the endpoint is skipped, after a failed request.
+    unhealthy_until: Option<Instant>,
+
+    health: Health,
+}
+
+/// Passive health of an endpoint, from the outcome of its requests.
+#[derive(Clone, Debug)]
+struct Health {
+    /// Moving average of the success of the requests, from 0 to 1.
+    success_rate: f64,
+
+    /// Moving average of the latency of the successful requests, in seconds.
+    latency_secs: Option<f64>,
+
+    /// Time from which the endpoint recovers, i.e. its weight grows.
+    recovering_since: Option<Instant>,
+
+    /// Counter of the smooth weighted round-robin.
+    current_weight: f64,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            success_rate: 1.0,
+            latency_secs: None,
+            recovering_since: None,
+            current_weight: 0.0,
+        }
+    }
+}
+
+impl Health {
+    /// Record the outcome of a request which took `latency`.
+    fn record(&mut self, healthy: bool, latency: Duration) {
+        let success = if healthy { 1.0 } else { 0.0 };
+        self.success_rate += HEALTH_SMOOTHING * (success - self.success_rate);
+        if healthy {
+            let latency = latency.as_secs_f64();
+            self.latency_secs = Some(match self.latency_secs {
+                Some(average) => average + HEALTH_SMOOTHING * (latency - average),
+                None => latency,
+            });
+        }
+    }
+
+    /// Return the weight of the endpoint at `now`, relative to the lowest
+    /// latency of the endpoints.
+    fn weight(&self, now: Instant, min_latency_secs: Option<f64>, slow_start: Duration) -> f64 {
+        let latency_share = match (min_latency_secs, self.latency_secs) {
+            (Some(min), Some(latency)) => min.max(1e-3) / latency.max(1e-3),
+            _ => 1.0,
+        };
+        let slow_start_share = match self.recovering_since {
+            Some(since) if !slow_start.is_zero() => {
+                let progress =
+                    now.saturating_duration_since(since).as_secs_f64() / slow_start.as_secs_f64();
+                SLOW_START_SHARE + (1.0 - SLOW_START_SHARE) * progress.min(1.0)
+            }
+            _ => 1.0,
+        };
+        (self.success_rate * self.success_rate * latency_share * slow_start_share).max(MIN_WEIGHT)
+    }
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    endpoints: Vec<Endpoint>,
+    resolved_at: Option<Instant>,
+    next: usize,
+}
+
+/// Endpoints of the upstream, balanced round-robin.
+#[derive(custom_debug_derive::Debug)]
+pub struct UpstreamPool {
+    upstream: String,
+    settings: DiscoverySettings,
+
+    #[debug(skip)]
+    resolver: Option<TokioAsyncResolver>,
+
+    state: Mutex<PoolState>,
+}
+
+impl UpstreamPool {
+    /// Create a pool of the endpoints of `upstream`.
+    pub fn try_new(upstream: String, settings: DiscoverySettings) -> Fallible<Self> {
+        let resolver = match settings.discovery {
+            Discovery::Static => None,
+            Discovery::Srv | Discovery::Dns => Some(
+                TokioAsyncResolver::tokio_from_system_conf()
+                    .context("Building the upstream DNS resolver")?,
+            ),
+        };
+
+        Ok(Self {
+            upstream,
+            settings,
+            resolver,
+            state: Mutex::new(PoolState::default()),
+        })
+    }
+
+    /// Select the URL of the endpoint to which the next request is sent.
+    pub async fn select(&self) -> Fallible<String> {
+        if self.settings.discovery == Discovery::Static {
+            return Ok(self.upstream.clone());
+        }
+
+        if self.needs_refresh() {
+            match self.resolve().await {
+                Ok(urls) => self.replace_endpoints(urls),
+                Err(e) => warn!(
+                    "failed to resolve the endpoints of upstream {}: {:#}",
+                    &self.upstream, e
+                ),
+            }
+        }
+
+        self.next_endpoint()
+            .ok_or_else(|| format_err!("no endpoint resolved for upstream {}", &self.upstream))
+    }
+
+    /// Record the outcome of a request to the endpoint at `url`, which took `latency`.
+    pub fn report(&self, url: &str, healthy: bool, latency: Duration) {
+        if self.settings.discovery == Discovery::Static {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(endpoint) = state.endpoints.iter_mut().find(|e| e.url == url) {
+            endpoint.health.record(healthy, latency);
+            endpoint.unhealthy_until = if healthy {
+                None
+            } else {
+                debug!("skipping unhealthy upstream endpoint {}", url);
+                let until =
+                    Instant::now() + Duration::from_secs(self.settings.unhealthy_cooldown_secs);
+                endpoint.health.recovering_since = Some(until);
+                Some(until)
+            };
+        }
+    }
+
+    fn needs_refresh(&self) -> bool {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.resolved_at {
+            Some(resolved_at) => {
+                state.endpoints.is_empty()
+                    || resolved_at.elapsed()
+                        >= Duration::from_secs(self.settings.discovery_refresh_secs)
+            }
+            None => true,
+        }
+    }
+
+    /// Replace the endpoints with those at `urls`, keeping the health of
+    /// the endpoints which are still resolved.
+    ///
+    /// Endpoints discovered next to known ones start recovering.
+    fn replace_endpoints(&self, urls: Vec<String>) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut previous = std::mem::take(&mut state.endpoints);
+        let now = Instant::now();
+        state.endpoints = urls
+            .into_iter()
+            .map(|url| match previous.iter_mut().find(|e| e.url == url) {
+                Some(known) => Endpoint {
+                    url,
+                    unhealthy_until: known.unhealthy_until.take(),
+                    health: known.health.clone(),
+                },
+                None => Endpoint {
+                    url,
+                    unhealthy_until: None,
+                    health: Health {
+                        recovering_since: Some(now).filter(|_| !previous.is_empty()),
+                        ..Default::default()
+                    },
+                },
+            })
+            .collect();
+        state.resolved_at = Some(now);
+    }
+
+    /// Pick the next healthy endpoint, or the next endpoint if none is healthy.
+    fn next_endpoint(&self) -> Option<String> {
+        if self.settings.balancing == Balancing::Weighted {
+            return self.next_weighted_endpoint();
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let count = state.endpoints.len();
+        if count == 0 {
+            return None;
+        }
+
+        let now = Instant::now();
+        let start = state.next;
+        let index = (0..count)
+            .map(|offset| (start + offset) % count)
+            .find(|index| {
+                state.endpoints[*index]
+                    .unhealthy_until
+                    .map_or(true, |until| until <= now)
+            })
+            .unwrap_or(start % count);
+        state.next = index + 1;
+        Some(state.endpoints[index].url.clone())
+    }
+
+    /// Pick a healthy endpoint, or any endpoint if none is healthy, by
+    /// smooth weighted round-robin over the weights of their health.
+    fn next_weighted_endpoint(&self) -> Option<String> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let is_healthy =
+            |endpoint: &Endpoint| endpoint.unhealthy_until.map_or(true, |until| until <= now);
+        let any_healthy = state.endpoints.iter().any(is_healthy);
+        let mut candidates: Vec<&mut Endpoint> = state
+            .endpoints
+            .iter_mut()
+            .filter(|endpoint| !any_healthy || is_healthy(endpoint))
+            .collect();
+
+        let min_latency_secs = candidates
+            .iter()
+            .filter_map(|endpoint| endpoint.health.latency_secs)
+            .fold(None, |min: Option<f64>, latency| {
+                Some(min.map_or(latency, |min| min.min(latency)))
+            });
+        let slow_start = Duration::from_secs(self.settings.slow_start_secs);
+        let mut total = 0.0;
+        for endpoint in candidates.iter_mut() {
+            let weight = endpoint.health.weight(now, min_latency_secs, slow_start);
+            endpoint.health.current_weight += weight;
+            total += weight;
+        }
+
+        // The first endpoint of the highest counter is picked.
+        let mut picked: Option<&mut Endpoint> = None;
+        for endpoint in candidates {
+            if picked.as_ref().map_or(true, |picked| {
+                endpoint.health.current_weight > picked.health.current_weight
+            }) {
+                picked = Some(endpoint);
+            }
+        }
+        let picked = picked?;
+        picked.health.current_weight -= total;
+        Some(picked.url.clone())
+    }
+
+    async fn resolve(&self) -> Fallible<Vec<String>> {
+        let resolver = self
+            .resolver
+            .as_ref()
+            .ok_or_else(|| format_err!("no DNS resolver for static upstream"))?;
+        let upstream = Url::parse(&self.upstream)?;
+        let host = upstream
+            .host_str()
+            .ok_or_else(|| format_err!("upstream URL {} has no host", &self.upstream))?;
+
+        let urls = match self.settings.discovery {
+            Discovery::Srv => {
+                let records: Vec<(u16, String, u16)> = resolver
+                    .srv_lookup(host)
+                    .await
+                    .context(format!("SRV lookup of {}", host))?
+                    .iter()
+                    .map(|srv| (srv.priority(), srv.target().to_utf8(), srv.port()))
+                    .collect();
+                srv_endpoints(&upstream, &records)?
+            }
+            Discovery::Dns => {
+                let ips: Vec<IpAddr> = resolver
+                    .lookup_ip(host)
+                    .await
+                    .context(format!("address lookup of {}", host))?
+                    .iter()
+                    .collect();
+                ip_endpoints(&upstream, &ips)?
+            }
+            Discovery::Static => vec![self.upstream.clone()],
+        };
+
+        ensure!(!urls.is_empty(), "no endpoint found for {}", host);
+        Ok(urls)
+    }
+}
+
+/// Build the endpoint URLs from the `(priority, target, port)` SRV records
+/// of the `upstream` host, keeping the records of the lowest priority.
+fn srv_endpoints(upstream: &Url, records: &[(u16, String, u16)]) -> Fallible<Vec<String>> {
+    let priority = match records.iter().map(|(priority, _, _)| *priority).min() {
+        Some(priority) => priority,
+        None => return Ok(vec![]),
+    };
+
+    let mut urls = vec![];
+    for (_, target, port) in records.iter().filter(|(p, _, _)| *p == priority) {
+        let mut url = upstream.clone();
+        url.set_host(Some(target.trim_end_matches('.')))
+            .context(format!("invalid SRV target {}", target))?;
+        url.set_port(Some(*port))
+            .map_err(|_| format_err!("cannot set port of {}", upstream))?;
+        urls.push(url.to_string());
+    }
+    urls.sort();
+    urls.dedup();
+    Ok(urls)
+}
+
+/// Build the endpoint URLs from the addresses of the `upstream` host.
+fn ip_endpoints(upstream: &Url, ips: &[IpAddr]) -> Fallible<Vec<String>> {
+    let mut urls = vec![];
+    for ip in ips {
+        let mut url = upstream.clone();
+        url.set_ip_host(*ip)
+            .map_err(|_| format_err!("cannot set host of {}", upstream))?;
+        urls.push(url.to_string());
+    }
+    urls.sort();
+    urls.dedup();
+    Ok(urls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(urls: &[&str]) -> UpstreamPool {
+        pool_with(
+            DiscoverySettings {
+                discovery: Discovery::Dns,
+                ..Default::default()
+            },
+            urls,
+        )
+    }
+
+    fn pool_with(settings: DiscoverySettings, urls: &[&str]) -> UpstreamPool {
+        let pool = UpstreamPool {
+            upstream: "http://graph-builder.test/graph".to_string(),
+            settings,
+            resolver: None,
+            state: Mutex::new(PoolState::default()),
+        };
+        pool.replace_endpoints(urls.iter().map(|url| url.to_string()).collect());
+        pool
+    }
+
+    #[test]
+    fn build_endpoints() -> Fallible<()> {
+        let upstream = Url::parse("http://_http._tcp.graph-builder.test/api/graph?arch=amd64")?;
+        let records = vec![
+            (10, "gb-1.graph-builder.test.".to_string(), 8080),
+            (10, "gb-0.graph-builder.test.".to_string(), 8080),
+            (20, "gb-backup.graph-builder.test.".to_string(), 9090),
+        ];
+        assert_eq!(
+            srv_endpoints(&upstream, &records)?,
+            vec![
+                "http://gb-0.graph-builder.test:8080/api/graph?arch=amd64",
+                "http://gb-1.graph-builder.test:8080/api/graph?arch=amd64",
+            ]
+        );
+        assert!(srv_endpoints(&upstream, &[])?.is_empty());
+
+        let upstream = Url::parse("http://graph-builder-headless.test:8080/graph")?;
+        let ips: Vec<IpAddr> = vec!["10.0.0.2".parse()?, "fd00::1".parse()?];
+        assert_eq!(
+            ip_endpoints(&upstream, &ips)?,
+            vec!["http://10.0.0.2:8080/graph", "http://[fd00::1]:8080/graph"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn balance_across_healthy_endpoints() {
+        let pool = pool(&[
+            "http://a.test/graph",
+            "http://b.test/graph",
+            "http://c.test/graph",
+        ]);
+        let picks: Vec<String> = (0..4).map(|_| pool.next_endpoint().unwrap()).collect();
+        assert_eq!(
+            picks,
+            vec![
+                "http://a.test/graph",
+                "http://b.test/graph",
+                "http://c.test/graph",
+                "http://a.test/graph",
+            ]
+        );
+
+        pool.report("http://b.test/graph", false, Duration::ZERO);
+        let picks: Vec<String> = (0..3).map(|_| pool.next_endpoint().unwrap()).collect();
+        assert!(!picks.contains(&"http://b.test/graph".to_string()));
+
+        // Health survives re-resolution.
+        pool.replace_endpoints(vec![
+            "http://b.test/graph".to_string(),
+            "http://d.test/graph".to_string(),
+        ]);
+        assert_eq!(pool.next_endpoint().unwrap(), "http://d.test/graph");
+
+        // Endpoints are still used when none is healthy.
+        pool.report("http://d.test/graph", false, Duration::ZERO);
+        assert!(pool.next_endpoint().is_some());
+
+        pool.report("http://b.test/graph", true, Duration::ZERO);
+        assert_eq!(pool.next_endpoint().unwrap(), "http://b.test/graph");
+    }
+
+    #[test]
+    fn weight_endpoints_by_health() {
+        let settings = |slow_start_secs| DiscoverySettings {
+            discovery: Discovery::Dns,
+            unhealthy_cooldown_secs: 0,
+            balancing: Balancing::Weighted,
+            slow_start_secs,
+            ..Default::default()
+        };
+        let count = |pool: &UpstreamPool, picks: usize| {
+            let mut counts = std::collections::BTreeMap::<String, usize>::new();
+            for _ in 0..picks {
+                *counts.entry(pool.next_endpoint().unwrap()).or_default() += 1;
+            }
+            counts
+        };
+
+        // Endpoints are picked in turn until their health is known.
+        let pool = pool_with(settings(0), &["http://a.test/graph", "http://b.test/graph"]);
+        assert_eq!(pool.next_endpoint().unwrap(), "http://a.test/graph");
+        assert_eq!(pool.next_endpoint().unwrap(), "http://b.test/graph");
+
+        // Faster endpoints get more requests.
+        pool.report("http://a.test/graph", true, Duration::from_millis(100));
+        pool.report("http://b.test/graph", true, Duration::from_secs(1));
+        let counts = count(&pool, 110);
+        assert_eq!(counts["http://a.test/graph"], 100);
+        assert_eq!(counts["http://b.test/graph"], 10);
+
+        // So do endpoints failing less.
+        let pool = pool_with(settings(0), &["http://a.test/graph", "http://b.test/graph"]);
+        pool.report("http://b.test/graph", false, Duration::ZERO);
+        let counts = count(&pool, 164);
+        assert_eq!(counts["http://a.test/graph"], 100);
+        assert_eq!(counts["http://b.test/graph"], 64);
+
+        // Endpoints discovered later start with a share of their weight.
+        let pool = pool_with(settings(3600), &["http://a.test/graph"]);
+        pool.replace_endpoints(vec![
+            "http://a.test/graph".to_string(),
+            "http://b.test/graph".to_string(),
+        ]);
+        let counts = count(&pool, 110);
+        assert_eq!(counts["http://a.test/graph"], 100);
+        assert_eq!(counts["http://b.test/graph"], 10);
+    }
+
+    #[test]
+    fn validate_settings() {
+        let settings = |discovery| DiscoverySettings {
+            discovery,
+            ..Default::default()
+        };
+        assert!(settings(Discovery::Static).validate("not a url").is_ok());
+        assert!(settings(Discovery::Srv).validate("not a url").is_err());
+        assert!(settings(Discovery::Dns)
+            .validate("http://graph-builder.test/graph")
+            .is_ok());
+    }
+}
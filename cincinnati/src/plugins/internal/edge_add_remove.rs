@@ -6,10 +6,17 @@ use std::collections::HashMap;
 
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::ReleaseVersion;
 
 pub static DEFAULT_KEY_FILTER: &str = "io.openshift.upgrades.graph";
 pub static DEFAULT_REMOVE_ALL_EDGES_VALUE: &str = "*";
 
+/// Metadata key suffix of the start of the schedule of `previous.remove_regex`.
+pub static PREVIOUS_REMOVE_REGEX_FROM_KEY: &str = "previous.remove_regex.active_from";
+
+/// Metadata key suffix of the end of the schedule of `previous.remove_regex`.
+pub static PREVIOUS_REMOVE_REGEX_UNTIL_KEY: &str = "previous.remove_regex.active_until";
+
 #[derive(Clone, Debug, Deserialize, SmartDefault)]
 #[serde(default)]
 pub struct EdgeAddRemovePlugin {
@@ -51,6 +58,16 @@ impl PluginSettings for EdgeAddRemovePlugin {
     fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
         Ok(new_plugin!(InternalPluginWrapper(self.clone())))
     }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.key_prefix.is_empty(), "empty prefix");
+        ensure!(
+            !self.remove_all_edges_value.is_empty(),
+            "empty value for removing all edges"
+        );
+
+        Ok(())
+    }
 }
 
 /// Adds and removes next and previous releases specified by metadata.
@@ -70,6 +87,11 @@ impl PluginSettings for EdgeAddRemovePlugin {
 ///
 /// This ordering has implications on the result of semantical contradictions, so that the `*.remove` labels take precedence over `*.add`.
 ///
+/// # Scheduled removals
+/// `previous.remove_regex` labels with a schedule, i.e. along with a `previous.remove_regex.active_from`
+/// or `previous.remove_regex.active_until` label, are kept as they are: the `edge-schedule` plugin
+/// of the policy-engine applies them while their schedule is active.
+///
 /// # Strictness
 /// The plugin aims to gracefully handle any inconsistencies to make the operation as robust as possible.
 /// This includes cases where add or remove instructions refer to edges or releases which don't exist in the graph.
@@ -80,12 +102,7 @@ impl EdgeAddRemovePlugin {
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
         let plugin: Self = cfg.try_into()?;
-
-        ensure!(!plugin.key_prefix.is_empty(), "empty prefix");
-        ensure!(
-            !plugin.remove_all_edges_value.is_empty(),
-            "empty value for removing all edges"
-        );
+        plugin.validate()?;
 
         Ok(Box::new(plugin))
     }
@@ -148,56 +165,28 @@ impl EdgeAddRemovePlugin {
 
         // Remove edges instructed by "previous.remove_regex"
         let previous_remove_regex_key = format!("{}.{}", self.key_prefix, "previous.remove_regex");
+        let schedule_keys = [
+            format!("{}.{}", self.key_prefix, PREVIOUS_REMOVE_REGEX_FROM_KEY),
+            format!("{}.{}", self.key_prefix, PREVIOUS_REMOVE_REGEX_UNTIL_KEY),
+        ];
         graph
             .find_by_metadata_key(&previous_remove_regex_key)
             .into_iter()
             .try_for_each(
                 |(to, to_version, from_regex_string): (ReleaseId, String, String)| -> Fallible<()> {
-                    if self.remove_consumed_metadata {
-                        graph
-                            .get_metadata_as_ref_mut(&to)
-                            .map(|metadata| metadata.remove(&previous_remove_regex_key))?;
-                    }
-
-                    let from_regex = regex::Regex::new(&from_regex_string)
-                        .context(format!("Parsing {} as Regex", &from_regex_string))?;
-
-                    if from_regex_string == ".*" {
-                        let parents: Vec<daggy::EdgeIndex> = graph
-                            .previous_releases(&to)
-                            .map(|(edge_index, _, _)| edge_index)
-                            .collect();
-
-                        trace!(
-                            "removing parents by regex for '{}': {:?}",
-                            to_version,
-                            parents
-                        );
-                        return graph.remove_edges_by_index(&parents);
-                    };
-
-                    let froms = graph.find_by_fn_mut(|release| {
-                        if from_regex.is_match(release.version()) {
-                            debug!(
-                                "Regex '{}' matches version '{}'",
-                                &from_regex,
-                                release.version(),
-                            );
-                            true
-                        } else {
-                            false
-                        }
-                    });
-
-                    for (from, from_version) in froms {
+                    let metadata = graph.get_metadata_as_ref_mut(&to)?;
+                    if schedule_keys.iter().any(|key| metadata.contains_key(key)) {
                         debug!(
-                            "[{}]: removing previous {} by regex",
-                            to_version, from_version
+                            "[{}]: leaving scheduled removal by regex to the policy-engine",
+                            to_version
                         );
-                        handle_remove_edge!(from, to);
+                        return Ok(());
+                    }
+                    if self.remove_consumed_metadata {
+                        metadata.remove(&previous_remove_regex_key);
                     }
 
-                    Ok(())
+                    remove_previous_by_regex(graph, &to, &to_version, &from_regex_string)
                 },
             )?;
 
@@ -420,7 +409,61 @@ impl EdgeAddRemovePlugin {
     }
 }
 
-/// Try to find the architecture metadata and add it to the version String assuming SemVer.
+/// Remove the edges to `to` from the releases matching `from_regex_string`.
+pub(crate) fn remove_previous_by_regex(
+    graph: &mut cincinnati::Graph,
+    to: &ReleaseId,
+    to_version: &str,
+    from_regex_string: &str,
+) -> Fallible<()> {
+    let from_regex = regex::Regex::new(from_regex_string)
+        .context(format!("Parsing {} as Regex", from_regex_string))?;
+
+    if from_regex_string == ".*" {
+        let parents: Vec<daggy::EdgeIndex> = graph
+            .previous_releases(to)
+            .map(|(edge_index, _, _)| edge_index)
+            .collect();
+
+        trace!(
+            "removing parents by regex for '{}': {:?}",
+            to_version,
+            parents
+        );
+        return graph.remove_edges_by_index(&parents);
+    };
+
+    let froms = graph.find_by_fn_mut(|release| {
+        if from_regex.is_match(release.version()) {
+            debug!(
+                "Regex '{}' matches version '{}'",
+                &from_regex,
+                release.version(),
+            );
+            true
+        } else {
+            false
+        }
+    });
+
+    for (from, from_version) in froms {
+        debug!(
+            "[{}]: removing previous {} by regex",
+            to_version, from_version
+        );
+        if let Err(e) = graph.remove_edge(&from, to) {
+            if let Some(eae) = e.downcast_ref::<cincinnati::errors::EdgeDoesntExist>() {
+                debug!("{}", eae);
+                continue;
+            };
+            bail!(e)
+        }
+    }
+
+    Ok(())
+}
+
+/// Try to find the architecture metadata and add it to the build metadata of the version String.
 ///
 /// If the referenced ReleaseId doesn't have the arch metadata, the version
 /// string will be passed through unchanged.
@@ -433,8 +476,8 @@ fn try_annotate_semver_build(
         .get_metadata_as_ref_mut(arch_reference)?
         .get("io.openshift.upgrades.graph.release.arch")
     {
-        let mut version = semver::Version::parse(version)?;
-        version.build = vec![semver::Identifier::AlphaNumeric(arch.to_string())];
+        let mut version: ReleaseVersion = version.parse()?;
+        version.set_build(arch);
         version.to_string()
     } else {
         version.to_string()
@@ -558,6 +601,55 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn ensure_scheduled_previous_remove_regex_kept() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let key_prefix = "test_prefix".to_string();
+        let metadata: Vec<(usize, MapImpl<String, String>)> = [
+            (0, [].iter().cloned().collect()),
+            (
+                1,
+                [
+                    (
+                        format!("{}.previous.remove_regex", key_prefix),
+                        "0\\..*".to_string(),
+                    ),
+                    (
+                        format!("{}.{}", key_prefix, PREVIOUS_REMOVE_REGEX_FROM_KEY),
+                        "2024-05-01T12:00:00Z".to_string(),
+                    ),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            ),
+        ]
+        .to_vec();
+
+        let input_graph: cincinnati::Graph =
+            generate_custom_graph("image", metadata, Some(vec![(0, 1)]));
+        let expected_graph = input_graph.clone();
+
+        let plugin = Box::new(EdgeAddRemovePlugin {
+            key_prefix,
+            remove_consumed_metadata: true,
+
+            ..Default::default()
+        });
+        let processed_graph = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: input_graph,
+                parameters: Default::default(),
+            }))
+            .context("plugin run failed")?
+            .graph;
+
+        assert_eq!(expected_graph, processed_graph);
+
+        Ok(())
+    }
+
     #[test]
     fn ensure_next_remove() -> Fallible<()> {
         let runtime = init_runtime()?;
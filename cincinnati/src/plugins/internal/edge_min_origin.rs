@@ -0,0 +1,240 @@
+//! This plugin removes the edges leading to a release from versions older
+//! than the minimum version the release declares it can be upgraded from.
+//!
+//! The minimum is read from the `<key_prefix>.previous.min_version` metadata
+//! of the release, e.g. `io.openshift.upgrades.graph.previous.min_version=4.13.5`,
+//! and compared with the origins of its edges, including conditional ones,
+//! according to the configured version scheme. Origins whose version is not
+//! valid in the scheme cannot be shown to conform, and their edges are
+//! removed too.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::VersionScheme;
+
+use std::collections::HashMap;
+
+pub static DEFAULT_KEY_FILTER: &str = "io.openshift.upgrades.graph";
+
+/// Suffix of the metadata key holding the minimum origin version.
+static MIN_VERSION_KEY_SUFFIX: &str = "previous.min_version";
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct EdgeMinOriginPlugin {
+    #[default(DEFAULT_KEY_FILTER.to_string())]
+    pub key_prefix: String,
+
+    /// Scheme by which origin versions are compared with the minimum.
+    pub version_scheme: VersionScheme,
+
+    /// If true causes the removal of the processed metadata from the releases.
+    #[default(false)]
+    pub remove_consumed_metadata: bool,
+}
+
+#[async_trait]
+impl InternalPlugin for EdgeMinOriginPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        self.remove_edges(&mut graph)?;
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+impl PluginSettings for EdgeMinOriginPlugin {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.key_prefix.is_empty(), "empty prefix");
+
+        Ok(())
+    }
+}
+
+impl EdgeMinOriginPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "edge-min-origin";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = cfg.try_into()?;
+        plugin.validate()?;
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Check whether the `origin` version is at least `min_version`.
+    fn conforms(&self, origin: &str, min_version: &cincinnati::VersionKey) -> bool {
+        match self.version_scheme.parse(origin) {
+            Ok(origin) => origin >= *min_version,
+            Err(e) => {
+                debug!("{:#}", e);
+                false
+            }
+        }
+    }
+
+    /// Remove the edges from origins older than the minimum version of their target.
+    fn remove_edges(&self, graph: &mut cincinnati::Graph) -> Fallible<()> {
+        let key = format!("{}.{}", self.key_prefix, MIN_VERSION_KEY_SUFFIX);
+
+        // Minimum origin versions, by target version.
+        let mut min_versions = HashMap::new();
+        for (to, to_version, min_version) in graph.find_by_metadata_key(&key) {
+            if self.remove_consumed_metadata {
+                graph
+                    .get_metadata_as_ref_mut(&to)
+                    .map(|metadata| metadata.remove(&key))?;
+            }
+
+            let min_version_key = match self.version_scheme.parse(min_version.trim()) {
+                Ok(key) => key,
+                Err(e) => {
+                    warn!("ignoring minimum origin version of {}: {:#}", to_version, e);
+                    continue;
+                }
+            };
+
+            let blocked: Vec<cincinnati::daggy::EdgeIndex> = graph
+                .previous_releases(&to)
+                .filter(|(_, _, from)| !self.conforms(from.version(), &min_version_key))
+                .map(|(edge_index, _, _)| edge_index)
+                .collect();
+            trace!(
+                "removing {} edges to '{}' from versions older than '{}'",
+                blocked.len(),
+                to_version,
+                min_version
+            );
+            graph.remove_edges_by_index(&blocked)?;
+
+            min_versions.insert(to_version, min_version_key);
+        }
+
+        if let Some(conditional_edges) = graph.conditional_edges.as_mut() {
+            conditional_edges.iter_mut().for_each(|ce| {
+                ce.edges.retain(|e| match min_versions.get(&e.to) {
+                    Some(min_version) => self.conforms(&e.from, min_version),
+                    None => true,
+                })
+            });
+            conditional_edges.retain(|ce| !ce.edges.is_empty());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use cincinnati::MapImpl;
+    use commons::testing::init_runtime;
+
+    fn min_version(version: &str) -> MapImpl<String, String> {
+        [(
+            format!("{}.{}", DEFAULT_KEY_FILTER, MIN_VERSION_KEY_SUFFIX),
+            version.to_string(),
+        )]
+        .iter()
+        .cloned()
+        .collect()
+    }
+
+    #[test]
+    fn remove_edges_from_older_versions() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let metadata = vec![
+            (0, MapImpl::new()),
+            (1, MapImpl::new()),
+            (2, MapImpl::new()),
+            (3, min_version("1.0.0")),
+            // Invalid minimums are ignored.
+            (4, min_version("latest")),
+        ];
+        let input_graph = generate_custom_graph(
+            "image",
+            metadata.clone(),
+            Some(vec![(0, 1), (0, 3), (1, 3), (2, 3), (0, 4)]),
+        );
+        let expected_graph = generate_custom_graph(
+            "image",
+            metadata,
+            Some(vec![(0, 1), (1, 3), (2, 3), (0, 4)]),
+        );
+
+        let plugin = EdgeMinOriginPlugin::default();
+        let processed_graph = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: input_graph,
+                parameters: Default::default(),
+            }))
+            .context("plugin run failed")?
+            .graph;
+
+        assert_eq!(expected_graph, processed_graph);
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove_consumed_metadata() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let input_graph = generate_custom_graph(
+            "image",
+            vec![(0, MapImpl::new()), (1, min_version("0.5.0"))],
+            Some(vec![(0, 1)]),
+        );
+        let expected_graph = generate_custom_graph(
+            "image",
+            vec![(0, MapImpl::new()), (1, MapImpl::new())],
+            Some(vec![]),
+        );
+
+        let plugin = EdgeMinOriginPlugin {
+            remove_consumed_metadata: true,
+            ..Default::default()
+        };
+        let processed_graph = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: input_graph,
+                parameters: Default::default(),
+            }))?
+            .graph;
+
+        assert_eq!(expected_graph, processed_graph);
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_settings() -> Fallible<()> {
+        let cfg = |extra: &str| -> Fallible<toml::Value> {
+            Ok(toml::from_str(&format!(
+                "name = {:?}\n{}",
+                EdgeMinOriginPlugin::PLUGIN_NAME,
+                extra
+            ))?)
+        };
+
+        EdgeMinOriginPlugin::deserialize_config(cfg("")?)?;
+        EdgeMinOriginPlugin::deserialize_config(cfg(r#"version_scheme = "pep440""#)?)?;
+        assert!(EdgeMinOriginPlugin::deserialize_config(cfg(r#"key_prefix = """#)?).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,358 @@
+//! This plugin enforces the schedules of blocked edges and risks.
+//!
+//! Graph-data can schedule when a blocked edge or a risk applies, so that
+//! blocks activate or expire at a given time without a graph-data change at
+//! that time. The graph-builder leaves the scheduled blocks in the release
+//! metadata, and this plugin evaluates them for each request:
+//!  * the edges of active blocks are removed, as the graph-builder removes
+//!    the edges of unscheduled blocks, and the metadata of inactive blocks
+//!    is removed;
+//!  * inactive risks are removed, along with the conditional edges left
+//!    without risks.
+//!
+//! The schedules themselves are not served.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::edge_add_remove::{
+    remove_previous_by_regex, DEFAULT_KEY_FILTER, PREVIOUS_REMOVE_REGEX_FROM_KEY,
+    PREVIOUS_REMOVE_REGEX_UNTIL_KEY,
+};
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::ActivationSchedule;
+
+use chrono::{DateTime, Utc};
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct EdgeSchedulePlugin {
+    /// Prefix of the metadata keys of the blocked edges.
+    #[default(DEFAULT_KEY_FILTER.to_string())]
+    pub key_prefix: String,
+}
+
+impl PluginSettings for EdgeSchedulePlugin {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.key_prefix.is_empty(), "empty prefix");
+
+        Ok(())
+    }
+}
+
+impl EdgeSchedulePlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "edge-schedule";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = cfg.try_into()?;
+        plugin.validate()?;
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Apply the blocked edges scheduled at `now`.
+    fn apply_blocked_edges(
+        &self,
+        graph: &mut cincinnati::Graph,
+        now: DateTime<Utc>,
+    ) -> Fallible<()> {
+        let remove_regex_key = format!("{}.{}", self.key_prefix, "previous.remove_regex");
+        let from_key = format!("{}.{}", self.key_prefix, PREVIOUS_REMOVE_REGEX_FROM_KEY);
+        let until_key = format!("{}.{}", self.key_prefix, PREVIOUS_REMOVE_REGEX_UNTIL_KEY);
+
+        graph
+            .find_by_metadata_key(&remove_regex_key)
+            .into_iter()
+            .try_for_each(|(to, to_version, from_regex_string)| -> Fallible<()> {
+                let metadata = graph.get_metadata_as_ref_mut(&to)?;
+                let schedule = ActivationSchedule {
+                    from: metadata.remove(&from_key),
+                    until: metadata.remove(&until_key),
+                };
+                if schedule == ActivationSchedule::default() {
+                    return Ok(());
+                }
+
+                if !schedule.is_active(now) {
+                    trace!("[{}]: blocked edges inactive at {}", to_version, now);
+                    metadata.remove(&remove_regex_key);
+                    return Ok(());
+                }
+                debug!("[{}]: blocked edges active at {}", to_version, now);
+                remove_previous_by_regex(graph, &to, &to_version, &from_regex_string)
+            })
+    }
+
+    /// Keep the risks scheduled at `now`, and the conditional edges left with risks.
+    fn apply_risks(&self, graph: &mut cincinnati::Graph, now: DateTime<Utc>) {
+        let conditional_edges = match graph.conditional_edges.as_mut() {
+            Some(conditional_edges) => conditional_edges,
+            None => return,
+        };
+
+        conditional_edges.retain_mut(|conditional_edge| {
+            if conditional_edge
+                .risks
+                .iter()
+                .all(|risk| risk.schedule.is_none())
+            {
+                return true;
+            }
+            conditional_edge.risks.retain_mut(|risk| {
+                risk.schedule
+                    .take()
+                    .map_or(true, |schedule| schedule.is_active(now))
+            });
+            !conditional_edge.risks.is_empty()
+        });
+    }
+
+    /// Apply the schedules of blocked edges and risks at `now`.
+    fn apply(&self, graph: &mut cincinnati::Graph, now: DateTime<Utc>) -> Fallible<()> {
+        self.apply_blocked_edges(graph, now)?;
+        self.apply_risks(graph, now);
+        Ok(())
+    }
+}
+
+/// Return the number of schedule bounds of `graph` reached at `now`.
+///
+/// The number only changes when a blocked edge or a risk of the graph
+/// activates or expires, so that graphs rendered from `graph` remain valid
+/// as long as it does not change.
+pub fn reached_bounds(graph: &mut cincinnati::Graph, now: DateTime<Utc>) -> usize {
+    let reached = |bound: &str| {
+        DateTime::parse_from_rfc3339(bound).map_or(false, |bound| bound.with_timezone(&Utc) <= now)
+    };
+
+    let mut count = 0;
+    let _ = graph.find_by_fn_mut(|release| {
+        if let Some(metadata) = release.get_metadata_mut() {
+            count += metadata
+                .iter()
+                .filter(|(key, value)| {
+                    (key.ends_with(PREVIOUS_REMOVE_REGEX_FROM_KEY)
+                        || key.ends_with(PREVIOUS_REMOVE_REGEX_UNTIL_KEY))
+                        && reached(value)
+                })
+                .count();
+        }
+        false
+    });
+    count
+        + graph
+            .conditional_edges
+            .iter()
+            .flatten()
+            .flat_map(|conditional_edge| conditional_edge.risks.iter())
+            .filter_map(|risk| risk.schedule.as_ref())
+            .flat_map(|schedule| schedule.from.iter().chain(schedule.until.iter()))
+            .filter(|bound| reached(bound))
+            .count()
+}
+
+#[async_trait]
+impl InternalPlugin for EdgeSchedulePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        self.apply(&mut graph, Utc::now())?;
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use cincinnati::MapImpl;
+    use cincinnati::{ConditionalEdge, ConditionalUpdateEdge, ConditionalUpdateRisk};
+
+    fn schedule(from: Option<&str>, until: Option<&str>) -> ActivationSchedule {
+        ActivationSchedule {
+            from: from.map(ToString::to_string),
+            until: until.map(ToString::to_string),
+        }
+    }
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(timestamp)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn evaluate_schedules() {
+        let window = schedule(
+            Some("2024-05-01T12:00:00Z"),
+            Some("2024-05-02T00:00:00+02:00"),
+        );
+        assert!(window.validate().is_ok());
+        assert!(!window.is_active(at("2024-05-01T11:59:59Z")));
+        assert!(window.is_active(at("2024-05-01T12:00:00Z")));
+        assert!(window.is_active(at("2024-05-01T21:59:59Z")));
+        assert!(!window.is_active(at("2024-05-01T22:00:00Z")));
+
+        assert!(schedule(None, None).is_active(at("2024-05-01T12:00:00Z")));
+        assert!(schedule(None, Some("2024-05-01T12:00:00Z")).is_active(at("2024-05-01T00:00:00Z")));
+        assert!(!schedule(Some("2024-05-01T12:00:00Z"), None).is_active(at("2024-05-01T00:00:00Z")));
+
+        let invalid = schedule(Some("tomorrow"), None);
+        assert!(invalid.validate().is_err());
+        assert!(invalid.is_active(at("2024-05-01T00:00:00Z")));
+        assert!(
+            schedule(Some("2024-05-02T00:00:00Z"), Some("2024-05-01T00:00:00Z"))
+                .validate()
+                .is_err()
+        );
+    }
+
+    fn graph() -> cincinnati::Graph {
+        let key = |suffix: &str| format!("{}.{}", DEFAULT_KEY_FILTER, suffix);
+        let blocked = |from: &str, until: &str| -> MapImpl<String, String> {
+            [
+                (key("previous.remove_regex"), "0\\..*".to_string()),
+                (key(PREVIOUS_REMOVE_REGEX_FROM_KEY), from.to_string()),
+                (key(PREVIOUS_REMOVE_REGEX_UNTIL_KEY), until.to_string()),
+            ]
+            .iter()
+            .cloned()
+            .collect()
+        };
+
+        let mut graph = generate_custom_graph(
+            "image",
+            vec![
+                (0, Default::default()),
+                (1, blocked("2024-05-01T00:00:00Z", "2024-06-01T00:00:00Z")),
+                (2, blocked("2024-06-01T00:00:00Z", "2024-07-01T00:00:00Z")),
+            ],
+            Some(vec![(0, 1), (0, 2)]),
+        );
+
+        let conditional_edge = |to: &str, risks: Vec<ConditionalUpdateRisk>| ConditionalEdge {
+            edges: vec![ConditionalUpdateEdge {
+                from: "0.0.0".to_string(),
+                to: to.to_string(),
+            }],
+            risks,
+            ..Default::default()
+        };
+        let risk = |name: &str, schedule: Option<ActivationSchedule>| ConditionalUpdateRisk {
+            name: name.to_string(),
+            schedule,
+            ..Default::default()
+        };
+        graph.conditional_edges = Some(vec![
+            conditional_edge(
+                "1.0.0",
+                vec![
+                    risk(
+                        "Scheduled",
+                        Some(schedule(Some("2024-05-01T00:00:00Z"), None)),
+                    ),
+                    risk("Unscheduled", None),
+                ],
+            ),
+            conditional_edge(
+                "2.0.0",
+                vec![risk(
+                    "Expiring",
+                    Some(schedule(None, Some("2024-06-01T00:00:00Z"))),
+                )],
+            ),
+        ]);
+        graph
+    }
+
+    fn risk_names(graph: &cincinnati::Graph) -> Vec<Vec<String>> {
+        graph
+            .conditional_edges
+            .iter()
+            .flatten()
+            .map(|ce| ce.risks.iter().map(|risk| risk.name.clone()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn apply_schedules() -> Fallible<()> {
+        let plugin = EdgeSchedulePlugin::default();
+
+        let mut before = graph();
+        plugin.apply(&mut before, at("2024-04-01T00:00:00Z"))?;
+        assert_eq!(
+            before
+                .next_releases(&before.find_by_version("0.0.0").unwrap())
+                .count(),
+            2
+        );
+        assert_eq!(
+            risk_names(&before),
+            vec![
+                vec!["Unscheduled".to_string()],
+                vec!["Expiring".to_string()]
+            ]
+        );
+        for version in &["1.0.0", "2.0.0"] {
+            let release = before.find_by_version(version).unwrap();
+            assert!(before.get_metadata_as_ref_mut(&release)?.is_empty());
+        }
+
+        let mut during = graph();
+        plugin.apply(&mut during, at("2024-05-15T00:00:00Z"))?;
+        let next: Vec<String> = during
+            .next_releases(&during.find_by_version("0.0.0").unwrap())
+            .map(|(_, _, release)| release.version().to_string())
+            .collect();
+        assert_eq!(next, vec!["2.0.0".to_string()]);
+        assert_eq!(
+            risk_names(&during),
+            vec![
+                vec!["Scheduled".to_string(), "Unscheduled".to_string()],
+                vec!["Expiring".to_string()]
+            ]
+        );
+        let release = during.find_by_version("1.0.0").unwrap();
+        assert_eq!(
+            during
+                .get_metadata_as_ref_mut(&release)?
+                .keys()
+                .collect::<Vec<_>>(),
+            vec![&format!("{}.previous.remove_regex", DEFAULT_KEY_FILTER)]
+        );
+        assert!(during
+            .conditional_edges
+            .iter()
+            .flatten()
+            .flat_map(|ce| ce.risks.iter())
+            .all(|risk| risk.schedule.is_none()));
+
+        let mut after = graph();
+        assert_eq!(reached_bounds(&mut after, at("2024-04-01T00:00:00Z")), 0);
+        assert_eq!(reached_bounds(&mut after, at("2024-05-01T00:00:00Z")), 2);
+        assert_eq!(reached_bounds(&mut after, at("2024-06-15T00:00:00Z")), 5);
+        plugin.apply(&mut after, at("2024-06-15T00:00:00Z"))?;
+        let next: Vec<String> = after
+            .next_releases(&after.find_by_version("0.0.0").unwrap())
+            .map(|(_, _, release)| release.version().to_string())
+            .collect();
+        assert_eq!(next, vec!["1.0.0".to_string()]);
+        assert_eq!(
+            risk_names(&after),
+            vec![vec!["Scheduled".to_string(), "Unscheduled".to_string()]]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+//! Time spent in each phase of a graph build.
+//!
+//! Scrape plugins add the time they spend in each phase to the IO
+//! parameters, under `GRAPH_BUILD_PHASES_PARAM_KEY`, so that the daemon can
+//! report them once the plugin chain completed. Phases run concurrently for
+//! many tags, so their durations are totals across tags and may exceed the
+//! duration of the build.
+
+use commons::prelude_errors::*;
+use commons::GRAPH_BUILD_PHASES_PARAM_KEY;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+/// Phase of a graph build.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BuildPhase {
+    /// Listing the tags of the repositories, until the last tag is listed.
+    TagListing,
+    /// Fetching the manifests of the tags.
+    ManifestFetch,
+    /// Fetching the image layers and decoding their release metadata.
+    MetadataDecode,
+    /// Assembling the scraped releases into a graph.
+    GraphAssembly,
+    /// Running the whole plugin chain.
+    PluginChain,
+}
+
+impl BuildPhase {
+    /// All phases, in pipeline order.
+    pub const ALL: [BuildPhase; 5] = [
+        BuildPhase::TagListing,
+        BuildPhase::ManifestFetch,
+        BuildPhase::MetadataDecode,
+        BuildPhase::GraphAssembly,
+        BuildPhase::PluginChain,
+    ];
+
+    /// Return the stable name of the phase, e.g. for metric labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildPhase::TagListing => "tag_listing",
+            BuildPhase::ManifestFetch => "manifest_fetch",
+            BuildPhase::MetadataDecode => "metadata_decode",
+            BuildPhase::GraphAssembly => "graph_assembly",
+            BuildPhase::PluginChain => "plugin_chain",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|phase| phase.as_str() == name)
+    }
+}
+
+/// Time spent in each phase of a build.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PhaseTimings(BTreeMap<BuildPhase, Duration>);
+
+impl PhaseTimings {
+    /// Add `duration` to the time spent in `phase`.
+    pub fn add(&mut self, phase: BuildPhase, duration: Duration) {
+        *self.0.entry(phase).or_default() += duration;
+    }
+
+    /// Return the time spent in `phase`, if it ran.
+    pub fn get(&self, phase: BuildPhase) -> Option<Duration> {
+        self.0.get(&phase).copied()
+    }
+
+    /// Iterate over the phases which ran, in pipeline order.
+    pub fn iter(&self) -> impl Iterator<Item = (BuildPhase, Duration)> + '_ {
+        self.0.iter().map(|(phase, duration)| (*phase, *duration))
+    }
+
+    /// Read the timings recorded in the IO `parameters`, if any.
+    pub fn from_parameters(parameters: &HashMap<String, String>) -> Fallible<Self> {
+        let encoded = match parameters.get(GRAPH_BUILD_PHASES_PARAM_KEY) {
+            Some(encoded) => encoded,
+            None => return Ok(Self::default()),
+        };
+        let seconds: BTreeMap<String, f64> = serde_json::from_str(encoded)
+            .context(format!("parsing build phase timings '{}'", encoded))?;
+
+        let mut timings = Self::default();
+        for (name, seconds) in seconds {
+            let phase = BuildPhase::from_name(&name)
+                .ok_or_else(|| format_err!("unknown build phase '{}'", name))?;
+            ensure!(
+                seconds.is_finite() && seconds >= 0.0,
+                "invalid duration {} of build phase '{}'",
+                seconds,
+                name
+            );
+            timings.add(phase, Duration::from_secs_f64(seconds));
+        }
+        Ok(timings)
+    }
+
+    /// Add these timings to those recorded in the IO `parameters`.
+    pub fn add_to_parameters(&self, parameters: &mut HashMap<String, String>) -> Fallible<()> {
+        let mut timings = Self::from_parameters(parameters)?;
+        for (phase, duration) in self.iter() {
+            timings.add(phase, duration);
+        }
+
+        let seconds: BTreeMap<&str, f64> = timings
+            .iter()
+            .map(|(phase, duration)| (phase.as_str(), duration.as_secs_f64()))
+            .collect();
+        parameters.insert(
+            GRAPH_BUILD_PHASES_PARAM_KEY.to_string(),
+            serde_json::to_string(&seconds)?,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulate_timings_in_parameters() -> Fallible<()> {
+        let mut parameters = HashMap::new();
+        assert_eq!(
+            PhaseTimings::from_parameters(&parameters)?,
+            PhaseTimings::default()
+        );
+
+        let mut first = PhaseTimings::default();
+        first.add(BuildPhase::TagListing, Duration::from_millis(250));
+        first.add(BuildPhase::ManifestFetch, Duration::from_millis(500));
+        first.add(BuildPhase::ManifestFetch, Duration::from_millis(500));
+        first.add_to_parameters(&mut parameters)?;
+
+        let mut second = PhaseTimings::default();
+        second.add(BuildPhase::ManifestFetch, Duration::from_secs(2));
+        second.add(BuildPhase::GraphAssembly, Duration::from_millis(125));
+        second.add_to_parameters(&mut parameters)?;
+
+        let timings = PhaseTimings::from_parameters(&parameters)?;
+        assert_eq!(
+            timings.iter().collect::<Vec<_>>(),
+            vec![
+                (BuildPhase::TagListing, Duration::from_millis(250)),
+                (BuildPhase::ManifestFetch, Duration::from_secs(3)),
+                (BuildPhase::GraphAssembly, Duration::from_millis(125)),
+            ]
+        );
+        assert_eq!(timings.get(BuildPhase::MetadataDecode), None);
+
+        parameters.insert(
+            GRAPH_BUILD_PHASES_PARAM_KEY.to_string(),
+            r#"{"unknown":1.0}"#.to_string(),
+        );
+        assert!(PhaseTimings::from_parameters(&parameters).is_err());
+
+        Ok(())
+    }
+}
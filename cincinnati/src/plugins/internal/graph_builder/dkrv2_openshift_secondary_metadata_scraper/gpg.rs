@@ -64,6 +64,7 @@ pub async fn fetch_url(http_client: &Client, base_url: &Url, sha: &str, i: u64)
     let url = base_url
         .join(format!("{}/", sha.replace(":", "=")).as_str())?
         .join(format!("signature-{}", i).as_str())?;
+    commons::budget::acquire_url(url.as_str()).await?;
     let res = http_client
         .get(url.clone())
         .send()
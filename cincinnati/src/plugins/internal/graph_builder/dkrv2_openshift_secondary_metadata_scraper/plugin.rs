@@ -1,8 +1,12 @@
 use crate as cincinnati;
 use crate::plugins::internal::dkrv2_openshift_secondary_metadata_scraper::gpg;
 use crate::plugins::internal::release_scrape_dockerv2::registry;
-use commons::{GRAPH_DATA_DIR_PARAM_KEY, SECONDARY_METADATA_PARAM_KEY};
-use reqwest::{Client, ClientBuilder};
+use commons::http::HttpClientBuilder;
+use commons::{
+    ScrapeError, GRAPH_DATA_DIR_PARAM_KEY, GRAPH_DATA_REVISION_PARAM_KEY,
+    GRAPH_DATA_SOURCE_PARAM_KEY, SECONDARY_METADATA_PARAM_KEY,
+};
+use reqwest::Client;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tempfile::TempDir;
@@ -196,8 +200,7 @@ impl DkrV2OpenshiftSecondaryMetadataScraperPlugin {
             settings.username = username;
             settings.password = password;
         }
-        let http_client = ClientBuilder::new()
-            .gzip(true)
+        let http_client = HttpClientBuilder::new()
             .timeout(Duration::from_secs(DEFAULT_SIGNATURE_FETCH_TIMEOUT_SECS))
             .build()
             .context("Building reqwest client")?;
@@ -220,11 +223,9 @@ impl PluginSettings for DkrV2OpenshiftSecondaryMetadataScraperSettings {
     }
 }
 
-#[async_trait]
-impl InternalPlugin for DkrV2OpenshiftSecondaryMetadataScraperPlugin {
-    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
-
-    async fn run_internal(&self, mut io: InternalIO) -> Fallible<InternalIO> {
+impl DkrV2OpenshiftSecondaryMetadataScraperPlugin {
+    /// Fetch the graph-data, recording its location in the parameters of `io`.
+    async fn scrape(&self, mut io: InternalIO) -> Fallible<InternalIO> {
         let registry_client = registry::new_registry_client(
             &self.registry,
             &self.settings.repository,
@@ -237,6 +238,14 @@ impl InternalPlugin for DkrV2OpenshiftSecondaryMetadataScraperPlugin {
             .get_manifest_and_ref(&self.settings.repository, &self.settings.tag)
             .await?;
         trace!("manifest: {:?}, reference: {:?}", manifest, reference);
+        if let Some(digest) = &reference {
+            io.parameters.insert(
+                GRAPH_DATA_SOURCE_PARAM_KEY.to_string(),
+                format!("{}/{}", self.settings.registry, self.settings.repository),
+            );
+            io.parameters
+                .insert(GRAPH_DATA_REVISION_PARAM_KEY.to_string(), digest.clone());
+        }
 
         if self.settings.verify_signature {
             let reference = reference.ok_or_else(|| {
@@ -329,6 +338,15 @@ impl InternalPlugin for DkrV2OpenshiftSecondaryMetadataScraperPlugin {
     }
 }
 
+#[async_trait]
+impl InternalPlugin for DkrV2OpenshiftSecondaryMetadataScraperPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        self.scrape(io).await.map_err(ScrapeError::graph_data)
+    }
+}
+
 impl DkrV2OpenshiftSecondaryMetadataScraperPlugin {
     async fn are_layers_cached(&self, layers: &[String], io: &mut InternalIO) -> Fallible<bool> {
         let state = self.state.lock().await;
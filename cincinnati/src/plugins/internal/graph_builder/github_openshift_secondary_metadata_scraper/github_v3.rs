@@ -4,6 +4,9 @@
 
 use serde::Deserialize;
 
+/// Default base URL of the GitHub API.
+pub static DEFAULT_API_BASE: &str = "https://api.github.com";
+
 /// Commit structure.
 #[derive(Default, Clone, Debug, Deserialize, PartialEq, Eq)]
 pub(crate) struct Commit {
@@ -20,18 +23,20 @@ pub(crate) struct Branch {
 }
 
 /// Format the URL to request branch information.
-pub(crate) fn branches_url(org: &str, repo: &str) -> String {
+pub(crate) fn branches_url(api_base: &str, org: &str, repo: &str) -> String {
     format!(
-        "https://api.github.com/repos/{org}/{repo}/branches",
+        "{api_base}/repos/{org}/{repo}/branches",
+        api_base = api_base.trim_end_matches('/'),
         org = &org,
         repo = &repo,
     )
 }
 
 /// Format the URL to request a tarball URL.
-pub(crate) fn tarball_url(org: &str, repo: &str, commit: &Commit) -> String {
+pub(crate) fn tarball_url(api_base: &str, org: &str, repo: &str, commit: &Commit) -> String {
     format!(
-        "https://api.github.com/repos/{org}/{repo}/tarball/{sha}",
+        "{api_base}/repos/{org}/{repo}/tarball/{sha}",
+        api_base = api_base.trim_end_matches('/'),
         org = org,
         repo = repo,
         sha = commit.sha,
@@ -43,11 +48,26 @@ pub(crate) fn archive_entry_directory_name(org: &str, repo: &str, commit: &Commi
     format!("{}-{}-{}", &org, &repo, &commit.sha[0..7],)
 }
 
+/// Format the URI of a repository, e.g. `git+https://github.com/openshift/cincinnati-graph-data`.
+pub(crate) fn repository_uri(api_base: &str, org: &str, repo: &str) -> String {
+    let api_base = api_base.trim_end_matches('/');
+    let web_base = if api_base == DEFAULT_API_BASE {
+        "https://github.com"
+    } else {
+        // GitHub Enterprise serves its API under `/api/v3`.
+        api_base.trim_end_matches("/api/v3")
+    };
+    format!("git+{}/{}/{}", web_base, org, repo)
+}
+
 /// Format a commit URL
-pub(crate) fn commit_url(org: &str, repo: &str, sha: &str) -> String {
+pub(crate) fn commit_url(api_base: &str, org: &str, repo: &str, sha: &str) -> String {
     format!(
-        "https://api.github.com/repos/{}/{}/commits/{}",
-        org, repo, sha
+        "{}/repos/{}/{}/commits/{}",
+        api_base.trim_end_matches('/'),
+        org,
+        repo,
+        sha
     )
 }
 
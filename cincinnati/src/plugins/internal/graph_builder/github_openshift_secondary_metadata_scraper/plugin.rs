@@ -6,7 +6,10 @@ use crate as cincinnati;
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
 
-use commons::{GRAPH_DATA_DIR_PARAM_KEY, SECONDARY_METADATA_PARAM_KEY};
+use commons::{
+    ScrapeError, GRAPH_DATA_DIR_PARAM_KEY, GRAPH_DATA_REVISION_PARAM_KEY,
+    GRAPH_DATA_SOURCE_PARAM_KEY, SECONDARY_METADATA_PARAM_KEY,
+};
 use tokio::sync::Mutex as FuturesMutex;
 
 pub static DEFAULT_OUTPUT_ALLOWLIST: &[&str] = &[
@@ -26,6 +29,12 @@ pub static GITHUB_SCRAPER_TOKEN_PATH_ENV: &str = "CINCINNATI_GITHUB_SCRAPER_OAUT
 
 static USER_AGENT: &str = "openshift/cincinnati";
 
+/// Default maximum size of a downloaded response, in bytes.
+pub const DEFAULT_MAX_DOWNLOAD_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Default maximum size of the decompressed tarball, in bytes.
+pub const DEFAULT_MAX_EXTRACTED_BYTES: u64 = 512 * 1024 * 1024;
+
 /// Models the scrape mode
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
@@ -68,6 +77,10 @@ impl TryFrom<(Option<&String>, Option<&String>)> for Reference {
 #[derive(Debug, SmartDefault, Clone, Deserialize)]
 #[serde(default)]
 pub struct GithubOpenshiftSecondaryMetadataScraperSettings {
+    /// Base URL of the GitHub API.
+    #[default(github_v3::DEFAULT_API_BASE.to_string())]
+    api_base: String,
+
     github_org: String,
     github_repo: String,
     output_directory: PathBuf,
@@ -90,6 +103,14 @@ pub struct GithubOpenshiftSecondaryMetadataScraperSettings {
     #[default(DEFAULT_OUTPUT_ALLOWLIST.iter().map(|s| (*s).to_string()).collect())]
     output_allowlist: Vec<String>,
     oauth_token_path: Option<PathBuf>,
+
+    /// Maximum size of a downloaded response, e.g. the tarball, in bytes. Unlimited if 0.
+    #[default(DEFAULT_MAX_DOWNLOAD_BYTES)]
+    max_download_bytes: u64,
+
+    /// Maximum size of the decompressed tarball, in bytes. Unlimited if 0.
+    #[default(DEFAULT_MAX_EXTRACTED_BYTES)]
+    max_extracted_bytes: u64,
 }
 
 impl GithubOpenshiftSecondaryMetadataScraperSettings {
@@ -100,6 +121,7 @@ impl GithubOpenshiftSecondaryMetadataScraperSettings {
             .try_into()
             .context(format!("Deserializing {:#?}", &cfg))?;
 
+        ensure!(!settings.api_base.is_empty(), "empty api_base");
         ensure!(!settings.github_org.is_empty(), "empty github_org");
         ensure!(!settings.github_repo.is_empty(), "empty github_repo");
 
@@ -126,6 +148,14 @@ impl GithubOpenshiftSecondaryMetadataScraperSettings {
 
         Ok(Box::new(settings))
     }
+
+    fn max_download_bytes(&self) -> Option<u64> {
+        Some(self.max_download_bytes).filter(|bytes| *bytes > 0)
+    }
+
+    fn max_extracted_bytes(&self) -> Option<u64> {
+        Some(self.max_extracted_bytes).filter(|bytes| *bytes > 0)
+    }
 }
 
 #[derive(Debug, Default)]
@@ -201,13 +231,19 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
             data_dir,
 
             state: FuturesMutex::new(State::default()),
-            client: reqwest::Client::default(),
+            client: commons::http::HttpClientBuilder::new()
+                .user_agent(USER_AGENT)
+                .build()?,
         })
     }
 
     /// Lookup the latest commit on the given branch.
     async fn get_commit_wanted_branch(&self, branch_wanted: &str) -> Fallible<github_v3::Commit> {
-        let url = github_v3::branches_url(&self.settings.github_org, &self.settings.github_repo);
+        let url = github_v3::branches_url(
+            &self.settings.api_base,
+            &self.settings.github_org,
+            &self.settings.github_repo,
+        );
 
         trace!("Getting branches from {}", &url);
 
@@ -215,7 +251,6 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
             let request = self
                 .client
                 .get(&url)
-                .header(reqwest::header::USER_AGENT, USER_AGENT)
                 .header(reqwest::header::ACCEPT, "application/vnd.github.v3+json");
             if let Some(token) = &self.oauth_token {
                 request.header(reqwest::header::AUTHORIZATION, format!("token {}", token))
@@ -224,11 +259,12 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
             }
         };
 
-        let bytes = request
+        commons::budget::acquire_url(&url).await?;
+        let response = request
             .send()
             .await
-            .context(format!("Getting branches from {}", &url))?
-            .bytes()
+            .context(format!("Getting branches from {}", &url))?;
+        let bytes = commons::download::read_body(response, self.settings.max_download_bytes())
             .await
             .context(format!("Getting bytes from request to {}", &url))?;
 
@@ -270,6 +306,7 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
     async fn get_commit_wanted_revision(&self, revision: &str) -> github_v3::Commit {
         github_v3::Commit {
             url: github_v3::commit_url(
+                &self.settings.api_base,
                 &self.settings.github_org,
                 &self.settings.github_repo,
                 revision,
@@ -308,27 +345,28 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
         };
 
         let url = github_v3::tarball_url(
+            &self.settings.api_base,
             &self.settings.github_org,
             &self.settings.github_repo,
             &commit_wanted,
         );
 
         trace!("Downloading {:?} from {}", &commit_wanted, &url);
-        reqwest::Client::new()
+        commons::budget::acquire_url(&url).await?;
+        let response = self
+            .client
             .get(&url)
-            .header(reqwest::header::USER_AGENT, USER_AGENT)
             .header(reqwest::header::ACCEPT, "application/vnd.github.v3.raw")
             .send()
             .await
-            .context(format!("Updating from tarball at {}", &url))?
-            .bytes()
+            .context(format!("Updating from tarball at {}", &url))?;
+        commons::download::read_body(response, self.settings.max_download_bytes())
             .await
             .context(format!(
                 "Getting bytes from the request response to {}",
                 &url,
             ))
-            .map_err(Into::into)
-            .map(|bytes| (commit_wanted, bytes.to_vec().into_boxed_slice()))
+            .map(|bytes| (commit_wanted, bytes.into_boxed_slice()))
     }
 
     /// Extract a given blob to the output directory, adhering to the output allowlist, and finally update the completed commit state.
@@ -340,14 +378,20 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
             let commit = commit.clone();
             let output_allowlist = self.output_allowlist.clone();
             let tmpdir = tmpdir.path().to_owned();
+            let max_extracted_bytes = self.settings.max_extracted_bytes().unwrap_or(u64::MAX);
 
             tokio::task::spawn_blocking(move || -> Fallible<()> {
+                use commons::download::LimitedReader;
                 use flate2::read::GzDecoder;
                 use tar::Archive;
 
-                let mut archive = Archive::new(GzDecoder::new(bytes.as_ref()));
+                let msg = format!("Extracting the tarball from commit {:?}", &commit);
+                let mut archive = Archive::new(LimitedReader::new(
+                    GzDecoder::new(bytes.as_ref()),
+                    max_extracted_bytes,
+                ));
 
-                archive
+                let extracted = archive
                     .entries()?
                     .filter_map(move |entry_result| match entry_result {
                         Ok(entry) => {
@@ -386,7 +430,11 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
                         };
 
                         Ok(())
-                    })
+                    });
+
+                // A truncated tarball must not be taken for complete graph-data.
+                archive.into_inner().check().context(msg)?;
+                extracted
             })
             .await??
         };
@@ -442,11 +490,9 @@ impl PluginSettings for GithubOpenshiftSecondaryMetadataScraperSettings {
     }
 }
 
-#[async_trait]
-impl InternalPlugin for GithubOpenshiftSecondaryMetadataScraperPlugin {
-    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
-
-    async fn run_internal(&self, mut io: InternalIO) -> Fallible<InternalIO> {
+impl GithubOpenshiftSecondaryMetadataScraperPlugin {
+    /// Fetch the graph-data, recording its location in the parameters of `io`.
+    async fn scrape(&self, mut io: InternalIO) -> Fallible<InternalIO> {
         io.parameters.insert(
             GRAPH_DATA_DIR_PARAM_KEY.to_string(),
             self.data_dir
@@ -505,10 +551,187 @@ impl InternalPlugin for GithubOpenshiftSecondaryMetadataScraperPlugin {
             );
         };
 
+        if let Some(commit) = &self.state.lock().await.commit_completed {
+            io.parameters.insert(
+                GRAPH_DATA_SOURCE_PARAM_KEY.to_string(),
+                github_v3::repository_uri(
+                    &self.settings.api_base,
+                    &self.settings.github_org,
+                    &self.settings.github_repo,
+                ),
+            );
+            io.parameters.insert(
+                GRAPH_DATA_REVISION_PARAM_KEY.to_string(),
+                commit.sha.clone(),
+            );
+        }
+
         Ok(io)
     }
 }
 
+#[async_trait]
+impl InternalPlugin for GithubOpenshiftSecondaryMetadataScraperPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        self.scrape(io).await.map_err(ScrapeError::graph_data)
+    }
+}
+
+#[cfg(test)]
+mod replay_tests {
+    use super::*;
+    use commons::recorder::{Cassette, Interaction, RecordedRequest, RecordedResponse, Recorder};
+
+    static SHA: &str = "6420f7fbf3724e1e5e329ae8d1e2985973f60c14";
+
+    fn interaction(path: &str, body: Vec<u8>) -> Interaction {
+        Interaction {
+            request: RecordedRequest {
+                method: "GET".to_string(),
+                path: path.to_string(),
+            },
+            response: RecordedResponse::new(200, vec![], body),
+        }
+    }
+
+    fn tarball(files: &[(&str, &str)]) -> Fallible<Vec<u8>> {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut archive = tar::Builder::new(encoder);
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive.append_data(
+                &mut header,
+                format!("openshift-cincinnati-graph-data-{}/{}", &SHA[0..7], path),
+                content.as_bytes(),
+            )?;
+        }
+        Ok(archive.into_inner()?.finish()?)
+    }
+
+    #[test]
+    fn replay_branch_scrape() -> Fallible<()> {
+        let runtime = commons::testing::init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+
+        let cassette_path = tmpdir.path().join("github.json");
+        Cassette {
+            interactions: vec![
+                interaction(
+                    "/repos/openshift/cincinnati-graph-data/branches",
+                    serde_json::to_vec(&serde_json::json!([{
+                        "name": "master",
+                        "commit": { "sha": SHA, "url": "" },
+                        "protected": true,
+                    }]))?,
+                ),
+                interaction(
+                    &format!("/repos/openshift/cincinnati-graph-data/tarball/{}", SHA),
+                    tarball(&[
+                        ("version", "1.0.0"),
+                        ("channels/stable-4.14.yaml", "name: stable-4.14"),
+                        ("README.md", "not allowed"),
+                    ])?,
+                ),
+            ],
+        }
+        .save(&cassette_path)?;
+        let recorder = Recorder::replay(&cassette_path)?;
+
+        let output_directory = tmpdir.path().join("output");
+        let settings = GithubOpenshiftSecondaryMetadataScraperSettings::deserialize_config(
+            toml::Value::from_str(&format!(
+                r#"
+                    api_base = "{}"
+                    github_org = "openshift"
+                    github_repo = "cincinnati-graph-data"
+                    output_directory = {:?}
+                "#,
+                recorder.url(),
+                &output_directory,
+            ))?,
+        )?;
+        let plugin = settings.build_plugin(None)?;
+
+        let io = runtime.block_on(plugin.run(cincinnati::plugins::PluginIO::InternalIO(
+            InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+            },
+        )))?;
+        let io: InternalIO = io.try_into()?;
+
+        let data_dir = PathBuf::from(&io.parameters[GRAPH_DATA_DIR_PARAM_KEY]);
+        assert_eq!(std::fs::read_to_string(data_dir.join("version"))?, "1.0.0");
+        assert!(data_dir.join("channels/stable-4.14.yaml").exists());
+        assert!(!data_dir.join("README.md").exists());
+        assert!(io.parameters.contains_key(SECONDARY_METADATA_PARAM_KEY));
+        assert_eq!(io.parameters[GRAPH_DATA_REVISION_PARAM_KEY], SHA);
+        assert_eq!(
+            io.parameters[GRAPH_DATA_SOURCE_PARAM_KEY],
+            format!("git+{}/openshift/cincinnati-graph-data", recorder.url())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_large_tarballs() -> Fallible<()> {
+        let runtime = commons::testing::init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+
+        let cassette_path = tmpdir.path().join("github.json");
+        Cassette {
+            interactions: vec![interaction(
+                &format!("/repos/openshift/cincinnati-graph-data/tarball/{}", SHA),
+                tarball(&[
+                    ("version", "1.0.0"),
+                    ("raw/metadata.json", &"0".repeat(64 * 1024)),
+                ])?,
+            )],
+        }
+        .save(&cassette_path)?;
+        let recorder = Recorder::replay(&cassette_path)?;
+
+        let settings = GithubOpenshiftSecondaryMetadataScraperSettings::deserialize_config(
+            toml::Value::from_str(&format!(
+                r#"
+                    api_base = "{}"
+                    github_org = "openshift"
+                    github_repo = "cincinnati-graph-data"
+                    reference_revision = "{}"
+                    output_directory = {:?}
+                    max_extracted_bytes = 16384
+                "#,
+                recorder.url(),
+                SHA,
+                tmpdir.path().join("output"),
+            ))?,
+        )?;
+        let plugin = settings.build_plugin(None)?;
+
+        let err = runtime
+            .block_on(
+                plugin.run(cincinnati::plugins::PluginIO::InternalIO(InternalIO {
+                    graph: Default::default(),
+                    parameters: Default::default(),
+                })),
+            )
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("exceeds the limit of 16384 bytes"),
+            "{:#}",
+            err
+        );
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "test-net")]
 mod network_tests {
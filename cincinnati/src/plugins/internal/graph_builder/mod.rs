@@ -3,7 +3,11 @@
 pub mod dkrv2_openshift_secondary_metadata_scraper;
 pub mod github_openshift_secondary_metadata_scraper;
 pub mod openshift_secondary_metadata_parser;
+pub mod release_scrape;
 pub mod release_scrape_dockerv2;
 
+pub mod build_phases;
+pub mod release_source;
+
 pub mod commons;
 pub mod release;
@@ -5,17 +5,110 @@ use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
 
 use crate::conditional_edges::{ConditionalEdge, ConditionalUpdateEdge, ConditionalUpdateRisk};
+use crate::plugins::internal::edge_add_remove::{
+    PREVIOUS_REMOVE_REGEX_FROM_KEY, PREVIOUS_REMOVE_REGEX_UNTIL_KEY,
+};
 use commons::GRAPH_DATA_DIR_PARAM_KEY;
-use std::collections::HashSet;
+use std::fmt;
 use std::path::Path;
 
 pub static DEFAULT_KEY_FILTER: &str = "io.openshift.upgrades.graph";
-static SUPPORTED_VERSIONS: &[&str] = &["1.0.0", "1.1.0", "1.2.0"];
+
+/// Versions of the current graph-data schema.
+static CURRENT_VERSIONS: &[&str] = &["1.0.0", "1.1.0", "1.2.0"];
+
+/// Versions of the next graph-data schema.
+static NEXT_VERSIONS: &[&str] = &["2.0.0"];
+
+/// Schema of the graph-data, as declared by the `version` file of a directory.
+///
+/// Both the current and the next schema are supported, so that the
+/// graph-data can move to the next schema without upgrading the
+/// graph-builder in lockstep. Deprecated constructs are accepted by the
+/// current schema, and counted, and rejected by the next one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemaVersion {
+    /// Schema 1.x, accepting the deprecated constructs.
+    Current,
+    /// Schema 2.x, rejecting the deprecated constructs.
+    Next,
+}
+
+impl SchemaVersion {
+    /// Return the schema of the graph-data `version`, if supported.
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim();
+        if CURRENT_VERSIONS.contains(&version) {
+            Some(SchemaVersion::Current)
+        } else if NEXT_VERSIONS.contains(&version) {
+            Some(SchemaVersion::Next)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the schema accepts the deprecated constructs.
+    pub fn accepts_deprecated(self) -> bool {
+        self == SchemaVersion::Current
+    }
+}
+
+/// Constructs of the current schema removed from the next one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeprecatedConstruct {
+    /// Blocked edge whose target encodes the s390x architecture in its
+    /// pre-release, e.g. `4.2.0-s390x` instead of `4.2.0+s390x`.
+    S390xPrerelease,
+    /// Channel whose name differs from the name of its file.
+    ChannelNameMismatch,
+}
+
+/// All deprecated constructs.
+static DEPRECATED_CONSTRUCTS: &[DeprecatedConstruct] = &[
+    DeprecatedConstruct::S390xPrerelease,
+    DeprecatedConstruct::ChannelNameMismatch,
+];
+
+impl DeprecatedConstruct {
+    /// Name of the construct, as used in metrics.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::S390xPrerelease => "s390x_prerelease",
+            Self::ChannelNameMismatch => "channel_name_mismatch",
+        }
+    }
+
+    /// Replacement of the construct in the next schema.
+    fn replacement(self) -> &'static str {
+        match self {
+            Self::S390xPrerelease => "declare the architecture as build metadata, e.g. '+s390x'",
+            Self::ChannelNameMismatch => "name the file after the channel",
+        }
+    }
+
+    /// Return the deprecated construct used by the blocked edge towards `to`, if any.
+    fn of_blocked_edge(to: &semver::Version) -> Option<Self> {
+        let special_case_s390x = vec![semver::Identifier::AlphaNumeric("s390x".to_string())];
+        if to.build.is_empty() && to.pre == special_case_s390x {
+            Some(Self::S390xPrerelease)
+        } else {
+            None
+        }
+    }
+
+    /// Return the deprecated construct used by the channel file at `path`, if any.
+    fn of_channel(path: &Path, channel: &graph_data_model::Channel) -> Option<Self> {
+        match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) if stem != channel.name => Some(Self::ChannelNameMismatch),
+            _ => None,
+        }
+    }
+}
 
 pub mod graph_data_model {
     //! This module contains the data types corresponding to the graph data files.
 
-    use super::cincinnati::ClusterCondition;
+    use super::cincinnati::{ActivationSchedule, ClusterCondition};
     use serde::de::Visitor;
     use serde::Deserialize;
     use serde::Deserializer;
@@ -25,6 +118,8 @@ pub mod graph_data_model {
     pub struct BlockedEdge {
         pub to: semver::Version,
         pub from: RegexWrapper,
+        #[serde(default, deserialize_with = "deserialize_schedule")]
+        pub schedule: Option<ActivationSchedule>,
     }
 
     /// Represents the conditional edges files in the data repository.
@@ -37,6 +132,22 @@ pub mod graph_data_model {
         pub message: String,
         #[serde(rename = "matchingRules")]
         pub matching_rules: Vec<ClusterCondition>,
+        #[serde(default, deserialize_with = "deserialize_schedule")]
+        pub schedule: Option<ActivationSchedule>,
+    }
+
+    /// Deserialize a schedule, rejecting invalid or out-of-order timestamps.
+    fn deserialize_schedule<'de, D>(deserializer: D) -> Result<Option<ActivationSchedule>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let schedule = Option::<ActivationSchedule>::deserialize(deserializer)?;
+        if let Some(schedule) = &schedule {
+            schedule
+                .validate()
+                .map_err(|e| serde::de::Error::custom(format!("invalid schedule: {:#}", e)))?;
+        }
+        Ok(schedule)
     }
 
     /// New type used to implement Deserialize for regex::Regex so we can use it in the `BlockedEdge` struct
@@ -74,9 +185,9 @@ pub mod graph_data_model {
 
                 fn expecting(
                     &self,
-                    _: &mut std::fmt::Formatter<'_>,
+                    formatter: &mut std::fmt::Formatter<'_>,
                 ) -> std::result::Result<(), std::fmt::Error> {
-                    panic!("impl of Visitor::expecting for RegexVisitor should not be required for deserialization.")
+                    formatter.write_str("a regular expression string")
                 }
             }
 
@@ -89,6 +200,9 @@ pub mod graph_data_model {
     pub struct Channel {
         pub name: String,
         pub versions: Vec<semver::Version>,
+        /// Group of the channel, instead of the one derived from its name.
+        #[serde(default)]
+        pub group: Option<String>,
     }
 
     /// Represents the raw metadata file in the data repository.
@@ -132,29 +246,42 @@ pub struct OpenshiftSecondaryMetadataParserSettings {
 
     /// This field is used to define errors which are not tolerated while processing the files.
     /// See the `DeserializeDirectoryFilesError` enum for possible options.
+    /// All files are processed before failing, so that every error is reported.
     disallowed_errors: std::collections::HashSet<DeserializeDirectoryFilesErrorDiscriminants>,
+
+    /// Record the channel groups of releases in their metadata.
+    channel_group_metadata: bool,
+
+    /// Directories overlaid on the data directory, in increasing order of
+    /// precedence, e.g. local emergency overrides. Missing directories and
+    /// files are skipped.
+    overlay_directories: Vec<PathBuf>,
 }
 
 impl OpenshiftSecondaryMetadataParserSettings {
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
         let settings: Self = cfg.try_into()?;
-
-        ensure!(!settings.key_prefix.is_empty(), "empty key_prefix");
-        ensure!(!settings.default_arch.is_empty(), "empty default_arch");
+        settings.validate()?;
 
         Ok(Box::new(settings))
     }
 }
 
 /// Plugin.
-#[derive(Debug)]
+#[derive(CustomDebug)]
 pub struct OpenshiftSecondaryMetadataParserPlugin {
     settings: OpenshiftSecondaryMetadataParserSettings,
 
     #[allow(dead_code)]
     // Stores the result of the last run
     state: state::State,
+
+    #[debug(skip)]
+    graph_data_file_errors: Option<prometheus::IntCounterVec>,
+
+    #[debug(skip)]
+    graph_data_deprecated_constructs: Option<prometheus::IntCounterVec>,
 }
 
 impl OpenshiftSecondaryMetadataParserPlugin {
@@ -162,15 +289,63 @@ impl OpenshiftSecondaryMetadataParserPlugin {
         Self {
             settings,
             state: state::new(),
+            graph_data_file_errors: None,
+            graph_data_deprecated_constructs: None,
         }
     }
+
+    /// Create the plugin, counting the invalid graph-data files and the
+    /// deprecated constructs it finds in `prometheus_registry`.
+    pub fn try_new(
+        settings: OpenshiftSecondaryMetadataParserSettings,
+        prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let mut plugin = Self::new(settings);
+
+        if let Some(prometheus_registry) = prometheus_registry {
+            let graph_data_file_errors = prometheus::IntCounterVec::new(
+                prometheus::Opts::new(
+                    "graph_data_file_errors_total",
+                    "Total number of errors found in graph-data files, by kind",
+                ),
+                &["kind"],
+            )?;
+            for kind in DESERIALIZE_DIRECTORY_FILES_ERROR_KINDS {
+                graph_data_file_errors.with_label_values(&[kind.as_str()]);
+            }
+            prometheus_registry.register(Box::new(graph_data_file_errors.clone()))?;
+            plugin.graph_data_file_errors = Some(graph_data_file_errors);
+
+            let graph_data_deprecated_constructs = prometheus::IntCounterVec::new(
+                prometheus::Opts::new(
+                    "graph_data_deprecated_constructs_total",
+                    "Total number of deprecated constructs found in graph-data files, by construct",
+                ),
+                &["construct"],
+            )?;
+            for construct in DEPRECATED_CONSTRUCTS {
+                graph_data_deprecated_constructs.with_label_values(&[construct.as_str()]);
+            }
+            prometheus_registry.register(Box::new(graph_data_deprecated_constructs.clone()))?;
+            plugin.graph_data_deprecated_constructs = Some(graph_data_deprecated_constructs);
+        }
+
+        Ok(plugin)
+    }
 }
 
 impl PluginSettings for OpenshiftSecondaryMetadataParserSettings {
-    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
-        let plugin = OpenshiftSecondaryMetadataParserPlugin::new(self.clone());
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = OpenshiftSecondaryMetadataParserPlugin::try_new(self.clone(), registry)?;
         Ok(new_plugin!(InternalPluginWrapper(plugin)))
     }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.key_prefix.is_empty(), "empty key_prefix");
+        ensure!(!self.default_arch.is_empty(), "empty default_arch");
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Fail, strum_macros::EnumDiscriminants)]
@@ -188,13 +363,119 @@ pub enum DeserializeDirectoryFilesError {
 
     #[error("Failed to deserialize {0:?}: {1:#?}")]
     Deserialize(PathBuf, serde_yaml::Error),
+
+    #[error("{0:?}: uses the construct {1:?}, removed from the graph-data schema")]
+    Deprecated(PathBuf, DeprecatedConstruct),
+}
+
+/// All kinds of errors found in graph-data files.
+static DESERIALIZE_DIRECTORY_FILES_ERROR_KINDS: &[DeserializeDirectoryFilesErrorDiscriminants] = &[
+    DeserializeDirectoryFilesErrorDiscriminants::File,
+    DeserializeDirectoryFilesErrorDiscriminants::InvalidExtension,
+    DeserializeDirectoryFilesErrorDiscriminants::MissingExtension,
+    DeserializeDirectoryFilesErrorDiscriminants::Deserialize,
+    DeserializeDirectoryFilesErrorDiscriminants::Deprecated,
+];
+
+impl DeserializeDirectoryFilesErrorDiscriminants {
+    /// Name of the error kind, as used in `disallowed_errors`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::File => "file",
+            Self::InvalidExtension => "invalid_extension",
+            Self::MissingExtension => "missing_extension",
+            Self::Deserialize => "deserialize",
+            Self::Deprecated => "deprecated",
+        }
+    }
+}
+
+impl DeserializeDirectoryFilesError {
+    /// Path of the invalid file.
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::File(path, _)
+            | Self::InvalidExtension(path, _)
+            | Self::MissingExtension(path)
+            | Self::Deserialize(path, _)
+            | Self::Deprecated(path, _) => path,
+        }
+    }
+
+    /// Line and column of the error in the YAML document, starting at 1, if known.
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::Deserialize(_, e) => e.location().map(|loc| (loc.line(), loc.column())),
+            _ => None,
+        }
+    }
+
+    /// Reason of the error, without the file path.
+    pub fn reason(&self) -> String {
+        match self {
+            Self::File(_, e) => e.to_string(),
+            Self::InvalidExtension(_, extension) => format!("invalid extension {:?}", extension),
+            Self::MissingExtension(_) => "missing extension".to_string(),
+            Self::Deserialize(_, e) => e.to_string(),
+            Self::Deprecated(_, construct) => format!(
+                "{} is not supported by this graph-data version: {}",
+                construct.as_str(),
+                construct.replacement()
+            ),
+        }
+    }
+}
+
+/// Errors found in the graph-data files, reported together once all files
+/// have been processed.
+#[derive(Debug, Default)]
+pub struct GraphDataErrors {
+    /// Errors, sorted by file path.
+    pub errors: Vec<DeserializeDirectoryFilesError>,
+}
+
+impl GraphDataErrors {
+    fn extend(&mut self, errors: Vec<DeserializeDirectoryFilesError>) {
+        self.errors.extend(errors);
+        self.errors.sort_by(|a, b| a.path().cmp(b.path()));
+    }
 }
 
+impl fmt::Display for GraphDataErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error(s) in graph-data files:", self.errors.len())?;
+        for error in &self.errors {
+            write!(f, "\n  {}", error.path().display())?;
+            if let Some((line, column)) = error.location() {
+                write!(f, ":{}:{}", line, column)?;
+            }
+            write!(f, ": {}", error.reason())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for GraphDataErrors {}
+
+/// Deserialize the files of `path` whose extension matches `extension_re`.
+///
+/// Invalid files are skipped, and returned as errors along with the valid ones.
 pub async fn deserialize_directory_files<T>(
     path: &Path,
     extension_re: regex::Regex,
-    disallowed_errors: &HashSet<DeserializeDirectoryFilesErrorDiscriminants>,
-) -> Fallible<Vec<T>>
+) -> Fallible<(Vec<T>, Vec<DeserializeDirectoryFilesError>)>
+where
+    T: DeserializeOwned + 'static,
+{
+    let (files, errors) = deserialize_directory_files_with_paths(path, extension_re).await?;
+    Ok((files.into_iter().map(|(_, value)| value).collect(), errors))
+}
+
+/// Like `deserialize_directory_files`, returning the path of each valid file along with its value.
+async fn deserialize_directory_files_with_paths<T>(
+    path: &Path,
+    extension_re: regex::Regex,
+) -> Fallible<(Vec<(PathBuf, T)>, Vec<DeserializeDirectoryFilesError>)>
 where
     T: DeserializeOwned + 'static,
 {
@@ -211,27 +492,15 @@ where
 
     // Even though we don't use concurrent threads, the usage of async forces us
     // to guarantee that the error container is thread-safe
-    let error: Arc<Mutex<Option<Error>>> = Arc::new(Mutex::new(None));
+    let errors: Arc<Mutex<Vec<DeserializeDirectoryFilesError>>> = Default::default();
     macro_rules! commit_error {
         ($error_var:ident, $e:expr) => {
-            let e = $e;
-            let variant: DeserializeDirectoryFilesErrorDiscriminants = (&e).into();
-            if disallowed_errors.contains(&variant) {
-                match $error_var.lock() {
-                    Ok(mut guard) => {
-                        let inner = if let Some(previous_error) = std::mem::replace(&mut *guard, None) {
-                            previous_error.context(e)
-                        } else {
-                            Error::from(e)
-                        };
-                        *guard = Some(inner);
-
-                    },
-                    Err(e) => {
-                        error!("the thread holding the error lock has paniced: {}", e);
-                    }
-                };
-            }
+            match $error_var.lock() {
+                Ok(mut guard) => guard.push($e),
+                Err(e) => {
+                    error!("the thread holding the error lock has paniced: {}", e);
+                }
+            };
         };
     }
 
@@ -249,7 +518,7 @@ where
                     Some(path)
                 } else {
                     commit_error!(
-                        error,
+                        errors,
                         DeserializeDirectoryFilesError::InvalidExtension(
                             path.clone(),
                             extension_str.to_string(),
@@ -261,16 +530,15 @@ where
             } else {
                 debug!("{:?} does not have an extension", &path);
                 commit_error!(
-                    error,
+                    errors,
                     DeserializeDirectoryFilesError::MissingExtension(path)
                 );
                 None
             }
         }
         Err(e) => {
-            warn!("{}", e);
             commit_error!(
-                error,
+                errors,
                 DeserializeDirectoryFilesError::File(path.to_path_buf(), e)
             );
             None
@@ -285,7 +553,7 @@ where
     while let Some(path) = paths.next().await {
         match tokio::fs::read(&path).await {
             Ok(yaml) => match serde_yaml::from_slice(&yaml) {
-                Ok(value) => t_vec.push(value),
+                Ok(value) => t_vec.push((path, value)),
                 Err(e) => {
                     let mut old_block: Option<graph_data_model::BlockedEdge> = None;
                     if is_conditional_edge {
@@ -300,31 +568,45 @@ where
                         };
                     }
                     if old_block.is_none() {
-                        warn!("Failed to deserialize file at {:?}: {}", &path, e);
-                        commit_error!(error, DeserializeDirectoryFilesError::Deserialize(path, e));
+                        commit_error!(errors, DeserializeDirectoryFilesError::Deserialize(path, e));
                     }
                 }
             },
             Err(e) => {
-                warn!("Couldn't read file {:?}: {}", &path, e);
-                commit_error!(error, DeserializeDirectoryFilesError::File(path, e));
+                commit_error!(errors, DeserializeDirectoryFilesError::File(path, e));
             }
         }
     }
 
-    if let Some(error) = Arc::try_unwrap(error)
+    let errors = Arc::try_unwrap(errors)
         .map_err(|_| Error::msg("could not unwrap the error container"))?
-        .into_inner()?
-    {
-        bail!(error);
-    }
+        .into_inner()
+        .map_err(|_| Error::msg("the error container lock is poisoned"))?;
 
-    Ok(t_vec)
+    Ok((t_vec, errors))
 }
 
 pub static BLOCKED_EDGES_DIR: &str = "blocked-edges";
 pub static CHANNELS_DIR: &str = "channels";
 
+/// Source of graph data, and how it is merged with the previous ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Source {
+    /// The data directory, which must be complete.
+    Primary,
+    /// An overlay directory, overriding the data of the previous sources:
+    ///  * raw metadata values replace the previous values of their keys,
+    ///  * blocked edges replace the previous ones of their target version,
+    ///  * conditional edges replace the previous risks with the same target version and name,
+    ///  * channels replace the previous versions of the channels with the same name.
+    Overlay,
+}
+
+/// Whether `path` exists, i.e. whether an overlay provides it.
+async fn path_exists(path: &Path) -> bool {
+    tokio::fs::metadata(path).await.is_ok()
+}
+
 impl OpenshiftSecondaryMetadataParserPlugin {
     pub(crate) const PLUGIN_NAME: &'static str = "openshift-secondary-metadata-parse";
 
@@ -336,30 +618,78 @@ impl OpenshiftSecondaryMetadataParserPlugin {
         }
     }
 
-    async fn process_version(&self, data_dir: &Path) -> Fallible<String> {
+    async fn process_version(&self, data_dir: &Path) -> Fallible<SchemaVersion> {
         let path = data_dir.join("version");
         let version = tokio::fs::read(&path)
             .await
             .context(format!("Reading {:?}", &path))?;
         let string_version = String::from_utf8_lossy(&version);
 
-        if SUPPORTED_VERSIONS.contains(&string_version.trim()) {
-            Ok(string_version.into_owned())
-        } else {
-            Err(format_err!(
+        SchemaVersion::parse(&string_version).ok_or_else(|| {
+            format_err!(
                 "unrecognized graph-data version {}; supported versions: {:?}",
                 string_version,
-                SUPPORTED_VERSIONS
-            ))
+                CURRENT_VERSIONS
+                    .iter()
+                    .chain(NEXT_VERSIONS.iter())
+                    .collect::<Vec<_>>()
+            )
+        })
+    }
+
+    /// Check whether the file at `path` may use `construct` in the schema `version`.
+    ///
+    /// Deprecated constructs are counted when accepted, and returned as
+    /// errors otherwise.
+    fn check_deprecated(
+        &self,
+        version: SchemaVersion,
+        path: &Path,
+        construct: DeprecatedConstruct,
+    ) -> Option<DeserializeDirectoryFilesError> {
+        if !version.accepts_deprecated() {
+            return Some(DeserializeDirectoryFilesError::Deprecated(
+                path.to_path_buf(),
+                construct,
+            ));
         }
+
+        debug!(
+            "{:?} uses the deprecated construct {}",
+            path,
+            construct.as_str()
+        );
+        if let Some(graph_data_deprecated_constructs) = &self.graph_data_deprecated_constructs {
+            graph_data_deprecated_constructs
+                .with_label_values(&[construct.as_str()])
+                .inc();
+        }
+        None
+    }
+
+    /// Return the overlay directories which exist.
+    async fn overlay_directories(&self) -> Vec<PathBuf> {
+        let mut overlays = Vec::with_capacity(self.settings.overlay_directories.len());
+        for overlay in &self.settings.overlay_directories {
+            if path_exists(overlay).await {
+                overlays.push(overlay.clone());
+            } else {
+                warn!("skipping missing overlay directory {:?}", overlay);
+            }
+        }
+        overlays
     }
 
     async fn process_raw_metadata(
         &self,
         graph: &mut cincinnati::Graph,
         data_dir: &Path,
+        source: Source,
     ) -> Fallible<()> {
         let path = data_dir.join("raw/metadata.json");
+        if source == Source::Overlay && !path_exists(&path).await {
+            return Ok(());
+        }
         let json = tokio::fs::read(&path)
             .await
             .context(format!("Reading {:?}", &path))?;
@@ -387,13 +717,18 @@ impl OpenshiftSecondaryMetadataParserPlugin {
                         (Ok(release_semver), Ok(version_semver))
                             if release_semver == version_semver =>
                         {
-                            release.get_metadata_mut().map(|metadata| {
-                                metadata
-                                    .entry((*key).to_string())
-                                    .and_modify(|previous_add| {
-                                        *previous_add += &format!(",{}", &value)
-                                    })
-                                    .or_insert_with(|| (*value).to_string())
+                            release.get_metadata_mut().map(|metadata| match source {
+                                Source::Primary => {
+                                    metadata
+                                        .entry((*key).to_string())
+                                        .and_modify(|previous_add| {
+                                            *previous_add += &format!(",{}", &value)
+                                        })
+                                        .or_insert_with(|| (*value).to_string());
+                                }
+                                Source::Overlay => {
+                                    metadata.insert((*key).to_string(), (*value).to_string());
+                                }
                             });
                             true
                         }
@@ -410,18 +745,35 @@ impl OpenshiftSecondaryMetadataParserPlugin {
         &self,
         graph: &mut cincinnati::Graph,
         data_dir: &Path,
-    ) -> Fallible<()> {
+        source: Source,
+        version: SchemaVersion,
+    ) -> Fallible<Vec<DeserializeDirectoryFilesError>> {
         let blocked_edges_dir = data_dir.join(BLOCKED_EDGES_DIR);
-        let blocked_edges: Vec<graph_data_model::BlockedEdge> = deserialize_directory_files(
-            &blocked_edges_dir,
-            regex::Regex::new("ya+ml")?,
-            &self.settings.disallowed_errors,
-        )
-        .await
-        .context(format!(
-            "Reading blocked edges from {:?}",
-            blocked_edges_dir
-        ))?;
+        if source == Source::Overlay && !path_exists(&blocked_edges_dir).await {
+            return Ok(vec![]);
+        }
+        let (blocked_edges, mut errors): (Vec<(PathBuf, graph_data_model::BlockedEdge)>, _) =
+            deserialize_directory_files_with_paths(&blocked_edges_dir, regex::Regex::new("ya+ml")?)
+                .await
+                .context(format!(
+                    "Reading blocked edges from {:?}",
+                    blocked_edges_dir
+                ))?;
+        let blocked_edges: Vec<_> = blocked_edges
+            .into_iter()
+            .filter_map(|(path, blocked_edge)| {
+                let construct = DeprecatedConstruct::of_blocked_edge(&blocked_edge.to);
+                match construct
+                    .and_then(|construct| self.check_deprecated(version, &path, construct))
+                {
+                    Some(error) => {
+                        errors.push(error);
+                        None
+                    }
+                    None => Some(blocked_edge),
+                }
+            })
+            .collect();
 
         debug!(
             "Found {} valid blocked edges declarations.",
@@ -492,6 +844,7 @@ impl OpenshiftSecondaryMetadataParserPlugin {
                                 &to.to_string()
                             )) {
                                 Ok(metadata) => {
+                                    // Later sources replace the blocked edges of earlier ones.
                                     metadata.insert(
                                         format!(
                                             "{}.{}",
@@ -499,6 +852,29 @@ impl OpenshiftSecondaryMetadataParserPlugin {
                                         ),
                                         blocked_edge.from.to_string(),
                                     );
+                                    // Scheduled blocks are left to the policy-engine.
+                                    let schedule = blocked_edge.schedule.as_ref();
+                                    for (suffix, bound) in &[
+                                        (
+                                            PREVIOUS_REMOVE_REGEX_FROM_KEY,
+                                            schedule.and_then(|schedule| schedule.from.as_ref()),
+                                        ),
+                                        (
+                                            PREVIOUS_REMOVE_REGEX_UNTIL_KEY,
+                                            schedule.and_then(|schedule| schedule.until.as_ref()),
+                                        ),
+                                    ] {
+                                        let key =
+                                            format!("{}.{}", self.settings.key_prefix, suffix);
+                                        match bound {
+                                            Some(bound) => {
+                                                metadata.insert(key, bound.to_string());
+                                            }
+                                            None => {
+                                                metadata.remove(&key);
+                                            }
+                                        }
+                                    }
                                 }
                                 Err(e) => debug!("{}", e),
                             };
@@ -512,26 +888,29 @@ impl OpenshiftSecondaryMetadataParserPlugin {
                 Ok(())
             })?;
 
-        Ok(())
+        Ok(errors)
     }
 
     async fn process_conditional_edges(
         &self,
         graph: &mut cincinnati::Graph,
         data_dir: &Path,
+        source: Source,
+        version: SchemaVersion,
     ) -> Fallible<()> {
         let blocked_edges_dir = data_dir.join(BLOCKED_EDGES_DIR);
-        let conditional_edges: Vec<graph_data_model::ConditionalEdgeYaml> =
-            deserialize_directory_files(
-                &blocked_edges_dir,
-                regex::Regex::new("ya+ml")?,
-                &self.settings.disallowed_errors,
-            )
-            .await
-            .context(format!(
-                "looking for conditional edges in blocked edges {:?}",
-                blocked_edges_dir
-            ))?;
+        if source == Source::Overlay && !path_exists(&blocked_edges_dir).await {
+            return Ok(());
+        }
+        // Files invalid as conditional edges are also invalid as blocked edges,
+        // so their errors are already reported while processing blocked edges.
+        let (conditional_edges, _): (Vec<graph_data_model::ConditionalEdgeYaml>, _) =
+            deserialize_directory_files(&blocked_edges_dir, regex::Regex::new("ya+ml")?)
+                .await
+                .context(format!(
+                    "looking for conditional edges in blocked edges {:?}",
+                    blocked_edges_dir
+                ))?;
 
         debug!(
             "Found {} valid conditional edges declarations.",
@@ -540,6 +919,11 @@ impl OpenshiftSecondaryMetadataParserPlugin {
 
         conditional_edges
             .into_iter()
+            // Edges rejected by the schema are reported while processing blocked edges.
+            .filter(|cey| {
+                version.accepts_deprecated()
+                    || DeprecatedConstruct::of_blocked_edge(&cey.to).is_none()
+            })
             .try_for_each(|cey| -> Fallible<()> {
                 let ce: ConditionalEdge = ConditionalEdge {
                     edge_regex: ConditionalUpdateEdge {
@@ -552,9 +936,20 @@ impl OpenshiftSecondaryMetadataParserPlugin {
                         name: cey.name,
                         message: cey.message,
                         matching_rules: cey.matching_rules,
+                        schedule: cey.schedule,
                     }],
                 };
-                graph.conditional_edges.as_mut().unwrap().push(ce);
+                let conditional_edges = graph.conditional_edges.get_or_insert_with(Vec::new);
+                if source == Source::Overlay {
+                    conditional_edges.retain(|previous| {
+                        previous.edge_regex.to != ce.edge_regex.to
+                            || previous
+                                .risks
+                                .iter()
+                                .all(|risk| risk.name != ce.risks[0].name)
+                    });
+                }
+                conditional_edges.push(ce);
 
                 Ok(())
             })?;
@@ -564,19 +959,47 @@ impl OpenshiftSecondaryMetadataParserPlugin {
     async fn process_channels(
         &self,
         graph: &mut cincinnati::Graph,
-        data_dir: &Path,
-    ) -> Fallible<()> {
-        let channels_dir = data_dir.join(CHANNELS_DIR);
-        let channels: Vec<graph_data_model::Channel> = deserialize_directory_files(
-            &channels_dir,
-            regex::Regex::new("ya+ml")?,
-            &self.settings.disallowed_errors,
-        )
-        .await
-        .context(format!("Reading channels from {:?}", channels_dir))?;
-        debug!("Found {} valid channel declarations.", channels.len());
-
-        let channels_key = format!("{}.release.channels", self.settings.key_prefix);
+        sources: &[(&Path, Source, SchemaVersion)],
+    ) -> Fallible<Vec<DeserializeDirectoryFilesError>> {
+        let mut channels: Vec<graph_data_model::Channel> = Vec::new();
+        let mut errors = Vec::new();
+
+        for (dir, source, version) in sources {
+            let channels_dir = dir.join(CHANNELS_DIR);
+            if *source == Source::Overlay && !path_exists(&channels_dir).await {
+                continue;
+            }
+            let (source_channels, source_errors): (Vec<(PathBuf, graph_data_model::Channel)>, _) =
+                deserialize_directory_files_with_paths(&channels_dir, regex::Regex::new("ya+ml")?)
+                    .await
+                    .context(format!("Reading channels from {:?}", channels_dir))?;
+            debug!(
+                "Found {} valid channel declarations in {:?}.",
+                source_channels.len(),
+                dir
+            );
+            errors.extend(source_errors);
+            for (path, channel) in source_channels {
+                if let Some(error) = DeprecatedConstruct::of_channel(&path, &channel)
+                    .and_then(|construct| self.check_deprecated(*version, &path, construct))
+                {
+                    errors.push(error);
+                    continue;
+                }
+                // Channels of overlays replace the channels of the same name.
+                if *source == Source::Overlay {
+                    channels.retain(|previous| previous.name != channel.name);
+                }
+                channels.push(channel);
+            }
+        }
+
+        let channel_groups: cincinnati::MapImpl<String, String> = channels
+            .iter()
+            .filter_map(|channel| Some((channel.name.clone(), channel.group.clone()?)))
+            .collect();
+
+        let channels_key = crate::channels_key(&self.settings.key_prefix);
         channels.into_iter().for_each(|channel|
         // Find out for each channel
         {
@@ -642,10 +1065,11 @@ impl OpenshiftSecondaryMetadataParserPlugin {
                         // this has to match the sorting at
                         // https://github.com/openshift/cincinnati-graph-data/blob/5fc8dd0825b42369de8070ecba2ae0c49d0a99d9/hack/graph-util.py#L187
                         channels_split.sort_unstable();
+                        // Channel names without a dash sort first instead of panicking.
                         channels_split.sort_by(|a, b| {
-                            let a_split: Vec<&str> = a.splitn(2, '-').collect();
-                            let b_split: Vec<&str> = b.splitn(2, '-').collect();
-                            a_split[1].cmp(b_split[1])
+                            let a_suffix = a.splitn(2, '-').nth(1).unwrap_or_default();
+                            let b_suffix = b.splitn(2, '-').nth(1).unwrap_or_default();
+                            a_suffix.cmp(b_suffix)
                         });
                         *channels = channels_split.join(",")
                     })
@@ -657,6 +1081,42 @@ impl OpenshiftSecondaryMetadataParserPlugin {
             sorted_releases.len()
         );
 
+        if self.settings.channel_group_metadata {
+            cincinnati::channel_groups::set_group_metadata(
+                graph,
+                &self.settings.key_prefix,
+                &channel_groups,
+            );
+        }
+
+        Ok(errors)
+    }
+
+    /// Log and count the errors found in the graph-data files, failing if
+    /// any of them is disallowed.
+    fn check_errors(&self, errors: GraphDataErrors) -> Fallible<()> {
+        let (disallowed, allowed): (Vec<_>, Vec<_>) =
+            errors.errors.into_iter().partition(|error| {
+                let kind: DeserializeDirectoryFilesErrorDiscriminants = error.into();
+                self.settings.disallowed_errors.contains(&kind)
+            });
+
+        for error in allowed.iter().chain(disallowed.iter()) {
+            if let Some(graph_data_file_errors) = &self.graph_data_file_errors {
+                let kind: DeserializeDirectoryFilesErrorDiscriminants = error.into();
+                graph_data_file_errors
+                    .with_label_values(&[kind.as_str()])
+                    .inc();
+            }
+        }
+
+        if !allowed.is_empty() {
+            warn!("{}", GraphDataErrors { errors: allowed });
+        }
+        if !disallowed.is_empty() {
+            bail!(GraphDataErrors { errors: disallowed });
+        }
+
         Ok(())
     }
 }
@@ -667,13 +1127,37 @@ impl InternalPlugin for OpenshiftSecondaryMetadataParserPlugin {
 
     async fn run_internal(&self, mut io: InternalIO) -> Fallible<InternalIO> {
         let data_dir = self.get_data_directory(&io);
+        let overlay_dirs = self.overlay_directories().await;
+
+        // Overlays without a version file follow the schema of the data directory.
+        let version = self.process_version(&data_dir).await?;
+        let mut sources: Vec<(&Path, Source, SchemaVersion)> =
+            vec![(data_dir.as_path(), Source::Primary, version)];
+        for overlay_dir in &overlay_dirs {
+            let overlay_version = if path_exists(&overlay_dir.join("version")).await {
+                self.process_version(overlay_dir).await?
+            } else {
+                version
+            };
+            sources.push((overlay_dir.as_path(), Source::Overlay, overlay_version));
+        }
+        for (dir, source, _) in &sources {
+            self.process_raw_metadata(&mut io.graph, dir, *source)
+                .await?;
+        }
 
-        self.process_version(&data_dir).await?;
-        self.process_raw_metadata(&mut io.graph, &data_dir).await?;
-        self.process_blocked_edges(&mut io.graph, &data_dir).await?;
-        self.process_conditional_edges(&mut io.graph, &data_dir)
-            .await?;
-        self.process_channels(&mut io.graph, &data_dir).await?;
+        // Invalid files are skipped, and reported together at the end.
+        let mut errors = GraphDataErrors::default();
+        for (dir, source, version) in &sources {
+            errors.extend(
+                self.process_blocked_edges(&mut io.graph, dir, *source, *version)
+                    .await?,
+            );
+            self.process_conditional_edges(&mut io.graph, dir, *source, *version)
+                .await?;
+        }
+        errors.extend(self.process_channels(&mut io.graph, &sources).await?);
+        self.check_errors(errors)?;
 
         Ok(io)
     }
@@ -694,6 +1178,51 @@ mod tests {
     use std::str::FromStr;
     use test_case::test_case;
 
+    #[test]
+    fn deserialize_blocked_edge_with_invalid_regex_type() {
+        use super::graph_data_model::BlockedEdge;
+
+        assert!(serde_yaml::from_str::<BlockedEdge>("to: 4.1.0\nfrom: 4.0.0").is_ok());
+        for from in &["[]", "{}", "'('"] {
+            let yaml = format!("to: 4.1.0\nfrom: {}", from);
+            assert!(
+                serde_yaml::from_str::<BlockedEdge>(&yaml).is_err(),
+                "accepted from: {}",
+                from
+            );
+        }
+    }
+
+    #[test]
+    fn deserialize_blocked_edge_schedules() {
+        use super::graph_data_model::BlockedEdge;
+
+        let parse = |schedule: &str| {
+            serde_yaml::from_str::<BlockedEdge>(&format!(
+                "to: 4.1.0\nfrom: 4.0.0\nschedule: {}",
+                schedule
+            ))
+        };
+        let blocked_edge = parse("{from: '2024-05-01T12:00:00Z'}").unwrap();
+        let schedule = blocked_edge.schedule.unwrap();
+        assert_eq!(schedule.from.as_deref(), Some("2024-05-01T12:00:00Z"));
+        assert_eq!(schedule.until, None);
+        assert!(parse("{until: '2024-05-01T12:00:00+02:00'}").is_ok());
+        for schedule in &[
+            "{from: 'tomorrow'}",
+            "{from: '2024-05-02T00:00:00Z', until: '2024-05-01T00:00:00Z'}",
+            "{start: '2024-05-01T12:00:00Z'}",
+        ] {
+            assert!(parse(schedule).is_err(), "accepted schedule: {}", schedule);
+        }
+        assert!(
+            serde_yaml::from_str::<BlockedEdge>("to: 4.1.0\nfrom: 4.0.0")
+                .unwrap()
+                .schedule
+                .is_none()
+        );
+    }
+
     lazy_static::lazy_static! {
         static ref TEST_FIXTURE_DIR: PathBuf = {
             PathBuf::from_str("src/plugins/internal/graph_builder/openshift_secondary_metadata_parser/test_fixtures").unwrap()
@@ -829,4 +1358,293 @@ mod tests {
             .context("Running plugin")
             .unwrap_err();
     }
+
+    #[test]
+    fn errors_are_aggregated() -> Fallible<()> {
+        use super::{DeserializeDirectoryFilesErrorDiscriminants, GraphDataErrors};
+
+        let runtime = commons::testing::init_runtime()?;
+        let data_directory = TEST_FIXTURE_DIR.join("invalid1/cincinnati-graph-data");
+        let registry = prometheus::Registry::new();
+
+        let plugin = OpenshiftSecondaryMetadataParserPlugin::try_new(
+            toml::from_str(&format!(
+                r#"
+                    data_directory = {:?}
+                    disallowed_errors = [ "deserialize" ]
+                "#,
+                &data_directory,
+            ))?,
+            Some(&registry),
+        )?;
+        let error = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+            }))
+            .unwrap_err();
+
+        // Both invalid files are reported, not only the first one.
+        let errors = &error
+            .downcast_ref::<GraphDataErrors>()
+            .expect("aggregated errors")
+            .errors;
+        let reported: Vec<_> = errors
+            .iter()
+            .map(|e| {
+                (
+                    e.path().file_name().unwrap().to_str().unwrap(),
+                    DeserializeDirectoryFilesErrorDiscriminants::from(e),
+                    e.location().map(|(line, _)| line),
+                )
+            })
+            .collect();
+        assert_eq!(
+            reported,
+            vec![
+                (
+                    "invalid_regex.yaml",
+                    DeserializeDirectoryFilesErrorDiscriminants::Deserialize,
+                    Some(2)
+                ),
+                (
+                    "fast-4.1.yaml",
+                    DeserializeDirectoryFilesErrorDiscriminants::Deserialize,
+                    Some(4)
+                ),
+            ]
+        );
+        assert!(error.to_string().contains("fast-4.1.yaml:4:"));
+
+        // Tolerated errors are counted as well.
+        let counts: Vec<(String, f64)> = registry
+            .gather()
+            .iter()
+            .filter(|family| family.get_name() == "graph_data_file_errors_total")
+            .flat_map(|family| family.get_metric().to_vec())
+            .map(|metric| {
+                (
+                    metric.get_label()[0].get_value().to_string(),
+                    metric.get_counter().get_value(),
+                )
+            })
+            .collect();
+        assert!(counts.contains(&("deserialize".to_string(), 2.0)));
+        assert!(counts.contains(&("invalid_extension".to_string(), 1.0)));
+        assert!(counts.contains(&("missing_extension".to_string(), 0.0)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlays_override_data_directory() -> Fallible<()> {
+        use std::fs;
+
+        let runtime = commons::testing::init_runtime()?;
+
+        let write_files = |dir: &std::path::Path, files: &[(&str, &str)]| -> Fallible<()> {
+            for (path, content) in files {
+                let path = dir.join(path);
+                fs::create_dir_all(path.parent().unwrap())?;
+                fs::write(path, content)?;
+            }
+            Ok(())
+        };
+        let data_directory = tempfile::tempdir()?;
+        write_files(
+            data_directory.path(),
+            &[
+                ("version", "1.2.0"),
+                (
+                    "raw/metadata.json",
+                    r#"{"4.1.1": {"io.openshift.upgrades.graph.release.manifestref": "sha256:1", "url": "https://example.com/4.1.1"}}"#,
+                ),
+                ("blocked-edges/4.1.1.yaml", "to: 4.1.1\nfrom: 4\\.1\\.0"),
+                (
+                    "channels/stable-4.1.yaml",
+                    "name: stable-4.1\nversions:\n- 4.1.0\n",
+                ),
+                (
+                    "channels/fast-4.1.yaml",
+                    "name: fast-4.1\nversions:\n- 4.1.0\n- 4.1.1\n",
+                ),
+            ],
+        )?;
+        let local_overrides = tempfile::tempdir()?;
+        write_files(
+            local_overrides.path(),
+            &[
+                (
+                    "raw/metadata.json",
+                    r#"{"4.1.1": {"url": "https://example.com/errata/4.1.1"}}"#,
+                ),
+                ("blocked-edges/4.1.1.yaml", "to: 4.1.1\nfrom: .*"),
+            ],
+        )?;
+        let config_map = tempfile::tempdir()?;
+        write_files(
+            config_map.path(),
+            &[(
+                "channels/stable-4.1.yaml",
+                "name: stable-4.1\nversions:\n- 4.1.0\n- 4.1.1\n",
+            )],
+        )?;
+
+        let plugin = OpenshiftSecondaryMetadataParserPlugin::new(toml::from_str(&format!(
+            r#"
+                data_directory = {:?}
+                overlay_directories = [{:?}, {:?}, "/nonexistent/overlay"]
+            "#,
+            data_directory.path(),
+            local_overrides.path(),
+            config_map.path(),
+        ))?);
+
+        let graph = cincinnati::testing::TestGraphBuilder::new()
+            .with_version_template("4.1.{{i}}")
+            .with_metadata(vec![(0, Default::default()), (1, Default::default())])
+            .build();
+        let graph = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph,
+                parameters: Default::default(),
+            }))?
+            .graph;
+
+        let release_id = graph.find_by_version("4.1.1").unwrap();
+        let metadata = match graph.find_by_releaseid(&release_id)? {
+            cincinnati::Release::Concrete(release) => release.metadata.clone(),
+            _ => bail!("abstract release"),
+        };
+        assert_eq!(metadata["url"], "https://example.com/errata/4.1.1");
+        assert_eq!(
+            metadata["io.openshift.upgrades.graph.release.manifestref"],
+            "sha256:1"
+        );
+        assert_eq!(
+            metadata["io.openshift.upgrades.graph.previous.remove_regex"],
+            ".*"
+        );
+        assert_eq!(
+            metadata["io.openshift.upgrades.graph.release.channels"],
+            "fast-4.1,stable-4.1"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema_versions_handle_deprecated_constructs() -> Fallible<()> {
+        use super::{DeserializeDirectoryFilesErrorDiscriminants, GraphDataErrors};
+        use std::fs;
+
+        let runtime = commons::testing::init_runtime()?;
+
+        let run = |version: &str| -> Fallible<(Fallible<cincinnati::Graph>, Vec<(String, f64)>)> {
+            let data_directory = tempfile::tempdir()?;
+            for (path, content) in &[
+                ("version", version),
+                ("raw/metadata.json", "{}"),
+                (
+                    "blocked-edges/4.1.1-s390x.yaml",
+                    "to: 4.1.1-s390x\nfrom: .*",
+                ),
+                (
+                    "channels/stable-4.1.yaml",
+                    "name: candidate-4.1\nversions:\n- 4.1.0\n",
+                ),
+            ] {
+                let path = data_directory.path().join(path);
+                fs::create_dir_all(path.parent().unwrap())?;
+                fs::write(path, content)?;
+            }
+
+            let registry = prometheus::Registry::new();
+            let plugin = OpenshiftSecondaryMetadataParserPlugin::try_new(
+                toml::from_str(&format!(
+                    r#"
+                        data_directory = {:?}
+                        disallowed_errors = [ "deprecated" ]
+                    "#,
+                    data_directory.path(),
+                ))?,
+                Some(&registry),
+            )?;
+            let graph = cincinnati::testing::TestGraphBuilder::new()
+                .with_version_template("4.1.{{i}}")
+                .with_metadata(vec![(0, Default::default()), (1, Default::default())])
+                .build();
+            let result = runtime
+                .block_on(plugin.run_internal(InternalIO {
+                    graph,
+                    parameters: Default::default(),
+                }))
+                .map(|io| io.graph);
+
+            let counts = registry
+                .gather()
+                .iter()
+                .filter(|family| family.get_name() == "graph_data_deprecated_constructs_total")
+                .flat_map(|family| family.get_metric().to_vec())
+                .map(|metric| {
+                    (
+                        metric.get_label()[0].get_value().to_string(),
+                        metric.get_counter().get_value(),
+                    )
+                })
+                .collect();
+            Ok((result, counts))
+        };
+
+        // The current schema accepts the deprecated constructs, and counts them.
+        let (graph, counts) = run("1.2.0")?;
+        let graph = graph?;
+        let release_id = graph.find_by_version("4.1.0").unwrap();
+        let metadata = match graph.find_by_releaseid(&release_id)? {
+            cincinnati::Release::Concrete(release) => release.metadata.clone(),
+            _ => bail!("abstract release"),
+        };
+        assert_eq!(
+            metadata["io.openshift.upgrades.graph.release.channels"],
+            "candidate-4.1"
+        );
+        assert!(counts.contains(&("s390x_prerelease".to_string(), 1.0)));
+        assert!(counts.contains(&("channel_name_mismatch".to_string(), 1.0)));
+
+        // The next schema rejects them.
+        let (graph, counts) = run("2.0.0")?;
+        let error = graph.unwrap_err();
+        let errors = &error
+            .downcast_ref::<GraphDataErrors>()
+            .expect("aggregated errors")
+            .errors;
+        let reported: Vec<_> = errors
+            .iter()
+            .map(|e| {
+                (
+                    e.path().file_name().unwrap().to_str().unwrap(),
+                    DeserializeDirectoryFilesErrorDiscriminants::from(e),
+                )
+            })
+            .collect();
+        assert_eq!(
+            reported,
+            vec![
+                (
+                    "4.1.1-s390x.yaml",
+                    DeserializeDirectoryFilesErrorDiscriminants::Deprecated
+                ),
+                (
+                    "stable-4.1.yaml",
+                    DeserializeDirectoryFilesErrorDiscriminants::Deprecated
+                ),
+            ]
+        );
+        assert!(counts.iter().all(|(_, count)| *count == 0.0));
+
+        // Unknown schemas are refused.
+        assert!(run("3.0.0")?.0.is_err());
+
+        Ok(())
+    }
 }
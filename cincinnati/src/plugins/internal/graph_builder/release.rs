@@ -14,12 +14,11 @@
 
 use crate as cincinnati;
 
-use self::cincinnati::MapImpl;
+use self::cincinnati::{MapImpl, ReleaseVersion};
 
 use commons::prelude_errors::*;
 use itertools::Itertools;
 use log::{debug, trace};
-use semver::Version;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -42,12 +41,12 @@ impl From<Release> for cincinnati::Release {
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Metadata {
     pub kind: MetadataKind,
-    pub version: Version,
+    pub version: ReleaseVersion,
 
     #[serde(default)]
-    pub previous: Vec<Version>,
+    pub previous: Vec<ReleaseVersion>,
     #[serde(default)]
-    pub next: Vec<Version>,
+    pub next: Vec<ReleaseVersion>,
     #[serde(default)]
     pub metadata: MapImpl<String, String>,
 }
@@ -85,78 +84,114 @@ pub fn create_graph(releases: Vec<Release>) -> Result<cincinnati::Graph, Error>
             Ok((
                 release.metadata.next.clone(),
                 release.metadata.previous.clone(),
-                release.metadata.version.build.clone(),
+                release.metadata.version.clone(),
                 graph.add_release(release)?,
             ))
         })
         .collect::<Vec<Fallible<_>>>()
         .into_iter()
         .try_for_each(|result| {
-            let (next, previous, current_build, current) = result?;
+            let (next, previous, current_version, current) = result?;
+            add_edges(&mut graph, &current, previous, next, &current_version)
+        })?;
+
+    Ok(graph)
+}
+
+/// Incremental assembly of releases into a Cincinnati Graph
+///
+/// Each release is added along with its previous/next edges as soon as it is
+/// fetched. Edge endpoints which were not added yet are represented by
+/// abstract releases, replaced once the corresponding release is added, so
+/// that the assembled graph is the same as the one of `create_graph`.
+#[derive(Debug, Default)]
+pub struct GraphAssembler {
+    graph: cincinnati::Graph,
+}
+
+impl GraphAssembler {
+    /// Add a release and its edges to the graph.
+    pub fn add(&mut self, release: Release) -> Fallible<()> {
+        trace!("Adding a release to the graph '{:?}'", release);
+        let next = release.metadata.next.clone();
+        let previous = release.metadata.previous.clone();
+        let current_version = release.metadata.version.clone();
+        let current = self.graph.add_release(release)?;
+        add_edges(&mut self.graph, &current, previous, next, &current_version)
+    }
+
+    /// Return the assembled graph.
+    pub fn finish(self) -> cincinnati::Graph {
+        self.graph
+    }
+}
 
+/// Add the edges from the `previous` releases to `current`, and from `current`
+/// to the `next` releases, adding abstract releases for missing ones.
+///
+/// The `previous` and `next` versions get the build metadata of
+/// `current_version`.
+fn add_edges(
+    graph: &mut cincinnati::Graph,
+    current: &cincinnati::ReleaseId,
+    previous: Vec<ReleaseVersion>,
+    next: Vec<ReleaseVersion>,
+    current_version: &ReleaseVersion,
+) -> Fallible<()> {
+    previous
+        .into_iter()
+        .map(|mut previous| {
+            previous.set_build_from(current_version);
             previous
-                .into_iter()
-                .map(|mut previous| {
-                    previous.build = current_build.clone();
-                    previous
-                })
-                .try_for_each(|version| -> Fallible<()> {
-                    let previous = match graph.find_by_version(&version.to_string()) {
-                        Some(id) => id,
-                        None => {
-                            debug!("Adding abstract release for {}", version.to_string());
-                            graph.add_release(cincinnati::Release::Abstract(
-                                cincinnati::AbstractRelease {
-                                    version: version.to_string(),
-                                },
-                            ))?
-                        }
-                    };
-
-                    if let Err(e) = graph.add_edge(&previous, &current) {
-                        if let Some(eae) = e.downcast_ref::<cincinnati::errors::EdgeAlreadyExists>()
-                        {
-                            debug!("{}", eae);
-                        } else {
-                            return Err(e);
-                        }
-                    };
-
-                    Ok(())
-                })?;
-
-            next.into_iter()
-                .map(|mut next| {
-                    next.build = current_build.clone();
-                    next
-                })
-                .try_for_each(|version| -> Fallible<()> {
-                    let next = match graph.find_by_version(&version.to_string()) {
-                        Some(id) => id,
-                        None => {
-                            debug!("Adding abstract release for {}", version.to_string());
-                            graph.add_release(cincinnati::Release::Abstract(
-                                cincinnati::AbstractRelease {
-                                    version: version.to_string(),
-                                },
-                            ))?
-                        }
-                    };
-
-                    if let Err(e) = graph.add_edge(&current, &next) {
-                        if let Some(eae) = e.downcast_ref::<cincinnati::errors::EdgeAlreadyExists>()
-                        {
-                            debug!("{:?}", eae);
-                        } else {
-                            return Err(e);
-                        }
-                    };
-
-                    Ok(())
-                })
+        })
+        .try_for_each(|version| -> Fallible<()> {
+            let previous = find_or_add_abstract(graph, &version)?;
+
+            if let Err(e) = graph.add_edge(&previous, current) {
+                if let Some(eae) = e.downcast_ref::<cincinnati::errors::EdgeAlreadyExists>() {
+                    debug!("{}", eae);
+                } else {
+                    return Err(e);
+                }
+            };
+
+            Ok(())
         })?;
 
-    Ok(graph)
+    next.into_iter()
+        .map(|mut next| {
+            next.set_build_from(current_version);
+            next
+        })
+        .try_for_each(|version| -> Fallible<()> {
+            let next = find_or_add_abstract(graph, &version)?;
+
+            if let Err(e) = graph.add_edge(current, &next) {
+                if let Some(eae) = e.downcast_ref::<cincinnati::errors::EdgeAlreadyExists>() {
+                    debug!("{:?}", eae);
+                } else {
+                    return Err(e);
+                }
+            };
+
+            Ok(())
+        })
+}
+
+/// Find the release of `version`, adding an abstract release if missing.
+fn find_or_add_abstract(
+    graph: &mut cincinnati::Graph,
+    version: &ReleaseVersion,
+) -> Fallible<cincinnati::ReleaseId> {
+    match graph.find_by_version(&version.to_string()) {
+        Some(id) => Ok(id),
+        None => {
+            debug!("Adding abstract release for {}", version.to_string());
+            graph.add_release(cincinnati::Release::Abstract(cincinnati::AbstractRelease {
+                version: version.to_string(),
+            }))
+        }
+    }
 }
 
 #[cfg(test)]
@@ -170,9 +205,9 @@ mod tests {
             source: "test-0.0.1".to_string(),
             metadata: Metadata {
                 kind: MetadataKind::V0,
-                version: semver::Version::from((0, 0, 1)),
+                version: semver::Version::from((0, 0, 1)).into(),
                 next: Default::default(),
-                previous: vec![semver::Version::from((0, 0, 0))],
+                previous: vec![semver::Version::from((0, 0, 0)).into()],
                 metadata: Default::default(),
             },
         }];
@@ -190,14 +225,14 @@ mod tests {
             source: "test-0.0.1".to_string(),
             metadata: Metadata {
                 kind: MetadataKind::V0,
-                version: semver::Version::from((0, 0, 1)),
+                version: semver::Version::from((0, 0, 1)).into(),
                 next: vec![
-                    semver::Version::from((0, 0, 2)),
-                    semver::Version::from((0, 0, 2)),
+                    semver::Version::from((0, 0, 2)).into(),
+                    semver::Version::from((0, 0, 2)).into(),
                 ],
                 previous: vec![
-                    semver::Version::from((0, 0, 0)),
-                    semver::Version::from((0, 0, 0)),
+                    semver::Version::from((0, 0, 0)).into(),
+                    semver::Version::from((0, 0, 0)).into(),
                 ],
                 metadata: Default::default(),
             },
@@ -225,7 +260,7 @@ mod tests {
             source: "test-0.0.1".to_string(),
             metadata: Metadata {
                 kind: MetadataKind::V0,
-                version: semver::Version::from((0, 0, 1)),
+                version: semver::Version::from((0, 0, 1)).into(),
                 next: Default::default(),
                 previous: Default::default(),
                 metadata: valid,
@@ -236,7 +271,7 @@ mod tests {
             source: "test-0.0.1".to_string(),
             metadata: Metadata {
                 kind: MetadataKind::V0,
-                version: semver::Version::from((0, 0, 1)),
+                version: semver::Version::from((0, 0, 1)).into(),
                 next: Default::default(),
                 previous: Default::default(),
                 metadata: invalid,
@@ -256,4 +291,60 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn assemble_graph_incrementally() -> Fallible<()> {
+        let release = |minor: u64, previous: &[u64]| Release {
+            source: format!("test-0.{}.0", minor),
+            metadata: Metadata {
+                kind: MetadataKind::V0,
+                version: semver::Version::from((0, minor, 0)).into(),
+                next: Default::default(),
+                previous: previous
+                    .iter()
+                    .map(|minor| semver::Version::from((0, *minor, 0)).into())
+                    .collect(),
+                metadata: Default::default(),
+            },
+        };
+        let releases = vec![release(0, &[]), release(1, &[0]), release(2, &[0, 1])];
+
+        // Releases are fetched in any order, here before their predecessors.
+        let mut assembler = GraphAssembler::default();
+        for release in releases.iter().rev().cloned() {
+            assembler.add(release)?;
+        }
+        let mut graph = assembler.finish();
+
+        assert_eq!(graph, create_graph(releases)?);
+        assert_eq!(graph.edges_count(), 3);
+        // Abstract releases were replaced by the fetched ones.
+        assert_eq!(graph.prune_abstract(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_graph_non_semver_versions() -> Fallible<()> {
+        let release: Release = serde_json::from_value(serde_json::json!({
+            "source": "test-2024.2",
+            "metadata": {
+                "kind": "cincinnati-metadata-v0",
+                "version": "2024.2+arm64",
+                "previous": ["2024.1"],
+            },
+        }))?;
+        assert_eq!(
+            release.metadata.version,
+            ReleaseVersion::Other("2024.2+arm64".to_string())
+        );
+
+        let graph = create_graph(vec![release])?;
+
+        // Edge endpoints get the build metadata of the release.
+        assert!(graph.find_by_version("2024.1+arm64").is_some());
+        assert_eq!(graph.edges_count(), 1);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,274 @@
+//! This plugin scrapes releases from a configurable source.
+//!
+//! The `source` setting selects where releases are fetched from:
+//!
+//!  * `registry-v2`: a Docker V2 compatible registry repository, configured
+//!    like the `release-scrape-dockerv2` plugin.
+//!  * `quay`: a Quay repository, authenticated with the API token read from
+//!    `api_token_path`.
+//!  * `directory`: the JSON files of the directory at `path`, one release per file.
+//!  * `file`: the JSON file at `path`, holding an array of releases.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use self::cincinnati::plugins::internal::graph_builder::release_scrape_dockerv2::registry;
+use self::cincinnati::plugins::internal::graph_builder::release_source::{
+    DirectorySource, FileSource, QuaySource, RegistryV2Source, ReleaseScrape, ReleaseSource,
+};
+
+/// Kind of release source.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReleaseSourceKind {
+    /// Docker V2 compatible registry repository.
+    #[default]
+    RegistryV2,
+    /// Quay repository.
+    Quay,
+    /// Directory of release files.
+    Directory,
+    /// File of releases.
+    File,
+}
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ReleaseScrapeSettings {
+    /// Source to fetch releases from.
+    pub source: ReleaseSourceKind,
+
+    /// Settings of the `registry-v2` and `quay` sources. Its
+    /// `merge_precedence` and `version_scheme` apply to all sources.
+    #[serde(flatten)]
+    pub registry: ReleaseScrapeDockerv2Settings,
+
+    /// File containing the Quay API token, for the `quay` source.
+    #[default(Option::None)]
+    pub api_token_path: Option<PathBuf>,
+
+    /// Path of the releases, for the `directory` and `file` sources.
+    #[default(Option::None)]
+    pub path: Option<PathBuf>,
+}
+
+impl PluginSettings for ReleaseScrapeSettings {
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = ReleaseScrapePlugin::try_new(self.clone(), None, registry)?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl ReleaseScrapeSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let mut settings: Self = cfg.try_into()?;
+
+        match settings.source {
+            ReleaseSourceKind::RegistryV2 | ReleaseSourceKind::Quay => {
+                settings.registry.validate()?
+            }
+            ReleaseSourceKind::Directory | ReleaseSourceKind::File => match &settings.path {
+                Some(path) => ensure!(path != &PathBuf::from(""), "empty path"),
+                None => bail!("missing path of the {:?} source", settings.source),
+            },
+        }
+        if let Some(api_token_path) = &settings.api_token_path {
+            ensure!(
+                settings.source == ReleaseSourceKind::Quay,
+                "api_token_path is only supported by the quay source"
+            );
+            ensure!(api_token_path != &PathBuf::from(""), "empty api_token_path");
+        }
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Release scraper of a configurable source.
+#[derive(Debug)]
+pub struct ReleaseScrapePlugin {
+    scrape: ReleaseScrape,
+}
+
+impl ReleaseScrapePlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "release-scrape";
+
+    pub fn try_new(
+        settings: ReleaseScrapeSettings,
+        cache: Option<registry::cache::Cache>,
+        prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let path = || {
+            settings
+                .path
+                .clone()
+                .ok_or_else(|| format_err!("missing path of the {:?} source", settings.source))
+        };
+        let source: Box<dyn ReleaseSource> = match settings.source {
+            ReleaseSourceKind::RegistryV2 => Box::new(RegistryV2Source::try_new(
+                &settings.registry,
+                cache,
+                prometheus_registry,
+            )?),
+            ReleaseSourceKind::Quay => Box::new(QuaySource::try_new(
+                &settings.registry,
+                settings.api_token_path.as_deref(),
+                cache,
+                prometheus_registry,
+            )?),
+            ReleaseSourceKind::Directory => Box::new(DirectorySource::new(path()?)),
+            ReleaseSourceKind::File => Box::new(FileSource::new(path()?)),
+        };
+        let scrape = ReleaseScrape::try_new(
+            source,
+            settings.registry.merge_precedence,
+            settings.registry.version_scheme.clone(),
+            prometheus_registry,
+        )?;
+
+        Ok(Self { scrape })
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for ReleaseScrapePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        self.scrape.run(io).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::VersionScheme;
+    use commons::testing::init_runtime;
+
+    fn cfg(extra: &str) -> Fallible<toml::Value> {
+        Ok(toml::from_str(&format!(
+            "name = {:?}\n{}",
+            ReleaseScrapePlugin::PLUGIN_NAME,
+            extra
+        ))?)
+    }
+
+    #[test]
+    fn validate_settings() -> Fallible<()> {
+        ReleaseScrapeSettings::deserialize_config(cfg("")?)?;
+        ReleaseScrapeSettings::deserialize_config(cfg(
+            "source = \"quay\"\napi_token_path = \"/etc/quay/token\"",
+        )?)?;
+        ReleaseScrapeSettings::deserialize_config(cfg(
+            "source = \"directory\"\npath = \"/var/lib/releases\"",
+        )?)?;
+        ReleaseScrapeSettings::deserialize_config(cfg(r#"version_scheme = "pep440""#)?)?;
+
+        assert!(ReleaseScrapeSettings::deserialize_config(cfg(r#"source = "file""#)?).is_err());
+        assert!(ReleaseScrapeSettings::deserialize_config(cfg(r#"source = "unknown""#)?).is_err());
+        assert!(ReleaseScrapeSettings::deserialize_config(cfg(r#"repository = """#)?).is_err());
+        assert!(
+            ReleaseScrapeSettings::deserialize_config(cfg(r#"version_scheme = "calver""#)?)
+                .is_err()
+        );
+        assert!(ReleaseScrapeSettings::deserialize_config(cfg(
+            "source = \"file\"\npath = \"/releases.json\"\napi_token_path = \"/token\"",
+        )?)
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn scrape_directory() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let dir = tempfile::tempdir()?;
+        for (version, previous) in &[("4.14.0", vec![]), ("4.14.1", vec!["4.14.0"])] {
+            std::fs::write(
+                dir.path().join(format!("{}.json", version)),
+                serde_json::json!({
+                    "source": format!("quay.io/test/release:{}", version),
+                    "metadata": {
+                        "kind": "cincinnati-metadata-v0",
+                        "version": version,
+                        "previous": previous,
+                        "metadata": {},
+                    },
+                })
+                .to_string(),
+            )?;
+        }
+
+        let plugin = ReleaseScrapePlugin::try_new(
+            ReleaseScrapeSettings {
+                source: ReleaseSourceKind::Directory,
+                path: Some(dir.path().to_path_buf()),
+                ..Default::default()
+            },
+            None,
+            None,
+        )?;
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+        }))?;
+
+        assert_eq!(io.graph.releases_count(), 2);
+        assert_eq!(io.graph.edges_count(), 1);
+        assert!(io.graph.find_by_version("4.14.1").is_some());
+        assert!(io
+            .parameters
+            .contains_key(commons::GRAPH_BUILD_PHASES_PARAM_KEY));
+
+        Ok(())
+    }
+
+    #[test]
+    fn scrape_version_scheme() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("releases.json");
+        let release = |version: &str, previous: &[&str]| {
+            serde_json::json!({
+                "source": format!("quay.io/test/release:{}", version),
+                "metadata": {
+                    "kind": "cincinnati-metadata-v0",
+                    "version": version,
+                    "previous": previous,
+                },
+            })
+        };
+        std::fs::write(
+            &path,
+            serde_json::json!([release("2024.1", &[]), release("2024.1.post1", &["2024.1"])])
+                .to_string(),
+        )?;
+        let scrape = |version_scheme: VersionScheme| {
+            let mut settings = ReleaseScrapeSettings {
+                source: ReleaseSourceKind::File,
+                path: Some(path.clone()),
+                ..Default::default()
+            };
+            settings.registry.version_scheme = version_scheme;
+            let plugin = ReleaseScrapePlugin::try_new(settings, None, None)?;
+            runtime.block_on(plugin.run_internal(InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+            }))
+        };
+
+        // Versions are semantic versions by default.
+        assert!(scrape(VersionScheme::Semver).is_err());
+
+        let io = scrape(VersionScheme::Pep440)?;
+        assert_eq!(io.graph.releases_count(), 2);
+        assert_eq!(io.graph.edges_count(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+//! Verification of the manifest digests scraped from a registry mirror.
+//!
+//! A mirror keeps scrapes local to disconnected deployments, but the content
+//! it serves cannot be trusted as such. The manifest digest of each scraped
+//! tag is checked against an allowlist published by the canonical source, or
+//! against the release signatures of its signature store, before the release
+//! metadata is fetched, so that tampered images are not served.
+//!
+//! The digest which is checked is computed over the fetched manifest, as the
+//! `Docker-Content-Digest` header is supplied by the mirror itself. Tags whose
+//! header does not match the content are rejected.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::graph_builder::dkrv2_openshift_secondary_metadata_scraper::gpg;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use super::ReleaseScrapeDockerv2Settings;
+use commons::http::HttpClientBuilder;
+use std::collections::HashSet;
+use std::sync::RwLock;
+use std::time::Duration;
+use url::Url;
+
+/// Timeout of the requests to the signature store.
+static SIGNATURE_FETCH_TIMEOUT_SECS: u64 = 30;
+
+/// Verifier of the manifest digests of scraped tags.
+#[derive(CustomDebug)]
+pub struct DigestVerifier {
+    allowlist_path: Option<PathBuf>,
+
+    // digests of the allowlist, read at the start of each scrape
+    #[debug(skip)]
+    allowlist: RwLock<HashSet<String>>,
+
+    signatures: Option<SignatureStore>,
+
+    // digests with a verified signature, which is not fetched again as
+    // manifests are immutable
+    #[debug(skip)]
+    verified: RwLock<HashSet<String>>,
+
+    #[debug(skip)]
+    graph_upstream_unverified_tags: prometheus::IntCounter,
+}
+
+/// Store of the signatures of the canonical releases.
+#[derive(CustomDebug)]
+struct SignatureStore {
+    base_url: Url,
+    #[debug(skip)]
+    client: reqwest::Client,
+    #[debug(skip)]
+    keyring: gpg::Keyring,
+}
+
+impl DigestVerifier {
+    /// Create a verifier from the settings of a registry scrape, if they
+    /// configure an allowlist or signature verification, registering its
+    /// metrics to `prometheus_registry`.
+    pub fn try_new(
+        settings: &ReleaseScrapeDockerv2Settings,
+        prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Option<Self>> {
+        if settings.digest_allowlist_path.is_none() && !settings.verify_signature {
+            return Ok(None);
+        }
+
+        let signatures = if settings.verify_signature {
+            let public_keys_path = settings
+                .public_keys_path
+                .as_ref()
+                .ok_or_else(|| format_err!("empty public keys path"))?;
+            Some(SignatureStore {
+                base_url: Url::parse(&settings.signature_baseurl)
+                    .context(format!("Parsing {} as URL", settings.signature_baseurl))?,
+                client: HttpClientBuilder::new()
+                    .timeout(Duration::from_secs(SIGNATURE_FETCH_TIMEOUT_SECS))
+                    .build()
+                    .context("Building reqwest client")?,
+                keyring: gpg::load_public_keys(public_keys_path)?,
+            })
+        } else {
+            None
+        };
+
+        let graph_upstream_unverified_tags = prometheus::IntCounter::with_opts(
+            prometheus::Opts::new(
+                "graph_upstream_unverified_tags_total",
+                "Total number of scraped tags skipped because their manifest digest could not be verified",
+            )
+            .const_label("repository", &settings.repository),
+        )?;
+        if let Some(prometheus_registry) = &prometheus_registry {
+            prometheus_registry.register(Box::new(graph_upstream_unverified_tags.clone()))?;
+        }
+
+        Ok(Some(Self {
+            allowlist_path: settings.digest_allowlist_path.clone(),
+            allowlist: Default::default(),
+            signatures,
+            verified: Default::default(),
+            graph_upstream_unverified_tags,
+        }))
+    }
+
+    /// Read the allowlist again, as the canonical source publishes new releases.
+    pub fn reload(&self) -> Fallible<()> {
+        let path = match &self.allowlist_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let content = std::fs::read_to_string(path)
+            .context(format!("could not read digest allowlist {:?}", path))?;
+        let allowlist =
+            parse_allowlist(&content).context(format!("invalid digest allowlist {:?}", path))?;
+        trace!("read {} digests from allowlist {:?}", allowlist.len(), path);
+        *self
+            .allowlist
+            .write()
+            .expect("digest allowlist lock poisoned") = allowlist;
+
+        Ok(())
+    }
+
+    /// Verify the manifest of a scraped tag, counting failures.
+    ///
+    /// `manifestref` is the digest reported by the registry, and `content_digest`
+    /// the one computed over the fetched manifest.
+    pub async fn verify(&self, manifestref: &str, content_digest: &str) -> Fallible<()> {
+        let result = self.check(manifestref, content_digest).await;
+        if result.is_err() {
+            self.graph_upstream_unverified_tags.inc();
+        }
+        result
+    }
+
+    async fn check(&self, manifestref: &str, digest: &str) -> Fallible<()> {
+        ensure!(
+            manifestref == digest,
+            "registry reports digest {} for a manifest with digest {}",
+            manifestref,
+            digest
+        );
+
+        if self
+            .allowlist
+            .read()
+            .expect("digest allowlist lock poisoned")
+            .contains(digest)
+        {
+            return Ok(());
+        }
+
+        let signatures = match &self.signatures {
+            Some(signatures) => signatures,
+            None => bail!("digest {} is not in the allowlist", digest),
+        };
+        if self
+            .verified
+            .read()
+            .expect("verified digests lock poisoned")
+            .contains(digest)
+        {
+            return Ok(());
+        }
+
+        gpg::verify_signatures_for_digest(
+            &signatures.client,
+            &signatures.base_url,
+            &signatures.keyring,
+            digest,
+        )
+        .await?;
+        self.verified
+            .write()
+            .expect("verified digests lock poisoned")
+            .insert(digest.to_string());
+
+        Ok(())
+    }
+}
+
+/// Parse an allowlist with a digest at the start of each line.
+///
+/// Anything following the digest on its line, e.g. the tag, is ignored, as
+/// are empty lines and comments starting with `#`.
+fn parse_allowlist(content: &str) -> Fallible<HashSet<String>> {
+    let mut allowlist = HashSet::new();
+    for (number, line) in content.lines().enumerate() {
+        let digest = match line.split_whitespace().next() {
+            Some(digest) if !digest.starts_with('#') => digest,
+            _ => continue,
+        };
+        ensure!(
+            matches!(digest.split_once(':'), Some((algorithm, hex)) if !algorithm.is_empty() && !hex.is_empty()),
+            "invalid digest '{}' on line {}",
+            digest,
+            number + 1
+        );
+        allowlist.insert(digest.to_string());
+    }
+    Ok(allowlist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_digest_allowlist() -> Fallible<()> {
+        let allowlist = parse_allowlist(
+            "# Releases of quay.io/openshift-release-dev/ocp-release\n\
+             sha256:0123 4.14.1-x86_64\n\
+             \n\
+               sha256:4567\n",
+        )?;
+        assert_eq!(
+            allowlist,
+            ["sha256:0123", "sha256:4567"]
+                .iter()
+                .map(|digest| digest.to_string())
+                .collect()
+        );
+
+        assert!(parse_allowlist("4.14.1-x86_64 sha256:0123").is_err());
+        assert!(parse_allowlist("sha256:").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_lying_manifestref() -> Fallible<()> {
+        use super::super::registry::manifest_digest;
+
+        let canonical = manifest_digest(br#"{"schemaVersion":2,"manifests":[]}"#);
+        let tampered = manifest_digest(br#"{"schemaVersion":2,"manifests":[{}]}"#);
+
+        let allowlist = tempfile::NamedTempFile::new()?;
+        std::fs::write(allowlist.path(), format!("{} 4.14.1-x86_64\n", canonical))?;
+        let settings = ReleaseScrapeDockerv2Settings {
+            digest_allowlist_path: Some(allowlist.path().to_path_buf()),
+            ..Default::default()
+        };
+        let verifier = DigestVerifier::try_new(&settings, None)?.expect("no verifier");
+        verifier.reload()?;
+
+        verifier.verify(&canonical, &canonical).await?;
+
+        // The mirror claims the allowlisted digest for tampered content.
+        assert!(verifier.verify(&canonical, &tampered).await.is_err());
+        // The header matches the tampered content, which is not allowlisted.
+        assert!(verifier.verify(&tampered, &tampered).await.is_err());
+        assert_eq!(verifier.graph_upstream_unverified_tags.get(), 2);
+
+        Ok(())
+    }
+}
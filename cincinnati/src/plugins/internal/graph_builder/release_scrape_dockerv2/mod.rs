@@ -1,9 +1,12 @@
 //! This plugin scrapes a Docker V2 compatible registry repository for release images.
 
+pub mod digests;
 pub mod plugin;
 pub mod registry;
+pub mod tags;
 
 pub use plugin::{
     ReleaseScrapeDockerv2Plugin, ReleaseScrapeDockerv2Settings, DEFAULT_FETCH_CONCURRENCY,
     DEFAULT_MANIFESTREF_KEY, DEFAULT_SCRAPE_REGISTRY, DEFAULT_SCRAPE_REPOSITORY,
+    PROVENANCE_NAMESPACE,
 };
@@ -5,7 +5,11 @@ use crate as cincinnati;
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
 
-use std::convert::TryInto;
+use self::cincinnati::plugins::internal::graph_builder::dkrv2_openshift_secondary_metadata_scraper::plugin::DEFAULT_SIGNATURE_BASEURL;
+use self::cincinnati::plugins::internal::graph_builder::release_source::{
+    RegistryV2Source, ReleaseScrape,
+};
+use self::cincinnati::VersionScheme;
 
 /// Default registry to scrape.
 pub static DEFAULT_SCRAPE_REGISTRY: &str = "quay.io";
@@ -19,6 +23,12 @@ pub static DEFAULT_MANIFESTREF_KEY: &str = "io.openshift.upgrades.graph.release.
 /// Default fetch concurrency.
 pub static DEFAULT_FETCH_CONCURRENCY: usize = 16;
 
+/// Default prefix of the referrer annotations added to release metadata.
+pub static DEFAULT_REFERRERS_ANNOTATION_PREFIX: &str = "io.openshift.upgrades.graph.";
+
+/// Namespace of the provenance metadata keys of scraped releases.
+pub static PROVENANCE_NAMESPACE: &str = "io.openshift.upgrades.graph.provenance";
+
 /// Plugin settings.
 #[derive(Clone, Debug, Deserialize, SmartDefault)]
 #[serde(default)]
@@ -48,6 +58,59 @@ pub struct ReleaseScrapeDockerv2Settings {
     /// Takes precedence over username and password
     #[default(Option::None)]
     pub credentials_path: Option<PathBuf>,
+
+    /// Release kept when the input graph, e.g. scraped from another
+    /// repository by a previous plugin, contains the same version.
+    pub merge_precedence: MergePrecedence,
+
+    /// Regular expression that tags must match to be scraped.
+    #[default(Option::None)]
+    pub tag_regex: Option<String>,
+
+    /// Artifact type of the OCI referrers whose annotations are added to
+    /// release metadata. Referrers are not fetched if unset.
+    #[default(Option::None)]
+    pub referrers_artifact_type: Option<String>,
+
+    /// Prefix of the referrer annotations added to release metadata.
+    #[default(DEFAULT_REFERRERS_ANNOTATION_PREFIX.to_string())]
+    pub referrers_annotation_prefix: String,
+
+    /// Scheme which the versions of scraped releases must follow.
+    pub version_scheme: VersionScheme,
+
+    /// File listing the manifest digests of the releases of the canonical
+    /// repository, when scraping a mirror. Tags with other digests are skipped.
+    #[default(Option::None)]
+    pub digest_allowlist_path: Option<PathBuf>,
+
+    /// Verify the signatures of the manifest digests which are not in the allowlist.
+    #[default(false)]
+    pub verify_signature: bool,
+
+    /// Base URL for signature verification
+    #[default(DEFAULT_SIGNATURE_BASEURL.to_string())]
+    pub signature_baseurl: String,
+
+    /// Public keys for signature verification
+    #[default(Option::None)]
+    pub public_keys_path: Option<PathBuf>,
+
+    /// Record the registry, repository, tag and manifest digest each release
+    /// was scraped from, and when it was first scraped, in its metadata under
+    /// `PROVENANCE_NAMESPACE`.
+    #[default(false)]
+    pub record_provenance: bool,
+
+    /// Maximum size of the release metadata file of an image, in bytes.
+    /// Unlimited if 0.
+    #[default(registry::DEFAULT_MAX_METADATA_BYTES)]
+    pub max_metadata_bytes: u64,
+
+    /// Maximum time spent decoding the release metadata of a layer, in
+    /// seconds. Unlimited if unset.
+    #[default(Option::None)]
+    pub max_decode_secs: Option<u64>,
 }
 
 impl PluginSettings for ReleaseScrapeDockerv2Settings {
@@ -61,33 +124,64 @@ impl ReleaseScrapeDockerv2Settings {
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
         let mut settings: Self = cfg.try_into()?;
+        settings.validate()?;
+
+        Ok(Box::new(settings))
+    }
 
-        ensure!(!settings.repository.is_empty(), "empty repository");
-        ensure!(!settings.registry.is_empty(), "empty registry");
+    /// Validate the settings, clearing an empty credentials path.
+    pub(crate) fn validate(&mut self) -> Fallible<()> {
+        ensure!(!self.repository.is_empty(), "empty repository");
+        ensure!(!self.registry.is_empty(), "empty registry");
         ensure!(
-            !settings.manifestref_key.is_empty(),
+            !self.manifestref_key.is_empty(),
             "empty manifestref_key prefix"
         );
-        if let Some(credentials_path) = &settings.credentials_path {
+        if let Some(tag_regex) = &self.tag_regex {
+            regex::Regex::new(tag_regex).context(format!("invalid tag_regex {}", tag_regex))?;
+        }
+        if let Some(artifact_type) = &self.referrers_artifact_type {
+            ensure!(!artifact_type.is_empty(), "empty referrers_artifact_type");
+            ensure!(
+                !self.referrers_annotation_prefix.is_empty(),
+                "empty referrers_annotation_prefix"
+            );
+        }
+        if self.verify_signature {
+            ensure!(
+                !self.signature_baseurl.is_empty(),
+                "empty signature base url"
+            );
+            ensure!(
+                url::Url::parse(&self.signature_baseurl).is_ok(),
+                "invalid signature base url"
+            );
+            ensure!(self.public_keys_path.is_some(), "empty public keys path");
+        }
+        ensure!(self.max_decode_secs != Some(0), "zero max_decode_secs");
+        if let Some(credentials_path) = &self.credentials_path {
             if credentials_path == &std::path::PathBuf::from("") {
                 warn!("Settings contain an empty credentials path, setting to None");
-                settings.credentials_path = None;
+                self.credentials_path = None;
             }
         }
 
-        Ok(Box::new(settings))
+        Ok(())
+    }
+
+    /// Return the limits on the release metadata of scraped images.
+    pub(crate) fn metadata_limits(&self) -> registry::MetadataLimits {
+        registry::MetadataLimits {
+            max_bytes: Some(self.max_metadata_bytes).filter(|bytes| *bytes > 0),
+            max_decode_time: self.max_decode_secs.map(std::time::Duration::from_secs),
+        }
     }
 }
 
 /// Metadata fetcher for quay.io API.
-#[derive(CustomDebug)]
+#[derive(Debug)]
 pub struct ReleaseScrapeDockerv2Plugin {
-    settings: ReleaseScrapeDockerv2Settings,
-    registry: registry::Registry,
-    cache: registry::cache::Cache,
-
-    #[debug(skip)]
-    graph_upstream_raw_releases: prometheus::IntGauge,
+    scrape: ReleaseScrape,
 }
 
 impl ReleaseScrapeDockerv2Plugin {
@@ -95,46 +189,19 @@ impl ReleaseScrapeDockerv2Plugin {
     pub const PLUGIN_NAME: &'static str = "release-scrape-dockerv2";
 
     pub fn try_new(
-        mut settings: ReleaseScrapeDockerv2Settings,
+        settings: ReleaseScrapeDockerv2Settings,
         cache: Option<registry::cache::Cache>,
         prometheus_registry: Option<&prometheus::Registry>,
     ) -> Fallible<Self> {
-        use prometheus::IntGauge;
-        let graph_upstream_raw_releases: IntGauge = IntGauge::new(
-            "graph_upstream_raw_releases",
-            "Number of releases fetched from upstream, before processing",
+        let source = RegistryV2Source::try_new(&settings, cache, prometheus_registry)?;
+        let scrape = ReleaseScrape::try_new(
+            Box::new(source),
+            settings.merge_precedence,
+            settings.version_scheme.clone(),
+            prometheus_registry,
         )?;
 
-        if let Some(prometheus_registry) = &prometheus_registry {
-            prometheus_registry.register(Box::new(graph_upstream_raw_releases.clone()))?;
-        }
-
-        let registry = registry::Registry::try_from_str(&settings.registry)
-            .context(format!("Parsing {} as Registry", &settings.registry))?;
-
-        if let Some(credentials_path) = &settings.credentials_path {
-            let (username, password) = registry::read_credentials(
-                Some(credentials_path),
-                &registry.host_port_string(),
-            )
-            .unwrap_or_else(|err| {
-                warn!(
-                    "Error reading registry credentials from {:?}. Access to {:?} will be unauthenticated: {} ",
-                    credentials_path, &registry.host_port_string() ,err
-                );
-                (None, None)
-            });
-
-            settings.username = username;
-            settings.password = password;
-        }
-
-        Ok(Self {
-            settings,
-            registry,
-            cache: cache.unwrap_or_else(registry::cache::new),
-            graph_upstream_raw_releases,
-        })
+        Ok(Self { scrape })
     }
 }
 
@@ -143,42 +210,13 @@ impl InternalPlugin for ReleaseScrapeDockerv2Plugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
 
     async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
-        let releases = registry::fetch_releases(
-            &self.registry,
-            &self.settings.repository,
-            self.settings.username.as_ref().map(String::as_ref),
-            self.settings.password.as_ref().map(String::as_ref),
-            self.cache.clone(),
-            &self.settings.manifestref_key,
-            self.settings.fetch_concurrency,
-        )
-        .await
-        .context(format!(
-            "failed to fetch all release metadata from {}/{}",
-            &self.registry.host_port_string(),
-            &self.settings.repository
-        ))?;
-
-        if releases.is_empty() {
-            warn!(
-                "could not find any releases in {}/{}",
-                &self.registry.host_port_string(),
-                &self.settings.repository
-            );
-        };
-
-        self.graph_upstream_raw_releases
-            .set(releases.len().try_into()?);
-
-        let graph = cincinnati::plugins::internal::graph_builder::release::create_graph(releases)?;
-
-        Ok(InternalIO {
-            graph,
-            parameters: io.parameters,
-        })
+        self.scrape.run(io).await
     }
 }
 
+#[cfg(test)]
+mod mock_registry_tests;
+
 #[cfg(test)]
 #[cfg(feature = "test-net")]
 mod network_tests;
@@ -0,0 +1,389 @@
+use super::super::tags;
+use super::*;
+
+use cincinnati::plugins::internal::graph_builder::commons::tests::common_init;
+use commons::prelude_errors::*;
+use mock_registry::{Fixtures, MockRegistry, ReleaseImage};
+
+fn scrape(registry: &MockRegistry, repo: &str) -> Fallible<cincinnati::Graph> {
+    scrape_into(registry, repo, Default::default(), Default::default())
+}
+
+fn scrape_into(
+    registry: &MockRegistry,
+    repo: &str,
+    graph: cincinnati::Graph,
+    merge_precedence: MergePrecedence,
+) -> Fallible<cincinnati::Graph> {
+    let (runtime, _) = common_init();
+
+    let plugin = Box::new(ReleaseScrapeDockerv2Plugin::try_new(
+        toml::from_str::<ReleaseScrapeDockerv2Settings>(&format!(
+            r#"
+                registry = "{}"
+                repository = "{}"
+                manifestref_key = "{}"
+                fetch_concurrency = {}
+                merge_precedence = "{}"
+            "#,
+            registry.url(),
+            repo,
+            DEFAULT_MANIFESTREF_KEY,
+            DEFAULT_FETCH_CONCURRENCY,
+            match merge_precedence {
+                MergePrecedence::Existing => "existing",
+                MergePrecedence::Incoming => "incoming",
+            },
+        ))?,
+        None,
+        None,
+    )?);
+
+    Ok(runtime
+        .block_on(plugin.run_internal(InternalIO {
+            graph,
+            parameters: Default::default(),
+        }))?
+        .graph)
+}
+
+#[test]
+fn scrape_mock_registry_must_succeed() -> Fallible<()> {
+    let repo = "test/release";
+    let mut fixtures = Fixtures::new();
+    fixtures.add_release(repo, "0.0.0", &ReleaseImage::new("0.0.0"))?;
+    fixtures.add_release(
+        repo,
+        "0.0.1",
+        &ReleaseImage::new("0.0.1")
+            .with_previous(&["0.0.0"])
+            .with_metadata("kind", "test"),
+    )?;
+    let registry = MockRegistry::start(fixtures)?;
+
+    let graph = scrape(&registry, repo)?;
+
+    assert_eq!(graph.releases_count(), 2);
+    let from = graph
+        .find_by_version("0.0.0+amd64")
+        .context("missing release 0.0.0")?;
+    let next: Vec<&str> = graph
+        .next_releases(&from)
+        .map(|(_, _, release)| release.version())
+        .collect();
+    assert_eq!(next, vec!["0.0.1+amd64"]);
+
+    Ok(())
+}
+
+#[test]
+fn scrape_multiple_repositories_must_merge() -> Fallible<()> {
+    let (stable, nightly) = ("test/stable", "test/nightly");
+    let mut fixtures = Fixtures::new();
+    fixtures.add_release(stable, "0.0.0", &ReleaseImage::new("0.0.0"))?;
+    fixtures.add_release(
+        stable,
+        "0.0.1",
+        &ReleaseImage::new("0.0.1").with_previous(&["0.0.0"]),
+    )?;
+    fixtures.add_release(
+        nightly,
+        "0.0.1",
+        &ReleaseImage::new("0.0.1").with_metadata("kind", "nightly"),
+    )?;
+    fixtures.add_release(
+        nightly,
+        "0.0.2-nightly",
+        &ReleaseImage::new("0.0.2-nightly").with_previous(&["0.0.1"]),
+    )?;
+    let registry = MockRegistry::start(fixtures)?;
+
+    let payload = |graph: &cincinnati::Graph, version: &str| -> Fallible<String> {
+        let id = graph
+            .find_by_version(version)
+            .context(format!("missing release {}", version))?;
+        match graph.find_by_releaseid(&id)? {
+            cincinnati::Release::Concrete(release) => Ok(release.payload.clone()),
+            cincinnati::Release::Abstract(_) => bail!("unexpected abstract release {}", version),
+        }
+    };
+
+    for (precedence, expected_repo) in &[
+        (MergePrecedence::Existing, stable),
+        (MergePrecedence::Incoming, nightly),
+    ] {
+        let graph = scrape(&registry, stable)?;
+        let graph = scrape_into(&registry, nightly, graph, *precedence)?;
+
+        assert_eq!(graph.releases_count(), 3);
+        assert!(payload(&graph, "0.0.1+amd64")?.contains(expected_repo));
+        assert!(payload(&graph, "0.0.2-nightly+amd64")?.contains(nightly));
+
+        let from = graph
+            .find_by_version("0.0.1+amd64")
+            .context("missing release 0.0.1")?;
+        let next: Vec<&str> = graph
+            .next_releases(&from)
+            .map(|(_, _, release)| release.version())
+            .collect();
+        assert_eq!(next, vec!["0.0.2-nightly+amd64"]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn scrape_unknown_repository_must_fail() -> Fallible<()> {
+    let registry = MockRegistry::start(Fixtures::new())?;
+
+    assert!(scrape(&registry, "test/missing").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn scrape_records_tag_dispositions() -> Fallible<()> {
+    let (runtime, _) = common_init();
+    let repo = "test/release";
+    let mut fixtures = Fixtures::new();
+    fixtures.add_release(repo, "0.0.0", &ReleaseImage::new("0.0.0"))?;
+    let digest = fixtures.add_release(repo, "0.0.1", &ReleaseImage::new("0.0.1"))?;
+    fixtures.tag(repo, "0.0.1-latest", &digest);
+    fixtures.add_release(repo, "0.0.2", &ReleaseImage::new("not-a-version"))?;
+    fixtures.add_release(repo, "ci-build", &ReleaseImage::new("0.0.3"))?;
+    let registry = MockRegistry::start(fixtures)?;
+
+    let plugin = ReleaseScrapeDockerv2Plugin::try_new(
+        toml::from_str::<ReleaseScrapeDockerv2Settings>(&format!(
+            r#"
+                registry = "{}"
+                repository = "{}"
+                fetch_concurrency = 1
+                tag_regex = "^[0-9]"
+            "#,
+            registry.url(),
+            repo,
+        ))?,
+        None,
+        None,
+    )?;
+    let io = runtime.block_on(plugin.run_internal(InternalIO {
+        graph: Default::default(),
+        parameters: Default::default(),
+    }))?;
+    assert_eq!(io.graph.releases_count(), 2);
+
+    let listing = tags::from_parameters(&io.parameters)?
+        .into_iter()
+        .find(|listing| listing.repository == format!("{}/{}", registry.url(), repo))
+        .context("missing tag listing")?;
+    let dispositions: Vec<(&str, tags::TagDisposition)> = listing
+        .tags
+        .iter()
+        .map(|tag| (tag.tag.as_str(), tag.disposition))
+        .collect();
+    assert_eq!(
+        dispositions,
+        vec![
+            ("0.0.0", tags::TagDisposition::Included),
+            ("0.0.1", tags::TagDisposition::Included),
+            ("0.0.1-latest", tags::TagDisposition::Duplicate),
+            ("0.0.2", tags::TagDisposition::InvalidMetadata),
+            ("ci-build", tags::TagDisposition::FilteredByRegex),
+        ]
+    );
+    assert_eq!(listing.tags[1].version.as_deref(), Some("0.0.1+amd64"));
+
+    Ok(())
+}
+
+#[test]
+fn scrape_skips_unverified_digests() -> Fallible<()> {
+    let (runtime, _) = common_init();
+    let repo = "test/release";
+    let mut fixtures = Fixtures::new();
+    let digest = fixtures.add_release(repo, "0.0.0", &ReleaseImage::new("0.0.0"))?;
+    fixtures.add_release(repo, "0.0.1", &ReleaseImage::new("0.0.1"))?;
+    let registry = MockRegistry::start(fixtures)?;
+
+    let allowlist = tempfile::NamedTempFile::new()?;
+    std::fs::write(
+        &allowlist,
+        format!("# canonical digests\n{} 0.0.0\n", digest),
+    )?;
+
+    let plugin = ReleaseScrapeDockerv2Plugin::try_new(
+        toml::from_str::<ReleaseScrapeDockerv2Settings>(&format!(
+            r#"
+                registry = "{}"
+                repository = "{}"
+                digest_allowlist_path = {:?}
+            "#,
+            registry.url(),
+            repo,
+            allowlist.path(),
+        ))?,
+        None,
+        None,
+    )?;
+    let run = || -> Fallible<InternalIO> {
+        runtime.block_on(plugin.run_internal(InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+        }))
+    };
+
+    let io = run()?;
+    assert_eq!(io.graph.releases_count(), 1);
+    assert!(io.graph.find_by_version("0.0.0+amd64").is_some());
+
+    let listing = tags::from_parameters(&io.parameters)?
+        .into_iter()
+        .find(|listing| listing.repository == format!("{}/{}", registry.url(), repo))
+        .context("missing tag listing")?;
+    assert_eq!(listing.tags[1].tag, "0.0.1");
+    assert_eq!(
+        listing.tags[1].disposition,
+        tags::TagDisposition::Unverified
+    );
+
+    // The allowlist is read again on each scrape.
+    std::fs::write(&allowlist, "")?;
+    assert_eq!(run()?.graph.releases_count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn scrape_adds_referrer_annotations() -> Fallible<()> {
+    let (runtime, _) = common_init();
+    let repo = "test/release";
+    let (artifact_type, channels_key, url_key) = (
+        "application/vnd.openshift.release-metadata",
+        "io.openshift.upgrades.graph.release.channels",
+        "io.openshift.upgrades.graph.release.url",
+    );
+    let mut fixtures = Fixtures::new();
+    let digest = fixtures.add_release(
+        repo,
+        "0.0.1",
+        &ReleaseImage::new("0.0.1").with_metadata(channels_key, "stable-4.1"),
+    )?;
+    let annotations = [
+        (channels_key, "fast-4.1"),
+        (url_key, "https://example.com/errata/0.0.1"),
+        ("org.example.unrelated", "ignored"),
+    ]
+    .iter()
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect();
+    fixtures.add_referrer(repo, &digest, artifact_type, &annotations)?;
+    let other = [(
+        "io.openshift.upgrades.graph.release.other".to_string(),
+        "ignored".to_string(),
+    )]
+    .iter()
+    .cloned()
+    .collect();
+    fixtures.add_referrer(repo, &digest, "application/x.other", &other)?;
+    let registry = MockRegistry::start(fixtures)?;
+
+    let plugin = ReleaseScrapeDockerv2Plugin::try_new(
+        toml::from_str::<ReleaseScrapeDockerv2Settings>(&format!(
+            r#"
+                registry = "{}"
+                repository = "{}"
+                referrers_artifact_type = "{}"
+            "#,
+            registry.url(),
+            repo,
+            artifact_type,
+        ))?,
+        None,
+        None,
+    )?;
+    let mut graph = runtime
+        .block_on(plugin.run_internal(InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+        }))?
+        .graph;
+
+    let release = graph
+        .find_by_version("0.0.1+amd64")
+        .context("missing release 0.0.1")?;
+    let metadata = graph.get_metadata_as_ref_mut(&release)?;
+    assert_eq!(
+        metadata.get(channels_key).map(String::as_str),
+        Some("stable-4.1")
+    );
+    assert_eq!(
+        metadata.get(url_key).map(String::as_str),
+        Some("https://example.com/errata/0.0.1")
+    );
+    assert!(metadata.get("org.example.unrelated").is_none());
+    assert!(metadata
+        .get("io.openshift.upgrades.graph.release.other")
+        .is_none());
+    assert!(registry
+        .requests()
+        .iter()
+        .any(|request| request.contains("/referrers/")));
+
+    Ok(())
+}
+
+#[test]
+fn scrape_records_provenance() -> Fallible<()> {
+    let (runtime, _) = common_init();
+    let repo = "test/release";
+    let mut fixtures = Fixtures::new();
+    let digest = fixtures.add_release(repo, "0.0.1", &ReleaseImage::new("0.0.1"))?;
+    let registry = MockRegistry::start(fixtures)?;
+
+    let plugin = ReleaseScrapeDockerv2Plugin::try_new(
+        toml::from_str::<ReleaseScrapeDockerv2Settings>(&format!(
+            r#"
+                registry = "{}"
+                repository = "{}"
+                record_provenance = true
+            "#,
+            registry.url(),
+            repo,
+        ))?,
+        None,
+        None,
+    )?;
+    let provenance = || -> Fallible<cincinnati::MapImpl<String, String>> {
+        let mut graph = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+            }))?
+            .graph;
+        let release = graph
+            .find_by_version("0.0.1+amd64")
+            .context("missing release 0.0.1")?;
+        Ok(graph
+            .get_metadata_as_ref_mut(&release)?
+            .iter()
+            .filter_map(|(key, value)| {
+                key.strip_prefix(&format!("{}.", PROVENANCE_NAMESPACE))
+                    .map(|name| (name.to_string(), value.clone()))
+            })
+            .collect())
+    };
+
+    let first = provenance()?;
+    assert!(registry.url().ends_with(&first["registry"]));
+    assert_eq!(first["repository"], repo);
+    assert_eq!(first["tag"], "0.0.1");
+    assert_eq!(first["digest"], digest);
+    chrono::DateTime::parse_from_rfc3339(&first["scraped_at"])?;
+    assert_eq!(first.len(), 5);
+
+    // Releases keep the time they were first scraped at.
+    assert_eq!(provenance()?, first);
+
+    Ok(())
+}
@@ -14,11 +14,17 @@
 
 use crate as cincinnati;
 
+use self::cincinnati::plugins::internal::graph_builder::build_phases::{BuildPhase, PhaseTimings};
 use self::cincinnati::plugins::internal::graph_builder::release::Metadata;
 use self::cincinnati::plugins::internal::graph_builder::release::MetadataKind;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
+use super::digests::DigestVerifier;
+use super::tags::{ScrapedTag, TagDisposition};
+use super::PROVENANCE_NAMESPACE;
+use commons::download::DeadlineReader;
 
 use flate2::read::GzDecoder;
+use futures::channel::mpsc;
 use futures::lock::Mutex as FuturesMutex;
 use futures::prelude::*;
 use futures::TryStreamExt;
@@ -26,17 +32,23 @@ use log::{debug, error, trace, warn};
 use semver::Version;
 use serde::Deserialize;
 use serde_json;
+use sha2::{Digest, Sha256};
 use std::fs::File;
 use std::io::Read;
 use std::iter::Iterator;
 use std::path::{Path, PathBuf};
 use std::string::String;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant};
 use tar::Archive;
 
 use dkregistry::mediatypes::MediaTypes::{ManifestList, ManifestV2S1Signed, ManifestV2S2};
 use dkregistry::v2::Client;
 
+mod referrers;
+pub use self::referrers::ReferrersClient;
+
 /// Module for the release cache
 pub mod cache {
     use super::cincinnati::plugins::internal::graph_builder::release::Metadata;
@@ -96,11 +108,10 @@ impl Registry {
                     insecure: Registry::insecure_scheme(&scheme)?,
                     scheme,
                     host: capture["host"].to_string(),
-                    port: Some(
-                        capture["port"]
-                            .parse()
-                            .expect("could not parse port as a number"),
-                    ),
+                    port: Some(capture["port"].parse().context(format!(
+                        "could not parse port {} as a number",
+                        &capture["port"]
+                    ))?),
                 });
             }
         );
@@ -127,11 +138,10 @@ impl Registry {
             {
                 return Ok(Registry {
                     host: capture["host"].to_string(),
-                    port: Some(
-                        capture["port"]
-                            .parse()
-                            .expect("could not parse port as a number"),
-                    ),
+                    port: Some(capture["port"].parse().context(format!(
+                        "could not parse port {} as a number",
+                        &capture["port"]
+                    ))?),
                     ..Default::default()
                 });
             }
@@ -246,23 +256,27 @@ pub async fn new_registry_client(
     Ok(client)
 }
 
-// get the architecture, manifestref and layers_digest for images with tag/digest
+// get the architecture, manifestref, content digest and layers_digest for images with tag/digest
 async fn get_manifest_layers(
     tag: String,
     repo: &str,
+    host: &str,
     registry_client: &Client,
-) -> Result<(Option<String>, String, Vec<String>), Error> {
+) -> Result<(Option<String>, String, String, Vec<String>), Error> {
     trace!("[{}] Fetching release", tag);
-    let (tag, manifest, manifestref) =
+    commons::budget::acquire(host).await?;
+    let (tag, manifest, manifestref, content_digest) =
         get_manifest_and_ref(tag, repo.to_owned(), &registry_client).await?;
 
-    // Try to read the architecture from the manifest
+    // Try to read the architecture from the manifest.
+    // Manifest lists are multi-arch payloads, even when they reference a single architecture.
+    let is_manifest_list = matches!(manifest, dkregistry::v2::manifest::Manifest::ML(_));
     let arch = match manifest.architectures() {
         Ok(archs) => {
-            if archs.len() == 1 {
+            if archs.len() == 1 && !is_manifest_list {
                 archs.first().map(std::string::ToString::to_string)
             } else {
-                Some(String::from("multi"))
+                Some(cincinnati::MULTI_ARCH.to_string())
             }
         }
         Err(e) => {
@@ -286,12 +300,28 @@ async fn get_manifest_layers(
         .rev()
         .collect();
 
-    Ok((arch, manifestref, layers_digests))
+    Ok((arch, manifestref, content_digest, layers_digests))
 }
 
-/// Fetches a vector of all release metadata from the given repository, hosted on the given
-/// registry.
-pub async fn fetch_releases(
+/// Fetches all release metadata from the given repository, hosted on the given
+/// registry, sending each release to `releases` as soon as it is decoded, and
+/// returns the disposition of every listed tag.
+///
+/// At most `concurrency` tags are fetched at once, and fetching waits while
+/// `releases` is full, so that only a bounded number of fetched releases is
+/// held before being consumed.
+///
+/// Only tags matching `tag_filter`, if any, are fetched. Annotations of the
+/// artifacts referring to each release, if `referrers` is given, are added
+/// to its metadata without overriding the metadata of the release image.
+/// Tags whose manifest digest is not verified by `digests`, if given, are
+/// skipped before their metadata is fetched. With `record_provenance`, the
+/// origin of each release is added to its metadata, see `add_provenance`.
+/// Layers exceeding the `metadata_limits` fail the scrape.
+/// The time spent listing tags, fetching manifests and decoding metadata is
+/// added to `timings`.
+#[allow(clippy::too_many_arguments)]
+pub async fn send_releases(
     registry: &Registry,
     repo: &str,
     username: Option<&str>,
@@ -299,48 +329,87 @@ pub async fn fetch_releases(
     cache: cache::Cache,
     manifestref_key: &str,
     concurrency: usize,
-) -> Result<Vec<cincinnati::plugins::internal::graph_builder::release::Release>, Error> {
+    tag_filter: Option<&regex::Regex>,
+    referrers: Option<&ReferrersClient>,
+    digests: Option<&DigestVerifier>,
+    record_provenance: bool,
+    metadata_limits: MetadataLimits,
+    timings: &Mutex<PhaseTimings>,
+    releases: mpsc::Sender<cincinnati::plugins::internal::graph_builder::release::Release>,
+) -> Result<Vec<ScrapedTag>, Error> {
     let registry_client = new_registry_client(registry, repo, username, password).await?;
 
     let registry_client_get_tags = registry_client.clone();
-    let tags = Box::pin(get_tags(repo, &registry_client_get_tags).await);
+    let listing_start = Instant::now();
+    let tags = Box::pin(
+        get_tags(repo, &registry.host, &registry_client_get_tags)
+            .await
+            .chain(
+                // Polled once all tags are listed, to time the listing.
+                futures::stream::poll_fn(move |_| {
+                    record_phase(timings, BuildPhase::TagListing, listing_start);
+                    Poll::Ready(None)
+                }),
+            ),
+    );
 
-    let releases = {
-        let estimated_releases = match tags.size_hint() {
-            (_, Some(upper)) => upper,
-            (lower, None) => lower,
-        };
-        Arc::new(FuturesMutex::new(Vec::with_capacity(estimated_releases)))
-    };
+    let scraped_tags = Arc::new(FuturesMutex::new(Vec::new()));
 
     tags.try_for_each_concurrent(concurrency, |tag| {
         let registry_client = registry_client.clone();
         let cache = cache.clone();
-        let releases = releases.clone();
+        let mut releases = releases.clone();
+        let scraped_tags = scraped_tags.clone();
 
         async move {
-            let (arch, manifestref, mut layers_digests) =
-                get_manifest_layers(tag.to_owned(), &repo, &registry_client).await?;
+            if !tag_filter.map_or(true, |filter| filter.is_match(&tag)) {
+                trace!("[{}] Skipping tag not matching {:?}", &tag, tag_filter);
+                scraped_tags.lock().await.push(ScrapedTag {
+                    tag,
+                    disposition: TagDisposition::FilteredByRegex,
+                    version: None,
+                    manifestref: None,
+                });
+                return Ok(());
+            }
+
+            let manifest_start = Instant::now();
+            let (arch, manifestref, content_digest, mut layers_digests) =
+                get_manifest_layers(tag.to_owned(), &repo, &registry.host, &registry_client)
+                    .await?;
 
             // if the image is multi arch, we will have to get one image from the manifest list and
             // use its metadata, because manifest lists are just collections of manifests and don't
             // have their own layers with metadata files.
-            if arch.as_ref().unwrap() == "multi" {
+            if arch.as_deref() == Some(cincinnati::MULTI_ARCH) {
                 let digest = layers_digests
                     .first()
                     .map(std::string::ToString::to_string)
-                    .expect(
-                        format!("no images referenced in ManifestList ref:{}", manifestref)
-                            .as_str(),
-                    );
+                    .ok_or_else(|| {
+                        format_err!("no images referenced in ManifestList ref:{}", manifestref)
+                    })?;
                 // TODO: destructured assignments are unstable in current rust, after updating rust
                 // change this to (_,_,layers_digests) and remove separate assignment from below.
-                let (_ml_arch, _ml_manifestref, ml_layers_digests) =
-                    get_manifest_layers(digest, &repo, &registry_client).await?;
+                let (_ml_arch, _ml_manifestref, _ml_content_digest, ml_layers_digests) =
+                    get_manifest_layers(digest, &repo, &registry.host, &registry_client).await?;
                 layers_digests = ml_layers_digests;
             }
+            record_phase(timings, BuildPhase::ManifestFetch, manifest_start);
+
+            if let Some(digests) = digests {
+                if let Err(e) = digests.verify(&manifestref, &content_digest).await {
+                    warn!("[{}] Skipping tag with unverified manifest: {:#}", &tag, e);
+                    scraped_tags.lock().await.push(ScrapedTag {
+                        tag,
+                        disposition: TagDisposition::Unverified,
+                        version: None,
+                        manifestref: Some(manifestref),
+                    });
+                    return Ok(());
+                }
+            }
 
-            let release = match lookup_or_fetch(
+            let mut release = match lookup_or_fetch(
                 layers_digests,
                 registry_client.to_owned(),
                 registry.to_owned(),
@@ -350,6 +419,9 @@ pub async fn fetch_releases(
                 manifestref.clone(),
                 manifestref_key.to_string(),
                 arch,
+                record_provenance,
+                metadata_limits,
+                timings,
             )
             .await?
             {
@@ -357,24 +429,54 @@ pub async fn fetch_releases(
                 None => {
                     // Reminder: this means the layer_digests point to layers
                     // without any release and we've cached this before
+                    scraped_tags.lock().await.push(ScrapedTag {
+                        tag,
+                        disposition: TagDisposition::InvalidMetadata,
+                        version: None,
+                        manifestref: Some(manifestref),
+                    });
                     return Ok(());
                 }
             };
 
-            releases.lock().await.push(release);
+            // Referrers are not cached, as artifacts can be attached to an
+            // image at any time.
+            if let Some(referrers) = referrers {
+                let annotations = referrers
+                    .annotations(&manifestref)
+                    .await
+                    .context(format!("[{}] could not fetch referrers", tag))?;
+                for (key, value) in annotations {
+                    release.metadata.metadata.entry(key).or_insert(value);
+                }
+            }
+
+            if record_provenance {
+                add_provenance(&mut release.metadata, registry, repo, &tag, &manifestref);
+            }
+
+            let version = release.metadata.version.to_string();
+            releases
+                .send(release)
+                .await
+                .map_err(|_| format_err!("[{}] release consumer stopped", tag))?;
+            scraped_tags.lock().await.push(ScrapedTag {
+                tag,
+                disposition: TagDisposition::Included,
+                version: Some(version),
+                manifestref: Some(manifestref),
+            });
 
             Ok(())
         }
     })
     .await?;
 
-    let releases = Arc::<
-        FuturesMutex<Vec<cincinnati::plugins::internal::graph_builder::release::Release>>,
-    >::try_unwrap(releases)
-    .map_err(|_| format_err!("Unwrapping the shared Releases vector. This must not fail."))?
-    .into_inner();
+    let scraped_tags = Arc::try_unwrap(scraped_tags)
+        .map_err(|_| format_err!("Unwrapping the shared tags vector. This must not fail."))?
+        .into_inner();
 
-    Ok(releases)
+    Ok(scraped_tags)
 }
 
 /// Look up release metadata for a specific tag, and cache it.
@@ -386,6 +488,9 @@ pub async fn fetch_releases(
 /// Update Images with release metadata should be immutable, but
 /// tags on registry can be mutated at any time. Thus, the cache
 /// is keyed on the manifest reference.
+///
+/// With `record_provenance`, the time the metadata is fetched is recorded
+/// along with it, so that it stays the same across scrapes.
 #[allow(clippy::too_many_arguments)]
 async fn lookup_or_fetch(
     layer_digests: Vec<String>,
@@ -397,6 +502,9 @@ async fn lookup_or_fetch(
     manifestref: String,
     manifestref_key: String,
     arch: Option<String>,
+    record_provenance: bool,
+    metadata_limits: MetadataLimits,
+    timings: &Mutex<PhaseTimings>,
 ) -> Fallible<Option<cincinnati::plugins::internal::graph_builder::release::Release>> {
     let cached_metadata = {
         // Nest the guard in a scope to guarantee that the cache isn't locked when trying to write to it later
@@ -415,22 +523,26 @@ async fn lookup_or_fetch(
         None => {
             let placeholder = Option::from(Metadata {
                 kind: MetadataKind::V0,
-                version: Version::new(0, 0, 0),
+                version: Version::new(0, 0, 0).into(),
                 previous: vec![],
                 next: vec![],
                 metadata: Default::default(),
             });
             cache.write().await.insert(manifestref.clone(), placeholder);
 
+            let decode_start = Instant::now();
             let metadata = find_first_release_metadata(
                 layer_digests,
                 registry_client,
+                &registry.host,
                 repo.clone(),
                 tag.clone(),
+                metadata_limits,
             )
             .await
-            .context("failed to find first release")?
-            .map(|mut metadata| {
+            .context("failed to find first release")?;
+            record_phase(timings, BuildPhase::MetadataDecode, decode_start);
+            let metadata = metadata.map(|mut metadata| {
                 // Attach the manifestref this release was found in for further processing
                 metadata
                     .metadata
@@ -438,8 +550,8 @@ async fn lookup_or_fetch(
 
                 // Process the manifest architecture if given
                 if let Some(arch) = arch {
-                    // Encode the architecture as SemVer information
-                    metadata.version.build = vec![semver::Identifier::AlphaNumeric(arch.clone())];
+                    // Encode the architecture as build metadata
+                    metadata.version.set_build(&arch);
 
                     // Attach the architecture for later processing
                     metadata
@@ -447,6 +559,13 @@ async fn lookup_or_fetch(
                         .insert("io.openshift.upgrades.graph.release.arch".to_owned(), arch);
                 };
 
+                if record_provenance {
+                    metadata.metadata.insert(
+                        provenance_key("scraped_at"),
+                        chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                    );
+                }
+
                 metadata
             });
 
@@ -466,26 +585,56 @@ async fn lookup_or_fetch(
     }))
 }
 
-// Get a stream of tags
+/// Add the time elapsed since `start` to the time spent in `phase`.
+fn record_phase(timings: &Mutex<PhaseTimings>, phase: BuildPhase, start: Instant) {
+    timings
+        .lock()
+        .expect("build phase timings lock poisoned")
+        .add(phase, start.elapsed());
+}
+
+/// Number of tags listed per request.
+static TAGS_PAGE_SIZE: usize = 20;
+
+// Get a stream of tags, acquiring a request from the budget of `host` for
+// each page.
 async fn get_tags<'a, 'b: 'a>(
     repo: &'b str,
+    host: &'b str,
     registry_client: &'b dkregistry::v2::Client,
 ) -> impl TryStreamExt<Item = Fallible<String>> + 'a {
-    registry_client
-        // According to https://docs.docker.com/registry/spec/api/#listing-image-tags
-        // the tags should be ordered lexically but they aren't
-        .get_tags(repo, Some(20))
-        .map_err(|e| format_err!("{}", e))
+    let first_page = commons::budget::acquire(host).await;
+
+    futures::stream::iter(first_page.err().map(Err)).chain(
+        registry_client
+            // According to https://docs.docker.com/registry/spec/api/#listing-image-tags
+            // the tags should be ordered lexically but they aren't
+            .get_tags(repo, Some(TAGS_PAGE_SIZE))
+            .map_err(|e| format_err!("{}", e))
+            .enumerate()
+            .then(move |(index, tag)| async move {
+                // The next page is requested once the last tag of a page is consumed.
+                if tag.is_ok() && (index + 1) % TAGS_PAGE_SIZE == 0 {
+                    commons::budget::acquire(host).await?;
+                }
+                tag
+            }),
+    )
 }
 
+/// Fetch the manifest of `repo`:`tag`, returning it with the manifestref
+/// reported by the registry and the digest of the fetched manifest content.
+///
+/// The manifestref is only a claim of the registry, whereas the content
+/// digest is computed over the bytes which are actually parsed.
 async fn get_manifest_and_ref(
     tag: String,
     repo: String,
     registry_client: &dkregistry::v2::Client,
-) -> Result<(String, dkregistry::v2::manifest::Manifest, String), Error> {
+) -> Result<(String, dkregistry::v2::manifest::Manifest, String, String), Error> {
     trace!("[{}] Processing {}", &tag, &repo);
-    let (manifest, manifestref) = registry_client
-        .get_manifest_and_ref(&repo, &tag)
+    let (raw_manifest, media_type, manifestref) = registry_client
+        .get_raw_manifest_and_metadata(&repo, &tag)
         .map_err(|e| {
             format_err!(
                 "fetching manifest and manifestref for {}:{}: {}",
@@ -498,23 +647,88 @@ async fn get_manifest_and_ref(
 
     let manifestref =
         manifestref.ok_or_else(|| format_err!("no manifestref found for {}:{}", &repo, &tag))?;
+    let content_digest = manifest_digest(&raw_manifest);
+    let manifest = parse_manifest(registry_client, &repo, media_type, &raw_manifest)
+        .await
+        .context(format!("parsing manifest for {}:{}", &repo, &tag))?;
 
-    Ok((tag, manifest, manifestref))
+    Ok((tag, manifest, manifestref, content_digest))
+}
+
+/// Parse a manifest of the given `media_type`, fetching the config blob of
+/// schema 2 manifests from `repo`.
+async fn parse_manifest(
+    registry_client: &dkregistry::v2::Client,
+    repo: &str,
+    media_type: dkregistry::mediatypes::MediaTypes,
+    raw_manifest: &[u8],
+) -> Fallible<dkregistry::v2::manifest::Manifest> {
+    use dkregistry::v2::manifest;
+
+    let parsed = match media_type {
+        ManifestV2S1Signed => manifest::Manifest::S1Signed(serde_json::from_slice(raw_manifest)?),
+        ManifestV2S2 => {
+            let spec: manifest::ManifestSchema2Spec = serde_json::from_slice(raw_manifest)?;
+            let schema2 = spec
+                .fetch_config_blob(registry_client.clone(), repo.to_string())
+                .await
+                .map_err(|e| format_err!("fetching config blob: {}", e))?;
+            manifest::Manifest::S2(schema2)
+        }
+        ManifestList => manifest::Manifest::ML(serde_json::from_slice(raw_manifest)?),
+        unsupported => bail!("unsupported manifest media type {:?}", unsupported),
+    };
+    Ok(parsed)
+}
+
+/// Digest of the content of a manifest, in the `sha256:<hex>` form of manifestrefs.
+pub(crate) fn manifest_digest(raw_manifest: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(raw_manifest)))
 }
 
 fn format_release_source(registry: &Registry, repo: &str, manifestref: &str) -> String {
     format!("{}/{}@{}", registry.host_port_string(), repo, manifestref)
 }
 
+/// Return the provenance metadata key `name`.
+pub fn provenance_key(name: &str) -> String {
+    format!("{}.{}", PROVENANCE_NAMESPACE, name)
+}
+
+/// Record where the release in `metadata` was scraped from: the `registry`,
+/// the `repo`sitory, the `tag` and the `manifestref` digest.
+fn add_provenance(
+    metadata: &mut Metadata,
+    registry: &Registry,
+    repo: &str,
+    tag: &str,
+    manifestref: &str,
+) {
+    let host = registry.host_port_string();
+    for (name, value) in &[
+        ("registry", host.as_str()),
+        ("repository", repo),
+        ("tag", tag),
+        ("digest", manifestref),
+    ] {
+        metadata
+            .metadata
+            .insert(provenance_key(name), value.to_string());
+    }
+}
+
 async fn find_first_release_metadata(
     layer_digests: Vec<String>,
     registry_client: dkregistry::v2::Client,
+    host: &str,
     repo: String,
     tag: String,
+    limits: MetadataLimits,
 ) -> Fallible<Option<Metadata>> {
     for layer_digest in layer_digests {
         trace!("[{}] Downloading layer {}", &tag, &layer_digest);
         let (repo, tag) = (repo.clone(), tag.clone());
+        commons::budget::acquire(host).await?;
 
         let blob = registry_client
             .get_blob(&repo, &layer_digest)
@@ -538,12 +752,17 @@ async fn find_first_release_metadata(
             &blob.len(),
         );
 
-        match tokio::task::spawn_blocking(move || assemble_metadata(&blob, metadata_filename))
-            .await?
+        match tokio::task::spawn_blocking(move || {
+            assemble_metadata_within(&blob, metadata_filename, limits)
+        })
+        .await?
         {
             Ok(metadata) => {
                 return Ok(Some(metadata));
             }
+            Err(e) if e.is::<MetadataLimitExceeded>() => {
+                return Err(e.context(format!("[{}] layer {}", &tag, &layer_digest)));
+            }
             Err(e) => {
                 debug!(
                     "[{}] Could not assemble metadata from layer ({}): {}",
@@ -583,9 +802,66 @@ struct Layer {
     blob_sum: String,
 }
 
-fn assemble_metadata(blob: &[u8], metadata_filename: &str) -> Result<Metadata, Error> {
-    let mut archive = Archive::new(GzDecoder::new(blob));
-    match archive
+/// Default maximum size of the release metadata file of an image, in bytes.
+pub const DEFAULT_MAX_METADATA_BYTES: u64 = 1024 * 1024;
+
+/// Limits on the release metadata of an image, so that an unexpectedly huge
+/// or malicious layer fails fast instead of being decoded at length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataLimits {
+    /// Maximum size of the release metadata file, in bytes. Unlimited if unset.
+    pub max_bytes: Option<u64>,
+
+    /// Maximum time spent looking for and decoding the release metadata in
+    /// a layer. Unlimited if unset.
+    pub max_decode_time: Option<Duration>,
+}
+
+impl Default for MetadataLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: Some(DEFAULT_MAX_METADATA_BYTES),
+            max_decode_time: None,
+        }
+    }
+}
+
+/// Error of a layer exceeding the `MetadataLimits`.
+#[derive(Debug, Fail)]
+#[error("{0}")]
+pub struct MetadataLimitExceeded(String);
+
+/// Extract and parse the release metadata file from a compressed layer blob,
+/// within the default `MetadataLimits`.
+pub fn assemble_metadata(blob: &[u8], metadata_filename: &str) -> Result<Metadata, Error> {
+    assemble_metadata_within(blob, metadata_filename, MetadataLimits::default())
+}
+
+/// Extract and parse the release metadata file from a compressed layer blob,
+/// failing with a `MetadataLimitExceeded` error once `limits` are exceeded.
+pub fn assemble_metadata_within(
+    blob: &[u8],
+    metadata_filename: &str,
+    limits: MetadataLimits,
+) -> Result<Metadata, Error> {
+    let deadline = limits
+        .max_decode_time
+        .and_then(|timeout| Instant::now().checked_add(timeout));
+    let check_deadline = || -> Result<(), Error> {
+        match (deadline, limits.max_decode_time) {
+            (Some(deadline), Some(timeout)) if Instant::now() >= deadline => {
+                Err(MetadataLimitExceeded(format!(
+                    "decoding '{}' exceeded the limit of {:?}",
+                    metadata_filename, timeout
+                ))
+                .into())
+            }
+            _ => Ok(()),
+        }
+    };
+
+    let mut archive = Archive::new(DeadlineReader::new(GzDecoder::new(blob), deadline));
+    let found = archive
         .entries()?
         .filter_map(|entry| match entry {
             Ok(file) => Some(file),
@@ -600,10 +876,23 @@ fn assemble_metadata(blob: &[u8], metadata_filename: &str) -> Result<Metadata, E
                 debug!("failed to read file header: {}", err);
                 false
             }
-        }) {
+        });
+    check_deadline()?;
+    match found {
         Some(mut file) => {
+            let size = file.header().size()?;
+            if let Some(max_bytes) = limits.max_bytes.filter(|max_bytes| size > *max_bytes) {
+                return Err(MetadataLimitExceeded(format!(
+                    "'{}' of {} bytes exceeds the limit of {} bytes",
+                    metadata_filename, size, max_bytes
+                ))
+                .into());
+            }
+
             let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
+            let read = file.read_to_string(&mut contents);
+            check_deadline()?;
+            read?;
             match serde_json::from_str::<Metadata>(&contents) {
                 Ok(m) => Ok::<Metadata, Error>(m),
                 Err(e) => bail!(format!("couldn't parse '{}': {}", metadata_filename, e)),
@@ -613,6 +902,90 @@ fn assemble_metadata(blob: &[u8], metadata_filename: &str) -> Result<Metadata, E
     }
 }
 
+/// Categories of registry errors, as reported by `error_category`.
+pub static ERROR_CATEGORIES: &[&str] = &[
+    "dns",
+    "tls",
+    "connect",
+    "timeout",
+    "auth",
+    "not_found",
+    "rate_limited",
+    "server_error",
+    "decode",
+    "other",
+];
+
+/// Classify a registry error, for metrics labels.
+///
+/// Well-known error types in the chain are inspected first, falling back to
+/// the error messages for errors which only carry a textual description.
+pub fn error_category(err: &Error) -> &'static str {
+    for cause in err.chain() {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            if let Some(status) = e.status() {
+                return status_category(status.as_u16());
+            }
+            if e.is_decode() {
+                return "decode";
+            }
+            if e.is_timeout() {
+                return "timeout";
+            }
+        }
+        if cause.is::<serde_json::Error>() || cause.is::<MetadataLimitExceeded>() {
+            return "decode";
+        }
+    }
+
+    let message = format!("{:#}", err).to_lowercase();
+    let status = message
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|word| word.len() == 3)
+        .filter_map(|word| word.parse::<u16>().ok())
+        .map(status_category)
+        .find(|category| *category != "other");
+    if let Some(category) = status {
+        return category;
+    }
+
+    let matches = |needles: &[&str]| needles.iter().any(|n| message.contains(n));
+    if matches(&["unauthorized", "forbidden", "denied"]) {
+        "auth"
+    } else if matches(&["too many requests", "rate limit"]) {
+        "rate_limited"
+    } else if matches(&["dns error", "failed to lookup address", "name resolution"]) {
+        "dns"
+    } else if matches(&["certificate", "tls", "ssl"]) {
+        "tls"
+    } else if matches(&["timed out", "timeout"]) {
+        "timeout"
+    } else if matches(&[
+        "connection refused",
+        "connection reset",
+        "error trying to connect",
+    ]) {
+        "connect"
+    } else if matches(&["not found", "unknown manifest", "name_unknown"]) {
+        "not_found"
+    } else if matches(&["couldn't parse", "json", "decode", "invalid"]) {
+        "decode"
+    } else {
+        "other"
+    }
+}
+
+/// Classify an HTTP status code returned by the registry.
+fn status_category(status: u16) -> &'static str {
+    match status {
+        401 | 403 => "auth",
+        404 => "not_found",
+        429 => "rate_limited",
+        500..=599 => "server_error",
+        _ => "other",
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -665,4 +1038,93 @@ mod tests {
             assert_eq!(input, registry.host_port_string());
         }
     }
+
+    #[test]
+    fn registry_try_parse_port_out_of_range() {
+        for input in &["http://localhost:99999", "quay.io:65536"] {
+            assert!(Registry::try_from_str(input).is_err(), "parsed {}", input);
+        }
+    }
+
+    #[test]
+    fn assemble_metadata_rejects_garbage() {
+        let metadata_filename = "release-manifests/release-metadata";
+        let blobs: Vec<&[u8]> = vec![b"", b"not a tarball", &[0x1f, 0x8b, 0x08, 0x00]];
+        for blob in blobs {
+            assert!(assemble_metadata(blob, metadata_filename).is_err());
+        }
+    }
+
+    #[test]
+    fn assemble_metadata_within_limits() -> Fallible<()> {
+        let metadata_filename = "release-manifests/release-metadata";
+        let contents = r#"{"kind": "cincinnati-metadata-v0", "version": "4.14.0"}"#;
+        let mut archive = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive.append_data(&mut header, metadata_filename, contents.as_bytes())?;
+        let blob = archive.into_inner()?.finish()?;
+
+        let assemble = |max_bytes, max_decode_time| {
+            assemble_metadata_within(
+                &blob,
+                metadata_filename,
+                MetadataLimits {
+                    max_bytes,
+                    max_decode_time,
+                },
+            )
+        };
+        assert_eq!(
+            assemble(Some(contents.len() as u64), Some(Duration::from_secs(5)))?
+                .version
+                .to_string(),
+            "4.14.0"
+        );
+        assert!(assemble(None, None).is_ok());
+
+        for err in vec![
+            assemble(Some(contents.len() as u64 - 1), None).unwrap_err(),
+            assemble(None, Some(Duration::ZERO)).unwrap_err(),
+        ] {
+            assert!(err.is::<MetadataLimitExceeded>(), "{:#}", err);
+            assert_eq!(error_category(&err), "decode");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn categorize_registry_errors() {
+        let tests = vec![
+            (format_err!("status code 401 Unauthorized"), "auth"),
+            (
+                format_err!("UNAUTHORIZED: access to the requested resource is not authorized"),
+                "auth",
+            ),
+            (format_err!("404 Not Found"), "not_found"),
+            (format_err!("429 Too Many Requests"), "rate_limited"),
+            (format_err!("503 Service Unavailable"), "server_error"),
+            (
+                format_err!("dns error: failed to lookup address information"),
+                "dns",
+            ),
+            (format_err!("the certificate was not trusted"), "tls"),
+            (format_err!("something unexpected"), "other"),
+        ];
+
+        for (err, expected) in tests {
+            let err = err.context("fetching tags from quay.io:5000/openshift");
+            assert_eq!(error_category(&err), expected, "{:#}", err);
+            assert!(ERROR_CATEGORIES.contains(&expected));
+        }
+
+        let err = Error::from(serde_json::from_str::<Metadata>("{").unwrap_err());
+        assert_eq!(error_category(&err), "decode");
+    }
 }
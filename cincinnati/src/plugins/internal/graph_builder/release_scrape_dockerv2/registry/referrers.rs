@@ -0,0 +1,256 @@
+//! Supplementary release metadata attached through the OCI referrers API.
+//!
+//! Artifacts referring to a release image, e.g. attached after the image was
+//! built, are listed by `GET /v2/<repo>/referrers/<digest>`. The annotations
+//! of the artifacts of the configured type whose keys start with the
+//! configured prefix are merged into the release metadata.
+//!
+//! See https://github.com/opencontainers/distribution-spec/blob/main/spec.md#listing-referrers
+
+use super::Registry;
+use crate::plugins::prelude_plugin_impl::*;
+
+use reqwest::header::{HeaderValue, ACCEPT, WWW_AUTHENTICATE};
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Media type of the image index listing the referrers of a manifest.
+pub static IMAGE_INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+
+/// Timeout of each referrers request, in seconds.
+static REFERRERS_TIMEOUT_SECS: u64 = 30;
+
+/// Referrers of a manifest, as an image index.
+#[derive(Debug, Deserialize)]
+struct ReferrersIndex {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+}
+
+/// Descriptor of an artifact referring to a manifest.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    #[serde(default)]
+    artifact_type: Option<String>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+/// Token issued by the registry authorization service.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    access_token: Option<String>,
+}
+
+/// Client looking up the referrers of the manifests of one repository.
+///
+/// A bearer token obtained for the repository is reused by all lookups.
+#[derive(Debug)]
+pub struct ReferrersClient {
+    client: reqwest::Client,
+    base_url: String,
+    repo: String,
+    credentials: Option<(String, String)>,
+    artifact_type: String,
+    annotation_prefix: String,
+    token: RwLock<Option<String>>,
+}
+
+impl ReferrersClient {
+    /// Create a client for the referrers of type `artifact_type` in `repo`.
+    pub fn try_new(
+        registry: &Registry,
+        repo: &str,
+        username: Option<&str>,
+        password: Option<&str>,
+        artifact_type: &str,
+        annotation_prefix: &str,
+    ) -> Fallible<Self> {
+        let client = commons::http::HttpClientBuilder::new()
+            .timeout(Duration::from_secs(REFERRERS_TIMEOUT_SECS))
+            .build()
+            .context("Building reqwest client")?;
+
+        let base_url = format!(
+            "{}://{}{}",
+            if registry.insecure { "http" } else { "https" },
+            registry.host,
+            registry
+                .port
+                .map(|port| format!(":{}", port))
+                .unwrap_or_default()
+        );
+
+        Ok(Self {
+            client,
+            base_url,
+            repo: repo.to_string(),
+            credentials: username
+                .zip(password)
+                .map(|(username, password)| (username.to_string(), password.to_string())),
+            artifact_type: artifact_type.to_string(),
+            annotation_prefix: annotation_prefix.to_string(),
+            token: RwLock::new(None),
+        })
+    }
+
+    /// Return the annotations with the configured prefix of the artifacts
+    /// referring to `manifestref`.
+    ///
+    /// Registries without support for the referrers API have no referrers.
+    /// When several artifacts carry the same key, the first one listed wins.
+    pub async fn annotations(&self, manifestref: &str) -> Fallible<HashMap<String, String>> {
+        let url = format!(
+            "{}/v2/{}/referrers/{}",
+            self.base_url, self.repo, manifestref
+        );
+
+        let mut response = self.get(&url).await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            let challenge = response.headers().get(WWW_AUTHENTICATE).cloned();
+            self.authenticate(challenge.as_ref()).await?;
+            response = self.get(&url).await?;
+        }
+
+        if response.status() == StatusCode::NOT_FOUND {
+            debug!(
+                "referrers of {}@{} not found, assuming unsupported referrers API",
+                self.repo, manifestref
+            );
+            return Ok(HashMap::new());
+        }
+
+        let index: ReferrersIndex = response
+            .error_for_status()
+            .context(format!(
+                "fetching referrers of {}@{}",
+                self.repo, manifestref
+            ))?
+            .json()
+            .await
+            .context(format!(
+                "parsing referrers of {}@{}",
+                self.repo, manifestref
+            ))?;
+
+        let mut annotations = HashMap::new();
+        for descriptor in index.manifests {
+            // The artifactType filter is optional for registries.
+            if descriptor.artifact_type.as_deref() != Some(&self.artifact_type) {
+                continue;
+            }
+            for (key, value) in descriptor.annotations {
+                if key.starts_with(&self.annotation_prefix) {
+                    annotations.entry(key).or_insert(value);
+                }
+            }
+        }
+        trace!(
+            "found {} referrer annotations for {}@{}",
+            annotations.len(),
+            self.repo,
+            manifestref
+        );
+
+        Ok(annotations)
+    }
+
+    async fn get(&self, url: &str) -> Fallible<reqwest::Response> {
+        let mut request = self
+            .client
+            .get(url)
+            .query(&[("artifactType", &self.artifact_type)])
+            .header(ACCEPT, IMAGE_INDEX_MEDIA_TYPE);
+        if let Some(token) = self.token.read().await.as_ref() {
+            request = request.bearer_auth(token);
+        }
+        commons::budget::acquire_url(url).await?;
+        request.send().await.context(format!("fetching {}", url))
+    }
+
+    /// Obtain a bearer token for pulling from the repository, as requested
+    /// by the `WWW-Authenticate` challenge of the registry.
+    async fn authenticate(&self, challenge: Option<&HeaderValue>) -> Fallible<()> {
+        let challenge = challenge
+            .and_then(|challenge| challenge.to_str().ok())
+            .ok_or_else(|| format_err!("401 Unauthorized without authentication challenge"))?;
+        let params = parse_bearer_challenge(challenge)
+            .ok_or_else(|| format_err!("unsupported authentication challenge '{}'", challenge))?;
+        let realm = params
+            .get("realm")
+            .ok_or_else(|| format_err!("no realm in authentication challenge '{}'", challenge))?;
+
+        let scope = format!("repository:{}:pull", self.repo);
+        let mut query = vec![("scope", scope.as_str())];
+        if let Some(service) = params.get("service") {
+            query.push(("service", service.as_str()));
+        }
+        let mut request = self.client.get(realm).query(&query);
+        if let Some((username, password)) = &self.credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        commons::budget::acquire_url(realm).await?;
+
+        let response: TokenResponse = request
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context(format!("requesting token from {}", realm))?
+            .json()
+            .await
+            .context(format!("parsing token from {}", realm))?;
+        let token = response
+            .token
+            .or(response.access_token)
+            .ok_or_else(|| format_err!("no token in response from {}", realm))?;
+
+        *self.token.write().await = Some(token);
+        Ok(())
+    }
+}
+
+/// Parse the parameters of a `Bearer` authentication challenge, e.g.
+/// `Bearer realm="https://auth.example.com/token",service="registry"`.
+fn parse_bearer_challenge(challenge: &str) -> Option<HashMap<String, String>> {
+    let (scheme, params) = challenge.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+
+    Some(
+        params
+            .split(',')
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| {
+                (
+                    key.trim().to_lowercase(),
+                    value.trim().trim_matches('"').to_string(),
+                )
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_bearer_challenges() {
+        let params = parse_bearer_challenge(
+            r#"Bearer realm="https://quay.io/v2/auth",service="quay.io",scope="repository:a/b:pull""#,
+        )
+        .unwrap();
+        assert_eq!(params["realm"], "https://quay.io/v2/auth");
+        assert_eq!(params["service"], "quay.io");
+
+        assert!(parse_bearer_challenge(r#"Basic realm="registry""#).is_none());
+        assert!(parse_bearer_challenge("Bearer").is_none());
+    }
+}
@@ -0,0 +1,155 @@
+//! Dispositions of the tags seen by the scrape of each repository.
+//!
+//! Scrape plugins add the listing of the repository they scraped to the IO
+//! parameters, under `GRAPH_TAG_LISTINGS_PARAM_KEY`, so that the daemon can
+//! keep the listings of the latest successful scrape of each graph.
+
+use commons::prelude_errors::*;
+use commons::GRAPH_TAG_LISTINGS_PARAM_KEY;
+use std::collections::{HashMap, HashSet};
+
+/// Outcome of the scrape of a tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagDisposition {
+    /// The tag release is part of the scraped releases.
+    Included,
+    /// The tag does not match the configured `tag_regex`.
+    FilteredByRegex,
+    /// No valid release metadata was found in the tag image.
+    InvalidMetadata,
+    /// The tag release version was already provided by another tag.
+    Duplicate,
+    /// The tag manifest digest is neither allowlisted nor signed.
+    Unverified,
+}
+
+/// A tag seen by a scrape.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrapedTag {
+    pub tag: String,
+    pub disposition: TagDisposition,
+    /// Release version, if metadata was found.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Manifest reference, if the tag was fetched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifestref: Option<String>,
+}
+
+/// Tags seen by the latest successful scrape of a repository.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagListing {
+    /// Registry and repository, e.g. "quay.io/openshift-release-dev/ocp-release".
+    pub repository: String,
+    pub tags: Vec<ScrapedTag>,
+}
+
+/// Sort `tags` by name and flag releases already provided by a previous tag.
+pub(crate) fn mark_duplicates(tags: &mut [ScrapedTag]) {
+    tags.sort_by(|a, b| a.tag.cmp(&b.tag));
+
+    let mut seen = HashSet::new();
+    for tag in tags.iter_mut() {
+        if tag.disposition != TagDisposition::Included {
+            continue;
+        }
+        if let Some(version) = &tag.version {
+            if !seen.insert(version.clone()) {
+                tag.disposition = TagDisposition::Duplicate;
+            }
+        }
+    }
+}
+
+/// Read the tag listings recorded in the IO `parameters`, sorted by repository.
+pub fn from_parameters(parameters: &HashMap<String, String>) -> Fallible<Vec<TagListing>> {
+    match parameters.get(GRAPH_TAG_LISTINGS_PARAM_KEY) {
+        Some(encoded) => serde_json::from_str(encoded).context("parsing tag listings"),
+        None => Ok(vec![]),
+    }
+}
+
+/// Replace the listing of `repository` recorded in the IO `parameters` with
+/// the tags of its latest scrape.
+pub(crate) fn add_to_parameters(
+    repository: String,
+    tags: Vec<ScrapedTag>,
+    parameters: &mut HashMap<String, String>,
+) -> Fallible<()> {
+    let mut listings = from_parameters(parameters)?;
+    listings.retain(|listing| listing.repository != repository);
+    listings.push(TagListing { repository, tags });
+    listings.sort_by(|a, b| a.repository.cmp(&b.repository));
+
+    parameters.insert(
+        GRAPH_TAG_LISTINGS_PARAM_KEY.to_string(),
+        serde_json::to_string(&listings)?,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str, disposition: TagDisposition, version: Option<&str>) -> ScrapedTag {
+        ScrapedTag {
+            tag: name.to_string(),
+            disposition,
+            version: version.map(str::to_string),
+            manifestref: None,
+        }
+    }
+
+    #[test]
+    fn mark_duplicate_tags() {
+        let mut tags = vec![
+            tag("latest", TagDisposition::Included, Some("4.14.1")),
+            tag("4.14.1", TagDisposition::Included, Some("4.14.1")),
+            tag("broken", TagDisposition::InvalidMetadata, None),
+            tag("4.14.0", TagDisposition::Included, Some("4.14.0")),
+        ];
+        mark_duplicates(&mut tags);
+
+        let dispositions: Vec<(&str, TagDisposition)> = tags
+            .iter()
+            .map(|t| (t.tag.as_str(), t.disposition))
+            .collect();
+        assert_eq!(
+            dispositions,
+            vec![
+                ("4.14.0", TagDisposition::Included),
+                ("4.14.1", TagDisposition::Included),
+                ("broken", TagDisposition::InvalidMetadata),
+                ("latest", TagDisposition::Duplicate),
+            ]
+        );
+    }
+
+    #[test]
+    fn replace_listings_in_parameters() -> Fallible<()> {
+        let mut parameters = HashMap::new();
+        assert!(from_parameters(&parameters)?.is_empty());
+
+        let mirror = "mirror.example.com/ocp-release".to_string();
+        let quay = "quay.io/openshift-release-dev/ocp-release".to_string();
+        let included = tag("4.14.0", TagDisposition::Included, Some("4.14.0"));
+        add_to_parameters(quay.clone(), vec![included.clone()], &mut parameters)?;
+        add_to_parameters(mirror.clone(), vec![], &mut parameters)?;
+        add_to_parameters(
+            quay.clone(),
+            vec![included.clone(), included],
+            &mut parameters,
+        )?;
+
+        let listings = from_parameters(&parameters)?;
+        let repositories: Vec<(&str, usize)> = listings
+            .iter()
+            .map(|listing| (listing.repository.as_str(), listing.tags.len()))
+            .collect();
+        assert_eq!(repositories, vec![(mirror.as_str(), 0), (quay.as_str(), 2)]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+//! Releases described by local files.
+//!
+//! Releases are described in JSON, with the payload as `source` and the
+//! release metadata as `metadata`:
+//!
+//! ```json
+//! {
+//!   "source": "quay.io/openshift-release-dev/ocp-release@sha256:...",
+//!   "metadata": {
+//!     "kind": "cincinnati-metadata-v0",
+//!     "version": "4.14.1",
+//!     "previous": ["4.14.0"],
+//!     "metadata": {"io.openshift.upgrades.graph.release.channels": "stable-4.14"}
+//!   }
+//! }
+//! ```
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::graph_builder::build_phases::{BuildPhase, PhaseTimings};
+use self::cincinnati::plugins::internal::graph_builder::release::Release;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use super::ReleaseSource;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Extension of the release files of a directory.
+static RELEASE_FILE_EXTENSION: &str = "json";
+
+/// Releases described by the JSON files of a directory, one release per file.
+#[derive(Debug)]
+pub struct DirectorySource {
+    path: PathBuf,
+}
+
+impl DirectorySource {
+    /// Create a source of the releases described in the directory at `path`.
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl ReleaseSource for DirectorySource {
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    async fn fetch_releases(&self, timings: &Mutex<PhaseTimings>) -> Fallible<Vec<Release>> {
+        let listing_start = Instant::now();
+        let mut paths = vec![];
+        let mut entries = tokio::fs::read_dir(&self.path)
+            .await
+            .context(format!("Reading directory {:?}", &self.path))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context(format!("Reading directory {:?}", &self.path))?
+        {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some(RELEASE_FILE_EXTENSION) {
+                paths.push(path);
+            } else {
+                debug!(
+                    "Skipping {:?} without .{} extension",
+                    &path, RELEASE_FILE_EXTENSION
+                );
+            }
+        }
+        paths.sort();
+        record_phase(timings, BuildPhase::TagListing, listing_start);
+
+        let decode_start = Instant::now();
+        let mut releases = Vec::with_capacity(paths.len());
+        for path in paths {
+            releases.push(read_json(&path).await?);
+        }
+        record_phase(timings, BuildPhase::MetadataDecode, decode_start);
+
+        Ok(releases)
+    }
+}
+
+/// Releases described by a single JSON file, as an array.
+#[derive(Debug)]
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    /// Create a source of the releases described in the file at `path`.
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl ReleaseSource for FileSource {
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    async fn fetch_releases(&self, timings: &Mutex<PhaseTimings>) -> Fallible<Vec<Release>> {
+        let decode_start = Instant::now();
+        let releases = read_json(&self.path).await?;
+        record_phase(timings, BuildPhase::MetadataDecode, decode_start);
+
+        Ok(releases)
+    }
+}
+
+/// Read and deserialize the JSON file at `path`.
+async fn read_json<T: DeserializeOwned>(path: &Path) -> Fallible<T> {
+    let json = tokio::fs::read(path)
+        .await
+        .context(format!("Reading {:?}", path))?;
+    serde_json::from_slice(&json).context(format!("Deserializing releases from {:?}", path))
+}
+
+/// Add the time elapsed since `start` to the time spent in `phase`.
+fn record_phase(timings: &Mutex<PhaseTimings>, phase: BuildPhase, start: Instant) {
+    timings
+        .lock()
+        .expect("build phase timings lock poisoned")
+        .add(phase, start.elapsed());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing::init_runtime;
+
+    fn release_json(version: &str, previous: &[&str]) -> serde_json::Value {
+        serde_json::json!({
+            "source": format!("quay.io/test/release:{}", version),
+            "metadata": {
+                "kind": "cincinnati-metadata-v0",
+                "version": version,
+                "previous": previous,
+                "metadata": {"kind": "test"},
+            },
+        })
+    }
+
+    fn versions(releases: &[Release]) -> Vec<String> {
+        releases
+            .iter()
+            .map(|release| release.metadata.version.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn fetch_releases_from_directory() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let dir = tempfile::tempdir()?;
+        std::fs::write(
+            dir.path().join("4.14.1.json"),
+            release_json("4.14.1", &["4.14.0"]).to_string(),
+        )?;
+        std::fs::write(
+            dir.path().join("4.14.0.json"),
+            release_json("4.14.0", &[]).to_string(),
+        )?;
+        std::fs::write(dir.path().join("README.md"), "not a release")?;
+
+        let source = DirectorySource::new(dir.path().to_path_buf());
+        let timings = Mutex::new(PhaseTimings::default());
+        let releases = runtime.block_on(source.fetch_releases(&timings))?;
+
+        assert_eq!(versions(&releases), vec!["4.14.0", "4.14.1"]);
+        assert_eq!(releases[1].metadata.previous[0].to_string(), "4.14.0");
+        let timings = timings.into_inner().unwrap();
+        assert!(timings.get(BuildPhase::TagListing).is_some());
+        assert!(timings.get(BuildPhase::MetadataDecode).is_some());
+
+        std::fs::write(dir.path().join("broken.json"), "{")?;
+        assert!(runtime
+            .block_on(source.fetch_releases(&Mutex::default()))
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn fetch_releases_from_file() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("releases.json");
+        std::fs::write(
+            &path,
+            serde_json::json!([release_json("4.14.0", &[]), release_json("4.14.1", &[])])
+                .to_string(),
+        )?;
+
+        let source = FileSource::new(path);
+        let releases = runtime.block_on(source.fetch_releases(&Mutex::default()))?;
+        assert_eq!(versions(&releases), vec!["4.14.0", "4.14.1"]);
+
+        let missing = FileSource::new(dir.path().join("missing.json"));
+        assert!(runtime
+            .block_on(missing.fetch_releases(&Mutex::default()))
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+//! Sources of the releases scraped by the graph-builder.
+//!
+//! A source only fetches releases; assembling them into a graph and merging
+//! it into the graph of previous plugins is shared by all sources, so that a
+//! new source only needs an implementation of `ReleaseSource`.
+//!
+//! Releases flow from the source to the graph assembly through a bounded
+//! channel: releases are added to the graph while others are still being
+//! fetched, and the source waits whenever `PIPELINE_CAPACITY` fetched
+//! releases are not assembled yet.
+
+mod local;
+mod quay;
+mod registry_v2;
+
+pub use local::{DirectorySource, FileSource};
+pub use quay::QuaySource;
+pub use registry_v2::RegistryV2Source;
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::graph_builder::build_phases::{BuildPhase, PhaseTimings};
+use self::cincinnati::plugins::internal::graph_builder::release::{GraphAssembler, Release};
+use self::cincinnati::plugins::internal::graph_builder::release_scrape_dockerv2::tags::{
+    self, ScrapedTag,
+};
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::VersionScheme;
+
+use commons::ScrapeError;
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use std::convert::TryInto;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Maximum number of fetched releases waiting to be assembled.
+pub static PIPELINE_CAPACITY: usize = 64;
+
+/// Source of releases.
+#[async_trait]
+pub trait ReleaseSource: std::fmt::Debug + Send + Sync {
+    /// Return the location of the source, e.g. "quay.io/openshift-release-dev/ocp-release".
+    fn location(&self) -> String;
+
+    /// Return the value of the `repository` label of the source metrics.
+    fn metrics_label(&self) -> String {
+        self.location()
+    }
+
+    /// Fetch all releases of the source, adding the time spent in each
+    /// phase to `timings`.
+    async fn fetch_releases(&self, timings: &Mutex<PhaseTimings>) -> Fallible<Vec<Release>>;
+
+    /// Fetch all releases of the source, sending each to `releases` as soon
+    /// as it is fetched, and adding the time spent in each phase to `timings`.
+    ///
+    /// Sources fetching releases one at a time should override this, so that
+    /// they are assembled while the next ones are fetched.
+    async fn send_releases(
+        &self,
+        timings: &Mutex<PhaseTimings>,
+        mut releases: mpsc::Sender<Release>,
+    ) -> Fallible<()> {
+        for release in self.fetch_releases(timings).await? {
+            releases
+                .send(release)
+                .await
+                .map_err(|_| format_err!("release consumer stopped"))?;
+        }
+        Ok(())
+    }
+
+    /// Take the tags seen by the latest scrape, for sources listing tags.
+    fn take_tag_listing(&self) -> Option<Vec<ScrapedTag>> {
+        None
+    }
+}
+
+/// Scrape of a release source into a graph.
+#[derive(CustomDebug)]
+pub struct ReleaseScrape {
+    source: Box<dyn ReleaseSource>,
+    merge_precedence: MergePrecedence,
+    version_scheme: VersionScheme,
+
+    #[debug(skip)]
+    graph_upstream_raw_releases: prometheus::IntGauge,
+}
+
+impl ReleaseScrape {
+    /// Create a scrape of `source`, whose release versions must follow
+    /// `version_scheme`, registering its metrics to `prometheus_registry`.
+    pub fn try_new(
+        source: Box<dyn ReleaseSource>,
+        merge_precedence: MergePrecedence,
+        version_scheme: VersionScheme,
+        prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let graph_upstream_raw_releases = prometheus::IntGauge::with_opts(
+            prometheus::Opts::new(
+                "graph_upstream_raw_releases",
+                "Number of releases fetched from upstream, before processing",
+            )
+            .const_label("repository", source.metrics_label()),
+        )?;
+        if let Some(prometheus_registry) = &prometheus_registry {
+            prometheus_registry.register(Box::new(graph_upstream_raw_releases.clone()))?;
+        }
+
+        Ok(Self {
+            source,
+            merge_precedence,
+            version_scheme,
+            graph_upstream_raw_releases,
+        })
+    }
+
+    /// Fetch the releases of the source and merge them into the graph of `io`.
+    pub async fn run(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let timings = Mutex::new(PhaseTimings::default());
+        let (sender, receiver) = mpsc::channel(PIPELINE_CAPACITY);
+
+        let fetch = async {
+            self.source
+                .send_releases(&timings, sender)
+                .await
+                .context(format!(
+                    "failed to fetch all release metadata from {}",
+                    self.source.location()
+                ))
+                .map_err(ScrapeError::registry)
+        };
+        let assemble = async {
+            let mut receiver = receiver;
+            let mut assembler = GraphAssembler::default();
+            let mut count = 0usize;
+            let mut assembly = Duration::default();
+            while let Some(release) = receiver.next().await {
+                let start = Instant::now();
+                self.version_scheme
+                    .validate(&release.metadata.version)
+                    .context(format!(
+                        "invalid version of release {} for the {} version scheme",
+                        release.source,
+                        self.version_scheme.name()
+                    ))?;
+                assembler.add(release)?;
+                assembly += start.elapsed();
+                count += 1;
+            }
+            Ok::<_, Error>((assembler, count, assembly))
+        };
+        // On the first error, the other half is dropped, which stops the pipeline.
+        let ((), (assembler, count, mut assembly)) = futures::try_join!(fetch, assemble)?;
+
+        if count == 0 {
+            warn!("could not find any releases in {}", self.source.location());
+        };
+
+        self.graph_upstream_raw_releases.set(count.try_into()?);
+
+        let assembly_start = Instant::now();
+        let scraped = assembler.finish();
+
+        // Merge into the releases scraped by previous plugins, if any.
+        let graph = if io.graph.releases_count() == 0 {
+            scraped
+        } else {
+            let mut graph = io.graph;
+            graph
+                .merge(scraped, self.merge_precedence)
+                .context(format!(
+                    "failed to merge releases from {}",
+                    self.source.location()
+                ))?;
+            graph
+        };
+
+        let mut timings = timings
+            .into_inner()
+            .expect("build phase timings lock poisoned");
+        assembly += assembly_start.elapsed();
+        timings.add(BuildPhase::GraphAssembly, assembly);
+        let mut parameters = io.parameters;
+        timings.add_to_parameters(&mut parameters)?;
+        if let Some(scraped_tags) = self.source.take_tag_listing() {
+            tags::add_to_parameters(self.source.location(), scraped_tags, &mut parameters)?;
+        }
+
+        Ok(InternalIO { graph, parameters })
+    }
+}
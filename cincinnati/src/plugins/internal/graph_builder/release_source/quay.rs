@@ -0,0 +1,70 @@
+//! Releases of a Quay repository.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::graph_builder::build_phases::PhaseTimings;
+use self::cincinnati::plugins::internal::graph_builder::release::Release;
+use self::cincinnati::plugins::internal::graph_builder::release_scrape_dockerv2::{
+    registry, ReleaseScrapeDockerv2Settings,
+};
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use super::{RegistryV2Source, ReleaseSource};
+use futures::channel::mpsc;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Username under which Quay accepts API tokens as registry passwords.
+static QUAY_TOKEN_USERNAME: &str = "$oauthtoken";
+
+/// Releases of the images tagged in a Quay repository.
+///
+/// Quay serves repositories through the registry API, authenticated with
+/// an API token instead of registry credentials.
+#[derive(Debug)]
+pub struct QuaySource(RegistryV2Source);
+
+impl QuaySource {
+    /// Create a source from the settings of a registry scrape, authenticated
+    /// with the API token read from `api_token_path`, if any.
+    pub fn try_new(
+        settings: &ReleaseScrapeDockerv2Settings,
+        api_token_path: Option<&Path>,
+        cache: Option<registry::cache::Cache>,
+        prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let mut settings = settings.clone();
+        if let Some(api_token_path) = api_token_path {
+            let token = quay::read_credentials(api_token_path)
+                .context("could not read quay API credentials")?;
+            settings.username = Some(QUAY_TOKEN_USERNAME.to_string());
+            settings.password = Some(token);
+            settings.credentials_path = None;
+        }
+
+        RegistryV2Source::try_new(&settings, cache, prometheus_registry).map(Self)
+    }
+}
+
+#[async_trait]
+impl ReleaseSource for QuaySource {
+    fn location(&self) -> String {
+        self.0.location()
+    }
+
+    fn metrics_label(&self) -> String {
+        self.0.metrics_label()
+    }
+
+    async fn fetch_releases(&self, timings: &Mutex<PhaseTimings>) -> Fallible<Vec<Release>> {
+        self.0.fetch_releases(timings).await
+    }
+
+    async fn send_releases(
+        &self,
+        timings: &Mutex<PhaseTimings>,
+        releases: mpsc::Sender<Release>,
+    ) -> Fallible<()> {
+        self.0.send_releases(timings, releases).await
+    }
+}
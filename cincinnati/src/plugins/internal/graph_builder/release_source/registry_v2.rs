@@ -0,0 +1,188 @@
+//! Releases of a Docker V2 compatible registry repository.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::graph_builder::build_phases::PhaseTimings;
+use self::cincinnati::plugins::internal::graph_builder::release::Release;
+use self::cincinnati::plugins::internal::graph_builder::release_scrape_dockerv2::{
+    digests::DigestVerifier, registry, tags, ReleaseScrapeDockerv2Settings,
+};
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use super::{ReleaseSource, PIPELINE_CAPACITY};
+use futures::channel::mpsc;
+use futures::StreamExt;
+use std::sync::Mutex;
+
+/// Releases of the images tagged in a registry repository.
+#[derive(CustomDebug)]
+pub struct RegistryV2Source {
+    registry: registry::Registry,
+    repository: String,
+    manifestref_key: String,
+    fetch_concurrency: usize,
+    username: Option<String>,
+    #[debug(skip)]
+    password: Option<String>,
+    cache: registry::cache::Cache,
+    tag_filter: Option<regex::Regex>,
+    referrers: Option<registry::ReferrersClient>,
+    digests: Option<DigestVerifier>,
+    record_provenance: bool,
+    metadata_limits: registry::MetadataLimits,
+    /// Tags seen by the latest scrape, until taken by the scrape assembling them.
+    #[debug(skip)]
+    tag_listing: Mutex<Option<Vec<tags::ScrapedTag>>>,
+
+    #[debug(skip)]
+    graph_upstream_scrape_errors: prometheus::IntCounterVec,
+}
+
+impl RegistryV2Source {
+    /// Create a source from the settings of a registry scrape, registering
+    /// its metrics to `prometheus_registry`.
+    pub fn try_new(
+        settings: &ReleaseScrapeDockerv2Settings,
+        cache: Option<registry::cache::Cache>,
+        prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let graph_upstream_scrape_errors = prometheus::IntCounterVec::new(
+            prometheus::Opts::new(
+                "graph_upstream_scrape_errors_total",
+                "Total number of failed scrapes of the upstream repository, by category",
+            )
+            .const_label("repository", &settings.repository),
+            &["category"],
+        )?;
+        for category in registry::ERROR_CATEGORIES {
+            graph_upstream_scrape_errors.with_label_values(&[category]);
+        }
+        if let Some(prometheus_registry) = &prometheus_registry {
+            prometheus_registry.register(Box::new(graph_upstream_scrape_errors.clone()))?;
+        }
+
+        let registry = registry::Registry::try_from_str(&settings.registry)
+            .context(format!("Parsing {} as Registry", &settings.registry))?;
+
+        let tag_filter = match &settings.tag_regex {
+            Some(tag_regex) => Some(
+                regex::Regex::new(tag_regex)
+                    .context(format!("Parsing {} as tag regex", tag_regex))?,
+            ),
+            None => None,
+        };
+
+        let (mut username, mut password) = (settings.username.clone(), settings.password.clone());
+        if let Some(credentials_path) = &settings.credentials_path {
+            let credentials = registry::read_credentials(
+                Some(credentials_path),
+                &registry.host_port_string(),
+            )
+            .unwrap_or_else(|err| {
+                warn!(
+                    "Error reading registry credentials from {:?}. Access to {:?} will be unauthenticated: {} ",
+                    credentials_path, &registry.host_port_string() ,err
+                );
+                (None, None)
+            });
+            username = credentials.0;
+            password = credentials.1;
+        }
+
+        let referrers = match &settings.referrers_artifact_type {
+            Some(artifact_type) => Some(registry::ReferrersClient::try_new(
+                &registry,
+                &settings.repository,
+                username.as_deref(),
+                password.as_deref(),
+                artifact_type,
+                &settings.referrers_annotation_prefix,
+            )?),
+            None => None,
+        };
+
+        let digests = DigestVerifier::try_new(settings, prometheus_registry)?;
+
+        Ok(Self {
+            registry,
+            repository: settings.repository.clone(),
+            manifestref_key: settings.manifestref_key.clone(),
+            fetch_concurrency: settings.fetch_concurrency,
+            username,
+            password,
+            cache: cache.unwrap_or_else(registry::cache::new),
+            tag_filter,
+            referrers,
+            digests,
+            record_provenance: settings.record_provenance,
+            metadata_limits: settings.metadata_limits(),
+            tag_listing: Default::default(),
+            graph_upstream_scrape_errors,
+        })
+    }
+}
+
+#[async_trait]
+impl ReleaseSource for RegistryV2Source {
+    fn location(&self) -> String {
+        format!("{}/{}", self.registry.host_port_string(), &self.repository)
+    }
+
+    fn metrics_label(&self) -> String {
+        self.repository.clone()
+    }
+
+    async fn fetch_releases(&self, timings: &Mutex<PhaseTimings>) -> Fallible<Vec<Release>> {
+        let (sender, receiver) = mpsc::channel(PIPELINE_CAPACITY);
+        let ((), releases) = futures::try_join!(self.send_releases(timings, sender), async {
+            Ok::<_, Error>(receiver.collect::<Vec<_>>().await)
+        })?;
+        Ok(releases)
+    }
+
+    async fn send_releases(
+        &self,
+        timings: &Mutex<PhaseTimings>,
+        releases: mpsc::Sender<Release>,
+    ) -> Fallible<()> {
+        if let Some(digests) = &self.digests {
+            digests.reload()?;
+        }
+
+        let mut scraped_tags = registry::send_releases(
+            &self.registry,
+            &self.repository,
+            self.username.as_deref(),
+            self.password.as_deref(),
+            self.cache.clone(),
+            &self.manifestref_key,
+            self.fetch_concurrency,
+            self.tag_filter.as_ref(),
+            self.referrers.as_ref(),
+            self.digests.as_ref(),
+            self.record_provenance,
+            self.metadata_limits,
+            timings,
+            releases,
+        )
+        .await
+        .map_err(|e| {
+            self.graph_upstream_scrape_errors
+                .with_label_values(&[registry::error_category(&e)])
+                .inc();
+            e
+        })?;
+
+        tags::mark_duplicates(&mut scraped_tags);
+        *self.tag_listing.lock().expect("tag listing lock poisoned") = Some(scraped_tags);
+
+        Ok(())
+    }
+
+    fn take_tag_listing(&self) -> Option<Vec<tags::ScrapedTag>> {
+        self.tag_listing
+            .lock()
+            .expect("tag listing lock poisoned")
+            .take()
+    }
+}
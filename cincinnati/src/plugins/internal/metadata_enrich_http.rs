@@ -0,0 +1,437 @@
+//! This plugin enriches release metadata with key/value pairs returned by an
+//! external HTTP service, e.g. ticket links or approval status kept in
+//! internal systems.
+//!
+//! The `(version, arch)` pairs of the releases are POSTed in batches as
+//! `{"releases": [{"version": "4.14.1", "arch": "amd64"}]}`, and the service
+//! answers with `{"releases": [{"version": "4.14.1", "arch": "amd64",
+//! "metadata": {"key": "value"}}]}`. Returned keys never override existing
+//! metadata.
+//!
+//! Responses are cached per pair for the configured TTL. When the service
+//! fails, releases are served with their cached metadata, if any.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use self::cincinnati::plugins::internal::arch_filter::{
+    DEFAULT_ARCH_KEY, DEFAULT_DEFAULT_ARCH, DEFAULT_KEY_FILTER,
+};
+use prometheus::Counter;
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Default timeout of each request to the service, in seconds.
+pub static DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+/// Default number of releases per request to the service.
+pub static DEFAULT_BATCH_SIZE: usize = 100;
+
+/// Default lifetime of cached metadata, in seconds.
+pub static DEFAULT_CACHE_TTL_SECS: u64 = 300;
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+struct MetadataEnrichHttpSettings {
+    /// URL of the enrichment service.
+    url: String,
+
+    #[default(DEFAULT_TIMEOUT_SECS)]
+    timeout_secs: u64,
+
+    #[default(DEFAULT_BATCH_SIZE)]
+    batch_size: usize,
+
+    #[default(DEFAULT_CACHE_TTL_SECS)]
+    cache_ttl_secs: u64,
+
+    /// Metadata key holding the architecture of a release.
+    #[default(format!("{}.{}", DEFAULT_KEY_FILTER, DEFAULT_ARCH_KEY))]
+    arch_key: String,
+
+    /// Architecture of releases without architecture metadata.
+    #[default(DEFAULT_DEFAULT_ARCH.to_string())]
+    default_arch: String,
+}
+
+/// Version and architecture of a release.
+type ReleaseKey = (String, String);
+
+/// Release sent to the service.
+#[derive(Debug, Serialize)]
+struct EnrichmentRequestRelease<'a> {
+    version: &'a str,
+    arch: &'a str,
+}
+
+/// Batch of releases sent to the service.
+#[derive(Debug, Serialize)]
+struct EnrichmentRequest<'a> {
+    releases: Vec<EnrichmentRequestRelease<'a>>,
+}
+
+/// Release metadata returned by the service.
+#[derive(Debug, Deserialize)]
+struct EnrichmentResponseRelease {
+    version: String,
+    arch: String,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+/// Response of the service to a batch.
+#[derive(Debug, Deserialize)]
+struct EnrichmentResponse {
+    #[serde(default)]
+    releases: Vec<EnrichmentResponseRelease>,
+}
+
+/// Metadata enricher calling an external HTTP service.
+#[derive(CustomDebug)]
+pub struct MetadataEnrichHttpPlugin {
+    url: String,
+    batch_size: usize,
+    cache_ttl: Duration,
+    arch_key: String,
+    default_arch: String,
+
+    client: reqwest::Client,
+
+    /// Metadata by release, with the time it was fetched.
+    #[debug(skip)]
+    cache: Mutex<HashMap<ReleaseKey, (Instant, HashMap<String, String>)>>,
+
+    #[debug(skip)]
+    requests_total: Counter,
+
+    #[debug(skip)]
+    errors_total: Counter,
+}
+
+impl PluginSettings for MetadataEnrichHttpSettings {
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = MetadataEnrichHttpPlugin::try_new(self.clone(), registry)?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.url.is_empty(), "empty url");
+        reqwest::Url::parse(&self.url).context(format!("invalid url '{}'", self.url))?;
+        ensure!(self.timeout_secs > 0, "timeout_secs must be greater than 0");
+        ensure!(self.batch_size > 0, "batch_size must be greater than 0");
+        ensure!(!self.arch_key.is_empty(), "empty arch_key");
+
+        Ok(())
+    }
+}
+
+impl MetadataEnrichHttpPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "metadata-enrich-http";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: MetadataEnrichHttpSettings = cfg.try_into()?;
+        settings.validate()?;
+
+        Ok(Box::new(settings))
+    }
+
+    fn try_new(
+        settings: MetadataEnrichHttpSettings,
+        registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let requests_total = Counter::new(
+            "metadata_enrichment_requests_total",
+            "Total number of requests to the metadata enrichment service",
+        )?;
+        let errors_total = Counter::new(
+            "metadata_enrichment_errors_total",
+            "Total number of failed requests to the metadata enrichment service",
+        )?;
+        if let Some(registry) = registry {
+            registry.register(Box::new(requests_total.clone()))?;
+            registry.register(Box::new(errors_total.clone()))?;
+        }
+
+        let client = commons::http::HttpClientBuilder::new()
+            .timeout(Duration::from_secs(settings.timeout_secs))
+            .build()
+            .context("Building metadata enrichment client")?;
+
+        Ok(Self {
+            url: settings.url,
+            batch_size: settings.batch_size,
+            cache_ttl: Duration::from_secs(settings.cache_ttl_secs),
+            arch_key: settings.arch_key,
+            default_arch: settings.default_arch,
+            client,
+            cache: Mutex::new(HashMap::new()),
+            requests_total,
+            errors_total,
+        })
+    }
+
+    /// Fetch the metadata of one batch of releases.
+    async fn fetch_batch(&self, batch: &[ReleaseKey]) -> Fallible<EnrichmentResponse> {
+        let request = EnrichmentRequest {
+            releases: batch
+                .iter()
+                .map(|(version, arch)| EnrichmentRequestRelease { version, arch })
+                .collect(),
+        };
+
+        self.requests_total.inc();
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context(format!("requesting metadata from {}", self.url))?
+            .json()
+            .await
+            .context(format!("parsing metadata from {}", self.url))?;
+        Ok(response)
+    }
+
+    /// Return the metadata of `releases`, from the cache or the service.
+    async fn metadata(
+        &self,
+        releases: Vec<ReleaseKey>,
+    ) -> HashMap<ReleaseKey, HashMap<String, String>> {
+        let mut metadata = HashMap::with_capacity(releases.len());
+        let mut missing = vec![];
+        {
+            let cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+            for release in releases {
+                match cache.get(&release) {
+                    Some((fetched, entry)) if fetched.elapsed() < self.cache_ttl => {
+                        metadata.insert(release, entry.clone());
+                    }
+                    _ => missing.push(release),
+                }
+            }
+        }
+
+        for batch in missing.chunks(self.batch_size) {
+            match self.fetch_batch(batch).await {
+                Ok(response) => {
+                    let now = Instant::now();
+                    let mut returned: HashMap<ReleaseKey, HashMap<String, String>> = response
+                        .releases
+                        .into_iter()
+                        .map(|release| ((release.version, release.arch), release.metadata))
+                        .collect();
+                    let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+                    for release in batch {
+                        // Releases unknown to the service have no metadata.
+                        let entry = returned.remove(release).unwrap_or_default();
+                        cache.insert(release.clone(), (now, entry.clone()));
+                        metadata.insert(release.clone(), entry);
+                    }
+                }
+                Err(e) => {
+                    self.errors_total.inc();
+                    warn!(
+                        "failed to enrich metadata of {} releases: {:#}",
+                        batch.len(),
+                        e
+                    );
+                    let cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+                    for release in batch {
+                        if let Some((_, entry)) = cache.get(release) {
+                            metadata.insert(release.clone(), entry.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        metadata
+    }
+
+    /// Return the version and architecture of `release`.
+    fn release_key(&self, release: &cincinnati::Release) -> Option<ReleaseKey> {
+        match release {
+            cincinnati::Release::Concrete(release) => Some((
+                release.version.clone(),
+                release
+                    .metadata
+                    .get(&self.arch_key)
+                    .cloned()
+                    .unwrap_or_else(|| self.default_arch.clone()),
+            )),
+            cincinnati::Release::Abstract(_) => None,
+        }
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for MetadataEnrichHttpPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let (mut graph, parameters) = (io.graph, io.parameters);
+
+        let mut releases = vec![];
+        graph.iter_releases_mut(|release| {
+            releases.extend(self.release_key(release));
+            Ok(())
+        })?;
+        releases.sort();
+        releases.dedup();
+
+        let metadata = self.metadata(releases).await;
+
+        graph.iter_releases_mut(|release| {
+            let entry = self.release_key(release).and_then(|key| metadata.get(&key));
+            if let (Some(entry), Some(release_metadata)) = (entry, release.get_metadata_mut()) {
+                for (key, value) in entry {
+                    if !release_metadata.contains_key(key) {
+                        release_metadata.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            Ok(())
+        })?;
+
+        Ok(InternalIO { graph, parameters })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use cincinnati::MapImpl;
+    use commons::testing::init_runtime;
+
+    fn plugin(url: &str) -> Fallible<MetadataEnrichHttpPlugin> {
+        MetadataEnrichHttpPlugin::try_new(
+            MetadataEnrichHttpSettings {
+                url: url.to_string(),
+                ..Default::default()
+            },
+            None,
+        )
+    }
+
+    fn graph() -> cincinnati::Graph {
+        let arch_key = format!("{}.{}", DEFAULT_KEY_FILTER, DEFAULT_ARCH_KEY);
+        generate_custom_graph(
+            "image",
+            vec![
+                (
+                    0,
+                    [(arch_key.clone(), "amd64".to_string())]
+                        .iter()
+                        .cloned()
+                        .collect::<MapImpl<String, String>>(),
+                ),
+                (
+                    1,
+                    [
+                        (arch_key, "arm64".to_string()),
+                        ("approved".to_string(), "yes".to_string()),
+                    ]
+                    .iter()
+                    .cloned()
+                    .collect(),
+                ),
+            ],
+            Some(vec![(0, 1)]),
+        )
+    }
+
+    fn release_metadata(graph: &cincinnati::Graph, version: &str) -> MapImpl<String, String> {
+        let release_id = graph.find_by_version(version).unwrap();
+        match graph.find_by_releaseid(&release_id).unwrap() {
+            cincinnati::Release::Concrete(release) => release.metadata.clone(),
+            _ => panic!("abstract release {}", version),
+        }
+    }
+
+    #[test]
+    fn enrich_metadata() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let plugin = plugin(&format!("{}/enrich", mockito::server_url()))?;
+
+        let mock = mockito::mock("POST", "/enrich")
+            .match_body(mockito::Matcher::Json(serde_json::json!({"releases": [
+                {"version": "0.0.0", "arch": "amd64"},
+                {"version": "1.0.0", "arch": "arm64"},
+            ]})))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"releases": [{"version": "1.0.0", "arch": "arm64", "metadata": {
+                    "ticket": "https://issues.example.com/OCPBUGS-1",
+                    "approved": "no"}}]}"#,
+            )
+            .expect(1)
+            .create();
+
+        for _ in 0..2 {
+            let io = runtime.block_on(plugin.run_internal(InternalIO {
+                graph: graph(),
+                parameters: Default::default(),
+            }))?;
+
+            assert!(!release_metadata(&io.graph, "0.0.0").contains_key("ticket"));
+            let metadata = release_metadata(&io.graph, "1.0.0");
+            assert_eq!(metadata["ticket"], "https://issues.example.com/OCPBUGS-1");
+            // Existing metadata is kept.
+            assert_eq!(metadata["approved"], "yes");
+        }
+
+        // The second run is served from the cache.
+        mock.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn serve_graph_when_service_fails() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let plugin = plugin("http://not.reachable.test/enrich")?;
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: graph(),
+            parameters: Default::default(),
+        }))?;
+        assert_eq!(io.graph, graph());
+        assert_eq!(plugin.errors_total.get() as u64, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_settings() -> Fallible<()> {
+        let cfg = |extra: &str| -> Fallible<toml::Value> {
+            Ok(toml::from_str(&format!(
+                "name = {:?}\n{}",
+                MetadataEnrichHttpPlugin::PLUGIN_NAME,
+                extra
+            ))?)
+        };
+
+        assert!(MetadataEnrichHttpPlugin::deserialize_config(cfg("")?).is_err());
+        assert!(
+            MetadataEnrichHttpPlugin::deserialize_config(cfg(r#"url = "not a url""#)?).is_err()
+        );
+        assert!(MetadataEnrichHttpPlugin::deserialize_config(cfg(
+            "url = \"https://metadata.example.com/enrich\"\nbatch_size = 0"
+        )?)
+        .is_err());
+        MetadataEnrichHttpPlugin::deserialize_config(cfg(
+            r#"url = "https://metadata.example.com/enrich""#,
+        )?)?;
+
+        Ok(())
+    }
+}
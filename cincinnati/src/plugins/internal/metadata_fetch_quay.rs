@@ -3,6 +3,9 @@
 //! The fetch process is all or nothing, i.e. it fails in these cases:
 //! * a Release doesn't contain the manifestref in its metadata
 //! * the dynamic metadata can't be fetched for a single manifestref
+//!
+//! The API token is read again from `api_credentials_path` whenever quay.io
+//! rejects it, so that it can be rotated without restarting.
 
 use crate as cincinnati;
 
@@ -54,6 +57,13 @@ impl PluginSettings for QuayMetadataSettings {
         )?;
         Ok(new_plugin!(InternalPluginWrapper(plugin)))
     }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.repository.is_empty(), "empty repository");
+        ensure!(!self.label_filter.is_empty(), "empty label_filter");
+
+        Ok(())
+    }
 }
 
 impl QuayMetadataFetchPlugin {
@@ -63,9 +73,7 @@ impl QuayMetadataFetchPlugin {
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
         let settings: QuayMetadataSettings = cfg.try_into()?;
-
-        ensure!(!settings.repository.is_empty(), "empty repository");
-        ensure!(!settings.label_filter.is_empty(), "empty label_filter");
+        settings.validate()?;
 
         Ok(Box::new(settings))
     }
@@ -77,15 +85,12 @@ impl QuayMetadataFetchPlugin {
         api_token_path: Option<PathBuf>,
         api_base: String,
     ) -> Fallible<Self> {
-        let api_token = api_token_path
-            .map(quay::read_credentials)
-            .transpose()
-            .context("could not read quay API credentials")?;
-
         let client: quay::v1::Client = quay::v1::Client::builder()
-            .access_token(api_token)
+            .access_token_path(api_token_path)
             .api_base(Some(api_base))
-            .build()?;
+            .http_client(Some(commons::http::HttpClientBuilder::new().build()?))
+            .build()
+            .context("could not read quay API credentials")?;
 
         Ok(Self {
             client,
@@ -172,6 +177,72 @@ impl InternalPlugin for QuayMetadataFetchPlugin {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use cincinnati::MapImpl;
+    use commons::testing::init_runtime;
+    use std::io::Write;
+
+    #[test]
+    fn metadata_fetch_reloads_rotated_token() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let labels_path = "/repository/openshift/manifest/sha256:0000/labels";
+        let _rejected = mockito::mock("GET", labels_path)
+            .match_query(mockito::Matcher::Any)
+            .match_header("authorization", "Bearer old")
+            .with_status(401)
+            .create();
+        let _accepted = mockito::mock("GET", labels_path)
+            .match_query(mockito::Matcher::Any)
+            .match_header("authorization", "Bearer new")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"labels": [{"key": "io.openshift.upgrades.graph.release.remove", "value": "true", "media_type": "text/plain", "id": "0", "source_type": "api"}]}"#,
+            )
+            .create();
+
+        let mut token_file = tempfile::NamedTempFile::new()?;
+        writeln!(token_file, "old")?;
+        let plugin = QuayMetadataFetchPlugin::try_new(
+            DEFAULT_QUAY_REPOSITORY.to_string(),
+            DEFAULT_QUAY_LABEL_FILTER.to_string(),
+            DEFAULT_QUAY_MANIFESTREF_KEY.to_string(),
+            Some(token_file.path().to_path_buf()),
+            format!("{}/", mockito::server_url()),
+        )?;
+
+        // Rotate the token after the plugin has read it.
+        std::fs::write(token_file.path(), "new\n")?;
+
+        let metadata: MapImpl<String, String> = [(
+            DEFAULT_QUAY_MANIFESTREF_KEY.to_string(),
+            "sha256:0000".to_string(),
+        )]
+        .iter()
+        .cloned()
+        .collect();
+        let mut graph = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: generate_custom_graph("image", vec![(0, metadata)], None),
+                parameters: Default::default(),
+            }))?
+            .graph;
+
+        let release_id = graph.find_by_version("0.0.0").expect("missing release");
+        let metadata = graph.get_metadata_as_ref_mut(&release_id)?;
+        assert_eq!(
+            metadata.get("io.openshift.upgrades.graph.release.remove"),
+            Some(&"true".to_string())
+        );
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "test-net")]
 mod tests_net {
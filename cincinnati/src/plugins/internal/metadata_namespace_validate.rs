@@ -0,0 +1,271 @@
+//! This plugin enforces the namespacing of release metadata keys.
+//!
+//! Keys outside of the configured reverse-DNS `namespaces`, and not listed in
+//! `allowed_keys`, are either stripped from the releases or cause the plugin
+//! to fail, depending on the configured `action`.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use self::cincinnati::MetadataNamespaces;
+use prometheus::IntCounter;
+
+/// Default namespace of metadata keys.
+pub static DEFAULT_NAMESPACE: &str = "io.openshift.upgrades.graph";
+
+/// Default keys allowed outside of the namespaces.
+pub static DEFAULT_ALLOWED_KEYS: &[&str] = &["url"];
+
+/// Maximum number of violating keys listed in errors.
+static MAX_REPORTED_VIOLATIONS: usize = 10;
+
+/// Handling of metadata keys outside of the namespaces.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ViolationAction {
+    /// Remove the keys from the release metadata.
+    #[default]
+    Strip,
+    /// Fail the plugin.
+    Reject,
+}
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct MetadataNamespaceValidateSettings {
+    /// Reverse-DNS namespaces of the allowed keys.
+    #[default(vec![DEFAULT_NAMESPACE.to_string()])]
+    pub namespaces: Vec<String>,
+
+    /// Keys allowed outside of the namespaces.
+    #[default(DEFAULT_ALLOWED_KEYS.iter().map(|key| key.to_string()).collect())]
+    pub allowed_keys: Vec<String>,
+
+    /// Handling of keys outside of the namespaces.
+    pub action: ViolationAction,
+}
+
+impl MetadataNamespaceValidateSettings {
+    fn metadata_namespaces(&self) -> MetadataNamespaces {
+        MetadataNamespaces {
+            namespaces: self.namespaces.clone(),
+            keys: self.allowed_keys.clone(),
+        }
+    }
+}
+
+impl PluginSettings for MetadataNamespaceValidateSettings {
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = MetadataNamespaceValidatePlugin::try_new(self.clone(), registry)?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+
+    fn validate(&self) -> Fallible<()> {
+        self.metadata_namespaces().validate()?;
+
+        Ok(())
+    }
+}
+
+/// Validator of the namespaces of release metadata keys.
+#[derive(CustomDebug)]
+pub struct MetadataNamespaceValidatePlugin {
+    namespaces: MetadataNamespaces,
+    action: ViolationAction,
+
+    #[debug(skip)]
+    violations_total: IntCounter,
+}
+
+impl MetadataNamespaceValidatePlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "metadata-namespace-validate";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: MetadataNamespaceValidateSettings = cfg.try_into()?;
+        settings.validate()?;
+
+        Ok(Box::new(settings))
+    }
+
+    fn try_new(
+        settings: MetadataNamespaceValidateSettings,
+        registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let violations_total = IntCounter::new(
+            "metadata_namespace_violations_total",
+            "Total number of release metadata keys found outside of the allowed namespaces",
+        )?;
+        if let Some(registry) = registry {
+            registry.register(Box::new(violations_total.clone()))?;
+        }
+
+        Ok(Self {
+            namespaces: settings.metadata_namespaces(),
+            action: settings.action,
+            violations_total,
+        })
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for MetadataNamespaceValidatePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        let violations = match self.action {
+            ViolationAction::Strip => graph.strip_metadata_namespace_violations(&self.namespaces),
+            ViolationAction::Reject => graph.metadata_namespace_violations(&self.namespaces),
+        };
+        if violations.is_empty() {
+            return Ok(InternalIO {
+                graph,
+                parameters: io.parameters,
+            });
+        }
+
+        self.violations_total.inc_by(violations.len() as u64);
+        let listed: Vec<String> = violations
+            .iter()
+            .take(MAX_REPORTED_VIOLATIONS)
+            .map(|(version, key)| format!("{} ({})", key, version))
+            .collect();
+        let summary = format!(
+            "{} metadata keys outside of the allowed namespaces: {}{}",
+            violations.len(),
+            listed.join(", "),
+            if violations.len() > MAX_REPORTED_VIOLATIONS {
+                ", ..."
+            } else {
+                ""
+            }
+        );
+
+        match self.action {
+            ViolationAction::Strip => {
+                debug!("stripped {}", summary);
+                Ok(InternalIO {
+                    graph,
+                    parameters: io.parameters,
+                })
+            }
+            ViolationAction::Reject => bail!("rejected {}", summary),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use commons::testing::init_runtime;
+
+    fn cfg(extra: &str) -> Fallible<toml::Value> {
+        Ok(toml::from_str(&format!(
+            "name = {:?}\n{}",
+            MetadataNamespaceValidatePlugin::PLUGIN_NAME,
+            extra
+        ))?)
+    }
+
+    fn graph() -> cincinnati::Graph {
+        let metadata = |keys: &[&str]| -> cincinnati::MapImpl<String, String> {
+            keys.iter()
+                .map(|key| (key.to_string(), "value".to_string()))
+                .collect()
+        };
+        generate_custom_graph(
+            "image",
+            vec![
+                (
+                    0,
+                    metadata(&["io.openshift.upgrades.graph.release.channels"]),
+                ),
+                (1, metadata(&["url", "com.example.ticket"])),
+            ],
+            None,
+        )
+    }
+
+    #[test]
+    fn validate_settings() -> Fallible<()> {
+        MetadataNamespaceValidatePlugin::deserialize_config(cfg("")?)?;
+        MetadataNamespaceValidatePlugin::deserialize_config(cfg(
+            "namespaces = [\"com.example\"]\naction = \"reject\"",
+        )?)?;
+
+        assert!(MetadataNamespaceValidatePlugin::deserialize_config(cfg(
+            "namespaces = [\"example\"]"
+        )?)
+        .is_err());
+        assert!(MetadataNamespaceValidatePlugin::deserialize_config(cfg(
+            "namespaces = []\nallowed_keys = []"
+        )?)
+        .is_err());
+        assert!(
+            MetadataNamespaceValidatePlugin::deserialize_config(cfg("action = \"drop\"")?).is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn strip_violations() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let registry = prometheus::Registry::new();
+        let plugin = MetadataNamespaceValidatePlugin::try_new(Default::default(), Some(&registry))?;
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: graph(),
+            parameters: Default::default(),
+        }))?;
+
+        let release = io
+            .graph
+            .find_by_version("1.0.0")
+            .map(|id| io.graph.find_by_releaseid(&id).map(Clone::clone))
+            .transpose()?
+            .unwrap();
+        match release {
+            cincinnati::Release::Concrete(release) => {
+                assert_eq!(release.metadata.keys().collect::<Vec<_>>(), vec!["url"])
+            }
+            _ => bail!("unexpected abstract release"),
+        }
+        assert_eq!(plugin.violations_total.get(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_violations() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let plugin = MetadataNamespaceValidatePlugin::try_new(
+            MetadataNamespaceValidateSettings {
+                action: ViolationAction::Reject,
+                ..Default::default()
+            },
+            None,
+        )?;
+
+        let err = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: graph(),
+                parameters: Default::default(),
+            }))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "rejected 1 metadata keys outside of the allowed namespaces: com.example.ticket (1.0.0)"
+        );
+        assert_eq!(plugin.violations_total.get(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+//! This plugin removes release metadata keys which are not meant to be served
+//! publicly, e.g. the provenance recorded by the `release-scrape-dockerv2`
+//! plugin.
+//!
+//! Keys within the configured reverse-DNS `namespaces`, or listed in `keys`,
+//! are removed from all releases.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::release_scrape_dockerv2::PROVENANCE_NAMESPACE;
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use self::cincinnati::MetadataNamespaces;
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct MetadataRedactSettings {
+    /// Reverse-DNS namespaces of the removed keys.
+    #[default(vec![PROVENANCE_NAMESPACE.to_string()])]
+    pub namespaces: Vec<String>,
+
+    /// Keys removed outside of the namespaces.
+    pub keys: Vec<String>,
+}
+
+impl MetadataRedactSettings {
+    fn metadata_namespaces(&self) -> MetadataNamespaces {
+        MetadataNamespaces {
+            namespaces: self.namespaces.clone(),
+            keys: self.keys.clone(),
+        }
+    }
+}
+
+impl PluginSettings for MetadataRedactSettings {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = MetadataRedactPlugin {
+            redacted: self.metadata_namespaces(),
+        };
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+
+    fn validate(&self) -> Fallible<()> {
+        self.metadata_namespaces().validate()?;
+
+        Ok(())
+    }
+}
+
+/// Remover of release metadata keys.
+#[derive(Debug)]
+pub struct MetadataRedactPlugin {
+    redacted: MetadataNamespaces,
+}
+
+impl MetadataRedactPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "metadata-redact";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: MetadataRedactSettings = cfg.try_into()?;
+        settings.validate()?;
+
+        Ok(Box::new(settings))
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for MetadataRedactPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        let mut redacted = 0;
+        graph.iter_releases_mut(|release| {
+            if let Some(metadata) = release.get_metadata_mut() {
+                let count = metadata.len();
+                metadata.retain(|key, _| !self.redacted.allows(key));
+                redacted += count - metadata.len();
+            }
+            Ok(())
+        })?;
+        trace!("redacted {} metadata keys", redacted);
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use commons::testing::init_runtime;
+
+    fn cfg(extra: &str) -> Fallible<toml::Value> {
+        Ok(toml::from_str(&format!(
+            "name = {:?}\n{}",
+            MetadataRedactPlugin::PLUGIN_NAME,
+            extra
+        ))?)
+    }
+
+    #[test]
+    fn validate_settings() -> Fallible<()> {
+        MetadataRedactPlugin::deserialize_config(cfg("")?)?;
+        MetadataRedactPlugin::deserialize_config(cfg(
+            "namespaces = [\"com.example.internal\"]\nkeys = [\"ticket\"]",
+        )?)?;
+
+        assert!(
+            MetadataRedactPlugin::deserialize_config(cfg("namespaces = [\"example\"]")?).is_err()
+        );
+        assert!(MetadataRedactPlugin::deserialize_config(cfg("namespaces = []")?).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn redact_provenance() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let plugin = MetadataRedactPlugin {
+            redacted: MetadataRedactSettings {
+                keys: vec!["ticket".to_string()],
+                ..Default::default()
+            }
+            .metadata_namespaces(),
+        };
+        let metadata = |keys: &[&str]| -> cincinnati::MapImpl<String, String> {
+            keys.iter()
+                .map(|key| (key.to_string(), "value".to_string()))
+                .collect()
+        };
+        let graph = generate_custom_graph(
+            "image",
+            vec![
+                (
+                    0,
+                    metadata(&[
+                        "io.openshift.upgrades.graph.provenance.digest",
+                        "io.openshift.upgrades.graph.release.channels",
+                    ]),
+                ),
+                (1, metadata(&["url", "ticket"])),
+            ],
+            None,
+        );
+
+        let graph = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph,
+                parameters: Default::default(),
+            }))?
+            .graph;
+
+        let keys = |version: &str| -> Fallible<Vec<String>> {
+            let id = graph
+                .find_by_version(version)
+                .context(format!("missing release {}", version))?;
+            match graph.find_by_releaseid(&id)? {
+                cincinnati::Release::Concrete(release) => {
+                    Ok(release.metadata.keys().cloned().collect())
+                }
+                _ => bail!("unexpected abstract release"),
+            }
+        };
+        assert_eq!(
+            keys("0.0.0")?,
+            vec!["io.openshift.upgrades.graph.release.channels"]
+        );
+        assert_eq!(keys("1.0.0")?, vec!["url"]);
+
+        Ok(())
+    }
+}
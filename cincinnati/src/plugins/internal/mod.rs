@@ -1,16 +1,26 @@
 //! This module implements the internal plugins
 
+pub mod accepted_risks;
 pub mod arch_filter;
+pub mod channel_derive;
 pub mod channel_filter;
 pub mod cincinnati_graph_fetch;
 pub mod edge_add_remove;
+pub mod edge_min_origin;
+pub mod edge_schedule;
+pub mod metadata_enrich_http;
 pub mod metadata_fetch_quay;
+pub mod metadata_namespace_validate;
+pub mod metadata_redact;
 pub mod node_remove;
+pub mod risk_message_template;
+pub mod upgrade_impact;
 pub mod versioned_graph;
 
 mod graph_builder;
 
 pub use graph_builder::{
-    dkrv2_openshift_secondary_metadata_scraper, github_openshift_secondary_metadata_scraper,
-    openshift_secondary_metadata_parser, release_scrape_dockerv2,
+    build_phases, dkrv2_openshift_secondary_metadata_scraper,
+    github_openshift_secondary_metadata_scraper, openshift_secondary_metadata_parser,
+    release_scrape, release_scrape_dockerv2, release_source,
 };
@@ -19,6 +19,12 @@ impl PluginSettings for NodeRemovePlugin {
     fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
         Ok(new_plugin!(InternalPluginWrapper(self.clone())))
     }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.key_prefix.is_empty(), "empty prefix");
+
+        Ok(())
+    }
 }
 
 impl NodeRemovePlugin {
@@ -28,8 +34,7 @@ impl NodeRemovePlugin {
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
         let plugin: Self = cfg.try_into()?;
-
-        ensure!(!plugin.key_prefix.is_empty(), "empty prefix");
+        plugin.validate()?;
 
         Ok(Box::new(plugin))
     }
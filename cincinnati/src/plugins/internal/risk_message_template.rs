@@ -0,0 +1,294 @@
+//! This plugin substitutes placeholders in the messages of conditional-edge
+//! risks, so that one message can be written for many edges.
+//!
+//! Placeholders have the form `{{name}}`, where name is one of:
+//!  * `version` or `to`: the version of the target release of the edge,
+//!  * `from`: the version of the source release of the edge,
+//!  * `metadata.<key>`: the metadata value at `<key>` of the target release,
+//!  * a client parameter listed in the `parameters` setting, e.g. `channel`.
+//!
+//! Unknown placeholders are left as they are. Conditional edges whose
+//! messages render differently for some of their edges are split.
+
+use crate as cincinnati;
+
+use self::cincinnati::conditional_edges::{
+    ConditionalEdge, ConditionalUpdateEdge, ConditionalUpdateRisk,
+};
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+
+/// Client parameters substituted by default.
+pub static DEFAULT_PARAMETERS: &[&str] = &["channel", "arch"];
+
+/// Prefix of the placeholders of release metadata.
+static METADATA_PLACEHOLDER_PREFIX: &str = "metadata.";
+
+lazy_static! {
+    static ref PLACEHOLDER_RE: regex::Regex =
+        regex::Regex::new(r"\{\{\s*([A-Za-z0-9_.\-/]+)\s*\}\}").expect("could not create regex");
+}
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct RiskMessageTemplatePlugin {
+    /// Client parameters which can be substituted in messages.
+    #[default(DEFAULT_PARAMETERS.iter().map(|p| p.to_string()).collect())]
+    pub parameters: Vec<String>,
+}
+
+impl PluginSettings for RiskMessageTemplatePlugin {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+
+    fn validate(&self) -> Fallible<()> {
+        for parameter in &self.parameters {
+            ensure!(
+                !parameter.is_empty()
+                    && parameter
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "_.-/".contains(c)),
+                "invalid parameter name '{}'",
+                parameter
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl RiskMessageTemplatePlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "risk-message-template";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = cfg.try_into()?;
+        plugin.validate()?;
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Substitute the placeholders of `message` for `edge`.
+    fn render(
+        &self,
+        message: &str,
+        edge: &ConditionalUpdateEdge,
+        graph: &cincinnati::Graph,
+        parameters: &HashMap<String, String>,
+    ) -> String {
+        PLACEHOLDER_RE
+            .replace_all(message, |captures: &regex::Captures| {
+                let name = &captures[1];
+                let value = match name {
+                    "version" | "to" => Some(edge.to.clone()),
+                    "from" => Some(edge.from.clone()),
+                    _ => match name.strip_prefix(METADATA_PLACEHOLDER_PREFIX) {
+                        Some(key) => release_metadata(graph, &edge.to, key),
+                        None if self.parameters.iter().any(|p| p == name) => {
+                            parameters.get(name).cloned()
+                        }
+                        None => None,
+                    },
+                };
+                value.unwrap_or_else(|| captures[0].to_string())
+            })
+            .into_owned()
+    }
+
+    /// Render the risks of `conditional_edge`, splitting it by rendered risks.
+    fn render_conditional_edge(
+        &self,
+        conditional_edge: ConditionalEdge,
+        graph: &cincinnati::Graph,
+        parameters: &HashMap<String, String>,
+    ) -> Vec<ConditionalEdge> {
+        let templated = conditional_edge
+            .risks
+            .iter()
+            .any(|risk| PLACEHOLDER_RE.is_match(&risk.message));
+        if !templated || conditional_edge.edges.is_empty() {
+            return vec![conditional_edge];
+        }
+
+        let mut groups: Vec<(Vec<ConditionalUpdateRisk>, Vec<ConditionalUpdateEdge>)> = vec![];
+        for edge in &conditional_edge.edges {
+            let risks: Vec<ConditionalUpdateRisk> = conditional_edge
+                .risks
+                .iter()
+                .map(|risk| ConditionalUpdateRisk {
+                    message: self.render(&risk.message, edge, graph, parameters),
+                    ..risk.clone()
+                })
+                .collect();
+
+            match groups
+                .iter_mut()
+                .find(|(group_risks, _)| *group_risks == risks)
+            {
+                Some((_, edges)) => edges.push(edge.clone()),
+                None => groups.push((risks, vec![edge.clone()])),
+            }
+        }
+
+        groups
+            .into_iter()
+            .map(|(risks, edges)| ConditionalEdge {
+                edge_regex: conditional_edge.edge_regex.clone(),
+                edges,
+                risks,
+            })
+            .collect()
+    }
+}
+
+/// Return the metadata value at `key` of the release with `version`.
+fn release_metadata(graph: &cincinnati::Graph, version: &str, key: &str) -> Option<String> {
+    let release_id = graph.find_by_version(version)?;
+    match graph.find_by_releaseid(&release_id) {
+        Ok(cincinnati::Release::Concrete(release)) => release.metadata.get(key).cloned(),
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for RiskMessageTemplatePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        if let Some(conditional_edges) = graph.conditional_edges.take() {
+            let rendered = conditional_edges
+                .into_iter()
+                .flat_map(|conditional_edge| {
+                    self.render_conditional_edge(conditional_edge, &graph, &io.parameters)
+                })
+                .collect();
+            graph.conditional_edges = Some(rendered);
+        }
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::conditional_edges::{ClusterCondition, PromQLClusterCondition};
+    use cincinnati::testing::generate_graph;
+    use commons::testing::init_runtime;
+
+    fn risk(message: &str) -> ConditionalUpdateRisk {
+        ConditionalUpdateRisk {
+            url: "https://bug.example.com/show_bug.cgi?id=example".to_string(),
+            name: "BrokenUpdates".to_string(),
+            message: message.to_string(),
+            matching_rules: vec![ClusterCondition {
+                condition_type: "PromQL".to_string(),
+                promql: PromQLClusterCondition {
+                    promql: "cluster_infrastructure_provider{type=\"CloudProvider\"}".to_string(),
+                },
+            }],
+            schedule: None,
+        }
+    }
+
+    fn edge(from: &str, to: &str) -> ConditionalUpdateEdge {
+        ConditionalUpdateEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    fn messages(graph: &cincinnati::Graph) -> Vec<(Vec<String>, String)> {
+        graph
+            .conditional_edges
+            .iter()
+            .flatten()
+            .map(|ce| {
+                (
+                    ce.edges.iter().map(|e| e.from.clone()).collect(),
+                    ce.risks[0].message.clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn render_risk_messages() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let plugin = Box::new(RiskMessageTemplatePlugin::default());
+
+        let mut graph = generate_graph(true, false);
+        let conditional_edge = &mut graph.conditional_edges.as_mut().unwrap()[0];
+        conditional_edge.edges = vec![edge("1.0.0", "2.0.0"), edge("1.0.1", "2.0.0")];
+        conditional_edge.risks = vec![risk(
+            "Updating to {{ version }} from {{from}} in {{channel}} ({{id}}, {{metadata.kind}})",
+        )];
+        let parameters: HashMap<String, String> = [
+            ("channel", "stable-2.0"),
+            ("id", "e6b31d1e-0fb0-4a47-8c53-a0d0e79ad537"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let graph = runtime
+            .block_on(plugin.run_internal(InternalIO { graph, parameters }))?
+            .graph;
+
+        assert_eq!(
+            messages(&graph),
+            vec![
+                (
+                    vec!["1.0.0".to_string()],
+                    "Updating to 2.0.0 from 1.0.0 in stable-2.0 ({{id}}, {{metadata.kind}})"
+                        .to_string()
+                ),
+                (
+                    vec!["1.0.1".to_string()],
+                    "Updating to 2.0.0 from 1.0.1 in stable-2.0 ({{id}}, {{metadata.kind}})"
+                        .to_string()
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn keep_shared_risk_messages() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let plugin = Box::new(RiskMessageTemplatePlugin::default());
+
+        let mut graph = generate_graph(true, false);
+        let conditional_edge = &mut graph.conditional_edges.as_mut().unwrap()[0];
+        conditional_edge.edges = vec![edge("1.0.0", "2.0.0"), edge("1.0.1", "2.0.0")];
+        conditional_edge.risks = vec![risk("Updates to {{version}} are broken")];
+
+        let graph = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph,
+                parameters: Default::default(),
+            }))?
+            .graph;
+
+        assert_eq!(
+            messages(&graph),
+            vec![(
+                vec!["1.0.0".to_string(), "1.0.1".to_string()],
+                "Updates to 2.0.0 are broken".to_string()
+            )]
+        );
+
+        Ok(())
+    }
+}
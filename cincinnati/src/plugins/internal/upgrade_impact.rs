@@ -0,0 +1,364 @@
+//! This plugin annotates the edges leading to releases which carry
+//! upgrade-impacting markers in their metadata.
+//!
+//! Each configured marker names a metadata key of the release, e.g.
+//! `io.openshift.upgrades.graph.release.admin_ack` for a required
+//! administrator acknowledgement, or a key noting API removals. For each
+//! release carrying a marker, the origins of its edges, including
+//! conditional ones, are listed in its
+//! `<key_prefix>.upgrade_impact.<impact>` metadata, so that clients can warn
+//! administrators about pre-upgrade actions when updating from one of them.
+//! By default, only edges from earlier minor versions are impacted, as
+//! z-stream updates do not cross the boundaries at which such actions are
+//! required.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use prometheus::{IntGaugeVec, Opts};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+pub static DEFAULT_KEY_FILTER: &str = "io.openshift.upgrades.graph";
+
+/// Infix of the metadata keys holding the impacted origins.
+static IMPACT_KEY_INFIX: &str = "upgrade_impact";
+
+/// Edges impacted by a marker.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, SmartDefault)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImpactScope {
+    /// Edges from earlier minor versions.
+    #[default]
+    Minor,
+    /// All edges.
+    All,
+}
+
+/// Upgrade-impacting marker of the release metadata.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ImpactMarker {
+    /// Name of the impact, e.g. "admin-ack".
+    pub impact: String,
+
+    /// Metadata key of the marker, whose presence with a non-empty value
+    /// marks the release.
+    pub key: String,
+
+    /// Edges impacted by the marker.
+    #[serde(default)]
+    pub scope: ImpactScope,
+}
+
+impl ImpactMarker {
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.key.is_empty(), "empty marker key");
+        ensure!(
+            !self.impact.is_empty()
+                && self
+                    .impact
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "invalid impact name '{}'",
+            self.impact
+        );
+        Ok(())
+    }
+
+    /// Whether the edge from `from` to `to` is impacted.
+    fn impacts(&self, from: &str, to: &str) -> bool {
+        match self.scope {
+            ImpactScope::All => true,
+            ImpactScope::Minor => {
+                match (semver::Version::parse(from), semver::Version::parse(to)) {
+                    (Ok(from), Ok(to)) => (from.major, from.minor) < (to.major, to.minor),
+                    // Origins which cannot be compared are impacted, to err on the side of warning.
+                    _ => true,
+                }
+            }
+        }
+    }
+}
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct UpgradeImpactSettings {
+    #[default(DEFAULT_KEY_FILTER.to_string())]
+    pub key_prefix: String,
+
+    /// Upgrade-impacting markers.
+    pub markers: Vec<ImpactMarker>,
+}
+
+impl PluginSettings for UpgradeImpactSettings {
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = UpgradeImpactPlugin::try_new(self.clone(), registry)?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+
+    fn validate(&self) -> Fallible<()> {
+        ensure!(!self.key_prefix.is_empty(), "empty prefix");
+        let mut impacts = HashSet::new();
+        for marker in &self.markers {
+            marker
+                .validate()
+                .context(format!("invalid marker '{}'", marker.key))?;
+            ensure!(
+                impacts.insert(&marker.impact),
+                "duplicate impact '{}'",
+                marker.impact
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Annotator of the edges impacted by release markers.
+#[derive(CustomDebug)]
+pub struct UpgradeImpactPlugin {
+    key_prefix: String,
+    markers: Vec<ImpactMarker>,
+
+    #[debug(skip)]
+    impacted_edges: IntGaugeVec,
+}
+
+impl UpgradeImpactPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "upgrade-impact-annotate";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: UpgradeImpactSettings = cfg.try_into()?;
+        settings.validate()?;
+
+        Ok(Box::new(settings))
+    }
+
+    fn try_new(
+        settings: UpgradeImpactSettings,
+        registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let impacted_edges = IntGaugeVec::new(
+            Opts::new(
+                "upgrade_impact_edges",
+                "Number of edges annotated with an upgrade impact by the latest run, by impact",
+            ),
+            &["impact"],
+        )?;
+        if let Some(registry) = registry {
+            registry.register(Box::new(impacted_edges.clone()))?;
+        }
+
+        Ok(Self {
+            key_prefix: settings.key_prefix,
+            markers: settings.markers,
+            impacted_edges,
+        })
+    }
+
+    /// Annotate the releases of `graph` with the origins impacted by their markers.
+    fn annotate(&self, graph: &mut cincinnati::Graph) -> Fallible<()> {
+        for marker in &self.markers {
+            let key = format!("{}.{}.{}", self.key_prefix, IMPACT_KEY_INFIX, marker.impact);
+
+            let marked: Vec<(ReleaseId, String)> = graph
+                .find_by_metadata_key(&marker.key)
+                .into_iter()
+                .filter(|(_, _, value)| !value.trim().is_empty())
+                .map(|(id, version, _)| (id, version))
+                .collect();
+
+            // Impacted origins, by marked release.
+            let mut origins: BTreeMap<String, (ReleaseId, BTreeSet<String>)> = marked
+                .into_iter()
+                .map(|(id, version)| {
+                    let from: BTreeSet<String> = graph
+                        .previous_releases(&id)
+                        .map(|(_, _, release)| release.version().to_string())
+                        .filter(|from| marker.impacts(from, &version))
+                        .collect();
+                    (version, (id, from))
+                })
+                .collect();
+            for edge in graph
+                .conditional_edges
+                .iter()
+                .flatten()
+                .flat_map(|ce| &ce.edges)
+            {
+                if let Some((_, from)) = origins.get_mut(&edge.to) {
+                    if marker.impacts(&edge.from, &edge.to) {
+                        from.insert(edge.from.clone());
+                    }
+                }
+            }
+
+            let mut count = 0;
+            for (version, (id, from)) in origins {
+                if from.is_empty() {
+                    continue;
+                }
+                trace!(
+                    "annotating {} edges to {} with impact {}",
+                    from.len(),
+                    version,
+                    marker.impact
+                );
+                count += from.len();
+                graph
+                    .get_metadata_as_ref_mut(&id)
+                    .context(format!("annotating impact of {}", version))?
+                    .insert(key.clone(), from.into_iter().collect::<Vec<_>>().join(","));
+            }
+            self.impacted_edges
+                .with_label_values(&[&marker.impact])
+                .set(count as i64);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for UpgradeImpactPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        self.annotate(&mut graph)?;
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::MapImpl;
+    use commons::testing::init_runtime;
+
+    static ADMIN_ACK_KEY: &str = "io.openshift.upgrades.graph.release.admin_ack";
+
+    fn cfg(extra: &str) -> Fallible<toml::Value> {
+        Ok(toml::from_str(&format!(
+            "name = {:?}\n{}",
+            UpgradeImpactPlugin::PLUGIN_NAME,
+            extra
+        ))?)
+    }
+
+    /// Graph of 4.8.1, 4.8.2 and 4.9.0, each updating to the later ones, with
+    /// an admin-ack marker on 4.8.2 and 4.9.0.
+    fn graph() -> Fallible<cincinnati::Graph> {
+        let mut graph = cincinnati::Graph::default();
+        let mut ids = Vec::new();
+        for version in &["4.8.1", "4.8.2", "4.9.0"] {
+            let mut metadata = MapImpl::new();
+            if *version != "4.8.1" {
+                metadata.insert(
+                    ADMIN_ACK_KEY.to_string(),
+                    "ack-4.8-kube-1.22-api-removals-in-4.9".to_string(),
+                );
+            }
+            ids.push(graph.add_release(cincinnati::Release::Concrete(
+                cincinnati::ConcreteRelease {
+                    version: version.to_string(),
+                    payload: format!("image:{}", version),
+                    metadata,
+                },
+            ))?);
+        }
+        graph.add_edge(&ids[0], &ids[1])?;
+        graph.add_edge(&ids[0], &ids[2])?;
+        graph.add_edge(&ids[1], &ids[2])?;
+        Ok(graph)
+    }
+
+    fn impacts(graph: &cincinnati::Graph) -> BTreeMap<String, String> {
+        graph
+            .find_by_metadata_key("io.openshift.upgrades.graph.upgrade_impact.admin-ack")
+            .into_iter()
+            .map(|(_, version, from)| (version, from))
+            .collect()
+    }
+
+    fn run(markers: &str) -> Fallible<cincinnati::Graph> {
+        let runtime = init_runtime()?;
+        let settings: UpgradeImpactSettings = cfg(markers)?.try_into()?;
+        let plugin = UpgradeImpactPlugin::try_new(settings, None)?;
+
+        Ok(runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: graph()?,
+                parameters: Default::default(),
+            }))?
+            .graph)
+    }
+
+    #[test]
+    fn annotate_minor_updates() -> Fallible<()> {
+        let graph = run(&format!(
+            "[[markers]]\nimpact = \"admin-ack\"\nkey = {:?}",
+            ADMIN_ACK_KEY
+        ))?;
+
+        assert_eq!(
+            impacts(&graph),
+            vec![("4.9.0".to_string(), "4.8.1,4.8.2".to_string())]
+                .into_iter()
+                .collect()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn annotate_all_updates() -> Fallible<()> {
+        let graph = run(&format!(
+            "[[markers]]\nimpact = \"admin-ack\"\nkey = {:?}\nscope = \"all\"",
+            ADMIN_ACK_KEY
+        ))?;
+
+        assert_eq!(
+            impacts(&graph),
+            vec![("4.8.2", "4.8.1"), ("4.9.0", "4.8.1,4.8.2")]
+                .into_iter()
+                .map(|(version, from)| (version.to_string(), from.to_string()))
+                .collect()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_settings() -> Fallible<()> {
+        UpgradeImpactPlugin::deserialize_config(cfg("")?)?;
+        UpgradeImpactPlugin::deserialize_config(cfg(
+            "[[markers]]\nimpact = \"api-removals\"\nkey = \"com.example.api_removals\"",
+        )?)?;
+
+        for invalid in &[
+            "key_prefix = \"\"",
+            "[[markers]]\nimpact = \"admin ack\"\nkey = \"admin_ack\"",
+            "[[markers]]\nimpact = \"admin-ack\"\nkey = \"\"",
+            "[[markers]]\nimpact = \"admin-ack\"\nkey = \"admin_ack\"\nscope = \"patch\"",
+            "[[markers]]\nimpact = \"admin-ack\"\nkey = \"a\"\n[[markers]]\nimpact = \"admin-ack\"\nkey = \"b\"",
+        ] {
+            assert!(
+                UpgradeImpactPlugin::deserialize_config(cfg(invalid)?).is_err(),
+                "{}",
+                invalid
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -2,7 +2,7 @@ use self::cincinnati::plugins::prelude_plugin_impl::*;
 use crate as cincinnati;
 use commons::{CINCINNATI_VERSION, MIN_CINCINNATI_VERSION};
 
-#[derive(Debug, Serialize, Deserialize, SmartDefault)]
+#[derive(Debug, Serialize, Deserialize, SmartDefault, schemars::JsonSchema)]
 #[serde(default)]
 pub struct VersionedGraph {
     pub version: i32,
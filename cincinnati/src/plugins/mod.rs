@@ -5,6 +5,7 @@
 pub mod macros;
 
 pub mod catalog;
+pub mod chain;
 pub mod external;
 pub mod interface;
 pub mod internal;
@@ -16,9 +17,11 @@ use self::cincinnati::plugins::interface::{PluginError, PluginExchange};
 use async_trait::async_trait;
 pub use commons::prelude_errors::*;
 use commons::tracing::get_tracer;
+use commons::GraphError;
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::fmt::Debug;
+use std::time::{Duration, Instant};
 
 use opentelemetry::{
     trace::{mark_span_as_active, FutureExt, Tracer},
@@ -33,22 +36,32 @@ pub mod prelude {
     pub use plugins::{BoxedPlugin, InternalPluginWrapper};
 
     pub use plugins::catalog::PluginSettings;
+    pub use plugins::chain::{PluginChain, PluginChainBuilder};
+    pub use plugins::internal::accepted_risks::AcceptedRisksPlugin;
     pub use plugins::internal::arch_filter::ArchFilterPlugin;
     pub use plugins::internal::channel_filter::ChannelFilterPlugin;
-    pub use plugins::internal::cincinnati_graph_fetch::CincinnatiGraphFetchPlugin;
+    pub use plugins::internal::cincinnati_graph_fetch::{
+        CincinnatiGraphFetchPlugin, CincinnatiGraphFetchSettings,
+    };
     pub use plugins::internal::edge_add_remove::EdgeAddRemovePlugin;
+    pub use plugins::internal::edge_schedule::EdgeSchedulePlugin;
     pub use plugins::internal::github_openshift_secondary_metadata_scraper::{
         GithubOpenshiftSecondaryMetadataScraperPlugin,
         GithubOpenshiftSecondaryMetadataScraperSettings,
     };
+    pub use plugins::internal::metadata_enrich_http::MetadataEnrichHttpPlugin;
     pub use plugins::internal::metadata_fetch_quay::QuayMetadataFetchPlugin;
     pub use plugins::internal::node_remove::NodeRemovePlugin;
     pub use plugins::internal::openshift_secondary_metadata_parser::{
         OpenshiftSecondaryMetadataParserPlugin, OpenshiftSecondaryMetadataParserSettings,
     };
+    pub use plugins::internal::release_scrape::{
+        ReleaseScrapePlugin, ReleaseScrapeSettings, ReleaseSourceKind,
+    };
     pub use plugins::internal::release_scrape_dockerv2::{
         ReleaseScrapeDockerv2Plugin, ReleaseScrapeDockerv2Settings,
     };
+    pub use plugins::internal::risk_message_template::RiskMessageTemplatePlugin;
 
     pub use std::iter::FromIterator;
 
@@ -59,7 +72,7 @@ pub mod prelude_plugin_impl {
     use self::cincinnati::plugins;
     use crate as cincinnati;
 
-    pub use self::cincinnati::{daggy, ReleaseId};
+    pub use self::cincinnati::{daggy, MergePrecedence, ReleaseId};
     pub use plugins::catalog::PluginSettings;
     pub use plugins::{BoxedPlugin, InternalIO, InternalPlugin, InternalPluginWrapper};
 
@@ -74,6 +87,11 @@ pub mod prelude_plugin_impl {
     pub use std::str::FromStr;
 }
 
+/// Prefix for client parameters which are addressed to a specific plugin.
+///
+/// Such parameters are expected in the form `plugin.<plugin-name>.<key>=<value>`.
+pub static PLUGIN_PARAMETERS_PREFIX: &str = "plugin";
+
 /// Convenience type for the thread-safe storage of plugins
 pub type BoxedPlugin = Box<dyn Plugin<PluginIO>>;
 
@@ -134,6 +152,11 @@ where
     async fn run(&self, t: T) -> Fallible<T>;
 
     fn get_name(&self) -> &'static str;
+
+    /// Keys of the per-request parameters accepted by this plugin.
+    fn get_parameters(&self) -> &'static [&'static str] {
+        &[]
+    }
 }
 
 /// Trait to be implemented by internal plugins with their native IO type
@@ -141,6 +164,9 @@ where
 pub trait InternalPlugin {
     const PLUGIN_NAME: &'static str;
 
+    /// Keys of the per-request parameters accepted by this plugin.
+    const PLUGIN_PARAMETERS: &'static [&'static str] = &[];
+
     async fn run_internal(&self, input: InternalIO) -> Fallible<InternalIO>;
 
     fn get_name(&self) -> &'static str {
@@ -159,6 +185,9 @@ where
 {
     const PLUGIN_NAME: &'static str;
 
+    /// Keys of the per-request parameters accepted by this plugin.
+    const PLUGIN_PARAMETERS: &'static [&'static str] = &[];
+
     async fn run_external(&self, input: ExternalIO) -> Fallible<ExternalIO>;
 
     fn get_name(&self) -> &'static str {
@@ -354,6 +383,10 @@ where
     fn get_name(&self) -> &'static str {
         <T as InternalPlugin>::PLUGIN_NAME
     }
+
+    fn get_parameters(&self) -> &'static [&'static str] {
+        <T as InternalPlugin>::PLUGIN_PARAMETERS
+    }
 }
 
 /// This implementation allows the process function to run ipmlementors of
@@ -373,6 +406,80 @@ where
     fn get_name(&self) -> &'static str {
         <T as ExternalPlugin>::PLUGIN_NAME
     }
+
+    fn get_parameters(&self) -> &'static [&'static str] {
+        <T as ExternalPlugin>::PLUGIN_PARAMETERS
+    }
+}
+
+/// Build the parameter key under which a plugin-specific parameter is delivered.
+pub fn plugin_parameter_key(plugin_name: &str, key: &str) -> String {
+    format!("{}.{}.{}", PLUGIN_PARAMETERS_PREFIX, plugin_name, key)
+}
+
+/// Look up a plugin-specific parameter, as passed by the client via `plugin.<name>.<key>`.
+pub fn get_plugin_parameter<'a>(
+    parameters: &'a HashMap<String, String>,
+    plugin_name: &str,
+    key: &str,
+) -> Option<&'a String> {
+    parameters.get(&plugin_parameter_key(plugin_name, key))
+}
+
+/// Make sure all plugin-specific parameters are addressed to a plugin in the
+/// given chain, and that the plugin declares the given key.
+pub fn validate_plugin_parameters<'a, T>(
+    plugins: T,
+    parameters: &HashMap<String, String>,
+) -> Result<(), GraphError>
+where
+    T: IntoIterator<Item = &'a BoxedPlugin>,
+{
+    let prefix = format!("{}.", PLUGIN_PARAMETERS_PREFIX);
+    let declared: HashMap<&'static str, &'static [&'static str]> = plugins
+        .into_iter()
+        .map(|plugin| (plugin.get_name(), plugin.get_parameters()))
+        .collect();
+
+    let mut invalid: Vec<&str> = parameters
+        .keys()
+        .filter_map(|param| param.strip_prefix(&prefix).map(|rest| (param, rest)))
+        .filter(|(_, rest)| match rest.split_once('.') {
+            Some((name, key)) => !declared.get(name).map_or(false, |keys| keys.contains(&key)),
+            None => true,
+        })
+        .map(|(param, _)| param.as_str())
+        .collect();
+
+    if invalid.is_empty() {
+        return Ok(());
+    }
+
+    invalid.sort_unstable();
+    Err(GraphError::InvalidParams(format!(
+        "unknown plugin parameters: {}",
+        invalid.join(", ")
+    )))
+}
+
+/// Run of a plugin within a chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginRun {
+    /// Plugin name.
+    pub name: &'static str,
+    /// Time spent in the plugin.
+    pub duration: Duration,
+    /// Error returned by the plugin, if it failed.
+    pub error: Option<String>,
+}
+
+/// Outcome of a plugin chain.
+#[derive(Debug)]
+pub struct ChainRun {
+    /// Output of the last plugin, or the first error.
+    pub io: Fallible<InternalIO>,
+    /// Runs of the plugins, in order, up to the first failing one.
+    pub plugins: Vec<PluginRun>,
 }
 
 /// Processes all given Plugins sequentially.
@@ -385,7 +492,47 @@ where
     T: Sync + Send,
     T: 'static,
 {
+    process_traced(plugins, initial_io).await.io
+}
+
+/// Processes all given Plugins sequentially, like `process`, also returning
+/// the time spent in each plugin.
+pub async fn process_timed<T>(
+    plugins: T,
+    initial_io: PluginIO,
+) -> Fallible<(InternalIO, Vec<(&'static str, Duration)>)>
+where
+    T: Iterator<Item = &'static BoxedPlugin>,
+    T: Sync + Send,
+    T: 'static,
+{
+    let chain = process_traced(plugins, initial_io).await;
+    let timings = chain
+        .plugins
+        .iter()
+        .map(|run| (run.name, run.duration))
+        .collect();
+    Ok((chain.io?, timings))
+}
+
+/// Processes all given Plugins sequentially, like `process`, also reporting
+/// the run of each plugin.
+///
+/// If the initial parameters set a deadline, each plugin runs within the
+/// time left until it, and the chain fails with the name of the plugin
+/// exceeding it.
+pub async fn process_traced<T>(plugins: T, initial_io: PluginIO) -> ChainRun
+where
+    T: Iterator<Item = &'static BoxedPlugin>,
+    T: Sync + Send,
+    T: 'static,
+{
+    let deadline = match &initial_io {
+        PluginIO::InternalIO(io) => commons::deadline::from_params(&io.parameters),
+        PluginIO::ExternalIO(_) => None,
+    };
     let mut io = initial_io;
+    let mut runs = Vec::new();
 
     let span = get_tracer().start("plugins");
     let _active_span = mark_span_as_active(span);
@@ -397,10 +544,33 @@ where
         let plugin_span = get_tracer().start(plugin_name);
         let _active_plugin_span = mark_span_as_active(plugin_span);
         let cx = ot_context::current();
-        io = next_plugin.run(io).with_context(cx).await?;
+        let start = Instant::now();
+        let result =
+            commons::deadline::within(deadline, plugin_name, next_plugin.run(io).with_context(cx))
+                .await;
+        let mut run = PluginRun {
+            name: plugin_name,
+            duration: start.elapsed(),
+            error: None,
+        };
+        match result {
+            Ok(next_io) => io = next_io,
+            Err(e) => {
+                run.error = Some(format!("{:#}", e));
+                runs.push(run);
+                return ChainRun {
+                    io: Err(commons::PluginFailure::attribute(plugin_name, e)),
+                    plugins: runs,
+                };
+            }
+        }
+        runs.push(run);
     }
 
-    io.try_into()
+    ChainRun {
+        io: io.try_into(),
+        plugins: runs,
+    }
 }
 
 /// Wrapper around `process` with an optional timeout.
@@ -422,23 +592,83 @@ where
     T: Iterator<Item = &'static BoxedPlugin>,
     T: Sync + Send,
     T: 'static,
+{
+    block_on_with_timeout(move || process(plugins, initial_io), timeout)
+}
+
+/// Wrapper around `process_traced` with an optional timeout, like `process_blocking`.
+///
+/// No plugin runs are reported if the timeout is exceeded.
+pub fn process_traced_blocking<T>(
+    plugins: T,
+    initial_io: PluginIO,
+    timeout: Option<std::time::Duration>,
+) -> ChainRun
+where
+    T: Iterator<Item = &'static BoxedPlugin>,
+    T: Sync + Send,
+    T: 'static,
+{
+    process_traced_budgeted_blocking(plugins, initial_io, timeout, None)
+}
+
+/// Wrapper around `process_traced_blocking`, drawing the outbound requests of
+/// the plugins from `budget`, if set, until the timeout.
+pub fn process_traced_budgeted_blocking<T>(
+    plugins: T,
+    initial_io: PluginIO,
+    timeout: Option<std::time::Duration>,
+    budget: Option<commons::budget::RequestBudget>,
+) -> ChainRun
+where
+    T: Iterator<Item = &'static BoxedPlugin>,
+    T: Sync + Send,
+    T: 'static,
+{
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let chain = block_on_with_timeout(
+        move || async move {
+            let chain = process_traced(plugins, initial_io);
+            Ok(match budget {
+                Some(budget) => budget.scope(deadline, chain).await,
+                None => chain.await,
+            })
+        },
+        timeout,
+    );
+    chain.unwrap_or_else(|e| ChainRun {
+        io: Err(e),
+        plugins: vec![],
+    })
+}
+
+/// Run the future built by `make_future` on a new runtime, with an optional timeout.
+///
+/// See `process_blocking` for details on the timeout implementation.
+fn block_on_with_timeout<F, Fut, O>(
+    make_future: F,
+    timeout: Option<std::time::Duration>,
+) -> Fallible<O>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = Fallible<O>>,
+    O: Send + 'static,
 {
     let runtime = tokio::runtime::Runtime::new()?;
 
     let timeout = match timeout {
-        None => return runtime.block_on(process(plugins, initial_io)),
+        None => return runtime.block_on(make_future()),
         Some(timeout) => timeout,
     };
     let deadline = timeout + (timeout / 100);
 
-    let (tx, rx) = std::sync::mpsc::channel::<Fallible<InternalIO>>();
+    let (tx, rx) = std::sync::mpsc::channel::<Fallible<O>>();
 
     {
         let tx = tx.clone();
 
         std::thread::spawn(move || {
-            let io_future =
-                async { tokio::time::timeout(timeout, process(plugins, initial_io)).await };
+            let io_future = async { tokio::time::timeout(timeout, make_future()).await };
             let io_result = runtime
                 .block_on(io_future)
                 .context(format!(
@@ -647,6 +877,115 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn process_traced_reports_failing_plugin() -> Fallible<()> {
+        let runtime = commons::testing::init_runtime()?;
+
+        lazy_static! {
+            static ref PLUGINS: Vec<BoxedPlugin> = new_plugins!(
+                InternalPluginWrapper(TestInternalPlugin {
+                    counter: Default::default(),
+                    dict: Arc::new(FuturesMutex::new(Default::default())),
+                    inner_fn: None,
+                }),
+                InternalPluginWrapper(TestInternalPlugin {
+                    counter: Default::default(),
+                    dict: Arc::new(FuturesMutex::new(Default::default())),
+                    inner_fn: Some(Arc::new(|| -> Fallible<()> {
+                        bail!("upstream unreachable")
+                    })),
+                }),
+                ExternalPluginWrapper(TestExternalPlugin {})
+            );
+        }
+
+        let initial_internalio = InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+        };
+
+        let chain = runtime.block_on(process_traced(
+            PLUGINS.iter(),
+            PluginIO::InternalIO(initial_internalio),
+        ));
+
+        assert!(chain.io.is_err());
+        let errors: Vec<Option<&str>> = chain
+            .plugins
+            .iter()
+            .map(|run| run.error.as_deref())
+            .collect();
+        assert_eq!(errors, vec![None, Some("upstream unreachable")]);
+
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct TestSleepPlugin {
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl InternalPlugin for TestSleepPlugin {
+        const PLUGIN_NAME: &'static str = "test_sleep_plugin";
+
+        async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+            tokio::time::sleep(self.delay).await;
+            Ok(io)
+        }
+    }
+
+    #[test]
+    fn process_traced_enforces_deadline() -> Fallible<()> {
+        let runtime = commons::testing::init_runtime()?;
+
+        lazy_static! {
+            static ref PLUGINS: Vec<BoxedPlugin> = new_plugins!(
+                InternalPluginWrapper(TestSleepPlugin {
+                    delay: Duration::from_millis(1),
+                }),
+                InternalPluginWrapper(TestSleepPlugin {
+                    delay: Duration::from_secs(100),
+                })
+            );
+        }
+
+        let run = |timeout: Duration| {
+            let deadline = std::time::SystemTime::now() + timeout;
+            let initial_internalio = InternalIO {
+                graph: Default::default(),
+                parameters: vec![(
+                    commons::GRAPH_DEADLINE_PARAM_KEY.to_string(),
+                    commons::deadline::to_param(deadline),
+                )]
+                .into_iter()
+                .collect(),
+            };
+            runtime.block_on(process_traced(
+                PLUGINS.iter(),
+                PluginIO::InternalIO(initial_internalio),
+            ))
+        };
+
+        let before_process = std::time::Instant::now();
+        let chain = run(Duration::from_millis(200));
+        assert!(before_process.elapsed() < Duration::from_secs(10));
+        let error = chain.io.expect_err("chain exceeded its deadline");
+        assert_eq!(
+            GraphError::from_error(error),
+            GraphError::DeadlineExceeded(TestSleepPlugin::PLUGIN_NAME.to_string())
+        );
+        assert_eq!(chain.plugins.len(), 2);
+        assert!(chain.plugins[0].error.is_none());
+
+        // Plugins do not start past the deadline.
+        let chain = run(Duration::ZERO);
+        assert!(chain.io.is_err());
+        assert_eq!(chain.plugins.len(), 1);
+
+        Ok(())
+    }
+
     #[test]
     fn process_blocking_succeeds() -> Fallible<()> {
         lazy_static! {
@@ -740,6 +1079,54 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn plugin_parameters_validation() -> Fallible<()> {
+        lazy_static! {
+            static ref PLUGINS: Vec<BoxedPlugin> = new_plugins!(
+                ExternalPluginWrapper(TestExternalPlugin {}),
+                InternalPluginWrapper(
+                    crate::plugins::internal::arch_filter::ArchFilterPlugin::default()
+                )
+            );
+        }
+
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        validate_plugin_parameters(PLUGINS.iter(), &params(&[("channel", "a")]))?;
+        validate_plugin_parameters(
+            PLUGINS.iter(),
+            &params(&[("plugin.arch-filter.default_arch", "arm64")]),
+        )?;
+
+        for invalid in &[
+            "plugin.arch-filter.unknown",
+            "plugin.unknown-plugin.default_arch",
+            "plugin.arch-filter",
+            "plugin.test_internal_plugin.key",
+        ] {
+            let err =
+                validate_plugin_parameters(PLUGINS.iter(), &params(&[(invalid, "x")])).unwrap_err();
+            assert!(
+                matches!(&err, GraphError::InvalidParams(msg) if msg.contains(invalid)),
+                "unexpected result for '{}': {:?}",
+                invalid,
+                err
+            );
+        }
+
+        assert_eq!(
+            plugin_parameter_key("arch-filter", "default_arch"),
+            "plugin.arch-filter.default_arch"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn plugin_names() -> Fallible<()> {
         lazy_static! {
@@ -0,0 +1,228 @@
+//! Product profiles.
+//!
+//! A profile gathers the conventions of a product whose releases are
+//! served: the namespace of its metadata keys, the repositories of its
+//! release images and of its graph-data, and the naming of its channels.
+//! The default plugin chains of the graph-builder and of the policy-engine
+//! follow the configured profile, the `ocp` one by default.
+//!
+//! Profiles are configured either by name, e.g. `profile = "okd"`, or as a
+//! table overriding some conventions of a built-in profile, e.g.
+//! `profile = { name = "okd", key_prefix = "io.okd.upgrades.graph" }`.
+
+use crate::is_reverse_dns;
+use crate::plugins::internal::channel_filter::ChannelPattern;
+use crate::plugins::internal::edge_add_remove::DEFAULT_KEY_FILTER;
+use crate::plugins::internal::release_scrape_dockerv2::{
+    DEFAULT_SCRAPE_REGISTRY, DEFAULT_SCRAPE_REPOSITORY,
+};
+use commons::prelude_errors::*;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+/// Name of the OpenShift Container Platform profile.
+pub const OCP_PROFILE: &str = "ocp";
+
+/// Name of the OKD profile, whose releases are based on Fedora CoreOS.
+pub const OKD_PROFILE: &str = "okd";
+
+/// Name of the OKD profile whose releases are based on CentOS Stream CoreOS.
+pub const OKD_SCOS_PROFILE: &str = "okd-scos";
+
+/// Profile of a product, as configured.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ProductProfileConfig {
+    Name(String),
+    Table(ProductProfileTable),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProductProfileTable {
+    name: String,
+    key_prefix: Option<String>,
+    registry: Option<String>,
+    repository: Option<String>,
+    graph_data_org: Option<String>,
+    graph_data_repo: Option<String>,
+    graph_data_branch: Option<String>,
+    channels: Option<Vec<String>>,
+}
+
+/// Conventions of a product whose releases are served.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "ProductProfileConfig")]
+pub struct ProductProfile {
+    /// Name of the built-in profile, e.g. `okd`.
+    pub name: String,
+    /// Namespace of the metadata keys, e.g. `io.openshift.upgrades.graph`.
+    pub key_prefix: String,
+    /// Registry of the release images, e.g. `quay.io`.
+    pub registry: String,
+    /// Repository of the release images, e.g. `openshift-release-dev/ocp-release`.
+    pub repository: String,
+    /// GitHub organization of the graph-data repository.
+    pub graph_data_org: String,
+    /// Name of the graph-data repository.
+    pub graph_data_repo: String,
+    /// Branch of the graph-data repository.
+    pub graph_data_branch: String,
+    /// Channels, globs or regular expressions of the channels which can be
+    /// served; all channels can be if empty.
+    pub channels: Vec<String>,
+}
+
+impl Default for ProductProfile {
+    fn default() -> Self {
+        Self::builtin(OCP_PROFILE).expect("missing built-in profile")
+    }
+}
+
+impl TryFrom<ProductProfileConfig> for ProductProfile {
+    type Error = Error;
+
+    fn try_from(config: ProductProfileConfig) -> Fallible<Self> {
+        let table = match config {
+            ProductProfileConfig::Name(name) => return Self::try_builtin(&name),
+            ProductProfileConfig::Table(table) => table,
+        };
+
+        let mut profile = Self::try_builtin(&table.name)?;
+        let overrides = [
+            (&mut profile.key_prefix, table.key_prefix),
+            (&mut profile.registry, table.registry),
+            (&mut profile.repository, table.repository),
+            (&mut profile.graph_data_org, table.graph_data_org),
+            (&mut profile.graph_data_repo, table.graph_data_repo),
+            (&mut profile.graph_data_branch, table.graph_data_branch),
+        ];
+        for (field, value) in overrides {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+        if let Some(channels) = table.channels {
+            profile.channels = channels;
+        }
+
+        profile
+            .validate()
+            .context(format!("invalid profile '{}'", profile.name))?;
+        Ok(profile)
+    }
+}
+
+impl ProductProfile {
+    /// Return the built-in profile named `name`, if any.
+    pub fn builtin(name: &str) -> Option<Self> {
+        let (repository, channels): (&str, &[&str]) = match name {
+            OCP_PROFILE => (DEFAULT_SCRAPE_REPOSITORY, &[]),
+            OKD_PROFILE => ("openshift/okd", &["stable-4*", "next-4*"]),
+            OKD_SCOS_PROFILE => ("okd/scos-release", &["stable-scos-4*", "next-scos-4*"]),
+            _ => return None,
+        };
+
+        Some(Self {
+            name: name.to_string(),
+            key_prefix: DEFAULT_KEY_FILTER.to_string(),
+            registry: DEFAULT_SCRAPE_REGISTRY.to_string(),
+            repository: repository.to_string(),
+            graph_data_org: "openshift".to_string(),
+            graph_data_repo: "cincinnati-graph-data".to_string(),
+            graph_data_branch: "master".to_string(),
+            channels: channels.iter().map(ToString::to_string).collect(),
+        })
+    }
+
+    fn try_builtin(name: &str) -> Fallible<Self> {
+        Self::builtin(name).ok_or_else(|| {
+            format_err!(
+                "unknown profile '{}', expected one of '{}', '{}' or '{}'",
+                name,
+                OCP_PROFILE,
+                OKD_PROFILE,
+                OKD_SCOS_PROFILE
+            )
+        })
+    }
+
+    /// Check that the conventions of the profile are consistent.
+    pub fn validate(&self) -> Fallible<()> {
+        ensure!(
+            is_reverse_dns(&self.key_prefix),
+            "metadata key prefix '{}' is not in reverse-DNS notation",
+            self.key_prefix
+        );
+        for (what, value) in &[
+            ("registry", &self.registry),
+            ("repository", &self.repository),
+            ("graph-data organization", &self.graph_data_org),
+            ("graph-data repository", &self.graph_data_repo),
+            ("graph-data branch", &self.graph_data_branch),
+        ] {
+            ensure!(!value.is_empty(), "empty {}", what);
+        }
+        for channel in &self.channels {
+            ChannelPattern::parse(channel).context(format!("invalid channel '{}'", channel))?;
+        }
+        Ok(())
+    }
+
+    /// Return the metadata key of `suffix`, e.g. `release.channels`.
+    pub fn metadata_key(&self, suffix: &str) -> String {
+        format!("{}.{}", self.key_prefix, suffix)
+    }
+
+    /// Return the metadata key listing the channels of a release.
+    pub fn channels_key(&self) -> String {
+        crate::channels_key(&self.key_prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configure_profiles() {
+        assert_eq!(ProductProfile::default().name, OCP_PROFILE);
+        assert_eq!(
+            ProductProfile::default().metadata_key("release.manifestref"),
+            "io.openshift.upgrades.graph.release.manifestref"
+        );
+        assert_eq!(
+            ProductProfile::default().channels_key(),
+            "io.openshift.upgrades.graph.release.channels"
+        );
+        for name in &[OCP_PROFILE, OKD_PROFILE, OKD_SCOS_PROFILE] {
+            assert!(ProductProfile::builtin(name).unwrap().validate().is_ok());
+        }
+
+        let parse = |input: &str| {
+            toml::from_str::<toml::Value>(input)
+                .unwrap()
+                .get("profile")
+                .cloned()
+                .unwrap()
+                .try_into::<ProductProfile>()
+        };
+        let okd = parse(r#"profile = "okd""#).unwrap();
+        assert_eq!(okd, ProductProfile::builtin(OKD_PROFILE).unwrap());
+        assert_eq!(okd.repository, "openshift/okd");
+
+        let custom = parse(
+            r#"profile = { name = "okd-scos", key_prefix = "io.okd.upgrades.graph", channels = [] }"#,
+        )
+        .unwrap();
+        assert_eq!(custom.name, OKD_SCOS_PROFILE);
+        assert_eq!(custom.key_prefix, "io.okd.upgrades.graph");
+        assert_eq!(custom.repository, "okd/scos-release");
+        assert!(custom.channels.is_empty());
+
+        assert!(parse(r#"profile = "rhel""#).is_err());
+        assert!(parse(r#"profile = { name = "okd", key_prefix = "okd" }"#).is_err());
+        assert!(parse(r#"profile = { name = "okd", channels = ["Stable"] }"#).is_err());
+        assert!(parse(r#"profile = { name = "okd", arch = "amd64" }"#).is_err());
+    }
+}
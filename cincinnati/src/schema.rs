@@ -0,0 +1,86 @@
+//! Schemas of the documents served on the graph endpoints.
+//!
+//! They are generated from the types used for serialization, so that they
+//! cannot drift from the actual responses.
+
+use crate::plugins::internal::versioned_graph::VersionedGraph;
+use commons::prelude_errors::*;
+use commons::ErrorMessage;
+use schemars::gen::SchemaSettings;
+use schemars::schema::RootSchema;
+use serde_json::{Map, Value};
+
+/// Name of the graph document schema among the OpenAPI components.
+pub const GRAPH_SCHEMA_NAME: &str = "Graph";
+
+/// Name of the error response schema among the OpenAPI components.
+pub const ERROR_SCHEMA_NAME: &str = "GraphError";
+
+/// Return the JSON Schema (draft 7) of the graph document.
+pub fn graph_json_schema() -> RootSchema {
+    schemars::schema_for!(VersionedGraph)
+}
+
+/// Return the OpenAPI 3.0 schemas of the graph endpoint responses and of their parts, by name.
+///
+/// The graph document is named `GRAPH_SCHEMA_NAME`, error responses are named
+/// `ERROR_SCHEMA_NAME`, and references between the schemas point to
+/// `#/components/schemas/<name>`.
+pub fn openapi_schemas() -> Fallible<Map<String, Value>> {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let mut graph = gen.root_schema_for::<VersionedGraph>().schema;
+    graph.metadata().title = Some(GRAPH_SCHEMA_NAME.to_string());
+    gen.subschema_for::<ErrorMessage>();
+
+    let mut schemas = Map::new();
+    for (name, schema) in gen.take_definitions() {
+        schemas.insert(name, serde_json::to_value(schema)?);
+    }
+    schemas.insert(GRAPH_SCHEMA_NAME.to_string(), serde_json::to_value(graph)?);
+    Ok(schemas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::generate_custom_graph;
+
+    #[test]
+    fn graph_document_schema() -> Fallible<()> {
+        let schema = serde_json::to_value(graph_json_schema())?;
+        let properties = &schema["properties"];
+        assert_eq!(properties["nodes"]["type"], "array");
+        assert_eq!(properties["edges"]["items"]["minItems"], 2);
+        assert!(properties["conditionalEdges"].is_object());
+        assert!(properties["version"].is_object());
+
+        // Fields of the serialized graph are all described by the schema.
+        let graph = generate_custom_graph(
+            "image",
+            (0..2).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1)]),
+        );
+        let document = serde_json::to_value(VersionedGraph { version: 1, graph })?;
+        for key in document.as_object().unwrap().keys() {
+            assert!(properties.get(key).is_some(), "undocumented field {}", key);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn graph_openapi_components() -> Fallible<()> {
+        let schemas = openapi_schemas()?;
+        assert!(schemas.contains_key(GRAPH_SCHEMA_NAME));
+        assert!(schemas.contains_key("Release"));
+        assert_eq!(
+            schemas[ERROR_SCHEMA_NAME]["required"],
+            serde_json::json!(["kind", "value"])
+        );
+        assert_eq!(
+            schemas[GRAPH_SCHEMA_NAME]["properties"]["nodes"]["items"]["$ref"],
+            "#/components/schemas/Release"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,553 @@
+//! Schemes of release versions.
+//!
+//! Release versions are semantic versions by default. Products whose
+//! versions are not, e.g. `2024.1.post2` or `24.03-r5`, can configure a
+//! `pep440` scheme or a `regex` scheme, whose capture groups are compared in
+//! order, each either numerically or lexically. The build metadata of a
+//! version, i.e. anything after the first `+`, encodes the architecture of
+//! the release and is ignored by all schemes.
+//!
+//! Schemes are configured either by name, e.g. `version_scheme = "pep440"`,
+//! or as a table, e.g.
+//! `version_scheme = { kind = "regex", pattern = '(\d+)\.(\d+)-r(\d+)' }`.
+
+use commons::prelude_errors::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use smart_default::SmartDefault;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// Version of a release, as found in its metadata.
+///
+/// Semantic versions are kept parsed, so that their build metadata can be
+/// manipulated; other versions are kept verbatim.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReleaseVersion {
+    /// Semantic version.
+    Semver(semver::Version),
+    /// Version which is not a semantic version.
+    Other(String),
+}
+
+impl ReleaseVersion {
+    /// Return the build metadata of the version, if any.
+    pub fn build(&self) -> Option<String> {
+        match self {
+            ReleaseVersion::Semver(version) if version.build.is_empty() => None,
+            ReleaseVersion::Semver(version) => Some(
+                version
+                    .build
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("."),
+            ),
+            ReleaseVersion::Other(version) => {
+                version.split_once('+').map(|(_, build)| build.to_string())
+            }
+        }
+    }
+
+    /// Set the build metadata of the version, e.g. to an architecture.
+    pub fn set_build(&mut self, build: &str) {
+        match self {
+            ReleaseVersion::Semver(version) => {
+                version.build = vec![semver::Identifier::AlphaNumeric(build.to_string())];
+            }
+            ReleaseVersion::Other(version) => {
+                *version = format!("{}+{}", strip_build(version), build);
+            }
+        }
+    }
+
+    /// Copy the build metadata of `other` to the version.
+    pub fn set_build_from(&mut self, other: &ReleaseVersion) {
+        if let (ReleaseVersion::Semver(version), ReleaseVersion::Semver(other)) =
+            (&mut *self, other)
+        {
+            version.build = other.build.clone();
+            return;
+        }
+
+        let version = self.to_string();
+        let version = match other.build() {
+            Some(build) => format!("{}+{}", strip_build(&version), build),
+            None => strip_build(&version).to_string(),
+        };
+        *self = ReleaseVersion::from(version);
+    }
+
+    /// Remove `build` from the build metadata of the version.
+    pub fn remove_build(&mut self, build: &str) {
+        match self {
+            ReleaseVersion::Semver(version) => {
+                version
+                    .build
+                    .retain(|identifier| identifier.to_string() != build);
+            }
+            ReleaseVersion::Other(version) => {
+                if version.split_once('+').map(|(_, b)| b) == Some(build) {
+                    *version = strip_build(version).to_string();
+                }
+            }
+        }
+    }
+}
+
+impl From<semver::Version> for ReleaseVersion {
+    fn from(version: semver::Version) -> Self {
+        ReleaseVersion::Semver(version)
+    }
+}
+
+impl From<String> for ReleaseVersion {
+    fn from(version: String) -> Self {
+        match semver::Version::parse(&version) {
+            Ok(version) => ReleaseVersion::Semver(version),
+            Err(_) => ReleaseVersion::Other(version),
+        }
+    }
+}
+
+impl FromStr for ReleaseVersion {
+    type Err = Error;
+
+    fn from_str(version: &str) -> Fallible<Self> {
+        ensure!(!version.trim().is_empty(), "empty version");
+        Ok(ReleaseVersion::from(version.to_string()))
+    }
+}
+
+impl fmt::Display for ReleaseVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReleaseVersion::Semver(version) => version.fmt(f),
+            ReleaseVersion::Other(version) => f.write_str(version),
+        }
+    }
+}
+
+impl Serialize for ReleaseVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ReleaseVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = String::deserialize(deserializer)?;
+        version.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Return `version` without its build metadata.
+fn strip_build(version: &str) -> &str {
+    version.split('+').next().unwrap_or(version)
+}
+
+/// Comparison of a component of `regex` versions.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ComponentOrdering {
+    /// Compare the component as an unsigned integer.
+    Numeric,
+    /// Compare the component as a string.
+    Lexical,
+}
+
+/// Scheme of release versions, as configured.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum VersionSchemeConfig {
+    Name(String),
+    Table(VersionSchemeTable),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VersionSchemeTable {
+    kind: String,
+    pattern: Option<String>,
+    #[serde(default)]
+    ordering: Vec<ComponentOrdering>,
+}
+
+/// Scheme of release versions, which determines their order.
+#[derive(Clone, Debug, SmartDefault, Deserialize)]
+#[serde(try_from = "VersionSchemeConfig")]
+pub enum VersionScheme {
+    /// Semantic versions, e.g. `4.14.1`.
+    #[default]
+    Semver,
+    /// PEP 440 versions, e.g. `1!2024.1rc1.post2.dev3`.
+    Pep440,
+    /// Versions matching `pattern`, whose capture groups are compared in
+    /// order, according to `ordering`.
+    Regex {
+        /// Pattern matching whole versions.
+        pattern: Regex,
+        /// Comparison of each capture group.
+        ordering: Vec<ComponentOrdering>,
+    },
+}
+
+impl TryFrom<VersionSchemeConfig> for VersionScheme {
+    type Error = Error;
+
+    fn try_from(config: VersionSchemeConfig) -> Fallible<Self> {
+        let table = match config {
+            VersionSchemeConfig::Name(kind) => VersionSchemeTable {
+                kind,
+                pattern: None,
+                ordering: vec![],
+            },
+            VersionSchemeConfig::Table(table) => table,
+        };
+
+        match (table.kind.as_str(), table.pattern) {
+            ("semver", None) if table.ordering.is_empty() => Ok(VersionScheme::Semver),
+            ("pep440", None) if table.ordering.is_empty() => Ok(VersionScheme::Pep440),
+            ("semver", _) | ("pep440", _) => bail!(
+                "pattern and ordering are only supported by the regex version scheme, not '{}'",
+                table.kind
+            ),
+            ("regex", Some(pattern)) => VersionScheme::try_new_regex(&pattern, table.ordering),
+            ("regex", None) => bail!("no pattern given for the regex version scheme"),
+            (kind, _) => bail!("unknown version scheme '{}'", kind),
+        }
+    }
+}
+
+impl VersionScheme {
+    /// Create a `regex` scheme, comparing the capture groups of `pattern`
+    /// according to `ordering`, or numerically if `ordering` is empty.
+    pub fn try_new_regex(pattern: &str, ordering: Vec<ComponentOrdering>) -> Fallible<Self> {
+        let regex = Regex::new(&format!("^(?:{})$", pattern))
+            .context(format!("invalid version pattern '{}'", pattern))?;
+        let groups = regex.captures_len() - 1;
+        ensure!(
+            groups > 0,
+            "version pattern '{}' has no capture group",
+            pattern
+        );
+        let ordering = if ordering.is_empty() {
+            vec![ComponentOrdering::Numeric; groups]
+        } else {
+            ordering
+        };
+        ensure!(
+            ordering.len() == groups,
+            "version pattern '{}' has {} capture groups, but {} are ordered",
+            pattern,
+            groups,
+            ordering.len()
+        );
+
+        Ok(VersionScheme::Regex {
+            pattern: regex,
+            ordering,
+        })
+    }
+
+    /// Return the name of the scheme.
+    pub fn name(&self) -> &'static str {
+        match self {
+            VersionScheme::Semver => "semver",
+            VersionScheme::Pep440 => "pep440",
+            VersionScheme::Regex { .. } => "regex",
+        }
+    }
+
+    /// Parse `version` into a key ordering it among the versions of the scheme.
+    pub fn parse(&self, version: &str) -> Fallible<VersionKey> {
+        let key = match self {
+            VersionScheme::Semver => Key::Semver(
+                semver::Version::parse(version)
+                    .context(format!("'{}' is not a semantic version", version))?,
+            ),
+            VersionScheme::Pep440 => Key::Pep440(
+                Pep440Key::parse(strip_build(version))
+                    .context(format!("'{}' is not a PEP 440 version", version))?,
+            ),
+            VersionScheme::Regex { pattern, ordering } => {
+                let captures = pattern.captures(strip_build(version)).ok_or_else(|| {
+                    format_err!("'{}' does not match '{}'", version, pattern.as_str())
+                })?;
+                let components = ordering
+                    .iter()
+                    .enumerate()
+                    .map(|(i, ordering)| match (captures.get(i + 1), ordering) {
+                        (None, _) => Ok(Component::Absent),
+                        (Some(group), ComponentOrdering::Numeric) => group
+                            .as_str()
+                            .parse()
+                            .map(Component::Numeric)
+                            .context(format!(
+                                "component {} of '{}' is not numeric",
+                                i + 1,
+                                version
+                            )),
+                        (Some(group), ComponentOrdering::Lexical) => {
+                            Ok(Component::Lexical(group.as_str().to_string()))
+                        }
+                    })
+                    .collect::<Fallible<_>>()?;
+                Key::Components(components)
+            }
+        };
+        Ok(VersionKey(key))
+    }
+
+    /// Check that `version` is valid in the scheme.
+    pub fn validate(&self, version: &ReleaseVersion) -> Fallible<()> {
+        match (self, version) {
+            (VersionScheme::Semver, ReleaseVersion::Semver(_)) => Ok(()),
+            _ => self.parse(&version.to_string()).map(|_| ()),
+        }
+    }
+
+    /// Compare versions `a` and `b`, falling back to lexical order unless
+    /// both are valid in the scheme.
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match (self.parse(a), self.parse(b)) {
+            (Ok(key_a), Ok(key_b)) => key_a.cmp(&key_b),
+            _ => a.cmp(b),
+        }
+    }
+}
+
+/// Key ordering versions of a scheme.
+///
+/// Keys are only comparable if parsed by the same scheme.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VersionKey(Key);
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Semver(semver::Version),
+    Pep440(Pep440Key),
+    Components(Vec<Component>),
+}
+
+/// Component of a `regex` version, absent ones ordered first.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Component {
+    Absent,
+    Numeric(u64),
+    Lexical(String),
+}
+
+lazy_static! {
+    static ref PEP440_PATTERN: Regex = Regex::new(
+        r"(?ix)^
+        v?
+        (?:(?P<epoch>\d+)!)?
+        (?P<release>\d+(?:\.\d+)*)
+        (?:[-_.]?(?P<pre_phase>alpha|a|beta|b|preview|pre|c|rc)[-_.]?(?P<pre>\d*))?
+        (?:-(?P<post_implicit>\d+)|[-_.]?(?:post|rev|r)[-_.]?(?P<post>\d*))?
+        (?:[-_.]?dev[-_.]?(?P<dev>\d*))?
+        $"
+    )
+    .expect("invalid PEP 440 pattern");
+}
+
+/// Fields of a PEP 440 version, in comparison order.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Pep440Key {
+    epoch: u64,
+    /// Release segments, without trailing zeros.
+    release: Vec<u64>,
+    /// Phase and number of the pre-release. Development releases without
+    /// pre-release nor post-release come before all pre-releases, and final
+    /// releases after them.
+    pre: (i8, u64),
+    post: Option<u64>,
+    /// Whether the version is not a development release, then its number.
+    dev: (bool, u64),
+}
+
+impl Pep440Key {
+    fn parse(version: &str) -> Fallible<Self> {
+        let captures = PEP440_PATTERN
+            .captures(version)
+            .ok_or_else(|| format_err!("unexpected format"))?;
+        let number = |name: &str| -> Fallible<Option<u64>> {
+            captures
+                .name(name)
+                .map(|m| match m.as_str() {
+                    "" => Ok(0),
+                    n => n.parse().context(format!("invalid {} number", name)),
+                })
+                .transpose()
+        };
+
+        let mut release = captures["release"]
+            .split('.')
+            .map(|segment| segment.parse().context("invalid release segment"))
+            .collect::<Fallible<Vec<u64>>>()?;
+        while release.len() > 1 && release.last() == Some(&0) {
+            release.pop();
+        }
+        let post = number("post_implicit")?.or(number("post")?);
+        let dev = number("dev")?;
+        let pre = match captures.name("pre_phase") {
+            Some(phase) => {
+                let phase = match phase.as_str().to_lowercase().as_str() {
+                    "a" | "alpha" => 0,
+                    "b" | "beta" => 1,
+                    _ => 2,
+                };
+                (phase, number("pre")?.unwrap_or_default())
+            }
+            None if post.is_none() && dev.is_some() => (-1, 0),
+            None => (3, 0),
+        };
+
+        Ok(Self {
+            epoch: number("epoch")?.unwrap_or_default(),
+            release,
+            pre,
+            post,
+            dev: (dev.is_none(), dev.unwrap_or_default()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheme(toml: &str) -> Fallible<VersionScheme> {
+        #[derive(Deserialize)]
+        struct Settings {
+            version_scheme: VersionScheme,
+        }
+        let settings: Settings = toml::from_str(toml)?;
+        Ok(settings.version_scheme)
+    }
+
+    fn assert_ordered(scheme: &VersionScheme, versions: &[&str]) {
+        for pair in versions.windows(2) {
+            assert_eq!(
+                scheme.compare(pair[0], pair[1]),
+                Ordering::Less,
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn configure_schemes() -> Fallible<()> {
+        assert_eq!(scheme(r#"version_scheme = "semver""#)?.name(), "semver");
+        assert_eq!(
+            scheme(r#"version_scheme = { kind = "pep440" }"#)?.name(),
+            "pep440"
+        );
+        assert_eq!(
+            scheme(r#"version_scheme = { kind = "regex", pattern = '(\d+)-(\w+)', ordering = ["numeric", "lexical"] }"#)?
+                .name(),
+            "regex"
+        );
+
+        for invalid in &[
+            r#"version_scheme = "calver""#,
+            r#"version_scheme = "regex""#,
+            r#"version_scheme = { kind = "pep440", pattern = '(\d+)' }"#,
+            r#"version_scheme = { kind = "regex", pattern = '\d+' }"#,
+            r#"version_scheme = { kind = "regex", pattern = '(\d+)', ordering = ["numeric", "numeric"] }"#,
+            r#"version_scheme = { kind = "regex", pattern = '(' }"#,
+        ] {
+            assert!(scheme(invalid).is_err(), "{}", invalid);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn order_semver_versions() {
+        let scheme = VersionScheme::default();
+        assert_ordered(&scheme, &["4.9.0", "4.10.0-rc.1", "4.10.0", "4.10.1"]);
+        assert!(scheme.parse("2024.1").is_err());
+    }
+
+    #[test]
+    fn order_pep440_versions() {
+        let scheme = VersionScheme::Pep440;
+        assert_ordered(
+            &scheme,
+            &[
+                "1.0.dev1",
+                "1.0a1",
+                "1.0b2.post1",
+                "1.0rc1",
+                "1.0",
+                "1.0.post1.dev2",
+                "1.0.post1",
+                "1.1",
+                "2024.1",
+                "1!0.1",
+            ],
+        );
+        assert_eq!(scheme.compare("1.0", "1.0.0"), Ordering::Equal);
+        assert_eq!(scheme.compare("1.0+amd64", "1.0"), Ordering::Equal);
+        assert!(scheme.parse("1.0-beta-x").is_err());
+    }
+
+    #[test]
+    fn order_regex_versions() -> Fallible<()> {
+        let scheme = VersionScheme::try_new_regex(
+            r"(\d+)\.(\d+)-(\w+)(?:\.(\d+))?",
+            vec![
+                ComponentOrdering::Numeric,
+                ComponentOrdering::Numeric,
+                ComponentOrdering::Lexical,
+                ComponentOrdering::Numeric,
+            ],
+        )?;
+        assert_ordered(
+            &scheme,
+            &[
+                "24.3-ga",
+                "24.3-update",
+                "24.3-update.2",
+                "24.3-update.10",
+                "24.10-ga+arm64",
+            ],
+        );
+        assert!(scheme.parse("24.3").is_err());
+        assert!(scheme.parse("24.3-update.x").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn release_version_build() -> Fallible<()> {
+        let mut semver: ReleaseVersion = "4.14.0".parse()?;
+        let mut other: ReleaseVersion = "2024.1".parse()?;
+        assert!(matches!(semver, ReleaseVersion::Semver(_)));
+        assert_eq!(other, ReleaseVersion::Other("2024.1".to_string()));
+
+        semver.set_build("amd64");
+        other.set_build_from(&semver);
+        assert_eq!(other.to_string(), "2024.1+amd64");
+        assert_eq!(other.build(), Some("amd64".to_string()));
+
+        other.remove_build("arm64");
+        assert_eq!(other.to_string(), "2024.1+amd64");
+        other.remove_build("amd64");
+        assert_eq!(other.to_string(), "2024.1");
+
+        semver.set_build_from(&other);
+        assert_eq!(semver.to_string(), "4.14.0");
+        assert!("".parse::<ReleaseVersion>().is_err());
+        assert_eq!(serde_json::to_string(&other)?, r#""2024.1""#);
+
+        Ok(())
+    }
+}
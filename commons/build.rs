@@ -0,0 +1,12 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("cincinnati_descriptor.bin"))
+        .compile(&["proto/cincinnati/v1/graph.proto"], &["proto"])?;
+
+    Ok(())
+}
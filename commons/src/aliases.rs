@@ -0,0 +1,282 @@
+//! Aliases of legacy request paths.
+//!
+//! Requests to an alias are either redirected to its target with a
+//! `308 Permanent Redirect`, which keeps the method and body, or transparently
+//! served as if they had been sent to the target. Paths are matched exactly,
+//! including the path prefix of the service, and query strings are kept.
+//!
+//! With trailing-slash tolerance, requests to a path ending with a slash are
+//! served as if sent without it, before aliases are matched.
+
+use crate::errors::{bail, ensure, Fallible};
+use actix_service::Service;
+use actix_web::body::EitherBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, uri, Uri};
+use actix_web::HttpResponse;
+use futures::future::{self, FutureExt, LocalBoxFuture, TryFutureExt};
+use prometheus::{IntCounterVec, Opts, Registry};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+/// Label of requests served despite a trailing slash.
+static TRAILING_SLASH_LABEL: &str = "trailing-slash";
+
+lazy_static! {
+    static ref ALIASED_REQUESTS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "http_aliased_requests_total",
+            "Total number of requests to path aliases, by alias"
+        ),
+        &["alias"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(ALIASED_REQUESTS.clone()))?;
+    Ok(())
+}
+
+/// Handling of the requests to an alias.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AliasMode {
+    /// Redirect clients to the target.
+    #[default]
+    Redirect,
+    /// Serve the target in place of the alias.
+    Rewrite,
+}
+
+/// Alias of a request path.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PathAlias {
+    /// Aliased path, e.g. `/api/upgrades_info/v1/graph`.
+    pub from: String,
+    /// Target path, e.g. `/v1/graph`.
+    pub to: String,
+    /// Handling of the requests to the alias.
+    #[serde(default)]
+    pub mode: AliasMode,
+}
+
+/// Aliases of the request paths of a service.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PathAliases {
+    /// Aliases, matched in order.
+    pub aliases: Vec<PathAlias>,
+    /// Whether requests to paths ending with a slash are served as if sent without it.
+    pub trailing_slash: bool,
+}
+
+impl PathAliases {
+    /// Check that aliases are absolute, distinct paths.
+    pub fn validate(&self) -> Fallible<()> {
+        let mut seen = HashSet::new();
+        for alias in &self.aliases {
+            for path in &[&alias.from, &alias.to] {
+                ensure!(
+                    path.starts_with('/') && !path.contains(&['?', '#'][..]),
+                    "path alias {:?} must be an absolute path, without query",
+                    path
+                );
+            }
+            ensure!(
+                alias.from != alias.to,
+                "path alias {:?} points to itself",
+                alias.from
+            );
+            if !seen.insert(alias.from.as_str()) {
+                bail!("duplicate path alias {:?}", alias.from);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether any path is aliased.
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty() && !self.trailing_slash
+    }
+
+    /// Return how to handle a request to `path`, and the path to handle it with, if aliased.
+    pub fn resolve(&self, path: &str) -> Option<(AliasMode, &str, &str)> {
+        let trimmed = match path.strip_suffix('/') {
+            Some(trimmed) if self.trailing_slash && !trimmed.is_empty() => trimmed,
+            _ => path,
+        };
+        match self.aliases.iter().find(|alias| alias.from == trimmed) {
+            Some(alias) => Some((alias.mode, alias.from.as_str(), alias.to.as_str())),
+            None if trimmed != path => Some((AliasMode::Rewrite, TRAILING_SLASH_LABEL, trimmed)),
+            None => None,
+        }
+    }
+
+    /// Redirect or rewrite requests to aliases, and forward the others to `srv`.
+    ///
+    /// This is meant to be used with `App::wrap_fn`, so that rewritten
+    /// requests are routed to their target.
+    pub fn enforce<S, B>(
+        &self,
+        mut req: ServiceRequest,
+        srv: &S,
+    ) -> LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, actix_web::Error>>
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+        S::Future: 'static,
+        B: 'static,
+    {
+        let (mode, target) = match self.resolve(req.path()) {
+            Some((mode, label, target)) => {
+                ALIASED_REQUESTS.with_label_values(&[label]).inc();
+                let target = match req.query_string() {
+                    "" => target.to_string(),
+                    query => format!("{}?{}", target, query),
+                };
+                (mode, target)
+            }
+            None => {
+                return srv
+                    .call(req)
+                    .map_ok(ServiceResponse::map_into_left_body)
+                    .boxed_local()
+            }
+        };
+
+        match mode {
+            AliasMode::Redirect => {
+                let response = HttpResponse::PermanentRedirect()
+                    .insert_header((header::LOCATION, target))
+                    .finish();
+                future::ok(req.into_response(response).map_into_right_body()).boxed_local()
+            }
+            AliasMode::Rewrite => {
+                let mut parts = req.head().uri.clone().into_parts();
+                parts.path_and_query = match uri::PathAndQuery::try_from(target.as_str()) {
+                    Ok(path_and_query) => Some(path_and_query),
+                    Err(e) => {
+                        return future::err(actix_web::error::ErrorBadRequest(e)).boxed_local()
+                    }
+                };
+                let uri = match Uri::from_parts(parts) {
+                    Ok(uri) => uri,
+                    Err(e) => {
+                        return future::err(actix_web::error::ErrorBadRequest(e)).boxed_local()
+                    }
+                };
+                req.match_info_mut().get_mut().update(&uri);
+                req.head_mut().uri = uri;
+                srv.call(req)
+                    .map_ok(ServiceResponse::map_into_left_body)
+                    .boxed_local()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    fn aliases() -> PathAliases {
+        PathAliases {
+            aliases: vec![
+                PathAlias {
+                    from: "/api/upgrades_info/v1/graph".to_string(),
+                    to: "/v1/graph".to_string(),
+                    mode: AliasMode::Redirect,
+                },
+                PathAlias {
+                    from: "/api/upgrades_info/graph".to_string(),
+                    to: "/v1/graph".to_string(),
+                    mode: AliasMode::Rewrite,
+                },
+            ],
+            trailing_slash: true,
+        }
+    }
+
+    #[test]
+    fn resolve_aliases() -> Fallible<()> {
+        let aliases = aliases();
+        aliases.validate()?;
+
+        assert_eq!(
+            aliases.resolve("/api/upgrades_info/v1/graph"),
+            Some((
+                AliasMode::Redirect,
+                "/api/upgrades_info/v1/graph",
+                "/v1/graph"
+            ))
+        );
+        assert_eq!(
+            aliases.resolve("/api/upgrades_info/graph/"),
+            Some((AliasMode::Rewrite, "/api/upgrades_info/graph", "/v1/graph"))
+        );
+        assert_eq!(
+            aliases.resolve("/v1/graph/"),
+            Some((AliasMode::Rewrite, TRAILING_SLASH_LABEL, "/v1/graph"))
+        );
+        assert_eq!(aliases.resolve("/v1/graph"), None);
+        assert_eq!(aliases.resolve("/"), None);
+
+        let mut invalid = aliases.clone();
+        invalid.aliases[1].from = "/api/upgrades_info/v1/graph".to_string();
+        assert!(invalid.validate().is_err());
+        let mut invalid = aliases;
+        invalid.aliases[0].to = "v1/graph".to_string();
+        assert!(invalid.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn enforce_aliases() -> Fallible<()> {
+        let rt = crate::testing::init_runtime()?;
+        let aliases = aliases();
+
+        rt.block_on(async {
+            let app = actix_web::test::init_service(
+                actix_web::App::new()
+                    .wrap_fn(move |req, srv| aliases.enforce(req, srv))
+                    .route(
+                        "/v1/graph",
+                        actix_web::web::get().to(|req: actix_web::HttpRequest| async move {
+                            HttpResponse::Ok().body(req.query_string().to_string())
+                        }),
+                    ),
+            )
+            .await;
+
+            let req = TestRequest::get()
+                .uri("/api/upgrades_info/v1/graph?channel=stable-4.14")
+                .to_request();
+            let resp = actix_web::test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+            assert_eq!(
+                resp.headers().get(header::LOCATION).unwrap(),
+                "/v1/graph?channel=stable-4.14"
+            );
+
+            for path in &["/api/upgrades_info/graph", "/v1/graph/"] {
+                let req = TestRequest::get()
+                    .uri(&format!("{}?channel=stable-4.14", path))
+                    .to_request();
+                let resp = actix_web::test::call_service(&app, req).await;
+                assert_eq!(resp.status(), StatusCode::OK, "{}", path);
+                let body = actix_web::test::read_body(resp).await;
+                assert_eq!(body, "channel=stable-4.14");
+            }
+
+            let req = TestRequest::get().uri("/graph").to_request();
+            let resp = actix_web::test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        });
+
+        Ok(())
+    }
+}
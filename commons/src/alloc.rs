@@ -0,0 +1,125 @@
+//! Counting of memory allocations.
+//!
+//! Binaries installing `CountingAllocator` as their global allocator can
+//! report the allocations made by a thread over a span of code, e.g. the run
+//! of a plugin. Counters are kept per thread, so that concurrent work on
+//! other threads is not accounted; work offloaded to other threads is not
+//! accounted either.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+thread_local! {
+    static COUNTERS: (Cell<u64>, Cell<u64>) = const { (Cell::new(0), Cell::new(0)) };
+}
+
+/// Whether `CountingAllocator` is the global allocator.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Global allocator counting the allocations of each thread, on top of the
+/// system allocator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn record(bytes: usize) {
+        // Only write the shared flag until set, as reads do not contend between threads.
+        if !INSTALLED.load(Ordering::Relaxed) {
+            INSTALLED.store(true, Ordering::Relaxed);
+        }
+        // Counters are unavailable while the thread is torn down.
+        let _ = COUNTERS.try_with(|(count, total)| {
+            count.set(count.get() + 1);
+            total.set(total.get() + bytes as u64);
+        });
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        Self::record(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        Self::record(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        Self::record(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// Allocations made by a thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Allocations {
+    /// Number of allocations, reallocations included.
+    pub count: u64,
+    /// Number of bytes requested by the allocations.
+    pub bytes: u64,
+}
+
+impl Allocations {
+    /// Return the allocations made by the current thread so far, if counted.
+    pub fn current() -> Option<Self> {
+        if !is_counting() {
+            return None;
+        }
+        COUNTERS
+            .try_with(|(count, bytes)| Self {
+                count: count.get(),
+                bytes: bytes.get(),
+            })
+            .ok()
+    }
+
+    /// Return the allocations made since `earlier`.
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            count: self.count.saturating_sub(earlier.count),
+            bytes: self.bytes.saturating_sub(earlier.bytes),
+        }
+    }
+}
+
+/// Whether allocations are counted, i.e. `CountingAllocator` is the global allocator.
+pub fn is_counting() -> bool {
+    INSTALLED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    #[test]
+    fn count_allocations() {
+        let _warmup = vec![0u8; 1];
+        assert!(is_counting());
+
+        let start = Allocations::current().unwrap();
+        let mut values = Vec::with_capacity(1024);
+        values.extend(0..1024u64);
+        let allocated = Allocations::current().unwrap().since(&start);
+        assert_eq!(values.len(), 1024);
+        assert!(allocated.count >= 1, "{:?}", allocated);
+        assert!(allocated.bytes >= 8 * 1024, "{:?}", allocated);
+
+        // Allocations of other threads are not accounted.
+        let start = Allocations::current().unwrap();
+        std::thread::spawn(|| vec![0u8; 1 << 20].len())
+            .join()
+            .unwrap();
+        let allocated = Allocations::current().unwrap().since(&start);
+        assert!(allocated.bytes < 1 << 20, "{:?}", allocated);
+    }
+}
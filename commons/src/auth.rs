@@ -0,0 +1,65 @@
+//! Bearer tokens guarding privileged endpoints.
+
+use crate::errors::{ensure, Context, Fallible};
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use std::path::Path;
+
+/// Bearer token required by privileged endpoints, such as debug or admin ones.
+#[derive(Clone)]
+pub struct BearerToken(String);
+
+impl std::fmt::Debug for BearerToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BearerToken(<redacted>)")
+    }
+}
+
+impl BearerToken {
+    /// Use `token` as the token.
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(token.into())
+    }
+
+    /// Read the token from the file at `path`.
+    pub fn read(path: &Path) -> Fallible<Self> {
+        let token = std::fs::read_to_string(path)
+            .context(format!("reading bearer token {}", path.display()))?;
+        let token = token.trim();
+        ensure!(
+            !token.is_empty(),
+            "empty bearer token in {}",
+            path.display()
+        );
+        Ok(Self(token.to_string()))
+    }
+
+    /// Whether `req` carries this token in its `Authorization` header.
+    pub fn authorizes(&self, req: &HttpRequest) -> bool {
+        let presented = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+
+        // Compare in constant time, to not leak the token through timings.
+        presented.len() == self.0.len()
+            && presented
+                .bytes()
+                .zip(self.0.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    /// Return an error response unless `req` carries this token.
+    pub fn check(&self, req: &HttpRequest) -> Result<(), HttpResponse> {
+        if self.authorizes(req) {
+            Ok(())
+        } else {
+            Err(HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .finish())
+        }
+    }
+}
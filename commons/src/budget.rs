@@ -0,0 +1,461 @@
+//! Budgets of outbound requests per remote host.
+//!
+//! Upstreams such as registries, GitHub or Prometheus rate-limit their API
+//! per account, and many deployments may share one. Requests are acquired
+//! from the budget of the remote host before being sent, so that they are
+//! counted per host and per window. The window restarts with each scrape,
+//! and lasts at most the configured duration. Once the budget of a host is
+//! consumed, further requests either fail, aborting the scrape, or are
+//! delayed until the next window, unless it starts past the deadline of the
+//! scrape.
+//!
+//! Each graph has a `RequestBudget` of its own. Scrapes run the plugin chain
+//! within `RequestBudget::scope`, from which `acquire` draws; clients with a
+//! budget of their own, such as the Prometheus one, acquire from it directly.
+//! Requests outside of any budget are only counted.
+
+use crate::errors::{bail, ensure, Fallible};
+use prometheus::{IntCounterVec, IntGaugeVec, Opts, Registry};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Default window over which requests are counted.
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(300);
+
+lazy_static! {
+    static ref REQUESTS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "upstream_requests_total",
+            "Total number of outbound requests, by remote host"
+        ),
+        &["host"]
+    )
+    .unwrap();
+    static ref BUDGET_CONSUMED: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "upstream_request_budget_consumed",
+            "Number of outbound requests in the current budget window, by remote host"
+        ),
+        &["host"]
+    )
+    .unwrap();
+    static ref BUDGET_LIMIT: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "upstream_request_budget_limit",
+            "Maximum number of outbound requests per budget window, by remote host"
+        ),
+        &["host"]
+    )
+    .unwrap();
+    static ref BUDGET_EXCEEDED: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "upstream_request_budget_exceeded_total",
+            "Total number of outbound requests aborted or delayed for exceeding their budget, by remote host"
+        ),
+        &["host"]
+    )
+    .unwrap();
+}
+
+tokio::task_local! {
+    /// Budget and deadline of the scrape running in the current task.
+    static SCOPE: Scope;
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(REQUESTS.clone()))?;
+    registry.register(Box::new(BUDGET_CONSUMED.clone()))?;
+    registry.register(Box::new(BUDGET_LIMIT.clone()))?;
+    registry.register(Box::new(BUDGET_EXCEEDED.clone()))?;
+    Ok(())
+}
+
+/// Handling of the requests exceeding their budget.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum BudgetAction {
+    /// Fail the request, which aborts the scrape.
+    #[default]
+    Abort,
+    /// Delay the request until the next window.
+    Delay,
+}
+
+/// Budgets of outbound requests, by remote host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestBudgets {
+    /// Window over which requests are counted.
+    pub window: Duration,
+    /// Handling of the requests exceeding their budget.
+    pub action: BudgetAction,
+    /// Maximum number of requests per window, by remote host.
+    pub hosts: BTreeMap<String, u64>,
+    /// Maximum number of requests per window to other hosts, unlimited if unset.
+    pub default: Option<u64>,
+}
+
+impl Default for RequestBudgets {
+    fn default() -> Self {
+        Self {
+            window: DEFAULT_WINDOW,
+            action: Default::default(),
+            hosts: Default::default(),
+            default: None,
+        }
+    }
+}
+
+impl RequestBudgets {
+    /// Check that budgets are positive.
+    pub fn validate(&self) -> Fallible<()> {
+        ensure!(
+            !self.window.is_zero(),
+            "request budget window must be greater than 0"
+        );
+        for (host, budget) in &self.hosts {
+            ensure!(
+                !host.is_empty(),
+                "request budgets must not have empty hosts"
+            );
+            ensure!(
+                *budget > 0,
+                "request budget of '{}' must be greater than 0",
+                host
+            );
+        }
+        ensure!(
+            self.default != Some(0),
+            "default request budget must be greater than 0"
+        );
+        Ok(())
+    }
+
+    /// Maximum number of requests per window to `host`, if limited.
+    fn limit(&self, host: &str) -> Option<u64> {
+        self.hosts.get(host).copied().or(self.default)
+    }
+}
+
+/// Requests counted in the current window.
+#[derive(Debug)]
+struct Accounting {
+    budgets: RequestBudgets,
+    window_start: Instant,
+    consumed: HashMap<String, u64>,
+}
+
+impl Accounting {
+    fn new(budgets: RequestBudgets) -> Self {
+        Self {
+            budgets,
+            window_start: Instant::now(),
+            consumed: HashMap::new(),
+        }
+    }
+
+    /// Start a new window at `now`.
+    fn restart(&mut self, now: Instant) {
+        self.window_start = now;
+        self.consumed.clear();
+        BUDGET_CONSUMED.reset();
+    }
+
+    /// Count a request to `host` at `now` if its budget allows it, or return
+    /// the time until the next window.
+    fn try_acquire(&mut self, host: &str, now: Instant) -> Result<u64, Duration> {
+        if now.duration_since(self.window_start) >= self.budgets.window {
+            self.restart(now);
+        }
+
+        let consumed = self.consumed.entry(host.to_string()).or_insert(0);
+        if let Some(limit) = self.budgets.limit(host) {
+            if *consumed >= limit {
+                return Err(self.budgets.window - now.duration_since(self.window_start));
+            }
+        }
+        *consumed += 1;
+        Ok(*consumed)
+    }
+}
+
+/// Budget of the outbound requests of a graph, shared by its clones.
+#[derive(Clone, Debug)]
+pub struct RequestBudget {
+    accounting: Arc<Mutex<Accounting>>,
+}
+
+impl Default for RequestBudget {
+    /// A budget without limits, which only counts requests.
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl RequestBudget {
+    /// Create a budget enforcing `budgets`.
+    pub fn new(budgets: RequestBudgets) -> Self {
+        for (host, budget) in &budgets.hosts {
+            BUDGET_LIMIT
+                .with_label_values(&[host])
+                .set(i64::try_from(*budget).unwrap_or(i64::MAX));
+        }
+        Self {
+            accounting: Arc::new(Mutex::new(Accounting::new(budgets))),
+        }
+    }
+
+    /// Start a new window, at the start of a scrape.
+    pub fn restart(&self) {
+        self.accounting
+            .lock()
+            .expect("request budget lock poisoned")
+            .restart(Instant::now());
+    }
+
+    /// Run `future` with the requests acquired by `acquire` drawn from this
+    /// budget, delaying them at most until `deadline`, if set.
+    ///
+    /// Tasks spawned by `future` run outside of the budget, unless spawned
+    /// within `in_current_scope`.
+    pub async fn scope<F: Future>(&self, deadline: Option<Instant>, future: F) -> F::Output {
+        let scope = Scope {
+            budget: self.clone(),
+            deadline,
+        };
+        SCOPE.scope(scope, future).await
+    }
+
+    /// Acquire a request to `host`, before sending it.
+    ///
+    /// Once the budget is consumed, this fails or waits for the next window,
+    /// depending on the configured action. Requests which would wait past
+    /// `deadline` fail right away.
+    pub async fn acquire(&self, host: &str, deadline: Option<Instant>) -> Fallible<()> {
+        while let Some(wait) = self.try_acquire(host, deadline)? {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    /// Acquire a request to `host` like `acquire`, blocking the current thread while delayed.
+    pub fn acquire_blocking(&self, host: &str, deadline: Option<Instant>) -> Fallible<()> {
+        while let Some(wait) = self.try_acquire(host, deadline)? {
+            std::thread::sleep(wait);
+        }
+        Ok(())
+    }
+
+    /// Count a request to `host`, or return how long to delay it.
+    fn try_acquire(&self, host: &str, deadline: Option<Instant>) -> Fallible<Option<Duration>> {
+        let now = Instant::now();
+        let (acquired, action, window) = {
+            let mut accounting = self
+                .accounting
+                .lock()
+                .expect("request budget lock poisoned");
+            (
+                accounting.try_acquire(host, now),
+                accounting.budgets.action,
+                accounting.budgets.window,
+            )
+        };
+
+        let wait = match acquired {
+            Ok(consumed) => {
+                REQUESTS.with_label_values(&[host]).inc();
+                BUDGET_CONSUMED
+                    .with_label_values(&[host])
+                    .set(i64::try_from(consumed).unwrap_or(i64::MAX));
+                return Ok(None);
+            }
+            Err(wait) => wait,
+        };
+
+        BUDGET_EXCEEDED.with_label_values(&[host]).inc();
+        match action {
+            BudgetAction::Abort => bail!(
+                "request budget of {} exhausted for the current {}s window",
+                host,
+                window.as_secs()
+            ),
+            BudgetAction::Delay => {
+                if let Some(deadline) = deadline {
+                    ensure!(
+                        now + wait < deadline,
+                        "request budget of {} exhausted until past the scrape deadline",
+                        host
+                    );
+                }
+                log::warn!(
+                    "request budget of {} exhausted, delaying request by {}s",
+                    host,
+                    wait.as_secs()
+                );
+                Ok(Some(wait))
+            }
+        }
+    }
+}
+
+/// Budget and deadline of a scrape.
+#[derive(Clone, Debug)]
+struct Scope {
+    budget: RequestBudget,
+    deadline: Option<Instant>,
+}
+
+/// Run `future` within the budget of the current scope, if any, e.g. for tasks it spawns.
+pub fn in_current_scope<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let scope = SCOPE.try_with(Scope::clone).ok();
+    async move {
+        match scope {
+            Some(scope) => SCOPE.scope(scope, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// Acquire a request to `host` from the budget of the current scope, before sending it.
+///
+/// Outside of any scope, the request is only counted.
+pub async fn acquire(host: &str) -> Fallible<()> {
+    match SCOPE.try_with(Scope::clone) {
+        Ok(scope) => scope.budget.acquire(host, scope.deadline).await,
+        Err(_) => {
+            REQUESTS.with_label_values(&[host]).inc();
+            Ok(())
+        }
+    }
+}
+
+/// Acquire a request to the host of `url` from its budget, before sending it.
+///
+/// Requests to invalid URLs are not counted, as they fail on their own.
+pub async fn acquire_url(url: &str) -> Fallible<()> {
+    let url = match url::Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return Ok(()),
+    };
+    match url.host_str() {
+        Some(host) => acquire(host).await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_requests_per_window() {
+        let mut accounting = Accounting::new(RequestBudgets {
+            window: Duration::from_secs(60),
+            hosts: vec![("quay.io".to_string(), 2)].into_iter().collect(),
+            ..Default::default()
+        });
+        let start = accounting.window_start;
+
+        assert_eq!(accounting.try_acquire("quay.io", start), Ok(1));
+        assert_eq!(accounting.try_acquire("quay.io", start), Ok(2));
+        assert_eq!(
+            accounting.try_acquire("quay.io", start + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+        // Other hosts are not limited.
+        for _ in 0..10 {
+            assert!(accounting.try_acquire("api.github.com", start).is_ok());
+        }
+
+        let next = start + Duration::from_secs(60);
+        assert_eq!(accounting.try_acquire("quay.io", next), Ok(1));
+        assert_eq!(accounting.try_acquire("api.github.com", next), Ok(1));
+    }
+
+    #[test]
+    fn restart_window_per_scrape() {
+        let mut accounting = Accounting::new(RequestBudgets {
+            default: Some(1),
+            ..Default::default()
+        });
+        let start = accounting.window_start;
+
+        assert!(accounting.try_acquire("quay.io", start).is_ok());
+        assert!(accounting.try_acquire("quay.io", start).is_err());
+        accounting.restart(start + Duration::from_secs(1));
+        assert_eq!(
+            accounting.try_acquire("quay.io", start + Duration::from_secs(1)),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn scoped_budgets() -> Fallible<()> {
+        let rt = crate::testing::init_runtime()?;
+        let budget = RequestBudget::new(RequestBudgets {
+            window: Duration::from_secs(3600),
+            action: BudgetAction::Delay,
+            default: Some(1),
+            ..Default::default()
+        });
+
+        // Requests outside of the scope are not drawn from the budget.
+        rt.block_on(acquire("quay.io"))?;
+        rt.block_on(budget.scope(None, acquire("quay.io")))?;
+
+        // Delays past the deadline of the scrape fail right away.
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let start = Instant::now();
+        assert!(rt
+            .block_on(budget.scope(Some(deadline), acquire("quay.io")))
+            .is_err());
+        assert!(start.elapsed() < Duration::from_secs(60));
+
+        // Spawned tasks draw from the budget of the scope they are spawned in.
+        budget.restart();
+        let spawned = budget.scope(Some(deadline), async {
+            tokio::spawn(in_current_scope(acquire("quay.io"))).await
+        });
+        rt.block_on(spawned)??;
+        assert!(budget.acquire_blocking("quay.io", Some(deadline)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn default_budget() {
+        let mut accounting = Accounting::new(RequestBudgets {
+            default: Some(1),
+            ..Default::default()
+        });
+        let start = accounting.window_start;
+
+        assert!(accounting.try_acquire("quay.io", start).is_ok());
+        assert!(accounting.try_acquire("quay.io", start).is_err());
+        assert!(accounting.try_acquire("api.github.com", start).is_ok());
+    }
+
+    #[test]
+    fn validate_budgets() {
+        assert!(RequestBudgets::default().validate().is_ok());
+        for invalid in &[
+            RequestBudgets {
+                window: Duration::from_secs(0),
+                ..Default::default()
+            },
+            RequestBudgets {
+                hosts: vec![("quay.io".to_string(), 0)].into_iter().collect(),
+                ..Default::default()
+            },
+            RequestBudgets {
+                default: Some(0),
+                ..Default::default()
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{:?}", invalid);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Deadlines of requests.
+//!
+//! The deadline of a request travels along the plugin chain in the
+//! `io.openshift.upgrades.graph.deadline` parameter, as milliseconds since
+//! the UNIX epoch, so that it also reaches external plugins and the services
+//! plugins call. Each stage of the request runs within the time left until
+//! the deadline, and fails with `GraphError::DeadlineExceeded` naming the
+//! stage once it is exceeded.
+
+use crate::{GraphError, GRAPH_DEADLINE_PARAM_KEY};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Serialize `deadline` as a parameter value.
+pub fn to_param(deadline: SystemTime) -> String {
+    deadline
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        .to_string()
+}
+
+/// Return the deadline set in the parameters, if any.
+///
+/// Deadlines are rounded down to the millisecond, thus never exceed the
+/// deadline they were set from.
+pub fn from_params(parameters: &HashMap<String, String>) -> Option<SystemTime> {
+    let millis = parameters.get(GRAPH_DEADLINE_PARAM_KEY)?.parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_millis(millis))
+}
+
+/// Return the time left until `deadline`, zero once it is exceeded.
+pub fn remaining(deadline: SystemTime) -> Duration {
+    deadline
+        .duration_since(SystemTime::now())
+        .unwrap_or_default()
+}
+
+/// Run `future` within the time left until `deadline`, if any.
+///
+/// Stages starting past the deadline are not run.
+pub async fn within<F, T, E>(deadline: Option<SystemTime>, stage: &str, future: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+    E: From<GraphError>,
+{
+    let deadline = match deadline {
+        Some(deadline) => deadline,
+        None => return future.await,
+    };
+
+    let remaining = remaining(deadline);
+    if remaining.is_zero() {
+        return Err(GraphError::DeadlineExceeded(stage.to_string()).into());
+    }
+    match tokio::time::timeout(remaining, future).await {
+        Ok(result) => result,
+        Err(_) => Err(GraphError::DeadlineExceeded(stage.to_string()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::init_runtime;
+
+    #[test]
+    fn deadline_params() {
+        let deadline = UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+        let parameters: HashMap<String, String> =
+            vec![(GRAPH_DEADLINE_PARAM_KEY.to_string(), to_param(deadline))]
+                .into_iter()
+                .collect();
+        assert_eq!(
+            from_params(&parameters),
+            Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123))
+        );
+        assert_eq!(from_params(&HashMap::new()), None);
+        assert_eq!(remaining(deadline), Duration::ZERO);
+    }
+
+    #[test]
+    fn run_within_deadline() {
+        let rt = init_runtime().unwrap();
+        let soon = Some(SystemTime::now() + Duration::from_millis(50));
+
+        let done: Result<u8, GraphError> = rt.block_on(within(soon, "fast", async { Ok(1) }));
+        assert_eq!(done, Ok(1));
+
+        let hanging: Result<u8, GraphError> = rt.block_on(within(
+            soon,
+            "slow",
+            futures::future::pending::<Result<u8, GraphError>>(),
+        ));
+        assert_eq!(
+            hanging,
+            Err(GraphError::DeadlineExceeded("slow".to_string()))
+        );
+
+        // Stages past the deadline do not run.
+        let ran = std::cell::Cell::new(false);
+        let past: Result<u8, GraphError> =
+            rt.block_on(within(Some(SystemTime::now()), "late", async {
+                ran.set(true);
+                Ok(2)
+            }));
+        assert_eq!(past, Err(GraphError::DeadlineExceeded("late".to_string())));
+        assert!(!ran.get());
+    }
+}
@@ -0,0 +1,175 @@
+//! Limits on downloads from upstreams.
+//!
+//! Response bodies and decompressed streams are bounded, so that an
+//! unexpectedly huge or malicious download fails fast instead of being
+//! buffered into memory or onto disk.
+
+use crate::prelude_errors::*;
+use std::io::Read;
+use std::time::Instant;
+
+/// Read the body of `response`, failing as soon as it exceeds `max_bytes`, if set.
+pub async fn read_body(
+    mut response: reqwest::Response,
+    max_bytes: Option<u64>,
+) -> Fallible<Vec<u8>> {
+    let max_bytes = match max_bytes {
+        Some(max_bytes) => max_bytes,
+        None => return Ok(response.bytes().await?.to_vec()),
+    };
+    if let Some(length) = response.content_length() {
+        ensure!(
+            length <= max_bytes,
+            "response of {} bytes exceeds the limit of {} bytes",
+            length,
+            max_bytes
+        );
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        ensure!(
+            (body.len() + chunk.len()) as u64 <= max_bytes,
+            "response exceeds the limit of {} bytes",
+            max_bytes
+        );
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Reader failing once more than a maximum number of bytes are read.
+#[derive(Debug)]
+pub struct LimitedReader<R> {
+    inner: R,
+    max_bytes: u64,
+    read: u64,
+}
+
+impl<R: Read> LimitedReader<R> {
+    /// Limit `inner` to `max_bytes`.
+    pub fn new(inner: R, max_bytes: u64) -> Self {
+        Self {
+            inner,
+            max_bytes,
+            read: 0,
+        }
+    }
+
+    /// Whether reading failed because the limit was exceeded.
+    pub fn is_exceeded(&self) -> bool {
+        self.read > self.max_bytes
+    }
+
+    /// Fail if reading stopped because the limit was exceeded.
+    pub fn check(&self) -> Fallible<()> {
+        ensure!(
+            !self.is_exceeded(),
+            "content exceeds the limit of {} bytes",
+            self.max_bytes
+        );
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.is_exceeded() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("content exceeds the limit of {} bytes", self.max_bytes),
+            ));
+        }
+        // Read one byte past the limit, to tell content of exactly `max_bytes` apart.
+        let allowed = (self.max_bytes - self.read)
+            .saturating_add(1)
+            .min(buf.len() as u64) as usize;
+        let read = self.inner.read(&mut buf[..allowed])?;
+        self.read += read as u64;
+        if self.is_exceeded() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("content exceeds the limit of {} bytes", self.max_bytes),
+            ));
+        }
+        Ok(read)
+    }
+}
+
+/// Reader failing once its deadline, if any, is exceeded.
+#[derive(Debug)]
+pub struct DeadlineReader<R> {
+    inner: R,
+    deadline: Option<Instant>,
+}
+
+impl<R: Read> DeadlineReader<R> {
+    /// Read `inner` until `deadline`, if set.
+    pub fn new(inner: R, deadline: Option<Instant>) -> Self {
+        Self { inner, deadline }
+    }
+}
+
+impl<R: Read> Read for DeadlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self
+            .deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "read deadline exceeded",
+            ));
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_reads() {
+        let mut content = String::new();
+        let mut reader = LimitedReader::new("0123456789".as_bytes(), 10);
+        reader.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "0123456789");
+        assert!(reader.check().is_ok());
+
+        let mut reader = LimitedReader::new("0123456789".as_bytes(), 9);
+        assert!(reader.read_to_string(&mut String::new()).is_err());
+        assert!(reader.is_exceeded());
+        assert!(reader.check().is_err());
+
+        let mut reader = DeadlineReader::new("0123456789".as_bytes(), None);
+        assert!(reader.read_to_string(&mut String::new()).is_ok());
+        let mut reader = DeadlineReader::new("0123456789".as_bytes(), Some(Instant::now()));
+        assert!(reader.read_to_string(&mut String::new()).is_err());
+    }
+
+    #[test]
+    fn limit_response_bodies() -> Fallible<()> {
+        let runtime = crate::testing::init_runtime()?;
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/blob")
+            .with_status(200)
+            .with_body("0123456789")
+            .expect_at_least(1)
+            .create();
+        let url = format!("{}/blob", server.url());
+
+        let read = |max_bytes| {
+            runtime.block_on(async {
+                let response = reqwest::get(&url).await?;
+                read_body(response, max_bytes).await
+            })
+        };
+        assert_eq!(read(None)?, b"0123456789");
+        assert_eq!(read(Some(10))?, b"0123456789");
+        assert!(read(Some(9)).is_err());
+
+        Ok(())
+    }
+}
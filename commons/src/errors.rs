@@ -39,7 +39,172 @@ pub fn register_metrics(registry: &Registry) -> Fallible<()> {
     Ok(())
 }
 
-#[derive(Debug, Error, Eq, PartialEq)]
+/// Class of errors, by the part of the services they originate from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// Invalid or unreadable configuration.
+    Config,
+    /// Failure of an upstream service, e.g. a Cincinnati graph endpoint.
+    Upstream,
+    /// Failure while scraping releases or graph-data.
+    Scrape,
+    /// Failure while running plugins.
+    Plugin,
+    /// Invalid client request.
+    ClientRequest,
+    /// Saturation of the service.
+    Service,
+}
+
+impl ErrorClass {
+    /// Return the stable identifier of the class.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorClass::Config => "config",
+            ErrorClass::Upstream => "upstream",
+            ErrorClass::Scrape => "scrape",
+            ErrorClass::Plugin => "plugin",
+            ErrorClass::ClientRequest => "client_request",
+            ErrorClass::Service => "service",
+        }
+    }
+}
+
+/// Configuration error.
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+pub enum ConfigError {
+    /// Configuration with invalid values.
+    #[error("invalid configuration: {}", _0)]
+    Invalid(String),
+
+    /// Configuration which cannot be read or parsed.
+    #[error("unreadable configuration: {}", _0)]
+    Unreadable(String),
+}
+
+impl ConfigError {
+    /// Classify an error validating the configuration.
+    pub fn invalid(e: Error) -> Error {
+        classify_as(e, ConfigError::Invalid)
+    }
+
+    /// Classify an error reading or parsing the configuration.
+    pub fn unreadable(e: Error) -> Error {
+        classify_as(e, ConfigError::Unreadable)
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ConfigError::Invalid(_) => "invalid_config",
+            ConfigError::Unreadable(_) => "unreadable_config",
+        }
+    }
+}
+
+/// Error of an upstream service.
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+pub enum UpstreamError {
+    /// Upstream service which cannot be reached.
+    #[error("upstream unreachable: {}", _0)]
+    Unreachable(String),
+
+    /// Upstream response which cannot be used.
+    #[error("invalid upstream response: {}", _0)]
+    InvalidResponse(String),
+}
+
+impl UpstreamError {
+    /// Classify an error reaching an upstream service.
+    pub fn unreachable(e: Error) -> Error {
+        classify_as(e, UpstreamError::Unreachable)
+    }
+
+    /// Classify an unusable response of an upstream service.
+    pub fn invalid_response(e: Error) -> Error {
+        classify_as(e, UpstreamError::InvalidResponse)
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            UpstreamError::Unreachable(_) => "upstream_unreachable",
+            UpstreamError::InvalidResponse(_) => "invalid_upstream_response",
+        }
+    }
+
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            UpstreamError::Unreachable(_) => http::StatusCode::SERVICE_UNAVAILABLE,
+            UpstreamError::InvalidResponse(_) => http::StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+/// Error while scraping the sources of the graph.
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+pub enum ScrapeError {
+    /// Failure while scraping a release registry.
+    #[error("failed to scrape registry: {}", _0)]
+    Registry(String),
+
+    /// Failure while scraping graph-data.
+    #[error("failed to scrape graph-data: {}", _0)]
+    GraphData(String),
+}
+
+impl ScrapeError {
+    /// Classify an error scraping a release registry.
+    pub fn registry(e: Error) -> Error {
+        classify_as(e, ScrapeError::Registry)
+    }
+
+    /// Classify an error scraping graph-data.
+    pub fn graph_data(e: Error) -> Error {
+        classify_as(e, ScrapeError::GraphData)
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ScrapeError::Registry(_) => "registry_scrape_failed",
+            ScrapeError::GraphData(_) => "graph_data_scrape_failed",
+        }
+    }
+}
+
+/// Failure of a plugin.
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+#[error("plugin '{}' failed: {}", plugin, reason)]
+pub struct PluginFailure {
+    /// Name of the failing plugin.
+    pub plugin: String,
+    /// Reason of the failure.
+    pub reason: String,
+}
+
+impl PluginFailure {
+    /// Attribute an error to the plugin which failed with it.
+    pub fn attribute(plugin: &str, e: Error) -> Error {
+        classify_as(e, |reason| PluginFailure {
+            plugin: plugin.to_string(),
+            reason,
+        })
+    }
+}
+
+/// Replace `e` by the typed error built from its whole chain of contexts,
+/// unless it is already classified by a graph error or a typed error.
+fn classify_as<T, F>(e: Error, typed: F) -> Error
+where
+    T: std::error::Error + Send + Sync + 'static,
+    F: FnOnce(String) -> T,
+{
+    if GraphError::classify(&e).is_some() {
+        return e;
+    }
+    Error::new(typed(format!("{:#}", e)))
+}
+
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
 /// Error that can be returned by graph endpoint.
 pub enum GraphError {
     /// Failed to deserialize JSON.
@@ -85,6 +250,70 @@ pub enum GraphError {
     /// Resource does not exist
     #[error("does not exist: {}", _0)]
     DoesNotExist(String),
+
+    /// Request URI exceeding the size limit
+    #[error("request URI longer than {} bytes", _0)]
+    UriTooLong(usize),
+
+    /// Request with too many query parameters
+    #[error("more than {} query parameters", _0)]
+    TooManyParams(usize),
+
+    /// Request headers exceeding the size limit
+    #[error("request headers larger than {} bytes", _0)]
+    HeadersTooLarge(usize),
+
+    /// Request to an endpoint with too many requests in flight
+    #[error("more than {} requests in flight", _0)]
+    Overloaded(usize),
+
+    /// Request exceeding the quota of the given scope
+    #[error("request quota of {} exceeded", _0)]
+    QuotaExceeded(String),
+
+    /// Request exceeding its deadline, in the given stage
+    #[error("request deadline exceeded in stage '{}'", _0)]
+    DeadlineExceeded(String),
+
+    /// Configuration error.
+    #[error(transparent)]
+    Config(ConfigError),
+
+    /// Error of an upstream service.
+    #[error(transparent)]
+    Upstream(UpstreamError),
+
+    /// Error while scraping the sources of the graph.
+    #[error(transparent)]
+    Scrape(ScrapeError),
+
+    /// Failure of a plugin.
+    #[error(transparent)]
+    Plugin(PluginFailure),
+}
+
+impl From<ConfigError> for GraphError {
+    fn from(e: ConfigError) -> Self {
+        GraphError::Config(e)
+    }
+}
+
+impl From<UpstreamError> for GraphError {
+    fn from(e: UpstreamError) -> Self {
+        GraphError::Upstream(e)
+    }
+}
+
+impl From<ScrapeError> for GraphError {
+    fn from(e: ScrapeError) -> Self {
+        GraphError::Scrape(e)
+    }
+}
+
+impl From<PluginFailure> for GraphError {
+    fn from(e: PluginFailure) -> Self {
+        GraphError::Plugin(e)
+    }
 }
 
 impl actix_web::error::ResponseError for GraphError {
@@ -98,10 +327,20 @@ impl actix_web::error::ResponseError for GraphError {
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct ErrorMessage {
-    kind: String,
-    value: String,
+/// Body of error responses of graph endpoints.
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+#[schemars(rename = "GraphError")]
+pub struct ErrorMessage {
+    /// Machine-readable error kind, e.g. "missing_params".
+    pub kind: String,
+    /// Stable error code, made of the error class and kind, e.g. "client_request.missing_params".
+    #[serde(default)]
+    pub code: String,
+    /// Human-readable error message.
+    pub value: String,
+    /// Stage of the request which failed, for errors reporting one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
 }
 
 impl GraphError {
@@ -110,11 +349,78 @@ impl GraphError {
         let code = self.status_code();
         let json_body = web::Json(ErrorMessage {
             kind: self.kind(),
+            code: self.code(),
             value: self.value(),
+            stage: self.stage(),
         });
         HttpResponse::build(code).json(json_body)
     }
 
+    /// Map any error to a graph error.
+    ///
+    /// Graph errors and typed errors are looked up through the whole chain of
+    /// contexts, so that they are not lost when errors are annotated on
+    /// their way up. Other errors are reported as plugin failures.
+    pub fn from_error(e: Error) -> Self {
+        let e = match e.downcast::<GraphError>() {
+            Ok(graph_error) => return graph_error,
+            Err(e) => e,
+        };
+        Self::classify(&e).unwrap_or_else(|| GraphError::FailedPluginExecution(e.to_string()))
+    }
+
+    /// Look up the graph error or typed error in the chain of `e`, if any.
+    fn classify(e: &Error) -> Option<Self> {
+        for cause in e.chain() {
+            if let Some(graph_error) = cause.downcast_ref::<GraphError>() {
+                return Some(graph_error.clone());
+            }
+            if let Some(e) = cause.downcast_ref::<ConfigError>() {
+                return Some(e.clone().into());
+            }
+            if let Some(e) = cause.downcast_ref::<UpstreamError>() {
+                return Some(e.clone().into());
+            }
+            if let Some(e) = cause.downcast_ref::<ScrapeError>() {
+                return Some(e.clone().into());
+            }
+            if let Some(e) = cause.downcast_ref::<PluginFailure>() {
+                return Some(e.clone().into());
+            }
+        }
+        None
+    }
+
+    /// Return the class of the error.
+    pub fn class(&self) -> ErrorClass {
+        match *self {
+            GraphError::InvalidContentType
+            | GraphError::MissingParams(_)
+            | GraphError::InvalidParams(_)
+            | GraphError::DoesNotExist(_)
+            | GraphError::UriTooLong(_)
+            | GraphError::TooManyParams(_)
+            | GraphError::HeadersTooLarge(_)
+            | GraphError::QuotaExceeded(_) => ErrorClass::ClientRequest,
+            GraphError::FailedJsonIn(_)
+            | GraphError::FailedUpstreamFetch(_)
+            | GraphError::FailedUpstreamRequest(_)
+            | GraphError::Upstream(_) => ErrorClass::Upstream,
+            GraphError::FailedJsonOut(_)
+            | GraphError::FailedPluginExecution(_)
+            | GraphError::ArchVersionError(_)
+            | GraphError::Plugin(_) => ErrorClass::Plugin,
+            GraphError::FileOpenError(_) | GraphError::Scrape(_) => ErrorClass::Scrape,
+            GraphError::Config(_) => ErrorClass::Config,
+            GraphError::Overloaded(_) | GraphError::DeadlineExceeded(_) => ErrorClass::Service,
+        }
+    }
+
+    /// Return the stable code for the error, made of its class and kind.
+    pub fn code(&self) -> String {
+        format!("{}.{}", self.class().as_str(), self.kind())
+    }
+
     /// Return the HTTP status code for the error.
     pub fn status_code(&self) -> http::StatusCode {
         match *self {
@@ -129,6 +435,16 @@ impl GraphError {
             GraphError::ArchVersionError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
             GraphError::FileOpenError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
             GraphError::DoesNotExist(_) => http::StatusCode::NOT_FOUND,
+            GraphError::UriTooLong(_) => http::StatusCode::URI_TOO_LONG,
+            GraphError::TooManyParams(_) => http::StatusCode::BAD_REQUEST,
+            GraphError::HeadersTooLarge(_) => http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            GraphError::Overloaded(_) => http::StatusCode::SERVICE_UNAVAILABLE,
+            GraphError::QuotaExceeded(_) => http::StatusCode::TOO_MANY_REQUESTS,
+            GraphError::DeadlineExceeded(_) => http::StatusCode::GATEWAY_TIMEOUT,
+            GraphError::Config(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            GraphError::Upstream(ref e) => e.status_code(),
+            GraphError::Scrape(_) => http::StatusCode::SERVICE_UNAVAILABLE,
+            GraphError::Plugin(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
@@ -146,10 +462,28 @@ impl GraphError {
             GraphError::ArchVersionError(_) => "arch_version_error",
             GraphError::FileOpenError(_) => "file_open_err",
             GraphError::DoesNotExist(_) => "does_not_exist",
+            GraphError::UriTooLong(_) => "uri_too_long",
+            GraphError::TooManyParams(_) => "too_many_params",
+            GraphError::HeadersTooLarge(_) => "headers_too_large",
+            GraphError::Overloaded(_) => "overloaded",
+            GraphError::QuotaExceeded(_) => "quota_exceeded",
+            GraphError::DeadlineExceeded(_) => "deadline_exceeded",
+            GraphError::Config(ref e) => e.kind(),
+            GraphError::Upstream(ref e) => e.kind(),
+            GraphError::Scrape(ref e) => e.kind(),
+            GraphError::Plugin(_) => "plugin_failed",
         };
         kind.to_string()
     }
 
+    /// Return the stage of the request which failed, if reported.
+    pub fn stage(&self) -> Option<String> {
+        match self {
+            GraphError::DeadlineExceeded(stage) => Some(stage.clone()),
+            _ => None,
+        }
+    }
+
     /// Return the value for the error.
     pub fn value(&self) -> String {
         let error_msg = format!("{}", self);
@@ -162,6 +496,7 @@ impl GraphError {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::ensure_query_params;
 
     #[test]
@@ -176,4 +511,85 @@ mod tests {
         assert!(err_msg.contains("bar, foo"), "unexpected: {}", err_msg);
         assert!(!err_msg.contains("key"), "unexpected: {}", err_msg);
     }
+
+    #[test]
+    fn error_class_and_code() {
+        let error = GraphError::MissingParams(vec!["channel".to_string()]);
+        assert_eq!(error.class(), ErrorClass::ClientRequest);
+        assert_eq!(error.code(), "client_request.missing_params");
+        assert_eq!(error.status_code(), http::StatusCode::BAD_REQUEST);
+
+        let error = GraphError::from(UpstreamError::Unreachable("timeout".to_string()));
+        assert_eq!(error.class(), ErrorClass::Upstream);
+        assert_eq!(error.code(), "upstream.upstream_unreachable");
+        assert_eq!(error.status_code(), http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error.value(), "upstream unreachable: timeout");
+
+        let error = GraphError::from(PluginFailure {
+            plugin: "channel-filter".to_string(),
+            reason: "missing channel".to_string(),
+        });
+        assert_eq!(error.code(), "plugin.plugin_failed");
+        assert_eq!(error.status_code(), http::StatusCode::INTERNAL_SERVER_ERROR);
+
+        let error = GraphError::DeadlineExceeded("cincinnati-graph-fetch".to_string());
+        assert_eq!(error.code(), "service.deadline_exceeded");
+        assert_eq!(error.status_code(), http::StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(error.stage(), Some("cincinnati-graph-fetch".to_string()));
+        assert_eq!(GraphError::Overloaded(1).stage(), None);
+    }
+
+    #[test]
+    fn map_errors_through_contexts() {
+        let error = Error::from(GraphError::InvalidParams("arch".to_string()));
+        assert_eq!(
+            GraphError::from_error(error),
+            GraphError::InvalidParams("arch".to_string())
+        );
+
+        let error = Error::from(ScrapeError::GraphData("no such tag".to_string()))
+            .context("fetching graph-data")
+            .context("refreshing the graph");
+        assert_eq!(
+            GraphError::from_error(error),
+            GraphError::Scrape(ScrapeError::GraphData("no such tag".to_string()))
+        );
+
+        let error = Error::from(GraphError::DoesNotExist("release".to_string()))
+            .context("looking up release");
+        assert_eq!(
+            GraphError::from_error(error),
+            GraphError::DoesNotExist("release".to_string())
+        );
+
+        let error = format_err!("something went wrong");
+        assert_eq!(
+            GraphError::from_error(error),
+            GraphError::FailedPluginExecution("something went wrong".to_string())
+        );
+    }
+
+    #[test]
+    fn classify_untyped_errors() {
+        let error = format_err!("connection refused").context("fetching graph");
+        assert_eq!(
+            GraphError::from_error(UpstreamError::unreachable(error)),
+            GraphError::Upstream(UpstreamError::Unreachable(
+                "fetching graph: connection refused".to_string()
+            ))
+        );
+
+        // Errors which are already classified keep their class.
+        let error = Error::from(GraphError::DeadlineExceeded("render".to_string()));
+        assert_eq!(
+            GraphError::from_error(PluginFailure::attribute("channel-filter", error)),
+            GraphError::DeadlineExceeded("render".to_string())
+        );
+
+        let error = ScrapeError::registry(format_err!("manifest unknown"));
+        assert_eq!(
+            GraphError::from_error(PluginFailure::attribute("release-scrape", error)).code(),
+            "scrape.registry_scrape_failed"
+        );
+    }
 }
@@ -0,0 +1,299 @@
+//! Runtime feature flags.
+//!
+//! Services declare the [`Feature`]s gating their experimental behaviors,
+//! each enabled or disabled by default. Flags are then overridden by the
+//! configuration, and by the `CINCINNATI_FEATURE_<NAME>` environment
+//! variables, where `<NAME>` is the flag name in upper case with dashes
+//! replaced by underscores, e.g. `CINCINNATI_FEATURE_COHORT_SERVING=false`.
+//!
+//! Flags are listed by [`serve_features`] and can be changed at runtime by
+//! [`update_feature`]. Runtime changes are not persisted across restarts.
+
+use crate::auth::BearerToken;
+use crate::errors::{bail, Context, Fallible};
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Prefix of the environment variables overriding flags.
+pub static ENV_PREFIX: &str = "CINCINNATI_FEATURE_";
+
+/// A feature, gated by a flag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Feature {
+    /// Name of the flag, in kebab-case.
+    pub name: &'static str,
+    /// Behavior gated by the flag.
+    pub description: &'static str,
+    /// Whether the feature is enabled unless overridden.
+    pub default: bool,
+}
+
+impl Feature {
+    /// Environment variable overriding the flag.
+    pub fn env_var(&self) -> String {
+        format!(
+            "{}{}",
+            ENV_PREFIX,
+            self.name.to_uppercase().replace('-', "_")
+        )
+    }
+}
+
+/// State of a flag, as served.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FeatureState {
+    /// Name of the flag.
+    pub name: &'static str,
+    /// Behavior gated by the flag.
+    pub description: &'static str,
+    /// Whether the feature is enabled.
+    pub enabled: bool,
+    /// Whether the feature is enabled by default.
+    pub default: bool,
+}
+
+#[derive(Debug)]
+struct Flag {
+    feature: Feature,
+    enabled: AtomicBool,
+}
+
+/// Flags of the features of a service, shared by its handlers.
+#[derive(Clone, Debug)]
+pub struct FeatureFlags {
+    flags: Arc<Vec<Flag>>,
+}
+
+impl FeatureFlags {
+    /// Create the flags of `features`, in their default state.
+    pub fn new(features: &[Feature]) -> Self {
+        let flags = features
+            .iter()
+            .map(|feature| Flag {
+                feature: *feature,
+                enabled: AtomicBool::new(feature.default),
+            })
+            .collect();
+        Self {
+            flags: Arc::new(flags),
+        }
+    }
+
+    /// Create the flags of `features`, overridden by `config`, then by the environment.
+    pub fn try_new(features: &[Feature], config: &BTreeMap<String, bool>) -> Fallible<Self> {
+        let flags = Self::new(features);
+        for (name, enabled) in config {
+            flags.set(name, *enabled)?;
+        }
+        flags.apply_vars(std::env::vars())?;
+        Ok(flags)
+    }
+
+    /// Override the flags with the matching environment variables of `vars`.
+    fn apply_vars(&self, vars: impl IntoIterator<Item = (String, String)>) -> Fallible<()> {
+        let vars: BTreeMap<String, String> = vars
+            .into_iter()
+            .filter(|(key, _)| key.starts_with(ENV_PREFIX))
+            .collect();
+        for flag in self.flags.iter() {
+            let var = flag.feature.env_var();
+            if let Some(value) = vars.get(&var) {
+                let enabled = value.parse().context(format!(
+                    "parsing {}={:?}, expected true or false",
+                    var, value
+                ))?;
+                flag.enabled.store(enabled, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the feature `name` is enabled. Unknown features are disabled.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.find(name)
+            .map_or(false, |flag| flag.enabled.load(Ordering::Relaxed))
+    }
+
+    /// Enable or disable the feature `name`.
+    pub fn set(&self, name: &str, enabled: bool) -> Fallible<FeatureState> {
+        let flag = match self.find(name) {
+            Some(flag) => flag,
+            None => bail!("unknown feature {:?}", name),
+        };
+        if flag.enabled.swap(enabled, Ordering::Relaxed) != enabled {
+            log::info!(
+                "feature {} {}",
+                name,
+                if enabled { "enabled" } else { "disabled" }
+            );
+        }
+        Ok(state(flag))
+    }
+
+    /// List the state of the flags.
+    pub fn list(&self) -> Vec<FeatureState> {
+        self.flags.iter().map(state).collect()
+    }
+
+    fn find(&self, name: &str) -> Option<&Flag> {
+        self.flags.iter().find(|flag| flag.feature.name == name)
+    }
+}
+
+fn state(flag: &Flag) -> FeatureState {
+    FeatureState {
+        name: flag.feature.name,
+        description: flag.feature.description,
+        enabled: flag.enabled.load(Ordering::Relaxed),
+        default: flag.feature.default,
+    }
+}
+
+/// Check that every flag of `config` is one of `features`.
+pub fn validate(features: &[Feature], config: &BTreeMap<String, bool>) -> Fallible<()> {
+    for name in config.keys() {
+        if !features.iter().any(|feature| feature.name == name) {
+            bail!(
+                "unknown feature {:?}, expected one of: {}",
+                name,
+                features
+                    .iter()
+                    .map(|feature| feature.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Serve the state of the flags, as JSON.
+pub async fn serve_features(flags: web::Data<FeatureFlags>) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "features": flags.list() }))
+}
+
+/// Requested state of a flag.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeatureUpdate {
+    /// Whether the feature is to be enabled.
+    pub enabled: bool,
+}
+
+/// Change the state of the flag named in the path, and serve its new state.
+///
+/// Requests must present the configured `BearerToken` as a bearer token.
+pub async fn update_feature(
+    req: HttpRequest,
+    token: web::Data<BearerToken>,
+    flags: web::Data<FeatureFlags>,
+    name: web::Path<String>,
+    update: web::Json<FeatureUpdate>,
+) -> HttpResponse {
+    if let Err(response) = token.check(&req) {
+        return response;
+    }
+    match flags.set(&name, update.enabled) {
+        Ok(state) => HttpResponse::Ok().json(state),
+        Err(_) => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FEATURES: &[Feature] = &[
+        Feature {
+            name: "cohort-serving",
+            description: "Serve cohorts their own plugin chain",
+            default: true,
+        },
+        Feature {
+            name: "risk-evaluation",
+            description: "Evaluate the risks of conditional edges",
+            default: false,
+        },
+    ];
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn override_flags() -> Fallible<()> {
+        let config = vec![("risk-evaluation".to_string(), true)]
+            .into_iter()
+            .collect();
+        validate(FEATURES, &config)?;
+        let flags = FeatureFlags::new(FEATURES);
+        assert!(flags.is_enabled("cohort-serving"));
+        assert!(!flags.is_enabled("risk-evaluation"));
+        assert!(!flags.is_enabled("unknown"));
+
+        flags.set("risk-evaluation", true)?;
+        flags.apply_vars(vars(&[
+            ("CINCINNATI_FEATURE_COHORT_SERVING", "false"),
+            ("COHORT_SERVING", "true"),
+        ]))?;
+        assert!(!flags.is_enabled("cohort-serving"));
+        assert!(flags.is_enabled("risk-evaluation"));
+        assert!(flags
+            .apply_vars(vars(&[("CINCINNATI_FEATURE_RISK_EVALUATION", "yes")]))
+            .is_err());
+
+        // Handles share the flags.
+        let handle = flags.clone();
+        assert_eq!(
+            handle.set("cohort-serving", true)?,
+            FeatureState {
+                name: "cohort-serving",
+                description: "Serve cohorts their own plugin chain",
+                enabled: true,
+                default: true,
+            }
+        );
+        assert!(flags.is_enabled("cohort-serving"));
+        assert!(flags.set("unknown", true).is_err());
+
+        let config = vec![("unknown".to_string(), true)].into_iter().collect();
+        assert!(validate(FEATURES, &config).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_requires_token() -> Fallible<()> {
+        use actix_web::http::header;
+        use actix_web::test::TestRequest;
+
+        let rt = crate::testing::init_runtime()?;
+        let token = web::Data::new(BearerToken::new("s3cr3t"));
+        let flags = web::Data::new(FeatureFlags::new(FEATURES));
+        let update = |authorization: &str, name: &str| {
+            let req = TestRequest::put()
+                .insert_header((header::AUTHORIZATION, authorization))
+                .to_http_request();
+            rt.block_on(update_feature(
+                req,
+                token.clone(),
+                flags.clone(),
+                web::Path::from(name.to_string()),
+                web::Json(FeatureUpdate { enabled: false }),
+            ))
+            .status()
+        };
+
+        assert_eq!(update("Bearer wrong", "cohort-serving"), 401);
+        assert!(flags.is_enabled("cohort-serving"));
+        assert_eq!(update("Bearer s3cr3t", "unknown"), 404);
+        assert_eq!(update("Bearer s3cr3t", "cohort-serving"), 200);
+        assert!(!flags.is_enabled("cohort-serving"));
+
+        Ok(())
+    }
+}
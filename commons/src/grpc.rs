@@ -0,0 +1,363 @@
+//! gRPC serving surface, shared by graph-builder and policy-engine.
+//!
+//! Both daemons expose the same `cincinnati.v1.GraphService`, backed by their
+//! own graph state. This module holds the generated protocol types, the
+//! conversion from the JSON graph representation, and the server setup
+//! (including the standard health and reflection services).
+
+use crate::errors::prelude::*;
+use crate::GraphError;
+use futures::SinkExt;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Number of graph elements produced ahead of a streaming client.
+const STREAM_BUFFER: usize = 64;
+
+/// Stream of graph elements sent to a `StreamGraph` client.
+pub type GraphElementStream =
+    futures::channel::mpsc::Receiver<Result<proto::GraphElement, tonic::Status>>;
+
+/// Generated protocol types and service definitions.
+#[allow(missing_docs)]
+pub mod proto {
+    tonic::include_proto!("cincinnati.v1");
+
+    /// Encoded file descriptor set, for the reflection service.
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("cincinnati_descriptor");
+}
+
+impl From<GraphError> for tonic::Status {
+    fn from(e: GraphError) -> Self {
+        let message = e.value();
+        match e {
+            GraphError::InvalidContentType
+            | GraphError::MissingParams(_)
+            | GraphError::InvalidParams(_) => tonic::Status::invalid_argument(message),
+            GraphError::DoesNotExist(_) => tonic::Status::not_found(message),
+            GraphError::FailedUpstreamFetch(_)
+            | GraphError::FailedUpstreamRequest(_)
+            | GraphError::Upstream(_)
+            | GraphError::Scrape(_) => tonic::Status::unavailable(message),
+            GraphError::DeadlineExceeded(_) => tonic::Status::deadline_exceeded(message),
+            GraphError::QuotaExceeded(_) => tonic::Status::resource_exhausted(message),
+            _ => tonic::Status::internal(message),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonRelease {
+    version: String,
+    payload: String,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct JsonConditionalEdge {
+    edges: Vec<JsonConditionalUpdateEdge>,
+    risks: Vec<JsonConditionalUpdateRisk>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct JsonConditionalUpdateEdge {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct JsonConditionalUpdateRisk {
+    url: String,
+    name: String,
+    message: String,
+    #[serde(rename = "matchingRules")]
+    matching_rules: Vec<JsonClusterCondition>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct JsonClusterCondition {
+    #[serde(rename = "type")]
+    condition_type: String,
+    promql: JsonPromQLClusterCondition,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct JsonPromQLClusterCondition {
+    promql: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonGraph {
+    #[serde(default)]
+    version: u64,
+    nodes: Vec<JsonRelease>,
+    edges: Vec<(u64, u64)>,
+    #[serde(default, rename = "conditionalEdges")]
+    conditional_edges: Vec<JsonConditionalEdge>,
+}
+
+impl From<JsonConditionalEdge> for proto::ConditionalEdge {
+    fn from(ce: JsonConditionalEdge) -> Self {
+        proto::ConditionalEdge {
+            edges: ce
+                .edges
+                .into_iter()
+                .map(|e| proto::ConditionalUpdateEdge {
+                    from: e.from,
+                    to: e.to,
+                })
+                .collect(),
+            risks: ce
+                .risks
+                .into_iter()
+                .map(|r| proto::ConditionalUpdateRisk {
+                    url: r.url,
+                    name: r.name,
+                    message: r.message,
+                    matching_rules: r
+                        .matching_rules
+                        .into_iter()
+                        .map(|c| proto::ClusterCondition {
+                            r#type: c.condition_type,
+                            promql: c.promql.promql,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Encode request parameters as a URL query string, as received by the HTTP endpoints.
+pub fn parameters_query(parameters: &HashMap<String, String>) -> String {
+    url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(parameters.iter())
+        .finish()
+}
+
+/// Convert a serialized JSON graph into its protocol representation.
+pub fn graph_from_json(json: &str) -> Result<proto::Graph, GraphError> {
+    let graph: JsonGraph =
+        serde_json::from_str(json).map_err(|e| GraphError::FailedJsonIn(e.to_string()))?;
+
+    Ok(proto::Graph {
+        version: graph.version,
+        nodes: graph
+            .nodes
+            .into_iter()
+            .map(|n| proto::Release {
+                version: n.version,
+                payload: n.payload,
+                metadata: n.metadata,
+            })
+            .collect(),
+        edges: graph
+            .edges
+            .into_iter()
+            .map(|(from, to)| proto::Edge { from, to })
+            .collect(),
+        conditional_edges: graph
+            .conditional_edges
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+    })
+}
+
+/// Split a graph into its elements, in streaming order.
+pub fn graph_elements(graph: proto::Graph) -> impl Iterator<Item = proto::GraphElement> {
+    use proto::graph_element::Element;
+
+    graph
+        .nodes
+        .into_iter()
+        .map(Element::Node)
+        .chain(graph.edges.into_iter().map(Element::Edge))
+        .chain(
+            graph
+                .conditional_edges
+                .into_iter()
+                .map(Element::ConditionalEdge),
+        )
+        .map(|element| proto::GraphElement {
+            element: Some(element),
+        })
+}
+
+/// Stream the elements of a graph, producing them only as fast as the client consumes them.
+///
+/// Must be called from within a tokio runtime.
+pub fn stream_graph_elements(graph: proto::Graph) -> GraphElementStream {
+    let (mut sender, receiver) = futures::channel::mpsc::channel(STREAM_BUFFER);
+    tokio::spawn(async move {
+        for element in graph_elements(graph) {
+            if sender.send(Ok(element)).await.is_err() {
+                // The client went away.
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Compute the releases (and conditional updates) reachable in one hop from `version`.
+pub fn next_updates(graph: proto::Graph, version: &str) -> Result<proto::NextUpdates, GraphError> {
+    let source = graph
+        .nodes
+        .iter()
+        .position(|n| n.version == version)
+        .ok_or_else(|| GraphError::DoesNotExist(format!("release '{}'", version)))?
+        as u64;
+
+    let targets: Vec<usize> = graph
+        .edges
+        .iter()
+        .filter(|e| e.from == source)
+        .map(|e| e.to as usize)
+        .collect();
+    let releases = targets
+        .into_iter()
+        .filter_map(|i| graph.nodes.get(i).cloned())
+        .collect();
+
+    let conditional_edges = graph
+        .conditional_edges
+        .into_iter()
+        .filter_map(|mut ce| {
+            ce.edges.retain(|e| e.from == version);
+            if ce.edges.is_empty() {
+                None
+            } else {
+                Some(ce)
+            }
+        })
+        .collect();
+
+    Ok(proto::NextUpdates {
+        releases,
+        conditional_edges,
+    })
+}
+
+/// Serve the given graph service on `addr`, together with health and reflection services.
+pub async fn serve<S>(addr: SocketAddr, service: S) -> Fallible<()>
+where
+    S: proto::graph_service_server::GraphService,
+{
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    health_reporter
+        .set_serving::<proto::graph_service_server::GraphServiceServer<S>>()
+        .await;
+
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()
+        .context("building gRPC reflection service")?;
+
+    tonic::transport::Server::builder()
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(proto::graph_service_server::GraphServiceServer::new(
+            service,
+        ))
+        .serve(addr)
+        .await
+        .context(format!("serving gRPC on {}", addr))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static GRAPH_JSON: &str = r#"{
+        "version": 1,
+        "nodes": [
+            {"version": "4.1.0", "payload": "image:4.1.0", "metadata": {"k": "v"}},
+            {"version": "4.1.1", "payload": "image:4.1.1", "metadata": {}},
+            {"version": "4.1.2", "payload": "image:4.1.2", "metadata": {}}
+        ],
+        "edges": [[0, 1], [1, 2]],
+        "conditionalEdges": [{
+            "edges": [{"from": "4.1.0", "to": "4.1.2"}],
+            "risks": [{
+                "url": "https://example.com",
+                "name": "Risk",
+                "message": "message",
+                "matchingRules": [{"type": "PromQL", "promql": {"promql": "1"}}]
+            }]
+        }]
+    }"#;
+
+    #[test]
+    fn convert_json_graph() {
+        let graph = graph_from_json(GRAPH_JSON).unwrap();
+
+        assert_eq!(graph.version, 1);
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(graph.nodes[0].metadata.get("k"), Some(&"v".to_string()));
+        assert_eq!(graph.edges[1], proto::Edge { from: 1, to: 2 });
+        assert_eq!(
+            graph.conditional_edges[0].risks[0].matching_rules[0].promql,
+            "1"
+        );
+        assert_eq!(graph_elements(graph).count(), 6);
+    }
+
+    #[test]
+    fn stream_elements_in_order() {
+        use futures::StreamExt;
+        use proto::graph_element::Element;
+
+        let graph = graph_from_json(GRAPH_JSON).unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let elements: Vec<_> =
+            runtime.block_on(async { stream_graph_elements(graph).collect().await });
+
+        assert_eq!(elements.len(), 6);
+        assert!(matches!(
+            elements[0].as_ref().unwrap().element,
+            Some(Element::Node(_))
+        ));
+        assert!(matches!(
+            elements[5].as_ref().unwrap().element,
+            Some(Element::ConditionalEdge(_))
+        ));
+    }
+
+    #[test]
+    fn encode_parameters_query() {
+        let parameters = vec![("channel".to_string(), "stable-4.14".to_string())]
+            .into_iter()
+            .collect();
+        assert_eq!(parameters_query(&parameters), "channel=stable-4.14");
+    }
+
+    #[test]
+    fn compute_next_updates() {
+        let graph = graph_from_json(GRAPH_JSON).unwrap();
+
+        let updates = next_updates(graph.clone(), "4.1.0").unwrap();
+        assert_eq!(updates.releases.len(), 1);
+        assert_eq!(updates.releases[0].version, "4.1.1");
+        assert_eq!(updates.conditional_edges.len(), 1);
+
+        let updates = next_updates(graph.clone(), "4.1.2").unwrap();
+        assert!(updates.releases.is_empty());
+        assert!(updates.conditional_edges.is_empty());
+
+        assert_eq!(
+            next_updates(graph, "4.0.0").unwrap_err(),
+            GraphError::DoesNotExist("release '4.0.0'".to_string())
+        );
+    }
+}
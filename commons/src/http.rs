@@ -0,0 +1,297 @@
+//! Construction of outbound HTTP clients.
+//!
+//! All outbound clients of the services are built here, so that timeouts,
+//! proxies, trusted CAs, the user-agent and connection pooling are
+//! configured consistently. Requests are not retried by the clients; callers
+//! retrying their deliveries do so according to a `RetryPolicy`.
+
+use crate::errors::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Default user-agent of outbound requests.
+pub static DEFAULT_USER_AGENT: &str = "openshift/cincinnati";
+
+/// Default timeout for establishing connections.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default time idle connections are kept in the pool.
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Policy for retrying failed deliveries, with exponential backoff.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of retries after the first attempt.
+    pub max_retries: u32,
+
+    /// Pause before the first retry, doubled on each further retry.
+    pub initial_backoff: Duration,
+
+    /// Upper bound for the pause between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Pause before the given retry, starting at 0.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .checked_mul(2u32.saturating_pow(retry))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+/// Builder of outbound HTTP clients.
+#[derive(Clone, Debug)]
+pub struct HttpClientBuilder {
+    timeout: Option<Duration>,
+    connect_timeout: Duration,
+    user_agent: String,
+    gzip: bool,
+    proxy: Option<String>,
+    ca_bundle: Option<PathBuf>,
+    identity: Option<(PathBuf, PathBuf)>,
+    accept_invalid_certs: bool,
+    pool_idle_timeout: Duration,
+    pool_max_idle_per_host: Option<usize>,
+}
+
+impl Default for HttpClientBuilder {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            gzip: true,
+            proxy: None,
+            ca_bundle: None,
+            identity: None,
+            accept_invalid_certs: false,
+            pool_idle_timeout: DEFAULT_POOL_IDLE_TIMEOUT,
+            pool_max_idle_per_host: None,
+        }
+    }
+}
+
+impl HttpClientBuilder {
+    /// Create a builder with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the timeout of whole requests, unlimited by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the timeout for establishing connections.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Set the user-agent of requests.
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Set whether gzip-compressed responses are accepted, the default.
+    pub fn gzip(mut self, gzip: bool) -> Self {
+        self.gzip = gzip;
+        self
+    }
+
+    /// Set (or reset) the proxy of all requests.
+    ///
+    /// Without proxy, the proxies of the environment are used, e.g.
+    /// `HTTPS_PROXY`.
+    pub fn proxy(mut self, proxy: Option<String>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Set (or reset) a PEM bundle of CA certificates to trust, in
+    /// addition to the system ones.
+    pub fn ca_bundle(mut self, ca_bundle: Option<PathBuf>) -> Self {
+        self.ca_bundle = ca_bundle;
+        self
+    }
+
+    /// Set (or reset) the PEM certificate and PKCS#8 key the client
+    /// authenticates with.
+    pub fn identity(mut self, identity: Option<(PathBuf, PathBuf)>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Set whether invalid server certificates are accepted.
+    pub fn accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// Set the time idle connections are kept in the pool.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+
+    /// Set (or reset) the maximum number of idle connections per host.
+    pub fn pool_max_idle_per_host(mut self, max_idle: Option<usize>) -> Self {
+        self.pool_max_idle_per_host = max_idle;
+        self
+    }
+
+    /// Build an asynchronous client.
+    pub fn build(&self) -> Fallible<reqwest::Client> {
+        let mut builder = reqwest::ClientBuilder::new()
+            .connect_timeout(self.connect_timeout)
+            .user_agent(self.user_agent.as_str())
+            .gzip(self.gzip)
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
+            .pool_idle_timeout(self.pool_idle_timeout);
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(max_idle) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(proxy) = self.load_proxy()? {
+            builder = builder.proxy(proxy);
+        }
+        for certificate in self.load_certificates()? {
+            builder = builder.add_root_certificate(certificate);
+        }
+        if let Some(identity) = self.load_identity()? {
+            builder = builder.identity(identity);
+        }
+
+        builder.build().context("building HTTP client")
+    }
+
+    /// Build a blocking client.
+    pub fn build_blocking(&self) -> Fallible<reqwest::blocking::Client> {
+        let mut builder = reqwest::blocking::ClientBuilder::new()
+            .connect_timeout(self.connect_timeout)
+            .user_agent(self.user_agent.as_str())
+            .gzip(self.gzip)
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .timeout(self.timeout);
+        if let Some(max_idle) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(proxy) = self.load_proxy()? {
+            builder = builder.proxy(proxy);
+        }
+        for certificate in self.load_certificates()? {
+            builder = builder.add_root_certificate(certificate);
+        }
+        if let Some(identity) = self.load_identity()? {
+            builder = builder.identity(identity);
+        }
+
+        builder.build().context("building blocking HTTP client")
+    }
+
+    fn load_proxy(&self) -> Fallible<Option<reqwest::Proxy>> {
+        self.proxy
+            .as_ref()
+            .map(|proxy| reqwest::Proxy::all(proxy).context(format!("invalid proxy '{}'", proxy)))
+            .transpose()
+    }
+
+    fn load_certificates(&self) -> Fallible<Vec<reqwest::Certificate>> {
+        let path = match &self.ca_bundle {
+            Some(path) => path,
+            None => return Ok(vec![]),
+        };
+        let pem = fs::read(path).context(format!("reading CA bundle {}", path.display()))?;
+        let certificates = pem_blocks(&pem)
+            .into_iter()
+            .map(|block| reqwest::Certificate::from_pem(block.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .context(format!("parsing CA bundle {}", path.display()))?;
+        ensure!(
+            !certificates.is_empty(),
+            "no certificate in CA bundle {}",
+            path.display()
+        );
+        Ok(certificates)
+    }
+
+    fn load_identity(&self) -> Fallible<Option<reqwest::Identity>> {
+        let (cert_path, key_path) = match &self.identity {
+            Some(identity) => identity,
+            None => return Ok(None),
+        };
+        let cert = fs::read(cert_path).context(format!(
+            "reading client certificate {}",
+            cert_path.display()
+        ))?;
+        let key =
+            fs::read(key_path).context(format!("reading client key {}", key_path.display()))?;
+        let identity = reqwest::Identity::from_pkcs8_pem(&cert, &key).context(format!(
+            "parsing client certificate {}",
+            cert_path.display()
+        ))?;
+        Ok(Some(identity))
+    }
+}
+
+/// Split a PEM bundle into its certificates.
+fn pem_blocks(pem: &[u8]) -> Vec<String> {
+    static END: &str = "-----END CERTIFICATE-----";
+
+    let pem = String::from_utf8_lossy(pem);
+    let mut blocks = vec![];
+    let mut rest = pem.as_ref();
+    while let Some(end) = rest.find(END) {
+        let (block, tail) = rest.split_at(end + END.len());
+        blocks.push(block.trim().to_string());
+        rest = tail;
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+        };
+
+        assert_eq!(policy.backoff(0), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2), Duration::from_secs(4));
+        assert_eq!(policy.backoff(3), Duration::from_secs(5));
+        assert_eq!(policy.backoff(40), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn split_pem_bundle() {
+        let bundle = "# first\n-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n\
+                      -----BEGIN CERTIFICATE-----\nBBBB\n-----END CERTIFICATE-----\n";
+        let blocks = pem_blocks(bundle.as_bytes());
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].ends_with("AAAA\n-----END CERTIFICATE-----"));
+        assert!(blocks[1].starts_with("-----BEGIN CERTIFICATE-----\nBBBB"));
+    }
+}
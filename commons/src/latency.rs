@@ -0,0 +1,184 @@
+//! Per-endpoint request latency.
+//!
+//! Request durations are exported as histograms labeled by route pattern, and
+//! requests slower than a configurable threshold are logged together with the
+//! timing breakdown attached to their response, if any.
+
+use crate::errors::Fallible;
+use actix_service::Service;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use futures::future::{FutureExt, LocalBoxFuture, TryFutureExt};
+use prometheus::{histogram_opts, HistogramVec, IntCounterVec, Opts, Registry};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Endpoint label of requests not matching any route.
+static UNMATCHED_ENDPOINT: &str = "unmatched";
+
+lazy_static! {
+    // Buckets aligned on usual latency objectives (in seconds).
+    static ref REQUEST_DURATION: HistogramVec = HistogramVec::new(
+        histogram_opts!(
+            "http_request_duration_seconds",
+            "HTTP request latency in seconds, by endpoint",
+            vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]
+        ),
+        &["endpoint"]
+    )
+    .unwrap();
+    static ref SLOW_REQUESTS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "http_slow_requests_total",
+            "Total number of requests exceeding the slow request threshold, by endpoint"
+        ),
+        &["endpoint"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(REQUEST_DURATION.clone()))?;
+    registry.register(Box::new(SLOW_REQUESTS.clone()))?;
+    Ok(())
+}
+
+/// Time spent in each step of a request, attached to response extensions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TimingBreakdown(pub Vec<(&'static str, Duration)>);
+
+impl fmt::Display for TimingBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (step, duration)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}: {} ms", step, duration.as_millis())?;
+        }
+        Ok(())
+    }
+}
+
+/// Request latency recorder.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyRecorder {
+    /// Latency above which requests are logged, if any.
+    pub slow_threshold: Option<Duration>,
+}
+
+impl LatencyRecorder {
+    /// Forward requests to `srv`, recording their latency.
+    ///
+    /// This is meant to be used with `App::wrap_fn`.
+    pub fn observe<S, B>(
+        &self,
+        req: ServiceRequest,
+        srv: &S,
+    ) -> LocalBoxFuture<'static, Result<ServiceResponse<B>, actix_web::Error>>
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+        S::Future: 'static,
+        B: 'static,
+    {
+        let slow_threshold = self.slow_threshold;
+        let start = Instant::now();
+        srv.call(req)
+            .map_ok(move |res| {
+                record(&res, start.elapsed(), slow_threshold);
+                res
+            })
+            .boxed_local()
+    }
+}
+
+/// Record the latency of a served request.
+fn record<B>(res: &ServiceResponse<B>, elapsed: Duration, slow_threshold: Option<Duration>) {
+    let endpoint = res
+        .request()
+        .match_pattern()
+        .unwrap_or_else(|| UNMATCHED_ENDPOINT.to_string());
+    REQUEST_DURATION
+        .with_label_values(&[&endpoint])
+        .observe(elapsed.as_secs_f64());
+
+    if !slow_threshold.map_or(false, |threshold| elapsed >= threshold) {
+        return;
+    }
+
+    SLOW_REQUESTS.with_label_values(&[&endpoint]).inc();
+    let breakdown = match res.response().extensions().get::<TimingBreakdown>() {
+        Some(breakdown) => format!(" ({})", breakdown),
+        None => String::new(),
+    };
+    log::warn!(
+        "slow request '{}' answered with {} in {} ms{}",
+        crate::format_request(res.request()),
+        res.status(),
+        elapsed.as_millis(),
+        breakdown
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use actix_web::HttpResponse;
+
+    #[test]
+    fn format_timing_breakdown() {
+        let breakdown = TimingBreakdown(vec![
+            ("cincinnati-graph-fetch", Duration::from_millis(1200)),
+            ("channel-filter", Duration::from_micros(4500)),
+        ]);
+        assert_eq!(
+            breakdown.to_string(),
+            "cincinnati-graph-fetch: 1200 ms, channel-filter: 4 ms"
+        );
+        assert_eq!(TimingBreakdown::default().to_string(), "");
+    }
+
+    #[test]
+    fn record_slow_requests() -> Fallible<()> {
+        let rt = crate::testing::init_runtime()?;
+        let recorder = LatencyRecorder {
+            slow_threshold: Some(Duration::from_millis(0)),
+        };
+
+        rt.block_on(async {
+            let app = actix_web::test::init_service(
+                actix_web::App::new()
+                    .wrap_fn(move |req, srv| recorder.observe(req, srv))
+                    .route(
+                        "/test-slow",
+                        actix_web::web::get().to(|| async {
+                            let mut response = HttpResponse::Ok();
+                            response.extensions_mut().insert(TimingBreakdown(vec![(
+                                "plugin",
+                                Duration::from_millis(1),
+                            )]));
+                            response.finish()
+                        }),
+                    ),
+            )
+            .await;
+
+            let before = SLOW_REQUESTS.with_label_values(&["/test-slow"]).get();
+            let req = TestRequest::get().uri("/test-slow").to_request();
+            let resp = actix_web::test::call_service(&app, req).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+            assert_eq!(
+                SLOW_REQUESTS.with_label_values(&["/test-slow"]).get(),
+                before + 1
+            );
+            assert!(
+                REQUEST_DURATION
+                    .with_label_values(&["/test-slow"])
+                    .get_sample_count()
+                    > 0
+            );
+        });
+
+        Ok(())
+    }
+}
@@ -11,33 +11,73 @@ extern crate serde_derive;
 mod config;
 pub use crate::config::MergeOptions;
 
+pub mod aliases;
+pub mod alloc;
+pub mod auth;
+pub mod budget;
 pub mod de;
+pub mod deadline;
+pub mod download;
+pub mod features;
+pub mod grpc;
+pub mod http;
+pub mod latency;
+pub mod limits;
+pub mod listen;
 pub mod metrics;
+pub mod params;
+#[cfg(feature = "http-recorder")]
+pub mod recorder;
 pub mod testing;
 pub mod tracing;
 
 mod errors;
-pub use errors::{register_metrics, Error, Fallible, GraphError, MISSING_APPSTATE_PANIC_MSG};
+pub use errors::{
+    register_metrics, ConfigError, Error, ErrorClass, ErrorMessage, Fallible, GraphError,
+    PluginFailure, ScrapeError, UpstreamError, MISSING_APPSTATE_PANIC_MSG,
+};
 
 /// Commonly used imports for error handling.
 pub mod prelude_errors {
     pub use crate::errors::prelude::*;
 }
 
-use actix_web::http::header::{HeaderMap, HeaderValue, ACCEPT};
-use actix_web::HttpRequest;
+use actix_web::http::header::{HeaderMap, HeaderValue, HttpDate, ACCEPT, WARNING};
+use actix_web::{HttpRequest, HttpResponseBuilder};
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fs::File;
+use std::net::IpAddr;
 use std::path::Path;
+use std::time::SystemTime;
 use url::form_urlencoded;
 
 /// Defines the key for placing the data directory path in the IO parameters
 pub static GRAPH_DATA_DIR_PARAM_KEY: &str = "io.openshift.upgrades.secondary_metadata.directory";
 /// Defines the key for placing the graph_data tar path in the IO parameters
 pub static SECONDARY_METADATA_PARAM_KEY: &str = "io.openshift.upgrades.secondary_metadata.tar";
+/// Defines the key for placing the URI of the scraped graph-data in the IO parameters
+pub static GRAPH_DATA_SOURCE_PARAM_KEY: &str = "io.openshift.upgrades.secondary_metadata.source";
+/// Defines the key for placing the revision of the scraped graph-data, a commit SHA or an image digest, in the IO parameters
+pub static GRAPH_DATA_REVISION_PARAM_KEY: &str =
+    "io.openshift.upgrades.secondary_metadata.revision";
+/// Defines the key for placing the HTTP-date since which the graph is stale in the IO parameters
+pub static GRAPH_STALE_SINCE_PARAM_KEY: &str = "io.openshift.upgrades.graph.stale_since";
+/// Defines the key for placing the digest of the upstream graph in the IO parameters
+pub static GRAPH_UPSTREAM_DIGEST_PARAM_KEY: &str = "io.openshift.upgrades.graph.upstream_digest";
+/// Defines the key for placing the request deadline, in milliseconds since the UNIX epoch, in the IO parameters
+pub static GRAPH_DEADLINE_PARAM_KEY: &str = "io.openshift.upgrades.graph.deadline";
+/// Defines the key for placing the time spent in each graph build phase in the IO parameters
+pub static GRAPH_BUILD_PHASES_PARAM_KEY: &str = "io.openshift.upgrades.graph.build_phases";
+/// Defines the key for placing the tags seen by the scrape of each repository in the IO parameters
+pub static GRAPH_TAG_LISTINGS_PARAM_KEY: &str = "io.openshift.upgrades.graph.tag_listings";
+
+/// Response header carrying the HTTP-date since which a served graph is stale
+pub static STALE_SINCE_HEADER: &str = "X-Graph-Stale-Since";
+/// Value of the `Warning` header attached to stale graph responses
+pub static STALE_WARNING: &str = "110 - \"Response is Stale\"";
 
 lazy_static! {
     /// list of cincinnati versions
@@ -210,14 +250,61 @@ pub fn format_request(req: &HttpRequest) -> String {
     )
 }
 
+/// Format a point in time as an HTTP-date.
+pub fn http_date(time: SystemTime) -> String {
+    HttpDate::from(time).to_string()
+}
+
+/// Mark a graph response as stale since the given HTTP-date, if any.
+pub fn insert_stale_headers(response: &mut HttpResponseBuilder, stale_since: Option<&str>) {
+    if let Some(stale_since) = stale_since {
+        response.insert_header((STALE_SINCE_HEADER, stale_since));
+        response.insert_header((WARNING, STALE_WARNING));
+    }
+}
+
+/// Listening address of a status service, advertised in OpenAPI documents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StatusListener {
+    /// Listening address.
+    pub address: IpAddr,
+    /// Listening port.
+    pub port: u16,
+}
+
+impl StatusListener {
+    /// Return the base URL of the status service, as reachable by the client of `req`.
+    ///
+    /// Services listening on all interfaces are advertised with the host the
+    /// client sent `req` to.
+    pub fn base_url(&self, req: &HttpRequest) -> String {
+        let info = req.connection_info();
+        let host = if self.address.is_unspecified() {
+            // Strip the port of the main service, if any, but not IPv6 address segments.
+            let host = info.host();
+            match host.rfind(':') {
+                Some(i) if !host[i..].contains(']') => host[..i].to_string(),
+                _ => host.to_string(),
+            }
+        } else {
+            match self.address {
+                IpAddr::V6(address) => format!("[{}]", address),
+                address => address.to_string(),
+            }
+        };
+        format!("{}://{}:{}", info.scheme(), host, self.port)
+    }
+}
+
 /// logs api request error
 pub fn api_response_error(req: &HttpRequest, e: GraphError) -> GraphError {
     log::error!(
-        "Error serving request \"{}\" from '{}': {:?}",
+        "Error serving request \"{}\" from '{}' ({}): {:?}",
         format_request(req),
         req.peer_addr()
             .map(|addr| addr.to_string())
             .unwrap_or_else(|| "<not available>".into()),
+        e.code(),
         e
     );
     e
@@ -354,4 +441,54 @@ mod tests {
         .unwrap();
         assert_eq!(version, "text/plain");
     }
+
+    #[test]
+    fn test_insert_stale_headers() {
+        let mut response = actix_web::HttpResponse::Ok();
+        insert_stale_headers(&mut response, None);
+        let response = response.finish();
+        assert!(response.headers().get(STALE_SINCE_HEADER).is_none());
+        assert!(response.headers().get(WARNING).is_none());
+
+        let stale_since = http_date(SystemTime::UNIX_EPOCH);
+        assert_eq!(stale_since, "Thu, 01 Jan 1970 00:00:00 GMT");
+
+        let mut response = actix_web::HttpResponse::Ok();
+        insert_stale_headers(&mut response, Some(&stale_since));
+        let response = response.finish();
+        assert_eq!(
+            response.headers().get(STALE_SINCE_HEADER).unwrap(),
+            stale_since.as_str()
+        );
+        assert_eq!(response.headers().get(WARNING).unwrap(), STALE_WARNING);
+    }
+
+    #[test]
+    fn test_status_listener_base_url() {
+        use actix_web::test::TestRequest;
+        use std::net::{Ipv4Addr, Ipv6Addr};
+
+        let req = TestRequest::get()
+            .insert_header(("host", "cincinnati.example.com:8080"))
+            .to_http_request();
+        let listener = StatusListener {
+            address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 9080,
+        };
+        assert_eq!(
+            listener.base_url(&req),
+            "http://cincinnati.example.com:9080"
+        );
+
+        let req = TestRequest::get()
+            .insert_header(("host", "[::1]"))
+            .to_http_request();
+        assert_eq!(listener.base_url(&req), "http://[::1]:9080");
+
+        let listener = StatusListener {
+            address: IpAddr::V6(Ipv6Addr::LOCALHOST),
+            port: 9081,
+        };
+        assert_eq!(listener.base_url(&req), "http://[::1]:9081");
+    }
 }
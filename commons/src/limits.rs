@@ -0,0 +1,865 @@
+//! Limits on the size of incoming requests, on the number of requests in
+//! flight and on the rate of requests to each graph.
+//!
+//! Requests exceeding the limits are answered with a JSON error before
+//! reaching any handler, and counted by rejection reason.
+
+use crate::errors::{ensure, Fallible, GraphError};
+use actix_service::Service;
+use actix_web::body::EitherBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::{HttpRequest, ResponseError};
+use futures::future::{self, FutureExt, LocalBoxFuture, TryFutureExt};
+use prometheus::{IntCounterVec, IntGaugeVec, Opts, Registry};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Default maximum length of the request URI, in bytes.
+pub const DEFAULT_MAX_URI_LENGTH: usize = 4096;
+
+/// Default maximum number of query parameters.
+pub const DEFAULT_MAX_QUERY_PARAMS: usize = 32;
+
+/// Default maximum total size of the request headers, in bytes.
+pub const DEFAULT_MAX_HEADERS_SIZE: usize = 16384;
+
+/// Default delay advertised to clients of saturated endpoints, in seconds.
+pub const DEFAULT_RETRY_AFTER_SECS: u64 = 1;
+
+lazy_static! {
+    static ref REJECTED_REQUESTS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "http_rejected_requests_total",
+            "Total number of requests rejected for exceeding request limits"
+        ),
+        &["reason"]
+    )
+    .unwrap();
+    static ref IN_FLIGHT_REQUESTS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "http_in_flight_requests",
+            "Number of requests in flight to endpoints with a concurrency limit"
+        ),
+        &["endpoint"]
+    )
+    .unwrap();
+    static ref QUOTA_REJECTED_REQUESTS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "http_quota_rejected_requests_total",
+            "Total number of requests rejected for exceeding the request quotas of a graph"
+        ),
+        &["path_prefix", "scope"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(REJECTED_REQUESTS.clone()))?;
+    registry.register(Box::new(IN_FLIGHT_REQUESTS.clone()))?;
+    registry.register(Box::new(QUOTA_REJECTED_REQUESTS.clone()))?;
+    Ok(())
+}
+
+/// Reject `req` with the error `e`, counting it by reason.
+fn reject<B>(
+    req: ServiceRequest,
+    e: GraphError,
+) -> LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, actix_web::Error>>
+where
+    B: 'static,
+{
+    REJECTED_REQUESTS.with_label_values(&[&e.kind()]).inc();
+    let response = req.into_response(e.error_response());
+    future::ok(response.map_into_right_body()).boxed_local()
+}
+
+/// Limits enforced on incoming requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Maximum length of the request URI, in bytes.
+    pub max_uri_length: usize,
+    /// Maximum number of query parameters.
+    pub max_query_params: usize,
+    /// Maximum total size of header names and values, in bytes.
+    pub max_headers_size: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_uri_length: DEFAULT_MAX_URI_LENGTH,
+            max_query_params: DEFAULT_MAX_QUERY_PARAMS,
+            max_headers_size: DEFAULT_MAX_HEADERS_SIZE,
+        }
+    }
+}
+
+impl RequestLimits {
+    /// Check a request against the limits.
+    pub fn check(&self, req: &HttpRequest) -> Result<(), GraphError> {
+        let uri_length = req.uri().to_string().len();
+        if uri_length > self.max_uri_length {
+            return Err(GraphError::UriTooLong(self.max_uri_length));
+        }
+
+        let query_params = url::form_urlencoded::parse(req.query_string().as_bytes()).count();
+        if query_params > self.max_query_params {
+            return Err(GraphError::TooManyParams(self.max_query_params));
+        }
+
+        let headers_size: usize = req
+            .headers()
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        if headers_size > self.max_headers_size {
+            return Err(GraphError::HeadersTooLarge(self.max_headers_size));
+        }
+
+        Ok(())
+    }
+
+    /// Reject requests exceeding the limits, and forward the others to `srv`.
+    ///
+    /// This is meant to be used with `App::wrap_fn`.
+    pub fn enforce<S, B>(
+        &self,
+        req: ServiceRequest,
+        srv: &S,
+    ) -> LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, actix_web::Error>>
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+        S::Future: 'static,
+        B: 'static,
+    {
+        match self.check(req.request()) {
+            Ok(()) => srv
+                .call(req)
+                .map_ok(ServiceResponse::map_into_left_body)
+                .boxed_local(),
+            Err(e) => reject(req, e),
+        }
+    }
+}
+
+/// Maximum numbers of requests in flight, per endpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConcurrencyLimits {
+    /// Maximum number of requests in flight, by endpoint path.
+    pub endpoints: BTreeMap<String, usize>,
+    /// Delay advertised in the `Retry-After` header of rejected requests, in seconds.
+    pub retry_after_secs: u64,
+}
+
+impl Default for ConcurrencyLimits {
+    fn default() -> Self {
+        Self {
+            endpoints: BTreeMap::new(),
+            retry_after_secs: DEFAULT_RETRY_AFTER_SECS,
+        }
+    }
+}
+
+impl ConcurrencyLimits {
+    /// Check that the limits are usable.
+    pub fn validate(&self) -> Fallible<()> {
+        for (path, max) in &self.endpoints {
+            ensure!(
+                path.starts_with('/'),
+                "concurrency limited endpoint '{}' must start with '/'",
+                path
+            );
+            ensure!(
+                *max > 0,
+                "concurrency limit of endpoint '{}' must be greater than 0",
+                path
+            );
+        }
+        ensure!(
+            self.retry_after_secs > 0,
+            "unexpected 0s concurrency limit retry delay"
+        );
+        Ok(())
+    }
+
+    /// Build the limiter of the endpoints, served under `path_prefix`.
+    pub fn limiter(&self, path_prefix: &str) -> ConcurrencyLimiter {
+        let endpoints = self
+            .endpoints
+            .iter()
+            .map(|(path, max)| {
+                let endpoint = InFlight {
+                    max: *max,
+                    current: AtomicUsize::new(0),
+                    gauge: IN_FLIGHT_REQUESTS.with_label_values(&[path.as_str()]),
+                };
+                (format!("{}{}", path_prefix, path), Arc::new(endpoint))
+            })
+            .collect();
+
+        ConcurrencyLimiter {
+            endpoints: Arc::new(endpoints),
+            retry_after_secs: self.retry_after_secs,
+        }
+    }
+}
+
+/// Requests in flight to an endpoint.
+#[derive(Debug)]
+struct InFlight {
+    max: usize,
+    current: AtomicUsize,
+    gauge: prometheus::IntGauge,
+}
+
+/// Slot of a request in flight, released on drop.
+#[derive(Debug)]
+pub struct InFlightGuard(Arc<InFlight>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.current.fetch_sub(1, Ordering::AcqRel);
+        self.0.gauge.dec();
+    }
+}
+
+/// Enforcer of the concurrency limits of endpoints.
+#[derive(Clone, Debug, Default)]
+pub struct ConcurrencyLimiter {
+    endpoints: Arc<HashMap<String, Arc<InFlight>>>,
+    retry_after_secs: u64,
+}
+
+impl ConcurrencyLimiter {
+    /// Take a slot for a request to `path`.
+    ///
+    /// Endpoints without a limit need no slot, and `None` is returned.
+    pub fn try_acquire(&self, path: &str) -> Result<Option<InFlightGuard>, GraphError> {
+        let endpoint = match self.endpoints.get(path) {
+            Some(endpoint) => endpoint,
+            None => return Ok(None),
+        };
+
+        endpoint
+            .current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                if current < endpoint.max {
+                    Some(current + 1)
+                } else {
+                    None
+                }
+            })
+            .map_err(|_| GraphError::Overloaded(endpoint.max))?;
+        endpoint.gauge.inc();
+
+        Ok(Some(InFlightGuard(endpoint.clone())))
+    }
+
+    /// Reject requests to saturated endpoints with a `Retry-After` header,
+    /// and forward the others to `srv`.
+    ///
+    /// This is meant to be used with `App::wrap_fn`.
+    pub fn enforce<S, B>(
+        &self,
+        req: ServiceRequest,
+        srv: &S,
+    ) -> LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, actix_web::Error>>
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+        S::Future: 'static,
+        B: 'static,
+    {
+        match self.try_acquire(req.path()) {
+            Ok(guard) => srv
+                .call(req)
+                .map_ok(move |response| {
+                    drop(guard);
+                    response.map_into_left_body()
+                })
+                .boxed_local(),
+            Err(e) => {
+                let retry_after = self.retry_after_secs;
+                reject(req, e)
+                    .map_ok(move |mut response| {
+                        response
+                            .headers_mut()
+                            .insert(header::RETRY_AFTER, header::HeaderValue::from(retry_after));
+                        response
+                    })
+                    .boxed_local()
+            }
+        }
+    }
+}
+
+/// Rate of requests allowed by a quota.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Quota {
+    /// Sustained number of requests per second.
+    pub requests_per_sec: f64,
+    /// Number of requests allowed in a burst, defaults to a second worth of requests.
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+impl Quota {
+    /// Return the number of requests allowed in a burst.
+    pub fn burst(&self) -> f64 {
+        match self.burst {
+            Some(burst) => f64::from(burst),
+            None => self.requests_per_sec.ceil().max(1.0),
+        }
+    }
+
+    fn validate(&self, scope: &str) -> Fallible<()> {
+        ensure!(
+            self.requests_per_sec.is_finite() && self.requests_per_sec > 0.0,
+            "{} request quota must allow more than 0 requests per second",
+            scope
+        );
+        ensure!(
+            self.burst != Some(0),
+            "{} request quota must allow bursts of at least 1 request",
+            scope
+        );
+        Ok(())
+    }
+}
+
+/// Request quotas of a graph.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RequestQuotas {
+    /// Quota of all the requests to the graph.
+    pub graph: Option<Quota>,
+    /// Quota of the requests to each channel of the graph.
+    pub channel: Option<Quota>,
+}
+
+impl RequestQuotas {
+    /// Check that the quotas are usable.
+    pub fn validate(&self) -> Fallible<()> {
+        if let Some(quota) = &self.graph {
+            quota.validate("graph")?;
+        }
+        if let Some(quota) = &self.channel {
+            quota.validate("channel")?;
+        }
+        Ok(())
+    }
+}
+
+/// Requests still allowed by a quota, refilled over time.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(quota: &Quota, now: Instant) -> Self {
+        Self {
+            tokens: quota.burst(),
+            updated: now,
+        }
+    }
+
+    /// Refill the bucket up to `now`, returning the delay until it allows a
+    /// request if it is empty.
+    fn refill(&mut self, quota: &Quota, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * quota.requests_per_sec).min(quota.burst());
+        self.updated = self.updated.max(now);
+
+        if self.tokens >= 1.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (1.0 - self.tokens) / quota.requests_per_sec,
+            ))
+        }
+    }
+}
+
+/// Buckets of the quotas of a graph.
+#[derive(Debug, Default)]
+struct QuotaBuckets {
+    graph: Option<Bucket>,
+    /// Buckets of the channels of the served graph, and of all other channels under `None`.
+    channels: HashMap<Option<String>, Bucket>,
+}
+
+/// Channels of a served graph, whose requests are accounted separately.
+///
+/// Clients choose the `channel` query parameter, so only the channels of the
+/// served graph get a quota of their own, and requests to any other channel
+/// share a single quota.
+#[derive(Clone, Debug, Default)]
+pub struct QuotaChannels(Arc<RwLock<HashSet<String>>>);
+
+impl QuotaChannels {
+    /// Replace the channels with those of the newly served graph.
+    pub fn replace<I>(&self, channels: I)
+    where
+        I: IntoIterator<Item = String>,
+    {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = channels.into_iter().collect();
+    }
+
+    fn contains(&self, channel: &str) -> bool {
+        self.0
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(channel)
+    }
+
+    fn len(&self) -> usize {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+/// Quotas of the graph served under a path prefix.
+#[derive(Debug)]
+struct GraphQuotas {
+    path_prefix: String,
+    quotas: RequestQuotas,
+    channels: QuotaChannels,
+    buckets: Mutex<QuotaBuckets>,
+}
+
+impl GraphQuotas {
+    fn serves(&self, path: &str) -> bool {
+        path.strip_prefix(self.path_prefix.as_str())
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    fn reject(&self, scope: &str, error: GraphError, delay: Duration) -> (GraphError, Duration) {
+        QUOTA_REJECTED_REQUESTS
+            .with_label_values(&[self.path_prefix.as_str(), scope])
+            .inc();
+        (error, delay)
+    }
+
+    fn try_acquire(&self, query: &str, now: Instant) -> Result<(), (GraphError, Duration)> {
+        let channel = self.quotas.channel.and_then(|_| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "channel")
+                .map(|(_, value)| value.into_owned())
+        });
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let QuotaBuckets { graph, channels } = &mut *buckets;
+
+        let graph_bucket = match &self.quotas.graph {
+            Some(quota) => {
+                let bucket = graph.get_or_insert_with(|| Bucket::new(quota, now));
+                if let Some(delay) = bucket.refill(quota, now) {
+                    let error = GraphError::QuotaExceeded("graph".to_string());
+                    return Err(self.reject("graph", error, delay));
+                }
+                Some(bucket)
+            }
+            None => None,
+        };
+
+        let channel_bucket = match (&self.quotas.channel, channel) {
+            (Some(quota), Some(channel)) => {
+                // Drop the buckets of the channels removed from the served graph.
+                if channels.len() > self.channels.len() + 1 {
+                    channels.retain(|key, _| {
+                        key.as_deref()
+                            .map_or(true, |channel| self.channels.contains(channel))
+                    });
+                }
+
+                let (key, scope) = if self.channels.contains(&channel) {
+                    (Some(channel.clone()), format!("channel '{}'", channel))
+                } else {
+                    (None, "unknown channels".to_string())
+                };
+                let bucket = channels
+                    .entry(key)
+                    .or_insert_with(|| Bucket::new(quota, now));
+                if let Some(delay) = bucket.refill(quota, now) {
+                    let error = GraphError::QuotaExceeded(scope);
+                    return Err(self.reject("channel", error, delay));
+                }
+                Some(bucket)
+            }
+            _ => None,
+        };
+
+        // Only take from the buckets once all quotas allow the request.
+        for bucket in graph_bucket.into_iter().chain(channel_bucket) {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+/// Enforcer of the request quotas of graphs, by path prefix.
+#[derive(Clone, Debug, Default)]
+pub struct QuotaLimiter {
+    graphs: Arc<Vec<GraphQuotas>>,
+}
+
+impl QuotaLimiter {
+    /// Build the limiter of the graphs served under the given path prefixes,
+    /// with their quotas and the channels of their served graphs.
+    ///
+    /// Requests are accounted to the graph with the longest matching path
+    /// prefix, so that tenants served under the prefix of another graph are
+    /// accounted separately.
+    pub fn new<I>(graphs: I) -> Self
+    where
+        I: IntoIterator<Item = (String, RequestQuotas, QuotaChannels)>,
+    {
+        let mut graphs: Vec<_> = graphs
+            .into_iter()
+            .map(|(path_prefix, quotas, channels)| GraphQuotas {
+                path_prefix,
+                quotas,
+                channels,
+                buckets: Default::default(),
+            })
+            .collect();
+        graphs.sort_by_key(|graph| std::cmp::Reverse(graph.path_prefix.len()));
+
+        Self {
+            graphs: Arc::new(graphs),
+        }
+    }
+
+    /// Account for a request to `path` with the query string `query`.
+    ///
+    /// Returns the error and the delay until the request may be retried, if
+    /// it exceeds a quota.
+    pub fn try_acquire(&self, path: &str, query: &str) -> Result<(), (GraphError, Duration)> {
+        match self.graphs.iter().find(|graph| graph.serves(path)) {
+            Some(graph) => graph.try_acquire(query, Instant::now()),
+            None => Ok(()),
+        }
+    }
+
+    /// Reject requests exceeding the quotas with a `Retry-After` header, and
+    /// forward the others to `srv`.
+    ///
+    /// This is meant to be used with `App::wrap_fn`.
+    pub fn enforce<S, B>(
+        &self,
+        req: ServiceRequest,
+        srv: &S,
+    ) -> LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, actix_web::Error>>
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+        S::Future: 'static,
+        B: 'static,
+    {
+        match self.try_acquire(req.path(), req.query_string()) {
+            Ok(()) => srv
+                .call(req)
+                .map_ok(ServiceResponse::map_into_left_body)
+                .boxed_local(),
+            Err((e, delay)) => {
+                let retry_after = delay.as_secs_f64().ceil().max(1.0) as u64;
+                reject(req, e)
+                    .map_ok(move |mut response| {
+                        response
+                            .headers_mut()
+                            .insert(header::RETRY_AFTER, header::HeaderValue::from(retry_after));
+                        response
+                    })
+                    .boxed_local()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn check_request_limits() {
+        let limits = RequestLimits {
+            max_uri_length: 32,
+            max_query_params: 2,
+            max_headers_size: 64,
+        };
+
+        let req = TestRequest::get()
+            .uri("/graph?channel=a&arch=b")
+            .to_http_request();
+        assert_eq!(limits.check(&req), Ok(()));
+
+        let req = TestRequest::get()
+            .uri("/graph?channel=stable-4.14&arch=amd64")
+            .to_http_request();
+        assert_eq!(limits.check(&req), Err(GraphError::UriTooLong(32)));
+
+        let req = TestRequest::get()
+            .uri("/graph?a=1&b=2&c=3")
+            .to_http_request();
+        assert_eq!(limits.check(&req), Err(GraphError::TooManyParams(2)));
+
+        let req = TestRequest::get()
+            .uri("/graph")
+            .insert_header(("x-padding", "x".repeat(64)))
+            .to_http_request();
+        assert_eq!(limits.check(&req), Err(GraphError::HeadersTooLarge(64)));
+    }
+
+    #[test]
+    fn enforce_request_limits() -> Fallible<()> {
+        let rt = crate::testing::init_runtime()?;
+        let limits = RequestLimits {
+            max_query_params: 1,
+            ..Default::default()
+        };
+
+        rt.block_on(async {
+            let app = actix_web::test::init_service(
+                actix_web::App::new()
+                    .wrap_fn(move |req, srv| limits.enforce(req, srv))
+                    .route(
+                        "/graph",
+                        actix_web::web::get().to(actix_web::HttpResponse::Ok),
+                    ),
+            )
+            .await;
+
+            let req = TestRequest::get().uri("/graph?a=1").to_request();
+            let resp = actix_web::test::call_service(&app, req).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+            let req = TestRequest::get().uri("/graph?a=1&b=2").to_request();
+            let resp = actix_web::test::call_service(&app, req).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+            assert_eq!(body["kind"], "too_many_params");
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn limit_requests_in_flight() -> Fallible<()> {
+        let limits = ConcurrencyLimits {
+            endpoints: vec![("/graph".to_string(), 2)].into_iter().collect(),
+            ..Default::default()
+        };
+        limits.validate()?;
+        let limiter = limits.limiter("/api");
+
+        let first = limiter.try_acquire("/api/graph")?;
+        let second = limiter.try_acquire("/api/graph")?;
+        assert!(first.is_some() && second.is_some());
+        assert_eq!(
+            limiter.try_acquire("/api/graph").unwrap_err(),
+            GraphError::Overloaded(2)
+        );
+        assert!(limiter.try_acquire("/api/openapi")?.is_none());
+
+        drop(first);
+        assert!(limiter.try_acquire("/api/graph")?.is_some());
+
+        let limits = ConcurrencyLimits {
+            endpoints: vec![("graph".to_string(), 1)].into_iter().collect(),
+            ..Default::default()
+        };
+        assert!(limits.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn enforce_concurrency_limits() -> Fallible<()> {
+        let rt = crate::testing::init_runtime()?;
+        let limiter = ConcurrencyLimits {
+            endpoints: vec![("/graph".to_string(), 1)].into_iter().collect(),
+            retry_after_secs: 5,
+        }
+        .limiter("");
+        let _in_flight = limiter.try_acquire("/graph")?;
+
+        rt.block_on(async {
+            let app = actix_web::test::init_service(
+                actix_web::App::new()
+                    .wrap_fn(move |req, srv| limiter.enforce(req, srv))
+                    .route(
+                        "/graph",
+                        actix_web::web::get().to(actix_web::HttpResponse::Ok),
+                    ),
+            )
+            .await;
+
+            let req = TestRequest::get().uri("/graph").to_request();
+            let resp = actix_web::test::call_service(&app, req).await;
+            assert_eq!(
+                resp.status(),
+                actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+            );
+            assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "5");
+            let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+            assert_eq!(body["kind"], "overloaded");
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn refill_quota_buckets() {
+        let quota = Quota {
+            requests_per_sec: 2.0,
+            burst: None,
+        };
+        assert_eq!(quota.burst(), 2.0);
+
+        let start = Instant::now();
+        let mut bucket = Bucket::new(&quota, start);
+        assert_eq!(bucket.refill(&quota, start), None);
+        bucket.tokens = 0.0;
+        assert_eq!(
+            bucket.refill(&quota, start),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            bucket.refill(&quota, start + Duration::from_millis(500)),
+            None
+        );
+        assert_eq!(bucket.refill(&quota, start + Duration::from_secs(60)), None);
+        assert_eq!(bucket.tokens, 2.0);
+    }
+
+    #[test]
+    fn limit_request_quotas() -> Fallible<()> {
+        let quota = |burst| Quota {
+            requests_per_sec: 0.001,
+            burst: Some(burst),
+        };
+        let main = RequestQuotas {
+            graph: Some(quota(2)),
+            channel: None,
+        };
+        let tenant = RequestQuotas {
+            graph: None,
+            channel: Some(quota(1)),
+        };
+        main.validate()?;
+        tenant.validate()?;
+        let tenant_channels = QuotaChannels::default();
+        tenant_channels.replace(vec!["stable-4".to_string(), "next-4".to_string()]);
+        let limiter = QuotaLimiter::new(vec![
+            (String::new(), main, QuotaChannels::default()),
+            ("/okd".to_string(), tenant, tenant_channels.clone()),
+        ]);
+
+        assert!(limiter.try_acquire("/v1/graph", "").is_ok());
+        assert!(limiter.try_acquire("/openapi", "").is_ok());
+        let (error, delay) = limiter.try_acquire("/v1/graph", "").unwrap_err();
+        assert_eq!(error, GraphError::QuotaExceeded("graph".to_string()));
+        assert!(delay > Duration::from_secs(60));
+
+        // Requests to the tenant are accounted separately, by channel.
+        assert!(limiter
+            .try_acquire("/okd/v1/graph", "channel=stable-4")
+            .is_ok());
+        assert!(limiter
+            .try_acquire("/okd/v1/graph", "channel=next-4")
+            .is_ok());
+        assert!(limiter.try_acquire("/okd/v1/graph", "arch=amd64").is_ok());
+        assert_eq!(
+            limiter
+                .try_acquire("/okd/v1/graph", "channel=stable-4")
+                .unwrap_err()
+                .0,
+            GraphError::QuotaExceeded("channel 'stable-4'".to_string())
+        );
+
+        // Channels missing from the served graph share a single quota.
+        assert!(limiter
+            .try_acquire("/okd/v1/graph", "channel=junk-1")
+            .is_ok());
+        assert_eq!(
+            limiter
+                .try_acquire("/okd/v1/graph", "channel=junk-2")
+                .unwrap_err()
+                .0,
+            GraphError::QuotaExceeded("unknown channels".to_string())
+        );
+
+        // Channels added to the served graph get a quota of their own.
+        tenant_channels.replace(vec!["stable-4".to_string(), "junk-2".to_string()]);
+        assert!(limiter
+            .try_acquire("/okd/v1/graph", "channel=junk-2")
+            .is_ok());
+        assert!(limiter
+            .try_acquire("/okd/v1/graph", "channel=next-4")
+            .is_err());
+        assert!(limiter.try_acquire("/okd-scos/v1/graph", "").is_err());
+
+        let invalid = RequestQuotas {
+            graph: Some(Quota {
+                requests_per_sec: 0.0,
+                burst: None,
+            }),
+            channel: None,
+        };
+        assert!(invalid.validate().is_err());
+        let invalid = RequestQuotas {
+            graph: None,
+            channel: Some(quota(0)),
+        };
+        assert!(invalid.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn enforce_request_quotas() -> Fallible<()> {
+        let rt = crate::testing::init_runtime()?;
+        let quotas = RequestQuotas {
+            graph: Some(Quota {
+                requests_per_sec: 0.5,
+                burst: Some(1),
+            }),
+            channel: None,
+        };
+        let limiter =
+            QuotaLimiter::new(vec![("/api".to_string(), quotas, QuotaChannels::default())]);
+
+        rt.block_on(async {
+            let app = actix_web::test::init_service(
+                actix_web::App::new()
+                    .wrap_fn(move |req, srv| limiter.enforce(req, srv))
+                    .route(
+                        "/api/graph",
+                        actix_web::web::get().to(actix_web::HttpResponse::Ok),
+                    ),
+            )
+            .await;
+
+            let req = TestRequest::get().uri("/api/graph").to_request();
+            let resp = actix_web::test::call_service(&app, req).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+            let req = TestRequest::get().uri("/api/graph").to_request();
+            let resp = actix_web::test::call_service(&app, req).await;
+            assert_eq!(
+                resp.status(),
+                actix_web::http::StatusCode::TOO_MANY_REQUESTS
+            );
+            assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "2");
+            let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+            assert_eq!(body["kind"], "quota_exceeded");
+        });
+
+        Ok(())
+    }
+}
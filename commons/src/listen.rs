@@ -0,0 +1,77 @@
+//! Listening sockets of the HTTP services.
+//!
+//! A service may listen on several addresses, e.g. an IPv4 and an IPv6 one
+//! on dual-stack clusters, each with a port of its own.
+
+use crate::prelude_errors::*;
+use socket2::{Domain, Socket, Type};
+use std::net::{IpAddr, SocketAddr, TcpListener};
+
+/// Return the listening addresses of a service: `listen` if not empty, the
+/// single `address` and `port` pair otherwise.
+pub fn listen_addrs(listen: &[SocketAddr], address: IpAddr, port: u16) -> Vec<SocketAddr> {
+    if listen.is_empty() {
+        vec![SocketAddr::new(address, port)]
+    } else {
+        listen.to_vec()
+    }
+}
+
+/// Bind a TCP listener on each of `addrs`, with at most `backlog` pending connections.
+///
+/// IPv6 listeners sharing their port with an IPv4 listener only accept IPv6
+/// connections, so that e.g. both `0.0.0.0:8080` and `[::]:8080` can be bound.
+pub fn bind_all(addrs: &[SocketAddr], backlog: u32) -> Fallible<Vec<TcpListener>> {
+    addrs
+        .iter()
+        .map(|addr| {
+            let only_v6 = addr.is_ipv6()
+                && addrs
+                    .iter()
+                    .any(|other| other.is_ipv4() && other.port() == addr.port());
+            bind(addr, only_v6, backlog).context(format!("binding {}", addr))
+        })
+        .collect()
+}
+
+fn bind(addr: &SocketAddr, only_v6: bool, backlog: u32) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+    if only_v6 {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&(*addr).into())?;
+    socket.listen(backlog.min(i32::MAX as u32) as i32)?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn bind_listeners() -> Fallible<()> {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert_eq!(
+            listen_addrs(&[], localhost, 8080),
+            vec![SocketAddr::new(localhost, 8080)]
+        );
+
+        let listeners = bind_all(
+            &[SocketAddr::new(localhost, 0), SocketAddr::new(localhost, 0)],
+            16,
+        )?;
+        let addrs = listeners
+            .iter()
+            .map(TcpListener::local_addr)
+            .collect::<std::io::Result<Vec<_>>>()?;
+        assert_ne!(addrs[0].port(), addrs[1].port());
+        assert_eq!(listen_addrs(&addrs, localhost, 8080), addrs);
+
+        // An address can't be bound twice.
+        assert!(bind_all(&[addrs[0]], 16).is_err());
+
+        Ok(())
+    }
+}
@@ -2,7 +2,39 @@
 
 use crate::prelude_errors::*;
 use actix_web::HttpResponse;
-use prometheus::{self, Registry};
+use prometheus::core::{
+    AtomicF64, AtomicU64, Collector, CounterVecBuilder, GaugeVecBuilder, MetricVec,
+    MetricVecBuilder,
+};
+use prometheus::proto::MetricFamily;
+use prometheus::{self, IntCounterVec, Opts, Registry};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Label value of the series aggregating the label sets over the limit of a
+/// `GuardedVec`.
+pub static OVERFLOW_LABEL: &str = "other";
+
+/// Default maximum number of label sets of a `GuardedVec`.
+pub const DEFAULT_MAX_LABEL_SETS: usize = 500;
+
+lazy_static! {
+    static ref LABEL_OVERFLOWS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "metric_label_overflows_total",
+            "Total number of observations recorded in the overflow series of a metric, by metric"
+        ),
+        &["metric"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(LABEL_OVERFLOWS.clone()))?;
+    Ok(())
+}
 
 /// For types that store a static Registry reference
 pub trait HasRegistry {
@@ -37,7 +69,22 @@ where
 
 /// Create a custom Prometheus registry.
 pub fn new_registry(prefix: Option<String>) -> Fallible<Registry> {
-    Registry::new_custom(prefix.clone(), None).map_err(|e| {
+    new_labeled_registry(prefix, &BTreeMap::new())
+}
+
+/// Create a custom Prometheus registry, adding the static `labels` to every
+/// metric it exports.
+pub fn new_labeled_registry(
+    prefix: Option<String>,
+    labels: &BTreeMap<String, String>,
+) -> Fallible<Registry> {
+    validate_labels(labels)?;
+    let labels = if labels.is_empty() {
+        None
+    } else {
+        Some(labels.clone().into_iter().collect())
+    };
+    Registry::new_custom(prefix.clone(), labels).map_err(|e| {
         format_err!(format!(
             "could not create a custom regostry with prefix {:?}: {}",
             prefix,
@@ -46,6 +93,172 @@ pub fn new_registry(prefix: Option<String>) -> Fallible<Registry> {
     })
 }
 
+/// Check that static metric labels have valid names and non-empty values.
+pub fn validate_labels(labels: &BTreeMap<String, String>) -> Fallible<()> {
+    for (name, value) in labels {
+        let mut chars = name.chars();
+        let valid = match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            }
+            _ => false,
+        };
+        ensure!(
+            valid && !name.starts_with("__"),
+            "invalid metric label name '{}'",
+            name
+        );
+        ensure!(!value.is_empty(), "empty value for metric label '{}'", name);
+    }
+    Ok(())
+}
+
+/// Label sets tracked by a `GuardedVec`.
+#[derive(Debug, Default)]
+struct GuardState {
+    /// Tracked label sets, with the time they were last used.
+    label_sets: HashMap<Vec<String>, Instant>,
+    /// Whether an overflowing label set was logged since the last pruning.
+    warned: bool,
+}
+
+/// Metric vector bounding the number of its label sets.
+///
+/// Label sets beyond `max_label_sets` are recorded in a single series with
+/// all labels set to `OVERFLOW_LABEL`, so that labels driven by clients or
+/// by the graph, e.g. request paths or channels, cannot blow up the number
+/// of series. Label sets unused for the idle timeout, if any, are pruned
+/// to make room for new ones.
+#[derive(Clone)]
+pub struct GuardedVec<T: MetricVecBuilder> {
+    vec: MetricVec<T>,
+    name: String,
+    max_label_sets: usize,
+    idle_timeout: Option<Duration>,
+    state: Arc<Mutex<GuardState>>,
+}
+
+impl<T: MetricVecBuilder> GuardedVec<T> {
+    /// Guard `vec`, tracking at most `max_label_sets` label sets.
+    pub fn new(vec: MetricVec<T>, max_label_sets: usize) -> Self {
+        let name = vec
+            .desc()
+            .first()
+            .map(|desc| desc.fq_name.clone())
+            .unwrap_or_default();
+        Self {
+            vec,
+            name,
+            max_label_sets,
+            idle_timeout: None,
+            state: Default::default(),
+        }
+    }
+
+    /// Prune the label sets unused for `timeout` when the limit is reached.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Return whether `values` are recorded in a series of their own,
+    /// tracking them if below the limit.
+    pub fn track(&self, values: &[&str]) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let key: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        if let Some(last_used) = state.label_sets.get_mut(&key) {
+            *last_used = now;
+            return true;
+        }
+
+        if state.label_sets.len() >= self.max_label_sets {
+            if let Some(timeout) = self.idle_timeout {
+                self.prune_locked(&mut state, now, timeout);
+            }
+        }
+        if state.label_sets.len() < self.max_label_sets {
+            state.label_sets.insert(key, now);
+            return true;
+        }
+
+        LABEL_OVERFLOWS.with_label_values(&[&self.name]).inc();
+        if state.warned {
+            log::debug!(
+                "recording labels {:?} of '{}' as overflow",
+                values,
+                self.name
+            );
+        } else {
+            state.warned = true;
+            log::warn!(
+                "metric '{}' reached its limit of {} label sets, recording labels {:?} and further ones as '{}'",
+                self.name, self.max_label_sets, values, OVERFLOW_LABEL
+            );
+        }
+        false
+    }
+
+    /// Return the series of `values`, or the overflow one if over the limit.
+    pub fn with_label_values(&self, values: &[&str]) -> T::M {
+        if self.track(values) {
+            self.vec.with_label_values(values)
+        } else {
+            self.vec
+                .with_label_values(&vec![OVERFLOW_LABEL; values.len()])
+        }
+    }
+
+    /// Remove the series of `values`, if any.
+    pub fn remove_label_values(&self, values: &[&str]) {
+        let key: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.label_sets.remove(&key);
+        let _ = self.vec.remove_label_values(values);
+    }
+
+    /// Remove the series unused for `timeout`, returning how many were removed.
+    pub fn prune(&self, timeout: Duration) -> usize {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        self.prune_locked(&mut state, Instant::now(), timeout)
+    }
+
+    fn prune_locked(&self, state: &mut GuardState, now: Instant, timeout: Duration) -> usize {
+        let idle: Vec<Vec<String>> = state
+            .label_sets
+            .iter()
+            .filter(|(_, last_used)| now.duration_since(**last_used) >= timeout)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &idle {
+            state.label_sets.remove(key);
+            let values: Vec<&str> = key.iter().map(String::as_str).collect();
+            let _ = self.vec.remove_label_values(&values);
+        }
+        if !idle.is_empty() {
+            log::debug!("pruned {} idle label sets of '{}'", idle.len(), self.name);
+            state.warned = false;
+        }
+        idle.len()
+    }
+}
+
+/// Guarded `IntCounterVec`.
+pub type GuardedIntCounterVec = GuardedVec<CounterVecBuilder<AtomicU64>>;
+
+/// Guarded `GaugeVec`.
+pub type GuardedGaugeVec = GuardedVec<GaugeVecBuilder<AtomicF64>>;
+
+impl<T: MetricVecBuilder + 'static> Collector for GuardedVec<T> {
+    fn desc(&self) -> Vec<&prometheus::core::Desc> {
+        self.vec.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.vec.collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -82,4 +295,127 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn serve_metrics_labeled() -> Fallible<()> {
+        let rt = testing::init_runtime()?;
+
+        let labels: BTreeMap<String, String> = [("environment", "prod"), ("region", "eu")]
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let registry_wrapped = RegistryWrapper(Box::leak(Box::new(new_labeled_registry(
+            Some("cincinnati".to_string()),
+            &labels,
+        )?)));
+
+        testing::dummy_gauge(registry_wrapped.0, 42.0)?;
+
+        let metrics_call = serve::<RegistryWrapper>(actix_web::web::Data::new(registry_wrapped));
+        let resp = rt.block_on(metrics_call);
+
+        assert_eq!(resp.status(), 200);
+        let bytes = match resp.into_body().try_into_bytes() {
+            Ok(bytes) => bytes,
+            Err(_) => bail!("expected bytes in body"),
+        };
+        // Static labels are exported in no particular order.
+        let sample = std::str::from_utf8(bytes.as_ref())?
+            .lines()
+            .find(|line| line.starts_with("cincinnati_dummy_gauge{"))
+            .ok_or_else(|| format_err!("missing labeled sample"))?;
+        assert!(sample.contains(r#"environment="prod""#), "{}", sample);
+        assert!(sample.contains(r#"region="eu""#), "{}", sample);
+        assert!(sample.ends_with("} 42"), "{}", sample);
+
+        Ok(())
+    }
+
+    /// Return the value of the counter of `path`, if any.
+    fn counter<T: MetricVecBuilder + 'static>(guarded: &GuardedVec<T>, path: &str) -> Option<u64> {
+        guarded
+            .collect()
+            .into_iter()
+            .flat_map(|family| family.get_metric().to_vec())
+            .find(|metric| metric.get_label()[0].get_value() == path)
+            .map(|metric| metric.get_counter().get_value() as u64)
+    }
+
+    #[test]
+    fn guard_label_sets() -> Fallible<()> {
+        let guarded = GuardedVec::new(
+            IntCounterVec::new(Opts::new("guarded_requests_total", "Requests"), &["path"])?,
+            2,
+        );
+
+        for path in &[
+            "/graph",
+            "/graph",
+            "/v1/graph",
+            "/unknown",
+            "/other/unknown",
+        ] {
+            guarded.with_label_values(&[path]).inc();
+        }
+        assert_eq!(counter(&guarded, "/graph"), Some(2));
+        assert_eq!(counter(&guarded, "/v1/graph"), Some(1));
+        assert_eq!(counter(&guarded, "/unknown"), None);
+        assert_eq!(counter(&guarded, OVERFLOW_LABEL), Some(2));
+        assert!(guarded.track(&["/graph"]));
+        assert!(!guarded.track(&["/unknown"]));
+
+        // Removed and pruned label sets make room for new ones.
+        guarded.remove_label_values(&["/v1/graph"]);
+        assert_eq!(counter(&guarded, "/v1/graph"), None);
+        guarded.with_label_values(&["/unknown"]).inc();
+        assert_eq!(counter(&guarded, "/unknown"), Some(1));
+        assert_eq!(guarded.prune(Duration::from_secs(0)), 2);
+        assert_eq!(counter(&guarded, "/graph"), None);
+        assert!(guarded.track(&["/v1/graph"]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn prune_idle_label_sets() -> Fallible<()> {
+        let guarded = GuardedVec::new(
+            IntCounterVec::new(Opts::new("idle_requests_total", "Requests"), &["path"])?,
+            1,
+        )
+        .idle_timeout(Duration::from_millis(10));
+
+        assert!(guarded.track(&["/graph"]));
+        assert!(!guarded.track(&["/v1/graph"]));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(guarded.track(&["/v1/graph"]));
+        assert!(!guarded.track(&["/graph"]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_metric_labels() {
+        let labels = |name: &str, value: &str| -> BTreeMap<String, String> {
+            vec![(name.to_string(), value.to_string())]
+                .into_iter()
+                .collect()
+        };
+
+        validate_labels(&labels("tenant", "acme")).unwrap();
+        validate_labels(&labels("_env2", "prod")).unwrap();
+        for (name, value) in &[
+            ("", "prod"),
+            ("2env", "prod"),
+            ("env-name", "prod"),
+            ("__name__", "prod"),
+            ("env", ""),
+        ] {
+            assert!(
+                validate_labels(&labels(name, value)).is_err(),
+                "{}={}",
+                name,
+                value
+            );
+        }
+    }
 }
@@ -0,0 +1,266 @@
+//! Constraints on the values of client parameters.
+//!
+//! Mandatory client parameters can declare the values they accept: a regular
+//! expression matching the whole value, a list of allowed values, or a well
+//! known format. Every occurrence of a constrained parameter in the query
+//! string is checked, and the constraints are advertised in the schema of the
+//! parameter in the OpenAPI document.
+
+use crate::errors::{ensure, Context, Fallible};
+use crate::GraphError;
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use url::form_urlencoded;
+
+/// Well known format of a parameter value.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParamFormat {
+    /// Hyphenated UUID, e.g. `01234567-89ab-cdef-0123-456789abcdef`.
+    Uuid,
+}
+
+impl ParamFormat {
+    /// Name of the format in OpenAPI schemas.
+    fn name(self) -> &'static str {
+        match self {
+            ParamFormat::Uuid => "uuid",
+        }
+    }
+
+    /// Check whether `value` is in this format.
+    fn accepts(self, value: &str) -> bool {
+        match self {
+            ParamFormat::Uuid => {
+                value.len() == 36
+                    && value.char_indices().all(|(i, c)| match i {
+                        8 | 13 | 18 | 23 => c == '-',
+                        _ => c.is_ascii_hexdigit(),
+                    })
+            }
+        }
+    }
+}
+
+/// Accepted values of a client parameter, as configured.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ParamConstraint {
+    /// Regular expression which must match the whole value.
+    pub pattern: Option<String>,
+    /// Allowed values.
+    pub values: Option<Vec<String>>,
+    /// Format of the value.
+    pub format: Option<ParamFormat>,
+}
+
+/// Compiled constraint of a client parameter.
+#[derive(Clone, Debug)]
+struct Rule {
+    pattern: Option<Regex>,
+    values: Option<Vec<String>>,
+    format: Option<ParamFormat>,
+}
+
+impl Rule {
+    fn try_new(name: &str, constraint: &ParamConstraint) -> Fallible<Self> {
+        ensure!(
+            constraint.pattern.is_some()
+                || constraint.values.is_some()
+                || constraint.format.is_some(),
+            "no pattern, values or format declared for client parameter '{}'",
+            name
+        );
+        if let Some(values) = &constraint.values {
+            ensure!(
+                !values.is_empty(),
+                "empty list of values for client parameter '{}'",
+                name
+            );
+        }
+        let pattern = constraint
+            .pattern
+            .as_ref()
+            .map(|pattern| {
+                Regex::new(&format!("^(?:{})$", pattern))
+                    .context(format!("invalid pattern for client parameter '{}'", name))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            pattern,
+            values: constraint.values.clone(),
+            format: constraint.format,
+        })
+    }
+
+    fn accepts(&self, value: &str) -> bool {
+        self.pattern.as_ref().map_or(true, |re| re.is_match(value))
+            && self
+                .values
+                .as_ref()
+                .map_or(true, |values| values.iter().any(|v| v == value))
+            && self.format.map_or(true, |format| format.accepts(value))
+    }
+}
+
+/// Constraints on the values of client parameters.
+#[derive(Clone, Debug, Default)]
+pub struct ParamConstraints {
+    rules: BTreeMap<String, Rule>,
+}
+
+impl ParamConstraints {
+    /// Compile the `constraints` of client parameters.
+    pub fn try_new(constraints: &BTreeMap<String, ParamConstraint>) -> Fallible<Self> {
+        let rules = constraints
+            .iter()
+            .map(|(name, constraint)| Ok((name.clone(), Rule::try_new(name, constraint)?)))
+            .collect::<Fallible<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Check that all parameters are among the `mandatory` ones, and that
+    /// their constraints are valid.
+    pub fn validate(
+        constraints: &BTreeMap<String, ParamConstraint>,
+        mandatory: &HashSet<String>,
+    ) -> Fallible<()> {
+        for name in constraints.keys() {
+            ensure!(
+                mandatory.contains(name),
+                "constraint declared for client parameter '{}', which is not mandatory",
+                name
+            );
+        }
+        Self::try_new(constraints).map(|_| ())
+    }
+
+    /// Whether no parameter is constrained.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Make sure the values of the constrained parameters of `query` are accepted.
+    pub fn ensure_query_values(&self, query: &str) -> Result<(), GraphError> {
+        if self.rules.is_empty() {
+            return Ok(());
+        }
+
+        let mut invalid: Vec<String> = form_urlencoded::parse(query.as_bytes())
+            .filter(|(key, value)| match self.rules.get(key.as_ref()) {
+                Some(rule) => !rule.accepts(value),
+                None => false,
+            })
+            .map(|(key, _)| key.into_owned())
+            .collect();
+        if !invalid.is_empty() {
+            invalid.sort();
+            invalid.dedup();
+            return Err(GraphError::InvalidParams(format!(
+                "unexpected value of {}",
+                invalid.join(", ")
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Return the OpenAPI schema of the parameter `name`.
+    pub fn schema(&self, name: &str) -> serde_json::Value {
+        let mut schema = serde_json::json!({ "type": "string" });
+        if let Some(rule) = self.rules.get(name) {
+            if let Some(pattern) = &rule.pattern {
+                schema["pattern"] = pattern.as_str().into();
+            }
+            if let Some(values) = &rule.values {
+                schema["enum"] = values.clone().into();
+            }
+            if let Some(format) = rule.format {
+                schema["format"] = format.name().into();
+            }
+        }
+        schema
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constraints(json: &str) -> BTreeMap<String, ParamConstraint> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn check_query_values() -> Fallible<()> {
+        let constraints = ParamConstraints::try_new(&constraints(
+            r#"{
+                "channel": { "pattern": "[a-z]+-4\\.[0-9]+" },
+                "arch": { "values": ["amd64", "arm64"] },
+                "id": { "format": "uuid" }
+            }"#,
+        ))?;
+
+        constraints.ensure_query_values(
+            "channel=stable-4.14&arch=arm64&id=01234567-89ab-cdef-0123-456789ABCDEF&other=x",
+        )?;
+        // Unconstrained or absent parameters are not checked.
+        constraints.ensure_query_values("channel=fast-4.15")?;
+
+        let err = constraints
+            .ensure_query_values("channel=stable-4.14-extra&arch=s390x&arch=amd64&id=1234")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid client parameters: unexpected value of arch, channel, id"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_constraints() {
+        let mandatory: HashSet<String> = vec!["channel".to_string()].into_iter().collect();
+        ParamConstraints::validate(
+            &constraints(r#"{"channel": {"format": "uuid"}}"#),
+            &mandatory,
+        )
+        .unwrap();
+
+        for invalid in &[
+            r#"{"arch": {"format": "uuid"}}"#,
+            r#"{"channel": {}}"#,
+            r#"{"channel": {"values": []}}"#,
+            r#"{"channel": {"pattern": "("}}"#,
+        ] {
+            assert!(
+                ParamConstraints::validate(&constraints(invalid), &mandatory).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn param_schema() -> Fallible<()> {
+        let constraints = ParamConstraints::try_new(&constraints(
+            r#"{"channel": {"pattern": "stable-.*", "values": ["stable-4.14"]}}"#,
+        ))?;
+
+        assert_eq!(
+            constraints.schema("channel"),
+            serde_json::json!({
+                "type": "string",
+                "pattern": "^(?:stable-.*)$",
+                "enum": ["stable-4.14"]
+            })
+        );
+        assert_eq!(
+            constraints.schema("id"),
+            serde_json::json!({ "type": "string" })
+        );
+
+        Ok(())
+    }
+}
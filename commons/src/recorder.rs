@@ -0,0 +1,532 @@
+//! Record-and-replay of HTTP interactions with upstream services.
+//!
+//! A [`Recorder`] is a local HTTP server fronting a single upstream, e.g. a
+//! container registry, the GitHub API or Prometheus. Clients under test are
+//! pointed at [`Recorder::url`] instead of the upstream.
+//!
+//! In [`Mode::Record`] requests are forwarded to the upstream and every
+//! interaction is written to a [`Cassette`] file. In [`Mode::Replay`] the
+//! responses are served from the cassette, without any network access, which
+//! allows turning real-world scrape failures into deterministic regression
+//! tests. The mode can be selected at runtime via [`MODE_ENV`], so cassettes
+//! can be refreshed by re-running the tests against the live upstream.
+//!
+//! Request headers and bodies are never recorded, so credentials sent to the
+//! upstream don't end up in cassettes.
+
+use crate::prelude_errors::*;
+use actix_web::dev::ServerHandle;
+use actix_web::http::StatusCode;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use base64::Engine;
+use log::{debug, error};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Environment variable selecting the mode of [`Recorder::from_env`], either
+/// `record` or `replay`.
+pub static MODE_ENV: &str = "CINCINNATI_HTTP_RECORDER_MODE";
+
+/// Headers which are not forwarded nor recorded.
+static HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "host",
+    "keep-alive",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Response headers which may contain absolute upstream URLs.
+static URL_HEADERS: &[&str] = &["link", "location", "www-authenticate"];
+
+/// Mode of operation of a [`Recorder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Forward requests to the upstream at the given base URL and record them.
+    Record {
+        /// Base URL of the upstream.
+        upstream: String,
+    },
+    /// Serve previously recorded responses.
+    Replay,
+}
+
+/// A recorded request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    /// HTTP method.
+    pub method: String,
+    /// Path and query, relative to the upstream base URL.
+    pub path: String,
+}
+
+/// A recorded response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    /// HTTP status code.
+    pub status: u16,
+    /// Response headers, in order.
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    /// Body, if it is valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Base64 encoded body, if it is not valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_base64: Option<String>,
+}
+
+impl RecordedResponse {
+    /// Create a response, storing the body in its most readable form.
+    pub fn new(status: u16, headers: Vec<(String, String)>, body: Vec<u8>) -> Self {
+        let (body, body_base64) = match String::from_utf8(body) {
+            Ok(body) => (Some(body), None),
+            Err(e) => (
+                None,
+                Some(base64::engine::general_purpose::STANDARD.encode(e.as_bytes())),
+            ),
+        };
+        Self {
+            status,
+            headers,
+            body,
+            body_base64,
+        }
+    }
+
+    /// Decode the body.
+    pub fn body_bytes(&self) -> Fallible<Vec<u8>> {
+        match (&self.body, &self.body_base64) {
+            (Some(body), _) => Ok(body.clone().into_bytes()),
+            (None, Some(encoded)) => base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .context("decoding recorded body"),
+            (None, None) => Ok(vec![]),
+        }
+    }
+}
+
+/// A recorded request together with its response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interaction {
+    /// The request.
+    pub request: RecordedRequest,
+    /// The response sent by the upstream.
+    pub response: RecordedResponse,
+}
+
+/// Interactions recorded during a run, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cassette {
+    /// The recorded interactions.
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    /// Load a cassette from a JSON file.
+    pub fn load(path: &Path) -> Fallible<Self> {
+        let file = std::fs::File::open(path).context(format!("opening cassette {:?}", path))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .context(format!("parsing cassette {:?}", path))
+    }
+
+    /// Save the cassette to a JSON file, creating parent directories as needed.
+    pub fn save(&self, path: &Path) -> Fallible<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).context(format!("creating {:?}", parent))?;
+        }
+        let json = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, json).context(format!("writing cassette {:?}", path))
+    }
+
+    /// Find the response to replay for a request.
+    ///
+    /// Interactions are replayed in recording order, each once. When all
+    /// matching interactions have been replayed, the last one is served again,
+    /// so clients polling the same resource keep working.
+    fn replay(&self, request: &RecordedRequest, replayed: &mut HashSet<usize>) -> Option<usize> {
+        let mut matching = self
+            .interactions
+            .iter()
+            .enumerate()
+            .filter(|(_, interaction)| &interaction.request == request)
+            .map(|(index, _)| index)
+            .peekable();
+
+        let mut last = None;
+        while let Some(index) = matching.next() {
+            if replayed.insert(index) {
+                return Some(index);
+            }
+            if matching.peek().is_none() {
+                last = Some(index);
+            }
+        }
+        last
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    mode: Mode,
+    client: reqwest::Client,
+    cassette: Mutex<Cassette>,
+    replayed: Mutex<HashSet<usize>>,
+    base_url: Mutex<String>,
+}
+
+/// A running recorder, stopped when dropped.
+///
+/// In record mode the cassette is saved when the recorder is dropped.
+#[derive(Debug)]
+pub struct Recorder {
+    addr: SocketAddr,
+    handle: ServerHandle,
+    state: Arc<State>,
+    cassette_path: PathBuf,
+    thread: Option<std::thread::JoinHandle<std::io::Result<()>>>,
+}
+
+impl Recorder {
+    /// Record interactions with `upstream` to the cassette at `cassette_path`.
+    pub fn record(upstream: &str, cassette_path: &Path) -> Fallible<Self> {
+        Self::start(
+            Mode::Record {
+                upstream: upstream.trim_end_matches('/').to_string(),
+            },
+            Cassette::default(),
+            cassette_path,
+        )
+    }
+
+    /// Replay interactions from the cassette at `cassette_path`.
+    pub fn replay(cassette_path: &Path) -> Fallible<Self> {
+        Self::start(Mode::Replay, Cassette::load(cassette_path)?, cassette_path)
+    }
+
+    /// Record or replay, depending on [`MODE_ENV`]. Replays by default.
+    pub fn from_env(upstream: &str, cassette_path: &Path) -> Fallible<Self> {
+        match std::env::var(MODE_ENV).as_deref() {
+            Ok("record") => Self::record(upstream, cassette_path),
+            Ok("replay") | Err(std::env::VarError::NotPresent) => Self::replay(cassette_path),
+            Ok(other) => bail!(
+                "invalid {} '{}', expected 'record' or 'replay'",
+                MODE_ENV,
+                other
+            ),
+            Err(e) => bail!("reading {}: {}", MODE_ENV, e),
+        }
+    }
+
+    fn start(mode: Mode, cassette: Cassette, cassette_path: &Path) -> Fallible<Self> {
+        let state = Arc::new(State {
+            mode,
+            client: reqwest::Client::new(),
+            cassette: Mutex::new(cassette),
+            replayed: Mutex::new(HashSet::new()),
+            base_url: Mutex::new(String::new()),
+        });
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let server_state = state.clone();
+        let thread = std::thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                let data = web::Data::from(server_state);
+                let server = HttpServer::new(move || {
+                    App::new()
+                        .app_data(data.clone())
+                        .default_service(web::to(handle))
+                })
+                .workers(1)
+                .bind(("127.0.0.1", 0));
+
+                let server = match server {
+                    Ok(server) => server,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return Ok(());
+                    }
+                };
+                let addr = server.addrs()[0];
+                let server = server.run();
+                let _ = tx.send(Ok((addr, server.handle())));
+                server.await
+            })
+        });
+
+        let (addr, handle) = rx
+            .recv()
+            .context("recorder thread exited before starting")?
+            .context("binding recorder")?;
+        *state
+            .base_url
+            .lock()
+            .map_err(|_| format_err!("recorder state poisoned"))? = format!("http://{}", addr);
+        debug!(
+            "HTTP recorder in {:?} mode listening on {}",
+            state.mode, addr
+        );
+
+        Ok(Self {
+            addr,
+            handle,
+            state,
+            cassette_path: cassette_path.to_path_buf(),
+            thread: Some(thread),
+        })
+    }
+
+    /// Base URL to point clients at, instead of the upstream.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Address of the recorder, as `host:port`.
+    pub fn host_port(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// Interactions recorded or loaded so far.
+    pub fn cassette(&self) -> Cassette {
+        self.state
+            .cassette
+            .lock()
+            .map(|cassette| cassette.clone())
+            .unwrap_or_default()
+    }
+
+    /// Save the cassette, if recording.
+    pub fn save(&self) -> Fallible<()> {
+        if let Mode::Record { .. } = self.state.mode {
+            self.cassette().save(&self.cassette_path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        futures::executor::block_on(self.handle.stop(false));
+        if let Some(thread) = self.thread.take() {
+            if let Err(e) = thread
+                .join()
+                .map_err(|_| format_err!("recorder thread panicked"))
+                .and_then(|result| result.map_err(Into::into))
+            {
+                error!("stopping HTTP recorder: {}", e);
+            }
+        }
+        if let Err(e) = self.save() {
+            error!("saving cassette {:?}: {}", self.cassette_path, e);
+        }
+    }
+}
+
+async fn handle(req: HttpRequest, body: web::Bytes, state: web::Data<State>) -> HttpResponse {
+    let request = RecordedRequest {
+        method: req.method().to_string(),
+        path: req
+            .uri()
+            .path_and_query()
+            .map(|path| path.to_string())
+            .unwrap_or_else(|| req.uri().path().to_string()),
+    };
+
+    let response = match &state.mode {
+        Mode::Record { upstream } => forward(&state, upstream, &req, body, &request).await,
+        Mode::Replay => replay(&state, &request),
+    };
+
+    match response.and_then(|response| to_http_response(&response)) {
+        Ok(response) => response,
+        Err(e) => {
+            error!("{} {}: {:#}", request.method, request.path, e);
+            HttpResponse::BadGateway().body(format!("{:#}", e))
+        }
+    }
+}
+
+async fn forward(
+    state: &State,
+    upstream: &str,
+    req: &HttpRequest,
+    body: web::Bytes,
+    request: &RecordedRequest,
+) -> Fallible<RecordedResponse> {
+    let url = format!("{}{}", upstream, request.path);
+    let method = reqwest::Method::from_bytes(request.method.as_bytes())?;
+
+    let mut upstream_request = state.client.request(method, &url).body(body.to_vec());
+    for (name, value) in req.headers() {
+        if !HOP_BY_HOP_HEADERS.contains(&name.as_str()) {
+            upstream_request = upstream_request.header(name.as_str(), value.as_bytes());
+        }
+    }
+
+    let upstream_response = upstream_request
+        .send()
+        .await
+        .context(format!("forwarding request to {}", url))?;
+
+    let base_url = state
+        .base_url
+        .lock()
+        .map_err(|_| format_err!("recorder state poisoned"))?
+        .clone();
+    let status = upstream_response.status().as_u16();
+    let headers = upstream_response
+        .headers()
+        .iter()
+        .filter(|(name, _)| !HOP_BY_HOP_HEADERS.contains(&name.as_str()))
+        .filter_map(|(name, value)| {
+            let value = value.to_str().ok()?;
+            let value = if URL_HEADERS.contains(&name.as_str()) {
+                value.replace(upstream, &base_url)
+            } else {
+                value.to_string()
+            };
+            Some((name.to_string(), value))
+        })
+        .collect();
+    let body = upstream_response
+        .bytes()
+        .await
+        .context(format!("reading response from {}", url))?;
+
+    let response = RecordedResponse::new(status, headers, body.to_vec());
+    state
+        .cassette
+        .lock()
+        .map_err(|_| format_err!("recorder state poisoned"))?
+        .interactions
+        .push(Interaction {
+            request: request.clone(),
+            response: response.clone(),
+        });
+
+    Ok(response)
+}
+
+fn replay(state: &State, request: &RecordedRequest) -> Fallible<RecordedResponse> {
+    let cassette = state
+        .cassette
+        .lock()
+        .map_err(|_| format_err!("recorder state poisoned"))?;
+    let mut replayed = state
+        .replayed
+        .lock()
+        .map_err(|_| format_err!("recorder state poisoned"))?;
+
+    let index = cassette
+        .replay(request, &mut replayed)
+        .ok_or_else(|| format_err!("no recorded interaction"))?;
+    Ok(cassette.interactions[index].response.clone())
+}
+
+fn to_http_response(response: &RecordedResponse) -> Fallible<HttpResponse> {
+    let mut builder = HttpResponse::build(StatusCode::from_u16(response.status)?);
+    for (name, value) in &response.headers {
+        builder.append_header((name.as_str(), value.as_str()));
+    }
+    Ok(builder.body(response.body_bytes()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(url: &str) -> Fallible<(u16, Vec<u8>)> {
+        let runtime = crate::testing::init_runtime()?;
+        runtime.block_on(async {
+            let response = reqwest::get(url).await?;
+            let status = response.status().as_u16();
+            Ok((status, response.bytes().await?.to_vec()))
+        })
+    }
+
+    #[test]
+    fn record_and_replay() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let cassette_path = dir.path().join("cassettes/upstream.json");
+
+        let mut upstream = mockito::Server::new();
+        let text = upstream
+            .mock("GET", "/text?page=1")
+            .with_status(200)
+            .with_header("content-type", "text/plain")
+            .with_body("hello")
+            .create();
+        let binary = upstream
+            .mock("GET", "/binary")
+            .with_status(404)
+            .with_body(vec![0u8, 159, 146, 150])
+            .create();
+
+        {
+            let recorder = Recorder::record(&upstream.url(), &cassette_path)?;
+            assert_eq!(
+                get(&format!("{}/text?page=1", recorder.url()))?,
+                (200, b"hello".to_vec())
+            );
+            assert_eq!(
+                get(&format!("{}/binary", recorder.url()))?,
+                (404, vec![0, 159, 146, 150])
+            );
+        }
+        text.assert();
+        binary.assert();
+
+        let cassette = Cassette::load(&cassette_path)?;
+        assert_eq!(cassette.interactions.len(), 2);
+        assert_eq!(
+            cassette.interactions[0].response.body.as_deref(),
+            Some("hello")
+        );
+        assert!(cassette.interactions[1].response.body_base64.is_some());
+
+        drop(upstream);
+        let recorder = Recorder::replay(&cassette_path)?;
+        assert_eq!(
+            get(&format!("{}/text?page=1", recorder.url()))?,
+            (200, b"hello".to_vec())
+        );
+        assert_eq!(
+            get(&format!("{}/binary", recorder.url()))?,
+            (404, vec![0, 159, 146, 150])
+        );
+        assert_eq!(get(&format!("{}/text", recorder.url()))?.0, 502);
+
+        Ok(())
+    }
+
+    #[test]
+    fn replay_in_order_and_repeat_last() {
+        let interaction = |path: &str, body: &str| Interaction {
+            request: RecordedRequest {
+                method: "GET".to_string(),
+                path: path.to_string(),
+            },
+            response: RecordedResponse::new(200, vec![], body.as_bytes().to_vec()),
+        };
+        let cassette = Cassette {
+            interactions: vec![
+                interaction("/a", "first"),
+                interaction("/b", "other"),
+                interaction("/a", "second"),
+            ],
+        };
+        let request = RecordedRequest {
+            method: "GET".to_string(),
+            path: "/a".to_string(),
+        };
+
+        let mut replayed = HashSet::new();
+        let replays: Vec<Option<usize>> = (0..3)
+            .map(|_| cassette.replay(&request, &mut replayed))
+            .collect();
+        assert_eq!(replays, vec![Some(0), Some(2), Some(2)]);
+    }
+}
@@ -0,0 +1,399 @@
+//! In-process end-to-end test harness.
+//!
+//! [`Harness`] starts a mock registry serving [`Fixtures`], a graph-builder
+//! scraping it, and a policy-engine using that graph-builder as upstream, all
+//! inside the test process and on free local ports. Each service is reached
+//! through a [`Service`] handle, so that cross-service behaviors, such as
+//! conditional requests, upstream failures or stale graphs, can be tested
+//! without external orchestration.
+//!
+//! Services run until the harness is dropped. Handles are blocking, and must
+//! not be used from within an async runtime.
+
+#![deny(missing_docs)]
+
+#[macro_use]
+extern crate log;
+
+pub use mock_registry::{Fixtures, MockRegistry, ReleaseImage};
+
+use actix_web::rt::System;
+use anyhow::{bail, format_err, Context, Result};
+use reqwest::blocking::{Client, Response};
+use reqwest::header;
+use std::future::Future;
+use std::net::{Ipv4Addr, TcpListener};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Default repository of the release images.
+pub static DEFAULT_REPOSITORY: &str = "openshift/release";
+
+/// Pause between two polls of a readiness endpoint.
+static READINESS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Builder of a [`Harness`].
+#[derive(Debug)]
+pub struct HarnessBuilder {
+    fixtures: Fixtures,
+    repository: String,
+    graph_builder_config: String,
+    policy_engine_config: String,
+}
+
+impl HarnessBuilder {
+    /// Serve `fixtures` from the mock registry.
+    pub fn new(fixtures: Fixtures) -> Self {
+        Self {
+            fixtures,
+            repository: DEFAULT_REPOSITORY.to_string(),
+            graph_builder_config: String::new(),
+            policy_engine_config: String::new(),
+        }
+    }
+
+    /// Scrape the release images of `repository`.
+    pub fn repository(mut self, repository: &str) -> Self {
+        self.repository = repository.to_string();
+        self
+    }
+
+    /// Merge the TOML configuration `config` into that of the graph-builder.
+    ///
+    /// Tables are merged recursively, and other values, including arrays
+    /// such as `plugin_settings`, replace the generated ones.
+    pub fn graph_builder_config(mut self, config: &str) -> Self {
+        self.graph_builder_config = config.to_string();
+        self
+    }
+
+    /// Merge the TOML configuration `config` into that of the policy-engine,
+    /// like [`HarnessBuilder::graph_builder_config`].
+    pub fn policy_engine_config(mut self, config: &str) -> Self {
+        self.policy_engine_config = config.to_string();
+        self
+    }
+
+    /// Start the mock registry, the graph-builder and the policy-engine.
+    ///
+    /// This does not wait for the services to be ready, see
+    /// [`Harness::wait_ready`].
+    pub fn start(self) -> Result<Harness> {
+        let registry = MockRegistry::start(self.fixtures)?;
+
+        let (gb_port, gb_public_port, gb_status_port) = (free_port()?, free_port()?, free_port()?);
+        let graph_builder_config = merge_config(
+            &format!(
+                r#"
+                    [service]
+                    address = "127.0.0.1"
+                    port = {}
+                    public_port = {}
+                    pause_secs = 1
+
+                    [status]
+                    address = "127.0.0.1"
+                    port = {}
+
+                    [[plugin_settings]]
+                    name = "release-scrape-dockerv2"
+                    registry = "{}"
+                    repository = "{}"
+                "#,
+                gb_port,
+                gb_public_port,
+                gb_status_port,
+                registry.url(),
+                self.repository,
+            ),
+            &self.graph_builder_config,
+        )?;
+        let settings = graph_builder::config::AppSettings::try_from_toml(&graph_builder_config)
+            .context("configuring graph-builder")?;
+        let graph_builder = Service::spawn(
+            "graph-builder",
+            gb_port,
+            gb_status_port,
+            "/readiness",
+            move || graph_builder::server::run(settings),
+        )?;
+
+        let (pe_port, pe_status_port) = (free_port()?, free_port()?);
+        let policy_engine_config = merge_config(
+            &format!(
+                r#"
+                    [service]
+                    address = "127.0.0.1"
+                    port = {}
+
+                    [status]
+                    address = "127.0.0.1"
+                    port = {}
+
+                    [upstream.cincinnati]
+                    url = "{}/graph"
+                "#,
+                pe_port,
+                pe_status_port,
+                graph_builder.url(),
+            ),
+            &self.policy_engine_config,
+        )?;
+        let settings = policy_engine::AppSettings::try_from_toml(&policy_engine_config)
+            .context("configuring policy-engine")?;
+        let policy_engine = Service::spawn(
+            "policy-engine",
+            pe_port,
+            pe_status_port,
+            "/readyz",
+            move || policy_engine::run(settings),
+        )?;
+
+        Ok(Harness {
+            policy_engine,
+            graph_builder,
+            registry: Some(registry),
+        })
+    }
+}
+
+/// Running registry, graph-builder and policy-engine, stopped when dropped.
+#[derive(Debug)]
+pub struct Harness {
+    // Fields are dropped in order, downstream services first.
+    policy_engine: Service,
+    graph_builder: Service,
+    registry: Option<MockRegistry>,
+}
+
+impl Harness {
+    /// Start a harness serving `fixtures`, with the default configuration.
+    pub fn start(fixtures: Fixtures) -> Result<Self> {
+        HarnessBuilder::new(fixtures).start()
+    }
+
+    /// The graph-builder.
+    pub fn graph_builder(&self) -> &Service {
+        &self.graph_builder
+    }
+
+    /// The policy-engine.
+    pub fn policy_engine(&self) -> &Service {
+        &self.policy_engine
+    }
+
+    /// The mock registry, unless stopped.
+    pub fn registry(&self) -> Option<&MockRegistry> {
+        self.registry.as_ref()
+    }
+
+    /// Stop the mock registry, so that subsequent scrapes fail.
+    pub fn stop_registry(&mut self) {
+        self.registry.take();
+    }
+
+    /// Wait up to `timeout` for the graph-builder, then the policy-engine, to be ready.
+    pub fn wait_ready(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        self.graph_builder.wait_ready_until(deadline)?;
+        self.policy_engine.wait_ready_until(deadline)
+    }
+}
+
+/// Handle of a service running in the harness, stopped when dropped.
+#[derive(Debug)]
+pub struct Service {
+    name: &'static str,
+    url: String,
+    status_url: String,
+    readiness_path: &'static str,
+    client: Client,
+    system: System,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl Service {
+    /// Run the server returned by `run` on its own thread and actix system.
+    fn spawn<F, Fut>(
+        name: &'static str,
+        port: u16,
+        status_port: u16,
+        readiness_path: &'static str,
+        run: F,
+    ) -> Result<Self>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + 'static,
+    {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let runner = System::new();
+                let _ = tx.send(System::current());
+                let (result_tx, result_rx) = std::sync::mpsc::channel();
+                runner.block_on(async move {
+                    actix_web::rt::spawn(async move {
+                        let _ = result_tx.send(run().await);
+                        System::current().stop();
+                    });
+                });
+                runner.run()?;
+                result_rx.try_recv().unwrap_or(Ok(()))
+            })?;
+        let system = rx
+            .recv()
+            .map_err(|_| format_err!("{} thread exited before starting", name))?;
+
+        Ok(Self {
+            name,
+            url: format!("http://{}:{}", Ipv4Addr::LOCALHOST, port),
+            status_url: format!("http://{}:{}", Ipv4Addr::LOCALHOST, status_port),
+            readiness_path,
+            client: Client::builder().timeout(Duration::from_secs(30)).build()?,
+            system,
+            thread: Some(thread),
+        })
+    }
+
+    /// Base URL of the main service.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Base URL of the status service.
+    pub fn status_url(&self) -> &str {
+        &self.status_url
+    }
+
+    /// HTTP client used by the handle, for custom requests.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Send a graph request to `path` of the main service, e.g. `/graph?channel=stable-4.14`.
+    pub fn get(&self, path: &str) -> Result<Response> {
+        let url = format!("{}{}", self.url, path);
+        self.client
+            .get(&url)
+            .header(header::ACCEPT, cincinnati::CONTENT_TYPE)
+            .send()
+            .context(format!("requesting {}", url))
+    }
+
+    /// Fetch the graph served at `/graph`, with the query string `query`.
+    pub fn graph(&self, query: &str) -> Result<serde_json::Value> {
+        let path = match query {
+            "" => "/graph".to_string(),
+            query => format!("/graph?{}", query),
+        };
+        let response = self.get(&path)?;
+        let status = response.status();
+        if !status.is_success() {
+            bail!(
+                "{} served {} for {}: {}",
+                self.name,
+                status,
+                path,
+                response.text().unwrap_or_default()
+            );
+        }
+        response
+            .json()
+            .context(format!("parsing graph from {}", self.name))
+    }
+
+    /// Send a request to `path` of the status service, e.g. `/metrics`.
+    pub fn get_status(&self, path: &str) -> Result<Response> {
+        let url = format!("{}{}", self.status_url, path);
+        self.client
+            .get(&url)
+            .send()
+            .context(format!("requesting {}", url))
+    }
+
+    /// Wait up to `timeout` for the service to be ready.
+    pub fn wait_ready(&self, timeout: Duration) -> Result<()> {
+        self.wait_ready_until(Instant::now() + timeout)
+    }
+
+    fn wait_ready_until(&self, deadline: Instant) -> Result<()> {
+        loop {
+            if self.thread.as_ref().map_or(true, JoinHandle::is_finished) {
+                bail!("{} exited before being ready", self.name);
+            }
+            match self.get_status(self.readiness_path) {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => debug!("{} not ready: {}", self.name, response.status()),
+                Err(e) => debug!("{} not ready: {:#}", self.name, e),
+            }
+            if Instant::now() >= deadline {
+                bail!("timed out waiting for {} to be ready", self.name);
+            }
+            std::thread::sleep(READINESS_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        self.system.stop();
+        if let Some(thread) = self.thread.take() {
+            if let Err(e) = thread
+                .join()
+                .map_err(|_| format_err!("{} thread panicked", self.name))
+                .and_then(|result| result)
+            {
+                error!("stopping {}: {:#}", self.name, e);
+            }
+        }
+    }
+}
+
+/// Return a local port which is free, at least until another process binds it.
+fn free_port() -> Result<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).context("reserving a local port")?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// Merge the TOML configuration `overrides` into `base`.
+fn merge_config(base: &str, overrides: &str) -> Result<String> {
+    fn merge(base: &mut toml::Table, overrides: toml::Table) {
+        for (key, value) in overrides {
+            match (base.get_mut(&key), value) {
+                (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => {
+                    merge(base, overrides)
+                }
+                (_, value) => {
+                    base.insert(key, value);
+                }
+            }
+        }
+    }
+
+    let mut config: toml::Table = toml::from_str(base).context("parsing base configuration")?;
+    merge(
+        &mut config,
+        toml::from_str(overrides).context("parsing configuration overrides")?,
+    );
+    Ok(toml::to_string(&config)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_configs() -> Result<()> {
+        let merged = merge_config(
+            "[service]\nport = 8080\npause_secs = 30\n[[plugin_settings]]\nname = \"a\"\n",
+            "[service]\npause_secs = 1\n[[plugin_settings]]\nname = \"b\"\n",
+        )?;
+        let merged: toml::Table = toml::from_str(&merged)?;
+        assert_eq!(merged["service"]["port"].as_integer(), Some(8080));
+        assert_eq!(merged["service"]["pause_secs"].as_integer(), Some(1));
+        assert_eq!(merged["plugin_settings"].as_array().map(Vec::len), Some(1));
+        assert_eq!(merged["plugin_settings"][0]["name"].as_str(), Some("b"));
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use anyhow::Result;
+use e2e_harness::{Fixtures, HarnessBuilder, ReleaseImage, DEFAULT_REPOSITORY};
+use std::time::Duration;
+
+static CHANNELS_KEY: &str = "io.openshift.upgrades.graph.release.channels";
+
+fn fixtures() -> Result<Fixtures> {
+    let mut fixtures = Fixtures::new();
+    fixtures.add_release(
+        DEFAULT_REPOSITORY,
+        "4.14.0",
+        &ReleaseImage::new("4.14.0").with_metadata(CHANNELS_KEY, "stable-4.14"),
+    )?;
+    fixtures.add_release(
+        DEFAULT_REPOSITORY,
+        "4.14.1",
+        &ReleaseImage::new("4.14.1")
+            .with_previous(&["4.14.0"])
+            .with_metadata(CHANNELS_KEY, "stable-4.14"),
+    )?;
+    Ok(fixtures)
+}
+
+#[test]
+fn serve_scraped_graph_through_policy_engine() -> Result<()> {
+    let mut harness = HarnessBuilder::new(fixtures()?).start()?;
+    harness.wait_ready(Duration::from_secs(60))?;
+
+    let graph = harness
+        .policy_engine()
+        .graph("channel=stable-4.14&arch=amd64")?;
+    assert_eq!(graph["nodes"].as_array().map(Vec::len), Some(2));
+    assert_eq!(graph["edges"].as_array().map(Vec::len), Some(1));
+
+    // The last built graph is still served once the registry is gone.
+    harness.stop_registry();
+    std::thread::sleep(Duration::from_secs(2));
+    let graph = harness
+        .policy_engine()
+        .graph("channel=stable-4.14&arch=amd64")?;
+    assert_eq!(graph["nodes"].as_array().map(Vec::len), Some(2));
+
+    Ok(())
+}
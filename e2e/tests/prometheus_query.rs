@@ -1,18 +1,23 @@
 use commons::prelude_errors::*;
+use prometheus_query::v1::in_cluster::InCluster;
 use prometheus_query::v1::queries::{QueryData, QueryResult, VectorResult};
 use prometheus_query::v1::Client;
 
 // #[tokio::test]
 #[test]
 fn query_prometheus() -> Fallible<()> {
-    let api_base = std::env::var("PROM_ENDPOINT").context("PROM_ENDPOINT not set")?;
-    let token = std::env::var("PROM_TOKEN").context("PROM_TOKEN not set")?;
-
-    let client = Client::builder()
-        .api_base(Some(api_base))
-        .access_token(Some(token))
-        .accept_invalid_certs(Some(true))
-        .build()?;
+    let client = match InCluster::from_env()? {
+        Some(in_cluster) => Client::builder().in_cluster(in_cluster)?.build()?,
+        None => {
+            let api_base = std::env::var("PROM_ENDPOINT").context("PROM_ENDPOINT not set")?;
+            let token = std::env::var("PROM_TOKEN").context("PROM_TOKEN not set")?;
+            Client::builder()
+                .api_base(Some(api_base))
+                .access_token(Some(token))
+                .accept_invalid_certs(Some(true))
+                .build()?
+        }
+    };
 
     let query = r#"up{job="apiserver"}"#;
 
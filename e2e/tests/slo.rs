@@ -1,12 +1,17 @@
 use anyhow::Context;
 use hamcrest2::prelude::*;
+use prometheus_query::v1::in_cluster::InCluster;
 use prometheus_query::v1::queries::{QueryData, QueryResult, QuerySuccess, VectorResult};
 use prometheus_query::v1::Client;
 use serde_json::{Map, Value};
 use std::env;
 use test_case::test_case;
 
-fn get_query_result_string(query: &'static str) -> VectorResult {
+fn prometheus_client() -> anyhow::Result<Client> {
+    if let Some(in_cluster) = InCluster::from_env()? {
+        return Client::builder().in_cluster(in_cluster)?.build();
+    }
+
     let prometheus_api_base = match env::var("PROM_ENDPOINT") {
         Ok(env) => format!("{}/api/v1/query", env),
         _ => panic!("PROM_ENDPOINT unset"),
@@ -17,11 +22,15 @@ fn get_query_result_string(query: &'static str) -> VectorResult {
         _ => panic!("PROM_TOKEN unset"),
     };
 
-    let prometheus_client = Client::builder()
+    Client::builder()
         .api_base(Some(prometheus_api_base))
         .access_token(Some(prometheus_token))
         .accept_invalid_certs(Some(true))
         .build()
+}
+
+fn get_query_result_string(query: &'static str) -> VectorResult {
+    let prometheus_client = prometheus_client()
         .context("Failed to establish Prometheus connection")
         .unwrap();
 
@@ -0,0 +1,398 @@
+//! Attestations of the served graph.
+//!
+//! Each served graph is attested by an [in-toto statement][statement]
+//! binding the digest of the served graph document to the inputs of the
+//! scrape which built it: the release images, by digest, and the revision of
+//! the graph-data. Statements are signed with an Ed25519 key and served in a
+//! [DSSE envelope][dsse] at `/v1/graph/attestation`, so that mirrors can
+//! prove the provenance of the graph they redistribute.
+//!
+//! [statement]: https://github.com/in-toto/attestation/blob/main/spec/v1/statement.md
+//! [dsse]: https://github.com/secure-systems-lab/dsse/blob/master/envelope.md
+
+use crate::graph::State;
+use actix_web::HttpResponse;
+use commons::prelude_errors::*;
+use commons::{GraphError, GRAPH_DATA_REVISION_PARAM_KEY, GRAPH_DATA_SOURCE_PARAM_KEY};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+use parking_lot::RwLock;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Type of the in-toto statements.
+pub static STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+
+/// Type of the predicate of the statements, describing a served graph.
+pub static PREDICATE_TYPE: &str = "https://github.com/openshift/cincinnati/attestation/graph/v1";
+
+/// Type of the payload of the DSSE envelopes.
+pub static PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+
+/// Media type of the attestation endpoint.
+pub static ENVELOPE_CONTENT_TYPE: &str = "application/vnd.dsse.envelope.v1+json";
+
+/// Revision of the graph-data from which a graph was built.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraphDataRevision {
+    /// URI of the graph-data, e.g. `git+https://github.com/openshift/cincinnati-graph-data`.
+    pub source: String,
+    /// Commit SHA or image digest of the graph-data.
+    pub revision: String,
+}
+
+impl GraphDataRevision {
+    /// Return the revision recorded by the graph-data scraper in the IO
+    /// `parameters` of a scrape, if any.
+    pub fn from_parameters(parameters: &HashMap<String, String>) -> Option<Self> {
+        Some(Self {
+            source: parameters.get(GRAPH_DATA_SOURCE_PARAM_KEY)?.clone(),
+            revision: parameters.get(GRAPH_DATA_REVISION_PARAM_KEY)?.clone(),
+        })
+    }
+}
+
+/// Resource described by a statement, either the attested graph or an input of its scrape.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceDescriptor {
+    /// Name of the resource.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// URI of the resource.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    /// Digests of the resource, by algorithm, e.g. `sha256` or `gitCommit`.
+    pub digest: BTreeMap<String, String>,
+}
+
+/// Facts about a served graph.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Predicate {
+    /// Time at which the graph was served, in RFC 3339 format.
+    pub served_at: String,
+    /// Version of the graph-builder which built the graph.
+    pub builder_version: String,
+    /// Inputs of the scrape: the release images and the graph-data.
+    pub materials: Vec<ResourceDescriptor>,
+}
+
+/// In-toto statement about a served graph.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Statement {
+    /// Type of the statement.
+    #[serde(rename = "_type")]
+    pub statement_type: String,
+    /// Attested graph.
+    pub subject: Vec<ResourceDescriptor>,
+    /// Type of the predicate.
+    #[serde(rename = "predicateType")]
+    pub predicate_type: String,
+    /// Facts about the attested graph.
+    pub predicate: Predicate,
+}
+
+impl Statement {
+    /// Describe the `graph` served as `json` at `name`, built from `graph_data`.
+    pub fn new(
+        name: &str,
+        json: &str,
+        graph: &cincinnati::Graph,
+        graph_data: Option<&GraphDataRevision>,
+        served_at: chrono::DateTime<chrono::Utc>,
+    ) -> Self {
+        let subject = ResourceDescriptor {
+            name: Some(name.to_string()),
+            uri: None,
+            digest: sha256_digest(hex::encode(Sha256::digest(json.as_bytes()))),
+        };
+
+        // Only the releases pinned by digest are inputs which can be verified.
+        let mut materials: Vec<ResourceDescriptor> = graph
+            .concrete_releases()
+            .filter_map(|release| {
+                let (_, digest) = release.payload.split_once("@sha256:")?;
+                Some((release.payload.clone(), digest.to_string()))
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|(payload, digest)| ResourceDescriptor {
+                name: None,
+                uri: Some(payload),
+                digest: sha256_digest(digest),
+            })
+            .collect();
+        if let Some(graph_data) = graph_data {
+            let digest = match graph_data.revision.split_once(':') {
+                Some((algorithm, digest)) => (algorithm.to_string(), digest.to_string()),
+                None => ("gitCommit".to_string(), graph_data.revision.clone()),
+            };
+            materials.push(ResourceDescriptor {
+                name: None,
+                uri: Some(graph_data.source.clone()),
+                digest: std::iter::once(digest).collect(),
+            });
+        }
+
+        Self {
+            statement_type: STATEMENT_TYPE.to_string(),
+            subject: vec![subject],
+            predicate_type: PREDICATE_TYPE.to_string(),
+            predicate: Predicate {
+                served_at: served_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                builder_version: crate::built_info::PKG_VERSION.to_string(),
+                materials,
+            },
+        }
+    }
+}
+
+fn sha256_digest(digest: String) -> BTreeMap<String, String> {
+    std::iter::once(("sha256".to_string(), digest)).collect()
+}
+
+/// Signature of a DSSE envelope.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnvelopeSignature {
+    /// Identifier of the signing key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyid: Option<String>,
+    /// Base64-encoded signature.
+    pub sig: String,
+}
+
+/// DSSE envelope of a signed statement.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    /// Type of the payload.
+    pub payload_type: String,
+    /// Base64-encoded statement.
+    pub payload: String,
+    /// Signatures of the payload.
+    pub signatures: Vec<EnvelopeSignature>,
+}
+
+/// Return the pre-authentication encoding of a payload, as signed in DSSE envelopes.
+pub fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut encoded = format!(
+        "DSSEv1 {} {} {} ",
+        payload_type.len(),
+        payload_type,
+        payload.len()
+    )
+    .into_bytes();
+    encoded.extend_from_slice(payload);
+    encoded
+}
+
+/// Signs statements with an Ed25519 key.
+pub struct AttestationSigner {
+    keypair: Keypair,
+    key_id: Option<String>,
+}
+
+impl std::fmt::Debug for AttestationSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AttestationSigner")
+            .field("key_id", &self.key_id)
+            .finish()
+    }
+}
+
+impl AttestationSigner {
+    /// Create a signer from a base64-encoded 32 bytes Ed25519 secret key.
+    pub fn from_base64_key(encoded: &str, key_id: Option<String>) -> Fallible<Self> {
+        let bytes = base64::decode(encoded.trim()).context("decoding attestation key")?;
+        let secret = SecretKey::from_bytes(&bytes)
+            .map_err(|e| format_err!("invalid Ed25519 attestation key: {}", e))?;
+        let public = PublicKey::from(&secret);
+
+        Ok(Self {
+            keypair: Keypair { secret, public },
+            key_id,
+        })
+    }
+
+    /// Create a signer from a file containing a base64-encoded Ed25519 secret key.
+    pub fn from_file<P>(path: P, key_id: Option<String>) -> Fallible<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let encoded = std::fs::read_to_string(&path).context(format!(
+            "failed to read attestation key {}",
+            path.as_ref().display()
+        ))?;
+        Self::from_base64_key(&encoded, key_id)
+    }
+
+    /// Sign `statement`, returning its envelope.
+    pub fn sign(&self, statement: &Statement) -> Fallible<Envelope> {
+        let payload = serde_json::to_vec(statement)?;
+        let signature = self.keypair.sign(&pae(PAYLOAD_TYPE, &payload));
+
+        Ok(Envelope {
+            payload_type: PAYLOAD_TYPE.to_string(),
+            payload: base64::encode(&payload),
+            signatures: vec![EnvelopeSignature {
+                keyid: self.key_id.clone(),
+                sig: base64::encode(signature.to_bytes()),
+            }],
+        })
+    }
+
+    /// Return the public half of the signing key.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.keypair.public
+    }
+}
+
+/// Attestation of the graph served under a path prefix.
+#[derive(Debug)]
+pub struct Attestation {
+    signer: Arc<AttestationSigner>,
+    /// Envelope of the served graph, serialized as JSON.
+    envelope: RwLock<Option<String>>,
+}
+
+impl Attestation {
+    /// Create the attestation of a graph, signed by `signer`.
+    pub fn new(signer: Arc<AttestationSigner>) -> Self {
+        Self {
+            signer,
+            envelope: Default::default(),
+        }
+    }
+
+    /// Attest the `graph` served as `json` at `name`, built from `graph_data`,
+    /// returning the envelope to serve along with it.
+    pub fn attest(
+        &self,
+        name: &str,
+        json: &str,
+        graph: &cincinnati::Graph,
+        graph_data: Option<&GraphDataRevision>,
+    ) -> Fallible<String> {
+        let statement = Statement::new(name, json, graph, graph_data, chrono::Utc::now());
+        Ok(serde_json::to_string(&self.signer.sign(&statement)?)?)
+    }
+
+    /// Serve `envelope` as the attestation of the served graph.
+    pub fn set(&self, envelope: Option<String>) {
+        *self.envelope.write() = envelope;
+    }
+
+    /// Return the envelope of the served graph, if attested.
+    pub fn envelope(&self) -> Option<String> {
+        self.envelope.read().clone()
+    }
+}
+
+/// Serve the attestation of the served graph.
+pub async fn index(app_data: actix_web::web::Data<State>) -> Result<HttpResponse, GraphError> {
+    let attestation = app_data
+        .attestation()
+        .ok_or_else(|| GraphError::DoesNotExist("graph attestations are disabled".to_string()))?;
+    let envelope = attestation.envelope().ok_or_else(|| {
+        GraphError::DoesNotExist("the served graph is not attested yet".to_string())
+    })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ENVELOPE_CONTENT_TYPE)
+        .body(envelope))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use ed25519_dalek::{Signature, Verifier};
+    use std::convert::TryFrom;
+
+    static TEST_KEY: &str = "nWGxne/9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A=";
+
+    #[test]
+    fn attest_served_graphs() -> Fallible<()> {
+        let mut graph = generate_custom_graph(
+            "quay.io/openshift-release-dev/ocp-release",
+            (0..2).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1)]),
+        );
+        // The second release is only pinned by tag.
+        let mut pinned = false;
+        graph.iter_releases_mut(|release| {
+            if let cincinnati::Release::Concrete(release) = release {
+                if !pinned {
+                    release.payload =
+                        "quay.io/openshift-release-dev/ocp-release@sha256:0123abcd".to_string();
+                    pinned = true;
+                }
+            }
+            Ok(())
+        })?;
+        let json = serde_json::to_string(&graph)?;
+        let graph_data = GraphDataRevision::from_parameters(
+            &[
+                (
+                    GRAPH_DATA_SOURCE_PARAM_KEY.to_string(),
+                    "git+https://github.com/openshift/cincinnati-graph-data".to_string(),
+                ),
+                (
+                    GRAPH_DATA_REVISION_PARAM_KEY.to_string(),
+                    "6420f7f".to_string(),
+                ),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        );
+        assert!(graph_data.is_some());
+
+        let signer = Arc::new(AttestationSigner::from_base64_key(
+            TEST_KEY,
+            Some("test-key".to_string()),
+        )?);
+        let attestation = Attestation::new(signer.clone());
+        assert_eq!(attestation.envelope(), None);
+        attestation.set(Some(attestation.attest(
+            "/v1/graph",
+            &json,
+            &graph,
+            graph_data.as_ref(),
+        )?));
+
+        let envelope: Envelope = serde_json::from_str(&attestation.envelope().unwrap())?;
+        assert_eq!(envelope.payload_type, PAYLOAD_TYPE);
+        assert_eq!(envelope.signatures[0].keyid.as_deref(), Some("test-key"));
+        let payload = base64::decode(&envelope.payload)?;
+        let signature =
+            Signature::try_from(base64::decode(&envelope.signatures[0].sig)?.as_slice())?;
+        signer
+            .public_key()
+            .verify(&pae(PAYLOAD_TYPE, &payload), &signature)?;
+
+        let statement: Statement = serde_json::from_slice(&payload)?;
+        assert_eq!(statement.statement_type, STATEMENT_TYPE);
+        assert_eq!(
+            statement.subject[0].digest["sha256"],
+            hex::encode(Sha256::digest(json.as_bytes()))
+        );
+        let materials = &statement.predicate.materials;
+        assert_eq!(materials.len(), 2);
+        assert_eq!(materials[0].digest["sha256"], "0123abcd");
+        assert_eq!(
+            materials[1].uri.as_deref(),
+            Some("git+https://github.com/openshift/cincinnati-graph-data")
+        );
+        assert_eq!(materials[1].digest["gitCommit"], "6420f7f");
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_key() {
+        AttestationSigner::from_base64_key("not base64!", None).unwrap_err();
+        AttestationSigner::from_base64_key("dG9vIHNob3J0", None).unwrap_err();
+    }
+}
@@ -0,0 +1,249 @@
+//! Changelog of the served graph.
+//!
+//! Each scrape which changes the served graph records the releases, edges
+//! and blocked edges it added and removed. The most recent changes are kept
+//! in memory, oldest first, and served at `/v1/changelog`. With a state
+//! store configured, they are also persisted there after each change, and
+//! restored on start, so that sequence numbers keep growing across restarts.
+
+use crate::graph::{GraphStats, State};
+use crate::state_store::{StateStore, CHANGELOG_KEY};
+use actix_web::{HttpRequest, HttpResponse};
+use cincinnati::{BlockedEdgesDiff, GraphDiff};
+use commons::prelude_errors::*;
+use commons::GraphError;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Default number of changes kept.
+pub static DEFAULT_CAPACITY: usize = 100;
+
+/// Changes made to the served graph by a scrape.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangelogEntry {
+    /// Sequence number of the change, starting at 1.
+    pub sequence: u64,
+    /// Time at which the changed graph was served, in RFC 3339 format.
+    pub timestamp: String,
+    /// Number of releases of the changed graph.
+    pub releases: u64,
+    /// Number of edges of the changed graph.
+    pub edges: u64,
+    /// Changed releases and edges.
+    #[serde(flatten)]
+    pub diff: GraphDiff,
+    /// Changed blocked edges.
+    #[serde(flatten)]
+    pub blocked_edges: BlockedEdgesDiff,
+}
+
+/// Persisted form of the changelog.
+#[derive(Debug, Deserialize)]
+struct PersistedChangelog {
+    /// Sequence number of the last change.
+    sequence: u64,
+    /// Kept changes, oldest first.
+    entries: VecDeque<ChangelogEntry>,
+}
+
+/// Most recent changes to the served graph.
+#[derive(Debug)]
+pub struct Changelog {
+    capacity: usize,
+    entries: Mutex<(u64, VecDeque<ChangelogEntry>)>,
+    store: Option<Arc<dyn StateStore>>,
+}
+
+impl Changelog {
+    /// Create a changelog keeping the `capacity` most recent changes.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new((0, VecDeque::with_capacity(capacity))),
+            store: None,
+        }
+    }
+
+    /// Persist the changes in `store`, restoring the ones persisted earlier.
+    pub fn with_store(mut self, store: Arc<dyn StateStore>) -> Fallible<Self> {
+        if let Some(content) = store.get(CHANGELOG_KEY).context("reading changelog")? {
+            let mut persisted: PersistedChangelog =
+                serde_json::from_slice(&content).context("parsing changelog")?;
+            while persisted.entries.len() > self.capacity {
+                persisted.entries.pop_front();
+            }
+            *self.entries.get_mut() = (persisted.sequence, persisted.entries);
+        }
+        self.store = Some(store);
+        Ok(self)
+    }
+
+    /// Record the changes going from `old` to `new`, served at `time`.
+    ///
+    /// Scrapes which do not change the graph are not recorded.
+    pub fn record(
+        &self,
+        old: &cincinnati::Graph,
+        new: &cincinnati::Graph,
+        stats: GraphStats,
+        time: SystemTime,
+    ) -> Option<ChangelogEntry> {
+        let diff = GraphDiff::new(old, new);
+        let blocked_edges = BlockedEdgesDiff::new(old, new);
+        if diff.is_empty() && blocked_edges.is_empty() {
+            return None;
+        }
+
+        let mut entries = self.entries.lock();
+        entries.0 += 1;
+        let entry = ChangelogEntry {
+            sequence: entries.0,
+            timestamp: chrono::DateTime::<chrono::Utc>::from(time).to_rfc3339(),
+            releases: stats.releases,
+            edges: stats.edges,
+            diff,
+            blocked_edges,
+        };
+        if entries.1.len() == self.capacity {
+            entries.1.pop_front();
+        }
+        entries.1.push_back(entry.clone());
+
+        if let Some(store) = &self.store {
+            let persisted = serde_json::to_vec(&serde_json::json!({
+                "sequence": entries.0,
+                "entries": entries.1,
+            }))
+            .map_err(Error::from)
+            .and_then(|content| store.put(CHANGELOG_KEY, &content));
+            if let Err(err) = persisted {
+                warn!(
+                    "failed to persist graph change {}: {:#}",
+                    entry.sequence, err
+                );
+            }
+        }
+
+        Some(entry)
+    }
+
+    /// Return the kept changes with a sequence number above `since`, oldest first.
+    pub fn since(&self, since: u64) -> Vec<ChangelogEntry> {
+        self.entries
+            .lock()
+            .1
+            .iter()
+            .filter(|entry| entry.sequence > since)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Serve the changelog of the graph held in `app_data`.
+///
+/// With the `since` query parameter, only changes with a greater sequence
+/// number are served, so that clients can poll for new changes.
+pub async fn index(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<State>,
+) -> Result<HttpResponse, GraphError> {
+    let changelog = app_data
+        .changelog()
+        .ok_or_else(|| GraphError::DoesNotExist("changelog is disabled".to_string()))?;
+
+    let mut since = 0;
+    for (key, value) in url::form_urlencoded::parse(req.query_string().as_bytes()) {
+        if key == "since" {
+            since = value.parse().map_err(|_| {
+                GraphError::InvalidParams(format!("invalid 'since' parameter '{}'", value))
+            })?;
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "changes": changelog.since(since) })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use std::time::Duration;
+
+    fn graph(releases: usize) -> cincinnati::Graph {
+        generate_custom_graph(
+            "image",
+            (0..releases).map(|i| (i, Default::default())).collect(),
+            None,
+        )
+    }
+
+    fn stats(graph: &cincinnati::Graph) -> GraphStats {
+        GraphStats {
+            releases: graph.releases_count(),
+            edges: graph.edges_count(),
+        }
+    }
+
+    #[test]
+    fn record_changes() -> Fallible<()> {
+        let changelog = Changelog::new(2);
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_791_979_200);
+
+        let graphs = vec![Default::default(), graph(1), graph(2), graph(2), graph(3)];
+        for pair in graphs.windows(2) {
+            changelog.record(&pair[0], &pair[1], stats(&pair[1]), time);
+        }
+
+        // Of the three changes, the oldest one was dropped.
+        let entries = changelog.since(0);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.sequence)
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(entries[0].timestamp, "2026-10-14T12:00:00+00:00");
+        assert_eq!(entries[0].releases, 2);
+        assert_eq!(entries[0].diff.added_releases, vec!["1.0.0".to_string()]);
+        assert_eq!(
+            entries[0].diff.added_edges,
+            vec![("0.0.0".to_string(), "1.0.0".to_string())]
+        );
+        assert!(entries[0].blocked_edges.is_empty());
+
+        assert_eq!(changelog.since(2), vec![entries[1].clone()]);
+        assert!(changelog.since(3).is_empty());
+
+        let json = serde_json::to_value(&entries[1])?;
+        assert_eq!(json["added_releases"], serde_json::json!(["2.0.0"]));
+        assert_eq!(json["removed_blocked_edges"], serde_json::json!([]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn persist_changes() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let store: Arc<dyn StateStore> = Arc::new(crate::state_store::DiskStore::open(dir.path())?);
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_791_979_200);
+
+        let changelog = Changelog::new(2).with_store(store.clone())?;
+        let graphs = vec![Default::default(), graph(1), graph(2), graph(3)];
+        for pair in graphs.windows(2) {
+            changelog.record(&pair[0], &pair[1], stats(&pair[1]), time);
+        }
+
+        // Restarts keep the changes, trimmed to the capacity, and their numbering.
+        let restored = Changelog::new(1).with_store(store)?;
+        assert_eq!(restored.since(0), changelog.since(2));
+        let entry = restored
+            .record(&graphs[3], &graph(4), stats(&graph(4)), time)
+            .unwrap();
+        assert_eq!(entry.sequence, 4);
+
+        Ok(())
+    }
+}
@@ -4,6 +4,7 @@ use super::options;
 use super::AppSettings;
 use commons::prelude_errors::*;
 use commons::MergeOptions;
+use std::path::PathBuf;
 
 /// CLI configuration flags, top-level.
 #[derive(Debug, StructOpt)]
@@ -16,6 +17,14 @@ pub struct CliOptions {
     #[structopt(short = "c")]
     pub config_path: Option<String>,
 
+    /// Build the graph once, write it to stdout and exit
+    #[structopt(long = "oneshot")]
+    pub oneshot: bool,
+
+    /// Write the graph to this file in one-shot mode, instead of stdout
+    #[structopt(long = "oneshot.output", requires = "oneshot")]
+    pub oneshot_output: Option<PathBuf>,
+
     #[structopt(flatten)]
     pub service: options::ServiceOptions,
 
@@ -28,6 +37,21 @@ pub struct CliOptions {
 
     #[structopt(flatten)]
     pub upstream_registry: options::DockerRegistryOptions,
+
+    #[structopt(flatten)]
+    pub events: options::EventsOptions,
+
+    #[structopt(flatten)]
+    pub publish: options::PublishOptions,
+
+    #[structopt(flatten)]
+    pub snapshots: options::SnapshotsOptions,
+
+    #[structopt(flatten)]
+    pub state: options::StateOptions,
+
+    #[structopt(flatten)]
+    pub changelog: options::ChangelogOptions,
 }
 
 impl MergeOptions<CliOptions> for AppSettings {
@@ -38,9 +62,16 @@ impl MergeOptions<CliOptions> for AppSettings {
             2 => log::LevelFilter::Debug,
             _ => log::LevelFilter::Trace,
         };
+        self.oneshot |= opts.oneshot;
+        assign_if_some!(self.oneshot_output, opts.oneshot_output);
         self.try_merge(Some(opts.service))?;
         self.try_merge(Some(opts.status))?;
         self.try_merge(Some(opts.upstream_registry))?;
+        self.try_merge(Some(opts.events))?;
+        self.try_merge(Some(opts.publish))?;
+        self.try_merge(Some(opts.snapshots))?;
+        self.try_merge(Some(opts.state))?;
+        self.try_merge(Some(opts.changelog))?;
 
         Ok(())
     }
@@ -69,13 +100,30 @@ mod tests {
         assert_eq!(svc_port_cli.service.port, Some(9999));
     }
 
+    #[test]
+    fn cli_oneshot() {
+        let mut settings = AppSettings::default();
+        assert!(!settings.oneshot);
+
+        let args = vec!["argv0", "--oneshot", "--oneshot.output", "/tmp/graph.json"];
+        settings
+            .try_merge(CliOptions::from_iter_safe(args).unwrap())
+            .unwrap();
+        assert!(settings.oneshot);
+        assert_eq!(settings.oneshot_output, Some("/tmp/graph.json".into()));
+
+        let args = vec!["argv0", "--oneshot.output", "/tmp/graph.json"];
+        assert!(CliOptions::from_iter_safe(args).is_err());
+    }
+
     #[test]
     fn cli_merge_settings() {
         let repo = "cincinnati/cli-test";
 
         let mut settings = AppSettings::default();
+        assert_eq!(settings.repository, None);
         assert_eq!(
-            settings.repository,
+            settings.profile.repository,
             cincinnati::plugins::internal::release_scrape_dockerv2::DEFAULT_SCRAPE_REPOSITORY
         );
 
@@ -84,7 +132,7 @@ mod tests {
         assert_eq!(cli.upstream_registry.repository, Some(repo.to_string()));
 
         settings.try_merge(cli).unwrap();
-        assert_eq!(settings.repository, repo.to_string());
+        assert_eq!(settings.repository, Some(repo.to_string()));
     }
 
     #[test]
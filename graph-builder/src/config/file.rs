@@ -1,10 +1,11 @@
 //! TOML file configuration options.
 
 use super::options;
-use super::AppSettings;
+use super::{AppSettings, TenantSettings};
 use commons::de::de_loglevel;
 use commons::prelude_errors::*;
-use commons::MergeOptions;
+use commons::{de_path_prefix, MergeOptions};
+use std::collections::BTreeMap;
 use std::io::Read;
 use std::{fs, io, path};
 
@@ -15,6 +16,9 @@ pub struct FileOptions {
     #[serde(default = "Option::default", deserialize_with = "de_loglevel")]
     pub verbosity: Option<log::LevelFilter>,
 
+    /// Product profile, by name or as a table.
+    pub profile: Option<cincinnati::ProductProfile>,
+
     /// Upstream options.
     pub upstream: Option<UpstreamOptions>,
 
@@ -24,8 +28,59 @@ pub struct FileOptions {
     /// Status service options.
     pub status: Option<options::StatusOptions>,
 
+    /// Graph-change event options.
+    pub events: Option<options::EventsOptions>,
+
+    /// Graph update publishing options.
+    pub publish: Option<options::PublishOptions>,
+
+    /// Graph snapshot options.
+    pub snapshots: Option<options::SnapshotsOptions>,
+
+    /// State store options.
+    pub state: Option<options::StateOptions>,
+
+    /// Graph changelog options.
+    pub changelog: Option<options::ChangelogOptions>,
+
+    /// Path aliases options.
+    pub paths: Option<options::PathAliasesOptions>,
+
+    /// Metrics options.
+    pub metrics: Option<options::MetricsOptions>,
+
+    /// Self-check options.
+    pub self_check: Option<options::SelfCheckOptions>,
+
+    /// Staging options.
+    pub staging: Option<options::StagingOptions>,
+
+    /// Simulation options.
+    pub simulate: Option<options::SimulateOptions>,
+
+    /// Dry-run scrapes options.
+    pub dry_run: Option<options::DryRunOptions>,
+
+    /// Graph attestations options.
+    pub attestation: Option<options::AttestationOptions>,
+
+    /// Outbound request budgets options.
+    pub budgets: Option<options::RequestBudgetsOptions>,
+
+    /// Request quotas options.
+    pub quotas: Option<options::QuotasOptions>,
+
+    /// Constraints on the values of mandatory client parameters, by parameter.
+    pub client_parameters: Option<BTreeMap<String, commons::params::ParamConstraint>>,
+
+    /// Outbound webhooks.
+    pub webhooks: Option<Vec<crate::webhooks::WebhookConfig>>,
+
     /// Plugin settings.
     pub plugin_settings: Option<Vec<toml::Value>>,
+
+    /// Additional release repositories, each served under its own prefix.
+    pub tenants: Option<Vec<TenantOptions>>,
 }
 
 impl FileOptions {
@@ -55,10 +110,30 @@ impl MergeOptions<Option<FileOptions>> for AppSettings {
     fn try_merge(&mut self, opts: Option<FileOptions>) -> Fallible<()> {
         if let Some(file) = opts {
             assign_if_some!(self.verbosity, file.verbosity);
+            assign_if_some!(self.profile, file.profile);
             self.try_merge(file.upstream)?;
             self.try_merge(file.service)?;
             self.try_merge(file.status)?;
+            self.try_merge(file.events)?;
+            self.try_merge(file.publish)?;
+            self.try_merge(file.snapshots)?;
+            self.try_merge(file.state)?;
+            self.try_merge(file.changelog)?;
+            self.try_merge(file.paths)?;
+            self.try_merge(file.metrics)?;
+            self.try_merge(file.self_check)?;
+            self.try_merge(file.staging)?;
+            self.try_merge(file.simulate)?;
+            self.try_merge(file.dry_run)?;
+            self.try_merge(file.attestation)?;
+            self.try_merge(file.budgets)?;
+            self.try_merge(file.quotas)?;
+            assign_if_some!(self.client_parameters, file.client_parameters);
+            if let Some(webhooks) = file.webhooks {
+                self.webhooks.extend(webhooks);
+            }
             self.try_merge(file.plugin_settings)?;
+            self.try_merge(file.tenants)?;
         }
         Ok(())
     }
@@ -76,6 +151,51 @@ impl MergeOptions<Option<Vec<toml::Value>>> for AppSettings {
     }
 }
 
+/// Options for a tenant, i.e. a graph scraped and served next to the default one.
+#[derive(Debug, Deserialize)]
+pub struct TenantOptions {
+    /// Tenant name, used as `tenant` label on its metrics.
+    pub name: String,
+
+    /// Endpoints namespace for the tenant, defaults to `/<name>`.
+    #[serde(default = "Option::default", deserialize_with = "de_path_prefix")]
+    pub path_prefix: Option<String>,
+
+    /// Plugin settings of the tenant.
+    pub plugin_settings: Vec<toml::Value>,
+
+    /// Request quotas of the tenant.
+    #[serde(default)]
+    pub quotas: commons::limits::RequestQuotas,
+}
+
+impl MergeOptions<Option<Vec<TenantOptions>>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<Vec<TenantOptions>>) -> Fallible<()> {
+        if let Some(tenants) = opts {
+            for tenant in tenants {
+                let plugin_settings = tenant
+                    .plugin_settings
+                    .into_iter()
+                    .map(cincinnati::plugins::catalog::deserialize_config)
+                    .collect::<Fallible<Vec<_>>>()
+                    .context(format!(
+                        "parsing plugin settings of tenant '{}'",
+                        tenant.name
+                    ))?;
+                self.tenants.push(TenantSettings {
+                    path_prefix: tenant
+                        .path_prefix
+                        .unwrap_or_else(|| commons::parse_path_prefix(&tenant.name)),
+                    name: tenant.name,
+                    plugin_settings,
+                    quotas: tenant.quotas,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Options for upstream fetcher.
 #[derive(Debug, Deserialize)]
 pub struct UpstreamOptions {
@@ -87,12 +207,16 @@ pub struct UpstreamOptions {
 
     /// Docker-registry-v2 upstream options.
     pub registry: Option<options::DockerRegistryOptions>,
+
+    /// Scheme ordering the versions of the upstream releases.
+    pub version_scheme: Option<cincinnati::VersionScheme>,
 }
 
 impl MergeOptions<Option<UpstreamOptions>> for AppSettings {
     fn try_merge(&mut self, opts: Option<UpstreamOptions>) -> Fallible<()> {
         if let Some(upstream) = opts {
             self.try_merge(upstream.registry)?;
+            assign_if_some!(self.version_scheme, upstream.version_scheme);
             if upstream.pause_secs.is_some() {
                 warn!("the upstream option 'pause_secs' has been deprecated and has no effect. please use '--pause-secs' instead");
             };
@@ -131,6 +255,430 @@ mod tests {
         assert_eq!(settings.status_port, 2222);
     }
 
+    #[test]
+    fn toml_request_limits() {
+        let mut settings = AppSettings::default();
+        assert_eq!(
+            settings.request_limits,
+            commons::limits::RequestLimits::default()
+        );
+
+        let toml_input = "[service]\nmax_uri_length = 1024\nmax_query_params = 8";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.request_limits.max_uri_length, 1024);
+        assert_eq!(settings.request_limits.max_query_params, 8);
+        assert_eq!(
+            settings.request_limits.max_headers_size,
+            commons::limits::DEFAULT_MAX_HEADERS_SIZE
+        );
+    }
+
+    #[test]
+    fn toml_metrics_labels() {
+        let mut settings = AppSettings::default();
+        let toml_input = "[metrics]\nlabels = { tenant = \"acme\" }";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.metrics_labels.into_iter().collect::<Vec<_>>(),
+            vec![("tenant".to_string(), "acme".to_string())]
+        );
+    }
+
+    #[test]
+    fn toml_self_check() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.self_check_interval, None);
+
+        let toml_input = "[self_check]\ninterval_secs = 60\nparams = { channel = \"stable-4.14\" }\nmin_releases = 100";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.self_check_interval,
+            Some(std::time::Duration::from_secs(60))
+        );
+        assert_eq!(settings.self_check_params["channel"], "stable-4.14");
+        assert_eq!(settings.self_check_min_releases, 100);
+        assert_eq!(settings.self_check_min_edges, 0);
+
+        let toml_input = "[self_check]\ninterval_secs = 0";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.self_check_interval, None);
+    }
+
+    #[test]
+    fn toml_staging() {
+        let mut settings = AppSettings::default();
+        assert!(!settings.staging_enabled);
+        assert!(settings.staging_auto_promote);
+
+        let toml_input = "[staging]\nenabled = true\nauto_promote = false\nadmin_token_path = \"/etc/cincinnati/admin-token\"\nmax_removed_releases = 10";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert!(settings.staging_enabled);
+        assert!(!settings.staging_auto_promote);
+        assert_eq!(
+            settings.staging_admin_token_path,
+            Some(std::path::PathBuf::from("/etc/cincinnati/admin-token"))
+        );
+        assert_eq!(settings.staging_max_removed_releases, Some(10));
+        assert_eq!(settings.staging_max_removed_edges, None);
+    }
+
+    #[test]
+    fn toml_simulate() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.simulate_token_path, None);
+
+        let toml_input = "[simulate]\ntoken_path = \"/etc/cincinnati/simulate-token\"";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.simulate_token_path,
+            Some(std::path::PathBuf::from("/etc/cincinnati/simulate-token"))
+        );
+    }
+
+    #[test]
+    fn toml_dry_run() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.dry_run_token_path, None);
+
+        let toml_input = "[dry_run]\ntoken_path = \"/etc/cincinnati/dry-run-token\"";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.dry_run_token_path,
+            Some(std::path::PathBuf::from("/etc/cincinnati/dry-run-token"))
+        );
+    }
+
+    #[test]
+    fn toml_attestation() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.attestation_key_path, None);
+
+        let toml_input = "[attestation]\nkey_path = \"/etc/cincinnati/attestation.key\"\nkey_id = \"graph-2026\"";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.attestation_key_path,
+            Some(std::path::PathBuf::from("/etc/cincinnati/attestation.key"))
+        );
+        assert_eq!(settings.attestation_key_id.as_deref(), Some("graph-2026"));
+    }
+
+    #[test]
+    fn toml_quotas() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.request_quotas, Default::default());
+
+        let toml_input = r#"
+            [quotas]
+            graph = { requests_per_sec = 100, burst = 200 }
+            channel = { requests_per_sec = 12.5 }
+
+            [[tenants]]
+            name = "okd"
+            plugin_settings = [{ name = "edge-add-remove" }]
+            quotas = { graph = { requests_per_sec = 10 } }
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        let graph = settings.request_quotas.graph.unwrap();
+        assert_eq!(graph.requests_per_sec, 100.0);
+        assert_eq!(graph.burst, Some(200));
+        assert_eq!(settings.request_quotas.channel.unwrap().burst(), 13.0);
+        let tenant = &settings.tenants[0].quotas;
+        assert_eq!(tenant.graph.unwrap().requests_per_sec, 10.0);
+        assert_eq!(tenant.channel, None);
+
+        let toml_input = "[quotas]\ngraph = { requests_per_sec = 1, limit = 2 }";
+        assert!(toml::from_str::<FileOptions>(toml_input).is_err());
+    }
+
+    #[test]
+    fn toml_state() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.state_backend, None);
+        assert_eq!(settings.state_region, "us-east-1");
+
+        let toml_input = "[state]\nbackend = \"s3\"\nurl = \"https://s3.eu-west-1.amazonaws.com/cincinnati\"\nregion = \"eu-west-1\"\nprefix = \"graph-builder/prod\"";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.state_backend.as_deref(), Some("s3"));
+        assert_eq!(
+            settings.state_url.as_deref(),
+            Some("https://s3.eu-west-1.amazonaws.com/cincinnati")
+        );
+        assert_eq!(settings.state_region, "eu-west-1");
+        assert_eq!(settings.state_prefix, "graph-builder/prod");
+
+        assert!(AppSettings::try_from_toml(
+            "[state]\nbackend = \"disk\"\ndir = \"/var/lib/cincinnati\""
+        )
+        .is_ok());
+        for invalid in &[
+            "[state]\nbackend = \"etcd\"",
+            "[state]\nbackend = \"disk\"",
+            "[state]\nbackend = \"redis\"",
+            "[state]\nbackend = \"disk\"\ndir = \"/var/lib/cincinnati\"\n[snapshots]\ndir = \"/var/lib/cincinnati/snapshots\"",
+        ] {
+            assert!(AppSettings::try_from_toml(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn toml_snapshots() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.snapshots_retention_days, 0);
+        assert_eq!(
+            settings.snapshots_gc_interval,
+            crate::snapshots::DEFAULT_GC_INTERVAL
+        );
+
+        let toml_input = "[snapshots]\ndir = \"/var/lib/cincinnati/snapshots\"\nretention = 10\nretention_days = 30\ngc_interval_secs = 600\nadmin_token_path = \"/etc/cincinnati/admin-token\"";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.snapshots_retention, 10);
+        assert_eq!(settings.snapshots_retention_days, 30);
+        assert_eq!(
+            settings.snapshots_gc_interval,
+            std::time::Duration::from_secs(600)
+        );
+        assert_eq!(
+            settings.snapshots_admin_token_path,
+            Some(std::path::PathBuf::from("/etc/cincinnati/admin-token"))
+        );
+    }
+
+    #[test]
+    fn toml_request_budgets() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.request_budgets().window, settings.pause_secs);
+        assert!(settings.request_budgets().hosts.is_empty());
+
+        let toml_input = "[budgets]\nwindow_secs = 600\naction = \"delay\"\ndefault = 1000\n[budgets.hosts]\n\"api.github.com\" = 50";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        let budgets = settings.request_budgets();
+        assert_eq!(budgets.window, std::time::Duration::from_secs(600));
+        assert_eq!(budgets.action, commons::budget::BudgetAction::Delay);
+        assert_eq!(budgets.hosts.get("api.github.com"), Some(&50));
+        assert_eq!(budgets.default, Some(1000));
+    }
+
+    #[test]
+    fn toml_http_server_settings() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.workers, None);
+        assert_eq!(settings.keep_alive, std::time::Duration::from_secs(10));
+
+        let toml_input =
+            "[service]\nworkers = 2\nworker_max_blocking_threads = 16\nkeep_alive = 30";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.workers, Some(2));
+        assert_eq!(settings.worker_max_blocking_threads, Some(16));
+        assert_eq!(settings.keep_alive, std::time::Duration::from_secs(30));
+        assert_eq!(settings.client_timeout, std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn toml_listen() {
+        let mut settings = AppSettings::default();
+        assert_eq!(
+            settings.listen_addrs(),
+            vec!["127.0.0.1:8080".parse().unwrap()]
+        );
+
+        let toml_input = "[service]\nlisten = [\"0.0.0.0:8080\", \"[::]:8080\"]\npublic_port = 8090\n[status]\nlisten = [\"[::]:9080\"]";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.listen_addrs(),
+            vec![
+                "0.0.0.0:8080".parse().unwrap(),
+                "[::]:8080".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            settings.public_listen_addrs(),
+            vec![
+                "0.0.0.0:8090".parse().unwrap(),
+                "[::]:8090".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            settings.status_listen_addrs(),
+            vec!["[::]:9080".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn toml_client_parameters() {
+        let mut settings = AppSettings::default();
+        assert!(settings.client_parameters.is_empty());
+
+        let toml_input = r#"
+            [service]
+            mandatory_client_parameters = ["channel", "id"]
+
+            [client_parameters.channel]
+            pattern = '[a-z]+-4\.[0-9]+'
+
+            [client_parameters.id]
+            format = "uuid"
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.client_parameters.len(), 2);
+        assert_eq!(
+            settings.client_parameters["id"].format,
+            Some(commons::params::ParamFormat::Uuid)
+        );
+    }
+
+    #[test]
+    fn toml_version_scheme() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.version_scheme.name(), "semver");
+
+        let toml_input = r#"
+            [upstream]
+            version_scheme = { kind = "regex", pattern = '(\d+)\.(\d+)-r(\d+)' }
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.version_scheme.name(), "regex");
+        assert!(toml::from_str::<FileOptions>("upstream.version_scheme = \"calver\"").is_err());
+    }
+
+    #[test]
+    fn toml_profile() {
+        let settings = AppSettings::try_from_toml("").unwrap();
+        assert_eq!(settings.profile.name, cincinnati::OCP_PROFILE);
+
+        let toml_input = r#"
+            profile = { name = "okd-scos", graph_data_branch = "okd" }
+
+            [upstream.registry]
+            repository = "okd/scos-nightly"
+        "#;
+        let settings = AppSettings::try_from_toml(toml_input).unwrap();
+        assert_eq!(settings.profile.name, cincinnati::OKD_SCOS_PROFILE);
+        assert_eq!(settings.profile.graph_data_branch, "okd");
+        assert_eq!(settings.profile.repository, "okd/scos-release");
+        assert_eq!(settings.repository, Some("okd/scos-nightly".to_string()));
+        assert_eq!(settings.registry, None);
+
+        assert!(toml::from_str::<FileOptions>("profile = \"rhel\"").is_err());
+    }
+
+    #[test]
+    fn toml_webhooks_settings() {
+        let mut settings = AppSettings::default();
+        assert!(settings.webhooks.is_empty());
+
+        let toml_input = r#"
+            [[webhooks]]
+            name = "stable"
+            url = "https://example.com/stable"
+            channels = ["stable-4.14"]
+
+            [[webhooks]]
+            name = "all"
+            url = "https://example.com/all"
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.webhooks.len(), 2);
+        assert_eq!(settings.webhooks[0].channels, vec!["stable-4.14"]);
+        assert!(settings.webhooks[1].channels.is_empty());
+    }
+
+    #[test]
+    fn toml_tenants_settings() {
+        let mut settings = AppSettings::default();
+        assert!(settings.tenants.is_empty());
+
+        let toml_input = r#"
+            [[tenants]]
+            name = "okd"
+            plugin_settings = [
+                { name = "release-scrape-dockerv2", repository = "openshift/okd" },
+                { name = "edge-add-remove" },
+            ]
+
+            [[tenants]]
+            name = "ocp"
+            path_prefix = "products/ocp/"
+            plugin_settings = [
+                { name = "release-scrape-dockerv2" },
+            ]
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.tenants.len(), 2);
+        assert_eq!(settings.tenants[0].name, "okd");
+        assert_eq!(settings.tenants[0].path_prefix, "/okd");
+        assert_eq!(settings.tenants[0].plugin_settings.len(), 2);
+        assert_eq!(settings.tenants[1].path_prefix, "/products/ocp");
+    }
+
+    #[test]
+    fn toml_tenants_invalid_plugin() {
+        let mut settings = AppSettings::default();
+
+        let toml_input = r#"
+            [[tenants]]
+            name = "okd"
+            plugin_settings = [{ name = "no-such-plugin" }]
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        assert!(settings.try_merge(Some(file_opts)).is_err());
+    }
+
+    #[test]
+    fn toml_events_settings() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.cloudevents_sink, None);
+
+        let toml_input = r#"
+            [events]
+            cloudevents_sink = "http://localhost:8080/events"
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.cloudevents_sink,
+            Some("http://localhost:8080/events".to_string())
+        );
+        assert_eq!(settings.cloudevents_source, "/cincinnati/graph-builder");
+        assert_eq!(settings.cloudevents_max_retries, 3);
+    }
+
     #[test]
     fn toml_sample_config() {
         use tempfile;
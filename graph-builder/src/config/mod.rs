@@ -11,7 +11,7 @@ mod file;
 mod options;
 mod settings;
 
-pub use self::settings::AppSettings;
+pub use self::settings::{AppSettings, TenantSettings};
 
 /// Common prefix for graph-builder metrics.
 pub const METRICS_PREFIX: &str = "cincinnati_gb";
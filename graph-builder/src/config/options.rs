@@ -3,8 +3,8 @@
 use super::AppSettings;
 use commons::prelude_errors::*;
 use commons::{de_path_prefix, parse_params_set, parse_path_prefix, MergeOptions};
-use std::collections::HashSet;
-use std::net::IpAddr;
+use std::collections::{BTreeMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::time::Duration;
 
@@ -21,6 +21,14 @@ pub struct StatusOptions {
     /// Port to which the status service will bind
     #[structopt(name = "status_port", long = "status.port")]
     pub port: Option<u16>,
+
+    /// Comma-separated addresses and ports on which the status service will listen, instead of `address` and `port`
+    #[structopt(long = "status.listen", use_delimiter = true)]
+    pub listen: Option<Vec<SocketAddr>>,
+
+    /// Path to a file containing the bearer token required by debug endpoints
+    #[structopt(long = "status.debug_token_path")]
+    pub debug_token_path: Option<PathBuf>,
 }
 
 /// Options for the main Cincinnati service.
@@ -50,6 +58,10 @@ pub struct ServiceOptions {
     #[structopt(name = "service_port", long = "service.port", alias = "port")]
     pub port: Option<u16>,
 
+    /// Comma-separated addresses and ports on which the server will listen, instead of `address` and `port`
+    #[structopt(long = "service.listen", use_delimiter = true)]
+    pub listen: Option<Vec<SocketAddr>>,
+
     /// Port to which the server will bind
     #[structopt(
         name = "service_public_port",
@@ -58,6 +70,10 @@ pub struct ServiceOptions {
     )]
     pub public_port: Option<u16>,
 
+    /// Port to which the gRPC service will bind (disabled if unset)
+    #[structopt(long = "service.grpc_port")]
+    pub grpc_port: Option<u16>,
+
     /// Namespace prefix for all service endpoints (e.g. '/<prefix>/graph')
     #[structopt(long = "service.path_prefix", parse(from_str = parse_path_prefix))]
     #[serde(default = "Option::default", deserialize_with = "de_path_prefix")]
@@ -73,6 +89,66 @@ pub struct ServiceOptions {
     /// Optional tracing endpoint
     #[structopt(name = "tracing_endpoint", long = "service.tracing_endpoint")]
     pub tracing_endpoint: Option<String>,
+
+    /// Maximum length of request URIs, in bytes
+    #[structopt(long = "service.max_uri_length")]
+    pub max_uri_length: Option<usize>,
+
+    /// Maximum number of query parameters per request
+    #[structopt(long = "service.max_query_params")]
+    pub max_query_params: Option<usize>,
+
+    /// Maximum total size of request headers, in bytes
+    #[structopt(long = "service.max_headers_size")]
+    pub max_headers_size: Option<usize>,
+
+    /// Number of HTTP worker threads per service (defaults to the number of physical cores)
+    #[structopt(long = "service.workers")]
+    pub workers: Option<usize>,
+
+    /// Maximum number of blocking threads per HTTP worker
+    #[structopt(long = "service.worker_max_blocking_threads")]
+    pub worker_max_blocking_threads: Option<usize>,
+
+    /// Maximum number of pending connections
+    #[structopt(long = "service.backlog")]
+    pub backlog: Option<u32>,
+
+    /// Maximum number of concurrent connections per HTTP worker
+    #[structopt(long = "service.max_connections")]
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of concurrent connection establishments per HTTP worker
+    #[structopt(long = "service.max_connection_rate")]
+    pub max_connection_rate: Option<usize>,
+
+    /// Duration (in seconds) of idle keep-alive connections
+    #[structopt(
+        long = "service.keep_alive",
+        parse(try_from_str = duration_from_secs)
+    )]
+    #[serde(default = "Option::default", deserialize_with = "de_duration_secs")]
+    pub keep_alive: Option<Duration>,
+
+    /// Timeout (in seconds) for clients to send the first request
+    #[structopt(
+        long = "service.client_timeout",
+        parse(try_from_str = duration_from_secs)
+    )]
+    #[serde(default = "Option::default", deserialize_with = "de_duration_secs")]
+    pub client_timeout: Option<Duration>,
+
+    /// Maximum number of releases in a built graph
+    #[structopt(long = "service.max_graph_releases")]
+    pub max_graph_releases: Option<u64>,
+
+    /// Maximum number of edges in a built graph
+    #[structopt(long = "service.max_graph_edges")]
+    pub max_graph_edges: Option<u64>,
+
+    /// Latency (in milliseconds) above which requests are logged
+    #[structopt(long = "service.slow_request_threshold_ms")]
+    pub slow_request_threshold_ms: Option<u64>,
 }
 
 /// Options for the Docker-registry-v2 fetcher.
@@ -102,6 +178,110 @@ pub struct DockerRegistryOptions {
     pub fetch_concurrency: Option<usize>,
 }
 
+/// Options for graph-change event emission.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct EventsOptions {
+    /// URL of the sink receiving CloudEvents on graph changes
+    #[structopt(long = "events.cloudevents_sink")]
+    pub cloudevents_sink: Option<String>,
+
+    /// Source attribute of the emitted CloudEvents
+    #[structopt(long = "events.cloudevents_source")]
+    pub cloudevents_source: Option<String>,
+
+    /// Maximum number of delivery retries per event
+    #[structopt(long = "events.cloudevents_max_retries")]
+    pub cloudevents_max_retries: Option<u32>,
+}
+
+/// Options for publishing graph updates to a message broker.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct PublishOptions {
+    /// Message broker backend ("kafka" or "nats")
+    #[structopt(long = "publish.backend")]
+    pub backend: Option<String>,
+
+    /// URL of the Kafka REST proxy or NATS server
+    #[structopt(long = "publish.url")]
+    pub url: Option<String>,
+
+    /// Kafka topic or NATS subject to publish to
+    #[structopt(long = "publish.topic")]
+    pub topic: Option<String>,
+
+    /// Whether to also publish full graph snapshots
+    #[structopt(long = "publish.snapshots")]
+    pub snapshots: Option<bool>,
+
+    /// Maximum number of delivery retries per message
+    #[structopt(long = "publish.max_retries")]
+    pub max_retries: Option<u32>,
+}
+
+/// Options for persisting graph snapshots.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct SnapshotsOptions {
+    /// Directory in which graph snapshots are persisted
+    #[structopt(long = "snapshots.dir")]
+    pub dir: Option<PathBuf>,
+
+    /// Number of graph snapshots kept
+    #[structopt(long = "snapshots.retention")]
+    pub retention: Option<usize>,
+
+    /// Number of latest days of which the newest graph snapshot is kept
+    #[structopt(long = "snapshots.retention-days")]
+    pub retention_days: Option<u32>,
+
+    /// Duration of the pause (in seconds) between garbage collections of the snapshots
+    #[structopt(
+        long = "snapshots.gc-interval-secs",
+        parse(try_from_str = duration_from_secs)
+    )]
+    #[serde(default = "Option::default", deserialize_with = "de_duration_secs")]
+    pub gc_interval_secs: Option<Duration>,
+
+    /// Path to a file containing the bearer token required to pin snapshots
+    #[structopt(long = "snapshots.admin-token-path")]
+    pub admin_token_path: Option<PathBuf>,
+
+    /// Whether to serve the latest snapshot until the first scrape succeeds
+    #[structopt(long = "snapshots.warm-start")]
+    pub warm_start: Option<bool>,
+}
+
+/// Options for the store of the persisted state.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct StateOptions {
+    /// Backend of the state store, one of "disk", "s3" or "redis"
+    #[structopt(long = "state.backend")]
+    pub backend: Option<String>,
+
+    /// Directory of the disk state store
+    #[structopt(long = "state.dir")]
+    pub dir: Option<PathBuf>,
+
+    /// URL of the S3 bucket or of the Redis server of the state store
+    #[structopt(long = "state.url")]
+    pub url: Option<String>,
+
+    /// Region of the S3 bucket of the state store
+    #[structopt(long = "state.region")]
+    pub region: Option<String>,
+
+    /// Prefix of the keys of the state store
+    #[structopt(long = "state.prefix")]
+    pub prefix: Option<String>,
+}
+
+/// Options for the changelog of the served graph.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct ChangelogOptions {
+    /// Number of graph changes kept, 0 to disable the changelog
+    #[structopt(long = "changelog.size")]
+    pub size: Option<usize>,
+}
+
 impl MergeOptions<Option<ServiceOptions>> for AppSettings {
     fn try_merge(&mut self, opts: Option<ServiceOptions>) -> Fallible<()> {
         if let Some(service) = opts {
@@ -109,9 +289,35 @@ impl MergeOptions<Option<ServiceOptions>> for AppSettings {
             assign_if_some!(self.scrape_timeout_secs, service.scrape_timeout_secs);
             assign_if_some!(self.address, service.address);
             assign_if_some!(self.port, service.port);
+            assign_if_some!(self.listen, service.listen);
             assign_if_some!(self.public_port, service.public_port);
+            assign_if_some!(self.grpc_port, service.grpc_port);
             assign_if_some!(self.path_prefix, service.path_prefix);
             assign_if_some!(self.tracing_endpoint, service.tracing_endpoint);
+            assign_if_some!(self.request_limits.max_uri_length, service.max_uri_length);
+            assign_if_some!(
+                self.request_limits.max_query_params,
+                service.max_query_params
+            );
+            assign_if_some!(
+                self.request_limits.max_headers_size,
+                service.max_headers_size
+            );
+            assign_if_some!(self.workers, service.workers);
+            assign_if_some!(
+                self.worker_max_blocking_threads,
+                service.worker_max_blocking_threads
+            );
+            assign_if_some!(self.backlog, service.backlog);
+            assign_if_some!(self.max_connections, service.max_connections);
+            assign_if_some!(self.max_connection_rate, service.max_connection_rate);
+            assign_if_some!(self.keep_alive, service.keep_alive);
+            assign_if_some!(self.client_timeout, service.client_timeout);
+            assign_if_some!(self.max_graph_releases, service.max_graph_releases);
+            assign_if_some!(self.max_graph_edges, service.max_graph_edges);
+            if let Some(millis) = service.slow_request_threshold_ms {
+                self.slow_request_threshold = Some(Duration::from_millis(millis));
+            }
             if let Some(params) = service.mandatory_client_parameters {
                 self.mandatory_client_parameters.extend(params);
             }
@@ -125,6 +331,8 @@ impl MergeOptions<Option<StatusOptions>> for AppSettings {
         if let Some(status) = opts {
             assign_if_some!(self.status_address, status.address);
             assign_if_some!(self.status_port, status.port);
+            assign_if_some!(self.status_listen, status.listen);
+            assign_if_some!(self.status_debug_token_path, status.debug_token_path);
         }
         Ok(())
     }
@@ -143,6 +351,286 @@ impl MergeOptions<Option<DockerRegistryOptions>> for AppSettings {
     }
 }
 
+impl MergeOptions<Option<EventsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<EventsOptions>) -> Fallible<()> {
+        if let Some(events) = opts {
+            assign_if_some!(self.cloudevents_sink, events.cloudevents_sink);
+            assign_if_some!(self.cloudevents_source, events.cloudevents_source);
+            assign_if_some!(self.cloudevents_max_retries, events.cloudevents_max_retries);
+        }
+        Ok(())
+    }
+}
+
+impl MergeOptions<Option<SnapshotsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<SnapshotsOptions>) -> Fallible<()> {
+        if let Some(snapshots) = opts {
+            assign_if_some!(self.snapshots_dir, snapshots.dir);
+            assign_if_some!(self.snapshots_retention, snapshots.retention);
+            assign_if_some!(self.snapshots_retention_days, snapshots.retention_days);
+            assign_if_some!(self.snapshots_gc_interval, snapshots.gc_interval_secs);
+            assign_if_some!(self.snapshots_admin_token_path, snapshots.admin_token_path);
+            assign_if_some!(self.snapshots_warm_start, snapshots.warm_start);
+        }
+        Ok(())
+    }
+}
+
+impl MergeOptions<Option<StateOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<StateOptions>) -> Fallible<()> {
+        if let Some(state) = opts {
+            assign_if_some!(self.state_backend, state.backend);
+            assign_if_some!(self.state_dir, state.dir);
+            assign_if_some!(self.state_url, state.url);
+            assign_if_some!(self.state_region, state.region);
+            assign_if_some!(self.state_prefix, state.prefix);
+        }
+        Ok(())
+    }
+}
+
+impl MergeOptions<Option<ChangelogOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<ChangelogOptions>) -> Fallible<()> {
+        if let Some(changelog) = opts {
+            assign_if_some!(self.changelog_size, changelog.size);
+        }
+        Ok(())
+    }
+}
+
+impl MergeOptions<Option<PublishOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<PublishOptions>) -> Fallible<()> {
+        if let Some(publish) = opts {
+            assign_if_some!(self.publish_backend, publish.backend);
+            assign_if_some!(self.publish_url, publish.url);
+            assign_if_some!(self.publish_topic, publish.topic);
+            assign_if_some!(self.publish_snapshots, publish.snapshots);
+            assign_if_some!(self.publish_max_retries, publish.max_retries);
+        }
+        Ok(())
+    }
+}
+
+/// Metrics options.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsOptions {
+    /// Static labels added to every exported metric, by name
+    pub labels: Option<BTreeMap<String, String>>,
+}
+
+impl MergeOptions<Option<MetricsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<MetricsOptions>) -> Fallible<()> {
+        if let Some(metrics) = opts {
+            assign_if_some!(self.metrics_labels, metrics.labels);
+        }
+        Ok(())
+    }
+}
+
+/// Options for the periodic self-check of the serving path.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SelfCheckOptions {
+    /// Duration of the pause (in seconds) between self-checks, 0 to disable them
+    #[serde(default = "Option::default", deserialize_with = "de_duration_secs")]
+    pub interval_secs: Option<Duration>,
+
+    /// Query parameters of the self-check graph requests
+    pub params: Option<BTreeMap<String, String>>,
+
+    /// Minimum number of releases of the served graph
+    pub min_releases: Option<u64>,
+
+    /// Minimum number of edges of the served graph
+    pub min_edges: Option<u64>,
+}
+
+impl MergeOptions<Option<SelfCheckOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<SelfCheckOptions>) -> Fallible<()> {
+        if let Some(self_check) = opts {
+            if let Some(interval) = self_check.interval_secs {
+                self.self_check_interval = Some(interval).filter(|interval| !interval.is_zero());
+            }
+            assign_if_some!(self.self_check_params, self_check.params);
+            assign_if_some!(self.self_check_min_releases, self_check.min_releases);
+            assign_if_some!(self.self_check_min_edges, self_check.min_edges);
+        }
+        Ok(())
+    }
+}
+
+/// Options for the staging of the built graphs.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StagingOptions {
+    /// Whether built graphs are validated in a staging slot before being served
+    pub enabled: Option<bool>,
+
+    /// Whether staged graphs passing the validation rules are served right away
+    pub auto_promote: Option<bool>,
+
+    /// Path to a file containing the bearer token required by the staging endpoints
+    pub admin_token_path: Option<PathBuf>,
+
+    /// Minimum number of releases of the staged graphs
+    pub min_releases: Option<u64>,
+
+    /// Minimum number of edges of the staged graphs
+    pub min_edges: Option<u64>,
+
+    /// Maximum number of releases removed from the served graph
+    pub max_removed_releases: Option<u64>,
+
+    /// Maximum number of edges removed from the served graph
+    pub max_removed_edges: Option<u64>,
+}
+
+impl MergeOptions<Option<StagingOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<StagingOptions>) -> Fallible<()> {
+        if let Some(staging) = opts {
+            assign_if_some!(self.staging_enabled, staging.enabled);
+            assign_if_some!(self.staging_auto_promote, staging.auto_promote);
+            assign_if_some!(self.staging_min_releases, staging.min_releases);
+            assign_if_some!(self.staging_min_edges, staging.min_edges);
+            assign_if_some!(self.staging_admin_token_path, staging.admin_token_path);
+            assign_if_some!(
+                self.staging_max_removed_releases,
+                staging.max_removed_releases
+            );
+            assign_if_some!(self.staging_max_removed_edges, staging.max_removed_edges);
+        }
+        Ok(())
+    }
+}
+
+/// Options for the simulation of graph-data changes.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulateOptions {
+    /// Path to a file containing the bearer token required by the simulation endpoint
+    pub token_path: Option<PathBuf>,
+}
+
+impl MergeOptions<Option<SimulateOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<SimulateOptions>) -> Fallible<()> {
+        if let Some(simulate) = opts {
+            assign_if_some!(self.simulate_token_path, simulate.token_path);
+        }
+        Ok(())
+    }
+}
+
+/// Options for the dry-run scrapes.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DryRunOptions {
+    /// Path to a file containing the bearer token required by the dry-run endpoint
+    pub token_path: Option<PathBuf>,
+}
+
+impl MergeOptions<Option<DryRunOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<DryRunOptions>) -> Fallible<()> {
+        if let Some(dry_run) = opts {
+            assign_if_some!(self.dry_run_token_path, dry_run.token_path);
+        }
+        Ok(())
+    }
+}
+
+/// Options for the attestations of the served graph.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttestationOptions {
+    /// Path to a file containing the base64-encoded Ed25519 key signing the attestations
+    pub key_path: Option<PathBuf>,
+
+    /// Identifier of the signing key, recorded in the attestations
+    pub key_id: Option<String>,
+}
+
+impl MergeOptions<Option<AttestationOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<AttestationOptions>) -> Fallible<()> {
+        if let Some(attestation) = opts {
+            assign_if_some!(self.attestation_key_path, attestation.key_path);
+            assign_if_some!(self.attestation_key_id, attestation.key_id);
+        }
+        Ok(())
+    }
+}
+
+/// Options for the request quotas of the main graph.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuotasOptions {
+    /// Quota of all the requests to the graph
+    pub graph: Option<commons::limits::Quota>,
+
+    /// Quota of the requests to each channel of the graph
+    pub channel: Option<commons::limits::Quota>,
+}
+
+impl MergeOptions<Option<QuotasOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<QuotasOptions>) -> Fallible<()> {
+        if let Some(quotas) = opts {
+            assign_if_some!(self.request_quotas.graph, quotas.graph);
+            assign_if_some!(self.request_quotas.channel, quotas.channel);
+        }
+        Ok(())
+    }
+}
+
+/// Options for the budgets of outbound requests.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestBudgetsOptions {
+    /// Duration of the window (in seconds) over which requests are counted
+    #[serde(default = "Option::default", deserialize_with = "de_duration_secs")]
+    pub window_secs: Option<Duration>,
+
+    /// Handling of the requests exceeding their budget
+    pub action: Option<commons::budget::BudgetAction>,
+
+    /// Maximum number of requests per window, by remote host
+    pub hosts: Option<BTreeMap<String, u64>>,
+
+    /// Maximum number of requests per window to other hosts
+    pub default: Option<u64>,
+}
+
+impl MergeOptions<Option<RequestBudgetsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<RequestBudgetsOptions>) -> Fallible<()> {
+        if let Some(budgets) = opts {
+            assign_if_some!(self.request_budget_window, budgets.window_secs);
+            assign_if_some!(self.request_budget_action, budgets.action);
+            assign_if_some!(self.request_budgets, budgets.hosts);
+            assign_if_some!(self.request_budget_default, budgets.default);
+        }
+        Ok(())
+    }
+}
+
+/// Path aliases options.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PathAliasesOptions {
+    /// Whether requests to paths ending with a slash are served as if sent without it
+    pub trailing_slash: Option<bool>,
+
+    /// Aliases of request paths, matched in order
+    pub aliases: Option<Vec<commons::aliases::PathAlias>>,
+}
+
+impl MergeOptions<Option<PathAliasesOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<PathAliasesOptions>) -> Fallible<()> {
+        if let Some(paths) = opts {
+            assign_if_some!(self.path_aliases.trailing_slash, paths.trailing_slash);
+            assign_if_some!(self.path_aliases.aliases, paths.aliases);
+        }
+        Ok(())
+    }
+}
+
 pub fn de_duration_secs<'de, D>(deserializer: D) -> Result<Option<std::time::Duration>, D::Error>
 where
     D: serde::Deserializer<'de>,
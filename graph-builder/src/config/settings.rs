@@ -4,9 +4,9 @@ use super::{cli, file};
 use cincinnati::plugins::catalog::{build_plugins, PluginSettings};
 use cincinnati::plugins::BoxedPlugin;
 use commons::prelude_errors::*;
-use commons::MergeOptions;
-use std::collections::HashSet;
-use std::net::{IpAddr, Ipv4Addr};
+use commons::{ConfigError, MergeOptions};
+use std::collections::{BTreeMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
 use std::time;
 use structopt::StructOpt;
@@ -24,9 +24,11 @@ pub struct AppSettings {
     /// Required client parameters for the main service.
     pub mandatory_client_parameters: HashSet<String>,
 
-    /// Metadata key where to record the manifest-reference.
-    #[default("io.openshift.upgrades.graph.release.manifestref")]
-    pub manifestref_key: String,
+    /// Constraints on the values of required client parameters, by parameter.
+    pub client_parameters: BTreeMap<String, commons::params::ParamConstraint>,
+
+    /// Metadata key where to record the manifest-reference, in the namespace of the profile if unset.
+    pub manifestref_key: Option<String>,
 
     /// Endpoints namespace for the main service.
     pub path_prefix: String,
@@ -38,22 +40,73 @@ pub struct AppSettings {
     /// Timeout (in seconds) per registry scrape.
     pub scrape_timeout_secs: Option<time::Duration>,
 
+    /// Whether to build the graph once, write it and exit.
+    pub oneshot: bool,
+
+    /// Optional file to which the graph is written in one-shot mode, instead of stdout.
+    pub oneshot_output: Option<PathBuf>,
+
     /// Listening port for the main service.
     #[default(8080)]
     pub port: u16,
 
+    /// Listening addresses for the main service, instead of `address` and `port` if not empty.
+    pub listen: Vec<SocketAddr>,
+
     /// Public port for graph-builder
     #[default(8090)]
     pub public_port: u16,
 
+    /// Optional listening port for the gRPC service.
+    pub grpc_port: Option<u16>,
+
+    /// Number of HTTP worker threads per service, defaults to the number of physical cores.
+    pub workers: Option<usize>,
+
+    /// Maximum number of blocking threads per HTTP worker, defaults to 512 divided by the number of workers.
+    pub worker_max_blocking_threads: Option<usize>,
+
+    /// Maximum number of pending connections.
+    #[default(1024)]
+    pub backlog: u32,
+
+    /// Maximum number of concurrent connections per HTTP worker.
+    #[default(25_000)]
+    pub max_connections: usize,
+
+    /// Maximum number of concurrent connection establishments per HTTP worker.
+    #[default(256)]
+    pub max_connection_rate: usize,
+
+    /// Duration of idle keep-alive connections.
+    #[default(time::Duration::from_secs(10))]
+    pub keep_alive: time::Duration,
+
+    /// Timeout for clients to send the first request.
+    #[default(time::Duration::from_secs(5))]
+    pub client_timeout: time::Duration,
+
+    /// Optional maximum number of releases in a built graph.
+    pub max_graph_releases: Option<u64>,
+
+    /// Optional maximum number of edges in a built graph.
+    pub max_graph_edges: Option<u64>,
+
+    /// Optional latency above which requests are logged.
+    pub slow_request_threshold: Option<time::Duration>,
+
+    /// Conventions of the served product, e.g. its repositories and metadata keys.
+    pub profile: cincinnati::ProductProfile,
+
     // TODO(lucab): split this in (TLS, hostname+port).
-    /// Target host for the registry scraper.
-    #[default(cincinnati::plugins::internal::release_scrape_dockerv2::DEFAULT_SCRAPE_REGISTRY.to_string())]
-    pub registry: String,
+    /// Target host for the registry scraper, the registry of the profile if unset.
+    pub registry: Option<String>,
+
+    /// Target image for the registry scraper, the repository of the profile if unset.
+    pub repository: Option<String>,
 
-    /// Target image for the registry scraper.
-    #[default(cincinnati::plugins::internal::release_scrape_dockerv2::DEFAULT_SCRAPE_REPOSITORY.to_string())]
-    pub repository: String,
+    /// Scheme ordering the versions of the releases, e.g. to find the newest release of a channel.
+    pub version_scheme: cincinnati::VersionScheme,
 
     /// Listening address for the status service.
     #[default(IpAddr::V4(Ipv4Addr::LOCALHOST))]
@@ -63,6 +116,114 @@ pub struct AppSettings {
     #[default(9080)]
     pub status_port: u16,
 
+    /// Listening addresses for the status service, instead of `status_address` and `status_port` if not empty.
+    pub status_listen: Vec<SocketAddr>,
+
+    /// Optional file containing the bearer token required by debug endpoints.
+    pub status_debug_token_path: Option<PathBuf>,
+
+    /// Optional directory in which graph snapshots are persisted.
+    pub snapshots_dir: Option<PathBuf>,
+
+    /// Number of graph snapshots kept.
+    #[default(crate::snapshots::DEFAULT_RETENTION)]
+    pub snapshots_retention: usize,
+
+    /// Whether to serve the latest snapshot until the first scrape succeeds.
+    pub snapshots_warm_start: bool,
+
+    /// Number of latest days of which the newest graph snapshot is kept.
+    pub snapshots_retention_days: u32,
+
+    /// Pause between garbage collections of the graph snapshots.
+    #[default(crate::snapshots::DEFAULT_GC_INTERVAL)]
+    pub snapshots_gc_interval: time::Duration,
+
+    /// Optional file containing the bearer token required to pin graph snapshots.
+    pub snapshots_admin_token_path: Option<PathBuf>,
+
+    /// Optional backend of the store of the persisted state.
+    pub state_backend: Option<String>,
+
+    /// Directory of the disk state store.
+    pub state_dir: Option<PathBuf>,
+
+    /// URL of the S3 bucket or of the Redis server of the state store.
+    pub state_url: Option<String>,
+
+    /// Region of the S3 bucket of the state store.
+    #[default(crate::state_store::DEFAULT_S3_REGION.to_string())]
+    pub state_region: String,
+
+    /// Prefix of the keys of the state store.
+    pub state_prefix: String,
+
+    /// Number of graph changes kept in the changelog, 0 if disabled.
+    #[default(crate::changelog::DEFAULT_CAPACITY)]
+    pub changelog_size: usize,
+
+    /// Pause between self-checks of the serving path, if enabled.
+    pub self_check_interval: Option<time::Duration>,
+
+    /// Query parameters of the self-check graph requests.
+    pub self_check_params: BTreeMap<String, String>,
+
+    /// Minimum number of releases of the graphs served to self-checks.
+    #[default(1)]
+    pub self_check_min_releases: u64,
+
+    /// Minimum number of edges of the graphs served to self-checks.
+    pub self_check_min_edges: u64,
+
+    /// Whether built graphs are validated in a staging slot before being served.
+    pub staging_enabled: bool,
+
+    /// Whether staged graphs passing the validation rules are served right away.
+    #[default(true)]
+    pub staging_auto_promote: bool,
+
+    /// Optional file containing the bearer token required by the staging endpoints.
+    pub staging_admin_token_path: Option<PathBuf>,
+
+    /// Minimum number of releases of the staged graphs.
+    pub staging_min_releases: u64,
+
+    /// Minimum number of edges of the staged graphs.
+    pub staging_min_edges: u64,
+
+    /// Maximum number of releases a staged graph may remove from the served one.
+    pub staging_max_removed_releases: Option<u64>,
+
+    /// Maximum number of edges a staged graph may remove from the served one.
+    pub staging_max_removed_edges: Option<u64>,
+
+    /// Optional file containing the bearer token required by the simulation endpoint,
+    /// which is enabled when set.
+    pub simulate_token_path: Option<PathBuf>,
+
+    /// Optional file containing the bearer token required by the dry-run endpoint,
+    /// which is enabled when set.
+    pub dry_run_token_path: Option<PathBuf>,
+
+    /// Optional file containing the Ed25519 key signing the attestations of
+    /// the served graphs, which are enabled when set.
+    pub attestation_key_path: Option<PathBuf>,
+
+    /// Optional identifier of the attestation key.
+    pub attestation_key_id: Option<String>,
+
+    /// Window over which outbound requests are counted, the pause between scrapes if unset.
+    pub request_budget_window: Option<time::Duration>,
+
+    /// Handling of the outbound requests exceeding their budget.
+    pub request_budget_action: commons::budget::BudgetAction,
+
+    /// Maximum number of outbound requests per window, by remote host.
+    pub request_budgets: BTreeMap<String, u64>,
+
+    /// Maximum number of outbound requests per window to other hosts.
+    pub request_budget_default: Option<u64>,
+
     /// Global log level.
     #[default(log::LevelFilter::Warn)]
     pub verbosity: log::LevelFilter,
@@ -83,6 +244,78 @@ pub struct AppSettings {
 
     /// Jaeger host and port for tracing support
     pub tracing_endpoint: Option<String>,
+
+    /// Optional sink URL for CloudEvents emitted on graph changes.
+    pub cloudevents_sink: Option<String>,
+
+    /// Source attribute of the emitted CloudEvents.
+    #[default("/cincinnati/graph-builder")]
+    pub cloudevents_source: String,
+
+    /// Maximum number of delivery retries per CloudEvent.
+    #[default(3)]
+    pub cloudevents_max_retries: u32,
+
+    /// Optional message broker backend for graph update publishing.
+    pub publish_backend: Option<String>,
+
+    /// URL of the message broker.
+    pub publish_url: Option<String>,
+
+    /// Kafka topic or NATS subject for graph updates.
+    #[default("cincinnati.graph.updates")]
+    pub publish_topic: String,
+
+    /// Whether to publish full graph snapshots along with change summaries.
+    pub publish_snapshots: bool,
+
+    /// Maximum number of delivery retries per published message.
+    #[default(3)]
+    pub publish_max_retries: u32,
+
+    /// Outbound webhooks notified on channel changes.
+    pub webhooks: Vec<crate::webhooks::WebhookConfig>,
+
+    /// Additional graphs, each scraped and served under its own prefix.
+    pub tenants: Vec<TenantSettings>,
+
+    /// Limits enforced on requests to the main and public services.
+    pub request_limits: commons::limits::RequestLimits,
+
+    /// Quotas of the requests to the main graph.
+    pub request_quotas: commons::limits::RequestQuotas,
+
+    /// Aliases of request paths of the main and public services.
+    pub path_aliases: commons::aliases::PathAliases,
+
+    /// Static labels added to every exported metric, by name.
+    pub metrics_labels: BTreeMap<String, String>,
+}
+
+/// Runtime settings of a tenant.
+#[derive(Debug)]
+pub struct TenantSettings {
+    /// Tenant name, used as `tenant` label on its metrics.
+    pub name: String,
+
+    /// Endpoints namespace for the tenant.
+    pub path_prefix: String,
+
+    /// Plugin configuration.
+    pub plugin_settings: Vec<Box<dyn PluginSettings>>,
+
+    /// Quotas of the requests to the tenant graph.
+    pub quotas: commons::limits::RequestQuotas,
+}
+
+impl TenantSettings {
+    /// Build the configured plugins of the tenant.
+    pub fn build_plugins(
+        &self,
+        registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Vec<BoxedPlugin>> {
+        build_plugins(&self.plugin_settings, registry)
+    }
 }
 
 impl AppSettings {
@@ -92,18 +325,57 @@ impl AppSettings {
         // Source options.
         let cli_opts = cli::CliOptions::from_args();
         let file_opts = match &cli_opts.config_path {
-            Some(ref path) => Some(file::FileOptions::read_filepath(path)?),
+            Some(ref path) => {
+                Some(file::FileOptions::read_filepath(path).map_err(ConfigError::unreadable)?)
+            }
             None => None,
         };
         let defaults = Self::default();
 
         // Combine options into a single config.
         let mut cfg = defaults;
-        cfg.try_merge(cli_opts)?;
-        cfg.try_merge(file_opts)?;
+        cfg.try_merge(cli_opts).map_err(ConfigError::invalid)?;
+        cfg.try_merge(file_opts).map_err(ConfigError::invalid)?;
 
         // Validate and convert to settings.
-        Self::try_validate(cfg)
+        Self::try_validate(cfg).map_err(ConfigError::invalid)
+    }
+
+    /// Merge a TOML configuration with defaults, and transform it into
+    /// valid runtime settings.
+    pub fn try_from_toml(content: &str) -> Fallible<Self> {
+        let file_opts: file::FileOptions = toml::from_str(content)
+            .context("failed to parse TOML configuration")
+            .map_err(ConfigError::unreadable)?;
+
+        let mut cfg = Self::default();
+        cfg.try_merge(Some(file_opts))
+            .map_err(ConfigError::invalid)?;
+
+        Self::try_validate(cfg).map_err(ConfigError::invalid)
+    }
+
+    /// Return the listening addresses of the main service.
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        commons::listen::listen_addrs(&self.listen, self.address, self.port)
+    }
+
+    /// Return the listening addresses of the public service, on the
+    /// addresses of the main service.
+    pub fn public_listen_addrs(&self) -> Vec<SocketAddr> {
+        let mut addrs = Vec::new();
+        for addr in self.listen_addrs() {
+            let public_addr = SocketAddr::new(addr.ip(), self.public_port);
+            if !addrs.contains(&public_addr) {
+                addrs.push(public_addr);
+            }
+        }
+        addrs
+    }
+
+    /// Return the listening addresses of the status service.
+    pub fn status_listen_addrs(&self) -> Vec<SocketAddr> {
+        commons::listen::listen_addrs(&self.status_listen, self.status_address, self.status_port)
     }
 
     /// Validate and return configured plugins.
@@ -122,12 +394,179 @@ impl AppSettings {
         build_plugins(plugin_settings, registry)
     }
 
+    /// Budgets of the outbound requests of scrapes.
+    pub fn request_budgets(&self) -> commons::budget::RequestBudgets {
+        commons::budget::RequestBudgets {
+            window: self.request_budget_window.unwrap_or(self.pause_secs),
+            action: self.request_budget_action,
+            hosts: self.request_budgets.clone(),
+            default: self.request_budget_default,
+        }
+    }
+
     /// Validate and build runtime settings.
     fn try_validate(self) -> Fallible<Self> {
         if self.pause_secs.as_secs() == 0 {
             bail!("unexpected 0s pause");
         }
 
+        if self.request_limits.max_uri_length == 0
+            || self.request_limits.max_query_params == 0
+            || self.request_limits.max_headers_size == 0
+        {
+            bail!("request limits must be greater than 0");
+        }
+        self.path_aliases.validate()?;
+        self.request_quotas
+            .validate()
+            .context("invalid request quotas")?;
+        commons::metrics::validate_labels(&self.metrics_labels)?;
+        commons::params::ParamConstraints::validate(
+            &self.client_parameters,
+            &self.mandatory_client_parameters,
+        )?;
+
+        if self.workers == Some(0) || self.worker_max_blocking_threads == Some(0) {
+            bail!("HTTP worker and blocking thread counts must be greater than 0");
+        }
+
+        if let Some(grpc_port) = self.grpc_port {
+            if grpc_port == self.public_port
+                || self
+                    .listen_addrs()
+                    .iter()
+                    .any(|addr| addr.port() == grpc_port)
+            {
+                bail!("gRPC service configured with the same port as an HTTP service");
+            }
+        }
+
+        let status_addrs = self.status_listen_addrs();
+        if let Some(addr) = self
+            .listen_addrs()
+            .iter()
+            .chain(self.public_listen_addrs().iter())
+            .find(|addr| status_addrs.contains(addr))
+        {
+            bail!(
+                "main and status service configured with the same address and port {}",
+                addr
+            );
+        }
+
+        if let Some(sink) = &self.cloudevents_sink {
+            reqwest::Url::parse(sink)
+                .context(format!("invalid CloudEvents sink URL '{}'", sink))?;
+        }
+
+        let mut webhook_names = HashSet::new();
+        for webhook in &self.webhooks {
+            if !webhook_names.insert(&webhook.name) {
+                bail!("duplicate webhook name '{}'", webhook.name);
+            }
+            reqwest::Url::parse(&webhook.url).context(format!(
+                "invalid URL '{}' for webhook '{}'",
+                webhook.url, webhook.name
+            ))?;
+        }
+
+        if self.snapshots_retention == 0 {
+            bail!("snapshot retention must be positive");
+        }
+        if self.snapshots_warm_start && self.snapshots_dir.is_none() && self.state_backend.is_none()
+        {
+            bail!("snapshot warm start requires a snapshots directory or a state store");
+        }
+        if self.snapshots_gc_interval.is_zero() {
+            bail!("unexpected 0s snapshot garbage collection interval");
+        }
+
+        if self.staging_enabled
+            && !self.staging_auto_promote
+            && self.staging_admin_token_path.is_none()
+        {
+            bail!("manual staging promotion requires an admin token path");
+        }
+
+        self.request_budgets().validate()?;
+
+        if self.self_check_interval.is_some() {
+            if let Some(missing) = self
+                .mandatory_client_parameters
+                .iter()
+                .find(|param| !self.self_check_params.contains_key(*param))
+            {
+                bail!(
+                    "self-check parameters lack the mandatory client parameter '{}'",
+                    missing
+                );
+            }
+        }
+
+        if let Some(backend) = &self.state_backend {
+            match backend.parse::<crate::state_store::StateBackend>()? {
+                crate::state_store::StateBackend::Disk if self.state_dir.is_none() => {
+                    bail!("the disk state store requires a directory")
+                }
+                crate::state_store::StateBackend::S3 | crate::state_store::StateBackend::Redis
+                    if self.state_url.is_none() =>
+                {
+                    bail!("the '{}' state store requires a URL", backend)
+                }
+                _ => {}
+            }
+            if self.snapshots_dir.is_some() {
+                bail!(
+                    "snapshots are kept in the state store, the snapshots directory must be unset"
+                );
+            }
+        }
+
+        if let Some(backend) = &self.publish_backend {
+            backend.parse::<crate::publish::PublisherBackend>()?;
+            if self.publish_url.is_none() {
+                bail!("publishing to '{}' requires a broker URL", backend);
+            }
+        }
+
+        let mut tenant_names = HashSet::new();
+        let mut tenant_prefixes = HashSet::new();
+        for tenant in &self.tenants {
+            if tenant.name.is_empty()
+                || !tenant
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                bail!(
+                    "invalid tenant name '{}', expected alphanumeric characters, '-' or '_'",
+                    tenant.name
+                );
+            }
+            if !tenant_names.insert(&tenant.name) {
+                bail!("duplicate tenant name '{}'", tenant.name);
+            }
+            if tenant.path_prefix.trim_matches('/').is_empty()
+                || tenant.path_prefix.trim_matches('/') == self.path_prefix.trim_matches('/')
+            {
+                bail!(
+                    "path prefix '{}' of tenant '{}' collides with the main service",
+                    tenant.path_prefix,
+                    tenant.name
+                );
+            }
+            if !tenant_prefixes.insert(&tenant.path_prefix) {
+                bail!("duplicate tenant path prefix '{}'", tenant.path_prefix);
+            }
+            if tenant.plugin_settings.is_empty() {
+                bail!("no plugin settings configured for tenant '{}'", tenant.name);
+            }
+            tenant.quotas.validate().context(format!(
+                "invalid request quotas of tenant '{}'",
+                tenant.name
+            ))?;
+        }
+
         Ok(self)
     }
 
@@ -140,6 +579,7 @@ impl AppSettings {
                 tempfile::tempdir().expect("failed to create tempdir");
         };
 
+        let profile = &self.profile;
         let plugins = vec![
             ReleaseScrapeDockerv2Settings::deserialize_config(toml::from_str(&format!(
                 r#"
@@ -151,9 +591,11 @@ impl AppSettings {
                     {}
                 "#,
                 ReleaseScrapeDockerv2Plugin::PLUGIN_NAME,
-                &self.registry,
-                &self.repository,
-                &self.manifestref_key,
+                self.registry.as_ref().unwrap_or(&profile.registry),
+                self.repository.as_ref().unwrap_or(&profile.repository),
+                self.manifestref_key
+                    .clone()
+                    .unwrap_or_else(|| profile.metadata_key("release.manifestref")),
                 self.fetch_concurrency,
                 self.credentials_path
                     .as_ref()
@@ -165,12 +607,15 @@ impl AppSettings {
             GithubOpenshiftSecondaryMetadataScraperSettings::deserialize_config(toml::from_str(
                 &format!(
                     r#"
-                        github_org = "openshift"
-                        github_repo = "cincinnati-graph-data"
-                        branch = "master"
+                        github_org = "{}"
+                        github_repo = "{}"
+                        branch = "{}"
                         output_directory = {:?}
                         {}
                     "#,
+                    profile.graph_data_org,
+                    profile.graph_data_repo,
+                    profile.graph_data_branch,
                     &GRAPH_DATA_DIR.path(),
                     std::env::var(GITHUB_SCRAPER_TOKEN_PATH_ENV)
                         .map(|path| format!("oauth_token_path = {:?}", path))
@@ -181,14 +626,84 @@ impl AppSettings {
                 toml::from_str(&format!(
                     r#"
                         data_directory = {:?}
+                        key_prefix = "{}"
                     "#,
                     &GRAPH_DATA_DIR.path(),
+                    profile.key_prefix,
                 ))
                 .context("Parsing config string to settings")?,
             )?,
-            plugin_config!(("name", EdgeAddRemovePlugin::PLUGIN_NAME))?,
+            plugin_config!(
+                ("name", EdgeAddRemovePlugin::PLUGIN_NAME),
+                ("key_prefix", profile.key_prefix.as_str())
+            )?,
         ];
 
         Ok(plugins)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::plugins::prelude::*;
+
+    fn tenant(name: &str, path_prefix: &str) -> TenantSettings {
+        TenantSettings {
+            name: name.to_string(),
+            path_prefix: path_prefix.to_string(),
+            plugin_settings: vec![
+                plugin_config!(("name", EdgeAddRemovePlugin::PLUGIN_NAME)).unwrap()
+            ],
+            quotas: Default::default(),
+        }
+    }
+
+    #[test]
+    fn validate_tenants() {
+        let settings = AppSettings {
+            tenants: vec![tenant("ocp", "/ocp"), tenant("okd", "/okd")],
+            ..Default::default()
+        };
+        assert!(AppSettings::try_validate(settings).is_ok());
+    }
+
+    #[test]
+    fn reject_invalid_tenants() {
+        let invalid = vec![
+            vec![tenant("ocp", "/ocp"), tenant("ocp", "/okd")],
+            vec![tenant("ocp", "/ocp"), tenant("okd", "/ocp")],
+            vec![tenant("o c p", "/ocp")],
+            vec![tenant("ocp", "/")],
+            vec![TenantSettings {
+                plugin_settings: vec![],
+                ..tenant("ocp", "/ocp")
+            }],
+            vec![TenantSettings {
+                quotas: commons::limits::RequestQuotas {
+                    graph: Some(commons::limits::Quota {
+                        requests_per_sec: 0.0,
+                        burst: None,
+                    }),
+                    channel: None,
+                },
+                ..tenant("ocp", "/ocp")
+            }],
+        ];
+
+        for tenants in invalid {
+            let settings = AppSettings {
+                tenants,
+                ..Default::default()
+            };
+            assert!(AppSettings::try_validate(settings).is_err());
+        }
+
+        let settings = AppSettings {
+            path_prefix: "/ocp".to_string(),
+            tenants: vec![tenant("ocp", "/ocp/")],
+            ..Default::default()
+        };
+        assert!(AppSettings::try_validate(settings).is_err());
+    }
+}
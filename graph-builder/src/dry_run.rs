@@ -0,0 +1,238 @@
+//! Dry-run scrapes.
+//!
+//! Operators can check the effect of registry or graph-data changes on the
+//! served graph before a scrape serves it. A dry run runs the whole plugin
+//! chain like a scrape, but the graph it builds is only compared with the
+//! served graph: it is never served, staged, published or snapshotted.
+//!
+//! Plugins keep state across runs, e.g. the directory of the scraped
+//! graph-data, so the plugin chains of a dry run and of the scrapes of the
+//! same graph never run concurrently.
+
+use crate::config::AppSettings;
+use crate::graph::{check_graph_size, State};
+use crate::simulate::SimulationResult;
+use crate::staging::{find_graph, StagingRules};
+use actix_web::{web, HttpRequest, HttpResponse};
+use cincinnati::plugins::{InternalIO, PluginIO};
+use commons::prelude_errors::*;
+use parking_lot::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Bearer token required by the dry-run endpoint.
+pub use commons::auth::BearerToken as DryRunToken;
+
+/// Outcome of a dry run, compared with the served graph.
+#[derive(Debug, Serialize)]
+pub struct DryRunResult {
+    /// Time spent in the plugin chain, in seconds.
+    pub duration_secs: f64,
+    /// Changes to the served graph.
+    #[serde(flatten)]
+    pub changes: SimulationResult,
+    /// Reasons for a scrape not to serve the built graph right away, i.e.
+    /// exceeded size limits and violated staging rules.
+    pub violations: Vec<String>,
+}
+
+/// Runner of the dry-run scrapes of a graph.
+#[derive(Debug)]
+pub struct DryRun {
+    /// Timeout of the plugin chain.
+    timeout: Option<Duration>,
+    /// Maximum number of releases of the built graphs.
+    max_releases: Option<u64>,
+    /// Maximum number of edges of the built graphs.
+    max_edges: Option<u64>,
+    /// Metadata key listing the channels of a release.
+    channels_key: String,
+    /// Held while the plugin chain runs, by the scrapes and the dry runs.
+    chain: Mutex<()>,
+    /// Held while a dry run runs, so that only one runs at a time.
+    running: Mutex<()>,
+}
+
+impl DryRun {
+    /// Create the dry-run runner of a graph, if enabled in `settings`.
+    pub fn from_settings(settings: &AppSettings) -> Option<Self> {
+        settings.dry_run_token_path.as_ref().map(|_| {
+            Self::new(
+                settings.scrape_timeout_secs,
+                settings.max_graph_releases,
+                settings.max_graph_edges,
+                settings.profile.channels_key(),
+            )
+        })
+    }
+
+    /// Create a runner of the plugin chain within `timeout`, checking the
+    /// built graphs against the size limits and reading the channels of
+    /// releases from the `channels_key` metadata.
+    pub fn new(
+        timeout: Option<Duration>,
+        max_releases: Option<u64>,
+        max_edges: Option<u64>,
+        channels_key: String,
+    ) -> Self {
+        Self {
+            timeout,
+            max_releases,
+            max_edges,
+            channels_key,
+            chain: Default::default(),
+            running: Default::default(),
+        }
+    }
+
+    /// Lock the plugin chain, for the duration of a scrape.
+    pub(crate) fn lock_chain(&self) -> MutexGuard<'_, ()> {
+        self.chain.lock()
+    }
+
+    /// Run the plugin chain of `state`, comparing the built graph with the served one.
+    ///
+    /// Returns `None` if no graph is served yet.
+    pub fn run(&self, state: &State) -> Fallible<Option<DryRunResult>> {
+        let served = match state.graph()? {
+            Some(served) => served,
+            None => return Ok(None),
+        };
+
+        let start = Instant::now();
+        let built = {
+            let _chain = self.lock_chain();
+            cincinnati::plugins::process_blocking(
+                state.plugins().iter(),
+                PluginIO::InternalIO(InternalIO {
+                    graph: Default::default(),
+                    parameters: Default::default(),
+                }),
+                self.timeout,
+            )?
+            .graph
+        };
+
+        Ok(Some(self.evaluate(
+            &served,
+            &built,
+            state.staging().map(|staging| staging.rules()),
+            start.elapsed(),
+        )))
+    }
+
+    /// Compare the `built` graph with the `served` one, checking it against
+    /// the size limits and the staging `rules`, if any.
+    fn evaluate(
+        &self,
+        served: &cincinnati::Graph,
+        built: &cincinnati::Graph,
+        rules: Option<&StagingRules>,
+        duration: Duration,
+    ) -> DryRunResult {
+        let mut violations = Vec::new();
+        if let Err(reason) = check_graph_size(built, self.max_releases, self.max_edges) {
+            violations.push(reason);
+        }
+        if let Some(rules) = rules {
+            violations.extend(rules.validate(served, built));
+        }
+
+        DryRunResult {
+            duration_secs: duration.as_secs_f64(),
+            changes: SimulationResult::compare(served, built, &self.channels_key),
+            violations,
+        }
+    }
+}
+
+/// Run a dry-run scrape of the graph selected by the `path_prefix` query
+/// parameter, the default graph if unset.
+pub async fn serve_dry_run(
+    req: HttpRequest,
+    token: web::Data<DryRunToken>,
+    app_data: web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = token.check(&req) {
+        return response;
+    }
+
+    let state = match find_graph(&req, &app_data) {
+        Ok(state) => state.clone(),
+        Err(response) => return response,
+    };
+    let dry_run = match state.dry_run() {
+        Some(dry_run) => dry_run.clone(),
+        None => return HttpResponse::NotFound().body("dry runs are disabled"),
+    };
+
+    let result = web::block(move || {
+        let _running = match dry_run.running.try_lock() {
+            Some(running) => running,
+            None => return Err(None),
+        };
+        info!("dry-run scrape of '{}' requested", state.path_prefix());
+        dry_run.run(&state).map_err(Some)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(result))) => HttpResponse::Ok().json(result),
+        Ok(Ok(None)) => HttpResponse::ServiceUnavailable().body("no graph served yet"),
+        Ok(Err(None)) => HttpResponse::TooManyRequests().body("a dry run is already running"),
+        Ok(Err(Some(e))) => {
+            debug!("dry run failed: {:#}", e);
+            HttpResponse::BadGateway().body(format!("{:#}", e))
+        }
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+
+    fn graph(releases: usize, edges: Vec<(usize, usize)>) -> cincinnati::Graph {
+        generate_custom_graph(
+            "image",
+            (0..releases).map(|i| (i, Default::default())).collect(),
+            Some(edges),
+        )
+    }
+
+    #[test]
+    fn evaluate_built_graphs() {
+        let served = graph(3, vec![(0, 1), (1, 2)]);
+        let dry_run = DryRun::new(
+            None,
+            Some(3),
+            None,
+            cincinnati::ProductProfile::default().channels_key(),
+        );
+
+        let result = dry_run.evaluate(&served, &served, None, Duration::from_secs(1));
+        assert_eq!(result.changes.releases, 3);
+        assert!(result.changes.diff.removed_edges.is_empty());
+        assert!(result.violations.is_empty());
+
+        let rules = StagingRules {
+            max_removed_edges: Some(0),
+            ..Default::default()
+        };
+        let result = dry_run.evaluate(
+            &served,
+            &graph(4, vec![(0, 1)]),
+            Some(&rules),
+            Duration::ZERO,
+        );
+        assert_eq!(result.changes.diff.added_releases.len(), 1);
+        assert_eq!(result.changes.diff.removed_edges.len(), 1);
+        assert_eq!(
+            result.violations,
+            vec![
+                "graph with 4 releases exceeds the limit of 3".to_string(),
+                "1 edges removed, expected at most 0".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! CloudEvents emission for graph changes.
+//!
+//! Events are delivered to a configurable sink using the HTTP protocol
+//! binding in structured content mode, from a thread of their own so that
+//! a slow or unavailable sink does not hold back serving new graphs.
+
+use cincinnati::GraphDiff;
+use commons::http::RetryPolicy;
+use commons::prelude_errors::*;
+use prometheus::IntCounter;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// CloudEvents specification version.
+pub static CLOUDEVENTS_SPEC_VERSION: &str = "1.0";
+
+/// Content type for structured-mode CloudEvents.
+pub static CLOUDEVENTS_CONTENT_TYPE: &str = "application/cloudevents+json";
+
+/// Type of the event emitted when the graph changes.
+pub static GRAPH_CHANGED_EVENT_TYPE: &str = "io.openshift.upgrades.graph.changed";
+
+/// Timeout for delivering a single event to the sink.
+static SINK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Upper bound of the pause between delivery retries.
+static MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Number of graph changes waiting to be emitted before new ones are dropped.
+const EVENTS_QUEUE_CAPACITY: usize = 16;
+
+lazy_static! {
+    static ref CLOUDEVENTS_SENT: IntCounter = IntCounter::new(
+        "cloudevents_sent_total",
+        "Total number of CloudEvents delivered to the sink"
+    )
+    .unwrap();
+    static ref CLOUDEVENTS_ERRORS: IntCounter = IntCounter::new(
+        "cloudevents_errors_total",
+        "Total number of CloudEvents which failed to be delivered"
+    )
+    .unwrap();
+    static ref CLOUDEVENTS_RETRIES: IntCounter = IntCounter::new(
+        "cloudevents_retries_total",
+        "Total number of CloudEvents delivery retries"
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(CLOUDEVENTS_SENT.clone()))?;
+    registry.register(Box::new(CLOUDEVENTS_ERRORS.clone()))?;
+    registry.register(Box::new(CLOUDEVENTS_RETRIES.clone()))?;
+    Ok(())
+}
+
+/// A CloudEvent in structured content mode.
+#[derive(Debug, Serialize)]
+struct CloudEvent<'a, T> {
+    specversion: &'a str,
+    id: String,
+    source: &'a str,
+    #[serde(rename = "type")]
+    ty: &'a str,
+    time: String,
+    datacontenttype: &'a str,
+    data: T,
+}
+
+/// Emitter of CloudEvents to an HTTP sink.
+#[derive(Debug)]
+pub struct CloudEventsEmitter {
+    client: reqwest::Client,
+    runtime: tokio::runtime::Runtime,
+    sink: reqwest::Url,
+    source: String,
+    sequence: u64,
+    retry_policy: RetryPolicy,
+}
+
+/// Queue of graph changes, emitted by the thread of a `CloudEventsEmitter`.
+#[derive(Debug)]
+pub struct CloudEventsQueue {
+    queue: mpsc::SyncSender<GraphDiff>,
+}
+
+impl CloudEventsQueue {
+    /// Queue a graph-changed event for the given diff.
+    ///
+    /// Events are dropped, and counted as errors, if the queue is full.
+    pub fn emit_graph_changed(&self, diff: GraphDiff) {
+        if self.queue.try_send(diff).is_err() {
+            CLOUDEVENTS_ERRORS.inc();
+            error!("CloudEvents queue unavailable, dropping event");
+        }
+    }
+}
+
+impl CloudEventsEmitter {
+    /// Create a new emitter delivering events with the given `source` to `sink`.
+    pub fn try_new(sink: &str, source: String, max_retries: u32) -> Fallible<Self> {
+        let sink = reqwest::Url::parse(sink)
+            .context(format!("invalid CloudEvents sink URL '{}'", sink))?;
+        let client = commons::http::HttpClientBuilder::new()
+            .timeout(SINK_TIMEOUT)
+            .build()
+            .context("building CloudEvents HTTP client")?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("building CloudEvents runtime")?;
+
+        Ok(Self {
+            client,
+            runtime,
+            sink,
+            source,
+            sequence: 0,
+            retry_policy: RetryPolicy {
+                max_retries,
+                initial_backoff: Duration::from_secs(1),
+                max_backoff: MAX_RETRY_BACKOFF,
+            },
+        })
+    }
+
+    /// Start emitting the graph changes of the returned queue from a thread of their own.
+    pub fn spawn(mut self) -> Fallible<CloudEventsQueue> {
+        let (queue, diffs) = mpsc::sync_channel::<GraphDiff>(EVENTS_QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("cloudevents".to_string())
+            .spawn(move || {
+                for diff in diffs {
+                    if let Err(err) = self.emit_graph_changed(&diff) {
+                        err.chain().for_each(|cause| error!("{}", cause));
+                    }
+                }
+            })
+            .context("starting CloudEvents emitting thread")?;
+
+        Ok(CloudEventsQueue { queue })
+    }
+
+    /// Deliver a graph-changed event for the given diff, blocking until the sink accepts it
+    /// or the retries are exhausted.
+    pub fn emit_graph_changed(&mut self, diff: &GraphDiff) -> Fallible<()> {
+        let body = self.event_body(GRAPH_CHANGED_EVENT_TYPE, diff)?;
+
+        let mut attempt = 0;
+        loop {
+            match self.send(&body) {
+                Ok(()) => {
+                    CLOUDEVENTS_SENT.inc();
+                    return Ok(());
+                }
+                Err(err) if attempt < self.retry_policy.max_retries => {
+                    CLOUDEVENTS_RETRIES.inc();
+                    warn!("failed to emit CloudEvent, retrying: {}", err);
+                    // Only the emitting thread waits.
+                    std::thread::sleep(self.retry_policy.backoff(attempt));
+                    attempt += 1;
+                }
+                Err(err) => {
+                    CLOUDEVENTS_ERRORS.inc();
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Serialize a new event, keeping its id across delivery retries.
+    fn event_body<T: serde::Serialize>(&mut self, ty: &str, data: T) -> Fallible<Vec<u8>> {
+        let now = chrono::Utc::now();
+        self.sequence += 1;
+
+        let event = CloudEvent {
+            specversion: CLOUDEVENTS_SPEC_VERSION,
+            id: format!("{}-{}", now.timestamp(), self.sequence),
+            source: &self.source,
+            ty,
+            time: now.to_rfc3339(),
+            datacontenttype: "application/json",
+            data,
+        };
+        Ok(serde_json::to_vec(&event).context("serializing CloudEvent")?)
+    }
+
+    fn send(&self, body: &[u8]) -> Fallible<()> {
+        let request = self
+            .client
+            .post(self.sink.clone())
+            .header(reqwest::header::CONTENT_TYPE, CLOUDEVENTS_CONTENT_TYPE)
+            .body(body.to_vec())
+            .send();
+        let response = self
+            .runtime
+            .block_on(request)
+            .context(format!("sending CloudEvent to {}", self.sink))?;
+
+        ensure!(
+            response.status().is_success(),
+            "CloudEvents sink {} responded with status {}",
+            self.sink,
+            response.status()
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_structured_event() -> Fallible<()> {
+        let diff = GraphDiff {
+            added_releases: vec!["4.1.0".to_string()],
+            ..Default::default()
+        };
+        let event = CloudEvent {
+            specversion: CLOUDEVENTS_SPEC_VERSION,
+            id: "1-1".to_string(),
+            source: "/cincinnati/graph-builder",
+            ty: GRAPH_CHANGED_EVENT_TYPE,
+            time: "2019-01-01T00:00:00+00:00".to_string(),
+            datacontenttype: "application/json",
+            data: &diff,
+        };
+
+        let value = serde_json::to_value(&event)?;
+        assert_eq!(value["specversion"], "1.0");
+        assert_eq!(value["type"], GRAPH_CHANGED_EVENT_TYPE);
+        assert_eq!(value["source"], "/cincinnati/graph-builder");
+        assert_eq!(value["data"]["added_releases"][0], "4.1.0");
+
+        Ok(())
+    }
+
+    #[test]
+    fn emit_gives_up_after_max_retries() -> Fallible<()> {
+        // Nothing listens on the discard port.
+        let mut emitter =
+            CloudEventsEmitter::try_new("http://127.0.0.1:9/", "/test".to_string(), 2)?;
+        emitter.retry_policy.initial_backoff = Duration::from_millis(1);
+        emitter.retry_policy.max_backoff = Duration::from_millis(1);
+
+        let retries = CLOUDEVENTS_RETRIES.get();
+        assert!(emitter.emit_graph_changed(&GraphDiff::default()).is_err());
+        assert_eq!(CLOUDEVENTS_RETRIES.get() - retries, 2);
+        assert_eq!(emitter.sequence, 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,430 @@
+//! Freshness of the channels of the built graph.
+//!
+//! After each successful scrape, the sub-graph of each channel, i.e. its
+//! releases and the edges between them, is compared with that of the
+//! previous scrape. The times at which the newest release of a channel and
+//! its sub-graph last changed are exported as ages, in seconds, so that a
+//! channel which the release pipeline stopped updating can be alerted on.
+//!
+//! Changes are only observed by the running process: after a restart, ages
+//! count from the first scrape. Beyond `DEFAULT_MAX_LABEL_SETS` channels,
+//! the gauges of the remaining ones are aggregated into the `other` channel,
+//! holding their largest ages.
+
+use crate::webhooks;
+use cincinnati::VersionScheme;
+use commons::metrics::{GuardedGaugeVec, GuardedVec, DEFAULT_MAX_LABEL_SETS, OVERFLOW_LABEL};
+use commons::Fallible;
+use prometheus::{GaugeVec, Opts};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::SystemTime;
+
+/// Releases of a channel and the edges between them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChannelSubgraph {
+    /// Versions of the releases in the channel.
+    pub releases: BTreeSet<String>,
+    /// Edges between releases of the channel, as `(from, to)` versions.
+    pub edges: BTreeSet<(String, String)>,
+}
+
+impl ChannelSubgraph {
+    /// Return the newest release of the channel, ordered by `version_scheme`.
+    fn newest(&self, version_scheme: &VersionScheme) -> Option<&str> {
+        self.releases
+            .iter()
+            .max_by(|a, b| version_scheme.compare(a, b))
+            .map(String::as_str)
+    }
+}
+
+/// Map each channel of `graph`, as listed in the `channels_key` metadata, to its sub-graph.
+pub fn channel_subgraphs(
+    graph: &cincinnati::Graph,
+    channels_key: &str,
+) -> BTreeMap<String, ChannelSubgraph> {
+    webhooks::channel_releases(graph, channels_key)
+        .into_iter()
+        .map(|(channel, releases)| {
+            let mut edges = BTreeSet::new();
+            for from in &releases {
+                let id = match graph.find_by_version(from) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                for (_, _, to) in graph.next_releases(&id) {
+                    if releases.contains(to.version()) {
+                        edges.insert((from.clone(), to.version().to_string()));
+                    }
+                }
+            }
+            (channel, ChannelSubgraph { releases, edges })
+        })
+        .collect()
+}
+
+/// Freshness gauges of the channels.
+#[derive(Clone)]
+pub struct FreshnessMetrics {
+    /// Time since the newest release of each channel changed.
+    newest_release_age: GuardedGaugeVec,
+    /// Time since the sub-graph of each channel changed.
+    last_change_age: GuardedGaugeVec,
+}
+
+impl FreshnessMetrics {
+    pub fn try_new() -> Fallible<Self> {
+        Self::with_max_channels(DEFAULT_MAX_LABEL_SETS)
+    }
+
+    /// Create gauges for at most `max_channels` channels.
+    fn with_max_channels(max_channels: usize) -> Fallible<Self> {
+        Ok(Self {
+            newest_release_age: GuardedVec::new(
+                GaugeVec::new(
+                    Opts::new(
+                        "graph_channel_newest_release_age_seconds",
+                        "Time since the newest release of the channel changed, in seconds",
+                    ),
+                    &["channel"],
+                )?,
+                max_channels,
+            ),
+            last_change_age: GuardedVec::new(
+                GaugeVec::new(
+                    Opts::new(
+                        "graph_channel_last_change_age_seconds",
+                        "Time since the releases or edges of the channel changed, in seconds",
+                    ),
+                    &["channel"],
+                )?,
+                max_channels,
+            ),
+        })
+    }
+
+    pub fn register(&self, registry: &prometheus::Registry) -> Fallible<()> {
+        registry.register(Box::new(self.newest_release_age.clone()))?;
+        registry.register(Box::new(self.last_change_age.clone()))?;
+        Ok(())
+    }
+}
+
+/// Observed state of a channel.
+#[derive(Debug)]
+struct ChannelState {
+    subgraph: ChannelSubgraph,
+    newest: Option<String>,
+    newest_changed: SystemTime,
+    subgraph_changed: SystemTime,
+}
+
+/// Tracker of the changes of the channels.
+#[derive(Debug, Default)]
+pub struct ChannelFreshness {
+    version_scheme: VersionScheme,
+    channels: BTreeMap<String, ChannelState>,
+    /// Channels which vanished since the gauges were last exported.
+    vanished: Vec<String>,
+}
+
+impl ChannelFreshness {
+    /// Create a tracker ordering the releases of channels by `version_scheme`.
+    pub fn new(version_scheme: VersionScheme) -> Self {
+        Self {
+            version_scheme,
+            ..Default::default()
+        }
+    }
+
+    /// Record the sub-graphs of the channels built at `now`.
+    pub fn update(&mut self, subgraphs: BTreeMap<String, ChannelSubgraph>, now: SystemTime) {
+        let mut previous = std::mem::take(&mut self.channels);
+        self.channels = subgraphs
+            .into_iter()
+            .map(|(channel, subgraph)| {
+                let newest = subgraph.newest(&self.version_scheme).map(str::to_string);
+                let state = match previous.remove(&channel) {
+                    Some(state) => ChannelState {
+                        newest_changed: if state.newest == newest {
+                            state.newest_changed
+                        } else {
+                            now
+                        },
+                        subgraph_changed: if state.subgraph == subgraph {
+                            state.subgraph_changed
+                        } else {
+                            now
+                        },
+                        subgraph,
+                        newest,
+                    },
+                    None => ChannelState {
+                        subgraph,
+                        newest,
+                        newest_changed: now,
+                        subgraph_changed: now,
+                    },
+                };
+                (channel, state)
+            })
+            .collect();
+        self.vanished
+            .extend(previous.into_iter().map(|(channel, _)| channel));
+    }
+
+    /// Return the age of the newest release and of the sub-graph of each channel, in seconds.
+    pub fn ages(&self, now: SystemTime) -> impl Iterator<Item = (&str, f64, f64)> + '_ {
+        let age = move |since: SystemTime| {
+            now.duration_since(since)
+                .map(|age| age.as_secs_f64())
+                .unwrap_or_default()
+        };
+        self.channels.iter().map(move |(channel, state)| {
+            (
+                channel.as_str(),
+                age(state.newest_changed),
+                age(state.subgraph_changed),
+            )
+        })
+    }
+
+    /// Set the freshness gauges at `now`, removing those of vanished channels.
+    pub fn export(&mut self, metrics: &FreshnessMetrics, now: SystemTime) {
+        for channel in self.vanished.drain(..) {
+            if self.channels.contains_key(&channel) {
+                continue;
+            }
+            metrics
+                .newest_release_age
+                .remove_label_values(&[channel.as_str()]);
+            metrics
+                .last_change_age
+                .remove_label_values(&[channel.as_str()]);
+        }
+
+        // Channels over the limit are exported by their largest ages.
+        let mut overflow: Option<(f64, f64)> = None;
+        for (channel, newest_release_age, last_change_age) in self.ages(now) {
+            if metrics.newest_release_age.track(&[channel])
+                && metrics.last_change_age.track(&[channel])
+            {
+                metrics
+                    .newest_release_age
+                    .with_label_values(&[channel])
+                    .set(newest_release_age);
+                metrics
+                    .last_change_age
+                    .with_label_values(&[channel])
+                    .set(last_change_age);
+            } else {
+                let (newest, last) = overflow.get_or_insert((0.0, 0.0));
+                *newest = newest.max(newest_release_age);
+                *last = last.max(last_change_age);
+            }
+        }
+        match overflow {
+            Some((newest_release_age, last_change_age)) => {
+                metrics
+                    .newest_release_age
+                    .with_label_values(&[OVERFLOW_LABEL])
+                    .set(newest_release_age);
+                metrics
+                    .last_change_age
+                    .with_label_values(&[OVERFLOW_LABEL])
+                    .set(last_change_age);
+            }
+            None => {
+                metrics
+                    .newest_release_age
+                    .remove_label_values(&[OVERFLOW_LABEL]);
+                metrics
+                    .last_change_age
+                    .remove_label_values(&[OVERFLOW_LABEL]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::core::Collector;
+    use std::time::Duration;
+
+    fn subgraph(releases: &[&str], edges: &[(&str, &str)]) -> ChannelSubgraph {
+        ChannelSubgraph {
+            releases: releases.iter().map(|r| r.to_string()).collect(),
+            edges: edges
+                .iter()
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn track_channel_changes() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut freshness = ChannelFreshness::default();
+
+        freshness.update(
+            vec![
+                (
+                    "stable-4.14".to_string(),
+                    subgraph(&["4.14.9", "4.14.10"], &[]),
+                ),
+                ("fast-4.14".to_string(), subgraph(&["4.14.10"], &[])),
+            ]
+            .into_iter()
+            .collect(),
+            at(0),
+        );
+        // An edge changed the sub-graph, but not the newest release.
+        freshness.update(
+            vec![
+                (
+                    "stable-4.14".to_string(),
+                    subgraph(&["4.14.9", "4.14.10"], &[("4.14.9", "4.14.10")]),
+                ),
+                (
+                    "fast-4.14".to_string(),
+                    subgraph(&["4.14.10", "4.14.11"], &[]),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            at(60),
+        );
+
+        let ages: Vec<_> = freshness.ages(at(100)).collect();
+        assert_eq!(
+            ages,
+            vec![("fast-4.14", 40.0, 40.0), ("stable-4.14", 100.0, 40.0)]
+        );
+
+        // Vanished channels are forgotten.
+        freshness.update(Default::default(), at(120));
+        assert_eq!(freshness.ages(at(120)).count(), 0);
+    }
+
+    #[test]
+    fn export_gauges() -> Fallible<()> {
+        let metrics = FreshnessMetrics::try_new()?;
+        let now = SystemTime::now();
+        let mut freshness = ChannelFreshness::default();
+        freshness.update(
+            vec![("stable-4.14".to_string(), subgraph(&["4.14.0"], &[]))]
+                .into_iter()
+                .collect(),
+            now - Duration::from_secs(30),
+        );
+
+        freshness.export(&metrics, now);
+        assert_eq!(
+            metrics
+                .newest_release_age
+                .with_label_values(&["stable-4.14"])
+                .get(),
+            30.0
+        );
+
+        freshness.update(Default::default(), now);
+        freshness.export(&metrics, now);
+        assert!(metrics.last_change_age.collect()[0].get_metric().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn aggregate_overflowing_channels() -> Fallible<()> {
+        let metrics = FreshnessMetrics::with_max_channels(1)?;
+        let now = SystemTime::now();
+        let mut freshness = ChannelFreshness::default();
+        freshness.update(
+            vec![("stable-4.14".to_string(), subgraph(&["4.14.0"], &[]))]
+                .into_iter()
+                .collect(),
+            now - Duration::from_secs(30),
+        );
+        freshness.export(&metrics, now);
+        freshness.update(
+            vec![
+                ("stable-4.14".to_string(), subgraph(&["4.14.0"], &[])),
+                ("candidate-4.15".to_string(), subgraph(&["4.15.0"], &[])),
+                ("fast-4.15".to_string(), subgraph(&["4.15.0"], &[])),
+            ]
+            .into_iter()
+            .collect(),
+            now - Duration::from_secs(10),
+        );
+
+        freshness.export(&metrics, now);
+        let ages: BTreeMap<String, f64> = metrics.newest_release_age.collect()[0]
+            .get_metric()
+            .iter()
+            .map(|m| {
+                (
+                    m.get_label()[0].get_value().to_string(),
+                    m.get_gauge().get_value(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            ages,
+            vec![
+                ("other".to_string(), 10.0),
+                ("stable-4.14".to_string(), 30.0)
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn build_channel_subgraphs() {
+        use cincinnati::testing::generate_custom_graph;
+
+        let channels_key = cincinnati::ProductProfile::default().channels_key();
+        let channels = |value: &str| -> cincinnati::MapImpl<String, String> {
+            [(channels_key.clone(), value.to_string())]
+                .iter()
+                .cloned()
+                .collect()
+        };
+        let graph = generate_custom_graph(
+            "image",
+            vec![
+                (0, channels("stable-4.14,fast-4.14")),
+                (1, channels("fast-4.14")),
+                (2, channels("stable-4.14,fast-4.14")),
+            ],
+            Some(vec![(0, 1), (0, 2), (1, 2)]),
+        );
+
+        let subgraphs = channel_subgraphs(&graph, &channels_key);
+        assert_eq!(
+            subgraphs["stable-4.14"],
+            subgraph(&["0.0.0", "2.0.0"], &[("0.0.0", "2.0.0")])
+        );
+        assert_eq!(subgraphs["fast-4.14"].edges.len(), 3);
+        assert_eq!(
+            subgraphs["fast-4.14"].newest(&VersionScheme::default()),
+            Some("2.0.0")
+        );
+    }
+
+    #[test]
+    fn newest_release_by_version_scheme() {
+        let releases = subgraph(&["2024.1.post1", "2024.1", "2024.1rc2", "2023.12"], &[]);
+
+        // Versions which are not semantic versions are ordered lexically.
+        assert_eq!(releases.newest(&VersionScheme::Semver), Some("2024.1rc2"));
+        assert_eq!(
+            releases.newest(&VersionScheme::Pep440),
+            Some("2024.1.post1")
+        );
+    }
+}
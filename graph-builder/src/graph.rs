@@ -12,66 +12,147 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::attestation::{Attestation, GraphDataRevision};
 use crate::built_info;
+use crate::changelog::Changelog;
 use crate::config;
+use crate::dry_run::DryRun;
+use crate::events::{CloudEventsEmitter, CloudEventsQueue};
+use crate::freshness::{self, ChannelFreshness, FreshnessMetrics};
+use crate::publish::{GraphUpdatePublisher, PublishQueue};
+use crate::self_check::SelfCheckReport;
+use crate::simulate::{self, Simulation};
+use crate::snapshots::SnapshotStore;
+use crate::staging::Staging;
+use crate::webhooks::WebhookDispatcher;
 use actix_files::NamedFile;
 use actix_web::http::header;
 use actix_web::{HttpRequest, HttpResponse};
+use cincinnati::plugins::internal::build_phases::{BuildPhase, PhaseTimings};
+use cincinnati::plugins::internal::release_scrape_dockerv2::tags::{self, TagListing};
 use cincinnati::plugins::prelude::*;
+use cincinnati::plugins::PluginRun;
 use cincinnati::CONTENT_TYPE;
-use commons::metrics::HasRegistry;
+use commons::budget::RequestBudget;
+use commons::limits::QuotaChannels;
+use commons::metrics::{GuardedIntCounterVec, GuardedVec, HasRegistry, DEFAULT_MAX_LABEL_SETS};
+use commons::params::ParamConstraints;
 use commons::tracing::get_tracer;
 use commons::{Fallible, GraphError, SECONDARY_METADATA_PARAM_KEY};
 use lazy_static;
 use opentelemetry::trace::{mark_span_as_active, Tracer};
 pub use parking_lot::RwLock;
 use prometheus::{
-    self, histogram_opts, labels, opts, Counter, Gauge, Histogram, IntCounterVec, IntGauge, Opts,
+    self, histogram_opts, labels, opts, Counter, Gauge, Histogram, HistogramVec, IntCounterVec,
+    IntGauge, Opts,
 };
 use serde_json;
-use std::collections::HashSet;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-lazy_static! {
-    static ref GRAPH_FINAL_RELEASES: IntGauge = IntGauge::new(
-        "graph_final_releases",
-        "Number of releases in the final graph, after processing"
-    )
-    .unwrap();
-    static ref GRAPH_LAST_SUCCESSFUL_REFRESH: IntGauge = IntGauge::new(
-        "graph_last_successful_refresh_timestamp",
-        "UTC timestamp of last successful graph refresh"
-    )
-    .unwrap();
-    static ref UPSTREAM_ERRORS: Counter = Counter::new(
-        "graph_upstream_errors_total",
-        "Total number of upstream scraping errors"
-    )
-    .unwrap();
-    static ref UPSTREAM_SCRAPES: Counter = Counter::new(
-        "graph_upstream_scrapes_total",
-        "Total number of upstream scrapes"
-    )
-    .unwrap();
-    static ref GRAPH_UPSTREAM_INITIAL_SCRAPE: Gauge = Gauge::new(
-        "graph_initial_upstream_scrape_duration",
-        "Duration of initial upstream scrape"
-    )
-    .unwrap();
+/// Number of recent scrape errors kept for the status page.
+static RECENT_ERRORS_CAPACITY: usize = 20;
+
+/// Time after which the request counter of an unused path may be pruned.
+const INCOMING_REQS_IDLE_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Metrics recorded by a scrape loop.
+#[derive(Clone)]
+struct ScrapeMetrics {
+    final_releases: IntGauge,
+    last_successful_refresh: IntGauge,
+    upstream_errors: Counter,
+    upstream_scrapes: Counter,
+    initial_scrape: Gauge,
+    stale: IntGauge,
+    size_limit_exceeded: IntGauge,
     /// Histogram with custom bucket values for upstream scraping duration in seconds
-    static ref UPSTREAM_SCRAPES_DURATION: Histogram = Histogram::with_opts(histogram_opts!(
-        "graph_upstream_scrapes_duration",
-        "Upstream scrape duration in seconds",
-        vec![5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 15.0, 20.0, 30.0 ]
-    ))
-    .unwrap();
-    static ref GRAPH_INCOMING_REQS: IntCounterVec = IntCounterVec::new(
-        Opts::new("graph_incoming_requests_total",
-        "Total number of incoming HTTP client request"),
-        &["uri_path"]
+    scrapes_duration: Histogram,
+    /// Histogram of the time spent in each build phase, in seconds
+    build_phase_duration: HistogramVec,
+    /// Freshness gauges of the channels
+    channel_freshness: FreshnessMetrics,
+}
+
+impl ScrapeMetrics {
+    fn try_new() -> Fallible<Self> {
+        Ok(Self {
+            final_releases: IntGauge::new(
+                "graph_final_releases",
+                "Number of releases in the final graph, after processing",
+            )?,
+            last_successful_refresh: IntGauge::new(
+                "graph_last_successful_refresh_timestamp",
+                "UTC timestamp of last successful graph refresh",
+            )?,
+            upstream_errors: Counter::new(
+                "graph_upstream_errors_total",
+                "Total number of upstream scraping errors",
+            )?,
+            upstream_scrapes: Counter::new(
+                "graph_upstream_scrapes_total",
+                "Total number of upstream scrapes",
+            )?,
+            initial_scrape: Gauge::new(
+                "graph_initial_upstream_scrape_duration",
+                "Duration of initial upstream scrape",
+            )?,
+            stale: IntGauge::new(
+                "graph_stale",
+                "Whether the served graph is stale because the latest scrape failed",
+            )?,
+            size_limit_exceeded: IntGauge::new(
+                "graph_size_limit_exceeded",
+                "Whether the latest built graph was discarded for exceeding the size limits",
+            )?,
+            scrapes_duration: Histogram::with_opts(histogram_opts!(
+                "graph_upstream_scrapes_duration",
+                "Upstream scrape duration in seconds",
+                vec![5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 15.0, 20.0, 30.0]
+            ))?,
+            build_phase_duration: HistogramVec::new(
+                histogram_opts!(
+                    "graph_build_phase_duration_seconds",
+                    "Time spent in each phase of graph builds, in seconds",
+                    vec![0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]
+                ),
+                &["phase"],
+            )?,
+            channel_freshness: FreshnessMetrics::try_new()?,
+        })
+    }
+
+    fn register(&self, registry: &prometheus::Registry) -> Fallible<()> {
+        registry.register(Box::new(self.final_releases.clone()))?;
+        registry.register(Box::new(self.last_successful_refresh.clone()))?;
+        registry.register(Box::new(self.upstream_errors.clone()))?;
+        registry.register(Box::new(self.upstream_scrapes.clone()))?;
+        registry.register(Box::new(self.initial_scrape.clone()))?;
+        registry.register(Box::new(self.stale.clone()))?;
+        registry.register(Box::new(self.size_limit_exceeded.clone()))?;
+        registry.register(Box::new(self.scrapes_duration.clone()))?;
+        registry.register(Box::new(self.build_phase_duration.clone()))?;
+        self.channel_freshness.register(registry)?;
+        Ok(())
+    }
+}
+
+lazy_static! {
+    /// Scrape metrics of the default graph.
+    static ref SCRAPE_METRICS: ScrapeMetrics = ScrapeMetrics::try_new().unwrap();
+    static ref GRAPH_INCOMING_REQS: GuardedIntCounterVec = GuardedVec::new(
+        IntCounterVec::new(
+            Opts::new("graph_incoming_requests_total",
+            "Total number of incoming HTTP client request"),
+            &["uri_path"]
+        )
+        .unwrap(),
+        DEFAULT_MAX_LABEL_SETS
     )
-    .unwrap();
+    .idle_timeout(INCOMING_REQS_IDLE_TIMEOUT);
     static ref BUILD_INFO: Counter = Counter::with_opts(opts!(
         "build_info",
         "Build information",
@@ -85,17 +166,94 @@ lazy_static! {
 /// Register relevant metrics to a prometheus registry.
 pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
     commons::register_metrics(registry)?;
-    registry.register(Box::new(GRAPH_FINAL_RELEASES.clone()))?;
-    registry.register(Box::new(GRAPH_LAST_SUCCESSFUL_REFRESH.clone()))?;
-    registry.register(Box::new(UPSTREAM_ERRORS.clone()))?;
-    registry.register(Box::new(UPSTREAM_SCRAPES.clone()))?;
-    registry.register(Box::new(GRAPH_UPSTREAM_INITIAL_SCRAPE.clone()))?;
-    registry.register(Box::new(UPSTREAM_SCRAPES_DURATION.clone()))?;
+    SCRAPE_METRICS.register(registry)?;
     registry.register(Box::new(GRAPH_INCOMING_REQS.clone()))?;
     registry.register(Box::new(BUILD_INFO.clone()))?;
     Ok(())
 }
 
+/// Condition of a graph, reported by the status service.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Condition {
+    /// Condition type.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// Human-readable details.
+    pub message: String,
+}
+
+/// Size of the served graph.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GraphStats {
+    /// Number of releases.
+    pub releases: u64,
+    /// Number of edges.
+    pub edges: u64,
+}
+
+/// Graph built by a scrape, ready to be served.
+#[derive(Debug)]
+pub struct BuiltGraph {
+    /// Built graph.
+    pub graph: cincinnati::Graph,
+    /// Built graph, serialized as JSON.
+    pub json: String,
+    /// Size of the built graph.
+    pub stats: GraphStats,
+    /// Secondary metadata scraped along with the graph, if any.
+    pub secondary_metadata: Option<String>,
+    /// Revision of the graph-data from which the graph was built, if known.
+    pub graph_data: Option<GraphDataRevision>,
+}
+
+/// Outcome of a scrape, reported by the status page.
+#[derive(Clone, Debug)]
+pub struct ScrapeReport {
+    /// Time at which the plugin chain completed.
+    pub finished: SystemTime,
+    /// Time spent in the plugin chain.
+    pub duration: Duration,
+    /// Reason for not serving the built graph, if the scrape failed.
+    pub error: Option<String>,
+    /// Stable code of the plugin chain failure, as reported by `GraphError::code`.
+    pub error_code: Option<String>,
+    /// Runs of the plugins, in order, up to the first failing one.
+    pub plugins: Vec<PluginRun>,
+    /// Time spent in each build phase.
+    pub phases: PhaseTimings,
+}
+
+/// Check the size of a built graph against the configured limits.
+pub fn check_graph_size(
+    graph: &cincinnati::Graph,
+    max_releases: Option<u64>,
+    max_edges: Option<u64>,
+) -> Result<(), String> {
+    let releases = graph.releases_count();
+    if let Some(max_releases) = max_releases.filter(|max| releases > *max) {
+        return Err(format!(
+            "graph with {} releases exceeds the limit of {}",
+            releases, max_releases
+        ));
+    }
+
+    let edges = graph.edges_count();
+    if let Some(max_edges) = max_edges.filter(|max| edges > *max) {
+        return Err(format!(
+            "graph with {} edges exceeds the limit of {}",
+            edges, max_edges
+        ));
+    }
+
+    Ok(())
+}
+
+/// Compute the entity tag of a serialized graph.
+pub fn graph_etag(graph_json: &str) -> String {
+    let digest = Sha256::digest(graph_json.as_bytes());
+    format!("\"{}\"", hex::encode(&digest[..16]))
+}
+
 /// Serve Cincinnati graph requests.
 pub async fn index(
     req: HttpRequest,
@@ -119,11 +277,16 @@ pub async fn index(
     // Check for required client parameters.
     let mandatory_params = &app_data.mandatory_params;
     commons::ensure_query_params(mandatory_params, req.query_string())?;
+    app_data
+        .param_constraints
+        .ensure_query_values(req.query_string())?;
 
-    let resp = HttpResponse::Ok()
-        .content_type(CONTENT_TYPE)
-        .body(app_data.json.read().clone());
-    Ok(resp)
+    let json = app_data.json.read().clone();
+    let mut resp = HttpResponse::Ok();
+    resp.content_type(CONTENT_TYPE);
+    resp.insert_header((header::ETAG, graph_etag(&json)));
+    commons::insert_stale_headers(&mut resp, app_data.stale_since().as_deref());
+    Ok(resp.body(json))
 }
 
 /// Serve Cincinnati graph-data requests.
@@ -145,13 +308,52 @@ pub async fn graph_data(
 #[derive(Clone)]
 pub struct State {
     json: Arc<RwLock<String>>,
+    /// Last successfully built graph, if shared with an embedding process.
+    shared_graph: Option<Arc<RwLock<Option<cincinnati::Graph>>>>,
     /// Query parameters that must be present in all client requests.
     mandatory_params: HashSet<String>,
+    /// Constraints on the values of the mandatory query parameters.
+    param_constraints: ParamConstraints,
     live: Arc<RwLock<bool>>,
     ready: Arc<RwLock<bool>>,
     plugins: &'static [BoxedPlugin],
     registry: &'static prometheus::Registry,
     secondary_metadata: Arc<RwLock<String>>,
+    /// Time of the first failed scrape since the graph was last refreshed.
+    stale_since: Arc<RwLock<Option<SystemTime>>>,
+    /// Reason for discarding the latest built graph, if it exceeded the size limits.
+    size_limit_exceeded: Arc<RwLock<Option<String>>>,
+    /// Size of the served graph, once a scrape succeeded.
+    graph_stats: Arc<RwLock<Option<GraphStats>>>,
+    /// Outcome of the latest scrape.
+    last_scrape: Arc<RwLock<Option<ScrapeReport>>>,
+    /// Tags seen by the latest successful scrape, by repository.
+    tag_listings: Arc<RwLock<Vec<TagListing>>>,
+    /// Most recent scrape errors, oldest first.
+    recent_errors: Arc<RwLock<VecDeque<(SystemTime, String)>>>,
+    /// Outcome of the latest self-check, if enabled.
+    last_self_check: Arc<RwLock<Option<SelfCheckReport>>>,
+    /// Endpoints namespace, as advertised in the OpenAPI document.
+    path_prefix: String,
+    scrape_metrics: ScrapeMetrics,
+    /// Store of the built graphs, if snapshots are persisted.
+    snapshots: Option<Arc<SnapshotStore>>,
+    /// Most recent changes to the served graph, if kept.
+    changelog: Option<Arc<Changelog>>,
+    /// Staging slot of the built graphs, if enabled.
+    staging: Option<Arc<Staging>>,
+    /// Simulator of graph-data changes, if enabled.
+    simulation: Option<Arc<Simulation>>,
+    /// Runner of the dry-run scrapes, if enabled.
+    dry_run: Option<Arc<DryRun>>,
+    /// Attestation of the served graph, if enabled.
+    attestation: Option<Arc<Attestation>>,
+    /// Budget of the outbound requests of the scrapes.
+    request_budget: RequestBudget,
+    /// Channels of the served graph, accounted separately by the channel request quota.
+    quota_channels: QuotaChannels,
+    /// States of the tenants served next to this graph.
+    tenants: Vec<State>,
 }
 
 impl State {
@@ -167,23 +369,347 @@ impl State {
     ) -> State {
         State {
             json,
+            shared_graph: None,
             mandatory_params,
+            param_constraints: Default::default(),
             live,
             ready,
             plugins,
             registry,
             secondary_metadata,
+            stale_since: Default::default(),
+            size_limit_exceeded: Default::default(),
+            graph_stats: Default::default(),
+            last_scrape: Default::default(),
+            tag_listings: Default::default(),
+            recent_errors: Default::default(),
+            last_self_check: Default::default(),
+            path_prefix: String::new(),
+            scrape_metrics: SCRAPE_METRICS.clone(),
+            snapshots: None,
+            changelog: None,
+            staging: None,
+            simulation: None,
+            dry_run: None,
+            attestation: None,
+            request_budget: Default::default(),
+            quota_channels: Default::default(),
+            tenants: vec![],
+        }
+    }
+
+    /// Creates the state of a tenant, with scrape metrics of its own registered to `registry`
+    pub fn try_new_tenant(
+        path_prefix: String,
+        mandatory_params: HashSet<String>,
+        plugins: &'static [BoxedPlugin],
+        registry: &'static prometheus::Registry,
+    ) -> Fallible<State> {
+        let scrape_metrics = ScrapeMetrics::try_new()?;
+        scrape_metrics.register(registry)?;
+
+        Ok(State {
+            json: Default::default(),
+            shared_graph: None,
+            mandatory_params,
+            param_constraints: Default::default(),
+            live: Default::default(),
+            ready: Default::default(),
+            plugins,
+            registry,
+            secondary_metadata: Default::default(),
+            stale_since: Default::default(),
+            size_limit_exceeded: Default::default(),
+            graph_stats: Default::default(),
+            last_scrape: Default::default(),
+            tag_listings: Default::default(),
+            recent_errors: Default::default(),
+            last_self_check: Default::default(),
+            path_prefix,
+            scrape_metrics,
+            snapshots: None,
+            changelog: None,
+            staging: None,
+            simulation: None,
+            dry_run: None,
+            attestation: None,
+            request_budget: Default::default(),
+            quota_channels: Default::default(),
+            tenants: vec![],
+        })
+    }
+
+    /// Sets the endpoints namespace
+    pub fn with_path_prefix(mut self, path_prefix: String) -> State {
+        self.path_prefix = path_prefix;
+        self
+    }
+
+    /// Sets the constraints on the values of the mandatory query parameters
+    pub fn with_param_constraints(mut self, param_constraints: ParamConstraints) -> State {
+        self.param_constraints = param_constraints;
+        self
+    }
+
+    /// Sets the store in which built graphs are persisted
+    pub fn with_snapshots(mut self, snapshots: Option<Arc<SnapshotStore>>) -> State {
+        self.snapshots = snapshots;
+        self
+    }
+
+    /// Sets the changelog in which changes to the served graph are recorded
+    pub fn with_changelog(mut self, changelog: Option<Arc<Changelog>>) -> State {
+        self.changelog = changelog;
+        self
+    }
+
+    /// Sets the staging slot in which built graphs wait before being served
+    pub fn with_staging(mut self, staging: Option<Arc<Staging>>) -> State {
+        self.staging = staging;
+        self
+    }
+
+    /// Sets the simulator to which the input of the graph-data parser is recorded
+    pub fn with_simulation(mut self, simulation: Option<Arc<Simulation>>) -> State {
+        self.simulation = simulation;
+        self
+    }
+
+    /// Sets the runner of the dry-run scrapes, which share the plugin chain with the scrape loop
+    pub fn with_dry_run(mut self, dry_run: Option<Arc<DryRun>>) -> State {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Sets the attestation of the served graph
+    pub fn with_attestation(mut self, attestation: Option<Arc<Attestation>>) -> State {
+        self.attestation = attestation;
+        self
+    }
+
+    /// Sets the budget of the outbound requests of the scrapes
+    pub fn with_request_budget(mut self, request_budget: RequestBudget) -> State {
+        self.request_budget = request_budget;
+        self
+    }
+
+    /// Keeps the last successfully built graph in memory, for processes embedding the scrape loop
+    pub fn with_shared_graph(mut self) -> State {
+        self.shared_graph = Some(Default::default());
+        self
+    }
+
+    /// Sets the tenants whose status is reported along with this state
+    pub fn with_tenants(mut self, tenants: Vec<State>) -> State {
+        self.tenants = tenants;
+        self
+    }
+
+    /// Returns the last successfully built graph, serialized as JSON
+    pub fn graph_json(&self) -> String {
+        self.json.read().clone()
+    }
+
+    /// Returns the last successfully built graph, if any
+    ///
+    /// Without a shared graph, or until the first scrape succeeds after a
+    /// warm start, the served JSON is parsed instead.
+    pub fn graph(&self) -> Fallible<Option<cincinnati::Graph>> {
+        if let Some(graph) = self
+            .shared_graph
+            .as_ref()
+            .and_then(|graph| graph.read().clone())
+        {
+            return Ok(Some(graph));
+        }
+        let json = self.json.read();
+        if json.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&json)?))
+    }
+
+    /// Returns the HTTP-date since which the served graph is stale, if scrapes are failing
+    pub fn stale_since(&self) -> Option<String> {
+        self.stale_since.read().map(commons::http_date)
+    }
+
+    /// Serves the graph of a snapshot taken at `taken`, until a scrape succeeds
+    ///
+    /// The graph is flagged as stale since the snapshot was taken.
+    pub fn warm_start(&self, json: String, stats: GraphStats, taken: SystemTime) {
+        *self.json.write() = json;
+        *self.graph_stats.write() = Some(stats);
+        *self.stale_since.write() = Some(taken);
+        self.scrape_metrics.stale.set(1);
+        *self.ready.write() = true;
+    }
+
+    /// Flags the served graph as stale, keeping the time of the first failure
+    fn mark_stale(&self) {
+        self.stale_since.write().get_or_insert_with(SystemTime::now);
+        self.scrape_metrics.stale.set(1);
+    }
+
+    /// Clears the staleness flag after a successful refresh
+    fn mark_fresh(&self) {
+        *self.stale_since.write() = None;
+        self.scrape_metrics.stale.set(0);
+    }
+
+    /// Records the outcome of a scrape, along with its error if any
+    pub(crate) fn record_scrape(&self, mut report: ScrapeReport, error: Option<String>) {
+        if let Some(error) = &error {
+            let mut recent_errors = self.recent_errors.write();
+            if recent_errors.len() == RECENT_ERRORS_CAPACITY {
+                recent_errors.pop_front();
+            }
+            recent_errors.push_back((report.finished, error.clone()));
+        }
+        report.error = error;
+        *self.last_scrape.write() = Some(report);
+    }
+
+    /// Returns the size of the served graph, if any
+    pub fn graph_stats(&self) -> Option<GraphStats> {
+        *self.graph_stats.read()
+    }
+
+    /// Returns the outcome of the latest scrape, if any
+    pub fn last_scrape(&self) -> Option<ScrapeReport> {
+        self.last_scrape.read().clone()
+    }
+
+    /// Returns the tags seen by the latest successful scrape, sorted by repository
+    pub fn tag_listings(&self) -> Vec<TagListing> {
+        self.tag_listings.read().clone()
+    }
+
+    /// Returns the most recent scrape errors, oldest first
+    pub fn recent_errors(&self) -> Vec<(SystemTime, String)> {
+        self.recent_errors.read().iter().cloned().collect()
+    }
+
+    /// Records the outcome of a self-check
+    pub(crate) fn record_self_check(&self, report: SelfCheckReport) {
+        *self.last_self_check.write() = Some(report);
+    }
+
+    /// Returns the outcome of the latest self-check, if any
+    pub fn last_self_check(&self) -> Option<SelfCheckReport> {
+        self.last_self_check.read().clone()
+    }
+
+    /// Returns the channels of the served graph, as accounted by the request quotas
+    pub fn quota_channels(&self) -> QuotaChannels {
+        self.quota_channels.clone()
+    }
+
+    /// Returns the names of the configured plugins, in order
+    pub fn plugin_names(&self) -> Vec<&'static str> {
+        self.plugins
+            .iter()
+            .map(|plugin| plugin.get_name())
+            .collect()
+    }
+
+    /// Returns the conditions currently affecting the served graph
+    pub fn conditions(&self) -> Vec<Condition> {
+        let mut conditions = vec![];
+        if let Some(stale_since) = self.stale_since() {
+            conditions.push(Condition {
+                kind: "GraphStale",
+                message: format!("scrapes failing since {}", stale_since),
+            });
+        }
+        if let Some(reason) = self.size_limit_exceeded.read().clone() {
+            conditions.push(Condition {
+                kind: "GraphSizeLimitExceeded",
+                message: reason,
+            });
+        }
+        if let Some(staged) = self.staging().and_then(Staging::staged) {
+            let mut message = format!(
+                "graph with {} releases staged at {} waits for promotion",
+                staged.releases, staged.staged
+            );
+            if !staged.violations.is_empty() {
+                message.push_str(&format!(": {}", staged.violations.join("; ")));
+            }
+            conditions.push(Condition {
+                kind: "GraphStaged",
+                message,
+            });
+        }
+        if let Some(report) = self.last_self_check.read().as_ref() {
+            if !report.failures.is_empty() {
+                conditions.push(Condition {
+                    kind: "SelfCheckFailed",
+                    message: report.failures.join("; "),
+                });
+            }
         }
+        conditions
+    }
+
+    /// Returns the query parameters that must be present in all client requests
+    pub fn mandatory_params(&self) -> &HashSet<String> {
+        &self.mandatory_params
+    }
+
+    /// Returns the constraints on the values of the mandatory query parameters
+    pub fn param_constraints(&self) -> &ParamConstraints {
+        &self.param_constraints
+    }
+
+    /// Returns the endpoints namespace
+    pub fn path_prefix(&self) -> &str {
+        &self.path_prefix
+    }
+
+    /// Returns the changelog of the served graph, if kept
+    pub fn changelog(&self) -> Option<&Changelog> {
+        self.changelog.as_deref()
+    }
+
+    /// Returns the attestation of the served graph, if enabled
+    pub fn attestation(&self) -> Option<&Attestation> {
+        self.attestation.as_deref()
+    }
+
+    /// Returns the staging slot of the built graphs, if enabled
+    pub fn staging(&self) -> Option<&Staging> {
+        self.staging.as_deref()
+    }
+
+    /// Returns the simulator of graph-data changes, if enabled
+    pub fn simulation(&self) -> Option<&Arc<Simulation>> {
+        self.simulation.as_ref()
     }
 
-    /// Returns the boolean inside self.live
+    /// Returns the runner of the dry-run scrapes, if enabled
+    pub fn dry_run(&self) -> Option<&Arc<DryRun>> {
+        self.dry_run.as_ref()
+    }
+
+    /// Returns the plugins of the scrape loop
+    pub(crate) fn plugins(&self) -> &'static [BoxedPlugin] {
+        self.plugins
+    }
+
+    /// Returns the states of the tenants
+    pub fn tenants(&self) -> &[State] {
+        &self.tenants
+    }
+
+    /// Returns the boolean inside self.live, and-ed with the liveness of all tenants
     pub fn is_live(&self) -> bool {
-        *self.live.read()
+        *self.live.read() && self.tenants.iter().all(State::is_live)
     }
 
-    /// Returns the boolean inside self.ready
+    /// Returns the boolean inside self.ready, and-ed with the readiness of all tenants
     pub fn is_ready(&self) -> bool {
-        *self.ready.read()
+        *self.ready.read() && self.tenants.iter().all(State::is_ready)
     }
 }
 
@@ -193,24 +719,248 @@ impl HasRegistry for State {
     }
 }
 
-#[allow(clippy::useless_let_if_seq)]
-pub fn run(settings: &config::AppSettings, state: &State) -> ! {
-    // Indicate if a panic happens
+/// Indicate if a panic happens, by marking `state` as not live.
+fn set_panic_hook(state: &State) {
     let previous_hook = std::panic::take_hook();
     let panic_live = state.live.clone();
     std::panic::set_hook(Box::new(move |panic_info| {
         *panic_live.write() = false;
         previous_hook(panic_info)
     }));
+}
+
+pub fn run(settings: &config::AppSettings, state: &State) -> ! {
+    set_panic_hook(state);
+
+    BUILD_INFO.inc();
+
+    // Emit CloudEvents on graph changes, if a sink is configured
+    let emitter = settings.cloudevents_sink.as_ref().and_then(|sink| {
+        match CloudEventsEmitter::try_new(
+            sink,
+            settings.cloudevents_source.clone(),
+            settings.cloudevents_max_retries,
+        )
+        .and_then(CloudEventsEmitter::spawn)
+        {
+            Ok(emitter) => Some(emitter),
+            Err(err) => {
+                error!("failed to set up CloudEvents emitter: {}", err);
+                None
+            }
+        }
+    });
+
+    // Publish graph updates to a message broker, if configured
+    let publisher = settings
+        .publish_backend
+        .as_ref()
+        .zip(settings.publish_url.as_ref())
+        .and_then(|(backend, url)| {
+            match backend.parse().and_then(|backend| {
+                GraphUpdatePublisher::try_new(
+                    backend,
+                    url,
+                    &settings.publish_topic,
+                    settings.publish_snapshots,
+                    settings.publish_max_retries,
+                )
+                .and_then(GraphUpdatePublisher::spawn)
+            }) {
+                Ok(publisher) => Some(publisher),
+                Err(err) => {
+                    error!("failed to set up graph update publisher: {}", err);
+                    None
+                }
+            }
+        });
+
+    // Notify webhooks on channel changes, if any is configured
+    let dispatcher = if settings.webhooks.is_empty() {
+        None
+    } else {
+        match WebhookDispatcher::try_new(&settings.webhooks) {
+            Ok(dispatcher) => Some(dispatcher),
+            Err(err) => {
+                error!("failed to set up webhooks: {}", err);
+                None
+            }
+        }
+    };
+
+    scrape_loop(settings, state, emitter, publisher, dispatcher)
+}
+
+/// Run the scrape loop of a tenant.
+///
+/// Graph changes of tenants are not forwarded to events, publishing or webhooks sinks.
+pub fn run_tenant(settings: &config::AppSettings, state: &State) -> ! {
+    set_panic_hook(state);
+
+    scrape_loop(settings, state, None, None, None)
+}
+
+/// Sinks and history updated whenever a graph is served.
+struct Serving {
+    emitter: Option<CloudEventsQueue>,
+    publisher: Option<PublishQueue>,
+    dispatcher: Option<WebhookDispatcher>,
+    previous_channels: Option<BTreeMap<String, BTreeSet<String>>>,
+    previous_graph: Option<cincinnati::Graph>,
+    channel_freshness: ChannelFreshness,
+    /// Metadata key listing the channels of a release, per the product profile.
+    channels_key: String,
+}
+
+impl Serving {
+    /// Serve the `built` graph, notifying the sinks of its changes.
+    fn serve(&mut self, state: &State, built: BuiltGraph) {
+        let metrics = &state.scrape_metrics;
+        let BuiltGraph {
+            graph,
+            json: json_graph,
+            stats: graph_stats,
+            secondary_metadata,
+            graph_data,
+        } = built;
+
+        if let Some(secondary_metadata) = secondary_metadata {
+            *state.secondary_metadata.write() = secondary_metadata;
+        }
+
+        let now = SystemTime::now();
+        let channel_subgraphs = freshness::channel_subgraphs(&graph, &self.channels_key);
+        state
+            .quota_channels
+            .replace(channel_subgraphs.keys().cloned());
+        self.channel_freshness.update(channel_subgraphs, now);
+        self.channel_freshness
+            .export(&metrics.channel_freshness, now);
+
+        // Webhooks are notified once the graph is served.
+        let mut webhook_deltas = None;
+        if self.dispatcher.is_some() {
+            let channels = crate::webhooks::channel_releases(&graph, &self.channels_key);
+            if let Some(previous_channels) = &self.previous_channels {
+                webhook_deltas = Some(crate::webhooks::channel_deltas(
+                    previous_channels,
+                    &channels,
+                ));
+            }
+            self.previous_channels = Some(channels);
+        }
+
+        if let Some(changelog) = &state.changelog {
+            if let Some(entry) = changelog.record(
+                self.previous_graph.as_ref().unwrap_or(&Default::default()),
+                &graph,
+                graph_stats,
+                SystemTime::now(),
+            ) {
+                debug!("recorded graph change {}", entry.sequence);
+            }
+        }
+
+        // Events are emitted and updates published once the graph is served.
+        let mut event = None;
+        let mut update = None;
+        if self.emitter.is_some() || self.publisher.is_some() {
+            let diff = cincinnati::GraphDiff::new(
+                self.previous_graph.as_ref().unwrap_or(&Default::default()),
+                &graph,
+            );
+
+            if self.emitter.is_some() && self.previous_graph.is_some() && !diff.is_empty() {
+                event = Some(diff.clone());
+            }
+
+            if let Some(publisher) = &self.publisher {
+                if !diff.is_empty() {
+                    let snapshot = Some(json_graph.clone()).filter(|_| publisher.snapshots());
+                    update = Some((diff, snapshot));
+                }
+            }
+        }
+
+        let attested = state.attestation.as_ref().map(|attestation| {
+            attestation.attest(
+                &format!("{}/v1/graph", state.path_prefix),
+                &json_graph,
+                &graph,
+                graph_data.as_ref(),
+            )
+        });
+
+        if let Some(shared_graph) = &state.shared_graph {
+            *shared_graph.write() = Some(graph.clone());
+        }
+
+        if self.emitter.is_some() || self.publisher.is_some() || state.changelog.is_some() {
+            self.previous_graph = Some(graph);
+        }
+
+        if let Some(snapshots) = &state.snapshots {
+            match snapshots.store(&json_graph, graph_stats, SystemTime::now()) {
+                Ok(Some(snapshot)) => info!("stored graph snapshot {}", snapshot.id),
+                Ok(None) => {}
+                Err(err) => err.chain().for_each(|cause| error!("{}", cause)),
+            }
+        }
+
+        *state.json.write() = json_graph;
+        *state.graph_stats.write() = Some(graph_stats);
+        if let (Some(attestation), Some(attested)) = (&state.attestation, attested) {
+            match attested {
+                Ok(envelope) => attestation.set(Some(envelope)),
+                Err(err) => {
+                    err.chain().for_each(|cause| error!("{}", cause));
+                    attestation.set(None);
+                }
+            }
+        }
+        state.mark_fresh();
+        metrics.final_releases.set(graph_stats.releases as i64);
+
+        if let (Some(emitter), Some(diff)) = (&self.emitter, event) {
+            emitter.emit_graph_changed(diff);
+        }
+        if let (Some(publisher), Some((diff, snapshot))) = (&self.publisher, update) {
+            publisher.publish_update(graph_stats.releases, diff, snapshot);
+        }
+        if let (Some(dispatcher), Some(deltas)) = (&self.dispatcher, webhook_deltas) {
+            dispatcher.dispatch(deltas);
+        }
+    }
+}
+
+/// Parse the served graph, to validate a staged graph against it.
+fn served_graph(state: &State) -> Fallible<cincinnati::Graph> {
+    Ok(serde_json::from_str(&state.graph_json())?)
+}
+
+#[allow(clippy::useless_let_if_seq)]
+fn scrape_loop(
+    settings: &config::AppSettings,
+    state: &State,
+    emitter: Option<CloudEventsQueue>,
+    publisher: Option<PublishQueue>,
+    dispatcher: Option<WebhookDispatcher>,
+) -> ! {
+    let metrics = &state.scrape_metrics;
 
     // Don't wait on the first iteration
     let mut first_iteration = true;
     let mut first_success = true;
 
-    BUILD_INFO.inc();
-
-    // Store amount of nodes in the graph for metrics
-    let mut nodes_count: i64;
+    let mut serving = Serving {
+        emitter,
+        publisher,
+        dispatcher,
+        previous_channels: None,
+        previous_graph: None,
+        channel_freshness: ChannelFreshness::new(settings.version_scheme.clone()),
+        channels_key: settings.profile.channels_key(),
+    };
 
     loop {
         // Store scrape duration value. It would be used for initial scrape gauge or scrape histogram
@@ -219,58 +969,181 @@ pub fn run(settings: &config::AppSettings, state: &State) -> ! {
         if first_iteration {
             *state.live.write() = true;
             first_iteration = false;
+        } else if let Some(staging) = &state.staging {
+            // Serve the staged graph as soon as it is promoted
+            if let Some(promoted) = staging.wait_promoted(settings.pause_secs) {
+                info!("serving promoted graph");
+                serving.serve(state, promoted);
+                continue;
+            }
         } else {
             thread::sleep(settings.pause_secs);
         }
 
         info!("graph update triggered");
-        let scrape_timer = UPSTREAM_SCRAPES_DURATION.start_timer();
-
-        let scrape = cincinnati::plugins::process_blocking(
-            state.plugins.iter(),
-            cincinnati::plugins::PluginIO::InternalIO(cincinnati::plugins::InternalIO {
-                // the first plugin will produce the initial graph
-                graph: Default::default(),
-                // the plugins used in the graph-builder don't expect any parameters yet
-                parameters: Default::default(),
-            }),
-            settings.scrape_timeout_secs,
-        );
-        UPSTREAM_SCRAPES.inc();
+        let scrape_timer = metrics.scrapes_duration.start_timer();
+
+        let chain_start = Instant::now();
+        // Dry runs don't run the plugin chain while the scrape does.
+        let chain_guard = state.dry_run.as_ref().map(|dry_run| dry_run.lock_chain());
+        // Each scrape starts a new window of outbound requests.
+        state.request_budget.restart();
+        let (chain, mut parser_input) = match &state.simulation {
+            // Keep the input of the graph-data parser to simulate changes on it
+            Some(_) => simulate::process_traced_capturing(
+                state.plugins,
+                settings.scrape_timeout_secs,
+                &state.request_budget,
+            ),
+            None => {
+                let chain = cincinnati::plugins::process_traced_budgeted_blocking(
+                    state.plugins.iter(),
+                    cincinnati::plugins::PluginIO::InternalIO(cincinnati::plugins::InternalIO {
+                        // the first plugin will produce the initial graph
+                        graph: Default::default(),
+                        // the plugins used in the graph-builder don't expect any parameters yet
+                        parameters: Default::default(),
+                    }),
+                    settings.scrape_timeout_secs,
+                    Some(state.request_budget.clone()),
+                );
+                (chain, None)
+            }
+        };
+        drop(chain_guard);
+        let chain_duration = chain_start.elapsed();
+        metrics.upstream_scrapes.inc();
+
+        let mut phases = match &chain.io {
+            Ok(internal_io) => PhaseTimings::from_parameters(&internal_io.parameters)
+                .unwrap_or_else(|err| {
+                    warn!("ignoring build phase timings: {}", err);
+                    PhaseTimings::default()
+                }),
+            Err(_) => PhaseTimings::default(),
+        };
+        phases.add(BuildPhase::PluginChain, chain_duration);
+        for (phase, duration) in phases.iter() {
+            metrics
+                .build_phase_duration
+                .with_label_values(&[phase.as_str()])
+                .observe(duration.as_secs_f64());
+        }
+
+        let report = ScrapeReport {
+            finished: SystemTime::now(),
+            duration: chain_duration,
+            error: None,
+            error_code: None,
+            plugins: chain.plugins,
+            phases,
+        };
+        let scrape = chain.io;
+
+        // Keep ages growing while scrapes fail
+        serving
+            .channel_freshness
+            .export(&metrics.channel_freshness, SystemTime::now());
 
         {
             let internal_io = match scrape {
                 Ok(internal_io) => internal_io,
                 Err(err) => {
-                    UPSTREAM_ERRORS.inc();
+                    metrics.upstream_errors.inc();
                     err.chain().for_each(|cause| error!("{}", cause));
+                    let message = format!("{:#}", err);
+                    let report = ScrapeReport {
+                        error_code: Some(GraphError::from_error(err).code()),
+                        ..report
+                    };
+                    state.record_scrape(report, Some(message));
+                    // Keep serving the previous graph, if any
+                    if !first_success {
+                        state.mark_stale();
+                    }
                     continue;
                 }
             };
+            match tags::from_parameters(&internal_io.parameters) {
+                Ok(listings) => *state.tag_listings.write() = listings,
+                Err(err) => warn!("ignoring tag listings: {}", err),
+            }
+
+            let size_check = check_graph_size(
+                &internal_io.graph,
+                settings.max_graph_releases,
+                settings.max_graph_edges,
+            );
+            if let Err(reason) = size_check {
+                error!("discarding built graph: {}", reason);
+                metrics.size_limit_exceeded.set(1);
+                state.record_scrape(report, Some(format!("discarding built graph: {}", reason)));
+                *state.size_limit_exceeded.write() = Some(reason);
+                if !first_success {
+                    state.mark_stale();
+                }
+                continue;
+            }
+            metrics.size_limit_exceeded.set(0);
+            *state.size_limit_exceeded.write() = None;
 
             let json_graph = match serde_json::to_string(&internal_io.graph) {
                 Ok(json) => json,
                 Err(err) => {
-                    UPSTREAM_ERRORS.inc();
+                    metrics.upstream_errors.inc();
                     error!("Failed to serialize graph: {}", err);
+                    state
+                        .record_scrape(report, Some(format!("failed to serialize graph: {}", err)));
+                    if !first_success {
+                        state.mark_stale();
+                    }
                     continue;
                 }
             };
 
-            if internal_io
-                .parameters
-                .contains_key(SECONDARY_METADATA_PARAM_KEY)
-            {
-                let secondary_metadata = internal_io
+            let built = BuiltGraph {
+                json: json_graph,
+                stats: GraphStats {
+                    releases: internal_io.graph.releases_count(),
+                    edges: internal_io.graph.edges_count(),
+                },
+                secondary_metadata: internal_io
                     .parameters
                     .get(SECONDARY_METADATA_PARAM_KEY)
-                    .unwrap();
-
-                *state.secondary_metadata.write() = secondary_metadata.to_string();
+                    .cloned(),
+                graph_data: GraphDataRevision::from_parameters(&internal_io.parameters),
+                graph: internal_io.graph,
+            };
+            if let (Some(simulation), Some(input)) = (&state.simulation, parser_input.take()) {
+                simulation.record(input, built.graph.clone());
             }
 
-            *state.json.write() = json_graph;
-            nodes_count = internal_io.graph.releases_count() as i64;
+            // Once a graph is served, built graphs are validated against it
+            // before replacing it, unless staging is disabled.
+            let built = match (&state.staging, state.graph_stats()) {
+                (Some(staging), Some(_)) => {
+                    // A graph promoted during the scrape is served first.
+                    if let Some(promoted) = staging.take_promoted() {
+                        info!("serving promoted graph");
+                        serving.serve(state, promoted);
+                    }
+                    match served_graph(state) {
+                        Ok(served) => staging.admit(&served, built),
+                        Err(err) => {
+                            error!("failed to parse the served graph: {}", err);
+                            Some(built)
+                        }
+                    }
+                }
+                _ => Some(built),
+            };
+
+            if let Some(built) = built {
+                let releases = built.stats.releases;
+                serving.serve(state, built);
+                info!("graph update completed, {} valid releases", releases);
+            }
+            state.record_scrape(report, None);
         }
 
         // Record scrape duration
@@ -279,14 +1152,39 @@ pub fn run(settings: &config::AppSettings, state: &State) -> ! {
         if first_success {
             *state.ready.write() = true;
             first_success = false;
-            GRAPH_UPSTREAM_INITIAL_SCRAPE.set(scrape_value);
+            metrics.initial_scrape.set(scrape_value);
         } else {
-            UPSTREAM_SCRAPES_DURATION.observe(scrape_value);
+            metrics.scrapes_duration.observe(scrape_value);
         }
 
-        GRAPH_LAST_SUCCESSFUL_REFRESH.set(chrono::Utc::now().timestamp() as i64);
+        metrics
+            .last_successful_refresh
+            .set(chrono::Utc::now().timestamp() as i64);
+    }
+}
 
-        GRAPH_FINAL_RELEASES.set(nodes_count);
-        info!("graph update completed, {} valid releases", nodes_count);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+
+    #[test]
+    fn graph_size_limits() {
+        let graph = generate_custom_graph(
+            "image",
+            (0..3).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (1, 2)]),
+        );
+
+        assert_eq!(check_graph_size(&graph, None, None), Ok(()));
+        assert_eq!(check_graph_size(&graph, Some(3), Some(2)), Ok(()));
+        assert_eq!(
+            check_graph_size(&graph, Some(2), None),
+            Err("graph with 3 releases exceeds the limit of 2".to_string())
+        );
+        assert_eq!(
+            check_graph_size(&graph, None, Some(1)),
+            Err("graph with 2 edges exceeds the limit of 1".to_string())
+        );
     }
 }
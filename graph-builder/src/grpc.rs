@@ -0,0 +1,65 @@
+//! gRPC graph service.
+
+use crate::graph::State;
+use commons::grpc::{self, proto};
+use commons::GraphError;
+use std::collections::HashMap;
+use tonic::{Request, Response, Status};
+
+/// Graph service backed by the last graph built by the scraper.
+#[derive(Clone)]
+pub struct GraphService {
+    state: State,
+}
+
+impl GraphService {
+    /// Creates a new service serving the graph held in `state`.
+    pub fn new(state: State) -> Self {
+        Self { state }
+    }
+
+    fn graph(&self, parameters: &HashMap<String, String>) -> Result<proto::Graph, GraphError> {
+        let query = grpc::parameters_query(parameters);
+        commons::ensure_query_params(self.state.mandatory_params(), &query)?;
+        self.state.param_constraints().ensure_query_values(&query)?;
+
+        let graph_json = self.state.graph_json();
+        if graph_json.is_empty() {
+            return Err(GraphError::DoesNotExist(
+                "graph has not been built yet".to_string(),
+            ));
+        }
+        grpc::graph_from_json(&graph_json)
+    }
+}
+
+#[tonic::async_trait]
+impl proto::graph_service_server::GraphService for GraphService {
+    async fn get_graph(
+        &self,
+        request: Request<proto::GetGraphRequest>,
+    ) -> Result<Response<proto::Graph>, Status> {
+        let graph = self.graph(&request.get_ref().parameters)?;
+        Ok(Response::new(graph))
+    }
+
+    type StreamGraphStream = grpc::GraphElementStream;
+
+    async fn stream_graph(
+        &self,
+        request: Request<proto::GetGraphRequest>,
+    ) -> Result<Response<Self::StreamGraphStream>, Status> {
+        let graph = self.graph(&request.get_ref().parameters)?;
+        Ok(Response::new(grpc::stream_graph_elements(graph)))
+    }
+
+    async fn get_next_updates(
+        &self,
+        request: Request<proto::GetNextUpdatesRequest>,
+    ) -> Result<Response<proto::NextUpdates>, Status> {
+        let request = request.get_ref();
+        let graph = self.graph(&request.parameters)?;
+        let updates = grpc::next_updates(graph, &request.version)?;
+        Ok(Response::new(updates))
+    }
+}
@@ -13,9 +13,25 @@ extern crate structopt;
 #[macro_use]
 extern crate cincinnati;
 
+pub mod attestation;
+pub mod changelog;
 pub mod config;
+pub mod dry_run;
+pub mod events;
+pub mod freshness;
 pub mod graph;
+pub mod grpc;
+pub mod oneshot;
+pub mod openapi;
+pub mod publish;
+pub mod self_check;
+pub mod server;
+pub mod simulate;
+pub mod snapshots;
+pub mod staging;
+pub mod state_store;
 pub mod status;
+pub mod webhooks;
 
 #[allow(dead_code)]
 /// Build info
@@ -0,0 +1,112 @@
+//! One-shot mode: build the graph once, write it and exit.
+
+use crate::config;
+use crate::graph::check_graph_size;
+use crate::state_store::write_atomically;
+use cincinnati::plugins::prelude::*;
+use commons::prelude_errors::*;
+use std::io::Write;
+
+/// Outcome of a one-shot run, reported as the process exit status.
+///
+/// Configuration errors are reported with the usual status 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OneshotStatus {
+    /// The graph was built and written.
+    Success = 0,
+    /// The plugin chain failed.
+    ScrapeFailed = 2,
+    /// The built graph exceeds the configured size limits.
+    SizeLimitExceeded = 3,
+    /// The graph could not be serialized or written.
+    OutputFailed = 4,
+}
+
+/// Run the plugin chain once, and write the resulting graph JSON to the
+/// configured output, or to stdout.
+pub fn run(settings: &config::AppSettings, plugins: &'static [BoxedPlugin]) -> OneshotStatus {
+    let chain = cincinnati::plugins::process_traced_budgeted_blocking(
+        plugins.iter(),
+        cincinnati::plugins::PluginIO::InternalIO(cincinnati::plugins::InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+        }),
+        settings.scrape_timeout_secs,
+        Some(commons::budget::RequestBudget::new(
+            settings.request_budgets(),
+        )),
+    );
+    for run in &chain.plugins {
+        info!("plugin {} ran in {} ms", run.name, run.duration.as_millis());
+    }
+
+    let internal_io = match chain.io {
+        Ok(internal_io) => internal_io,
+        Err(err) => {
+            err.chain().for_each(|cause| error!("{}", cause));
+            return OneshotStatus::ScrapeFailed;
+        }
+    };
+
+    if let Err(reason) = check_graph_size(
+        &internal_io.graph,
+        settings.max_graph_releases,
+        settings.max_graph_edges,
+    ) {
+        error!("discarding built graph: {}", reason);
+        return OneshotStatus::SizeLimitExceeded;
+    }
+
+    let written = serde_json::to_string(&internal_io.graph)
+        .context("Failed to serialize graph")
+        .and_then(|json| match &settings.oneshot_output {
+            Some(path) => write_atomically(path, json.as_bytes()),
+            None => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(json.as_bytes())?;
+                stdout.flush()?;
+                Ok(())
+            }
+        });
+    if let Err(err) = written {
+        err.chain().for_each(|cause| error!("{}", cause));
+        return OneshotStatus::OutputFailed;
+    }
+
+    info!(
+        "graph built with {} releases and {} edges",
+        internal_io.graph.releases_count(),
+        internal_io.graph.edges_count()
+    );
+    OneshotStatus::Success
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oneshot_writes_graph() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let output = dir.path().join("graph.json");
+        let settings = config::AppSettings {
+            oneshot_output: Some(output.clone()),
+            ..Default::default()
+        };
+        let plugins: &'static [BoxedPlugin] = Box::leak(Box::new([
+            EdgeAddRemovePlugin::default().build_plugin(None)?
+        ]));
+
+        assert_eq!(run(&settings, plugins), OneshotStatus::Success);
+        let graph: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&output)?)?;
+        assert_eq!(graph["nodes"], serde_json::json!([]));
+
+        let settings = config::AppSettings {
+            oneshot_output: Some(dir.path().join("missing").join("graph.json")),
+            ..Default::default()
+        };
+        assert_eq!(run(&settings, plugins), OneshotStatus::OutputFailed);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+//! OpenAPI document of the graph and status endpoints.
+
+use crate::graph::State;
+use actix_web::{HttpRequest, HttpResponse};
+use commons::params::ParamConstraints;
+use commons::prelude_errors::*;
+use commons::StatusListener;
+use openapiv3::{OpenAPI, ReferenceOr};
+use std::collections::HashSet;
+
+/// Template for graph-builder OpenAPIv3 document.
+const SPEC: &str = include_str!("openapiv3.json");
+
+/// Template for the OpenAPIv3 document of the status service endpoints.
+const STATUS_SPEC: &str = include_str!("openapiv3-status.json");
+
+/// Serve the OpenAPI document of the graph held in `app_data`, and of the status service.
+pub async fn index(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<State>,
+    status: actix_web::web::Data<StatusListener>,
+) -> HttpResponse {
+    match render(
+        app_data.path_prefix(),
+        app_data.mandatory_params(),
+        app_data.param_constraints(),
+        &status.base_url(&req),
+    ) {
+        Ok(spec) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(spec),
+        Err(e) => {
+            error!("{:?}", e);
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
+
+/// Serve the JSON Schema of the graph document.
+pub async fn graph_schema() -> HttpResponse {
+    HttpResponse::Ok().json(cincinnati::schema::graph_json_schema())
+}
+
+/// Render the document for endpoints under `path_prefix`, and for the
+/// status service at `status_url`.
+fn render(
+    path_prefix: &str,
+    mandatory_params: &HashSet<String>,
+    param_constraints: &ParamConstraints,
+    status_url: &str,
+) -> Fallible<String> {
+    let mut spec_object: OpenAPI =
+        serde_json::from_str(SPEC).context("Could not deserialize to OpenAPI object")?;
+
+    for path in ["/graph", "/v1/graph"].iter() {
+        if let Some(ReferenceOr::Item(item)) = spec_object.paths.paths.get_mut(*path) {
+            add_mandatory_params(item, mandatory_params, param_constraints)?;
+        }
+    }
+    add_response_schemas(&mut spec_object)?;
+
+    // Prefix all paths with `path_prefix`
+    spec_object.paths.paths = std::mem::take(&mut spec_object.paths.paths)
+        .into_iter()
+        .map(|(path, path_item)| (format!("{}{}", path_prefix, path), path_item))
+        .collect();
+    add_status_paths(&mut spec_object, status_url)?;
+
+    serde_json::to_string(&spec_object).context("Could not serialize OpenAPI object")
+}
+
+/// Add the response schemas, generated from their Rust types, to the components.
+fn add_response_schemas(spec_object: &mut OpenAPI) -> Fallible<()> {
+    let components = spec_object.components.get_or_insert_with(Default::default);
+    for (name, schema) in cincinnati::schema::openapi_schemas()? {
+        let schema = serde_json::from_value(schema)
+            .with_context(|| format!("Could not convert schema {}", name))?;
+        components.schemas.insert(name, schema);
+    }
+    Ok(())
+}
+
+/// Add the endpoints of the status service, served at `status_url`, and their schemas.
+fn add_status_paths(spec_object: &mut OpenAPI, status_url: &str) -> Fallible<()> {
+    let status: OpenAPI = serde_json::from_str(STATUS_SPEC)
+        .context("Could not deserialize status endpoints to OpenAPI object")?;
+    let server: openapiv3::Server = serde_json::from_value(serde_json::json!({
+        "url": status_url,
+        "description": "Status service"
+    }))?;
+
+    for (path, mut path_item) in status.paths.paths {
+        if let ReferenceOr::Item(item) = &mut path_item {
+            item.servers = vec![server.clone()];
+        }
+        spec_object.paths.paths.insert(path, path_item);
+    }
+    if let Some(components) = status.components {
+        spec_object
+            .components
+            .get_or_insert_with(Default::default)
+            .schemas
+            .extend(components.schemas);
+    }
+    Ok(())
+}
+
+/// Add mandatory parameters, with the values they accept, to a graph endpoint.
+fn add_mandatory_params(
+    item: &mut openapiv3::PathItem,
+    params: &HashSet<String>,
+    constraints: &ParamConstraints,
+) -> Fallible<()> {
+    let mut params: Vec<&String> = params.iter().collect();
+    params.sort();
+
+    for name in params {
+        // `openapiv3::Parameter` has private fields, so build it from JSON.
+        let param: openapiv3::Parameter = serde_json::from_value(serde_json::json!({
+            "in": "query",
+            "name": name,
+            "required": true,
+            "schema": constraints.schema(name)
+        }))?;
+        item.parameters.push(ReferenceOr::Item(param));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_tenant_document() -> Fallible<()> {
+        let params: HashSet<String> = vec!["channel".to_string()].into_iter().collect();
+        let constraints = ParamConstraints::try_new(
+            &vec![(
+                "channel".to_string(),
+                commons::params::ParamConstraint {
+                    values: Some(vec!["stable-4.14".to_string()]),
+                    ..Default::default()
+                },
+            )]
+            .into_iter()
+            .collect(),
+        )?;
+        let rendered = render(
+            "/okd",
+            &params,
+            &constraints,
+            "http://cincinnati.example.com:9080",
+        )?;
+        let spec: OpenAPI = serde_json::from_str(&rendered)?;
+
+        let mut paths: Vec<&String> = spec.paths.paths.keys().collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                "/liveness",
+                "/metrics",
+                "/okd/graph",
+                "/okd/v1/changelog",
+                "/okd/v1/graph",
+                "/okd/v1/graph/schema",
+                "/readiness",
+                "/status",
+                "/version",
+            ]
+        );
+
+        // Status endpoints are not prefixed, and served by the status service.
+        match &spec.paths.paths["/status"] {
+            ReferenceOr::Item(item) => {
+                assert_eq!(item.servers.len(), 1);
+                assert_eq!(item.servers[0].url, "http://cincinnati.example.com:9080");
+            }
+            _ => bail!("unexpected reference"),
+        };
+        match &spec.paths.paths["/okd/graph"] {
+            ReferenceOr::Item(item) => assert!(item.servers.is_empty()),
+            _ => bail!("unexpected reference"),
+        };
+
+        let schemas = &spec.components.as_ref().unwrap().schemas;
+        for name in &[
+            "Graph",
+            "Release",
+            "GraphError",
+            "GraphStatus",
+            "VersionInfo",
+        ] {
+            assert!(schemas.contains_key(*name), "missing schema {}", name);
+        }
+
+        match &spec.paths.paths["/okd/graph"] {
+            ReferenceOr::Item(item) => match &item.parameters[..] {
+                [ReferenceOr::Item(openapiv3::Parameter::Query { parameter_data, .. })] => {
+                    assert_eq!(parameter_data.name, "channel");
+                    assert!(parameter_data.required);
+                }
+                other => bail!("unexpected parameters {:?}", other),
+            },
+            _ => bail!("unexpected reference"),
+        };
+        let spec: serde_json::Value = serde_json::from_str(&rendered)?;
+        assert_eq!(
+            spec["paths"]["/okd/graph"]["parameters"][0]["schema"],
+            serde_json::json!({ "type": "string", "enum": ["stable-4.14"] })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_schema_references() -> Fallible<()> {
+        fn collect_refs<'a>(value: &'a serde_json::Value, refs: &mut Vec<&'a str>) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    if let Some(serde_json::Value::String(r)) = map.get("$ref") {
+                        refs.push(r);
+                    }
+                    map.values().for_each(|v| collect_refs(v, refs));
+                }
+                serde_json::Value::Array(values) => {
+                    values.iter().for_each(|v| collect_refs(v, refs))
+                }
+                _ => {}
+            }
+        }
+
+        let spec: serde_json::Value = serde_json::from_str(&render(
+            "",
+            &HashSet::new(),
+            &Default::default(),
+            "http://localhost:9080",
+        )?)?;
+        let mut refs = vec![];
+        collect_refs(&spec, &mut refs);
+        assert!(refs.contains(&"#/components/schemas/GraphError"));
+
+        for r in refs {
+            let name = r.trim_start_matches("#/components/schemas/");
+            assert!(
+                spec["components"]["schemas"].get(name).is_some(),
+                "dangling reference {}",
+                r
+            );
+        }
+
+        Ok(())
+    }
+}
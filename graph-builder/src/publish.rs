@@ -0,0 +1,493 @@
+//! Publishing of graph updates to message brokers.
+//!
+//! After each successful scrape which changed the graph, a summary of the
+//! changes (and optionally a full snapshot of the graph) is published to a
+//! Kafka topic or NATS subject. Kafka is reached through a Kafka REST proxy
+//! only, as native Kafka clients need librdkafka; NATS through the
+//! `async-nats` client. Publishing to NATS does not use JetStream, so updates
+//! published while no subscriber is connected are not kept.
+//!
+//! Updates are published, and retried, by a thread of their own once the new
+//! graph is served, so that an unavailable broker never delays scrapes.
+
+use cincinnati::GraphDiff;
+use commons::http::RetryPolicy;
+use commons::prelude_errors::*;
+use prometheus::{IntCounterVec, Opts};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Timeout for a single delivery attempt.
+static DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Upper bound for the pause between delivery attempts.
+static MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Number of graph updates which may wait to be published.
+static PUBLISH_QUEUE_CAPACITY: usize = 16;
+
+/// Content type for Kafka REST proxy JSON records.
+static KAFKA_REST_CONTENT_TYPE: &str = "application/vnd.kafka.json.v2+json";
+
+lazy_static! {
+    static ref PUBLISH_MESSAGES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "publish_messages_total",
+            "Total number of graph update messages delivered to the broker"
+        ),
+        &["kind"]
+    )
+    .unwrap();
+    static ref PUBLISH_ERRORS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "publish_errors_total",
+            "Total number of graph update messages which failed to be delivered"
+        ),
+        &["kind"]
+    )
+    .unwrap();
+    static ref PUBLISH_RETRIES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "publish_retries_total",
+            "Total number of retried graph update deliveries"
+        ),
+        &["kind"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(PUBLISH_MESSAGES.clone()))?;
+    registry.register(Box::new(PUBLISH_ERRORS.clone()))?;
+    registry.register(Box::new(PUBLISH_RETRIES.clone()))?;
+    Ok(())
+}
+
+/// Supported message brokers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublisherBackend {
+    /// Kafka, through a REST proxy.
+    Kafka,
+    /// NATS core publishing.
+    Nats,
+}
+
+impl std::str::FromStr for PublisherBackend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "kafka" => Ok(PublisherBackend::Kafka),
+            "nats" => Ok(PublisherBackend::Nats),
+            _ => bail!("unknown publisher backend '{}'", s),
+        }
+    }
+}
+
+/// A destination for serialized messages.
+pub trait Publisher: std::fmt::Debug + Send {
+    /// Deliver a single message.
+    fn publish(&mut self, key: &str, payload: &[u8]) -> Fallible<()>;
+}
+
+/// Publisher to a Kafka topic through a Kafka REST proxy.
+#[derive(Debug)]
+pub struct KafkaRestPublisher {
+    client: reqwest::Client,
+    runtime: tokio::runtime::Runtime,
+    topic_url: reqwest::Url,
+}
+
+impl KafkaRestPublisher {
+    /// Create a publisher for `topic` on the REST proxy at `url`.
+    pub fn try_new(url: &str, topic: &str) -> Fallible<Self> {
+        let base = reqwest::Url::parse(url).context(format!("invalid Kafka URL '{}'", url))?;
+        let topic_url = base
+            .join(&format!("topics/{}", topic))
+            .context(format!("invalid Kafka topic '{}'", topic))?;
+        let client = commons::http::HttpClientBuilder::new()
+            .timeout(DELIVERY_TIMEOUT)
+            .build()
+            .context("building Kafka HTTP client")?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("building Kafka publisher runtime")?;
+
+        Ok(Self {
+            client,
+            runtime,
+            topic_url,
+        })
+    }
+}
+
+impl Publisher for KafkaRestPublisher {
+    fn publish(&mut self, key: &str, payload: &[u8]) -> Fallible<()> {
+        let value: serde_json::Value = serde_json::from_slice(payload)?;
+        let body = serde_json::to_vec(&serde_json::json!({
+            "records": [{ "key": key, "value": value }]
+        }))?;
+
+        let request = self
+            .client
+            .post(self.topic_url.clone())
+            .header(reqwest::header::CONTENT_TYPE, KAFKA_REST_CONTENT_TYPE)
+            .body(body)
+            .send();
+        let response = self
+            .runtime
+            .block_on(request)
+            .context(format!("publishing to {}", self.topic_url))?;
+
+        ensure!(
+            response.status().is_success(),
+            "Kafka REST proxy {} responded with status {}",
+            self.topic_url,
+            response.status()
+        );
+
+        Ok(())
+    }
+}
+
+/// Publisher to a NATS subject.
+#[derive(Debug)]
+pub struct NatsPublisher {
+    runtime: tokio::runtime::Runtime,
+    url: String,
+    subject: String,
+    /// Connection to the server, established on the first delivery.
+    client: Option<async_nats::Client>,
+}
+
+impl NatsPublisher {
+    /// Create a publisher for `subject` on the NATS server at `url`.
+    pub fn try_new(url: &str, subject: &str) -> Fallible<Self> {
+        let url = reqwest::Url::parse(url).context(format!("invalid NATS URL '{}'", url))?;
+        ensure!(
+            url.scheme() == "nats",
+            "unsupported NATS URL scheme '{}'",
+            url.scheme()
+        );
+        ensure!(
+            url.host_str().is_some(),
+            "missing host in NATS URL '{}'",
+            url
+        );
+        ensure!(
+            !subject.is_empty() && !subject.contains(char::is_whitespace),
+            "invalid NATS subject '{}'",
+            subject
+        );
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("building NATS publisher runtime")?;
+
+        Ok(Self {
+            runtime,
+            url: url.to_string(),
+            subject: subject.to_string(),
+            client: None,
+        })
+    }
+
+    async fn deliver(
+        client: &mut Option<async_nats::Client>,
+        url: &str,
+        subject: &str,
+        payload: &[u8],
+    ) -> Fallible<()> {
+        let client = match client {
+            Some(client) => client,
+            None => client.insert(
+                async_nats::ConnectOptions::new()
+                    .connection_timeout(DELIVERY_TIMEOUT)
+                    .connect(url)
+                    .await
+                    .context(format!("connecting to NATS server {}", url))?,
+            ),
+        };
+
+        client
+            .publish(subject.to_string(), payload.to_vec().into())
+            .await
+            .context(format!("publishing to NATS subject {}", subject))?;
+        // A flush confirms that the publication was written to the server.
+        client
+            .flush()
+            .await
+            .context(format!("flushing NATS connection to {}", url))?;
+        Ok(())
+    }
+}
+
+impl Publisher for NatsPublisher {
+    fn publish(&mut self, _key: &str, payload: &[u8]) -> Fallible<()> {
+        let delivery = tokio::time::timeout(
+            DELIVERY_TIMEOUT,
+            Self::deliver(&mut self.client, &self.url, &self.subject, payload),
+        );
+        let result = match self.runtime.block_on(delivery) {
+            Ok(result) => result,
+            Err(_) => Err(format_err!(
+                "publishing to NATS server {} timed out",
+                self.url
+            )),
+        };
+        if result.is_err() {
+            // Reconnect on the next attempt.
+            self.client = None;
+        }
+        result
+    }
+}
+
+/// Summary of a graph update.
+#[derive(Debug, Serialize)]
+struct UpdateSummary<'a> {
+    timestamp: i64,
+    releases: u64,
+    changes: &'a GraphDiff,
+}
+
+/// Publisher of graph updates, with retry.
+#[derive(Debug)]
+pub struct GraphUpdatePublisher {
+    publisher: Box<dyn Publisher>,
+    snapshots: bool,
+    retry_policy: RetryPolicy,
+}
+
+/// Graph update waiting to be published.
+#[derive(Debug)]
+struct GraphUpdate {
+    releases: u64,
+    diff: GraphDiff,
+    snapshot: Option<String>,
+}
+
+/// Queue of graph updates, published by the thread of a `GraphUpdatePublisher`.
+#[derive(Debug)]
+pub struct PublishQueue {
+    queue: mpsc::SyncSender<GraphUpdate>,
+    snapshots: bool,
+}
+
+impl PublishQueue {
+    /// Whether full graph snapshots are published along with summaries.
+    pub fn snapshots(&self) -> bool {
+        self.snapshots
+    }
+
+    /// Queue a graph update for publishing.
+    ///
+    /// Updates are dropped, and counted as errors, if the queue is full.
+    pub fn publish_update(&self, releases: u64, diff: GraphDiff, snapshot: Option<String>) {
+        let update = GraphUpdate {
+            releases,
+            diff,
+            snapshot: snapshot.filter(|_| self.snapshots),
+        };
+        if self.queue.try_send(update).is_err() {
+            PUBLISH_ERRORS.with_label_values(&["summary"]).inc();
+            error!("graph update publishing queue unavailable, dropping update");
+        }
+    }
+}
+
+impl GraphUpdatePublisher {
+    /// Create a new update publisher from the given settings.
+    pub fn try_new(
+        backend: PublisherBackend,
+        url: &str,
+        topic: &str,
+        snapshots: bool,
+        max_retries: u32,
+    ) -> Fallible<Self> {
+        let publisher: Box<dyn Publisher> = match backend {
+            PublisherBackend::Kafka => Box::new(KafkaRestPublisher::try_new(url, topic)?),
+            PublisherBackend::Nats => Box::new(NatsPublisher::try_new(url, topic)?),
+        };
+
+        Ok(Self {
+            publisher,
+            snapshots,
+            retry_policy: RetryPolicy {
+                max_retries,
+                initial_backoff: Duration::from_secs(1),
+                max_backoff: MAX_RETRY_BACKOFF,
+            },
+        })
+    }
+
+    /// Start publishing the updates of the returned queue from a thread of their own.
+    pub fn spawn(mut self) -> Fallible<PublishQueue> {
+        let snapshots = self.snapshots;
+        let (queue, updates) = mpsc::sync_channel::<GraphUpdate>(PUBLISH_QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("publisher".to_string())
+            .spawn(move || {
+                for update in updates {
+                    if let Err(err) = self.publish_update(
+                        update.releases,
+                        &update.diff,
+                        update.snapshot.as_deref(),
+                    ) {
+                        err.chain().for_each(|cause| error!("{}", cause));
+                    }
+                }
+            })
+            .context("starting graph update publishing thread")?;
+
+        Ok(PublishQueue { queue, snapshots })
+    }
+
+    /// Whether full graph snapshots are published along with summaries.
+    pub fn snapshots(&self) -> bool {
+        self.snapshots
+    }
+
+    /// Publish the summary of a graph update and, if enabled, the given graph snapshot.
+    pub fn publish_update(
+        &mut self,
+        releases: u64,
+        diff: &GraphDiff,
+        snapshot: Option<&str>,
+    ) -> Fallible<()> {
+        let summary = serde_json::to_vec(&UpdateSummary {
+            timestamp: chrono::Utc::now().timestamp(),
+            releases,
+            changes: diff,
+        })?;
+        self.publish_with_retry("summary", &summary)?;
+
+        if let Some(snapshot) = snapshot.filter(|_| self.snapshots) {
+            self.publish_with_retry("snapshot", snapshot.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    fn publish_with_retry(&mut self, kind: &str, payload: &[u8]) -> Fallible<()> {
+        let mut attempt = 0;
+        loop {
+            match self.publisher.publish(kind, payload) {
+                Ok(()) => {
+                    PUBLISH_MESSAGES.with_label_values(&[kind]).inc();
+                    return Ok(());
+                }
+                Err(err) if attempt < self.retry_policy.max_retries => {
+                    PUBLISH_RETRIES.with_label_values(&[kind]).inc();
+                    warn!("failed to publish graph {}, retrying: {}", kind, err);
+                    // Only the publishing thread waits.
+                    std::thread::sleep(self.retry_policy.backoff(attempt));
+                    attempt += 1;
+                }
+                Err(err) => {
+                    PUBLISH_ERRORS.with_label_values(&[kind]).inc();
+                    return Err(err.context(format!("publishing graph {}", kind)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Default)]
+    struct FlakyPublisher {
+        failures: u32,
+        published: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Publisher for FlakyPublisher {
+        fn publish(&mut self, key: &str, _payload: &[u8]) -> Fallible<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                bail!("transient failure");
+            }
+            self.published.lock().unwrap().push(key.to_string());
+            Ok(())
+        }
+    }
+
+    fn update_publisher(
+        failures: u32,
+        max_retries: u32,
+    ) -> (GraphUpdatePublisher, Arc<Mutex<Vec<String>>>) {
+        let published = Arc::new(Mutex::new(vec![]));
+        let publisher = GraphUpdatePublisher {
+            publisher: Box::new(FlakyPublisher {
+                failures,
+                published: published.clone(),
+            }),
+            snapshots: true,
+            retry_policy: RetryPolicy {
+                max_retries,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+            },
+        };
+        (publisher, published)
+    }
+
+    #[test]
+    fn publish_retries_transient_failures() -> Fallible<()> {
+        let (mut publisher, published) = update_publisher(2, 3);
+
+        publisher.publish_update(1, &GraphDiff::default(), Some("{}"))?;
+        assert_eq!(*published.lock().unwrap(), vec!["summary", "snapshot"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn publish_gives_up_after_max_retries() {
+        let (mut publisher, published) = update_publisher(3, 2);
+
+        assert!(publisher
+            .publish_update(1, &GraphDiff::default(), None)
+            .is_err());
+        assert!(published.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn publish_queued_updates() {
+        let (publisher, published) = update_publisher(1, 1);
+        let queue = publisher.spawn().unwrap();
+
+        queue.publish_update(1, GraphDiff::default(), Some("{}".to_string()));
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while published.lock().unwrap().len() < 2 && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*published.lock().unwrap(), vec!["summary", "snapshot"]);
+    }
+
+    #[test]
+    fn parse_backends() {
+        assert_eq!(
+            "kafka".parse::<PublisherBackend>().unwrap(),
+            PublisherBackend::Kafka
+        );
+        assert_eq!(
+            "nats".parse::<PublisherBackend>().unwrap(),
+            PublisherBackend::Nats
+        );
+        assert!("amqp".parse::<PublisherBackend>().is_err());
+    }
+
+    #[test]
+    fn nats_url_validation() {
+        assert!(NatsPublisher::try_new("nats://localhost:4222", "graph.updates").is_ok());
+        assert!(NatsPublisher::try_new("http://localhost:4222", "graph.updates").is_err());
+        assert!(NatsPublisher::try_new("nats://localhost", "graph updates").is_err());
+    }
+}
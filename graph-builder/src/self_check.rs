@@ -0,0 +1,375 @@
+//! Periodic self-check of the serving path.
+//!
+//! A background task requests the served graphs through the service
+//! endpoint, as a client would, and checks the responses: the status, the
+//! number of releases and edges against configured thresholds, and the
+//! `ETag` header, which must match the served graph and change with it
+//! after scrapes. Failures are reported as a condition at `/status` and
+//! counted in metrics, without affecting the served graphs.
+
+use crate::config::AppSettings;
+use crate::graph::{self, State};
+use cincinnati::CONTENT_TYPE;
+use commons::metrics::HasRegistry;
+use commons::prelude_errors::*;
+use prometheus::{IntCounterVec, IntGauge, Opts};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, SystemTime};
+
+/// Timeout of the self-check graph requests.
+static REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Outcome of a self-check.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SelfCheckReport {
+    /// Time of the self-check, in RFC 3339 format.
+    pub time: String,
+    /// Failed checks, empty if the self-check passed.
+    pub failures: Vec<String>,
+}
+
+/// Self-check metrics of a graph.
+struct SelfCheckMetrics {
+    failed: IntGauge,
+    failures: IntCounterVec,
+}
+
+impl SelfCheckMetrics {
+    fn try_new(registry: &prometheus::Registry) -> Fallible<Self> {
+        let metrics = Self {
+            failed: IntGauge::new(
+                "graph_self_check_failed",
+                "Whether the latest self-check of the serving path failed",
+            )?,
+            failures: IntCounterVec::new(
+                Opts::new(
+                    "graph_self_check_failures_total",
+                    "Total number of failed self-checks, by check",
+                ),
+                &["check"],
+            )?,
+        };
+        registry.register(Box::new(metrics.failed.clone()))?;
+        registry.register(Box::new(metrics.failures.clone()))?;
+        Ok(metrics)
+    }
+}
+
+/// Thresholds of the served graphs.
+#[derive(Clone, Copy, Debug)]
+struct Thresholds {
+    min_releases: u64,
+    min_edges: u64,
+}
+
+/// Response to a self-check graph request.
+#[derive(Debug)]
+struct Observation {
+    status: u16,
+    etag: Option<String>,
+    body: String,
+}
+
+/// Served graph and `ETag` of the previous self-check.
+#[derive(Clone, Debug)]
+struct Previous {
+    graph_etag: String,
+    served_etag: Option<String>,
+}
+
+/// Start self-checking the serving paths of `state` and its tenants.
+///
+/// This must be called from within the runtime of the service.
+pub fn spawn(settings: &'static AppSettings, state: &State) -> Fallible<()> {
+    let interval = match settings.self_check_interval {
+        Some(interval) => interval,
+        None => return Ok(()),
+    };
+
+    let client = commons::http::HttpClientBuilder::new()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .context("building self-check HTTP client")?;
+    let listen_addr = settings.listen_addrs()[0];
+    let addr = service_addr(listen_addr.ip(), listen_addr.port());
+    let thresholds = Thresholds {
+        min_releases: settings.self_check_min_releases,
+        min_edges: settings.self_check_min_edges,
+    };
+
+    for state in std::iter::once(state).chain(state.tenants()).cloned() {
+        let metrics = SelfCheckMetrics::try_new(state.registry())?;
+        let url = reqwest::Url::parse_with_params(
+            &format!("http://{}{}/graph", addr, state.path_prefix()),
+            &settings.self_check_params,
+        )
+        .context("building self-check URL")?;
+        let client = client.clone();
+
+        actix_web::rt::spawn(async move {
+            let mut previous = None;
+            loop {
+                actix_web::rt::time::sleep(interval).await;
+                if state.graph_stats().is_none() {
+                    trace!("skipping self-check of {}, no graph yet", url);
+                    continue;
+                }
+                previous =
+                    Some(run_once(&client, &url, &state, &metrics, thresholds, previous).await);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Address at which the service can be reached locally.
+fn service_addr(address: IpAddr, port: u16) -> SocketAddr {
+    let ip = match address {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    SocketAddr::new(ip, port)
+}
+
+/// Request the graph at `url`, check the response and record the outcome.
+async fn run_once(
+    client: &reqwest::Client,
+    url: &reqwest::Url,
+    state: &State,
+    metrics: &SelfCheckMetrics,
+    thresholds: Thresholds,
+    previous: Option<Previous>,
+) -> Previous {
+    // The graph may be replaced by a scrape during the request.
+    let before = graph::graph_etag(&state.graph_json());
+    let observation = request(client, url).await;
+    let after = graph::graph_etag(&state.graph_json());
+
+    let failures = match &observation {
+        Ok(observation) => evaluate(
+            thresholds,
+            observation,
+            &[&before, &after],
+            previous.as_ref(),
+        ),
+        Err(e) => vec![("request", format!("{:#}", e))],
+    };
+
+    for (check, failure) in &failures {
+        warn!("self-check of {} failed: {}", url, failure);
+        metrics.failures.with_label_values(&[check]).inc();
+    }
+    metrics.failed.set(!failures.is_empty() as i64);
+    state.record_self_check(SelfCheckReport {
+        time: chrono::DateTime::<chrono::Utc>::from(SystemTime::now()).to_rfc3339(),
+        failures: failures.into_iter().map(|(_, failure)| failure).collect(),
+    });
+
+    Previous {
+        graph_etag: after,
+        served_etag: observation.ok().and_then(|observation| observation.etag),
+    }
+}
+
+/// Request the graph at `url`.
+async fn request(client: &reqwest::Client, url: &reqwest::Url) -> Fallible<Observation> {
+    let response = client
+        .get(url.clone())
+        .header(reqwest::header::ACCEPT, CONTENT_TYPE)
+        .send()
+        .await
+        .context(format!("requesting {}", url))?;
+    let status = response.status().as_u16();
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+    let body = response
+        .text()
+        .await
+        .context(format!("reading response of {}", url))?;
+
+    Ok(Observation { status, etag, body })
+}
+
+/// Check a graph response, given the `ETag`s of the graphs served meanwhile.
+///
+/// Returns the failed checks, by name.
+fn evaluate(
+    thresholds: Thresholds,
+    observation: &Observation,
+    served: &[&str],
+    previous: Option<&Previous>,
+) -> Vec<(&'static str, String)> {
+    let mut failures = Vec::new();
+
+    if observation.status != 200 {
+        failures.push((
+            "status",
+            format!("unexpected response status {}", observation.status),
+        ));
+        return failures;
+    }
+
+    match serde_json::from_str::<cincinnati::Graph>(&observation.body) {
+        Ok(graph) => {
+            if graph.releases_count() < thresholds.min_releases {
+                failures.push((
+                    "releases",
+                    format!(
+                        "{} releases, expected at least {}",
+                        graph.releases_count(),
+                        thresholds.min_releases
+                    ),
+                ));
+            }
+            if graph.edges_count() < thresholds.min_edges {
+                failures.push((
+                    "edges",
+                    format!(
+                        "{} edges, expected at least {}",
+                        graph.edges_count(),
+                        thresholds.min_edges
+                    ),
+                ));
+            }
+        }
+        Err(e) => failures.push(("graph", format!("invalid graph: {}", e))),
+    }
+
+    let etag = match &observation.etag {
+        Some(etag) => etag,
+        None => {
+            failures.push(("etag", "missing ETag header".to_string()));
+            return failures;
+        }
+    };
+    if *etag != graph::graph_etag(&observation.body) {
+        failures.push(("etag", format!("ETag {} does not match the body", etag)));
+    } else if !served.contains(&etag.as_str()) {
+        failures.push(("etag", format!("ETag {} is not the served graph", etag)));
+    }
+    if let Some(previous) = previous {
+        let scraped = !served.contains(&previous.graph_etag.as_str());
+        if scraped && previous.served_etag.as_ref() == Some(etag) {
+            failures.push((
+                "etag",
+                format!("ETag {} did not change after a scrape", etag),
+            ));
+        }
+    }
+
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+
+    static THRESHOLDS: Thresholds = Thresholds {
+        min_releases: 2,
+        min_edges: 1,
+    };
+
+    fn observe(releases: usize, edges: Vec<(usize, usize)>) -> Fallible<Observation> {
+        let graph = generate_custom_graph(
+            "image",
+            (0..releases).map(|i| (i, Default::default())).collect(),
+            Some(edges),
+        );
+        let body = serde_json::to_string(&graph)?;
+        Ok(Observation {
+            status: 200,
+            etag: Some(graph::graph_etag(&body)),
+            body,
+        })
+    }
+
+    fn checks(failures: Vec<(&'static str, String)>) -> Vec<&'static str> {
+        failures.into_iter().map(|(check, _)| check).collect()
+    }
+
+    #[test]
+    fn evaluate_responses() -> Fallible<()> {
+        let observation = observe(2, vec![(0, 1)])?;
+        let etag = observation.etag.clone().unwrap();
+        assert!(evaluate(THRESHOLDS, &observation, &[&etag], None).is_empty());
+
+        // The graph was replaced by a scrape during the request.
+        assert!(evaluate(THRESHOLDS, &observation, &["\"0\"", &etag], None).is_empty());
+
+        let small = observe(1, vec![])?;
+        let small_etag = small.etag.clone().unwrap();
+        assert_eq!(
+            checks(evaluate(THRESHOLDS, &small, &[&small_etag], None)),
+            vec!["releases", "edges"]
+        );
+
+        assert_eq!(
+            checks(evaluate(THRESHOLDS, &observation, &[&small_etag], None)),
+            vec!["etag"]
+        );
+
+        let mut missing = observe(2, vec![(0, 1)])?;
+        missing.etag = None;
+        assert_eq!(
+            checks(evaluate(THRESHOLDS, &missing, &[&etag], None)),
+            vec!["etag"]
+        );
+
+        let failed = Observation {
+            status: 503,
+            etag: None,
+            body: String::new(),
+        };
+        assert_eq!(
+            checks(evaluate(THRESHOLDS, &failed, &[&etag], None)),
+            vec!["status"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn evaluate_etag_changes() -> Fallible<()> {
+        let observation = observe(2, vec![(0, 1)])?;
+        let etag = observation.etag.clone().unwrap();
+
+        let unchanged = Previous {
+            graph_etag: etag.clone(),
+            served_etag: Some(etag.clone()),
+        };
+        assert!(evaluate(THRESHOLDS, &observation, &[&etag], Some(&unchanged)).is_empty());
+
+        let stale = Previous {
+            graph_etag: "\"0\"".to_string(),
+            served_etag: Some(etag.clone()),
+        };
+        assert_eq!(
+            checks(evaluate(THRESHOLDS, &observation, &[&etag], Some(&stale))),
+            vec!["etag"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn local_service_addr() {
+        assert_eq!(
+            service_addr("0.0.0.0".parse().unwrap(), 8080),
+            "127.0.0.1:8080".parse().unwrap()
+        );
+        assert_eq!(
+            service_addr("::".parse().unwrap(), 8080),
+            "[::1]:8080".parse().unwrap()
+        );
+        assert_eq!(
+            service_addr("10.0.0.1".parse().unwrap(), 8080),
+            "10.0.0.1:8080".parse().unwrap()
+        );
+    }
+}
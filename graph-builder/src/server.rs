@@ -0,0 +1,796 @@
+// Copyright 2018 Alex Crawford
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Graph-builder servers.
+//!
+//! The `graph-builder` binary assembles its settings and calls `run`, which
+//! can also be called from tests to serve a graph-builder in-process.
+//! Processes serving the built graph themselves, e.g. a standalone
+//! policy-engine, call `spawn_pipeline` instead.
+
+use crate::{
+    attestation, changelog, config, dry_run, events, graph, grpc, oneshot, openapi, publish,
+    self_check, simulate, snapshots, staging, state_store, status, webhooks,
+};
+use actix_service::Service;
+use actix_web::{middleware, App, HttpServer};
+use commons::metrics::{self, HasRegistry};
+use commons::prelude_errors::*;
+use commons::tracing::{get_context, get_tracer, init_tracer, set_span_tags};
+use futures::future;
+use opentelemetry::{
+    trace::{mark_span_as_active, FutureExt, Tracer},
+    Context as ot_context,
+};
+use parking_lot::RwLock;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::thread;
+
+/// Serve the graph-builder with `settings`, until its servers stop.
+///
+/// In one-shot mode, the graph is built once and the process exits instead.
+pub async fn run(settings: config::AppSettings) -> Result<(), Error> {
+    let registry: prometheus::Registry = metrics::new_labeled_registry(
+        Some(config::METRICS_PREFIX.to_string()),
+        &settings.metrics_labels,
+    )?;
+
+    // Enable tracing
+    init_tracer("graph-builder", settings.tracing_endpoint.clone())?;
+
+    let plugins = settings.validate_and_build_plugins(Some(&registry))?;
+
+    ensure_registered_metrics(
+        &registry,
+        config::METRICS_PREFIX,
+        &settings.metrics_required,
+    )?;
+
+    if settings.oneshot {
+        if !settings.tenants.is_empty() {
+            log::warn!("tenants are not built in one-shot mode");
+        }
+        let settings: &'static config::AppSettings = Box::leak(Box::new(settings));
+        let plugins: &'static [cincinnati::plugins::BoxedPlugin] =
+            Box::leak(plugins.into_boxed_slice());
+        // Plugins run on a runtime of their own, outside of the actix one.
+        let outcome = thread::spawn(move || oneshot::run(settings, plugins))
+            .join()
+            .map_err(|_| format_err!("one-shot run panicked"))?;
+        std::process::exit(outcome as i32);
+    }
+
+    let param_constraints =
+        commons::params::ParamConstraints::try_new(&settings.client_parameters)?;
+    let new_changelog = || {
+        Some(settings.changelog_size)
+            .filter(|size| *size > 0)
+            .map(|size| Arc::new(changelog::Changelog::new(size)))
+    };
+    let new_staging = || staging::Staging::from_settings(&settings).map(Arc::new);
+    let new_dry_run = || dry_run::DryRun::from_settings(&settings).map(Arc::new);
+    let attestation_signer = settings
+        .attestation_key_path
+        .as_ref()
+        .map(|path| {
+            attestation::AttestationSigner::from_file(path, settings.attestation_key_id.clone())
+        })
+        .transpose()?
+        .map(Arc::new);
+    let new_attestation = || {
+        attestation_signer
+            .clone()
+            .map(|signer| Arc::new(attestation::Attestation::new(signer)))
+    };
+    // Outbound requests are budgeted per graph, each tenant having a budget of its own.
+    let new_request_budget = || commons::budget::RequestBudget::new(settings.request_budgets());
+    let new_simulation = || {
+        settings.simulate_token_path.as_ref().map(|_| {
+            Arc::new(simulate::Simulation::new(
+                settings.scrape_timeout_secs,
+                settings.profile.channels_key(),
+            ))
+        })
+    };
+
+    // Tenants, each with a registry of its own so that their metrics are labelled.
+    let tenants = settings
+        .tenants
+        .iter()
+        .map(|tenant| {
+            let registry: &'static prometheus::Registry =
+                Box::leak(Box::new(new_tenant_registry(&tenant.name)?));
+            let plugins = tenant
+                .build_plugins(Some(registry))
+                .context(format!("building plugins of tenant '{}'", tenant.name))?;
+            ensure_registered_metrics(
+                registry,
+                config::METRICS_PREFIX,
+                &settings.metrics_required,
+            )?;
+
+            graph::State::try_new_tenant(
+                tenant.path_prefix.clone(),
+                settings.mandatory_client_parameters.clone(),
+                Box::leak(Box::new(plugins)),
+                registry,
+            )
+            .map(|state| {
+                state
+                    .with_param_constraints(param_constraints.clone())
+                    .with_changelog(new_changelog())
+                    .with_staging(new_staging())
+                    .with_simulation(new_simulation())
+                    .with_dry_run(new_dry_run())
+                    .with_attestation(new_attestation())
+                    .with_request_budget(new_request_budget())
+            })
+        })
+        .collect::<Fallible<Vec<_>>>()?;
+
+    let service_addrs = settings.listen_addrs();
+    let public_addrs = settings.public_listen_addrs();
+    // The gRPC service listens on the first address of the main service.
+    let grpc_addr = settings
+        .grpc_port
+        .map(|port| std::net::SocketAddr::new(service_addrs[0].ip(), port));
+    let status_addrs = settings.status_listen_addrs();
+    let app_prefix = settings.path_prefix.clone();
+    let public_app_prefix = app_prefix.clone();
+
+    let state_store = state_store::from_settings(&settings)?;
+    let snapshot_store = open_snapshots(&settings, state_store.as_ref())?;
+
+    // Shared state.
+    let state = {
+        let json_graph = Arc::new(RwLock::new(String::new()));
+        let live = Arc::new(RwLock::new(false));
+        let ready = Arc::new(RwLock::new(false));
+        let secondary_metadata = Arc::new(RwLock::new(String::new()));
+        graph::State::new(
+            json_graph,
+            settings.mandatory_client_parameters.clone(),
+            live,
+            ready,
+            Box::leak(Box::new(plugins)),
+            Box::leak(Box::new(registry)),
+            secondary_metadata,
+        )
+        .with_path_prefix(settings.path_prefix.clone())
+        .with_param_constraints(param_constraints)
+        .with_snapshots(snapshot_store.clone())
+        .with_changelog(open_changelog(&settings, state_store.as_ref())?)
+        .with_staging(new_staging())
+        .with_simulation(new_simulation())
+        .with_dry_run(new_dry_run())
+        .with_attestation(new_attestation())
+        .with_request_budget(new_request_budget())
+        .with_tenants(tenants.clone())
+    };
+
+    // Serve the latest snapshot until the first scrape succeeds.
+    if let Some(store) = snapshot_store
+        .as_ref()
+        .filter(|_| settings.snapshots_warm_start)
+    {
+        match snapshots::warm_start(store, &state) {
+            Ok(Some(entry)) => info!("serving graph snapshot {} until the first scrape", entry.id),
+            Ok(None) => info!("no graph snapshot to warm start from"),
+            Err(err) => error!("failed to load the latest graph snapshot: {:#}", err),
+        }
+    }
+    if let Some(store) = &snapshot_store {
+        snapshots::spawn_gc(store.clone(), settings.snapshots_gc_interval);
+    }
+
+    // Graph scrapers
+    let settings: &'static config::AppSettings = Box::leak(Box::new(settings));
+    for tenant_state in tenants.iter().cloned() {
+        thread::spawn(move || {
+            graph::run_tenant(settings, &tenant_state);
+        });
+    }
+    {
+        let graph_state = state.clone();
+        thread::spawn(move || {
+            graph::run(settings, &graph_state);
+        });
+    }
+
+    // Status service.
+    graph::register_metrics(state.registry())?;
+    events::register_metrics(state.registry())?;
+    publish::register_metrics(state.registry())?;
+    webhooks::register_metrics(state.registry())?;
+    commons::limits::register_metrics(state.registry())?;
+    commons::aliases::register_metrics(state.registry())?;
+    commons::latency::register_metrics(state.registry())?;
+    commons::budget::register_metrics(state.registry())?;
+    commons::metrics::register_metrics(state.registry())?;
+    if snapshot_store.is_some() {
+        snapshots::register_metrics(state.registry())?;
+    }
+
+    let debug_token = settings
+        .status_debug_token_path
+        .as_deref()
+        .map(status::DebugToken::read)
+        .transpose()?;
+    let admin_token = settings
+        .staging_admin_token_path
+        .as_deref()
+        .filter(|_| settings.staging_enabled)
+        .map(staging::AdminToken::read)
+        .transpose()?;
+    let simulate_token = settings
+        .simulate_token_path
+        .as_deref()
+        .map(simulate::SimulateToken::read)
+        .transpose()?;
+    let dry_run_token = settings
+        .dry_run_token_path
+        .as_deref()
+        .map(dry_run::DryRunToken::read)
+        .transpose()?;
+    let snapshots_token = settings
+        .snapshots_admin_token_path
+        .as_deref()
+        .filter(|_| snapshot_store.is_some())
+        .map(commons::auth::BearerToken::read)
+        .transpose()?;
+    let status_state = state.clone();
+    let mut metrics_server = HttpServer::new(move || {
+        let app = App::new()
+            .app_data(actix_web::web::Data::new(status_state.clone()))
+            .service(
+                actix_web::web::resource("/liveness")
+                    .route(actix_web::web::get().to(status::serve_liveness)),
+            )
+            .service(
+                actix_web::web::resource("/metrics")
+                    .route(actix_web::web::get().to(status::serve_metrics)),
+            )
+            .service(
+                actix_web::web::resource("/readiness")
+                    .route(actix_web::web::get().to(status::serve_readiness)),
+            )
+            .service(
+                actix_web::web::resource("/")
+                    .route(actix_web::web::get().to(status::serve_status_page)),
+            )
+            .service(
+                actix_web::web::resource("/status")
+                    .route(actix_web::web::get().to(status::serve_status)),
+            )
+            .service(
+                actix_web::web::resource("/version")
+                    .route(actix_web::web::get().to(status::serve_version)),
+            );
+        let app = match &debug_token {
+            Some(token) => app.service(
+                actix_web::web::resource("/debug/tags")
+                    .app_data(actix_web::web::Data::new(token.clone()))
+                    .route(actix_web::web::get().to(status::serve_debug_tags)),
+            ),
+            None => app,
+        };
+        let app = match &admin_token {
+            Some(token) => app.service(
+                actix_web::web::scope("/staging")
+                    .app_data(actix_web::web::Data::new(token.clone()))
+                    .service(
+                        actix_web::web::resource("")
+                            .route(actix_web::web::get().to(staging::serve_index)),
+                    )
+                    .service(
+                        actix_web::web::resource("/promote")
+                            .route(actix_web::web::post().to(staging::serve_promote)),
+                    )
+                    .service(
+                        actix_web::web::resource("/discard")
+                            .route(actix_web::web::post().to(staging::serve_discard)),
+                    ),
+            ),
+            None => app,
+        };
+        let app = match &simulate_token {
+            Some(token) => app.service(
+                actix_web::web::resource("/v1/simulate")
+                    .app_data(actix_web::web::Data::new(token.clone()))
+                    .route(actix_web::web::post().to(simulate::serve_simulate)),
+            ),
+            None => app,
+        };
+        let app = match &dry_run_token {
+            Some(token) => app.service(
+                actix_web::web::resource("/v1/dry-run")
+                    .app_data(actix_web::web::Data::new(token.clone()))
+                    .route(actix_web::web::post().to(dry_run::serve_dry_run)),
+            ),
+            None => app,
+        };
+        let app = match (&snapshot_store, &snapshots_token) {
+            (Some(_), Some(token)) => app.service(
+                actix_web::web::resource("/snapshots/{id}/pin")
+                    .app_data(actix_web::web::Data::new(token.clone()))
+                    .route(actix_web::web::put().to(snapshots::serve_pin))
+                    .route(actix_web::web::delete().to(snapshots::serve_unpin)),
+            ),
+            _ => app,
+        };
+        match &snapshot_store {
+            Some(store) => app
+                .app_data(actix_web::web::Data::from(store.clone()))
+                .service(
+                    actix_web::web::resource("/snapshots")
+                        .route(actix_web::web::get().to(snapshots::serve_index)),
+                )
+                .service(
+                    actix_web::web::resource("/snapshots/{id}")
+                        .route(actix_web::web::get().to(snapshots::serve_snapshot)),
+                ),
+            None => app,
+        }
+    });
+    for listener in commons::listen::bind_all(&status_addrs, settings.backlog)? {
+        metrics_server = metrics_server.listen(listener)?;
+    }
+    let metrics_server = metrics_server.run();
+
+    // Main service.
+    let main_state = state.clone();
+    let main_tenants = tenants.clone();
+    let request_limits = settings.request_limits;
+    let quota_limiter =
+        commons::limits::QuotaLimiter::new(
+            std::iter::once((
+                settings.path_prefix.clone(),
+                settings.request_quotas,
+                state.quota_channels(),
+            ))
+            .chain(settings.tenants.iter().zip(&tenants).map(
+                |(tenant, tenant_state)| {
+                    (
+                        tenant.path_prefix.clone(),
+                        tenant.quotas,
+                        tenant_state.quota_channels(),
+                    )
+                },
+            )),
+        );
+    let path_aliases = settings.path_aliases.clone();
+    let status_listener = commons::StatusListener {
+        address: status_addrs[0].ip(),
+        port: status_addrs[0].port(),
+    };
+    let latency_recorder = commons::latency::LatencyRecorder {
+        slow_threshold: settings.slow_request_threshold,
+    };
+    let main_path_aliases = path_aliases.clone();
+    let mut main_server = HttpServer::new(move || {
+        let path_aliases = main_path_aliases.clone();
+        let quota_limiter = quota_limiter.clone();
+        let mut app = App::new()
+            .wrap_fn(move |req, srv| quota_limiter.enforce(req, srv))
+            .wrap_fn(move |req, srv| path_aliases.enforce(req, srv))
+            .wrap_fn(move |req, srv| request_limits.enforce(req, srv))
+            .wrap_fn(move |req, srv| latency_recorder.observe(req, srv))
+            .wrap(middleware::Compress::default())
+            .wrap_fn(|req, srv| {
+                let parent_context = get_context(&req);
+                let mut span = get_tracer().start_with_context("request", parent_context);
+                set_span_tags(req.path(), req.headers(), &mut span);
+                let _active_span = mark_span_as_active(span);
+                let cx = ot_context::current();
+                srv.call(req).with_context(cx)
+            })
+            .app_data(actix_web::web::Data::new(main_state.clone()))
+            .app_data(actix_web::web::Data::new(status_listener))
+            .service(
+                // keeping this for backward compatibility
+                actix_web::web::resource(&format!("{}/v1/graph", app_prefix.clone()))
+                    .route(actix_web::web::get().to(graph::index)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/graph", app_prefix.clone()))
+                    .route(actix_web::web::get().to(graph::index)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/v1/graph/schema", app_prefix.clone()))
+                    .route(actix_web::web::get().to(openapi::graph_schema)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/v1/graph/attestation", app_prefix.clone()))
+                    .route(actix_web::web::get().to(attestation::index)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/v1/changelog", app_prefix.clone()))
+                    .route(actix_web::web::get().to(changelog::index)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/openapi", app_prefix.clone()))
+                    .route(actix_web::web::get().to(openapi::index)),
+            );
+        for tenant_state in &main_tenants {
+            app = app.service(
+                actix_web::web::scope(tenant_state.path_prefix())
+                    .app_data(actix_web::web::Data::new(tenant_state.clone()))
+                    .service(
+                        actix_web::web::resource("/v1/graph")
+                            .route(actix_web::web::get().to(graph::index)),
+                    )
+                    .service(
+                        actix_web::web::resource("/graph")
+                            .route(actix_web::web::get().to(graph::index)),
+                    )
+                    .service(
+                        actix_web::web::resource("/v1/graph/schema")
+                            .route(actix_web::web::get().to(openapi::graph_schema)),
+                    )
+                    .service(
+                        actix_web::web::resource("/v1/graph/attestation")
+                            .route(actix_web::web::get().to(attestation::index)),
+                    )
+                    .service(
+                        actix_web::web::resource("/v1/changelog")
+                            .route(actix_web::web::get().to(changelog::index)),
+                    )
+                    .service(
+                        actix_web::web::resource("/openapi")
+                            .route(actix_web::web::get().to(openapi::index)),
+                    ),
+            );
+        }
+        app
+    })
+    .backlog(settings.backlog)
+    .max_connections(settings.max_connections)
+    .max_connection_rate(settings.max_connection_rate)
+    .keep_alive(settings.keep_alive)
+    .client_request_timeout(settings.client_timeout);
+    if let Some(workers) = settings.workers {
+        main_server = main_server.workers(workers);
+    }
+    if let Some(threads) = settings.worker_max_blocking_threads {
+        main_server = main_server.worker_max_blocking_threads(threads);
+    }
+    for listener in commons::listen::bind_all(&service_addrs, settings.backlog)? {
+        main_server = main_server.listen(listener)?;
+    }
+    let main_server = main_server.run();
+
+    // Optional self-check of the serving path.
+    self_check::spawn(settings, &state)?;
+
+    // Optional gRPC service.
+    if let Some(grpc_addr) = grpc_addr {
+        let grpc_service = grpc::GraphService::new(state.clone());
+        actix_web::rt::spawn(async move {
+            if let Err(e) = commons::grpc::serve(grpc_addr, grpc_service).await {
+                error!("gRPC service failed: {:?}", e);
+            }
+        });
+    }
+
+    // Public service.
+    let public_state = state;
+    let public_tenants = tenants;
+    let mut public_server = HttpServer::new(move || {
+        let path_aliases = path_aliases.clone();
+        let mut app = App::new()
+            .wrap_fn(move |req, srv| path_aliases.enforce(req, srv))
+            .wrap_fn(move |req, srv| request_limits.enforce(req, srv))
+            .wrap_fn(move |req, srv| latency_recorder.observe(req, srv))
+            .wrap(middleware::Compress::default())
+            .wrap_fn(|req, srv| {
+                let parent_context = get_context(&req);
+                let mut span = get_tracer().start_with_context("request", parent_context);
+                set_span_tags(req.path(), req.headers(), &mut span);
+                let _active_span = mark_span_as_active(span);
+                let cx = ot_context::current();
+                srv.call(req).with_context(cx)
+            })
+            .app_data(actix_web::web::Data::new(public_state.clone()))
+            .service(
+                actix_web::web::resource(&format!("{}/graph-data", public_app_prefix.clone()))
+                    .route(actix_web::web::get().to(graph::graph_data)),
+            );
+        for tenant_state in &public_tenants {
+            app = app.service(
+                actix_web::web::scope(tenant_state.path_prefix())
+                    .app_data(actix_web::web::Data::new(tenant_state.clone()))
+                    .service(
+                        actix_web::web::resource("/graph-data")
+                            .route(actix_web::web::get().to(graph::graph_data)),
+                    ),
+            );
+        }
+        app
+    })
+    .backlog(settings.backlog)
+    .max_connections(settings.max_connections)
+    .max_connection_rate(settings.max_connection_rate)
+    .keep_alive(settings.keep_alive)
+    .client_request_timeout(settings.client_timeout);
+    if let Some(workers) = settings.workers {
+        public_server = public_server.workers(workers);
+    }
+    if let Some(threads) = settings.worker_max_blocking_threads {
+        public_server = public_server.worker_max_blocking_threads(threads);
+    }
+    for listener in commons::listen::bind_all(&public_addrs, settings.backlog)? {
+        public_server = public_server.listen(listener)?;
+    }
+    let public_server = public_server.run();
+
+    future::try_join3(metrics_server, main_server, public_server).await?;
+
+    Ok(())
+}
+
+/// Start the scrape loop of the graph-builder with `settings`, without its servers.
+///
+/// The returned state shares the built graph in memory. Tenants, staging and
+/// one-shot mode are not supported, as they rely on the servers.
+pub fn spawn_pipeline(settings: config::AppSettings) -> Fallible<graph::State> {
+    let registry: prometheus::Registry = metrics::new_labeled_registry(
+        Some(config::METRICS_PREFIX.to_string()),
+        &settings.metrics_labels,
+    )?;
+
+    ensure!(
+        !settings.oneshot,
+        "one-shot mode is not supported by embedded graph-builders"
+    );
+    if !settings.tenants.is_empty() {
+        log::warn!("tenants are not built by embedded graph-builders");
+    }
+    if settings.staging_enabled {
+        log::warn!("built graphs are not staged by embedded graph-builders");
+    }
+
+    let plugins = settings.validate_and_build_plugins(Some(&registry))?;
+    ensure_registered_metrics(
+        &registry,
+        config::METRICS_PREFIX,
+        &settings.metrics_required,
+    )?;
+
+    let state_store = state_store::from_settings(&settings)?;
+    let snapshot_store = open_snapshots(&settings, state_store.as_ref())?;
+    let changelog = open_changelog(&settings, state_store.as_ref())?;
+
+    let state = graph::State::new(
+        Arc::new(RwLock::new(String::new())),
+        settings.mandatory_client_parameters.clone(),
+        Arc::new(RwLock::new(false)),
+        Arc::new(RwLock::new(false)),
+        Box::leak(Box::new(plugins)),
+        Box::leak(Box::new(registry)),
+        Arc::new(RwLock::new(String::new())),
+    )
+    .with_path_prefix(settings.path_prefix.clone())
+    .with_param_constraints(commons::params::ParamConstraints::try_new(
+        &settings.client_parameters,
+    )?)
+    .with_snapshots(snapshot_store.clone())
+    .with_changelog(changelog)
+    .with_request_budget(commons::budget::RequestBudget::new(
+        settings.request_budgets(),
+    ))
+    .with_shared_graph();
+
+    if let Some(store) = snapshot_store
+        .as_ref()
+        .filter(|_| settings.snapshots_warm_start)
+    {
+        match snapshots::warm_start(store, &state) {
+            Ok(Some(entry)) => info!("serving graph snapshot {} until the first scrape", entry.id),
+            Ok(None) => info!("no graph snapshot to warm start from"),
+            Err(err) => error!("failed to load the latest graph snapshot: {:#}", err),
+        }
+    }
+    if let Some(store) = &snapshot_store {
+        snapshots::spawn_gc(store.clone(), settings.snapshots_gc_interval);
+        snapshots::register_metrics(state.registry())?;
+    }
+
+    graph::register_metrics(state.registry())?;
+    events::register_metrics(state.registry())?;
+    publish::register_metrics(state.registry())?;
+    webhooks::register_metrics(state.registry())?;
+    commons::budget::register_metrics(state.registry())?;
+    commons::metrics::register_metrics(state.registry())?;
+
+    let settings: &'static config::AppSettings = Box::leak(Box::new(settings));
+    let graph_state = state.clone();
+    thread::spawn(move || {
+        graph::run(settings, &graph_state);
+    });
+
+    Ok(state)
+}
+
+/// Open the snapshots configured by `settings`, kept in their directory or in `store`.
+fn open_snapshots(
+    settings: &config::AppSettings,
+    store: Option<&Arc<dyn state_store::StateStore>>,
+) -> Fallible<Option<Arc<snapshots::SnapshotStore>>> {
+    let snapshots = match (&settings.snapshots_dir, store) {
+        (Some(dir), _) => snapshots::SnapshotStore::open(dir, settings.snapshots_retention)?,
+        (None, Some(store)) => snapshots::SnapshotStore::open_in(
+            state_store::scoped(store.clone(), state_store::SNAPSHOTS_PREFIX),
+            settings.snapshots_retention,
+        )?,
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(Arc::new(
+        snapshots.with_daily_retention(settings.snapshots_retention_days),
+    )))
+}
+
+/// Open the changelog configured by `settings`, persisted in `store` if any.
+fn open_changelog(
+    settings: &config::AppSettings,
+    store: Option<&Arc<dyn state_store::StateStore>>,
+) -> Fallible<Option<Arc<changelog::Changelog>>> {
+    if settings.changelog_size == 0 {
+        return Ok(None);
+    }
+    let changelog = changelog::Changelog::new(settings.changelog_size);
+    let changelog = match store {
+        Some(store) => changelog.with_store(store.clone())?,
+        None => changelog,
+    };
+    Ok(Some(Arc::new(changelog)))
+}
+
+/// Create the registry of a tenant, labelling all its metrics with the tenant name.
+fn new_tenant_registry(name: &str) -> Fallible<prometheus::Registry> {
+    let labels = std::iter::once(("tenant".to_string(), name.to_string())).collect();
+    prometheus::Registry::new_custom(Some(config::METRICS_PREFIX.to_string()), Some(labels))
+        .context(format!("could not create a registry for tenant '{}'", name))
+}
+
+fn ensure_registered_metrics(
+    registry: &prometheus::Registry,
+    metrics_prefix: &str,
+    metrics_required: &HashSet<String>,
+) -> Fallible<()> {
+    let registered_metric_names = registry
+        .gather()
+        .iter()
+        .map(prometheus::proto::MetricFamily::get_name)
+        .map(Into::into)
+        .collect::<HashSet<String>>();
+
+    metrics_required.iter().try_for_each(|required_metric| {
+        ensure!(
+            registered_metric_names.contains(&format!("{}_{}", metrics_prefix, required_metric)),
+            "Required metric '{}' has not been registered: {:#?}",
+            required_metric,
+            registered_metric_names,
+        );
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{self, State};
+    use crate::status::{serve_liveness, serve_readiness};
+    use actix_web::body::MessageBody;
+    use commons::metrics::HasRegistry;
+    use commons::metrics::RegistryWrapper;
+    use commons::testing;
+    use memchr::memmem;
+    use parking_lot::RwLock;
+    use prometheus::Registry;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    fn mock_state(is_live: bool, is_ready: bool) -> State {
+        let json_graph = Arc::new(RwLock::new(String::new()));
+        let live = Arc::new(RwLock::new(is_live));
+        let ready = Arc::new(RwLock::new(is_ready));
+
+        let plugins = Box::leak(Box::new([]));
+        let registry: &'static Registry = Box::leak(Box::new(
+            metrics::new_registry(Some(config::METRICS_PREFIX.to_string())).unwrap(),
+        ));
+        let secondary_metadata = Arc::new(RwLock::new(String::new()));
+
+        State::new(
+            json_graph,
+            HashSet::new(),
+            live,
+            ready,
+            plugins,
+            registry,
+            secondary_metadata,
+        )
+    }
+
+    #[test]
+    fn serve_metrics_basic() -> Fallible<()> {
+        let rt = testing::init_runtime()?;
+        let state = mock_state(false, false);
+
+        let registry = <dyn HasRegistry>::registry(&state);
+        graph::register_metrics(registry)?;
+        testing::dummy_gauge(registry, 42.0)?;
+
+        let metrics_call =
+            metrics::serve::<RegistryWrapper>(actix_web::web::Data::new(RegistryWrapper(registry)));
+        let resp = rt.block_on(metrics_call);
+
+        assert_eq!(resp.status(), 200);
+        if let Ok(bytes) = resp.into_body().try_into_bytes() {
+            assert!(!bytes.is_empty());
+            println!("{:?}", std::str::from_utf8(bytes.as_ref()));
+            assert!(
+                memmem::find_iter(bytes.as_ref(), b"cincinnati_gb_dummy_gauge 42\n")
+                    .next()
+                    .is_some()
+            );
+        } else {
+            bail!("expected bytes in body")
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_liveness_readiness() -> Fallible<()> {
+        let rt = testing::init_runtime()?;
+
+        let liveness_is_live = serve_liveness(actix_web::web::Data::new(mock_state(true, false)));
+        let resp = rt.block_on(liveness_is_live);
+        assert!(
+            resp.status().is_success(),
+            "liveness check failed. Application returned {}, expected success",
+            resp.status()
+        );
+
+        let liveness_not_live = serve_liveness(actix_web::web::Data::new(mock_state(false, false)));
+        let resp = rt.block_on(liveness_not_live);
+        assert!(
+            !resp.status().is_success(),
+            "liveness check failed. Application returned {}, expected failure",
+            resp.status()
+        );
+
+        let readiness_is_ready = serve_readiness(actix_web::web::Data::new(mock_state(true, true)));
+        let resp = rt.block_on(readiness_is_ready);
+        assert!(
+            resp.status().is_success(),
+            "readiness check failed. Application returned {}, expected success",
+            resp.status()
+        );
+
+        let readiness_not_ready =
+            serve_readiness(actix_web::web::Data::new(mock_state(true, false)));
+        let resp = rt.block_on(readiness_not_ready);
+        assert!(
+            !resp.status().is_success(),
+            "readiness check failed. Application returned {}, expected failure",
+            resp.status()
+        );
+
+        Ok(())
+    }
+}
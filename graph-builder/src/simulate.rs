@@ -0,0 +1,356 @@
+//! Simulation of graph-data changes.
+//!
+//! Graph-data authors can preview the effect of candidate blocked edges and
+//! channels before merging them. The input of the graph-data parser, i.e.
+//! the scraped releases and graph-data, is kept from the latest successful
+//! scrape; a simulation copies that graph-data, applies the candidate files
+//! on top of it, runs the plugins from the parser on, and returns the
+//! differences with the graph built by that scrape.
+
+use crate::graph::State;
+use crate::staging::find_graph;
+use crate::webhooks::{self, ChannelDelta};
+use actix_web::{web, HttpRequest, HttpResponse};
+use cincinnati::plugins::prelude::*;
+use cincinnati::plugins::{BoxedPlugin, ChainRun, InternalIO, InternalPlugin, PluginIO};
+use cincinnati::{BlockedEdgesDiff, GraphDiff};
+use commons::budget::RequestBudget;
+use commons::prelude_errors::*;
+use commons::GRAPH_DATA_DIR_PARAM_KEY;
+use parking_lot::{Mutex, RwLock};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Bearer token required by the simulation endpoint.
+pub use commons::auth::BearerToken as SimulateToken;
+
+/// Directories of the graph-data files which can be simulated.
+static BLOCKED_EDGES_DIR: &str = "blocked-edges";
+static CHANNELS_DIR: &str = "channels";
+
+/// Candidate graph-data files, by file name. Files set to `null` are removed.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulationRequest {
+    /// Files of the `blocked-edges` directory.
+    #[serde(default)]
+    pub blocked_edges: BTreeMap<String, Option<String>>,
+    /// Files of the `channels` directory.
+    #[serde(default)]
+    pub channels: BTreeMap<String, Option<String>>,
+}
+
+/// Outcome of a simulation, compared with the graph of the latest scrape.
+#[derive(Debug, Serialize)]
+pub struct SimulationResult {
+    /// Number of releases of the simulated graph.
+    pub releases: u64,
+    /// Number of edges of the simulated graph.
+    pub edges: u64,
+    /// Changes to the releases and edges.
+    pub diff: GraphDiff,
+    /// Changes to the blocked edges.
+    pub blocked_edges: BlockedEdgesDiff,
+    /// Changes to the releases of the channels, for changed channels only.
+    pub channels: Vec<ChannelDelta>,
+}
+
+/// Graph-data parser input and built graph of a scrape.
+#[derive(Debug)]
+struct SimulationBase {
+    input: InternalIO,
+    built: cincinnati::Graph,
+}
+
+impl SimulationResult {
+    /// Compare the `candidate` graph with the `base` one, reading the
+    /// channels of releases from the `channels_key` metadata.
+    pub(crate) fn compare(
+        base: &cincinnati::Graph,
+        candidate: &cincinnati::Graph,
+        channels_key: &str,
+    ) -> Self {
+        Self {
+            releases: candidate.releases_count(),
+            edges: candidate.edges_count(),
+            diff: GraphDiff::new(base, candidate),
+            blocked_edges: BlockedEdgesDiff::new(base, candidate),
+            channels: webhooks::channel_deltas(
+                &webhooks::channel_releases(base, channels_key),
+                &webhooks::channel_releases(candidate, channels_key),
+            ),
+        }
+    }
+}
+
+/// Simulator of graph-data changes on the graph of a scrape loop.
+#[derive(Debug)]
+pub struct Simulation {
+    /// Timeout of the plugins run by a simulation.
+    timeout: Option<Duration>,
+    /// Metadata key listing the channels of a release.
+    channels_key: String,
+    base: RwLock<Option<Arc<SimulationBase>>>,
+    /// Held while a simulation runs, so that only one runs at a time.
+    running: Mutex<()>,
+}
+
+/// Return the position of the graph-data parser in `plugins`, if any.
+pub(crate) fn parser_index(plugins: &[BoxedPlugin]) -> Option<usize> {
+    plugins.iter().position(|plugin| {
+        plugin.get_name() == <OpenshiftSecondaryMetadataParserPlugin as InternalPlugin>::PLUGIN_NAME
+    })
+}
+
+/// Process `plugins` like a scrape, within `timeout` and `budget`, also
+/// returning the input of the graph-data parser if the chain reached it.
+pub(crate) fn process_traced_capturing(
+    plugins: &'static [BoxedPlugin],
+    timeout: Option<Duration>,
+    budget: &RequestBudget,
+) -> (ChainRun, Option<InternalIO>) {
+    let initial_io = PluginIO::InternalIO(InternalIO {
+        graph: Default::default(),
+        parameters: Default::default(),
+    });
+    let index = match parser_index(plugins) {
+        Some(index) => index,
+        None => {
+            let chain = cincinnati::plugins::process_traced_budgeted_blocking(
+                plugins.iter(),
+                initial_io,
+                timeout,
+                Some(budget.clone()),
+            );
+            return (chain, None);
+        }
+    };
+
+    let start = Instant::now();
+    let head = cincinnati::plugins::process_traced_budgeted_blocking(
+        plugins[..index].iter(),
+        initial_io,
+        timeout,
+        Some(budget.clone()),
+    );
+    let input = match head.io {
+        Ok(input) => input,
+        Err(_) => return (head, None),
+    };
+    let tail = cincinnati::plugins::process_traced_budgeted_blocking(
+        plugins[index..].iter(),
+        PluginIO::InternalIO(input.clone()),
+        timeout.map(|timeout| timeout.saturating_sub(start.elapsed())),
+        Some(budget.clone()),
+    );
+
+    let mut runs = head.plugins;
+    runs.extend(tail.plugins);
+    let chain = ChainRun {
+        io: tail.io,
+        plugins: runs,
+    };
+    (chain, Some(input))
+}
+
+impl Simulation {
+    /// Return a simulator running plugins within `timeout`, reading the
+    /// channels of releases from the `channels_key` metadata.
+    pub fn new(timeout: Option<Duration>, channels_key: String) -> Self {
+        Self {
+            timeout,
+            channels_key,
+            base: Default::default(),
+            running: Default::default(),
+        }
+    }
+
+    /// Keep the parser `input` of a scrape, along with the graph it `built`.
+    pub(crate) fn record(&self, input: InternalIO, built: cincinnati::Graph) {
+        *self.base.write() = Some(Arc::new(SimulationBase { input, built }));
+    }
+
+    /// Apply `request` on the latest scrape, running the plugins of
+    /// `plugins` from the graph-data parser on.
+    ///
+    /// Returns `None` if no scrape succeeded yet.
+    pub fn run(
+        &self,
+        plugins: &'static [BoxedPlugin],
+        request: &SimulationRequest,
+    ) -> Fallible<Option<SimulationResult>> {
+        let base = match self.base.read().clone() {
+            Some(base) => base,
+            None => return Ok(None),
+        };
+        let index = parser_index(plugins)
+            .ok_or_else(|| format_err!("the plugin chain has no graph-data parser"))?;
+
+        let data_dir = base
+            .input
+            .parameters
+            .get(GRAPH_DATA_DIR_PARAM_KEY)
+            .ok_or_else(|| format_err!("the scrape recorded no graph-data directory"))?;
+        let candidate = tempfile::tempdir()?;
+        copy_dir(Path::new(data_dir), candidate.path())
+            .context(format!("copying graph-data from {}", data_dir))?;
+        apply_files(
+            &candidate.path().join(BLOCKED_EDGES_DIR),
+            &request.blocked_edges,
+        )?;
+        apply_files(&candidate.path().join(CHANNELS_DIR), &request.channels)?;
+
+        let mut input = base.input.clone();
+        input.parameters.insert(
+            GRAPH_DATA_DIR_PARAM_KEY.to_string(),
+            candidate.path().to_string_lossy().into_owned(),
+        );
+        let simulated = cincinnati::plugins::process_blocking(
+            plugins[index..].iter(),
+            PluginIO::InternalIO(input),
+            self.timeout,
+        )?
+        .graph;
+
+        Ok(Some(SimulationResult::compare(
+            &base.built,
+            &simulated,
+            &self.channels_key,
+        )))
+    }
+}
+
+/// Copy the directory `src`, recursively, into the existing directory `dst`.
+fn copy_dir(src: &Path, dst: &Path) -> Fallible<()> {
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            std::fs::create_dir(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the candidate `files` to `dir`, removing those set to `None`.
+fn apply_files(dir: &Path, files: &BTreeMap<String, Option<String>>) -> Fallible<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir)?;
+    for (name, content) in files {
+        ensure!(
+            !name.is_empty()
+                && !name.starts_with('.')
+                && !name.contains(|c| c == '/' || c == '\\')
+                && (name.ends_with(".yaml") || name.ends_with(".yml")),
+            "invalid graph-data file name '{}'",
+            name
+        );
+        let path = dir.join(name);
+        match content {
+            Some(content) => std::fs::write(&path, content)?,
+            None if path.exists() => std::fs::remove_file(&path)?,
+            None => bail!("no graph-data file '{}' to remove", name),
+        }
+    }
+    Ok(())
+}
+
+/// Simulate the candidate graph-data of the request body on the graph
+/// selected by the `path_prefix` query parameter, the default graph if unset.
+pub async fn serve_simulate(
+    req: HttpRequest,
+    token: web::Data<SimulateToken>,
+    body: web::Json<SimulationRequest>,
+    app_data: web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = token.check(&req) {
+        return response;
+    }
+
+    let state = match find_graph(&req, &app_data) {
+        Ok(state) => state.clone(),
+        Err(response) => return response,
+    };
+    let simulation = match state.simulation() {
+        Some(simulation) => simulation.clone(),
+        None => return HttpResponse::NotFound().body("simulation is disabled"),
+    };
+
+    let result = web::block(move || {
+        let _running = match simulation.running.try_lock() {
+            Some(running) => running,
+            None => return Err(None),
+        };
+        simulation.run(state.plugins(), &body).map_err(Some)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(result))) => HttpResponse::Ok().json(result),
+        Ok(Ok(None)) => HttpResponse::ServiceUnavailable().body("no graph built yet"),
+        Ok(Err(None)) => HttpResponse::TooManyRequests().body("a simulation is already running"),
+        Ok(Err(Some(e))) => {
+            debug!("simulation failed: {:#}", e);
+            HttpResponse::UnprocessableEntity().body(format!("{:#}", e))
+        }
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_candidate_files() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let blocked_edges = dir.path().join(BLOCKED_EDGES_DIR);
+        std::fs::create_dir(&blocked_edges)?;
+        std::fs::write(blocked_edges.join("4.1.0.yaml"), "to: 4.1.0\nfrom: .*\n")?;
+
+        let files = |entries: &[(&str, Option<&str>)]| {
+            entries
+                .iter()
+                .map(|(name, content)| (name.to_string(), content.map(str::to_string)))
+                .collect::<BTreeMap<_, _>>()
+        };
+        apply_files(
+            &blocked_edges,
+            &files(&[
+                ("4.1.0.yaml", None),
+                ("4.1.1.yaml", Some("to: 4.1.1\nfrom: 4.0.0\n")),
+            ]),
+        )?;
+        assert!(!blocked_edges.join("4.1.0.yaml").exists());
+        assert_eq!(
+            std::fs::read_to_string(blocked_edges.join("4.1.1.yaml"))?,
+            "to: 4.1.1\nfrom: 4.0.0\n"
+        );
+
+        for name in &["../version", ".hidden.yaml", "4.1.2.json", ""] {
+            assert!(
+                apply_files(&blocked_edges, &files(&[(*name, Some(""))])).is_err(),
+                "{}",
+                name
+            );
+        }
+        assert!(apply_files(&blocked_edges, &files(&[("4.1.0.yaml", None)])).is_err());
+
+        let copy = tempfile::tempdir()?;
+        copy_dir(dir.path(), copy.path())?;
+        assert!(copy
+            .path()
+            .join(BLOCKED_EDGES_DIR)
+            .join("4.1.1.yaml")
+            .exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,669 @@
+//! Persistence of graph snapshots.
+//!
+//! Each built graph which differs from the latest snapshot is written to the
+//! state store as `<id>.json.zst`, compressed with zstd. The store also
+//! holds `index.json`, listing the snapshots from oldest to newest with the
+//! digest of their uncompressed JSON and the time at which they were taken.
+//!
+//! Snapshots are retained according to a policy: the newest snapshots, the
+//! newest snapshot of each of the latest days, and the pinned snapshots are
+//! kept. All others are pruned when a snapshot is stored, and by a periodic
+//! garbage collection, which also removes snapshot files missing from the
+//! index.
+//!
+//! On start, the latest snapshot can be loaded to serve a graph before the
+//! first scrape completes. Snapshot files are memory-mapped, if the store is
+//! a local directory, and decompressed into the served JSON, which is never
+//! parsed into a graph.
+
+use crate::state_store::{DiskStore, StateStore};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use commons::auth::BearerToken;
+use commons::prelude_errors::*;
+use parking_lot::Mutex;
+use prometheus::{IntCounter, IntGauge};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Key of the index of the snapshots.
+static INDEX_FILE: &str = "index.json";
+
+/// Extension of the snapshot files.
+static SNAPSHOT_EXTENSION: &str = "json.zst";
+
+/// Compression level of the snapshots.
+static COMPRESSION_LEVEL: i32 = 9;
+
+/// Default number of snapshots kept.
+pub static DEFAULT_RETENTION: usize = 1000;
+
+/// Default pause between garbage collections of the snapshots.
+pub static DEFAULT_GC_INTERVAL: Duration = Duration::from_secs(3600);
+
+lazy_static! {
+    static ref SNAPSHOTS: IntGauge =
+        IntGauge::new("graph_snapshots", "Number of stored graph snapshots").unwrap();
+    static ref SNAPSHOTS_PINNED: IntGauge =
+        IntGauge::new("graph_snapshots_pinned", "Number of pinned graph snapshots").unwrap();
+    static ref SNAPSHOTS_SIZE: IntGauge = IntGauge::new(
+        "graph_snapshots_size_bytes",
+        "Total size of the stored graph snapshot files, in bytes"
+    )
+    .unwrap();
+    static ref SNAPSHOTS_PRUNED: IntCounter = IntCounter::new(
+        "graph_snapshots_pruned_total",
+        "Total number of graph snapshots pruned by the retention policy"
+    )
+    .unwrap();
+    static ref SNAPSHOTS_GC_ERRORS: IntCounter = IntCounter::new(
+        "graph_snapshots_gc_errors_total",
+        "Total number of failed garbage collections of the graph snapshots"
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(SNAPSHOTS.clone()))?;
+    registry.register(Box::new(SNAPSHOTS_PINNED.clone()))?;
+    registry.register(Box::new(SNAPSHOTS_SIZE.clone()))?;
+    registry.register(Box::new(SNAPSHOTS_PRUNED.clone()))?;
+    registry.register(Box::new(SNAPSHOTS_GC_ERRORS.clone()))?;
+    Ok(())
+}
+
+/// Entry of the snapshots index.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    /// Identifier of the snapshot, e.g. "20261014T120000Z-3f2a9c1b0d4e".
+    pub id: String,
+    /// Time at which the snapshot was taken, in RFC 3339 format.
+    pub timestamp: String,
+    /// Digest of the uncompressed graph JSON, e.g. "sha256:3f2a...".
+    pub digest: String,
+    /// Number of releases of the graph.
+    pub releases: u64,
+    /// Number of edges of the graph.
+    pub edges: u64,
+    /// Size of the uncompressed graph JSON, in bytes.
+    pub size: u64,
+    /// Size of the snapshot file, in bytes.
+    pub compressed_size: u64,
+    /// Whether the snapshot is kept regardless of the retention policy.
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+impl SnapshotEntry {
+    /// Time at which the snapshot was taken.
+    pub fn taken(&self) -> Fallible<SystemTime> {
+        let time = chrono::DateTime::parse_from_rfc3339(&self.timestamp)
+            .context(format!("parsing timestamp of snapshot {}", self.id))?;
+        Ok(time.into())
+    }
+
+    /// Size of the graph of the snapshot.
+    pub fn stats(&self) -> crate::graph::GraphStats {
+        crate::graph::GraphStats {
+            releases: self.releases,
+            edges: self.edges,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    snapshots: Vec<SnapshotEntry>,
+}
+
+impl Index {
+    fn update_metrics(&self) {
+        SNAPSHOTS.set(self.snapshots.len() as i64);
+        SNAPSHOTS_PINNED.set(self.snapshots.iter().filter(|s| s.pinned).count() as i64);
+        SNAPSHOTS_SIZE.set(
+            self.snapshots
+                .iter()
+                .map(|s| s.compressed_size as i64)
+                .sum(),
+        );
+    }
+}
+
+/// Policy selecting the snapshots which are kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Number of newest snapshots kept.
+    pub keep_last: usize,
+    /// Number of latest days, including the current one, of which the newest
+    /// snapshot is kept.
+    pub keep_daily_days: u32,
+}
+
+impl RetentionPolicy {
+    /// Identifiers of the `snapshots` retained at `now`.
+    fn retained(&self, snapshots: &[SnapshotEntry], now: SystemTime) -> HashSet<String> {
+        let mut retained: HashSet<String> = snapshots
+            .iter()
+            .rev()
+            .take(self.keep_last)
+            .chain(snapshots.iter().filter(|s| s.pinned))
+            .map(|s| s.id.clone())
+            .collect();
+
+        if self.keep_daily_days > 0 {
+            let today = chrono::DateTime::<chrono::Utc>::from(now).date_naive();
+            let mut days = HashSet::new();
+            for snapshot in snapshots.iter().rev() {
+                let day = match snapshot.taken() {
+                    Ok(taken) => chrono::DateTime::<chrono::Utc>::from(taken).date_naive(),
+                    Err(err) => {
+                        warn!("retaining snapshot with invalid timestamp: {:#}", err);
+                        retained.insert(snapshot.id.clone());
+                        continue;
+                    }
+                };
+                let age = (today - day).num_days();
+                if (0..i64::from(self.keep_daily_days)).contains(&age) && days.insert(day) {
+                    retained.insert(snapshot.id.clone());
+                }
+            }
+        }
+
+        retained
+    }
+}
+
+/// Snapshots of the graph, kept in a state store.
+#[derive(Debug)]
+pub struct SnapshotStore {
+    store: Arc<dyn StateStore>,
+    policy: RetentionPolicy,
+    index: Mutex<Index>,
+}
+
+impl SnapshotStore {
+    /// Open the store in `dir`, creating it if needed, keeping up to `retention` snapshots.
+    pub fn open(dir: &Path, retention: usize) -> Fallible<Self> {
+        Self::open_in(Arc::new(DiskStore::open(dir)?), retention)
+    }
+
+    /// Open the store at the root of `store`, keeping up to `retention` snapshots.
+    pub fn open_in(store: Arc<dyn StateStore>, retention: usize) -> Fallible<Self> {
+        ensure!(retention > 0, "snapshot retention must be positive");
+
+        let index: Index = match store.get(INDEX_FILE).context("reading snapshots index")? {
+            Some(content) => serde_json::from_slice(&content).context("parsing snapshots index")?,
+            None => Index::default(),
+        };
+
+        index.update_metrics();
+
+        Ok(Self {
+            store,
+            policy: RetentionPolicy {
+                keep_last: retention,
+                keep_daily_days: 0,
+            },
+            index: Mutex::new(index),
+        })
+    }
+
+    /// Also keep the newest snapshot of each of the latest `days`.
+    pub fn with_daily_retention(mut self, days: u32) -> Self {
+        self.policy.keep_daily_days = days;
+        self
+    }
+
+    /// Store the graph `json`, taken at `now`, unless it is the same as the latest snapshot.
+    pub fn store(
+        &self,
+        json: &str,
+        stats: crate::graph::GraphStats,
+        now: SystemTime,
+    ) -> Fallible<Option<SnapshotEntry>> {
+        let digest = format!("sha256:{}", hex::encode(Sha256::digest(json.as_bytes())));
+        let mut index = self.index.lock();
+        if index.snapshots.last().map(|last| &last.digest) == Some(&digest) {
+            return Ok(None);
+        }
+
+        let time = chrono::DateTime::<chrono::Utc>::from(now);
+        let id = format!(
+            "{}-{}",
+            time.format("%Y%m%dT%H%M%SZ"),
+            &digest["sha256:".len().."sha256:".len() + 12]
+        );
+        let compressed = zstd::encode_all(json.as_bytes(), COMPRESSION_LEVEL)
+            .context("compressing graph snapshot")?;
+        self.store.put(&snapshot_key(&id), &compressed)?;
+
+        let entry = SnapshotEntry {
+            id,
+            timestamp: time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            digest,
+            releases: stats.releases,
+            edges: stats.edges,
+            size: json.len() as u64,
+            compressed_size: compressed.len() as u64,
+            pinned: false,
+        };
+        index.snapshots.push(entry.clone());
+        self.prune(&mut index, now)?;
+
+        Ok(Some(entry))
+    }
+
+    /// Prune the snapshots not retained at `now` and persist the index,
+    /// returning the number of pruned snapshots.
+    fn prune(&self, index: &mut Index, now: SystemTime) -> Fallible<usize> {
+        let retained = self.policy.retained(&index.snapshots, now);
+        let (kept, pruned): (Vec<SnapshotEntry>, Vec<SnapshotEntry>) =
+            std::mem::take(&mut index.snapshots)
+                .into_iter()
+                .partition(|s| retained.contains(&s.id));
+        index.snapshots = kept;
+
+        self.write_index(index)?;
+        for snapshot in &pruned {
+            if let Err(err) = self.store.delete(&snapshot_key(&snapshot.id)) {
+                warn!("failed to prune snapshot {}: {:#}", snapshot.id, err);
+            }
+        }
+        SNAPSHOTS_PRUNED.inc_by(pruned.len() as u64);
+
+        Ok(pruned.len())
+    }
+
+    /// Prune the snapshots not retained at `now`, and remove the snapshot
+    /// files missing from the index, returning the number of removed
+    /// snapshots.
+    pub fn collect_garbage(&self, now: SystemTime) -> Fallible<usize> {
+        let mut index = self.index.lock();
+        let mut removed =
+            if self.policy.retained(&index.snapshots, now).len() < index.snapshots.len() {
+                self.prune(&mut index, now)?
+            } else {
+                0
+            };
+
+        // Files of failed prunes, or of snapshots written before a crash.
+        let indexed: HashSet<String> = index
+            .snapshots
+            .iter()
+            .map(|s| snapshot_key(&s.id))
+            .collect();
+        let suffix = format!(".{}", SNAPSHOT_EXTENSION);
+        for key in self.store.list("").context("listing snapshots")? {
+            let is_snapshot = !key.contains('/') && key.ends_with(&suffix);
+            if is_snapshot && !indexed.contains(&key) {
+                self.store
+                    .delete(&key)
+                    .context(format!("removing unindexed snapshot {}", key))?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Pin or unpin the snapshot `id`, returning it if it exists.
+    ///
+    /// Unpinned snapshots are pruned by the next garbage collection, unless
+    /// they are retained by the policy.
+    pub fn pin(&self, id: &str, pinned: bool) -> Fallible<Option<SnapshotEntry>> {
+        let mut index = self.index.lock();
+        let entry = match index.snapshots.iter_mut().find(|s| s.id == id) {
+            Some(entry) => {
+                entry.pinned = pinned;
+                entry.clone()
+            }
+            None => return Ok(None),
+        };
+        self.write_index(&index)?;
+
+        Ok(Some(entry))
+    }
+
+    fn write_index(&self, index: &Index) -> Fallible<()> {
+        self.store
+            .put(INDEX_FILE, &serde_json::to_vec_pretty(index)?)
+            .context("writing snapshots index")?;
+        index.update_metrics();
+        Ok(())
+    }
+
+    /// List the snapshots, from oldest to newest.
+    pub fn list(&self) -> Vec<SnapshotEntry> {
+        self.index.lock().snapshots.clone()
+    }
+
+    /// Return the graph JSON of the snapshot `id`, if it exists.
+    pub fn fetch(&self, id: &str) -> Fallible<Option<Vec<u8>>> {
+        // Only indexed identifiers are turned into keys.
+        if !self.index.lock().snapshots.iter().any(|s| s.id == id) {
+            return Ok(None);
+        }
+
+        self.read_snapshot(id).map(Some)
+    }
+
+    /// Return the latest snapshot and its graph JSON, checked against the
+    /// digest of the index, if any snapshot was taken.
+    ///
+    /// The graph JSON is fully decompressed, as it is served from memory.
+    pub fn load_latest(&self) -> Fallible<Option<(SnapshotEntry, String)>> {
+        let entry = match self.index.lock().snapshots.last() {
+            Some(entry) => entry.clone(),
+            None => return Ok(None),
+        };
+
+        let json = self.read_snapshot(&entry.id)?;
+        let digest = format!("sha256:{}", hex::encode(Sha256::digest(&json)));
+        ensure!(
+            digest == entry.digest,
+            "snapshot {} has digest {}, expected {}",
+            entry.id,
+            digest,
+            entry.digest
+        );
+        let json = String::from_utf8(json).context(format!("decoding snapshot {}", entry.id))?;
+
+        Ok(Some((entry, json)))
+    }
+
+    /// Decompress the snapshot `id`, memory-mapped if the store allows it.
+    fn read_snapshot(&self, id: &str) -> Fallible<Vec<u8>> {
+        let compressed = self
+            .store
+            .get_mapped(&snapshot_key(id))
+            .context(format!("reading snapshot {}", id))?
+            .ok_or_else(|| format_err!("missing snapshot {}", id))?;
+        let json =
+            zstd::decode_all(&compressed[..]).context(format!("decompressing snapshot {}", id))?;
+        Ok(json)
+    }
+}
+
+/// Key of the snapshot `id`.
+fn snapshot_key(id: &str) -> String {
+    format!("{}.{}", id, SNAPSHOT_EXTENSION)
+}
+
+/// Start collecting the garbage of `store` every `interval`.
+pub fn spawn_gc(store: Arc<SnapshotStore>, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        match store.collect_garbage(SystemTime::now()) {
+            Ok(0) => trace!("no graph snapshot to collect"),
+            Ok(removed) => info!("collected {} graph snapshots", removed),
+            Err(err) => {
+                SNAPSHOTS_GC_ERRORS.inc();
+                error!("failed to collect graph snapshots: {:#}", err);
+            }
+        }
+    });
+}
+
+/// Serve the latest snapshot of `store` from `state`, until a scrape succeeds.
+pub fn warm_start(
+    store: &SnapshotStore,
+    state: &crate::graph::State,
+) -> Fallible<Option<SnapshotEntry>> {
+    let (entry, json) = match store.load_latest()? {
+        Some(latest) => latest,
+        None => return Ok(None),
+    };
+    state.warm_start(json, entry.stats(), entry.taken()?);
+    Ok(Some(entry))
+}
+
+/// Serve the index of the snapshots.
+pub async fn serve_index(store: web::Data<SnapshotStore>) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "snapshots": store.list() }))
+}
+
+/// Pin the snapshot selected by the path.
+pub async fn serve_pin(
+    req: HttpRequest,
+    token: web::Data<BearerToken>,
+    store: web::Data<SnapshotStore>,
+    id: web::Path<String>,
+) -> HttpResponse {
+    update_pin(req, token, store, id, true).await
+}
+
+/// Unpin the snapshot selected by the path.
+pub async fn serve_unpin(
+    req: HttpRequest,
+    token: web::Data<BearerToken>,
+    store: web::Data<SnapshotStore>,
+    id: web::Path<String>,
+) -> HttpResponse {
+    update_pin(req, token, store, id, false).await
+}
+
+async fn update_pin(
+    req: HttpRequest,
+    token: web::Data<BearerToken>,
+    store: web::Data<SnapshotStore>,
+    id: web::Path<String>,
+    pinned: bool,
+) -> HttpResponse {
+    if let Err(response) = token.check(&req) {
+        return response;
+    }
+
+    let id = id.into_inner();
+    match web::block(move || store.pin(&id, pinned)).await {
+        Ok(Ok(Some(entry))) => HttpResponse::Ok().json(entry),
+        Ok(Ok(None)) => HttpResponse::NotFound().finish(),
+        Ok(Err(err)) => {
+            error!("failed to update snapshot pin: {:#}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+        Err(err) => {
+            error!("failed to update snapshot pin: {}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Serve the graph of a snapshot.
+pub async fn serve_snapshot(
+    store: web::Data<SnapshotStore>,
+    id: web::Path<String>,
+) -> HttpResponse {
+    let id = id.into_inner();
+    match web::block(move || store.fetch(&id)).await {
+        Ok(Ok(Some(json))) => HttpResponse::Ok()
+            .insert_header((header::CONTENT_TYPE, cincinnati::CONTENT_TYPE))
+            .body(json),
+        Ok(Ok(None)) => HttpResponse::NotFound().finish(),
+        Ok(Err(err)) => {
+            error!("failed to fetch snapshot: {:#}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+        Err(err) => {
+            error!("failed to fetch snapshot: {}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::GraphStats;
+    use std::time::Duration;
+
+    fn stats(releases: u64) -> GraphStats {
+        GraphStats { releases, edges: 0 }
+    }
+
+    #[test]
+    fn store_and_prune_snapshots() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let store = SnapshotStore::open(dir.path(), 2)?;
+
+        let first = store.store(r#"{"nodes":[]}"#, stats(0), start)?.unwrap();
+        assert_eq!(first.timestamp, "2023-11-14T22:13:20Z");
+        assert_eq!(first.taken()?, start);
+        assert!(first.id.starts_with("20231114T221320Z-"));
+        // Unchanged graphs are not stored again.
+        assert_eq!(
+            store.store(r#"{"nodes":[]}"#, stats(0), start + Duration::from_secs(60))?,
+            None
+        );
+
+        let json = r#"{"nodes":[{"version":"4.14.0"}]}"#;
+        let second = store
+            .store(json, stats(1), start + Duration::from_secs(120))?
+            .unwrap();
+        assert_eq!(store.fetch(&second.id)?, Some(json.as_bytes().to_vec()));
+
+        let third = store
+            .store(
+                r#"{"nodes":[{"version":"4.14.1"}]}"#,
+                stats(1),
+                start + Duration::from_secs(180),
+            )?
+            .unwrap();
+        assert_eq!(store.list(), vec![second.clone(), third.clone()]);
+        assert_eq!(store.fetch(&first.id)?, None);
+        assert!(!dir.path().join(format!("{}.json.zst", first.id)).exists());
+        assert_eq!(store.fetch("../index")?, None);
+
+        // The index survives restarts.
+        let reopened = SnapshotStore::open(dir.path(), 2)?;
+        assert_eq!(reopened.list(), vec![second, third.clone()]);
+        let (latest, json) = reopened.load_latest()?.unwrap();
+        assert_eq!(latest, third);
+        assert_eq!(json, r#"{"nodes":[{"version":"4.14.1"}]}"#);
+
+        Ok(())
+    }
+
+    #[test]
+    fn retain_daily_and_pinned_snapshots() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let day = Duration::from_secs(24 * 3600);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_006_400);
+        let store = SnapshotStore::open(dir.path(), 1)?.with_daily_retention(2);
+
+        let old = store
+            .store(r#"{"nodes":[]}"#, stats(0), start - 2 * day)?
+            .unwrap();
+        assert_eq!(store.pin(&old.id, true)?.map(|s| s.pinned), Some(true));
+        let yesterday = store
+            .store(r#"{"nodes":[{"version":"4.14.0"}]}"#, stats(1), start - day)?
+            .unwrap();
+        let morning = store
+            .store(r#"{"nodes":[{"version":"4.14.1"}]}"#, stats(1), start)?
+            .unwrap();
+        let noon = store
+            .store(
+                r#"{"nodes":[{"version":"4.14.2"}]}"#,
+                stats(1),
+                start + Duration::from_secs(3600),
+            )?
+            .unwrap();
+
+        // The earlier snapshot of the current day is superseded.
+        let ids = |store: &SnapshotStore| -> Vec<String> {
+            store.list().into_iter().map(|s| s.id).collect()
+        };
+        assert_eq!(
+            ids(&store),
+            vec![old.id.clone(), yesterday.id, noon.id.clone()]
+        );
+        assert!(!dir.path().join(format!("{}.json.zst", morning.id)).exists());
+
+        assert_eq!(
+            store.collect_garbage(start + Duration::from_secs(3 * 3600))?,
+            0
+        );
+
+        // Snapshots of days beyond the policy are collected, unless pinned.
+        assert_eq!(store.pin(&old.id, false)?.map(|s| s.pinned), Some(false));
+        assert_eq!(store.pin("unknown", true)?, None);
+        assert_eq!(store.collect_garbage(start + day)?, 2);
+        assert_eq!(ids(&store), vec![noon.id.clone()]);
+
+        // Pins survive restarts.
+        store.pin(&noon.id, true)?;
+        let reopened = SnapshotStore::open(dir.path(), 1)?;
+        assert!(reopened.list()[0].pinned);
+
+        Ok(())
+    }
+
+    #[test]
+    fn collect_unindexed_snapshots() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let store = SnapshotStore::open(dir.path(), 2)?;
+        let entry = store
+            .store(r#"{"nodes":[]}"#, stats(0), SystemTime::now())?
+            .unwrap();
+        let orphan = dir.path().join("20231114T221320Z-000000000000.json.zst");
+        std::fs::write(&orphan, b"")?;
+
+        assert_eq!(store.collect_garbage(SystemTime::now())?, 1);
+        assert!(!orphan.exists());
+        assert!(dir.path().join(INDEX_FILE).exists());
+        assert_eq!(store.fetch(&entry.id)?, Some(br#"{"nodes":[]}"#.to_vec()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn store_snapshots_in_state_store() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let state: Arc<dyn StateStore> = Arc::new(DiskStore::open(dir.path())?);
+        let store = SnapshotStore::open_in(
+            crate::state_store::scoped(state.clone(), crate::state_store::SNAPSHOTS_PREFIX),
+            2,
+        )?;
+        let entry = store
+            .store(r#"{"nodes":[]}"#, stats(0), SystemTime::now())?
+            .unwrap();
+        state.put("changelog.json", b"{}")?;
+
+        assert_eq!(
+            state.list("")?,
+            vec![
+                "changelog.json".to_string(),
+                format!("snapshots/{}.json.zst", entry.id),
+                "snapshots/index.json".to_string(),
+            ]
+        );
+        // Keys outside of the snapshots are not collected.
+        assert_eq!(store.collect_garbage(SystemTime::now())?, 0);
+        assert_eq!(state.get("changelog.json")?, Some(b"{}".to_vec()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_corrupted_snapshot() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let store = SnapshotStore::open(dir.path(), 2)?;
+        assert_eq!(store.load_latest()?, None);
+
+        let entry = store
+            .store(r#"{"nodes":[]}"#, stats(0), SystemTime::now())?
+            .unwrap();
+        std::fs::write(
+            dir.path().join(format!("{}.json.zst", entry.id)),
+            zstd::encode_all(&br#"{"nodes":[{}]}"#[..], COMPRESSION_LEVEL)?,
+        )?;
+        assert!(store.load_latest().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,430 @@
+//! Staging of the built graphs before they are served.
+//!
+//! With staging enabled, a graph built by a scrape is not served right away
+//! once a graph is served: it is compared with the served graph against the
+//! configured validation rules, and only promoted to serving if they all pass
+//! and automatic promotion is enabled. Otherwise it waits in the staging
+//! slot, replaced by the graph of the next scrape, until an admin promotes or
+//! discards it through the status service.
+
+use crate::config::AppSettings;
+use crate::graph::{BuiltGraph, State};
+use actix_web::{web, HttpRequest, HttpResponse};
+use cincinnati::GraphDiff;
+use parking_lot::{Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Bearer token required by the staging endpoints.
+pub use commons::auth::BearerToken as AdminToken;
+
+/// Validation rules of the staged graphs.
+#[derive(Clone, Debug, Default)]
+pub struct StagingRules {
+    /// Minimum number of releases.
+    pub min_releases: u64,
+    /// Minimum number of edges.
+    pub min_edges: u64,
+    /// Maximum number of releases removed from the served graph.
+    pub max_removed_releases: Option<u64>,
+    /// Maximum number of edges removed from the served graph.
+    pub max_removed_edges: Option<u64>,
+}
+
+impl StagingRules {
+    /// Check the `staged` graph against the `served` one.
+    ///
+    /// Returns the violated rules, empty if the staged graph is valid.
+    pub fn validate(&self, served: &cincinnati::Graph, staged: &cincinnati::Graph) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        if staged.releases_count() < self.min_releases {
+            violations.push(format!(
+                "{} releases, expected at least {}",
+                staged.releases_count(),
+                self.min_releases
+            ));
+        }
+        if staged.edges_count() < self.min_edges {
+            violations.push(format!(
+                "{} edges, expected at least {}",
+                staged.edges_count(),
+                self.min_edges
+            ));
+        }
+
+        if self.max_removed_releases.is_some() || self.max_removed_edges.is_some() {
+            let diff = GraphDiff::new(served, staged);
+            let removed_releases = diff.removed_releases.len() as u64;
+            if let Some(max) = self
+                .max_removed_releases
+                .filter(|max| removed_releases > *max)
+            {
+                violations.push(format!(
+                    "{} releases removed, expected at most {}",
+                    removed_releases, max
+                ));
+            }
+            let removed_edges = diff.removed_edges.len() as u64;
+            if let Some(max) = self.max_removed_edges.filter(|max| removed_edges > *max) {
+                violations.push(format!(
+                    "{} edges removed, expected at most {}",
+                    removed_edges, max
+                ));
+            }
+        }
+
+        violations
+    }
+}
+
+/// Graph waiting in the staging slot.
+#[derive(Debug)]
+pub struct StagedGraph {
+    /// Built graph.
+    pub built: BuiltGraph,
+    /// Time at which the graph was staged.
+    pub staged: SystemTime,
+    /// Validation rules the graph violates.
+    pub violations: Vec<String>,
+}
+
+/// Summary of a staged graph, as served by the staging endpoints.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct StagedSummary {
+    /// Time at which the graph was staged, in RFC 3339 format.
+    pub staged: String,
+    /// Number of releases of the graph.
+    pub releases: u64,
+    /// Number of edges of the graph.
+    pub edges: u64,
+    /// Validation rules the graph violates.
+    pub violations: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Slots {
+    staged: Option<StagedGraph>,
+    promoted: Option<StagedGraph>,
+}
+
+/// Staging slot of a graph.
+#[derive(Debug)]
+pub struct Staging {
+    rules: StagingRules,
+    auto_promote: bool,
+    slots: Mutex<Slots>,
+    promotions: Condvar,
+}
+
+impl Staging {
+    /// Create the staging slot of a graph, if enabled in `settings`.
+    pub fn from_settings(settings: &AppSettings) -> Option<Self> {
+        if !settings.staging_enabled {
+            return None;
+        }
+
+        Some(Self::new(
+            StagingRules {
+                min_releases: settings.staging_min_releases,
+                min_edges: settings.staging_min_edges,
+                max_removed_releases: settings.staging_max_removed_releases,
+                max_removed_edges: settings.staging_max_removed_edges,
+            },
+            settings.staging_auto_promote,
+        ))
+    }
+
+    /// Create a staging slot validating graphs against `rules`.
+    pub fn new(rules: StagingRules, auto_promote: bool) -> Self {
+        Self {
+            rules,
+            auto_promote,
+            slots: Default::default(),
+            promotions: Condvar::new(),
+        }
+    }
+
+    /// Return the validation rules of the staged graphs.
+    pub fn rules(&self) -> &StagingRules {
+        &self.rules
+    }
+
+    /// Validate the graph `built` by a scrape against the `served` graph.
+    ///
+    /// Returns the graph back if it can be served right away, or stages it.
+    pub(crate) fn admit(
+        &self,
+        served: &cincinnati::Graph,
+        built: BuiltGraph,
+    ) -> Option<BuiltGraph> {
+        let violations = self.rules.validate(served, &built.graph);
+        if self.auto_promote && violations.is_empty() {
+            self.slots.lock().staged = None;
+            return Some(built);
+        }
+
+        if violations.is_empty() {
+            info!("staging built graph, waiting for promotion");
+        } else {
+            warn!(
+                "staging built graph, which violates validation rules: {}",
+                violations.join("; ")
+            );
+        }
+        self.slots.lock().staged = Some(StagedGraph {
+            built,
+            staged: SystemTime::now(),
+            violations,
+        });
+        None
+    }
+
+    /// Return a summary of the staged graph, if any.
+    pub fn staged(&self) -> Option<StagedSummary> {
+        self.slots
+            .lock()
+            .staged
+            .as_ref()
+            .map(|staged| StagedSummary {
+                staged: chrono::DateTime::<chrono::Utc>::from(staged.staged).to_rfc3339(),
+                releases: staged.built.stats.releases,
+                edges: staged.built.stats.edges,
+                violations: staged.violations.clone(),
+            })
+    }
+
+    /// Promote the staged graph, to be served by the scrape loop.
+    ///
+    /// Returns false if no graph is staged.
+    pub fn promote(&self) -> bool {
+        let mut slots = self.slots.lock();
+        match slots.staged.take() {
+            Some(staged) => {
+                slots.promoted = Some(staged);
+                self.promotions.notify_all();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Discard the staged graph.
+    ///
+    /// Returns false if no graph is staged.
+    pub fn discard(&self) -> bool {
+        self.slots.lock().staged.take().is_some()
+    }
+
+    /// Take the promoted graph, if any.
+    pub(crate) fn take_promoted(&self) -> Option<BuiltGraph> {
+        self.slots
+            .lock()
+            .promoted
+            .take()
+            .map(|promoted| promoted.built)
+    }
+
+    /// Wait up to `timeout` for a graph to be promoted, and take it.
+    pub(crate) fn wait_promoted(&self, timeout: Duration) -> Option<BuiltGraph> {
+        let deadline = Instant::now() + timeout;
+        let mut slots = self.slots.lock();
+        while slots.promoted.is_none() {
+            if self.promotions.wait_until(&mut slots, deadline).timed_out() {
+                break;
+            }
+        }
+        slots.promoted.take().map(|promoted| promoted.built)
+    }
+}
+
+/// Find the graph addressed by the `path_prefix` query parameter of `req`,
+/// the default graph if unset.
+pub(crate) fn find_graph<'a>(
+    req: &HttpRequest,
+    state: &'a State,
+) -> Result<&'a State, HttpResponse> {
+    let mut path_prefix = String::new();
+    for (key, value) in url::form_urlencoded::parse(req.query_string().as_bytes()) {
+        if key == "path_prefix" {
+            path_prefix = value.into_owned();
+        }
+    }
+
+    std::iter::once(state)
+        .chain(state.tenants())
+        .find(|state| state.path_prefix() == path_prefix)
+        .ok_or_else(|| {
+            HttpResponse::NotFound().body(format!("no graph with path prefix '{}'", path_prefix))
+        })
+}
+
+/// Serve the staged graphs of the default graph and of all tenants, as JSON.
+pub async fn serve_index(
+    req: HttpRequest,
+    token: web::Data<AdminToken>,
+    app_data: web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = token.check(&req) {
+        return response;
+    }
+
+    let staged: Vec<serde_json::Value> = std::iter::once(app_data.get_ref())
+        .chain(app_data.tenants())
+        .filter_map(|state| {
+            state.staging().map(|staging| {
+                serde_json::json!({
+                    "path_prefix": state.path_prefix(),
+                    "staged": staging.staged(),
+                })
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({ "graphs": staged }))
+}
+
+/// Promote the staged graph selected by the `path_prefix` query parameter.
+///
+/// The graph is served by the scrape loop shortly after the response.
+pub async fn serve_promote(
+    req: HttpRequest,
+    token: web::Data<AdminToken>,
+    app_data: web::Data<State>,
+) -> HttpResponse {
+    update_slot(&req, &token, &app_data, Staging::promote, "promoted")
+}
+
+/// Discard the staged graph selected by the `path_prefix` query parameter.
+pub async fn serve_discard(
+    req: HttpRequest,
+    token: web::Data<AdminToken>,
+    app_data: web::Data<State>,
+) -> HttpResponse {
+    update_slot(&req, &token, &app_data, Staging::discard, "discarded")
+}
+
+fn update_slot(
+    req: &HttpRequest,
+    token: &AdminToken,
+    state: &State,
+    update: fn(&Staging) -> bool,
+    action: &str,
+) -> HttpResponse {
+    if let Err(response) = token.check(req) {
+        return response;
+    }
+
+    let state = match find_graph(req, state) {
+        Ok(state) => state,
+        Err(response) => return response,
+    };
+    match state.staging() {
+        Some(staging) if update(staging) => {
+            info!(
+                "staged graph of '{}' {} by admin request",
+                state.path_prefix(),
+                action
+            );
+            HttpResponse::Accepted().finish()
+        }
+        Some(_) => HttpResponse::NotFound().body("no staged graph"),
+        None => HttpResponse::NotFound().body("staging is disabled"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::GraphStats;
+    use cincinnati::testing::generate_custom_graph;
+    use std::thread;
+
+    fn built(releases: usize, edges: Vec<(usize, usize)>) -> BuiltGraph {
+        let graph = generate_custom_graph(
+            "image",
+            (0..releases).map(|i| (i, Default::default())).collect(),
+            Some(edges),
+        );
+        BuiltGraph {
+            json: serde_json::to_string(&graph).unwrap(),
+            stats: GraphStats {
+                releases: graph.releases_count(),
+                edges: graph.edges_count(),
+            },
+            graph,
+            secondary_metadata: None,
+            graph_data: None,
+        }
+    }
+
+    #[test]
+    fn validate_rules() {
+        let served = built(3, vec![(0, 1), (1, 2)]).graph;
+        let rules = StagingRules {
+            min_releases: 2,
+            min_edges: 1,
+            max_removed_releases: Some(0),
+            max_removed_edges: Some(1),
+        };
+
+        assert!(rules.validate(&served, &served).is_empty());
+        assert!(rules
+            .validate(&served, &built(4, vec![(0, 1), (0, 3)]).graph)
+            .is_empty());
+        assert_eq!(
+            rules.validate(&served, &built(2, vec![]).graph),
+            vec![
+                "0 edges, expected at least 1".to_string(),
+                "1 releases removed, expected at most 0".to_string(),
+                "2 edges removed, expected at most 1".to_string(),
+            ]
+        );
+        assert!(StagingRules::default()
+            .validate(&served, &Default::default())
+            .is_empty());
+    }
+
+    #[test]
+    fn admit_valid_graphs() {
+        let served = built(2, vec![(0, 1)]).graph;
+        let rules = StagingRules {
+            max_removed_releases: Some(0),
+            ..Default::default()
+        };
+
+        let staging = Staging::new(rules.clone(), true);
+        assert!(staging.admit(&served, built(3, vec![(0, 1)])).is_some());
+        assert!(staging.staged().is_none());
+        assert!(staging.admit(&served, built(1, vec![])).is_none());
+        assert_eq!(staging.staged().unwrap().releases, 1);
+
+        let staging = Staging::new(rules, false);
+        assert!(staging.admit(&served, built(3, vec![(0, 1)])).is_none());
+        let staged = staging.staged().unwrap();
+        assert_eq!(staged.releases, 3);
+        assert!(staged.violations.is_empty());
+    }
+
+    #[test]
+    fn promote_staged_graphs() {
+        let served = built(2, vec![(0, 1)]).graph;
+        let staging = Staging::new(Default::default(), false);
+        assert!(!staging.promote());
+        assert!(!staging.discard());
+
+        staging.admit(&served, built(3, vec![]));
+        assert!(staging.discard());
+        assert!(staging.take_promoted().is_none());
+
+        staging.admit(&served, built(3, vec![]));
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                assert!(staging.promote());
+            });
+            let promoted = staging.wait_promoted(Duration::from_secs(30));
+            assert_eq!(promoted.map(|built| built.stats.releases), Some(3));
+        });
+        assert!(staging.staged().is_none());
+        assert!(staging.wait_promoted(Duration::from_millis(10)).is_none());
+    }
+}
@@ -0,0 +1,162 @@
+//! State store in a local directory.
+
+use super::{validate_key, StateStore, StateValue};
+use commons::prelude_errors::*;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// State store keeping each value in a file, at the path of its key
+/// relative to a directory.
+#[derive(Debug)]
+pub struct DiskStore {
+    dir: PathBuf,
+}
+
+impl DiskStore {
+    /// Open the store in `dir`, creating it if needed.
+    pub fn open(dir: &Path) -> Fallible<Self> {
+        std::fs::create_dir_all(dir)
+            .context(format!("creating state directory {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn path(&self, key: &str) -> Fallible<PathBuf> {
+        validate_key(key)?;
+        Ok(self.dir.join(key))
+    }
+
+    /// Collect the keys of the files under `dir` into `keys`.
+    fn walk(&self, dir: &Path, keys: &mut Vec<String>) -> Fallible<()> {
+        for dir_entry in
+            std::fs::read_dir(dir).context(format!("listing state directory {}", dir.display()))?
+        {
+            let dir_entry = dir_entry?;
+            let path = dir_entry.path();
+            // Temporary files of ongoing writes are hidden.
+            if dir_entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            if dir_entry.file_type()?.is_dir() {
+                self.walk(&path, keys)?;
+            } else if let Some(key) = path
+                .strip_prefix(&self.dir)
+                .ok()
+                .and_then(|relative| relative.to_str())
+            {
+                keys.push(key.replace(std::path::MAIN_SEPARATOR, "/"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StateStore for DiskStore {
+    fn get(&self, key: &str) -> Fallible<Option<Vec<u8>>> {
+        let path = self.path(key)?;
+        match std::fs::read(&path) {
+            Ok(content) => Ok(Some(content)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::from(err).context(format!("reading {}", path.display()))),
+        }
+    }
+
+    fn get_mapped(&self, key: &str) -> Fallible<Option<StateValue>> {
+        let path = self.path(key)?;
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(Error::from(err).context(format!("opening {}", path.display()))),
+        };
+        // Safety: files are never modified in place, only replaced or removed.
+        let map =
+            unsafe { memmap2::Mmap::map(&file) }.context(format!("mapping {}", path.display()))?;
+        Ok(Some(StateValue::Mapped(map)))
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> Fallible<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .context(format!("creating state directory {}", parent.display()))?;
+        }
+        write_atomically(&path, value)
+    }
+
+    fn delete(&self, key: &str) -> Fallible<()> {
+        let path = self.path(key)?;
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(Error::from(err).context(format!("removing {}", path.display())))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Fallible<Vec<String>> {
+        let mut keys = Vec::new();
+        self.walk(&self.dir, &mut keys)?;
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+/// Write `content` to `path`, so that readers never see a partial file.
+///
+/// The temporary file is created next to `path`, in the current directory
+/// for bare file names, so that it is renamed within the same filesystem.
+pub(crate) fn write_atomically(path: &Path, content: &[u8]) -> Fallible<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut file = tempfile::Builder::new()
+        .prefix(".tmp")
+        .tempfile_in(dir)
+        .context(format!("creating temporary file in {}", dir.display()))?;
+    file.write_all(content)?;
+    file.persist(path)
+        .context(format!("writing {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_files() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let store = DiskStore::open(dir.path())?;
+
+        assert_eq!(store.get("snapshots/index.json")?, None);
+        store.put("snapshots/index.json", b"{}")?;
+        store.put("snapshots/a.json.zst", b"a")?;
+        store.put("changelog.json", b"[]")?;
+        assert_eq!(
+            std::fs::read(dir.path().join("snapshots").join("index.json"))?,
+            b"{}"
+        );
+        assert_eq!(store.get("snapshots/a.json.zst")?, Some(b"a".to_vec()));
+        let mapped = store.get_mapped("snapshots/a.json.zst")?.unwrap();
+        assert!(matches!(mapped, StateValue::Mapped(_)));
+        assert_eq!(&mapped[..], b"a");
+        assert_eq!(
+            store.list("snapshots/")?,
+            vec![
+                "snapshots/a.json.zst".to_string(),
+                "snapshots/index.json".to_string()
+            ]
+        );
+
+        store.delete("snapshots/a.json.zst")?;
+        store.delete("snapshots/a.json.zst")?;
+        assert_eq!(store.get("snapshots/a.json.zst")?, None);
+        assert!(store.get_mapped("snapshots/a.json.zst")?.is_none());
+        assert!(store.get("../secret").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+//! Storage of the persisted state of the graph-builder.
+//!
+//! Persisted state, i.e. the graph snapshots and the changelog, is kept as
+//! opaque values under `/`-separated keys of a `StateStore`, so that the
+//! features persisting it do not depend on where it lives. The store is a
+//! local directory, an S3 bucket or a Redis database, selected by the
+//! `state` configuration section. Each feature keeps its keys under a
+//! prefix of its own, e.g. `snapshots/`.
+//!
+//! The release metadata cache of the scrape plugins is deliberately not part
+//! of the persisted state: it is keyed by manifest digest, only saves the
+//! layer downloads of releases which were already scraped, and is refilled
+//! by the first scrape of each process, whereas the state store holds what
+//! must survive a restart.
+
+mod disk;
+mod redis;
+mod s3;
+
+pub(crate) use self::disk::write_atomically;
+pub use self::disk::DiskStore;
+pub use self::redis::RedisStore;
+pub use self::s3::S3Store;
+
+use crate::config::AppSettings;
+use commons::prelude_errors::*;
+use std::sync::Arc;
+
+/// Key of the changelog in the state store.
+pub static CHANGELOG_KEY: &str = "changelog.json";
+
+/// Prefix of the keys of the graph snapshots in the state store.
+pub static SNAPSHOTS_PREFIX: &str = "snapshots";
+
+/// Default region of S3 buckets.
+pub static DEFAULT_S3_REGION: &str = "us-east-1";
+
+/// Store of persisted state, by key.
+///
+/// Keys are non-empty `/`-separated segments, none of which is empty, `.`
+/// or `..`. Operations block the calling thread, and must not be called
+/// from asynchronous tasks.
+pub trait StateStore: std::fmt::Debug + Send + Sync {
+    /// Return the value stored under `key`, if any.
+    fn get(&self, key: &str) -> Fallible<Option<Vec<u8>>>;
+
+    /// Return the value stored under `key` for reading, if any.
+    ///
+    /// Stores which can map values in memory do so instead of reading them,
+    /// e.g. for large snapshots which are only decompressed.
+    fn get_mapped(&self, key: &str) -> Fallible<Option<StateValue>> {
+        Ok(self.get(key)?.map(StateValue::Read))
+    }
+
+    /// Store `value` under `key`, so that readers never see a partial value.
+    fn put(&self, key: &str, value: &[u8]) -> Fallible<()>;
+
+    /// Remove the value stored under `key`, if any.
+    fn delete(&self, key: &str) -> Fallible<()>;
+
+    /// List the keys starting with `prefix`, sorted.
+    fn list(&self, prefix: &str) -> Fallible<Vec<String>>;
+}
+
+/// Value of a state store, either read or mapped in memory.
+pub enum StateValue {
+    /// Value read into memory.
+    Read(Vec<u8>),
+    /// File mapped in memory.
+    Mapped(memmap2::Mmap),
+}
+
+impl std::ops::Deref for StateValue {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            StateValue::Read(value) => value,
+            StateValue::Mapped(map) => map,
+        }
+    }
+}
+
+/// Supported state store backends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateBackend {
+    /// Files in a local directory.
+    Disk,
+    /// Objects in an S3 bucket.
+    S3,
+    /// Strings in a Redis database.
+    Redis,
+}
+
+impl std::str::FromStr for StateBackend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "disk" => Ok(StateBackend::Disk),
+            "s3" => Ok(StateBackend::S3),
+            "redis" => Ok(StateBackend::Redis),
+            _ => bail!("unknown state store backend '{}'", s),
+        }
+    }
+}
+
+/// Open the state store configured by `settings`, if any.
+pub fn from_settings(settings: &AppSettings) -> Fallible<Option<Arc<dyn StateStore>>> {
+    let backend: StateBackend = match &settings.state_backend {
+        Some(backend) => backend.parse()?,
+        None => return Ok(None),
+    };
+
+    let store: Arc<dyn StateStore> = match backend {
+        StateBackend::Disk => {
+            let dir = settings
+                .state_dir
+                .as_deref()
+                .ok_or_else(|| format_err!("the disk state store requires a directory"))?;
+            Arc::new(DiskStore::open(dir)?)
+        }
+        StateBackend::S3 => {
+            let url = settings
+                .state_url
+                .as_deref()
+                .ok_or_else(|| format_err!("the S3 state store requires a bucket URL"))?;
+            Arc::new(S3Store::try_new(
+                url,
+                &settings.state_region,
+                s3::Credentials::from_env()
+                    .context("reading S3 credentials from the environment")?,
+            )?)
+        }
+        StateBackend::Redis => {
+            let url = settings
+                .state_url
+                .as_deref()
+                .ok_or_else(|| format_err!("the Redis state store requires a server URL"))?;
+            Arc::new(RedisStore::try_new(url)?)
+        }
+    };
+    info!("persisting state in {:?}", store);
+
+    Ok(Some(scoped(store, &settings.state_prefix)))
+}
+
+/// Return a view of `store` with the keys under `prefix`, without it.
+pub fn scoped(store: Arc<dyn StateStore>, prefix: &str) -> Arc<dyn StateStore> {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        return store;
+    }
+    Arc::new(ScopedStore {
+        inner: store,
+        prefix: format!("{}/", prefix),
+    })
+}
+
+/// View of a store with the keys under a prefix.
+#[derive(Debug)]
+struct ScopedStore {
+    inner: Arc<dyn StateStore>,
+    /// Prefix, ending with `/`.
+    prefix: String,
+}
+
+impl StateStore for ScopedStore {
+    fn get(&self, key: &str) -> Fallible<Option<Vec<u8>>> {
+        self.inner.get(&format!("{}{}", self.prefix, key))
+    }
+
+    fn get_mapped(&self, key: &str) -> Fallible<Option<StateValue>> {
+        self.inner.get_mapped(&format!("{}{}", self.prefix, key))
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> Fallible<()> {
+        self.inner.put(&format!("{}{}", self.prefix, key), value)
+    }
+
+    fn delete(&self, key: &str) -> Fallible<()> {
+        self.inner.delete(&format!("{}{}", self.prefix, key))
+    }
+
+    fn list(&self, prefix: &str) -> Fallible<Vec<String>> {
+        Ok(self
+            .inner
+            .list(&format!("{}{}", self.prefix, prefix))?
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&self.prefix).map(str::to_string))
+            .collect())
+    }
+}
+
+/// Check that `key` is a valid key.
+pub(crate) fn validate_key(key: &str) -> Fallible<()> {
+    ensure!(
+        !key.is_empty()
+            && !key.contains('\\')
+            && key
+                .split('/')
+                .all(|segment| !segment.is_empty() && segment != "." && segment != ".."),
+        "invalid state key '{}'",
+        key
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_backends() {
+        assert_eq!("disk".parse::<StateBackend>().unwrap(), StateBackend::Disk);
+        assert_eq!("s3".parse::<StateBackend>().unwrap(), StateBackend::S3);
+        assert_eq!(
+            "redis".parse::<StateBackend>().unwrap(),
+            StateBackend::Redis
+        );
+        assert!("etcd".parse::<StateBackend>().is_err());
+    }
+
+    #[test]
+    fn validate_keys() {
+        for valid in &["changelog.json", "snapshots/index.json", "a/b/c"] {
+            assert!(validate_key(valid).is_ok(), "{}", valid);
+        }
+        for invalid in &[
+            "",
+            "/index.json",
+            "snapshots/",
+            "a//b",
+            "../index",
+            "a/./b",
+            "a\\b",
+        ] {
+            assert!(validate_key(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn scope_keys() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let store: Arc<dyn StateStore> = Arc::new(DiskStore::open(dir.path())?);
+        let snapshots = scoped(store.clone(), "/snapshots/");
+
+        snapshots.put("index.json", b"{}")?;
+        store.put("changelog.json", b"[]")?;
+        assert_eq!(store.get("snapshots/index.json")?, Some(b"{}".to_vec()));
+        assert_eq!(snapshots.list("")?, vec!["index.json".to_string()]);
+        assert_eq!(snapshots.get("changelog.json")?, None);
+
+        snapshots.delete("index.json")?;
+        assert_eq!(store.list("")?, vec!["changelog.json".to_string()]);
+
+        Ok(())
+    }
+}
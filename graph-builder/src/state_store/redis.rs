@@ -0,0 +1,171 @@
+//! State store in a Redis database.
+//!
+//! Values are Redis strings, accessed with the `redis` client through a
+//! connection opened for each operation. The URL selects the server, the
+//! database and the password, e.g. `redis://:<password>@redis:6379/2`, where
+//! reserved characters of the password are percent-encoded.
+
+use super::{validate_key, StateStore};
+use commons::prelude_errors::*;
+use redis::Commands;
+use std::time::Duration;
+
+/// Timeout of a single operation.
+static OPERATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of keys scanned per round trip when listing keys.
+static SCAN_COUNT: usize = 1000;
+
+/// State store keeping each value in a string of a Redis database.
+pub struct RedisStore {
+    client: redis::Client,
+}
+
+impl std::fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let info = self.client.get_connection_info();
+        f.debug_struct("RedisStore")
+            .field("address", &info.addr.to_string())
+            .field("database", &info.redis.db)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisStore {
+    /// Create a store in the database of the server at `url`.
+    pub fn try_new(url: &str) -> Fallible<Self> {
+        let parsed = url::Url::parse(url).context(format!("invalid Redis URL '{}'", url))?;
+        ensure!(
+            parsed.scheme() == "redis" || parsed.scheme() == "rediss",
+            "unsupported Redis URL scheme '{}'",
+            parsed.scheme()
+        );
+        // The client percent-decodes the credentials of the URL.
+        let client = redis::Client::open(url)
+            .map_err(|e| format_err!("invalid Redis URL '{}': {}", parsed, e))?;
+
+        Ok(Self { client })
+    }
+
+    fn connect(&self) -> Fallible<redis::Connection> {
+        let connection = self
+            .client
+            .get_connection_with_timeout(OPERATION_TIMEOUT)
+            .context(format!(
+                "connecting to Redis server {}",
+                self.client.get_connection_info().addr
+            ))?;
+        connection.set_read_timeout(Some(OPERATION_TIMEOUT))?;
+        connection.set_write_timeout(Some(OPERATION_TIMEOUT))?;
+        Ok(connection)
+    }
+}
+
+impl StateStore for RedisStore {
+    fn get(&self, key: &str) -> Fallible<Option<Vec<u8>>> {
+        validate_key(key)?;
+        let value = self
+            .connect()?
+            .get::<_, Option<Vec<u8>>>(key)
+            .context(format!("fetching Redis key '{}'", key))?;
+        Ok(value)
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> Fallible<()> {
+        validate_key(key)?;
+        self.connect()?
+            .set::<_, _, ()>(key, value)
+            .context(format!("storing Redis key '{}'", key))?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Fallible<()> {
+        validate_key(key)?;
+        self.connect()?
+            .del::<_, ()>(key)
+            .context(format!("removing Redis key '{}'", key))?;
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Fallible<Vec<String>> {
+        let mut pattern = String::new();
+        for c in prefix.chars() {
+            if "*?[]\\".contains(c) {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+        pattern.push('*');
+
+        let mut connection = self.connect()?;
+        let mut keys: Vec<String> = redis::cmd("SCAN")
+            .cursor_arg(0)
+            .arg("MATCH")
+            .arg(&pattern)
+            .arg("COUNT")
+            .arg(SCAN_COUNT)
+            .iter(&mut connection)
+            .context(format!("listing Redis keys under '{}'", prefix))?
+            .collect();
+
+        // Keys may be returned more than once while the database changes.
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn parse_server_urls() -> Fallible<()> {
+        let store = RedisStore::try_new("redis://:s3cr3t@redis.example.com/2")?;
+        let info = store.client.get_connection_info();
+        assert_eq!(info.addr.to_string(), "redis.example.com:6379");
+        assert_eq!(info.redis.db, 2);
+        assert_eq!(info.redis.password.as_deref(), Some("s3cr3t"));
+        assert!(!format!("{:?}", store).contains("s3cr3t"));
+
+        // Reserved characters of the password are percent-decoded.
+        let store = RedisStore::try_new("redis://:p%40ss%3Aw%2Frd@localhost:6380")?;
+        let info = store.client.get_connection_info();
+        assert_eq!(info.redis.password.as_deref(), Some("p@ss:w/rd"));
+        assert_eq!(info.redis.db, 0);
+
+        assert!(RedisStore::try_new("http://localhost:6379").is_err());
+        assert!(RedisStore::try_new("redis://localhost/db").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn send_commands() -> Fallible<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("redis://:s3cr3t@{}/1", listener.local_addr()?);
+        let server = std::thread::spawn(move || -> Fallible<Vec<u8>> {
+            let (mut stream, _) = listener.accept()?;
+            stream.set_read_timeout(Some(OPERATION_TIMEOUT))?;
+            stream.write_all(b"+OK\r\n+OK\r\n$2\r\n{}\r\n")?;
+            let mut received = Vec::new();
+            // The client closes the connection once the reply is read.
+            stream.read_to_end(&mut received)?;
+            Ok(received)
+        });
+
+        let store = RedisStore::try_new(&url)?;
+        assert_eq!(store.get("snapshots/index.json")?, Some(b"{}".to_vec()));
+        let received = server.join().unwrap()?;
+        assert_eq!(
+            String::from_utf8(received)?,
+            "*2\r\n$4\r\nAUTH\r\n$6\r\ns3cr3t\r\n\
+             *2\r\n$6\r\nSELECT\r\n$1\r\n1\r\n\
+             *2\r\n$3\r\nGET\r\n$20\r\nsnapshots/index.json\r\n"
+        );
+
+        Ok(())
+    }
+}
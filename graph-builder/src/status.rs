@@ -1,12 +1,17 @@
 //! Status service.
 
-use crate::graph::State;
-use actix_web::HttpResponse;
+use crate::graph::{Condition, State};
+use crate::self_check::SelfCheckReport;
+use actix_web::{HttpRequest, HttpResponse};
+use cincinnati::plugins::internal::release_scrape_dockerv2::tags;
+use commons::metrics::HasRegistry;
+use std::collections::BTreeMap;
+use std::fmt::Write;
 
 /// Expose liveness status.
 ///
 /// Status:
-///  * Live (200 code): The upstream scrape loop thread is running
+///  * Live (200 code): The upstream scrape loop threads of all tenants are running
 ///  * Not Live (503 code): everything else.
 pub async fn serve_liveness(app_data: actix_web::web::Data<State>) -> HttpResponse {
     if app_data.is_live() {
@@ -19,7 +24,8 @@ pub async fn serve_liveness(app_data: actix_web::web::Data<State>) -> HttpRespon
 /// Expose readiness status.
 ///
 /// Status:
-///  * Ready (200 code): a JSON graph as the result of a successful scrape is available.
+///  * Ready (200 code): a JSON graph as the result of a successful scrape is available,
+///    for all tenants.
 ///  * Not Ready (503 code): no JSON graph available yet.
 pub async fn serve_readiness(app_data: actix_web::web::Data<State>) -> HttpResponse {
     if app_data.is_ready() {
@@ -28,3 +34,458 @@ pub async fn serve_readiness(app_data: actix_web::web::Data<State>) -> HttpRespo
         HttpResponse::ServiceUnavailable().finish()
     }
 }
+
+/// Status of a graph.
+#[derive(Debug, Serialize)]
+struct GraphStatus {
+    path_prefix: String,
+    conditions: Vec<Condition>,
+    /// Seconds spent in each phase of the latest build, by phase.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_build_phases: Option<BTreeMap<&'static str, f64>>,
+    /// Stable code of the error which failed the latest scrape.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_scrape_error_code: Option<String>,
+    /// Outcome of the latest self-check, if enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_self_check: Option<SelfCheckReport>,
+}
+
+impl GraphStatus {
+    fn new(state: &State) -> Self {
+        Self {
+            path_prefix: state.path_prefix().to_string(),
+            conditions: state.conditions(),
+            last_build_phases: state.last_scrape().map(|report| {
+                report
+                    .phases
+                    .iter()
+                    .map(|(phase, duration)| (phase.as_str(), duration.as_secs_f64()))
+                    .collect()
+            }),
+            last_scrape_error_code: state.last_scrape().and_then(|report| report.error_code),
+            last_self_check: state.last_self_check(),
+        }
+    }
+}
+
+/// Expose the conditions of the default graph and of all tenants, as JSON.
+///
+/// The served graphs are unaffected by the conditions, which only flag that
+/// the latest scrapes did not refresh them.
+pub async fn serve_status(app_data: actix_web::web::Data<State>) -> HttpResponse {
+    let statuses: Vec<GraphStatus> = std::iter::once(app_data.get_ref())
+        .chain(app_data.tenants())
+        .map(GraphStatus::new)
+        .collect();
+    HttpResponse::Ok().json(statuses)
+}
+
+/// Version of the service.
+#[derive(Debug, Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    git_commit: Option<&'static str>,
+}
+
+/// Expose the version of the service, as JSON.
+pub async fn serve_version() -> HttpResponse {
+    HttpResponse::Ok().json(VersionInfo {
+        version: crate::built_info::PKG_VERSION,
+        git_commit: crate::built_info::GIT_COMMIT_HASH,
+    })
+}
+
+/// Serve a read-only HTML page summarizing the default graph and all tenants.
+pub async fn serve_status_page(app_data: actix_web::web::Data<State>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(render_status_page(&app_data))
+}
+
+/// Render the status page of `state` and its tenants.
+fn render_status_page(state: &State) -> String {
+    let mut page = String::from(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+        "<title>graph-builder status</title>\n",
+        "<style>body{font-family:sans-serif} table{border-collapse:collapse} ",
+        "td,th{border:1px solid #ccc;padding:2px 8px;text-align:left} ",
+        ".ok{color:green} .failed{color:red} .skipped{color:gray}</style>\n",
+        "</head>\n<body>\n<h1>graph-builder status</h1>\n",
+    ));
+    for graph in std::iter::once(state).chain(state.tenants()) {
+        // Writing to a String cannot fail.
+        let _ = render_graph_section(&mut page, graph);
+    }
+    page.push_str("</body>\n</html>\n");
+    page
+}
+
+/// Render the section of a single graph.
+fn render_graph_section(page: &mut String, state: &State) -> std::fmt::Result {
+    let path_prefix = match state.path_prefix() {
+        "" => "/",
+        path_prefix => path_prefix,
+    };
+    writeln!(page, "<h2>Graph {}</h2>", escape_html(path_prefix))?;
+
+    match state.graph_stats() {
+        Some(stats) => writeln!(
+            page,
+            "<p>Serving {} releases and {} edges.</p>",
+            stats.releases, stats.edges
+        )?,
+        None => writeln!(page, "<p>No graph built yet.</p>")?,
+    }
+    for condition in state.conditions() {
+        writeln!(
+            page,
+            "<p class=\"failed\">{}: {}</p>",
+            condition.kind,
+            escape_html(&condition.message)
+        )?;
+    }
+
+    let last_scrape = state.last_scrape();
+    match &last_scrape {
+        Some(report) => {
+            let outcome = match &report.error {
+                Some(error) => format!("<span class=\"failed\">{}</span>", escape_html(error)),
+                None => "<span class=\"ok\">succeeded</span>".to_string(),
+            };
+            writeln!(
+                page,
+                "<p>Last scrape at {} took {} ms: {}</p>",
+                commons::http_date(report.finished),
+                report.duration.as_millis(),
+                outcome
+            )?;
+            let phases: Vec<String> = report
+                .phases
+                .iter()
+                .map(|(phase, duration)| format!("{} {} ms", phase.as_str(), duration.as_millis()))
+                .collect();
+            if !phases.is_empty() {
+                writeln!(page, "<p>Build phases: {}</p>", phases.join(", "))?;
+            }
+        }
+        None => writeln!(page, "<p>No scrape completed yet.</p>")?,
+    }
+
+    writeln!(page, "<h3>Plugins</h3>\n<table>")?;
+    writeln!(
+        page,
+        "<tr><th>Plugin</th><th>Health</th><th>Duration</th></tr>"
+    )?;
+    let runs = last_scrape.map(|report| report.plugins).unwrap_or_default();
+    for (i, name) in state.plugin_names().into_iter().enumerate() {
+        let (health, duration) = match runs.get(i) {
+            Some(run) => {
+                let health = match &run.error {
+                    Some(error) => format!("<span class=\"failed\">{}</span>", escape_html(error)),
+                    None => "<span class=\"ok\">ok</span>".to_string(),
+                };
+                (health, format!("{} ms", run.duration.as_millis()))
+            }
+            None => (
+                "<span class=\"skipped\">not run</span>".to_string(),
+                String::new(),
+            ),
+        };
+        writeln!(
+            page,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(name),
+            health,
+            duration
+        )?;
+    }
+    writeln!(page, "</table>")?;
+
+    let recent_errors = state.recent_errors();
+    if !recent_errors.is_empty() {
+        writeln!(page, "<h3>Recent errors</h3>\n<ul>")?;
+        for (time, error) in recent_errors.iter().rev() {
+            writeln!(
+                page,
+                "<li>{}: {}</li>",
+                commons::http_date(*time),
+                escape_html(error)
+            )?;
+        }
+        writeln!(page, "</ul>")?;
+    }
+
+    Ok(())
+}
+
+/// Escape text for inclusion in HTML.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Bearer token required by the debug endpoints.
+pub use commons::auth::BearerToken as DebugToken;
+
+/// Tags seen by the latest successful scrape of a repository of a graph.
+#[derive(Debug, Serialize)]
+struct GraphTagListing {
+    path_prefix: String,
+    #[serde(flatten)]
+    listing: tags::TagListing,
+}
+
+/// Serve the disposition of every tag seen by the latest scrape of each
+/// repository of the default graph and of all tenants, as JSON.
+///
+/// Requests must present the configured `DebugToken` as a bearer token.
+pub async fn serve_debug_tags(
+    req: HttpRequest,
+    token: actix_web::web::Data<DebugToken>,
+    app_data: actix_web::web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = token.check(&req) {
+        return response;
+    }
+    let listings: Vec<GraphTagListing> = std::iter::once(app_data.get_ref())
+        .chain(app_data.tenants())
+        .flat_map(|state| {
+            state
+                .tag_listings()
+                .into_iter()
+                .map(move |listing| GraphTagListing {
+                    path_prefix: state.path_prefix().to_string(),
+                    listing,
+                })
+        })
+        .collect();
+    HttpResponse::Ok().json(listings)
+}
+
+/// Serve metrics requests (Prometheus textual format).
+///
+/// Metrics of all tenants are served along with the ones of the default graph.
+pub async fn serve_metrics(app_data: actix_web::web::Data<State>) -> HttpResponse {
+    use prometheus::Encoder;
+
+    let metrics = gather_metrics(&app_data);
+    let tenc = prometheus::TextEncoder::new();
+    let mut buf = vec![];
+    match tenc.encode(&metrics, &mut buf) {
+        Ok(()) => HttpResponse::Ok().body(buf),
+        Err(e) => HttpResponse::InternalServerError().body(format!("{}", e)),
+    }
+}
+
+/// Gather the metrics of `state` and its tenants.
+///
+/// Tenant registries may hold metrics with the same names, which are merged
+/// into a single family as the text format allows only one per name.
+fn gather_metrics(state: &State) -> Vec<prometheus::proto::MetricFamily> {
+    let mut families = state.registry().gather();
+
+    for tenant in state.tenants() {
+        for mut family in tenant.registry().gather() {
+            match families
+                .iter_mut()
+                .find(|existing| existing.get_name() == family.get_name())
+            {
+                Some(existing) => {
+                    for metric in family.take_metric() {
+                        existing.mut_metric().push(metric);
+                    }
+                }
+                None => families.push(family),
+            }
+        }
+    }
+
+    families
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header;
+    use commons::metrics::new_registry;
+    use prometheus::{labels, IntGauge};
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn gather_tenant_metrics() -> commons::Fallible<()> {
+        let registry = Box::leak(Box::new(new_registry(Some("test".to_string()))?));
+        let gauge = IntGauge::new("releases", "Number of releases")?;
+        gauge.set(1);
+        registry.register(Box::new(gauge))?;
+
+        let tenants = ["ocp", "okd"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let registry = Box::leak(Box::new(prometheus::Registry::new_custom(
+                    Some("test".to_string()),
+                    Some(labels! { "tenant".to_string() => name.to_string() }),
+                )?));
+                let gauge = IntGauge::new("releases", "Number of releases")?;
+                gauge.set(i as i64 + 2);
+                registry.register(Box::new(gauge))?;
+                State::try_new_tenant(
+                    format!("/{}", name),
+                    HashSet::new(),
+                    Box::leak(Box::new([])),
+                    registry,
+                )
+            })
+            .collect::<commons::Fallible<Vec<_>>>()?;
+
+        let state = State::try_new_tenant(
+            String::new(),
+            HashSet::new(),
+            Box::leak(Box::new([])),
+            registry,
+        )?
+        .with_tenants(tenants);
+
+        let families = gather_metrics(&state);
+        let releases: Vec<_> = families
+            .iter()
+            .filter(|family| family.get_name() == "test_releases")
+            .collect();
+        assert_eq!(releases.len(), 1);
+
+        let values: HashMap<String, f64> = releases[0]
+            .get_metric()
+            .iter()
+            .map(|metric| {
+                let tenant = metric
+                    .get_label()
+                    .iter()
+                    .find(|label| label.get_name() == "tenant")
+                    .map(|label| label.get_value().to_string())
+                    .unwrap_or_default();
+                (tenant, metric.get_gauge().get_value())
+            })
+            .collect();
+        assert_eq!(values[""], 1.0);
+        assert_eq!(values["ocp"], 2.0);
+        assert_eq!(values["okd"], 3.0);
+
+        let scrapes = families
+            .iter()
+            .find(|family| family.get_name() == "test_graph_upstream_scrapes_total")
+            .expect("missing scrape metrics");
+        assert_eq!(scrapes.get_metric().len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn debug_tags_require_token() -> commons::Fallible<()> {
+        use actix_web::test::TestRequest;
+
+        let rt = commons::testing::init_runtime()?;
+        let token = actix_web::web::Data::new(DebugToken::new("s3cr3t"));
+        let state = actix_web::web::Data::new(State::try_new_tenant(
+            String::new(),
+            HashSet::new(),
+            Box::leak(Box::new([])),
+            Box::leak(Box::new(new_registry(Some("test".to_string()))?)),
+        )?);
+
+        for (authorization, expected) in &[
+            (None, 401),
+            (Some("Bearer wrong"), 401),
+            (Some("Basic s3cr3t"), 401),
+            (Some("Bearer s3cr3t"), 200),
+        ] {
+            let mut req = TestRequest::get().uri("/debug/tags");
+            if let Some(authorization) = authorization {
+                req = req.insert_header((header::AUTHORIZATION, *authorization));
+            }
+            let resp = rt.block_on(serve_debug_tags(
+                req.to_http_request(),
+                token.clone(),
+                state.clone(),
+            ));
+            assert_eq!(resp.status(), *expected, "{:?}", authorization);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_plugin_health() -> commons::Fallible<()> {
+        use crate::graph::ScrapeReport;
+        use cincinnati::plugins::internal::build_phases::{BuildPhase, PhaseTimings};
+        use cincinnati::plugins::prelude::*;
+        use cincinnati::plugins::PluginRun;
+        use std::time::{Duration, SystemTime};
+
+        let registry = Box::leak(Box::new(new_registry(Some("test".to_string()))?));
+        let plugins = vec![
+            EdgeAddRemovePlugin::default().build_plugin(None)?,
+            NodeRemovePlugin::default().build_plugin(None)?,
+        ];
+        let state = State::try_new_tenant(
+            "/okd".to_string(),
+            HashSet::new(),
+            Box::leak(plugins.into_boxed_slice()),
+            registry,
+        )?;
+
+        let page = render_status_page(&state);
+        assert!(page.contains("No scrape completed yet."));
+
+        let mut phases = PhaseTimings::default();
+        phases.add(BuildPhase::TagListing, Duration::from_millis(12));
+        phases.add(BuildPhase::PluginChain, Duration::from_millis(42));
+        let report = ScrapeReport {
+            finished: SystemTime::UNIX_EPOCH,
+            duration: Duration::from_millis(42),
+            error: None,
+            error_code: Some("plugin.plugin_failed".to_string()),
+            plugins: vec![PluginRun {
+                name: "edge-add-remove",
+                duration: Duration::from_millis(40),
+                error: Some("invalid <edge>".to_string()),
+            }],
+            phases,
+        };
+        state.record_scrape(report, Some("invalid <edge>".to_string()));
+
+        let page = render_status_page(&state);
+        assert!(page.contains("<h2>Graph /okd</h2>"));
+        assert!(page.contains("took 42 ms"));
+        assert!(page.contains("<p>Build phases: tag_listing 12 ms, plugin_chain 42 ms</p>"));
+        assert_eq!(
+            serde_json::to_value(GraphStatus::new(&state))?["last_build_phases"],
+            serde_json::json!({"tag_listing": 0.012, "plugin_chain": 0.042})
+        );
+        assert_eq!(
+            serde_json::to_value(GraphStatus::new(&state))?["last_scrape_error_code"],
+            "plugin.plugin_failed"
+        );
+        assert!(page.contains(
+            "<tr><td>edge-add-remove</td><td><span class=\"failed\">invalid &lt;edge&gt;</span></td><td>40 ms</td></tr>"
+        ));
+        assert!(page.contains(
+            "<tr><td>node-remove</td><td><span class=\"skipped\">not run</span></td><td></td></tr>"
+        ));
+        assert!(page.contains("<li>Thu, 01 Jan 1970 00:00:00 GMT: invalid &lt;edge&gt;</li>"));
+        assert!(!page.contains("<edge>"));
+
+        Ok(())
+    }
+}
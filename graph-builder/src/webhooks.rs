@@ -0,0 +1,433 @@
+//! Outbound webhook notifications for channel changes.
+//!
+//! After each scrape, the per-channel delta between the previous and the
+//! current graph is computed and delivered to every webhook whose channel
+//! filter matches. Payloads are signed with HMAC-SHA256 when a secret is
+//! configured for the webhook.
+//!
+//! Deliveries, and their retries, run on a thread of their own once the new
+//! graph is served, so that slow or failing webhooks never delay scrapes.
+
+use commons::http::RetryPolicy;
+use commons::prelude_errors::*;
+use hmac::{Hmac, Mac};
+use prometheus::{IntCounterVec, Opts};
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Header carrying the payload signature.
+pub static SIGNATURE_HEADER: &str = "x-cincinnati-signature";
+
+/// Timeout for a single delivery attempt.
+static DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Initial pause between delivery attempts, doubled on each retry.
+static RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Upper bound for the pause between delivery attempts.
+static MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Number of scrapes whose notifications may wait for delivery.
+static DELIVERY_QUEUE_CAPACITY: usize = 16;
+
+lazy_static! {
+    static ref WEBHOOK_DELIVERIES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "webhook_deliveries_total",
+            "Total number of webhook notifications delivered"
+        ),
+        &["webhook"]
+    )
+    .unwrap();
+    static ref WEBHOOK_ERRORS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "webhook_errors_total",
+            "Total number of webhook notifications which failed to be delivered"
+        ),
+        &["webhook"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(WEBHOOK_DELIVERIES.clone()))?;
+    registry.register(Box::new(WEBHOOK_ERRORS.clone()))?;
+    Ok(())
+}
+
+/// Configuration of a single webhook.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct WebhookConfig {
+    /// Name of the webhook, used in logs and metrics.
+    pub name: String,
+
+    /// URL to which notifications are POSTed.
+    pub url: String,
+
+    /// Channels for which notifications are delivered; all channels if empty.
+    #[serde(default)]
+    pub channels: Vec<String>,
+
+    /// Path to a file containing the secret used to sign payloads.
+    pub secret_path: Option<PathBuf>,
+
+    /// Maximum number of delivery retries.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+/// Releases added to and removed from a channel.
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
+pub struct ChannelDelta {
+    /// Name of the channel.
+    pub channel: String,
+    /// Versions of the releases which appeared in the channel.
+    pub added_releases: Vec<String>,
+    /// Versions of the releases which disappeared from the channel.
+    pub removed_releases: Vec<String>,
+}
+
+/// Map each channel to the versions of the releases it contains.
+pub fn channel_releases(
+    graph: &cincinnati::Graph,
+    channels_key: &str,
+) -> BTreeMap<String, BTreeSet<String>> {
+    let mut channels: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for (_, version, value) in graph.find_by_metadata_key(channels_key) {
+        for channel in value.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            channels
+                .entry(channel.to_string())
+                .or_default()
+                .insert(version.clone());
+        }
+    }
+    channels
+}
+
+/// Compute the non-empty per-channel deltas going from `old` to `new`.
+pub fn channel_deltas(
+    old: &BTreeMap<String, BTreeSet<String>>,
+    new: &BTreeMap<String, BTreeSet<String>>,
+) -> Vec<ChannelDelta> {
+    let empty = BTreeSet::new();
+    old.keys()
+        .chain(new.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|channel| {
+            let old_releases = old.get(channel).unwrap_or(&empty);
+            let new_releases = new.get(channel).unwrap_or(&empty);
+            ChannelDelta {
+                channel: channel.clone(),
+                added_releases: new_releases.difference(old_releases).cloned().collect(),
+                removed_releases: old_releases.difference(new_releases).cloned().collect(),
+            }
+        })
+        .filter(|delta| !delta.added_releases.is_empty() || !delta.removed_releases.is_empty())
+        .collect()
+}
+
+/// Compute the HMAC-SHA256 of `payload` with `secret`, hex-encoded.
+pub fn sign_payload(secret: &[u8], payload: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(payload);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[derive(Debug, Serialize)]
+struct Notification<'a> {
+    webhook: &'a str,
+    timestamp: i64,
+    #[serde(flatten)]
+    delta: &'a ChannelDelta,
+}
+
+#[derive(Debug)]
+struct Webhook {
+    config: WebhookConfig,
+    url: reqwest::Url,
+    secret: Option<Vec<u8>>,
+}
+
+/// Dispatcher of notifications to all configured webhooks.
+///
+/// Notifications are queued for the delivery thread of the dispatcher.
+#[derive(Debug)]
+pub struct WebhookDispatcher {
+    queue: mpsc::SyncSender<Vec<ChannelDelta>>,
+}
+
+impl WebhookDispatcher {
+    /// Create a dispatcher for the given webhooks, reading their secrets,
+    /// and start its delivery thread.
+    pub fn try_new(configs: &[WebhookConfig]) -> Fallible<Self> {
+        let deliverer = Deliverer::try_new(configs)?;
+        let (queue, deliveries) = mpsc::sync_channel(DELIVERY_QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("webhooks".to_string())
+            .spawn(move || {
+                for deltas in deliveries {
+                    deliverer.dispatch(&deltas);
+                }
+            })
+            .context("starting webhook delivery thread")?;
+
+        Ok(Self { queue })
+    }
+
+    /// Queue the given channel deltas for delivery to all matching webhooks.
+    ///
+    /// Notifications are dropped, and logged, if the queue is full.
+    pub fn dispatch(&self, deltas: Vec<ChannelDelta>) {
+        if deltas.is_empty() {
+            return;
+        }
+        if let Err(err) = self.queue.try_send(deltas) {
+            let deltas = match err {
+                mpsc::TrySendError::Full(deltas) | mpsc::TrySendError::Disconnected(deltas) => {
+                    deltas
+                }
+            };
+            error!(
+                "webhook delivery queue unavailable, dropping notifications for {} channels",
+                deltas.len()
+            );
+        }
+    }
+}
+
+/// Deliverer of notifications, owned by the delivery thread.
+#[derive(Debug)]
+struct Deliverer {
+    client: reqwest::Client,
+    runtime: tokio::runtime::Runtime,
+    webhooks: Vec<Webhook>,
+}
+
+impl Deliverer {
+    fn try_new(configs: &[WebhookConfig]) -> Fallible<Self> {
+        let webhooks = configs
+            .iter()
+            .map(|config| {
+                let url = reqwest::Url::parse(&config.url).context(format!(
+                    "invalid URL '{}' for webhook '{}'",
+                    config.url, config.name
+                ))?;
+                let secret = match &config.secret_path {
+                    Some(path) => Some(
+                        std::fs::read_to_string(path)
+                            .context(format!("reading secret for webhook '{}'", config.name))?
+                            .trim()
+                            .as_bytes()
+                            .to_vec(),
+                    ),
+                    None => None,
+                };
+                Ok(Webhook {
+                    config: config.clone(),
+                    url,
+                    secret,
+                })
+            })
+            .collect::<Fallible<Vec<_>>>()?;
+
+        let client = commons::http::HttpClientBuilder::new()
+            .timeout(DELIVERY_TIMEOUT)
+            .build()
+            .context("building webhook HTTP client")?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("building webhook runtime")?;
+
+        Ok(Self {
+            client,
+            runtime,
+            webhooks,
+        })
+    }
+
+    /// Deliver the given channel deltas to all matching webhooks.
+    ///
+    /// Failures are logged and counted, but do not stop deliveries to other webhooks.
+    fn dispatch(&self, deltas: &[ChannelDelta]) {
+        for webhook in &self.webhooks {
+            let matching = deltas.iter().filter(|delta| {
+                webhook.config.channels.is_empty()
+                    || webhook.config.channels.contains(&delta.channel)
+            });
+            for delta in matching {
+                let name = webhook.config.name.as_str();
+                match self.runtime.block_on(self.deliver(webhook, delta)) {
+                    Ok(()) => WEBHOOK_DELIVERIES.with_label_values(&[name]).inc(),
+                    Err(err) => {
+                        WEBHOOK_ERRORS.with_label_values(&[name]).inc();
+                        error!(
+                            "failed to notify webhook '{}' for channel '{}': {}",
+                            name, delta.channel, err
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    async fn deliver(&self, webhook: &Webhook, delta: &ChannelDelta) -> Fallible<()> {
+        let body = serde_json::to_vec(&Notification {
+            webhook: &webhook.config.name,
+            timestamp: chrono::Utc::now().timestamp(),
+            delta,
+        })?;
+        let signature = webhook
+            .secret
+            .as_ref()
+            .map(|secret| format!("sha256={}", sign_payload(secret, &body)));
+        let retry_policy = RetryPolicy {
+            max_retries: webhook.config.max_retries,
+            initial_backoff: RETRY_BACKOFF,
+            max_backoff: MAX_RETRY_BACKOFF,
+        };
+
+        let mut attempt = 0;
+        loop {
+            let mut request = self
+                .client
+                .post(webhook.url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+
+            let result = request
+                .send()
+                .await
+                .map_err(Error::from)
+                .and_then(|response| {
+                    ensure!(
+                        response.status().is_success(),
+                        "webhook responded with status {}",
+                        response.status()
+                    );
+                    Ok(())
+                });
+
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < retry_policy.max_retries => {
+                    warn!(
+                        "webhook '{}' delivery failed, retrying: {}",
+                        webhook.config.name, err
+                    );
+                    tokio::time::sleep(retry_policy.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use cincinnati::MapImpl;
+
+    fn channels(pairs: &[(usize, &str)]) -> cincinnati::testing::TestMetadata {
+        pairs
+            .iter()
+            .map(|(i, channels)| {
+                let mut metadata = MapImpl::new();
+                metadata.insert("channels".to_string(), channels.to_string());
+                (*i, metadata)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compute_channel_deltas() {
+        let old = generate_custom_graph(
+            "image",
+            channels(&[(0, "stable-4.14"), (1, "fast-4.14, stable-4.14")]),
+            None,
+        );
+        let new = generate_custom_graph(
+            "image",
+            channels(&[(0, "stable-4.14"), (1, "fast-4.14"), (2, "fast-4.14")]),
+            None,
+        );
+
+        let deltas = channel_deltas(
+            &channel_releases(&old, "channels"),
+            &channel_releases(&new, "channels"),
+        );
+
+        assert_eq!(
+            deltas,
+            vec![
+                ChannelDelta {
+                    channel: "fast-4.14".to_string(),
+                    added_releases: vec!["2.0.0".to_string()],
+                    removed_releases: vec![],
+                },
+                ChannelDelta {
+                    channel: "stable-4.14".to_string(),
+                    added_releases: vec![],
+                    removed_releases: vec!["1.0.0".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn sign_payload_hmac_sha256() {
+        // Test case 2 from RFC 4231.
+        assert_eq!(
+            sign_payload(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // Test case 6 from RFC 4231, with a key longer than the block size.
+        assert_eq!(
+            sign_payload(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn queue_notifications() -> Fallible<()> {
+        let dispatcher = WebhookDispatcher::try_new(&[])?;
+        for _ in 0..DELIVERY_QUEUE_CAPACITY * 2 {
+            // Queueing never waits for deliveries.
+            dispatcher.dispatch(vec![ChannelDelta::default()]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_webhook_config() {
+        let config: WebhookConfig = toml::from_str(
+            r#"
+                name = "bot"
+                url = "https://example.com/hook"
+                channels = ["stable-4.14"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.channels, vec!["stable-4.14".to_string()]);
+        assert_eq!(config.secret_path, None);
+        assert_eq!(config.max_retries, 3);
+    }
+}
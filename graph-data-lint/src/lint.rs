@@ -0,0 +1,512 @@
+//! Checks of a graph-data checkout, producing findings with file/line context.
+
+use cincinnati::plugins::internal::openshift_secondary_metadata_parser::plugin::{
+    graph_data_model, BLOCKED_EDGES_DIR, CHANNELS_DIR,
+};
+use commons::prelude_errors::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Channel prefixes, in promotion order: a release must be in a channel
+/// before it can appear in the next one for the same minor version.
+static PROMOTION_ORDER: &[&str] = &["candidate", "fast", "stable"];
+
+/// Severity of a finding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Suspicious, but processed fine by graph-builder.
+    Warning,
+    /// Rejected or mis-processed by graph-builder.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single problem found in the graph data.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Finding {
+    /// File the problem was found in.
+    pub path: PathBuf,
+    /// Line of the problem, starting at 1, if known.
+    pub line: Option<usize>,
+    /// Severity of the problem.
+    pub severity: Severity,
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: ", self.path.display(), line)?,
+            None => write!(f, "{}: ", self.path.display())?,
+        }
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Collection of findings.
+#[derive(Debug, Default)]
+pub struct Findings(Vec<Finding>);
+
+impl Findings {
+    fn push(&mut self, path: &Path, line: Option<usize>, severity: Severity, message: String) {
+        self.0.push(Finding {
+            path: path.to_path_buf(),
+            line,
+            severity,
+            message,
+        })
+    }
+
+    /// Record an error.
+    pub fn error(&mut self, path: &Path, line: Option<usize>, message: String) {
+        self.push(path, line, Severity::Error, message)
+    }
+
+    /// Record a warning.
+    pub fn warning(&mut self, path: &Path, line: Option<usize>, message: String) {
+        self.push(path, line, Severity::Warning, message)
+    }
+
+    /// Number of findings with the given severity.
+    pub fn count(&self, severity: Severity) -> usize {
+        self.0.iter().filter(|f| f.severity == severity).count()
+    }
+
+    /// All findings, sorted by file and line.
+    pub fn into_sorted(mut self) -> Vec<Finding> {
+        self.0.sort();
+        self.0
+    }
+}
+
+/// A channel file.
+#[derive(Debug)]
+pub struct ChannelFile {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// Name of the channel.
+    pub name: String,
+    /// Versions in the channel, with the line they are listed on.
+    pub versions: Vec<(semver::Version, Option<usize>)>,
+}
+
+/// A blocked-edges file, declaring either a blocked or a conditional edge.
+#[derive(Debug)]
+pub struct BlockedEdgeFile {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// Target version of the edges.
+    pub to: semver::Version,
+    /// Regular expression matching the source versions.
+    pub from: regex::Regex,
+}
+
+/// Find the first line satisfying `predicate`, starting at 1.
+fn find_line(text: &str, predicate: impl Fn(&str) -> bool) -> Option<usize> {
+    text.lines()
+        .position(|l| predicate(l.trim()))
+        .map(|i| i + 1)
+}
+
+/// Line of the top-level `key` in a YAML document.
+fn key_line(text: &str, key: &str) -> Option<usize> {
+    find_line(text, |l| {
+        l.strip_prefix(key)
+            .map_or(false, |rest| rest.starts_with(':'))
+    })
+}
+
+fn yaml_error_line(e: &serde_yaml::Error) -> Option<usize> {
+    e.location().map(|location| location.line())
+}
+
+/// List the YAML files of a directory, reporting all other entries.
+fn yaml_files(dir: &Path, findings: &mut Findings) -> Fallible<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in std::fs::read_dir(dir).context(format!("reading directory {:?}", dir))? {
+        let path = entry?.path();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => paths.push(path),
+            Some(extension) => findings.error(
+                &path,
+                None,
+                format!("invalid extension '{}', expected 'yaml'", extension),
+            ),
+            None => findings.error(&path, None, "missing extension".to_string()),
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Parse all channel files, reporting schema errors.
+pub fn load_channels(data_dir: &Path, findings: &mut Findings) -> Fallible<Vec<ChannelFile>> {
+    let mut channels = vec![];
+    for path in yaml_files(&data_dir.join(CHANNELS_DIR), findings)? {
+        let text = std::fs::read_to_string(&path).context(format!("reading {:?}", path))?;
+        let channel: graph_data_model::Channel = match serde_yaml::from_str(&text) {
+            Ok(channel) => channel,
+            Err(e) => {
+                findings.error(&path, yaml_error_line(&e), e.to_string());
+                continue;
+            }
+        };
+
+        // List items are matched to versions in order; fall back to searching
+        // for each version if the list is not in block style.
+        let item_lines: Vec<usize> = text
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| l.trim_start().starts_with("- "))
+            .map(|(i, _)| i + 1)
+            .collect();
+        let in_order = item_lines.len() == channel.versions.len();
+        let versions = channel
+            .versions
+            .into_iter()
+            .enumerate()
+            .map(|(i, version)| {
+                let line = if in_order {
+                    Some(item_lines[i])
+                } else {
+                    let printed = version.to_string();
+                    find_line(&text, |l| {
+                        l.strip_prefix('-').map_or(false, |v| {
+                            v.trim().trim_matches('\'').trim_matches('"') == printed
+                        })
+                    })
+                };
+                (version, line)
+            })
+            .collect();
+
+        channels.push(ChannelFile {
+            path,
+            name: channel.name,
+            versions,
+        });
+    }
+    Ok(channels)
+}
+
+/// Parse all blocked-edges files, reporting schema errors.
+pub fn load_blocked_edges(
+    data_dir: &Path,
+    findings: &mut Findings,
+) -> Fallible<Vec<BlockedEdgeFile>> {
+    let mut edges = vec![];
+    for path in yaml_files(&data_dir.join(BLOCKED_EDGES_DIR), findings)? {
+        let text = std::fs::read_to_string(&path).context(format!("reading {:?}", path))?;
+
+        // Files declaring risks are conditional edges; all others are plain blocked edges.
+        let (to, from) = if key_line(&text, "matchingRules").is_some() {
+            match serde_yaml::from_str::<graph_data_model::ConditionalEdgeYaml>(&text) {
+                Ok(edge) => {
+                    for rule in &edge.matching_rules {
+                        if rule.condition_type == "PromQL" && rule.promql.promql.trim().is_empty() {
+                            findings.error(
+                                &path,
+                                key_line(&text, "matchingRules"),
+                                "PromQL matching rule without a query".to_string(),
+                            );
+                        }
+                    }
+                    if edge.url.is_empty() || edge.name.is_empty() || edge.message.is_empty() {
+                        findings.error(
+                            &path,
+                            None,
+                            "conditional edge requires a url, name and message".to_string(),
+                        );
+                    }
+                    (edge.to, edge.from)
+                }
+                Err(e) => {
+                    findings.error(&path, yaml_error_line(&e), e.to_string());
+                    continue;
+                }
+            }
+        } else {
+            match serde_yaml::from_str::<graph_data_model::BlockedEdge>(&text) {
+                Ok(edge) => (edge.to, edge.from),
+                Err(e) => {
+                    findings.error(&path, yaml_error_line(&e), e.to_string());
+                    continue;
+                }
+            }
+        };
+
+        edges.push(BlockedEdgeFile {
+            path,
+            to,
+            from: (*from).clone(),
+        });
+    }
+    Ok(edges)
+}
+
+/// Check channels for naming, duplicates and promotion consistency.
+pub fn check_channels(channels: &[ChannelFile], findings: &mut Findings) {
+    let mut names: BTreeMap<&str, &Path> = BTreeMap::new();
+    for channel in channels {
+        let stem = channel.path.file_stem().and_then(|s| s.to_str());
+        if stem != Some(channel.name.as_str()) {
+            findings.error(
+                &channel.path,
+                Some(1),
+                format!(
+                    "channel name '{}' does not match the file name",
+                    channel.name
+                ),
+            );
+        }
+        if let Some(previous) = names.insert(&channel.name, &channel.path) {
+            findings.error(
+                &channel.path,
+                Some(1),
+                format!(
+                    "channel '{}' is also declared in {}",
+                    channel.name,
+                    previous.display()
+                ),
+            );
+        }
+
+        let mut seen = BTreeSet::new();
+        for (version, line) in &channel.versions {
+            if !seen.insert(version.to_string()) {
+                findings.warning(
+                    &channel.path,
+                    *line,
+                    format!("{} is listed more than once", version),
+                );
+            }
+        }
+    }
+
+    // Releases are promoted from candidate to fast to stable channels of the same minor version.
+    let by_name: BTreeMap<&str, &ChannelFile> =
+        channels.iter().map(|c| (c.name.as_str(), c)).collect();
+    for channel in channels {
+        let (prefix, minor) = match channel.name.split_once('-') {
+            Some(split) => split,
+            None => continue,
+        };
+        let position = match PROMOTION_ORDER.iter().position(|p| *p == prefix) {
+            Some(position) if position > 0 => position,
+            _ => continue,
+        };
+        let previous_name = format!("{}-{}", PROMOTION_ORDER[position - 1], minor);
+        let previous = match by_name.get(previous_name.as_str()) {
+            Some(previous) => previous,
+            None => continue,
+        };
+
+        let previous_versions: BTreeSet<String> = previous
+            .versions
+            .iter()
+            .map(|(v, _)| v.to_string())
+            .collect();
+        let mut reported = BTreeSet::new();
+        for (version, line) in &channel.versions {
+            let version_string = version.to_string();
+            if !previous_versions.contains(&version_string) && reported.insert(version_string) {
+                findings.warning(
+                    &channel.path,
+                    *line,
+                    format!(
+                        "{} is in {} but not in {}",
+                        version, channel.name, previous_name
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Check that blocked edges reference releases known to the channels.
+pub fn check_blocked_edges(
+    edges: &[BlockedEdgeFile],
+    channels: &[ChannelFile],
+    findings: &mut Findings,
+) {
+    let known: BTreeSet<String> = channels
+        .iter()
+        .flat_map(|c| c.versions.iter().map(|(v, _)| v.to_string()))
+        .collect();
+
+    let mut declared: BTreeMap<(String, String), &Path> = BTreeMap::new();
+    for edge in edges {
+        let to = edge.to.to_string();
+        let text = std::fs::read_to_string(&edge.path).unwrap_or_default();
+
+        // Blocked edges apply to all architectures unless the target names one.
+        let mut unqualified = edge.to.clone();
+        unqualified.build.clear();
+        let unqualified = unqualified.to_string();
+        let to_known = known.contains(&to)
+            || known
+                .iter()
+                .any(|v| v.split('+').next() == Some(unqualified.as_str()));
+        if !to_known {
+            findings.warning(
+                &edge.path,
+                key_line(&text, "to"),
+                format!("blocked edge target {} is not in any channel", to),
+            );
+        }
+
+        if !known.iter().any(|v| edge.from.is_match(v)) {
+            findings.warning(
+                &edge.path,
+                key_line(&text, "from"),
+                format!(
+                    "blocked edge source '{}' does not match any release in a channel",
+                    edge.from
+                ),
+            );
+        }
+
+        if let Some(previous) = declared.insert((to.clone(), edge.from.to_string()), &edge.path) {
+            findings.warning(
+                &edge.path,
+                None,
+                format!(
+                    "blocked edge to {} from '{}' is also declared in {}",
+                    to,
+                    edge.from,
+                    previous.display()
+                ),
+            );
+        }
+    }
+}
+
+/// Check that all releases listed in channels are part of `graph`.
+pub fn check_graph(
+    graph: &mut cincinnati::Graph,
+    channels: &[ChannelFile],
+    findings: &mut Findings,
+) {
+    let releases: BTreeSet<String> = graph
+        .find_by_fn_mut(|_| true)
+        .into_iter()
+        .flat_map(|(_, version)| {
+            let unqualified = version.split('+').next().unwrap_or_default().to_string();
+            vec![version, unqualified]
+        })
+        .collect();
+
+    for channel in channels {
+        for (version, line) in &channel.versions {
+            if !releases.contains(&version.to_string()) {
+                findings.warning(
+                    &channel.path,
+                    *line,
+                    format!("{} is not in the release graph", version),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, path: &str, content: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn lint(dir: &Path) -> Vec<Finding> {
+        let mut findings = Findings::default();
+        let channels = load_channels(dir, &mut findings).unwrap();
+        let edges = load_blocked_edges(dir, &mut findings).unwrap();
+        check_channels(&channels, &mut findings);
+        check_blocked_edges(&edges, &channels, &mut findings);
+        findings.into_sorted()
+    }
+
+    #[test]
+    fn clean_graph_data() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "channels/fast-4.14.yaml",
+            "name: fast-4.14\nversions:\n- 4.14.0\n- 4.14.1\n",
+        );
+        write(
+            dir.path(),
+            "channels/stable-4.14.yaml",
+            "name: stable-4.14\nversions:\n- 4.14.1\n",
+        );
+        write(
+            dir.path(),
+            "blocked-edges/4.14.1.yaml",
+            "to: 4.14.1\nfrom: 4\\.14\\.0\n",
+        );
+
+        assert_eq!(lint(dir.path()), vec![]);
+    }
+
+    #[test]
+    fn report_problems_with_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "channels/fast-4.14.yaml",
+            "name: fast-4.14\nversions:\n- 4.14.0\n",
+        );
+        write(
+            dir.path(),
+            "channels/stable-4.14.yaml",
+            "name: stable-4.14\nversions:\n- 4.14.0\n- 4.14.1\n- 4.14.1\n",
+        );
+        write(
+            dir.path(),
+            "channels/broken.yaml",
+            "name: broken\nversions:\n- not-a-version\n",
+        );
+        write(
+            dir.path(),
+            "blocked-edges/4.15.0.yaml",
+            "to: 4.15.0\nfrom: 4\\.14\\..*\n",
+        );
+        write(dir.path(), "blocked-edges/notes.txt", "");
+
+        let findings: Vec<String> = lint(dir.path())
+            .iter()
+            .map(|f| {
+                let path = f
+                    .path
+                    .strip_prefix(dir.path())
+                    .unwrap()
+                    .display()
+                    .to_string();
+                format!("{}:{:?}: {}: {}", path, f.line, f.severity, f.message)
+            })
+            .collect();
+
+        assert_eq!(findings.len(), 5, "{:#?}", findings);
+        assert!(findings[0]
+            .starts_with("blocked-edges/4.15.0.yaml:Some(1): warning: blocked edge target 4.15.0"));
+        assert!(findings[1].starts_with("blocked-edges/notes.txt:None: error: invalid extension"));
+        assert!(findings[2].starts_with("channels/broken.yaml:Some("));
+        assert_eq!(findings[3], "channels/stable-4.14.yaml:Some(4): warning: 4.14.1 is in stable-4.14 but not in fast-4.14");
+        assert_eq!(
+            findings[4],
+            "channels/stable-4.14.yaml:Some(5): warning: 4.14.1 is listed more than once"
+        );
+    }
+}
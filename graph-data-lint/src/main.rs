@@ -0,0 +1,108 @@
+//! Linter for graph-data repositories.
+//!
+//! Checks the channel and blocked-edge files of a graph-data checkout for
+//! schema errors, references to unknown releases and inconsistent channels,
+//! then runs the same secondary metadata parser graph-builder uses on it.
+//! Findings are reported with file and line context; the exit status is
+//! non-zero if any errors were found.
+
+mod lint;
+
+use cincinnati::plugins::internal::openshift_secondary_metadata_parser::{
+    OpenshiftSecondaryMetadataParserPlugin, OpenshiftSecondaryMetadataParserSettings,
+};
+use cincinnati::plugins::{InternalIO, InternalPlugin};
+use commons::prelude_errors::*;
+use lint::{Findings, Severity};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Lint a graph-data checkout")]
+struct Options {
+    /// Path to the graph-data checkout
+    #[structopt(parse(from_os_str), default_value = ".")]
+    data_dir: PathBuf,
+
+    /// Release graph in JSON format (e.g. from graph-builder) to check channels against
+    #[structopt(long = "graph", parse(from_os_str))]
+    graph: Option<PathBuf>,
+
+    /// Fail on warnings as well as errors
+    #[structopt(long = "deny-warnings")]
+    deny_warnings: bool,
+}
+
+/// Run the secondary metadata parser on `graph`, failing on any invalid file.
+async fn run_parser(data_dir: &Path, graph: cincinnati::Graph) -> Fallible<cincinnati::Graph> {
+    let settings: OpenshiftSecondaryMetadataParserSettings =
+        serde_json::from_value(serde_json::json!({
+            "data_directory": data_dir,
+            "disallowed_errors": ["file", "invalid_extension", "missing_extension", "deserialize"],
+        }))
+        .context("building parser settings")?;
+
+    let io = OpenshiftSecondaryMetadataParserPlugin::new(settings)
+        .run_internal(InternalIO {
+            graph,
+            parameters: Default::default(),
+        })
+        .await?;
+
+    Ok(io.graph)
+}
+
+fn run(options: &Options) -> Fallible<Findings> {
+    let data_dir = &options.data_dir;
+    let mut findings = Findings::default();
+
+    let channels = lint::load_channels(data_dir, &mut findings)?;
+    let edges = lint::load_blocked_edges(data_dir, &mut findings)?;
+    lint::check_channels(&channels, &mut findings);
+    lint::check_blocked_edges(&edges, &channels, &mut findings);
+
+    let mut graph: cincinnati::Graph = match &options.graph {
+        Some(path) => {
+            let json = std::fs::read(path).context(format!("reading {:?}", path))?;
+            serde_json::from_slice(&json).context(format!("parsing graph in {:?}", path))?
+        }
+        None => Default::default(),
+    };
+
+    // The checks above already report the parser errors in more detail; only
+    // run it to catch what they do not cover.
+    if findings.count(Severity::Error) == 0 {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .context("building runtime")?;
+        match runtime.block_on(run_parser(data_dir, graph.clone())) {
+            Ok(parsed) => graph = parsed,
+            Err(e) => findings.error(data_dir, None, format!("{:#}", e)),
+        }
+    }
+
+    if options.graph.is_some() {
+        lint::check_graph(&mut graph, &channels, &mut findings);
+    }
+
+    Ok(findings)
+}
+
+fn main() -> Fallible<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("error")).init();
+    let options = Options::from_args();
+
+    let findings = run(&options)?;
+    let errors = findings.count(Severity::Error);
+    let warnings = findings.count(Severity::Warning);
+
+    for finding in findings.into_sorted() {
+        println!("{}", finding);
+    }
+    eprintln!("{} error(s), {} warning(s)", errors, warnings);
+
+    if errors > 0 || (options.deny_warnings && warnings > 0) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -23,6 +23,9 @@ pub struct FileOptions {
 
     /// Signatures service options
     pub signatures: Option<options::SignaturesOptions>,
+
+    /// Metrics options.
+    pub metrics: Option<options::MetricsOptions>,
 }
 
 impl FileOptions {
@@ -56,6 +59,7 @@ impl MergeOptions<Option<FileOptions>> for AppSettings {
             self.try_merge(file.service)?;
             self.try_merge(file.status)?;
             self.try_merge(file.signatures)?;
+            self.try_merge(file.metrics)?;
         }
         Ok(())
     }
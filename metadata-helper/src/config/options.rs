@@ -3,7 +3,8 @@
 use super::AppSettings;
 use commons::prelude_errors::*;
 use commons::{de_path_prefix, parse_path_prefix, MergeOptions};
-use std::net::IpAddr;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
 use std::time::Duration;
 
 /// Status service options.
@@ -16,6 +17,10 @@ pub struct StatusOptions {
     /// Port to which the status service will bind
     #[structopt(name = "status_port", long = "status.port")]
     pub port: Option<u16>,
+
+    /// Comma-separated addresses and ports on which the status service will listen, instead of `address` and `port`
+    #[structopt(long = "status.listen", use_delimiter = true)]
+    pub listen: Option<Vec<SocketAddr>>,
 }
 
 impl MergeOptions<Option<StatusOptions>> for AppSettings {
@@ -23,6 +28,24 @@ impl MergeOptions<Option<StatusOptions>> for AppSettings {
         if let Some(status) = opts {
             assign_if_some!(self.status_address, status.address);
             assign_if_some!(self.status_port, status.port);
+            assign_if_some!(self.status_listen, status.listen);
+        }
+        Ok(())
+    }
+}
+
+/// Metrics options.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsOptions {
+    /// Static labels added to every exported metric, by name
+    pub labels: Option<BTreeMap<String, String>>,
+}
+
+impl MergeOptions<Option<MetricsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<MetricsOptions>) -> Fallible<()> {
+        if let Some(metrics) = opts {
+            assign_if_some!(self.metrics_labels, metrics.labels);
         }
         Ok(())
     }
@@ -56,6 +79,10 @@ pub struct ServiceOptions {
     #[structopt(name = "service_port", long = "service.port")]
     pub port: Option<u16>,
 
+    /// Comma-separated addresses and ports on which the server will listen, instead of `address` and `port`
+    #[structopt(long = "service.listen", use_delimiter = true)]
+    pub listen: Option<Vec<SocketAddr>>,
+
     /// Namespace prefix for all service endpoints (e.g. '/<prefix>/graph')
     #[structopt(long = "service.path_prefix", parse(from_str = parse_path_prefix))]
     #[serde(default = "Option::default", deserialize_with = "de_path_prefix")]
@@ -82,6 +109,7 @@ impl MergeOptions<Option<ServiceOptions>> for AppSettings {
         if let Some(service) = opts {
             assign_if_some!(self.address, service.address);
             assign_if_some!(self.port, service.port);
+            assign_if_some!(self.listen, service.listen);
             assign_if_some!(self.path_prefix, service.path_prefix);
             assign_if_some!(self.tracing_endpoint, service.tracing_endpoint);
             assign_if_some!(self.backlog, service.backlog);
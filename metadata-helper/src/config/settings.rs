@@ -3,7 +3,8 @@
 use super::{cli, file};
 use commons::prelude_errors::*;
 use custom_debug_derive::Debug as CustomDebug;
-use std::net::{IpAddr, Ipv4Addr};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::time::Duration;
 use structopt::StructOpt;
 
@@ -20,6 +21,8 @@ pub struct AppSettings {
     /// Listening port for the main service.
     #[default(8082)]
     pub port: u16,
+    /// Listening addresses for the main service, instead of `address` and `port` if not empty.
+    pub listen: Vec<SocketAddr>,
     /// Endpoints namespace for the main service.
     pub path_prefix: String,
 
@@ -29,6 +32,8 @@ pub struct AppSettings {
     /// Listening port for the status service.
     #[default(9082)]
     pub status_port: u16,
+    /// Listening addresses for the status service, instead of `status_address` and `status_port` if not empty.
+    pub status_listen: Vec<SocketAddr>,
 
     /// directory to store signatures
     pub signatures_dir: String,
@@ -36,6 +41,9 @@ pub struct AppSettings {
     /// Jaeger host and port for tracing support
     pub tracing_endpoint: Option<String>,
 
+    /// Static labels added to every exported metric, by name.
+    pub metrics_labels: BTreeMap<String, String>,
+
     /// Actix-web maximum number of pending connections, defaults to 2048: https://docs.rs/actix-web/latest/actix_web/struct.HttpServer.html#method.backlog
     #[default(10)]
     pub backlog: u32,
@@ -77,12 +85,29 @@ impl AppSettings {
         Self::try_validate(cfg)
     }
 
+    /// Return the listening addresses of the main service.
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        commons::listen::listen_addrs(&self.listen, self.address, self.port)
+    }
+
+    /// Return the listening addresses of the status service.
+    pub fn status_listen_addrs(&self) -> Vec<SocketAddr> {
+        commons::listen::listen_addrs(&self.status_listen, self.status_address, self.status_port)
+    }
+
     /// Validate and build runtime settings.
     fn try_validate(self) -> Fallible<Self> {
-        if self.address == self.status_address && self.port == self.status_port {
+        let status_addrs = self.status_listen_addrs();
+        if self
+            .listen_addrs()
+            .iter()
+            .any(|addr| status_addrs.contains(addr))
+        {
             bail!("main and status service configured with the same address and port");
         }
 
+        commons::metrics::validate_labels(&self.metrics_labels)?;
+
         Ok(self)
     }
 }
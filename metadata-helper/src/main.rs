@@ -78,9 +78,10 @@ async fn main() -> Result<(), Error> {
     info!("application settings:\n{:#?}", &settings);
 
     // Metrics service.
-    let registry: &'static Registry = Box::leak(Box::new(metrics::new_registry(Some(
-        METRICS_PREFIX.to_string(),
-    ))?));
+    let registry: &'static Registry = Box::leak(Box::new(metrics::new_labeled_registry(
+        Some(METRICS_PREFIX.to_string()),
+        &settings.metrics_labels,
+    )?));
     registry.register(Box::new(BUILD_INFO.clone()))?;
 
     let mut signatures_dir = settings.signatures_dir.clone();
@@ -108,7 +109,7 @@ async fn main() -> Result<(), Error> {
 
     signatures::register_metrics(state.registry())?;
     let metric_state = state.clone();
-    let metrics_server = HttpServer::new(move || {
+    let mut metrics_server = HttpServer::new(move || {
         App::new()
             .wrap(middleware::Compress::default())
             .app_data(actix_web::web::Data::new(metric_state.clone()))
@@ -124,14 +125,16 @@ async fn main() -> Result<(), Error> {
                 actix_web::web::resource("/readyz")
                     .route(actix_web::web::get().to(status::serve_readiness)),
             )
-    })
-    .bind((settings.status_address, settings.status_port))?
-    .run();
+    });
+    for listener in commons::listen::bind_all(&settings.status_listen_addrs(), settings.backlog)? {
+        metrics_server = metrics_server.listen(listener)?;
+    }
+    let metrics_server = metrics_server.run();
 
     // Enable tracing
     init_tracer(METRICS_PREFIX, settings.tracing_endpoint.clone())?;
     let main_state = state.clone();
-    let main_server = HttpServer::new(move || {
+    let mut main_server = HttpServer::new(move || {
         let app_prefix = main_state.path_prefix.clone();
         App::new()
             .wrap_fn(|req, srv| {
@@ -161,9 +164,11 @@ async fn main() -> Result<(), Error> {
     .max_connections(settings.max_connections)
     .max_connection_rate(settings.max_connection_rate)
     .keep_alive(settings.keep_alive)
-    .client_request_timeout(settings.client_timeout)
-    .bind((settings.address, settings.port))?
-    .run();
+    .client_request_timeout(settings.client_timeout);
+    for listener in commons::listen::bind_all(&settings.listen_addrs(), settings.backlog)? {
+        main_server = main_server.listen(listener)?;
+    }
+    let main_server = main_server.run();
 
     // metrics endpoints has started running
     *state.live.write() = true;
@@ -0,0 +1,305 @@
+//! Fixture data served by the mock registry.
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::Path;
+
+/// Media type of image manifests (schema 2).
+pub static MANIFEST_V2_MEDIA_TYPE: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+/// Media type of OCI image manifests, used for artifacts.
+pub static OCI_MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// Media type of OCI image indexes, used for listing referrers.
+pub static OCI_INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+
+/// Media type of empty artifact configuration blobs.
+pub static OCI_EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+
+/// Media type of image configuration blobs.
+pub static CONFIG_MEDIA_TYPE: &str = "application/vnd.docker.container.image.v1+json";
+
+/// Media type of compressed layer blobs.
+pub static LAYER_MEDIA_TYPE: &str = "application/vnd.docker.image.rootfs.diff.tar.gzip";
+
+/// Path of the release metadata inside release images.
+pub static RELEASE_METADATA_PATH: &str = "release-manifests/release-metadata";
+
+/// Compute the content digest of `data`.
+pub fn digest(data: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(data)))
+}
+
+/// A stored manifest.
+#[derive(Debug, Clone)]
+pub(crate) struct Manifest {
+    pub(crate) media_type: String,
+    pub(crate) body: Vec<u8>,
+}
+
+/// Content of a single repository.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Repository {
+    pub(crate) tags: BTreeMap<String, String>,
+    pub(crate) manifests: HashMap<String, Manifest>,
+    pub(crate) blobs: HashMap<String, Vec<u8>>,
+    /// Descriptors of the artifacts referring to a manifest, by its digest.
+    pub(crate) referrers: HashMap<String, Vec<serde_json::Value>>,
+}
+
+/// Repositories, tags, manifests and blobs served by the mock registry.
+#[derive(Debug, Default, Clone)]
+pub struct Fixtures {
+    pub(crate) repositories: BTreeMap<String, Repository>,
+}
+
+impl Fixtures {
+    /// Create empty fixtures.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a blob in `repo`, returning its digest.
+    pub fn add_blob(&mut self, repo: &str, data: impl Into<Vec<u8>>) -> String {
+        let data = data.into();
+        let digest = digest(&data);
+        self.repository(repo).blobs.insert(digest.clone(), data);
+        digest
+    }
+
+    /// Store a manifest in `repo`, returning its digest.
+    pub fn add_manifest(
+        &mut self,
+        repo: &str,
+        media_type: &str,
+        body: impl Into<Vec<u8>>,
+    ) -> String {
+        let body = body.into();
+        let digest = digest(&body);
+        self.repository(repo).manifests.insert(
+            digest.clone(),
+            Manifest {
+                media_type: media_type.to_string(),
+                body,
+            },
+        );
+        digest
+    }
+
+    /// Point `tag` in `repo` to the manifest with the given digest.
+    pub fn tag(&mut self, repo: &str, tag: &str, manifest_digest: &str) {
+        self.repository(repo)
+            .tags
+            .insert(tag.to_string(), manifest_digest.to_string());
+    }
+
+    /// Store a release image in `repo` under `tag`, returning its manifest digest.
+    pub fn add_release(&mut self, repo: &str, tag: &str, release: &ReleaseImage) -> Result<String> {
+        let config = serde_json::to_vec(&serde_json::json!({
+            "architecture": release.arch,
+            "os": "linux",
+            "config": {},
+            "rootfs": { "type": "layers", "diff_ids": [] },
+        }))?;
+        let layer = release.layer()?;
+
+        let manifest = serde_json::to_vec(&serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": MANIFEST_V2_MEDIA_TYPE,
+            "config": {
+                "mediaType": CONFIG_MEDIA_TYPE,
+                "size": config.len(),
+                "digest": self.add_blob(repo, config.clone()),
+            },
+            "layers": [{
+                "mediaType": LAYER_MEDIA_TYPE,
+                "size": layer.len(),
+                "digest": self.add_blob(repo, layer.clone()),
+            }],
+        }))?;
+
+        let manifest_digest = self.add_manifest(repo, MANIFEST_V2_MEDIA_TYPE, manifest);
+        self.tag(repo, tag, &manifest_digest);
+        Ok(manifest_digest)
+    }
+
+    /// Store an artifact of `artifact_type` in `repo`, referring to the
+    /// manifest with digest `subject` and carrying `annotations`, returning
+    /// the artifact manifest digest.
+    pub fn add_referrer(
+        &mut self,
+        repo: &str,
+        subject: &str,
+        artifact_type: &str,
+        annotations: &BTreeMap<String, String>,
+    ) -> Result<String> {
+        let empty_config = b"{}".to_vec();
+        let manifest = serde_json::to_vec(&serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_MANIFEST_MEDIA_TYPE,
+            "artifactType": artifact_type,
+            "config": {
+                "mediaType": OCI_EMPTY_MEDIA_TYPE,
+                "size": empty_config.len(),
+                "digest": self.add_blob(repo, empty_config),
+            },
+            "layers": [],
+            "subject": { "digest": subject },
+            "annotations": annotations,
+        }))?;
+        let size = manifest.len();
+
+        let manifest_digest = self.add_manifest(repo, OCI_MANIFEST_MEDIA_TYPE, manifest);
+        self.repository(repo)
+            .referrers
+            .entry(subject.to_string())
+            .or_default()
+            .push(serde_json::json!({
+                "mediaType": OCI_MANIFEST_MEDIA_TYPE,
+                "size": size,
+                "digest": manifest_digest,
+                "artifactType": artifact_type,
+                "annotations": annotations,
+            }));
+        Ok(manifest_digest)
+    }
+
+    /// Load fixtures from a directory.
+    ///
+    /// Every directory containing a `tags` directory is a repository, named by
+    /// its path relative to `dir`. `tags/<tag>.json` holds the manifest for a
+    /// tag, and every file in `blobs` is served under its content digest.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut fixtures = Self::new();
+        fixtures.load_repositories(dir, dir)?;
+        Ok(fixtures)
+    }
+
+    fn load_repositories(&mut self, root: &Path, dir: &Path) -> Result<()> {
+        let tags_dir = dir.join("tags");
+        if tags_dir.is_dir() {
+            let repo = dir
+                .strip_prefix(root)?
+                .to_str()
+                .context(format!("non UTF-8 repository path {:?}", dir))?
+                .to_string();
+
+            for entry in std::fs::read_dir(&tags_dir)? {
+                let path = entry?.path();
+                let tag = path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .context(format!("invalid tag file {:?}", path))?
+                    .to_string();
+                let body = std::fs::read(&path).context(format!("reading {:?}", path))?;
+                let media_type = serde_json::from_slice::<serde_json::Value>(&body)
+                    .context(format!("parsing manifest {:?}", path))?
+                    .get("mediaType")
+                    .and_then(|t| t.as_str())
+                    .unwrap_or(MANIFEST_V2_MEDIA_TYPE)
+                    .to_string();
+                let digest = self.add_manifest(&repo, &media_type, body);
+                self.tag(&repo, &tag, &digest);
+            }
+
+            let blobs_dir = dir.join("blobs");
+            if blobs_dir.is_dir() {
+                for entry in std::fs::read_dir(&blobs_dir)? {
+                    let path = entry?.path();
+                    let data = std::fs::read(&path).context(format!("reading {:?}", path))?;
+                    self.add_blob(&repo, data);
+                }
+            }
+            return Ok(());
+        }
+
+        for entry in std::fs::read_dir(dir).context(format!("reading directory {:?}", dir))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                self.load_repositories(root, &path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn repository(&mut self, repo: &str) -> &mut Repository {
+        self.repositories.entry(repo.to_string()).or_default()
+    }
+}
+
+/// A release image, carrying Cincinnati release metadata in its only layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReleaseImage {
+    /// Version of the release.
+    pub version: String,
+    /// Versions which can be updated to this release.
+    pub previous: Vec<String>,
+    /// Versions this release can be updated to.
+    pub next: Vec<String>,
+    /// Additional release metadata.
+    pub metadata: BTreeMap<String, String>,
+    /// Architecture of the image.
+    pub arch: String,
+}
+
+impl ReleaseImage {
+    /// Create a release image for `version`, for the amd64 architecture.
+    pub fn new(version: &str) -> Self {
+        Self {
+            version: version.to_string(),
+            previous: vec![],
+            next: vec![],
+            metadata: BTreeMap::new(),
+            arch: "amd64".to_string(),
+        }
+    }
+
+    /// Set the versions which can be updated to this release.
+    pub fn with_previous(mut self, previous: &[&str]) -> Self {
+        self.previous = previous.iter().map(|v| v.to_string()).collect();
+        self
+    }
+
+    /// Set the versions this release can be updated to.
+    pub fn with_next(mut self, next: &[&str]) -> Self {
+        self.next = next.iter().map(|v| v.to_string()).collect();
+        self
+    }
+
+    /// Add a release metadata entry.
+    pub fn with_metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Set the architecture of the image.
+    pub fn with_arch(mut self, arch: &str) -> Self {
+        self.arch = arch.to_string();
+        self
+    }
+
+    /// Build the compressed layer holding the release metadata.
+    fn layer(&self) -> Result<Vec<u8>> {
+        let metadata = serde_json::to_vec(&serde_json::json!({
+            "kind": "cincinnati-metadata-v0",
+            "version": self.version,
+            "previous": self.previous,
+            "next": self.next,
+            "metadata": self.metadata,
+        }))?;
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(metadata.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut archive = tar::Builder::new(encoder);
+        archive.append_data(&mut header, RELEASE_METADATA_PATH, metadata.as_slice())?;
+        let mut encoder = archive.into_inner()?;
+        encoder.flush()?;
+        Ok(encoder.finish()?)
+    }
+}
@@ -0,0 +1,447 @@
+//! Minimal Docker registry v2 server for hermetic integration tests.
+//!
+//! The server serves tags, manifests and blobs from [`Fixtures`], without
+//! authentication, on a random local port. It implements just enough of the
+//! [registry API](https://docs.docker.com/registry/spec/api/) for scraping
+//! release images: the version check, paginated tag listing, manifest and
+//! blob retrieval by tag or digest, and the OCI referrers listing.
+
+#![deny(missing_docs)]
+
+#[macro_use]
+extern crate log;
+
+mod fixtures;
+
+pub use crate::fixtures::{
+    digest, Fixtures, ReleaseImage, MANIFEST_V2_MEDIA_TYPE, OCI_INDEX_MEDIA_TYPE,
+    OCI_MANIFEST_MEDIA_TYPE,
+};
+
+use actix_web::dev::ServerHandle;
+use actix_web::http::header;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use anyhow::{format_err, Context, Result};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Header identifying registry v2 implementations.
+static API_VERSION_HEADER: &str = "Docker-Distribution-API-Version";
+
+/// Header carrying the digest of served content.
+static CONTENT_DIGEST_HEADER: &str = "Docker-Content-Digest";
+
+#[derive(Debug)]
+struct State {
+    fixtures: Fixtures,
+    requests: Mutex<Vec<String>>,
+}
+
+/// A running mock registry, stopped when dropped.
+#[derive(Debug)]
+pub struct MockRegistry {
+    addr: SocketAddr,
+    handle: ServerHandle,
+    state: Arc<State>,
+    thread: Option<std::thread::JoinHandle<std::io::Result<()>>>,
+}
+
+impl MockRegistry {
+    /// Start serving `fixtures` on a random local port.
+    pub fn start(fixtures: Fixtures) -> Result<Self> {
+        let state = Arc::new(State {
+            fixtures,
+            requests: Mutex::new(vec![]),
+        });
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let server_state = state.clone();
+        let thread = std::thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                let data = web::Data::from(server_state);
+                let server = HttpServer::new(move || {
+                    App::new()
+                        .app_data(data.clone())
+                        .route("/v2/", web::get().to(version_check))
+                        .route("/v2/{path:.*}", web::get().to(dispatch))
+                        .route("/v2/{path:.*}", web::head().to(dispatch))
+                })
+                .workers(1)
+                .bind(("127.0.0.1", 0));
+
+                let server = match server {
+                    Ok(server) => server,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return Ok(());
+                    }
+                };
+                let addr = server.addrs()[0];
+                let server = server.run();
+                let _ = tx.send(Ok((addr, server.handle())));
+                server.await
+            })
+        });
+
+        let (addr, handle) = rx
+            .recv()
+            .context("mock registry thread exited before starting")?
+            .context("binding mock registry")?;
+        debug!("mock registry listening on {}", addr);
+
+        Ok(Self {
+            addr,
+            handle,
+            state,
+            thread: Some(thread),
+        })
+    }
+
+    /// Registry address, as `host:port`.
+    pub fn host_port(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// Registry URL, including the scheme.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Paths of all requests served so far, in order.
+    pub fn requests(&self) -> Vec<String> {
+        self.state
+            .requests
+            .lock()
+            .map(|requests| requests.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for MockRegistry {
+    fn drop(&mut self) {
+        futures::executor::block_on(self.handle.stop(false));
+        if let Some(thread) = self.thread.take() {
+            if let Err(e) = thread
+                .join()
+                .map_err(|_| format_err!("mock registry thread panicked"))
+                .and_then(|result| result.map_err(Into::into))
+            {
+                error!("stopping mock registry: {}", e);
+            }
+        }
+    }
+}
+
+fn error_response(status: actix_web::http::StatusCode, code: &str, message: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .insert_header((API_VERSION_HEADER, "registry/2.0"))
+        .json(serde_json::json!({
+            "errors": [{ "code": code, "message": message, "detail": null }]
+        }))
+}
+
+fn not_found(code: &str, message: &str) -> HttpResponse {
+    error_response(actix_web::http::StatusCode::NOT_FOUND, code, message)
+}
+
+async fn version_check(req: HttpRequest, state: web::Data<State>) -> HttpResponse {
+    record(&req, &state);
+    HttpResponse::Ok()
+        .insert_header((API_VERSION_HEADER, "registry/2.0"))
+        .json(serde_json::json!({}))
+}
+
+fn record(req: &HttpRequest, state: &State) {
+    if let Ok(mut requests) = state.requests.lock() {
+        requests.push(req.uri().to_string());
+    }
+}
+
+async fn dispatch(req: HttpRequest, state: web::Data<State>) -> HttpResponse {
+    record(&req, &state);
+    let path = req.match_info().query("path").to_string();
+
+    if let Some(repo) = path.strip_suffix("/tags/list") {
+        return list_tags(&req, &state, repo);
+    }
+    if let Some((repo, digest)) = path.rsplit_once("/referrers/") {
+        return list_referrers(&req, &state, repo, digest);
+    }
+    if let Some((repo, reference)) = path.rsplit_once("/manifests/") {
+        return get_manifest(&req, &state, repo, reference);
+    }
+    if let Some((repo, digest)) = path.rsplit_once("/blobs/") {
+        return get_blob(&req, &state, repo, digest);
+    }
+
+    not_found("UNSUPPORTED", "unsupported operation")
+}
+
+fn list_tags(req: &HttpRequest, state: &State, repo: &str) -> HttpResponse {
+    let repository = match state.fixtures.repositories.get(repo) {
+        Some(repository) => repository,
+        None => return not_found("NAME_UNKNOWN", "repository name not known to registry"),
+    };
+
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(web::Query::into_inner)
+        .unwrap_or_default();
+    let last = query.get("last");
+    let page_size = query.get("n").and_then(|n| n.parse::<usize>().ok());
+
+    let remaining: Vec<&String> = repository
+        .tags
+        .keys()
+        .filter(|tag| last.map_or(true, |last| *tag > last))
+        .collect();
+    let page: Vec<&String> = remaining
+        .iter()
+        .take(page_size.unwrap_or(usize::MAX))
+        .cloned()
+        .collect();
+
+    let mut response = HttpResponse::Ok();
+    response.insert_header((API_VERSION_HEADER, "registry/2.0"));
+    if let (Some(n), Some(last)) = (page_size, page.last()) {
+        if remaining.len() > page.len() {
+            response.insert_header((
+                header::LINK,
+                format!(
+                    "</v2/{}/tags/list?n={}&last={}>; rel=\"next\"",
+                    repo, n, last
+                ),
+            ));
+        }
+    }
+    response.json(serde_json::json!({ "name": repo, "tags": page }))
+}
+
+fn get_manifest(req: &HttpRequest, state: &State, repo: &str, reference: &str) -> HttpResponse {
+    let repository = match state.fixtures.repositories.get(repo) {
+        Some(repository) => repository,
+        None => return not_found("NAME_UNKNOWN", "repository name not known to registry"),
+    };
+
+    let digest = repository
+        .tags
+        .get(reference)
+        .map(String::as_str)
+        .unwrap_or(reference);
+    let manifest = match repository.manifests.get(digest) {
+        Some(manifest) => manifest,
+        None => return not_found("MANIFEST_UNKNOWN", "manifest unknown"),
+    };
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((API_VERSION_HEADER, "registry/2.0"))
+        .insert_header((CONTENT_DIGEST_HEADER, digest))
+        .insert_header((header::ETAG, format!("\"{}\"", digest)))
+        .content_type(manifest.media_type.as_str());
+    if req.method() == actix_web::http::Method::HEAD {
+        return response.finish();
+    }
+    response.body(manifest.body.clone())
+}
+
+fn list_referrers(req: &HttpRequest, state: &State, repo: &str, digest: &str) -> HttpResponse {
+    let repository = match state.fixtures.repositories.get(repo) {
+        Some(repository) => repository,
+        None => return not_found("NAME_UNKNOWN", "repository name not known to registry"),
+    };
+
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(web::Query::into_inner)
+        .unwrap_or_default();
+    let artifact_type = query.get("artifactType");
+
+    // Unknown subjects have no referrers, rather than not being found.
+    let referrers: Vec<&serde_json::Value> = repository
+        .referrers
+        .get(digest)
+        .into_iter()
+        .flatten()
+        .filter(|descriptor| {
+            artifact_type.map_or(true, |artifact_type| {
+                descriptor["artifactType"].as_str() == Some(artifact_type.as_str())
+            })
+        })
+        .collect();
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((API_VERSION_HEADER, "registry/2.0"))
+        .content_type(OCI_INDEX_MEDIA_TYPE);
+    if artifact_type.is_some() {
+        response.insert_header(("OCI-Filters-Applied", "artifactType"));
+    }
+    response.json(serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": OCI_INDEX_MEDIA_TYPE,
+        "manifests": referrers,
+    }))
+}
+
+fn get_blob(req: &HttpRequest, state: &State, repo: &str, digest: &str) -> HttpResponse {
+    let blob = match state
+        .fixtures
+        .repositories
+        .get(repo)
+        .and_then(|repository| repository.blobs.get(digest))
+    {
+        Some(blob) => blob,
+        None => return not_found("BLOB_UNKNOWN", "blob unknown to registry"),
+    };
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((API_VERSION_HEADER, "registry/2.0"))
+        .insert_header((CONTENT_DIGEST_HEADER, digest))
+        .content_type("application/octet-stream");
+    if req.method() == actix_web::http::Method::HEAD {
+        return response.finish();
+    }
+    response.body(blob.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixtures() -> Fixtures {
+        let mut fixtures = Fixtures::new();
+        for (tag, version) in &[("0.0.0", "0.0.0"), ("0.0.1", "0.0.1"), ("0.0.2", "0.0.2")] {
+            fixtures
+                .add_release("test/release", tag, &ReleaseImage::new(version))
+                .unwrap();
+        }
+        fixtures
+    }
+
+    #[test]
+    fn serve_tags_manifests_and_blobs() -> Result<()> {
+        let registry = MockRegistry::start(fixtures())?;
+        let client = reqwest::blocking::Client::new();
+        let url = registry.url();
+
+        let ping = client.get(format!("{}/v2/", url)).send()?;
+        assert!(ping.status().is_success());
+        assert_eq!(ping.headers()[API_VERSION_HEADER], "registry/2.0");
+
+        let tags = client
+            .get(format!("{}/v2/test/release/tags/list?n=2", url))
+            .send()?;
+        assert!(tags.headers()[header::LINK.as_str()]
+            .to_str()?
+            .contains("last=0.0.1"));
+        let tags: serde_json::Value = tags.json()?;
+        assert_eq!(tags["tags"], serde_json::json!(["0.0.0", "0.0.1"]));
+
+        let manifest = client
+            .get(format!("{}/v2/test/release/manifests/0.0.1", url))
+            .send()?;
+        let manifest_digest = manifest.headers()[CONTENT_DIGEST_HEADER]
+            .to_str()?
+            .to_string();
+        let manifest = manifest.bytes()?;
+        assert_eq!(digest(&manifest), manifest_digest);
+        let manifest: serde_json::Value = serde_json::from_slice(&manifest)?;
+
+        let layer_digest = manifest["layers"][0]["digest"].as_str().unwrap();
+        let layer = client
+            .get(format!("{}/v2/test/release/blobs/{}", url, layer_digest))
+            .send()?
+            .bytes()?;
+        assert_eq!(digest(&layer), layer_digest);
+
+        let by_digest = client
+            .get(format!(
+                "{}/v2/test/release/manifests/{}",
+                url, manifest_digest
+            ))
+            .send()?;
+        assert!(by_digest.status().is_success());
+
+        let missing = client
+            .get(format!("{}/v2/test/missing/tags/list", url))
+            .send()?;
+        assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+
+        assert_eq!(registry.requests().len(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn serve_referrers() -> Result<()> {
+        let mut fixtures = fixtures();
+        let subject = fixtures.repositories["test/release"].tags["0.0.1"].clone();
+        let annotations = [("key".to_string(), "value".to_string())]
+            .iter()
+            .cloned()
+            .collect();
+        fixtures.add_referrer("test/release", &subject, "application/x.test", &annotations)?;
+        fixtures.add_referrer(
+            "test/release",
+            &subject,
+            "application/x.other",
+            &annotations,
+        )?;
+        let registry = MockRegistry::start(fixtures)?;
+        let client = reqwest::blocking::Client::new();
+        let url = registry.url();
+
+        let referrers: serde_json::Value = client
+            .get(format!(
+                "{}/v2/test/release/referrers/{}?artifactType=application/x.test",
+                url, subject
+            ))
+            .send()?
+            .json()?;
+        let manifests = referrers["manifests"].as_array().unwrap();
+        assert_eq!(manifests.len(), 1);
+        assert_eq!(manifests[0]["annotations"]["key"], "value");
+
+        let artifact = client
+            .get(format!(
+                "{}/v2/test/release/manifests/{}",
+                url,
+                manifests[0]["digest"].as_str().unwrap()
+            ))
+            .send()?;
+        assert!(artifact.status().is_success());
+
+        let none: serde_json::Value = client
+            .get(format!("{}/v2/test/release/referrers/sha256:00", url))
+            .send()?
+            .json()?;
+        assert_eq!(none["manifests"], serde_json::json!([]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_fixtures_from_directory() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let repo_dir = dir.path().join("org/repo");
+        std::fs::create_dir_all(repo_dir.join("tags"))?;
+        std::fs::create_dir_all(repo_dir.join("blobs"))?;
+        std::fs::write(repo_dir.join("blobs/config"), "{}")?;
+        std::fs::write(
+            repo_dir.join("tags/latest.json"),
+            serde_json::to_vec(&serde_json::json!({
+                "schemaVersion": 2,
+                "mediaType": MANIFEST_V2_MEDIA_TYPE,
+                "config": { "size": 2, "digest": digest(b"{}") },
+                "layers": [],
+            }))?,
+        )?;
+
+        let fixtures = Fixtures::load(dir.path())?;
+        let repository = &fixtures.repositories["org/repo"];
+        assert!(repository.tags.contains_key("latest"));
+        assert!(repository.blobs.contains_key(&digest(b"{}")));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+//! Aggregated analytics of client graph queries.
+//!
+//! Successful graph requests are counted by channel, version and
+//! architecture, both as labeled metrics and in memory, from which a summary
+//! is logged periodically. Client identifiers and addresses are never
+//! recorded, so that the fleet can be observed without tracking clusters.
+
+use actix_web::HttpRequest;
+use commons::prelude_errors::*;
+use parking_lot::Mutex;
+use prometheus::{IntCounterVec, Opts, Registry};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default interval between logged summaries.
+pub const DEFAULT_SUMMARY_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Default maximum number of distinct (channel, version, arch) series.
+pub const DEFAULT_MAX_SERIES: usize = 1000;
+
+/// Label of parameters missing from a query.
+static MISSING_LABEL: &str = "none";
+
+/// Label of the series beyond the maximum number of series.
+static OVERFLOW_LABEL: &str = "other";
+
+/// Number of series reported in each summary.
+static SUMMARY_TOP_SERIES: usize = 10;
+
+lazy_static! {
+    static ref CLIENT_QUERIES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "graph_client_queries_total",
+            "Total number of successful graph queries, by channel, version and architecture"
+        ),
+        &["channel", "version", "arch"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(CLIENT_QUERIES.clone()))?;
+    Ok(())
+}
+
+/// Channel, version and architecture of a query.
+type Series = (String, String, String);
+
+#[derive(Debug, Default)]
+struct Counts {
+    /// Series with their own labels, bounded by the maximum number of series.
+    known: HashSet<Series>,
+    /// Queries by series since the last summary.
+    since_summary: HashMap<Series, u64>,
+}
+
+/// Handle to the aggregated query counts.
+#[derive(Clone, Debug)]
+pub(crate) struct QueryAnalytics {
+    max_series: usize,
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl QueryAnalytics {
+    /// Create the analytics, counting at most `max_series` distinct series.
+    pub(crate) fn new(max_series: usize) -> Self {
+        Self {
+            max_series,
+            counts: Default::default(),
+        }
+    }
+
+    /// Start a background thread logging a summary every `interval`.
+    pub(crate) fn spawn_summary(&self, interval: Duration) -> Fallible<()> {
+        let analytics = self.clone();
+        std::thread::Builder::new()
+            .name("query-analytics".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                if let Some(summary) = analytics.take_summary() {
+                    info!(
+                        "client queries in the last {}s: {}",
+                        interval.as_secs(),
+                        summary
+                    );
+                }
+            })
+            .context("spawning query analytics summary")?;
+        Ok(())
+    }
+
+    /// Count a successful graph request.
+    pub(crate) fn record(&self, req: &HttpRequest) {
+        let (mut channel, mut version, mut arch) = (None, None, None);
+        for (key, value) in url::form_urlencoded::parse(req.query_string().as_bytes()) {
+            match key.as_ref() {
+                "channel" => channel = Some(value.into_owned()),
+                "version" => version = Some(value.into_owned()),
+                "arch" => arch = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+        self.record_query(channel, version, arch);
+    }
+
+    /// Count a query, collapsing series beyond the maximum into one.
+    fn record_query(&self, channel: Option<String>, version: Option<String>, arch: Option<String>) {
+        let label = |value: Option<String>| {
+            value
+                .filter(|value| !value.is_empty())
+                .unwrap_or_else(|| MISSING_LABEL.to_string())
+        };
+        let mut series = (label(channel), label(version), label(arch));
+
+        let mut counts = self.counts.lock();
+        if !counts.known.contains(&series) {
+            if counts.known.len() < self.max_series {
+                counts.known.insert(series.clone());
+            } else {
+                series = (
+                    OVERFLOW_LABEL.to_string(),
+                    OVERFLOW_LABEL.to_string(),
+                    OVERFLOW_LABEL.to_string(),
+                );
+            }
+        }
+
+        CLIENT_QUERIES
+            .with_label_values(&[&series.0, &series.1, &series.2])
+            .inc();
+        *counts.since_summary.entry(series).or_insert(0) += 1;
+    }
+
+    /// Return a summary of the queries since the last one, if any, and reset
+    /// the counts.
+    fn take_summary(&self) -> Option<String> {
+        let since_summary = std::mem::take(&mut self.counts.lock().since_summary);
+        if since_summary.is_empty() {
+            return None;
+        }
+
+        let total: u64 = since_summary.values().sum();
+        let mut series: Vec<(Series, u64)> = since_summary.into_iter().collect();
+        series.sort_by(|(a_series, a_count), (b_series, b_count)| {
+            b_count.cmp(a_count).then_with(|| a_series.cmp(b_series))
+        });
+
+        let top: Vec<String> = series
+            .iter()
+            .take(SUMMARY_TOP_SERIES)
+            .map(|((channel, version, arch), count)| {
+                format!(
+                    "channel={} version={} arch={}: {}",
+                    channel, version, arch, count
+                )
+            })
+            .collect();
+        Some(format!(
+            "{} total across {} series, top: [{}]",
+            total,
+            series.len(),
+            top.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarize_queries() {
+        let analytics = QueryAnalytics::new(DEFAULT_MAX_SERIES);
+        assert_eq!(analytics.take_summary(), None);
+
+        for query in &[
+            "/graph?channel=stable-4.14&version=4.14.1&arch=amd64&id=cluster-1",
+            "/graph?channel=stable-4.14&version=4.14.1&arch=amd64&id=cluster-2",
+            "/graph?channel=fast-4.15&version=4.15.0",
+        ] {
+            let req = actix_web::test::TestRequest::get()
+                .uri(query)
+                .to_http_request();
+            analytics.record(&req);
+        }
+
+        let summary = analytics.take_summary().unwrap();
+        assert_eq!(
+            summary,
+            "3 total across 2 series, top: [\
+             channel=stable-4.14 version=4.14.1 arch=amd64: 2, \
+             channel=fast-4.15 version=4.15.0 arch=none: 1]"
+        );
+        assert!(!summary.contains("cluster"));
+        assert_eq!(analytics.take_summary(), None);
+    }
+
+    #[test]
+    fn collapse_series_beyond_maximum() {
+        let analytics = QueryAnalytics::new(1);
+        let query = |channel: &str| {
+            analytics.record_query(
+                Some(channel.to_string()),
+                Some("4.14.1".to_string()),
+                Some("amd64".to_string()),
+            )
+        };
+        query("stable-4.14");
+        query("fast-4.14");
+        query("candidate-4.14");
+        query("stable-4.14");
+
+        let summary = analytics.take_summary().unwrap();
+        assert_eq!(
+            summary,
+            "4 total across 2 series, top: [\
+             channel=other version=other arch=other: 2, \
+             channel=stable-4.14 version=4.14.1 arch=amd64: 2]"
+        );
+    }
+}
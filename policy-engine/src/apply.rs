@@ -0,0 +1,134 @@
+//! Offline apply mode: run the plugin chain on a graph read from a file.
+//!
+//! This previews the graph served to clients for given parameters, e.g.
+//! before deploying changes to the graph data.
+
+use crate::config::AppSettings;
+use crate::graph::{is_upstream_plugin, latest_content_type, process_plugins};
+use cincinnati::plugins::internal::versioned_graph::VersionedGraph;
+use cincinnati::plugins::prelude::*;
+use commons::prelude_errors::*;
+use commons::GraphError;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+
+/// Apply the configured plugin chain to the input graph, writing the result
+/// to the configured output, or to stdout.
+pub(crate) async fn run(settings: &AppSettings, plugins: &'static [BoxedPlugin]) -> Fallible<()> {
+    let input = settings
+        .apply_graph_path
+        .as_ref()
+        .ok_or_else(|| format_err!("no input graph to apply plugins to"))?;
+    let graph = read_graph(input)?;
+
+    let graph_json = apply(
+        plugins,
+        graph,
+        &settings.apply_params,
+        &settings.mandatory_client_parameters,
+    )
+    .await
+    .map_err(|e| format_err!("failed to apply plugins: {}", e.value()))?;
+
+    match &settings.apply_output {
+        Some(path) => std::fs::write(path, &graph_json)
+            .context(format!("writing graph to {}", path.display()))?,
+        None => {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(graph_json.as_bytes())?;
+            stdout.flush()?;
+        }
+    };
+    Ok(())
+}
+
+/// Read a graph, either as served by graph-builder or by policy-engine.
+fn read_graph(path: &Path) -> Fallible<cincinnati::Graph> {
+    let json =
+        std::fs::read_to_string(path).context(format!("reading graph {}", path.display()))?;
+    let versioned: VersionedGraph =
+        serde_json::from_str(&json).context(format!("parsing graph {}", path.display()))?;
+    Ok(versioned.graph)
+}
+
+/// Run `plugins` on `graph` with the given client parameters, returning the
+/// graph document as served.
+///
+/// Plugins fetching the upstream graph are skipped.
+async fn apply(
+    plugins: &'static [BoxedPlugin],
+    graph: cincinnati::Graph,
+    params: &[(String, String)],
+    mandatory_params: &HashSet<String>,
+) -> Result<String, GraphError> {
+    let mut plugin_params: HashMap<String, String> = params.iter().cloned().collect();
+
+    let mut missing: Vec<String> = mandatory_params
+        .iter()
+        .filter(|param| !plugin_params.contains_key(*param))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        missing.sort();
+        return Err(GraphError::MissingParams(missing));
+    }
+    cincinnati::plugins::validate_plugin_parameters(plugins.iter(), &plugin_params)?;
+    plugin_params.insert(String::from("content_type"), latest_content_type());
+
+    let plugins = plugins.iter().filter(|plugin| !is_upstream_plugin(plugin));
+    let rendered = process_plugins(plugins, graph, plugin_params).await?;
+    Ok(rendered.graph_json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::{TestGraphBuilder, TestMetadata};
+
+    #[test]
+    fn apply_channel_filter() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let plugins: &'static [BoxedPlugin] = Box::leak(Box::new([
+            plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", "http://offline.url.test")
+            )?
+            .build_plugin(None)?,
+            plugin_config!(("name", ChannelFilterPlugin::PLUGIN_NAME))?.build_plugin(None)?,
+        ]));
+
+        let metadata: TestMetadata = vec![
+            (
+                0,
+                [(
+                    "io.openshift.upgrades.graph.release.channels".to_string(),
+                    "stable-4.14".to_string(),
+                )]
+                .iter()
+                .cloned()
+                .collect(),
+            ),
+            (1, Default::default()),
+        ];
+        let graph = TestGraphBuilder::new().with_metadata(metadata).build();
+        let mandatory: HashSet<String> = ["channel".to_string()].iter().cloned().collect();
+
+        let params = vec![("channel".to_string(), "stable-4.14".to_string())];
+        let served: serde_json::Value = serde_json::from_str(&rt.block_on(apply(
+            plugins,
+            graph.clone(),
+            &params,
+            &mandatory,
+        ))?)?;
+        assert_eq!(served["nodes"].as_array().map(Vec::len), Some(1));
+        assert_eq!(served["version"], 1);
+
+        match rt.block_on(apply(plugins, graph, &[], &mandatory)) {
+            Err(GraphError::MissingParams(params)) => assert_eq!(params, vec!["channel"]),
+            other => bail!("unexpected result {:?}", other.map(|_| ())),
+        };
+
+        Ok(())
+    }
+}
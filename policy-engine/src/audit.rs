@@ -0,0 +1,362 @@
+//! Audit log of graph requests.
+//!
+//! Each graph request is recorded with its client, query and outcome, and
+//! delivered by a background thread either to a file, as JSON lines, or to an
+//! HTTP sink, one JSON document per POST. Client identifiers and addresses are
+//! recorded according to the configured `PiiPolicy`.
+
+use actix_web::http::StatusCode;
+use actix_web::HttpRequest;
+use commons::prelude_errors::*;
+use custom_debug_derive::Debug as CustomDebug;
+use prometheus::{IntCounter, Registry};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::time::Duration;
+
+/// Number of records buffered before new ones are dropped.
+static QUEUE_CAPACITY: usize = 1024;
+
+/// Timeout for a single delivery to an HTTP sink.
+static DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref AUDIT_RECORDS: IntCounter = IntCounter::new(
+        "audit_records_total",
+        "Total number of audit records delivered"
+    )
+    .unwrap();
+    static ref AUDIT_RECORDS_FAILED: IntCounter = IntCounter::new(
+        "audit_records_failed_total",
+        "Total number of audit records dropped or which failed to be delivered"
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(AUDIT_RECORDS.clone()))?;
+    registry.register(Box::new(AUDIT_RECORDS_FAILED.clone()))?;
+    Ok(())
+}
+
+/// How client identifiers and addresses are recorded.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, SmartDefault)]
+#[serde(rename_all = "lowercase")]
+pub enum PiiPolicy {
+    /// Record them as received.
+    #[default]
+    Full,
+    /// Record the SHA-256 of client identifiers, and addresses truncated to
+    /// their /24 (IPv4) or /48 (IPv6) network.
+    Mask,
+    /// Do not record them.
+    Omit,
+}
+
+impl FromStr for PiiPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "full" => Ok(PiiPolicy::Full),
+            "mask" => Ok(PiiPolicy::Mask),
+            "omit" => Ok(PiiPolicy::Omit),
+            _ => bail!("unknown PII policy '{}'", s),
+        }
+    }
+}
+
+impl PiiPolicy {
+    /// Apply the policy to a client identifier.
+    fn client_id(self, id: String) -> Option<String> {
+        match self {
+            PiiPolicy::Full => Some(id),
+            PiiPolicy::Mask => Some(hex::encode(Sha256::digest(id.as_bytes()))),
+            PiiPolicy::Omit => None,
+        }
+    }
+
+    /// Apply the policy to a client address.
+    fn client_ip(self, ip: IpAddr) -> Option<IpAddr> {
+        match (self, ip) {
+            (PiiPolicy::Full, ip) => Some(ip),
+            (PiiPolicy::Mask, IpAddr::V4(ip)) => {
+                let [a, b, c, _] = ip.octets();
+                Some(IpAddr::from([a, b, c, 0]))
+            }
+            (PiiPolicy::Mask, IpAddr::V6(ip)) => {
+                let [a, b, c, ..] = ip.segments();
+                Some(IpAddr::from([a, b, c, 0, 0, 0, 0, 0]))
+            }
+            (PiiPolicy::Omit, _) => None,
+        }
+    }
+}
+
+/// Destination of audit records.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuditSink {
+    /// File to which records are appended.
+    File(PathBuf),
+    /// URL to which records are POSTed.
+    Http(reqwest::Url),
+}
+
+/// A single audited graph request.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(crate) struct AuditRecord {
+    /// Time of the request, in RFC 3339 format.
+    timestamp: String,
+    client_id: Option<String>,
+    client_ip: Option<IpAddr>,
+    channel: Option<String>,
+    version: Option<String>,
+    arch: Option<String>,
+    /// Response status code.
+    status: u16,
+    /// Entity tag of the served graph.
+    etag: Option<String>,
+}
+
+impl AuditRecord {
+    /// Build the record of a request, applying the PII policy.
+    fn new(req: &HttpRequest, status: StatusCode, etag: Option<&str>, pii: PiiPolicy) -> Self {
+        let mut record = AuditRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            client_id: None,
+            client_ip: None,
+            channel: None,
+            version: None,
+            arch: None,
+            status: status.as_u16(),
+            etag: etag.map(str::to_string),
+        };
+
+        for (key, value) in url::form_urlencoded::parse(req.query_string().as_bytes()) {
+            let value = Some(value.into_owned());
+            match key.as_ref() {
+                "id" => record.client_id = value.and_then(|id| pii.client_id(id)),
+                "channel" => record.channel = value,
+                "version" => record.version = value,
+                "arch" => record.arch = value,
+                _ => {}
+            }
+        }
+
+        record.client_ip = req
+            .connection_info()
+            .realip_remote_addr()
+            .and_then(parse_ip)
+            .and_then(|ip| pii.client_ip(ip));
+
+        record
+    }
+}
+
+/// Parse an address, with or without a port.
+fn parse_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| addr.parse::<IpAddr>())
+        .ok()
+}
+
+/// Handle to the background audit log writer.
+#[derive(Clone, CustomDebug)]
+pub(crate) struct AuditLogger {
+    pii: PiiPolicy,
+    #[debug(skip)]
+    tx: SyncSender<AuditRecord>,
+}
+
+impl AuditLogger {
+    /// Open the sink and start the background writer.
+    pub(crate) fn try_new(sink: AuditSink, pii: PiiPolicy) -> Fallible<Self> {
+        let writer = AuditWriter::try_new(sink)?;
+        let (tx, rx) = mpsc::sync_channel(QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || writer.run(rx))
+            .context("spawning audit log writer")?;
+
+        Ok(Self { pii, tx })
+    }
+
+    /// Record a graph request, dropping the record if the writer lags behind.
+    pub(crate) fn record(&self, req: &HttpRequest, status: StatusCode, etag: Option<&str>) {
+        let record = AuditRecord::new(req, status, etag, self.pii);
+        match self.tx.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                AUDIT_RECORDS_FAILED.inc();
+                warn!("audit log queue is full, dropping record");
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                AUDIT_RECORDS_FAILED.inc();
+                error!("audit log writer is not running, dropping record");
+            }
+        }
+    }
+}
+
+/// Writer delivering records to the sink.
+enum AuditWriter {
+    File(File),
+    Http {
+        url: reqwest::Url,
+        client: reqwest::Client,
+        runtime: tokio::runtime::Runtime,
+    },
+}
+
+impl AuditWriter {
+    fn try_new(sink: AuditSink) -> Fallible<Self> {
+        match sink {
+            AuditSink::File(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .context(format!("opening audit log {}", path.display()))?;
+                Ok(AuditWriter::File(file))
+            }
+            AuditSink::Http(url) => {
+                let client = commons::http::HttpClientBuilder::new()
+                    .timeout(DELIVERY_TIMEOUT)
+                    .build()
+                    .context("building audit log HTTP client")?;
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .context("building audit log runtime")?;
+                Ok(AuditWriter::Http {
+                    url,
+                    client,
+                    runtime,
+                })
+            }
+        }
+    }
+
+    /// Deliver records until all loggers are dropped.
+    fn run(mut self, rx: Receiver<AuditRecord>) {
+        for record in rx {
+            match self.deliver(&record) {
+                Ok(()) => AUDIT_RECORDS.inc(),
+                Err(e) => {
+                    AUDIT_RECORDS_FAILED.inc();
+                    error!("failed to deliver audit record: {:#}", e);
+                }
+            }
+        }
+    }
+
+    fn deliver(&mut self, record: &AuditRecord) -> Fallible<()> {
+        let mut payload = serde_json::to_vec(record)?;
+        match self {
+            AuditWriter::File(file) => {
+                payload.push(b'\n');
+                file.write_all(&payload)?;
+                file.flush()?;
+            }
+            AuditWriter::Http {
+                url,
+                client,
+                runtime,
+            } => {
+                let request = client
+                    .post(url.clone())
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(payload)
+                    .send();
+                runtime.block_on(request)?.error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn record_with_pii_policy() {
+        let req = actix_web::test::TestRequest::get()
+            .uri("/graph?channel=stable-4.14&id=cluster-1&version=4.14.1&arch=amd64")
+            .peer_addr("192.0.2.42:4242".parse().unwrap())
+            .to_http_request();
+
+        let record = AuditRecord::new(&req, StatusCode::OK, Some("\"abc\""), PiiPolicy::Full);
+        assert_eq!(record.client_id.as_deref(), Some("cluster-1"));
+        assert_eq!(record.client_ip, Some("192.0.2.42".parse().unwrap()));
+        assert_eq!(record.channel.as_deref(), Some("stable-4.14"));
+        assert_eq!(record.version.as_deref(), Some("4.14.1"));
+        assert_eq!(record.arch.as_deref(), Some("amd64"));
+        assert_eq!(record.status, 200);
+        assert_eq!(record.etag.as_deref(), Some("\"abc\""));
+
+        let record = AuditRecord::new(&req, StatusCode::OK, None, PiiPolicy::Mask);
+        assert_eq!(
+            record.client_id,
+            Some(hex::encode(Sha256::digest(b"cluster-1")))
+        );
+        assert_eq!(record.client_ip, Some("192.0.2.0".parse().unwrap()));
+
+        let record = AuditRecord::new(&req, StatusCode::BAD_REQUEST, None, PiiPolicy::Omit);
+        assert_eq!(record.client_id, None);
+        assert_eq!(record.client_ip, None);
+        assert_eq!(record.channel.as_deref(), Some("stable-4.14"));
+        assert_eq!(record.status, 400);
+    }
+
+    #[test]
+    fn mask_ipv6_address() {
+        let ip: IpAddr = "2001:db8:1234:5678::1".parse().unwrap();
+        assert_eq!(
+            PiiPolicy::Mask.client_ip(ip),
+            Some("2001:db8:1234::".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_pii_policy() -> Fallible<()> {
+        assert_eq!(PiiPolicy::from_str("mask")?, PiiPolicy::Mask);
+        assert!(PiiPolicy::from_str("MASK").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn deliver_to_file() -> Fallible<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        let mut writer = AuditWriter::try_new(AuditSink::File(file.path().to_path_buf()))?;
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/graph?channel=fast")
+            .to_http_request();
+        for _ in 0..2 {
+            let record = AuditRecord::new(&req, StatusCode::OK, None, PiiPolicy::Omit);
+            writer.deliver(&record)?;
+        }
+
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["channel"], "fast");
+        assert_eq!(lines[0]["status"], 200);
+        assert!(lines[0]["client_id"].is_null());
+
+        Ok(())
+    }
+}
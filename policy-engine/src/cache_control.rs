@@ -0,0 +1,179 @@
+//! Caching policies of the main service endpoints.
+//!
+//! Policies are advertised with `Cache-Control` and `Expires` headers, so
+//! that caches in front of the service, e.g. CDNs, store responses as long as
+//! configured. No header is sent for endpoints without a policy.
+
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use std::time::{Duration, SystemTime};
+
+/// Default interval between refreshes of the graph served by the endpoints.
+///
+/// This matches the lifetime of cached upstream graphs.
+pub const DEFAULT_GRAPH_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Caching policy of an endpoint.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CachePolicy {
+    /// Whether responses must not be stored at all.
+    pub no_store: bool,
+
+    /// Whether shared caches may store responses, otherwise private.
+    pub public: bool,
+
+    /// Lifetime of responses, in seconds.
+    pub max_age_secs: Option<u64>,
+
+    /// Lifetime of responses in shared caches, in seconds.
+    pub s_maxage_secs: Option<u64>,
+
+    /// Time during which stale responses may be served while revalidating, in seconds.
+    pub stale_while_revalidate_secs: Option<u64>,
+
+    /// Number of graph refresh intervals during which stale responses may be
+    /// served if the service fails.
+    pub stale_if_error_intervals: Option<u32>,
+}
+
+impl CachePolicy {
+    /// Return the `Cache-Control` directives of the policy, if any.
+    pub fn directives(&self, refresh_interval: Duration) -> Option<String> {
+        if self.no_store {
+            return Some("no-store".to_string());
+        }
+
+        let mut directives = vec![];
+        if let Some(max_age) = self.max_age_secs {
+            directives.push(if self.public { "public" } else { "private" }.to_string());
+            directives.push(format!("max-age={}", max_age));
+        } else if self.public {
+            directives.push("public".to_string());
+        }
+        if let Some(s_maxage) = self.s_maxage_secs {
+            directives.push(format!("s-maxage={}", s_maxage));
+        }
+        if let Some(secs) = self.stale_while_revalidate_secs {
+            directives.push(format!("stale-while-revalidate={}", secs));
+        }
+        if let Some(intervals) = self.stale_if_error_intervals {
+            directives.push(format!(
+                "stale-if-error={}",
+                refresh_interval.as_secs() * u64::from(intervals)
+            ));
+        }
+
+        if directives.is_empty() {
+            None
+        } else {
+            Some(directives.join(", "))
+        }
+    }
+}
+
+/// Caching policies of the main service endpoints.
+#[derive(Clone, Debug, SmartDefault, PartialEq, Eq)]
+pub struct CacheControl {
+    /// Interval between refreshes of the served graph, bounding stale responses.
+    #[default(DEFAULT_GRAPH_REFRESH_INTERVAL)]
+    pub refresh_interval: Duration,
+
+    /// Policy of the graph and graph signature endpoints.
+    pub graph: CachePolicy,
+
+    /// Policy of the channel groups endpoint.
+    pub channels: CachePolicy,
+
+    /// Policy of the OpenAPI document and graph schema endpoints.
+    pub openapi: CachePolicy,
+}
+
+impl CacheControl {
+    /// Insert the `Cache-Control` and `Expires` headers of `policy` into
+    /// those of a successful response.
+    pub fn insert_headers(&self, policy: &CachePolicy, headers: &mut HeaderMap) {
+        let directives = match policy.directives(self.refresh_interval) {
+            Some(directives) => directives,
+            None => return,
+        };
+        if let Ok(value) = HeaderValue::from_str(&directives) {
+            headers.insert(header::CACHE_CONTROL, value);
+        }
+
+        let expires = match (policy.no_store, policy.max_age_secs) {
+            (true, _) => Some(SystemTime::UNIX_EPOCH),
+            (false, Some(max_age)) => Some(SystemTime::now() + Duration::from_secs(max_age)),
+            (false, None) => None,
+        };
+        if let Some(expires) = expires {
+            if let Ok(value) = HeaderValue::from_str(&commons::http_date(expires)) {
+                headers.insert(header::EXPIRES, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_policy_directives() {
+        let refresh_interval = Duration::from_secs(60);
+        let directives = |policy: CachePolicy| policy.directives(refresh_interval);
+
+        assert_eq!(directives(CachePolicy::default()), None);
+        assert_eq!(
+            directives(CachePolicy {
+                no_store: true,
+                max_age_secs: Some(60),
+                ..Default::default()
+            }),
+            Some("no-store".to_string())
+        );
+        assert_eq!(
+            directives(CachePolicy {
+                public: true,
+                max_age_secs: Some(30),
+                s_maxage_secs: Some(60),
+                stale_while_revalidate_secs: Some(15),
+                stale_if_error_intervals: Some(10),
+                ..Default::default()
+            }),
+            Some(
+                "public, max-age=30, s-maxage=60, stale-while-revalidate=15, stale-if-error=600"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            directives(CachePolicy {
+                max_age_secs: Some(0),
+                ..Default::default()
+            }),
+            Some("private, max-age=0".to_string())
+        );
+    }
+
+    #[test]
+    fn insert_cache_headers() {
+        let cache_control = CacheControl {
+            graph: CachePolicy {
+                public: true,
+                max_age_secs: Some(300),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut headers = HeaderMap::new();
+        cache_control.insert_headers(&cache_control.openapi, &mut headers);
+        assert!(headers.is_empty());
+
+        cache_control.insert_headers(&cache_control.graph, &mut headers);
+        assert_eq!(
+            headers.get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=300"
+        );
+        assert!(headers.contains_key(header::EXPIRES));
+    }
+}
@@ -0,0 +1,186 @@
+//! Client capabilities.
+//!
+//! Clients list the graph features they support in the `capabilities` query
+//! parameter, e.g. `capabilities=conditional-edges,multi-arch`, so that new
+//! features can be served without breaking the clients which predate them.
+//! The negotiated capabilities, i.e. those both requested and supported, are
+//! reported in the `x-cincinnati-capabilities` response header.
+//!
+//! Clients which do not send the parameter are served all the supported
+//! capabilities, as before capabilities were negotiated. Capabilities unknown
+//! to the policy-engine are ignored, so that clients can announce features
+//! which are not served yet.
+
+use cincinnati::Graph;
+use commons::GraphError;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// Query parameter listing the capabilities of the client, comma-separated.
+pub(crate) static CAPABILITIES_PARAM: &str = "capabilities";
+
+/// Response header listing the negotiated capabilities.
+pub(crate) static CAPABILITIES_HEADER: &str = "x-cincinnati-capabilities";
+
+/// Conditional edges, served in the `conditionalEdges` field of graphs.
+pub(crate) static CONDITIONAL_EDGES: &str = "conditional-edges";
+
+/// Graphs of multi-architecture payloads, served for `arch=multi`.
+pub(crate) static MULTI_ARCH: &str = "multi-arch";
+
+/// Capabilities supported by the policy-engine.
+static SUPPORTED: &[&str] = &[CONDITIONAL_EDGES, MULTI_ARCH];
+
+/// Query parameter selecting the architecture of the graph.
+static ARCH_PARAM: &str = "arch";
+
+/// Architecture of the graphs of multi-architecture payloads.
+static MULTI_ARCH_VALUE: &str = "multi";
+
+/// Capabilities negotiated with a client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Capabilities(BTreeSet<&'static str>);
+
+impl Default for Capabilities {
+    /// All the supported capabilities, for clients which do not list theirs.
+    fn default() -> Self {
+        Self(SUPPORTED.iter().copied().collect())
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.0.iter().copied().collect();
+        f.write_str(&names.join(","))
+    }
+}
+
+impl Capabilities {
+    /// Return the supported capabilities among the comma-separated `value`.
+    pub(crate) fn parse(value: &str) -> Self {
+        Self(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter_map(|name| {
+                    SUPPORTED
+                        .iter()
+                        .copied()
+                        .find(|supported| *supported == name)
+                })
+                .collect(),
+        )
+    }
+
+    /// Return the capabilities listed in the plugin parameters, all if unset.
+    pub(crate) fn from_params(plugin_params: &HashMap<String, String>) -> Self {
+        plugin_params
+            .get(CAPABILITIES_PARAM)
+            .map(String::as_str)
+            .map_or_else(Self::default, Self::parse)
+    }
+
+    /// Negotiate the capabilities requested in the plugin parameters.
+    ///
+    /// The parameter is rewritten to the negotiated capabilities, or removed
+    /// if all are, so that requests negotiating the same capabilities share
+    /// their rendered graphs.
+    pub(crate) fn negotiate(
+        plugin_params: &mut HashMap<String, String>,
+    ) -> Result<Self, GraphError> {
+        let capabilities = Self::from_params(plugin_params);
+        if !capabilities.contains(MULTI_ARCH)
+            && plugin_params.get(ARCH_PARAM).map(String::as_str) == Some(MULTI_ARCH_VALUE)
+        {
+            return Err(GraphError::InvalidParams(format!(
+                "{}={} requires the {} capability",
+                ARCH_PARAM, MULTI_ARCH_VALUE, MULTI_ARCH
+            )));
+        }
+
+        if capabilities == Self::default() {
+            plugin_params.remove(CAPABILITIES_PARAM);
+        } else {
+            plugin_params.insert(CAPABILITIES_PARAM.to_string(), capabilities.to_string());
+        }
+        Ok(capabilities)
+    }
+
+    /// Whether the capability named `name` was negotiated.
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.0.contains(name)
+    }
+
+    /// Remove from `graph` the fields of the capabilities which were not negotiated.
+    pub(crate) fn apply(&self, graph: &mut Graph) {
+        if !self.contains(CONDITIONAL_EDGES) {
+            graph.remove_conditional_edges();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn negotiate_capabilities() -> Result<(), GraphError> {
+        assert_eq!(
+            Capabilities::default().to_string(),
+            "conditional-edges,multi-arch"
+        );
+        assert_eq!(
+            Capabilities::parse(" multi-arch, future-feature,,conditional-edges"),
+            Capabilities::default()
+        );
+        assert_eq!(Capabilities::parse("").to_string(), "");
+
+        // Clients negotiating all capabilities share the graphs of those listing none.
+        let mut all = params(&[("channel", "stable-4.14")]);
+        assert_eq!(Capabilities::negotiate(&mut all)?, Capabilities::default());
+        let mut listed = params(&[
+            ("channel", "stable-4.14"),
+            (CAPABILITIES_PARAM, "multi-arch,conditional-edges"),
+        ]);
+        assert_eq!(
+            Capabilities::negotiate(&mut listed)?,
+            Capabilities::default()
+        );
+        assert_eq!(all, listed);
+
+        let mut some = params(&[(CAPABILITIES_PARAM, "multi-arch,future-feature")]);
+        let capabilities = Capabilities::negotiate(&mut some)?;
+        assert!(capabilities.contains(MULTI_ARCH));
+        assert!(!capabilities.contains(CONDITIONAL_EDGES));
+        assert_eq!(some, params(&[(CAPABILITIES_PARAM, "multi-arch")]));
+        assert_eq!(Capabilities::from_params(&some), capabilities);
+
+        let mut multi = params(&[("arch", "multi"), (CAPABILITIES_PARAM, "conditional-edges")]);
+        assert!(matches!(
+            Capabilities::negotiate(&mut multi),
+            Err(GraphError::InvalidParams(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn apply_capabilities() {
+        let mut graph = cincinnati::testing::generate_graph(true, false);
+        Capabilities::default().apply(&mut graph);
+        assert!(graph.has_conditional_edges());
+
+        Capabilities::parse(MULTI_ARCH).apply(&mut graph);
+        assert!(!graph.has_conditional_edges());
+        let graph_json = serde_json::to_value(&graph).unwrap();
+        assert!(graph_json.get("conditionalEdges").is_none());
+        assert!(graph_json.get("nodes").is_some());
+    }
+}
@@ -0,0 +1,124 @@
+//! Channel groups endpoint.
+//!
+//! Groups are collected from the upstream graph, before any policy plugin
+//! runs, so that they cover all channels regardless of client parameters.
+
+use crate::graph::{self, latest_content_type};
+use crate::AppState;
+use actix_web::{HttpRequest, HttpResponse};
+use cincinnati::channel_groups::{channel_groups, ChannelGroup};
+use cincinnati::plugins::internal::versioned_graph::VersionedGraph;
+use commons::tracing::get_tracer;
+use commons::{api_response_error, GraphError};
+use opentelemetry::trace::{mark_span_as_active, Tracer};
+use std::collections::HashMap;
+
+/// Metadata key prefix of the channels of a release.
+static KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+
+/// Channel groups document.
+#[derive(Debug, Serialize)]
+struct ChannelGroups {
+    groups: Vec<ChannelGroup>,
+}
+
+/// Serve the channel groups of the upstream graph.
+pub(crate) async fn index(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    _index(&app_data)
+        .await
+        .map_err(|e| api_response_error(&req, e))
+}
+
+async fn _index(app_data: &AppState) -> Result<HttpResponse, GraphError> {
+    let span = get_tracer().start("channel_groups");
+    let _active_span = mark_span_as_active(span);
+
+    let params: HashMap<String, String> = [(String::from("content_type"), latest_content_type())]
+        .iter()
+        .cloned()
+        .collect();
+    let upstream = app_data
+        .plugins
+        .iter()
+        .filter(|plugin| graph::is_upstream_plugin(plugin));
+    let rendered =
+        graph::process_upstream_plugins(app_data.pipeline.as_ref(), upstream, params).await?;
+
+    let mut versioned: VersionedGraph = serde_json::from_str(&rendered.graph_json)
+        .map_err(|e| GraphError::FailedJsonIn(e.to_string()))?;
+    let groups = channel_groups(&mut versioned.graph, KEY_PREFIX);
+
+    let mut response = HttpResponse::Ok();
+    commons::insert_stale_headers(&mut response, rendered.stale_since.as_deref());
+    let mut response = response.json(ChannelGroups { groups });
+    app_data
+        .cache_control
+        .insert_headers(&app_data.cache_control.channels, response.headers_mut());
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::tests::common_init;
+    use actix_web::body::MessageBody;
+    use cincinnati::plugins::prelude::*;
+
+    #[test]
+    fn serve_channel_groups() -> Result<(), Error> {
+        let rt = common_init();
+
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[
+                plugin_config!(
+                    ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                    (
+                        "upstream",
+                        &format!("{}/channel-groups", mockito::server_url())
+                    )
+                )?,
+                plugin_config!(("name", ChannelFilterPlugin::PLUGIN_NAME))?,
+            ],
+            None,
+        )?;
+        let state = AppState {
+            mandatory_params: vec!["channel".to_string()].into_iter().collect(),
+            plugins: Box::leak(Box::new(plugins)),
+            ..Default::default()
+        };
+
+        let _m = mockito::mock("GET", "/channel-groups")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"nodes":[
+                    {"version":"4.1.0","payload":"image/4.1.0","metadata":{
+                        "io.openshift.upgrades.graph.release.channels":"fast-4.1,stable-4.1"}},
+                    {"version":"4.2.0","payload":"image/4.2.0","metadata":{
+                        "io.openshift.upgrades.graph.release.channels":"fast-4.2"}}
+                ],"edges":[[0,1]]}"#,
+            )
+            .create();
+
+        // The channel is not required, as policy plugins do not run.
+        let req = actix_web::test::TestRequest::get().to_http_request();
+        let resp = rt.block_on(index(req, actix_web::web::Data::new(state)))?;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let body = resp.into_body().try_into_bytes().unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(
+            document,
+            serde_json::json!({"groups": [
+                {"name": "fast", "channels": ["fast-4.1", "fast-4.2"]},
+                {"name": "stable", "channels": ["stable-4.1"]},
+            ]})
+        );
+
+        Ok(())
+    }
+}
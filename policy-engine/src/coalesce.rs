@@ -0,0 +1,198 @@
+//! Coalescing of identical concurrent computations.
+//!
+//! The first caller for a given key runs the computation, while callers
+//! arriving before it completes wait for its result instead of running it
+//! again. If the first caller is cancelled, one of the waiters takes over.
+
+use commons::prelude_errors::*;
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+use prometheus::{IntCounter, Registry};
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Arc;
+
+lazy_static! {
+    static ref COALESCED_REQUESTS: IntCounter = IntCounter::new(
+        "coalesced_requests_total",
+        "Total number of requests served with the result of an identical concurrent request"
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(COALESCED_REQUESTS.clone()))?;
+    Ok(())
+}
+
+/// Waiters for the result of each in-flight computation.
+type Inflight<K, V> = Mutex<HashMap<K, Vec<oneshot::Sender<V>>>>;
+
+/// Shared set of in-flight computations, keyed by their inputs.
+pub(crate) struct Coalescer<K, V> {
+    inflight: Arc<Inflight<K, V>>,
+}
+
+impl<K, V> Clone for Coalescer<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inflight: self.inflight.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Default for Coalescer<K, V> {
+    fn default() -> Self {
+        Self {
+            inflight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<K, V> std::fmt::Debug for Coalescer<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Coalescer")
+            .field("inflight", &self.inflight.lock().len())
+            .finish()
+    }
+}
+
+impl<K, V> Coalescer<K, V>
+where
+    K: Clone + Eq + Hash,
+    V: Clone,
+{
+    /// Run `compute` for `key`, or wait for the result of an identical
+    /// computation already in flight.
+    pub(crate) async fn run<F, Fut>(&self, key: K, compute: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let mut compute = Some(compute);
+        loop {
+            let waiter = {
+                let mut inflight = self.inflight.lock();
+                match inflight.get_mut(&key) {
+                    Some(waiters) => {
+                        let (tx, rx) = oneshot::channel();
+                        waiters.push(tx);
+                        Some(rx)
+                    }
+                    None => {
+                        inflight.insert(key.clone(), Vec::new());
+                        None
+                    }
+                }
+            };
+
+            match waiter {
+                Some(rx) => {
+                    if let Ok(value) = rx.await {
+                        COALESCED_REQUESTS.inc();
+                        return value;
+                    }
+                    // The running computation was cancelled, try again.
+                }
+                None => {
+                    let leader = Leader {
+                        inflight: &self.inflight,
+                        key: &key,
+                        done: false,
+                    };
+                    let compute = compute.take().expect("computation already started");
+                    let value = compute().await;
+                    for tx in leader.finish() {
+                        let _ = tx.send(value.clone());
+                    }
+                    return value;
+                }
+            }
+        }
+    }
+}
+
+/// Registration of the running computation, removed when dropped.
+struct Leader<'a, K: Eq + Hash, V> {
+    inflight: &'a Inflight<K, V>,
+    key: &'a K,
+    done: bool,
+}
+
+impl<K: Eq + Hash, V> Leader<'_, K, V> {
+    /// Unregister the computation, returning its waiters.
+    fn finish(mut self) -> Vec<oneshot::Sender<V>> {
+        self.done = true;
+        self.inflight.lock().remove(self.key).unwrap_or_default()
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for Leader<'_, K, V> {
+    fn drop(&mut self) {
+        if !self.done {
+            // Dropping the senders wakes up the waiters, one of which takes over.
+            self.inflight.lock().remove(self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn coalesce_concurrent_computations() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let coalescer = Coalescer::<&str, usize>::default();
+        let runs = &AtomicUsize::new(0);
+        let (gate_tx, gate_rx) = oneshot::channel::<()>();
+
+        let compute = move || async move {
+            runs.fetch_add(1, Ordering::SeqCst);
+            42
+        };
+        let (first, second, third, ()) = rt.block_on(futures::future::join4(
+            coalescer.run("stable-4.14", move || async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                gate_rx.await.ok();
+                42
+            }),
+            coalescer.run("stable-4.14", compute),
+            coalescer.run("fast-4.14", compute),
+            async {
+                gate_tx.send(()).unwrap();
+            },
+        ));
+
+        assert_eq!((first, second, third), (42, 42, 42));
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(coalescer.inflight.lock().len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn take_over_cancelled_computation() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let coalescer = Coalescer::<&str, usize>::default();
+
+        let value = rt.block_on(async {
+            let mut cancelled = Box::pin(coalescer.run("stable-4.14", futures::future::pending));
+            let waiter = coalescer.run("stable-4.14", || async { 7 });
+            futures::pin_mut!(waiter);
+
+            assert!(futures::poll!(cancelled.as_mut()).is_pending());
+            assert!(futures::poll!(waiter.as_mut()).is_pending());
+            drop(cancelled);
+            waiter.await
+        });
+
+        assert_eq!(value, 7);
+        assert_eq!(coalescer.inflight.lock().len(), 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+//! Cohorts: plugin chains served to a fraction of the clients.
+//!
+//! Clients are assigned to cohorts deterministically by their identifier, the
+//! `id` query parameter, so that a cluster keeps being served the same chain
+//! as long as the configuration does not change. Clients without identifier,
+//! or outside all cohorts, are served the main plugin chain.
+
+use cincinnati::plugins::BoxedPlugin;
+use commons::prelude_errors::*;
+use prometheus::{IntCounterVec, Registry};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Response header naming the cohort a client was assigned to.
+pub(crate) static COHORT_HEADER: &str = "x-cincinnati-cohort";
+
+/// Name of the cohort of clients served the main plugin chain.
+pub(crate) static DEFAULT_COHORT: &str = "default";
+
+/// Number of buckets clients are hashed into, i.e. a granularity of 0.01%.
+const BUCKETS: u64 = 10_000;
+
+lazy_static! {
+    static ref COHORT_REQS: IntCounterVec = IntCounterVec::new(
+        prometheus::Opts::new(
+            "graph_cohort_requests_total",
+            "Total number of graph requests, by cohort"
+        ),
+        &["cohort"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(COHORT_REQS.clone()))?;
+    Ok(())
+}
+
+/// A cohort with its own plugin chain.
+#[derive(Debug)]
+pub(crate) struct Cohort {
+    /// Name of the cohort, reported to clients.
+    pub(crate) name: String,
+    /// Number of buckets assigned to the cohort.
+    buckets: u64,
+    /// Plugin chain served to the cohort.
+    pub(crate) plugins: &'static [BoxedPlugin],
+}
+
+/// Cohorts, in assignment order.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Cohorts {
+    salt: String,
+    cohorts: Arc<Vec<Cohort>>,
+}
+
+impl Cohorts {
+    /// Create cohorts from their name, percentage of clients and plugins.
+    ///
+    /// Clients are hashed together with `salt`, so that changing it
+    /// reshuffles the assignments.
+    pub(crate) fn new(
+        salt: String,
+        cohorts: impl IntoIterator<Item = (String, f64, &'static [BoxedPlugin])>,
+    ) -> Self {
+        let cohorts = cohorts
+            .into_iter()
+            .map(|(name, percent, plugins)| Cohort {
+                name,
+                buckets: (percent * (BUCKETS as f64) / 100.0).round() as u64,
+                plugins,
+            })
+            .collect();
+        Self {
+            salt,
+            cohorts: Arc::new(cohorts),
+        }
+    }
+
+    /// Whether any cohort is configured.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.cohorts.is_empty()
+    }
+
+    /// Return the cohort of the client with the given identifier, if any.
+    pub(crate) fn assign(&self, client_id: Option<&str>) -> Option<&Cohort> {
+        let client_id = client_id.filter(|id| !id.is_empty())?;
+        let bucket = self.bucket(client_id);
+
+        let mut upper = 0;
+        self.cohorts.iter().find(|cohort| {
+            upper += cohort.buckets;
+            bucket < upper
+        })
+    }
+
+    /// Count a request served to `cohort`, or to the main plugin chain.
+    pub(crate) fn record(&self, cohort: Option<&Cohort>) {
+        if self.is_empty() {
+            return;
+        }
+        let name = cohort.map_or(DEFAULT_COHORT, |cohort| cohort.name.as_str());
+        COHORT_REQS.with_label_values(&[name]).inc();
+    }
+
+    fn bucket(&self, client_id: &str) -> u64 {
+        let digest = Sha256::new()
+            .chain_update(self.salt.as_bytes())
+            .chain_update(b":")
+            .chain_update(client_id.as_bytes())
+            .finalize();
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&digest[..8]);
+        u64::from_be_bytes(prefix) % BUCKETS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cohorts(percents: &[(&str, f64)]) -> Cohorts {
+        Cohorts::new(
+            "salt".to_string(),
+            percents
+                .iter()
+                .map(|(name, percent)| (name.to_string(), *percent, &[] as &'static [BoxedPlugin])),
+        )
+    }
+
+    #[test]
+    fn assign_cohorts() {
+        let cohorts = cohorts(&[("canary", 10.0), ("beta", 20.0)]);
+        let restarted = self::cohorts(&[("canary", 10.0), ("beta", 20.0)]);
+
+        let mut counts = std::collections::HashMap::new();
+        for i in 0..10_000 {
+            let id = format!("cluster-{}", i);
+            let name = cohorts
+                .assign(Some(&id))
+                .map_or(DEFAULT_COHORT, |cohort| cohort.name.as_str());
+            *counts.entry(name.to_string()).or_insert(0) += 1;
+
+            // Assignments do not change across restarts.
+            assert_eq!(
+                cohorts.assign(Some(&id)).map(|cohort| &cohort.name),
+                restarted.assign(Some(&id)).map(|cohort| &cohort.name)
+            );
+        }
+        for (name, expected) in &[("canary", 1_000), ("beta", 2_000), ("default", 7_000)] {
+            let count = counts[*name];
+            assert!(
+                (count - *expected).abs() < 300,
+                "{} clients in cohort {}",
+                count,
+                name
+            );
+        }
+
+        assert!(cohorts.assign(None).is_none());
+        assert!(cohorts.assign(Some("")).is_none());
+    }
+
+    #[test]
+    fn assign_all_clients() {
+        let cohorts = cohorts(&[("everyone", 100.0)]);
+        assert!((0..1_000).all(|i| cohorts.assign(Some(&i.to_string())).is_some()));
+
+        let cohorts = Cohorts::default();
+        assert!(cohorts.is_empty());
+        assert!(cohorts.assign(Some("cluster")).is_none());
+    }
+}
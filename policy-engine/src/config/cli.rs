@@ -4,6 +4,7 @@ use super::options;
 use super::AppSettings;
 use commons::prelude_errors::*;
 use commons::MergeOptions;
+use std::path::PathBuf;
 
 /// CLI configuration flags, top-level.
 #[derive(Debug, StructOpt)]
@@ -16,6 +17,23 @@ pub struct CliOptions {
     #[structopt(short = "c")]
     pub config_path: Option<String>,
 
+    /// Apply the plugin chain to the graph in this file, write the result to stdout and exit
+    #[structopt(name = "apply", long = "apply")]
+    pub apply_graph_path: Option<PathBuf>,
+
+    /// Client parameter in apply mode, as KEY=VALUE (repeatable)
+    #[structopt(
+        long = "apply.param",
+        requires = "apply",
+        number_of_values = 1,
+        parse(try_from_str = parse_key_value)
+    )]
+    pub apply_params: Vec<(String, String)>,
+
+    /// Write the graph to this file in apply mode, instead of stdout
+    #[structopt(long = "apply.output", requires = "apply")]
+    pub apply_output: Option<PathBuf>,
+
     // Status service options
     #[structopt(flatten)]
     pub service: options::ServiceOptions,
@@ -31,6 +49,34 @@ pub struct CliOptions {
     // Cincinnati upstream options
     #[structopt(flatten)]
     pub upstream_cincinnati: options::UpCincinnatiOptions,
+
+    // Audit log options
+    #[structopt(flatten)]
+    pub audit: options::AuditOptions,
+
+    // Request shadowing options
+    #[structopt(flatten)]
+    pub shadow: options::ShadowOptions,
+
+    // Query analytics options
+    #[structopt(flatten)]
+    pub analytics: options::AnalyticsOptions,
+
+    // Standalone mode options
+    #[structopt(flatten)]
+    pub standalone: options::StandaloneOptions,
+
+    // Rendered graph variant store options
+    #[structopt(flatten)]
+    pub variants: options::VariantsOptions,
+}
+
+/// Parse a `KEY=VALUE` pair.
+fn parse_key_value(pair: &str) -> Fallible<(String, String)> {
+    match pair.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => bail!("expected KEY=VALUE, got '{}'", pair),
+    }
 }
 
 impl MergeOptions<CliOptions> for AppSettings {
@@ -42,9 +88,18 @@ impl MergeOptions<CliOptions> for AppSettings {
             _ => log::LevelFilter::Trace,
         };
 
+        assign_if_some!(self.apply_graph_path, opts.apply_graph_path);
+        self.apply_params.extend(opts.apply_params);
+        assign_if_some!(self.apply_output, opts.apply_output);
+
         self.try_merge(Some(opts.service))?;
         self.try_merge(Some(opts.status))?;
         self.try_merge(Some(opts.upstream_cincinnati))?;
+        self.try_merge(Some(opts.audit))?;
+        self.try_merge(Some(opts.shadow))?;
+        self.try_merge(Some(opts.analytics))?;
+        self.try_merge(Some(opts.standalone))?;
+        self.try_merge(Some(opts.variants))?;
 
         Ok(())
     }
@@ -73,6 +128,35 @@ mod tests {
         assert_eq!(svc_port_cli.service.port, Some(9999));
     }
 
+    #[test]
+    fn cli_apply() {
+        let args = vec![
+            "argv0",
+            "--apply",
+            "graph.json",
+            "--apply.param",
+            "channel=stable-4.14",
+            "--apply.param",
+            "arch=amd64",
+        ];
+        let cli = CliOptions::from_iter_safe(args).unwrap();
+        let mut settings = AppSettings::default();
+        settings.try_merge(cli).unwrap();
+        assert_eq!(settings.apply_graph_path, Some("graph.json".into()));
+        assert_eq!(
+            settings.apply_params,
+            vec![
+                ("channel".to_string(), "stable-4.14".to_string()),
+                ("arch".to_string(), "amd64".to_string()),
+            ]
+        );
+
+        let args = vec!["argv0", "--apply", "graph.json", "--apply.param", "channel"];
+        assert!(CliOptions::from_iter_safe(args).is_err());
+        let args = vec!["argv0", "--apply.output", "out.json"];
+        assert!(CliOptions::from_iter_safe(args).is_err());
+    }
+
     #[test]
     fn cli_merge_settings() {
         let upstream = "https://example.com";
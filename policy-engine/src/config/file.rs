@@ -1,10 +1,12 @@
 //! TOML file configuration options.
 
 use super::options;
+use super::settings::CohortSettings;
 use super::AppSettings;
 use commons::de::de_loglevel;
 use commons::prelude_errors::*;
 use commons::MergeOptions;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::Read;
 use std::{fs, io, path};
 
@@ -15,6 +17,9 @@ pub struct FileOptions {
     #[serde(default = "Option::default", deserialize_with = "de_loglevel")]
     pub verbosity: Option<log::LevelFilter>,
 
+    /// Product profile, by name or as a table.
+    pub profile: Option<cincinnati::ProductProfile>,
+
     /// Upstream options.
     pub upstream: Option<UpstreamOptions>,
 
@@ -26,6 +31,54 @@ pub struct FileOptions {
 
     /// Status service options.
     pub status: Option<options::StatusOptions>,
+
+    /// Audit log options.
+    pub audit: Option<options::AuditOptions>,
+
+    /// Request shadowing options.
+    pub shadow: Option<options::ShadowOptions>,
+
+    /// Query analytics options.
+    pub analytics: Option<options::AnalyticsOptions>,
+
+    /// Caching policies options.
+    pub cache_control: Option<options::CacheControlOptions>,
+
+    /// Concurrency limits options.
+    pub concurrency_limits: Option<options::ConcurrencyLimitsOptions>,
+
+    /// Metrics options.
+    pub metrics: Option<options::MetricsOptions>,
+
+    /// Path aliases options.
+    pub paths: Option<options::PathAliasesOptions>,
+
+    /// Constraints on the values of mandatory client parameters, by parameter.
+    pub client_parameters: Option<BTreeMap<String, commons::params::ParamConstraint>>,
+
+    /// Default values of query parameters omitted by clients, by parameter.
+    pub default_params: Option<BTreeMap<String, String>>,
+
+    /// Legacy query conventions options.
+    pub legacy: Option<options::LegacyParamsOptions>,
+
+    /// Cohorts served their own policy plugins.
+    pub cohorts: Option<Vec<CohortOptions>>,
+
+    /// Clients served a fixed graph for a period.
+    pub pins: Option<Vec<PinOptions>>,
+
+    /// Standalone mode options.
+    pub standalone: Option<options::StandaloneOptions>,
+
+    /// Rendered graph variant store options.
+    pub variants: Option<options::VariantsOptions>,
+
+    /// Startup warm-up options.
+    pub warmup: Option<WarmupOptions>,
+
+    /// Feature flags, by name.
+    pub features: Option<BTreeMap<String, bool>>,
 }
 
 impl FileOptions {
@@ -56,10 +109,27 @@ impl MergeOptions<Option<FileOptions>> for AppSettings {
     fn try_merge(&mut self, opts: Option<FileOptions>) -> Fallible<()> {
         if let Some(file) = opts {
             assign_if_some!(self.verbosity, file.verbosity);
+            assign_if_some!(self.profile, file.profile);
             self.try_merge(file.policy)?;
             self.try_merge(file.service)?;
             self.try_merge(file.status)?;
             self.try_merge(file.upstream)?;
+            self.try_merge(file.audit)?;
+            self.try_merge(file.shadow)?;
+            self.try_merge(file.analytics)?;
+            self.try_merge(file.cache_control)?;
+            self.try_merge(file.concurrency_limits)?;
+            self.try_merge(file.metrics)?;
+            self.try_merge(file.paths)?;
+            self.try_merge(file.legacy)?;
+            self.try_merge(file.cohorts)?;
+            self.try_merge(file.pins)?;
+            self.try_merge(file.standalone)?;
+            self.try_merge(file.variants)?;
+            self.try_merge(file.warmup)?;
+            assign_if_some!(self.features, file.features);
+            assign_if_some!(self.client_parameters, file.client_parameters);
+            assign_if_some!(self.default_params, file.default_params);
         }
         Ok(())
     }
@@ -77,6 +147,117 @@ impl MergeOptions<Option<Vec<toml::Value>>> for AppSettings {
     }
 }
 
+/// Options of a cohort.
+#[derive(Debug, Deserialize)]
+pub struct CohortOptions {
+    /// Cohort name, reported to clients.
+    pub name: String,
+
+    /// Percentage of the clients assigned to the cohort.
+    pub percent: f64,
+
+    /// Policy plugins options of the cohort.
+    pub policy: Vec<toml::Value>,
+}
+
+impl MergeOptions<Option<Vec<CohortOptions>>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<Vec<CohortOptions>>) -> Fallible<()> {
+        if let Some(cohorts) = opts {
+            for cohort in cohorts {
+                let plugin_settings = cohort
+                    .policy
+                    .into_iter()
+                    .map(cincinnati::plugins::catalog::deserialize_config)
+                    .collect::<Fallible<Vec<_>>>()
+                    .context(format!("parsing policy of cohort '{}'", cohort.name))?;
+                self.cohorts.push(CohortSettings {
+                    name: cohort.name,
+                    percent: cohort.percent,
+                    plugin_settings,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Options of a pin.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PinOptions {
+    /// Pin name, reported to clients.
+    pub name: String,
+
+    /// Identifiers of the pinned clients.
+    pub ids: BTreeSet<String>,
+
+    /// Start of the pin, as an RFC 3339 timestamp.
+    pub from: Option<String>,
+
+    /// Expiry of the pin, as an RFC 3339 timestamp.
+    pub until: String,
+
+    /// Identifier of the graph-builder snapshot served to the pinned clients.
+    pub snapshot: Option<String>,
+}
+
+impl MergeOptions<Option<Vec<PinOptions>>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<Vec<PinOptions>>) -> Fallible<()> {
+        fn parse_timestamp(pin: &str, timestamp: &str) -> Fallible<std::time::SystemTime> {
+            let timestamp = chrono::DateTime::parse_from_rfc3339(timestamp).context(format!(
+                "invalid timestamp '{}' of pin '{}'",
+                timestamp, pin
+            ))?;
+            Ok(timestamp.into())
+        }
+
+        if let Some(pins) = opts {
+            for pin in pins {
+                let from = pin
+                    .from
+                    .as_deref()
+                    .map(|from| parse_timestamp(&pin.name, from))
+                    .transpose()?;
+                let until = parse_timestamp(&pin.name, &pin.until)?;
+                self.pins.push(crate::pins::PinSettings {
+                    name: pin.name,
+                    ids: pin.ids,
+                    from,
+                    until,
+                    snapshot: pin.snapshot,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Options of the startup warm-up.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WarmupOptions {
+    /// Number of variants to render, from the first one.
+    pub top: Option<usize>,
+
+    /// Variants to render, in decreasing popularity.
+    #[serde(default)]
+    pub variants: Vec<crate::warmup::WarmupVariant>,
+}
+
+impl MergeOptions<Option<WarmupOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<WarmupOptions>) -> Fallible<()> {
+        if let Some(warmup) = opts {
+            let mut variants = warmup.variants;
+            if let Some(top) = warmup.top {
+                ensure!(top > 0, "warm-up top must be greater than 0");
+                variants.truncate(top);
+            }
+            self.warmup_variants = variants;
+        }
+        Ok(())
+    }
+}
+
 /// Options for upstream fetcher.
 #[derive(Debug, Deserialize)]
 pub struct UpstreamOptions {
@@ -124,6 +305,91 @@ mod tests {
         assert_eq!(settings.status_port, 2222);
     }
 
+    #[test]
+    fn toml_listen() {
+        let toml_input = "[service]\nlisten = [\"0.0.0.0:8081\", \"[::]:8081\"]\n[status]\nlisten = [\"[::]:9081\", \"[::]:8081\"]";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        let mut settings = AppSettings::default();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.listen_addrs(),
+            vec![
+                "0.0.0.0:8081".parse().unwrap(),
+                "[::]:8081".parse().unwrap()
+            ]
+        );
+        assert_eq!(settings.status_listen_addrs().len(), 2);
+        assert!(AppSettings::try_from_toml(toml_input).is_err());
+    }
+
+    #[test]
+    fn toml_upstream_auth() {
+        let mut settings = AppSettings::default();
+        assert!(settings.upstream_bearer_token_path.is_none());
+
+        let toml_input = r#"
+            [upstream.cincinnati]
+            url = "https://graph-builder.example.com/graph"
+            bearer_token_path = "/var/run/secrets/upstream/token"
+            ca_cert_path = "/var/run/secrets/upstream/ca.crt"
+            discovery = "dns"
+            balancing = "weighted"
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.upstream_bearer_token_path,
+            Some("/var/run/secrets/upstream/token".into())
+        );
+        assert_eq!(
+            settings.upstream_ca_cert_path,
+            Some("/var/run/secrets/upstream/ca.crt".into())
+        );
+        assert!(settings.upstream_client_cert_path.is_none());
+        assert_eq!(settings.upstream_discovery.as_deref(), Some("dns"));
+        assert_eq!(settings.upstream_balancing.as_deref(), Some("weighted"));
+    }
+
+    #[test]
+    fn toml_upstream_limits() {
+        let mut settings = AppSettings::default();
+        assert!(settings.upstream_max_response_bytes.is_none());
+
+        let toml_input = r#"
+            [upstream.cincinnati]
+            max_response_bytes = 1048576
+            max_decode_secs = 10
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.upstream_max_response_bytes, Some(1_048_576));
+        assert_eq!(settings.upstream_max_decode_secs, Some(10));
+        assert!(AppSettings::try_from_toml(toml_input).is_ok());
+        assert!(AppSettings::try_from_toml("[upstream.cincinnati]\nmax_decode_secs = 0").is_err());
+    }
+
+    #[test]
+    fn toml_upstream_additional_urls() {
+        let mut settings = AppSettings::default();
+        assert!(settings.upstream_additional_urls.is_empty());
+
+        let toml_input = r#"
+            [upstream.cincinnati]
+            url = "http://graph-builder-amd64:8080/graph"
+            additional_urls = ["http://graph-builder-arm64:8080/graph"]
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.upstream_additional_urls,
+            vec!["http://graph-builder-arm64:8080/graph".to_string()]
+        );
+    }
+
     #[test]
     fn toml_sample_config() {
         use super::FileOptions;
@@ -172,6 +438,7 @@ mod tests {
             cincinnati::new_plugins!(InternalPluginWrapper(ChannelFilterPlugin {
                 key_prefix: String::from("io.openshift.upgrades.graph"),
                 key_suffix: String::from("release.channels"),
+                ..Default::default()
             }));
         let mut settings = AppSettings::default();
 
@@ -198,4 +465,470 @@ mod tests {
         let plugins = settings.validate_and_build_plugins(None).unwrap();
         assert_eq!(plugins, expected);
     }
+
+    #[test]
+    fn toml_audit() {
+        use crate::audit::{AuditSink, PiiPolicy};
+
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.audit_sink().unwrap(), None);
+
+        let toml_input = "[audit]\nfile_path = '/var/log/cincinnati/audit.log'\npii = 'mask'";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.audit_pii, PiiPolicy::Mask);
+        assert_eq!(
+            settings.audit_sink().unwrap(),
+            Some(AuditSink::File("/var/log/cincinnati/audit.log".into()))
+        );
+
+        settings.audit_url = Some("https://audit.example.com/".to_string());
+        assert!(settings.audit_sink().is_err());
+    }
+
+    #[test]
+    fn toml_shadow() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.shadow_target().unwrap(), None);
+
+        let toml_input = "[shadow]\nurl = 'http://policy-engine-canary:8081'\npercent = 5.0";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.shadow_percent, 5.0);
+        assert_eq!(
+            settings.shadow_target().unwrap(),
+            Some(reqwest::Url::parse("http://policy-engine-canary:8081").unwrap())
+        );
+
+        settings.shadow_percent = 0.0;
+        assert!(settings.shadow_target().is_err());
+        settings.shadow_percent = 100.0;
+        settings.shadow_url = Some("not a URL".to_string());
+        assert!(settings.shadow_target().is_err());
+    }
+
+    #[test]
+    fn toml_analytics() {
+        let mut settings = AppSettings::default();
+        assert!(!settings.analytics_enabled);
+
+        let toml_input = "[analytics]\nenabled = true\nsummary_interval_secs = 600";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert!(settings.analytics_enabled);
+        assert_eq!(settings.analytics_summary_interval.as_secs(), 600);
+        assert_eq!(
+            settings.analytics_max_series,
+            crate::analytics::DEFAULT_MAX_SERIES
+        );
+    }
+
+    #[test]
+    fn toml_cache_control() {
+        use crate::cache_control::CachePolicy;
+
+        let mut settings = AppSettings::default();
+        let toml_input = r#"
+            [cache_control]
+            refresh_interval_secs = 30
+
+            [cache_control.graph]
+            public = true
+            max_age_secs = 60
+            stale_if_error_intervals = 20
+
+            [cache_control.openapi]
+            max_age_secs = 3600
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        let cache_control = &settings.cache_control;
+        assert_eq!(cache_control.refresh_interval.as_secs(), 30);
+        assert_eq!(
+            cache_control.graph,
+            CachePolicy {
+                public: true,
+                max_age_secs: Some(60),
+                stale_if_error_intervals: Some(20),
+                ..Default::default()
+            }
+        );
+        assert_eq!(cache_control.channels, CachePolicy::default());
+        assert_eq!(cache_control.openapi.max_age_secs, Some(3600));
+
+        let toml_input = "[cache_control.graph]
+max_age = 60";
+        assert!(toml::from_str::<FileOptions>(toml_input).is_err());
+    }
+
+    #[test]
+    fn toml_concurrency_limits() {
+        let mut settings = AppSettings::default();
+        let toml_input = r#"
+            [concurrency_limits]
+            retry_after_secs = 2
+
+            [concurrency_limits.endpoints]
+            "/graph" = 64
+            "/v1/graph" = 64
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        let limits = &settings.concurrency_limits;
+        assert_eq!(limits.retry_after_secs, 2);
+        assert_eq!(limits.endpoints.len(), 2);
+        assert_eq!(limits.endpoints["/v1/graph"], 64);
+    }
+
+    #[test]
+    fn toml_metrics_labels() {
+        let mut settings = AppSettings::default();
+        assert!(settings.metrics_labels.is_empty());
+
+        let toml_input = "[metrics.labels]\nenvironment = \"prod\"\nregion = \"eu\"";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        assert_eq!(settings.metrics_labels.len(), 2);
+        assert_eq!(settings.metrics_labels["environment"], "prod");
+    }
+
+    #[test]
+    fn toml_path_aliases() {
+        use commons::aliases::AliasMode;
+
+        let toml_input = r#"
+            [paths]
+            trailing_slash = true
+
+            [[paths.aliases]]
+            from = "/api/upgrades_info/v1/graph"
+            to = "/v1/graph"
+
+            [[paths.aliases]]
+            from = "/api/upgrades_info/graph"
+            to = "/v1/graph"
+            mode = "rewrite"
+        "#;
+        let settings = AppSettings::try_from_toml(toml_input).unwrap();
+        let path_aliases = &settings.path_aliases;
+        assert!(path_aliases.trailing_slash);
+        assert_eq!(path_aliases.aliases.len(), 2);
+        assert_eq!(path_aliases.aliases[0].mode, AliasMode::Redirect);
+        assert_eq!(path_aliases.aliases[1].mode, AliasMode::Rewrite);
+
+        let toml_input = r#"
+            [[paths.aliases]]
+            from = "/graph"
+            to = "/graph"
+        "#;
+        assert!(AppSettings::try_from_toml(toml_input).is_err());
+    }
+
+    #[test]
+    fn toml_client_parameters() {
+        let toml_input = r#"
+            [service]
+            mandatory_client_parameters = ["channel", "arch"]
+
+            [client_parameters.arch]
+            values = ["amd64", "arm64", "multi"]
+        "#;
+        let settings = AppSettings::try_from_toml(toml_input).unwrap();
+        assert_eq!(
+            settings.client_parameters["arch"].values,
+            Some(vec![
+                "amd64".to_string(),
+                "arm64".to_string(),
+                "multi".to_string()
+            ])
+        );
+
+        // Only mandatory parameters can be constrained.
+        let toml_input = r#"
+            [client_parameters.id]
+            format = "uuid"
+        "#;
+        assert!(AppSettings::try_from_toml(toml_input).is_err());
+    }
+
+    #[test]
+    fn toml_default_params() {
+        let toml_input = r#"
+            default_params = { channel = "stable-4.19", arch = "amd64" }
+
+            [service]
+            mandatory_client_parameters = ["channel", "arch"]
+
+            [client_parameters.arch]
+            values = ["amd64", "arm64"]
+        "#;
+        let settings = AppSettings::try_from_toml(toml_input).unwrap();
+        assert_eq!(settings.default_params.len(), 2);
+        assert_eq!(settings.default_params["channel"], "stable-4.19");
+
+        // Defaults must be accepted values.
+        let toml_input = r#"
+            default_params = { arch = "s390x" }
+
+            [service]
+            mandatory_client_parameters = ["arch"]
+
+            [client_parameters.arch]
+            values = ["amd64", "arm64"]
+        "#;
+        assert!(AppSettings::try_from_toml(toml_input).is_err());
+    }
+
+    #[test]
+    fn toml_legacy_params() {
+        let toml_input = r#"
+            [legacy]
+            params = { channel_name = "channel" }
+            headers = { "x-cluster-id" = "id" }
+        "#;
+        let settings = AppSettings::try_from_toml(toml_input).unwrap();
+        assert_eq!(settings.legacy_params.params["channel_name"], "channel");
+        assert_eq!(settings.legacy_params.headers["x-cluster-id"], "id");
+
+        // Legacy parameters must not be mapped to.
+        let toml_input = r#"
+            [legacy]
+            params = { channel_name = "channel", channel_id = "channel_name" }
+        "#;
+        assert!(AppSettings::try_from_toml(toml_input).is_err());
+    }
+
+    #[test]
+    fn toml_cohorts() {
+        let mut settings = AppSettings::default();
+        let toml_input = r#"
+            [service]
+            cohort_salt = "2024-rollout"
+
+            [[cohorts]]
+            name = "canary"
+            percent = 5.0
+
+            [[cohorts.policy]]
+            name = "channel-filter"
+
+            [[cohorts.policy]]
+            name = "arch-filter"
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        assert_eq!(settings.cohort_salt, "2024-rollout");
+        assert_eq!(settings.cohorts.len(), 1);
+        assert_eq!(settings.cohorts[0].name, "canary");
+        assert_eq!(settings.cohorts[0].percent, 5.0);
+        assert_eq!(settings.cohorts[0].plugin_settings.len(), 2);
+
+        let toml_input = r#"
+            [[cohorts]]
+            name = "canary"
+            percent = 5.0
+
+            [[cohorts.policy]]
+            name = "no-such-plugin"
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        assert!(AppSettings::default().try_merge(Some(file_opts)).is_err());
+    }
+
+    #[test]
+    fn toml_standalone() {
+        let toml_input = r#"
+            [standalone]
+            graph_builder_config_path = "/etc/cincinnati/graph-builder.toml"
+        "#;
+        let settings = AppSettings::try_from_toml(toml_input).unwrap();
+        assert_eq!(
+            settings.standalone_graph_builder_config_path,
+            Some("/etc/cincinnati/graph-builder.toml".into())
+        );
+
+        let toml_input = "[standalone]\nconfig_path = \"gb.toml\"";
+        assert!(toml::from_str::<FileOptions>(toml_input).is_err());
+    }
+
+    #[test]
+    fn toml_variants() {
+        let settings = AppSettings::try_from_toml("").unwrap();
+        assert_eq!(
+            settings.variants_max_bytes,
+            crate::variants::DEFAULT_MAX_BYTES
+        );
+
+        let toml_input = "[variants]\nmax_bytes = 0";
+        let settings = AppSettings::try_from_toml(toml_input).unwrap();
+        assert_eq!(settings.variants_max_bytes, 0);
+    }
+
+    #[test]
+    fn toml_warmup() {
+        let toml_input = r#"
+            [warmup]
+            top = 2
+
+            [[warmup.variants]]
+            channel = "stable-4.14"
+            arch = "amd64"
+
+            [[warmup.variants]]
+            channel = "stable-4.14"
+            arch = "arm64"
+
+            [[warmup.variants]]
+            channel = "fast-4.14"
+        "#;
+        let settings = AppSettings::try_from_toml(toml_input).unwrap();
+        let variants: Vec<(&str, Option<&str>)> = settings
+            .warmup_variants
+            .iter()
+            .map(|variant| (variant.channel.as_str(), variant.arch.as_deref()))
+            .collect();
+        assert_eq!(
+            variants,
+            vec![
+                ("stable-4.14", Some("amd64")),
+                ("stable-4.14", Some("arm64"))
+            ]
+        );
+
+        for invalid in &[
+            "[warmup]\ntop = 0",
+            "[[warmup.variants]]\narch = \"amd64\"",
+            "[[warmup.variants]]\nchannel = \"\"",
+        ] {
+            assert!(AppSettings::try_from_toml(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn toml_pins() {
+        let toml_input = r#"
+            [upstream.cincinnati]
+            snapshots_url = "http://graph-builder:9080/snapshots"
+
+            [[pins]]
+            name = "acme-freeze"
+            ids = ["cluster-a", "cluster-b"]
+            until = "2026-12-01T00:00:00Z"
+
+            [[pins]]
+            name = "acme-incident"
+            ids = ["cluster-c"]
+            from = "2026-11-01T00:00:00+02:00"
+            until = "2026-11-08T00:00:00+02:00"
+            snapshot = "20261014T120000Z-3f2a9c1b0d4e"
+        "#;
+        let settings = AppSettings::try_from_toml(toml_input).unwrap();
+        assert_eq!(settings.pins.len(), 2);
+        assert_eq!(settings.pins[0].name, "acme-freeze");
+        assert_eq!(settings.pins[0].ids.len(), 2);
+        assert_eq!(settings.pins[0].from, None);
+        assert_eq!(
+            settings.pins[1].until,
+            std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_794_088_800)
+        );
+
+        // Snapshots require the snapshots URL of the upstream.
+        let toml_input = r#"
+            [[pins]]
+            name = "acme-incident"
+            ids = ["cluster-c"]
+            until = "2026-11-08T00:00:00Z"
+            snapshot = "20261014T120000Z-3f2a9c1b0d4e"
+        "#;
+        assert!(AppSettings::try_from_toml(toml_input).is_err());
+
+        let toml_input = r#"
+            [[pins]]
+            name = "acme-freeze"
+            ids = ["cluster-a"]
+            until = "next week"
+        "#;
+        assert!(AppSettings::try_from_toml(toml_input).is_err());
+    }
+
+    #[test]
+    fn toml_slow_request_threshold() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.slow_request_threshold, None);
+
+        let toml_input = "[service]\nslow_request_threshold_ms = 250";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.slow_request_threshold,
+            Some(std::time::Duration::from_millis(250))
+        );
+    }
+
+    #[test]
+    fn toml_request_timeout() {
+        let settings = AppSettings::try_from_toml("").unwrap();
+        assert_eq!(settings.request_timeout, None);
+
+        let toml_input = "[service]\nrequest_timeout_ms = 5000";
+        let settings = AppSettings::try_from_toml(toml_input).unwrap();
+        assert_eq!(
+            settings.request_timeout,
+            Some(std::time::Duration::from_secs(5))
+        );
+
+        let toml_input = "[service]\nrequest_timeout_ms = 0";
+        assert!(AppSettings::try_from_toml(toml_input).is_err());
+    }
+
+    #[test]
+    fn toml_features() {
+        let toml_input = r#"
+            [status]
+            admin_token_path = "/etc/policy-engine/admin-token"
+            debug_token_path = "/etc/policy-engine/debug-token"
+
+            [features]
+            cohort-serving = false
+        "#;
+        let settings = AppSettings::try_from_toml(toml_input).unwrap();
+        assert_eq!(
+            settings.status_admin_token_path,
+            Some(path::PathBuf::from("/etc/policy-engine/admin-token"))
+        );
+        assert_eq!(
+            settings.status_debug_token_path,
+            Some(path::PathBuf::from("/etc/policy-engine/debug-token"))
+        );
+        assert_eq!(settings.features.get("cohort-serving"), Some(&false));
+
+        assert!(AppSettings::try_from_toml("[features]\nno-such-feature = true").is_err());
+    }
+
+    #[test]
+    fn toml_profile() {
+        use cincinnati::plugins::prelude::*;
+
+        let settings = AppSettings::try_from_toml(r#"profile = "okd""#).unwrap();
+        assert_eq!(settings.profile.name, cincinnati::OKD_PROFILE);
+
+        let expected: Vec<BoxedPlugin> =
+            cincinnati::new_plugins!(InternalPluginWrapper(ChannelFilterPlugin {
+                key_prefix: String::from("io.openshift.upgrades.graph"),
+                key_suffix: String::from("release.channels"),
+                allowed_channels: vec!["stable-4*".to_string(), "next-4*".to_string()],
+            }));
+        let plugins = settings.validate_and_build_plugins(None).unwrap();
+        assert_eq!(plugins[1..2], expected[..]);
+
+        let toml_input = r#"profile = { name = "okd", key_prefix = "io.okd.upgrades" }"#;
+        let settings = AppSettings::try_from_toml(toml_input).unwrap();
+        assert_eq!(settings.profile.key_prefix, "io.okd.upgrades");
+
+        assert!(AppSettings::try_from_toml(r#"profile = "rhel""#).is_err());
+    }
 }
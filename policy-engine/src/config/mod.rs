@@ -15,4 +15,5 @@ mod settings;
 pub(crate) use self::file::FileOptions;
 
 pub use self::settings::AppSettings;
+pub use self::settings::DEFAULT_EVENTS_POLL_INTERVAL;
 pub use self::settings::DEFAULT_UPSTREAM_URL;
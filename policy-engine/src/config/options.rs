@@ -1,10 +1,13 @@
 //! Options shared by CLI and TOML.
 
 use super::AppSettings;
+use crate::audit::PiiPolicy;
+use crate::cache_control::CachePolicy;
 use commons::prelude_errors::*;
 use commons::{de_path_prefix, parse_params_set, parse_path_prefix, MergeOptions};
-use std::collections::HashSet;
-use std::net::IpAddr;
+use std::collections::{BTreeMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::time::Duration;
 
 /// Status service options.
@@ -17,6 +20,18 @@ pub struct StatusOptions {
     /// Port to which the status service will bind
     #[structopt(name = "status_port", long = "status.port")]
     pub port: Option<u16>,
+
+    /// Comma-separated addresses and ports on which the status service will listen, instead of `address` and `port`
+    #[structopt(long = "status.listen", use_delimiter = true)]
+    pub listen: Option<Vec<SocketAddr>>,
+
+    /// Path to a file containing the bearer token required to change feature flags
+    #[structopt(long = "status.admin_token_path")]
+    pub admin_token_path: Option<PathBuf>,
+
+    /// Path to a file containing the bearer token required by debug endpoints
+    #[structopt(long = "status.debug_token_path")]
+    pub debug_token_path: Option<PathBuf>,
 }
 
 impl MergeOptions<Option<StatusOptions>> for AppSettings {
@@ -24,6 +39,9 @@ impl MergeOptions<Option<StatusOptions>> for AppSettings {
         if let Some(status) = opts {
             assign_if_some!(self.status_address, status.address);
             assign_if_some!(self.status_port, status.port);
+            assign_if_some!(self.status_listen, status.listen);
+            assign_if_some!(self.status_admin_token_path, status.admin_token_path);
+            assign_if_some!(self.status_debug_token_path, status.debug_token_path);
         }
         Ok(())
     }
@@ -40,6 +58,10 @@ pub struct ServiceOptions {
     #[structopt(name = "service_port", long = "service.port")]
     pub port: Option<u16>,
 
+    /// Comma-separated addresses and ports on which the server will listen, instead of `address` and `port`
+    #[structopt(long = "service.listen", use_delimiter = true)]
+    pub listen: Option<Vec<SocketAddr>>,
+
     /// Namespace prefix for all service endpoints (e.g. '/<prefix>/graph')
     #[structopt(long = "service.path_prefix", parse(from_str = parse_path_prefix))]
     #[serde(default = "Option::default", deserialize_with = "de_path_prefix")]
@@ -66,6 +88,58 @@ pub struct ServiceOptions {
     pub keep_alive: Option<u64>,
     #[structopt(name = "client_timeout", long = "service.client_timeout")]
     pub client_timeout: Option<u64>,
+
+    /// Path to a base64-encoded Ed25519 key used to sign graph responses
+    #[structopt(long = "service.signing_key_path")]
+    pub signing_key_path: Option<PathBuf>,
+
+    /// Key identifier to advertise in graph signatures
+    #[structopt(long = "service.signing_key_id")]
+    pub signing_key_id: Option<String>,
+
+    /// Port to which the gRPC service will bind (disabled if unset)
+    #[structopt(long = "service.grpc_port")]
+    pub grpc_port: Option<u16>,
+
+    /// Interval (in seconds) between graph refreshes for events streams
+    #[structopt(long = "service.events_poll_interval_secs")]
+    pub events_poll_interval_secs: Option<u64>,
+
+    /// Maximum length of request URIs, in bytes
+    #[structopt(long = "service.max_uri_length")]
+    pub max_uri_length: Option<usize>,
+
+    /// Maximum number of query parameters per request
+    #[structopt(long = "service.max_query_params")]
+    pub max_query_params: Option<usize>,
+
+    /// Maximum total size of request headers, in bytes
+    #[structopt(long = "service.max_headers_size")]
+    pub max_headers_size: Option<usize>,
+
+    /// Maximum depth of GraphQL queries
+    #[structopt(long = "service.graphql_max_depth")]
+    pub graphql_max_depth: Option<usize>,
+
+    /// Number of HTTP worker threads (defaults to the number of physical cores)
+    #[structopt(long = "service.workers")]
+    pub workers: Option<usize>,
+
+    /// Maximum number of blocking threads per HTTP worker
+    #[structopt(long = "service.worker_max_blocking_threads")]
+    pub worker_max_blocking_threads: Option<usize>,
+
+    /// Latency (in milliseconds) above which requests are logged
+    #[structopt(long = "service.slow_request_threshold_ms")]
+    pub slow_request_threshold_ms: Option<u64>,
+
+    /// Time (in milliseconds) after which graph requests fail, if not shortened by clients
+    #[structopt(long = "service.request_timeout_ms")]
+    pub request_timeout_ms: Option<u64>,
+
+    /// Salt hashed with client identifiers when assigning them to cohorts
+    #[structopt(long = "service.cohort_salt")]
+    pub cohort_salt: Option<String>,
 }
 
 impl MergeOptions<Option<ServiceOptions>> for AppSettings {
@@ -73,11 +147,31 @@ impl MergeOptions<Option<ServiceOptions>> for AppSettings {
         if let Some(service) = opts {
             assign_if_some!(self.address, service.address);
             assign_if_some!(self.port, service.port);
+            assign_if_some!(self.listen, service.listen);
             assign_if_some!(self.path_prefix, service.path_prefix);
             assign_if_some!(self.tracing_endpoint, service.tracing_endpoint);
             assign_if_some!(self.backlog, service.backlog);
             assign_if_some!(self.max_connections, service.max_connections);
             assign_if_some!(self.max_connection_rate, service.max_connection_rate);
+            assign_if_some!(self.signing_key_path, service.signing_key_path);
+            assign_if_some!(self.signing_key_id, service.signing_key_id);
+            assign_if_some!(self.grpc_port, service.grpc_port);
+            assign_if_some!(self.request_limits.max_uri_length, service.max_uri_length);
+            assign_if_some!(
+                self.request_limits.max_query_params,
+                service.max_query_params
+            );
+            assign_if_some!(
+                self.request_limits.max_headers_size,
+                service.max_headers_size
+            );
+            assign_if_some!(self.graphql_max_depth, service.graphql_max_depth);
+            assign_if_some!(self.workers, service.workers);
+            assign_if_some!(self.cohort_salt, service.cohort_salt);
+            assign_if_some!(
+                self.worker_max_blocking_threads,
+                service.worker_max_blocking_threads
+            );
             self.keep_alive = match service.keep_alive {
                 Some(x) => Some(Duration::new(x, 0)),
                 None => None,
@@ -85,6 +179,15 @@ impl MergeOptions<Option<ServiceOptions>> for AppSettings {
             if let Some(duration) = service.client_timeout {
                 self.client_timeout = Duration::new(duration, 0);
             }
+            if let Some(secs) = service.events_poll_interval_secs {
+                self.events_poll_interval = Duration::from_secs(secs);
+            }
+            if let Some(millis) = service.slow_request_threshold_ms {
+                self.slow_request_threshold = Some(Duration::from_millis(millis));
+            }
+            if let Some(millis) = service.request_timeout_ms {
+                self.request_timeout = Some(Duration::from_millis(millis));
+            }
             if let Some(params) = service.mandatory_client_parameters {
                 self.mandatory_client_parameters.extend(params);
             }
@@ -93,6 +196,237 @@ impl MergeOptions<Option<ServiceOptions>> for AppSettings {
     }
 }
 
+/// Audit log options.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct AuditOptions {
+    /// Path to a file to which graph requests are appended as JSON lines
+    #[structopt(long = "audit.file_path")]
+    pub file_path: Option<PathBuf>,
+
+    /// URL to which graph requests are POSTed as JSON documents
+    #[structopt(long = "audit.url")]
+    pub url: Option<String>,
+
+    /// How client identifiers and addresses are recorded ("full", "mask" or "omit")
+    #[structopt(long = "audit.pii")]
+    pub pii: Option<PiiPolicy>,
+}
+
+impl MergeOptions<Option<AuditOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<AuditOptions>) -> Fallible<()> {
+        if let Some(audit) = opts {
+            assign_if_some!(self.audit_file_path, audit.file_path);
+            assign_if_some!(self.audit_url, audit.url);
+            assign_if_some!(self.audit_pii, audit.pii);
+        }
+        Ok(())
+    }
+}
+
+/// Request shadowing options.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct ShadowOptions {
+    /// Base URL of a secondary deployment to which a sample of the graph requests is mirrored
+    #[structopt(long = "shadow.url")]
+    pub url: Option<String>,
+
+    /// Percentage of the graph requests mirrored to the secondary deployment
+    #[structopt(long = "shadow.percent")]
+    pub percent: Option<f64>,
+}
+
+impl MergeOptions<Option<ShadowOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<ShadowOptions>) -> Fallible<()> {
+        if let Some(shadow) = opts {
+            assign_if_some!(self.shadow_url, shadow.url);
+            assign_if_some!(self.shadow_percent, shadow.percent);
+        }
+        Ok(())
+    }
+}
+
+/// Query analytics options.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct AnalyticsOptions {
+    /// Count graph queries by channel, version and architecture
+    #[structopt(long = "analytics.enabled")]
+    pub enabled: Option<bool>,
+
+    /// Interval (in seconds) between logged summaries of the queries
+    #[structopt(long = "analytics.summary_interval_secs")]
+    pub summary_interval_secs: Option<u64>,
+
+    /// Maximum number of distinct (channel, version, arch) series counted
+    #[structopt(long = "analytics.max_series")]
+    pub max_series: Option<usize>,
+}
+
+impl MergeOptions<Option<AnalyticsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<AnalyticsOptions>) -> Fallible<()> {
+        if let Some(analytics) = opts {
+            assign_if_some!(self.analytics_enabled, analytics.enabled);
+            assign_if_some!(self.analytics_max_series, analytics.max_series);
+            if let Some(secs) = analytics.summary_interval_secs {
+                self.analytics_summary_interval = Duration::from_secs(secs);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Caching policies options, per endpoint.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheControlOptions {
+    /// Interval (in seconds) between refreshes of the served graph
+    pub refresh_interval_secs: Option<u64>,
+
+    /// Policy of the graph endpoints
+    pub graph: Option<CachePolicy>,
+
+    /// Policy of the channel groups endpoint
+    pub channels: Option<CachePolicy>,
+
+    /// Policy of the OpenAPI document and graph schema endpoints
+    pub openapi: Option<CachePolicy>,
+}
+
+impl MergeOptions<Option<CacheControlOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<CacheControlOptions>) -> Fallible<()> {
+        if let Some(cache_control) = opts {
+            if let Some(secs) = cache_control.refresh_interval_secs {
+                self.cache_control.refresh_interval = Duration::from_secs(secs);
+            }
+            assign_if_some!(self.cache_control.graph, cache_control.graph);
+            assign_if_some!(self.cache_control.channels, cache_control.channels);
+            assign_if_some!(self.cache_control.openapi, cache_control.openapi);
+        }
+        Ok(())
+    }
+}
+
+/// Concurrency limits options.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConcurrencyLimitsOptions {
+    /// Delay (in seconds) advertised to clients of saturated endpoints
+    pub retry_after_secs: Option<u64>,
+
+    /// Maximum number of requests in flight, by endpoint path
+    pub endpoints: Option<BTreeMap<String, usize>>,
+}
+
+impl MergeOptions<Option<ConcurrencyLimitsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<ConcurrencyLimitsOptions>) -> Fallible<()> {
+        if let Some(limits) = opts {
+            assign_if_some!(
+                self.concurrency_limits.retry_after_secs,
+                limits.retry_after_secs
+            );
+            assign_if_some!(self.concurrency_limits.endpoints, limits.endpoints);
+        }
+        Ok(())
+    }
+}
+
+/// Metrics options.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsOptions {
+    /// Static labels added to every exported metric, by name
+    pub labels: Option<BTreeMap<String, String>>,
+}
+
+impl MergeOptions<Option<MetricsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<MetricsOptions>) -> Fallible<()> {
+        if let Some(metrics) = opts {
+            assign_if_some!(self.metrics_labels, metrics.labels);
+        }
+        Ok(())
+    }
+}
+
+/// Path aliases options.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PathAliasesOptions {
+    /// Whether requests to paths ending with a slash are served as if sent without it
+    pub trailing_slash: Option<bool>,
+
+    /// Aliases of request paths, matched in order
+    pub aliases: Option<Vec<commons::aliases::PathAlias>>,
+}
+
+impl MergeOptions<Option<PathAliasesOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<PathAliasesOptions>) -> Fallible<()> {
+        if let Some(paths) = opts {
+            assign_if_some!(self.path_aliases.trailing_slash, paths.trailing_slash);
+            assign_if_some!(self.path_aliases.aliases, paths.aliases);
+        }
+        Ok(())
+    }
+}
+
+/// Legacy query conventions options.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LegacyParamsOptions {
+    /// Modern query parameters, by legacy query parameter
+    pub params: Option<BTreeMap<String, String>>,
+
+    /// Modern query parameters, by legacy request header
+    pub headers: Option<BTreeMap<String, String>>,
+}
+
+impl MergeOptions<Option<LegacyParamsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<LegacyParamsOptions>) -> Fallible<()> {
+        if let Some(legacy) = opts {
+            assign_if_some!(self.legacy_params.params, legacy.params);
+            assign_if_some!(self.legacy_params.headers, legacy.headers);
+        }
+        Ok(())
+    }
+}
+
+/// Standalone mode options.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+#[serde(deny_unknown_fields)]
+pub struct StandaloneOptions {
+    /// Path to a graph-builder configuration file, whose pipeline runs in-process instead of fetching the upstream
+    #[structopt(long = "standalone.graph_builder_config_path")]
+    pub graph_builder_config_path: Option<PathBuf>,
+}
+
+impl MergeOptions<Option<StandaloneOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<StandaloneOptions>) -> Fallible<()> {
+        if let Some(standalone) = opts {
+            assign_if_some!(
+                self.standalone_graph_builder_config_path,
+                standalone.graph_builder_config_path
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Rendered graph variant store options.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+#[serde(deny_unknown_fields)]
+pub struct VariantsOptions {
+    /// Memory budget of the rendered graph variants, in bytes; 0 disables storing variants
+    #[structopt(long = "variants.max_bytes")]
+    pub max_bytes: Option<u64>,
+}
+
+impl MergeOptions<Option<VariantsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<VariantsOptions>) -> Fallible<()> {
+        if let Some(variants) = opts {
+            assign_if_some!(self.variants_max_bytes, variants.max_bytes);
+        }
+        Ok(())
+    }
+}
+
 /// Options for a Cincinnati upstream.
 #[derive(Debug, Deserialize, StructOpt)]
 pub struct UpCincinnatiOptions {
@@ -100,12 +434,62 @@ pub struct UpCincinnatiOptions {
     #[structopt(long = "upstream.cincinnati.url", parse(try_from_str = uri_from_str))]
     #[serde(default = "Option::default", deserialize_with = "de_uri")]
     pub url: Option<hyper::Uri>,
+
+    /// Path to a file containing a bearer token presented to the upstream
+    #[structopt(long = "upstream.cincinnati.bearer_token_path")]
+    pub bearer_token_path: Option<PathBuf>,
+
+    /// Path to a PEM client certificate presented to the upstream
+    #[structopt(long = "upstream.cincinnati.client_cert_path")]
+    pub client_cert_path: Option<PathBuf>,
+
+    /// Path to the PEM (PKCS#8) private key of the client certificate
+    #[structopt(long = "upstream.cincinnati.client_key_path")]
+    pub client_key_path: Option<PathBuf>,
+
+    /// Path to PEM CA certificates to trust for the upstream
+    #[structopt(long = "upstream.cincinnati.ca_cert_path")]
+    pub ca_cert_path: Option<PathBuf>,
+
+    /// How the upstream endpoints are discovered: "static", "srv" or "dns"
+    #[structopt(long = "upstream.cincinnati.discovery")]
+    pub discovery: Option<String>,
+
+    /// How requests are balanced across the discovered endpoints: "round-robin" or "weighted"
+    #[structopt(long = "upstream.cincinnati.balancing")]
+    pub balancing: Option<String>,
+
+    /// URLs of additional upstreams whose graphs are merged, e.g. per-architecture graph-builders
+    #[structopt(long = "upstream.cincinnati.additional_urls")]
+    pub additional_urls: Option<Vec<String>>,
+
+    /// Base URL of the graph-builder snapshots, from which pinned snapshots are fetched
+    #[structopt(long = "upstream.cincinnati.snapshots_url")]
+    pub snapshots_url: Option<String>,
+
+    /// Maximum size of an upstream response, in bytes; 0 disables the limit
+    #[structopt(long = "upstream.cincinnati.max_response_bytes")]
+    pub max_response_bytes: Option<u64>,
+
+    /// Maximum time spent decoding an upstream response, in seconds
+    #[structopt(long = "upstream.cincinnati.max_decode_secs")]
+    pub max_decode_secs: Option<u64>,
 }
 
 impl MergeOptions<Option<UpCincinnatiOptions>> for AppSettings {
     fn try_merge(&mut self, opts: Option<UpCincinnatiOptions>) -> Fallible<()> {
         if let Some(up) = opts {
             assign_if_some!(self.upstream, up.url);
+            assign_if_some!(self.upstream_bearer_token_path, up.bearer_token_path);
+            assign_if_some!(self.upstream_client_cert_path, up.client_cert_path);
+            assign_if_some!(self.upstream_client_key_path, up.client_key_path);
+            assign_if_some!(self.upstream_ca_cert_path, up.ca_cert_path);
+            assign_if_some!(self.upstream_discovery, up.discovery);
+            assign_if_some!(self.upstream_balancing, up.balancing);
+            assign_if_some!(self.upstream_additional_urls, up.additional_urls);
+            assign_if_some!(self.upstream_snapshots_url, up.snapshots_url);
+            assign_if_some!(self.upstream_max_response_bytes, up.max_response_bytes);
+            assign_if_some!(self.upstream_max_decode_secs, up.max_decode_secs);
         }
         Ok(())
     }
@@ -1,19 +1,29 @@
 //! Application settings for policy-engine.
 
 use super::{cli, file};
+use crate::audit::{AuditSink, PiiPolicy};
 use cincinnati::plugins::catalog::{self, PluginSettings};
 use cincinnati::plugins::BoxedPlugin;
 use commons::prelude_errors::*;
+use commons::ConfigError;
 use custom_debug_derive::Debug as CustomDebug;
 use hyper::Uri;
-use std::collections::HashSet;
-use std::net::{IpAddr, Ipv4Addr};
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 use std::time::Duration;
 use structopt::StructOpt;
 
 /// Default URL to upstream graph provider.
 pub static DEFAULT_UPSTREAM_URL: &str = "http://localhost:8080/graph";
 
+/// Default interval between graph refreshes for events streams.
+pub const DEFAULT_EVENTS_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default maximum depth of GraphQL queries.
+pub const DEFAULT_GRAPHQL_MAX_DEPTH: usize = 16;
+
 /// Runtime application settings (validated config).
 #[derive(CustomDebug, SmartDefault)]
 pub struct AppSettings {
@@ -21,10 +31,43 @@ pub struct AppSettings {
     #[default(log::LevelFilter::Warn)]
     pub verbosity: log::LevelFilter,
 
+    /// Conventions of the served product, e.g. its metadata keys and channels.
+    pub profile: cincinnati::ProductProfile,
+
     /// URL for the upstream graph builder or policy engine
     #[default(Uri::from_static(DEFAULT_UPSTREAM_URL))]
     pub upstream: Uri,
 
+    /// Optional file containing a bearer token presented to the upstream.
+    pub upstream_bearer_token_path: Option<PathBuf>,
+
+    /// Optional client certificate presented to the upstream.
+    pub upstream_client_cert_path: Option<PathBuf>,
+
+    /// Private key of the upstream client certificate.
+    pub upstream_client_key_path: Option<PathBuf>,
+
+    /// Optional CA certificates to trust for the upstream.
+    pub upstream_ca_cert_path: Option<PathBuf>,
+
+    /// Optional discovery of the upstream endpoints through DNS.
+    pub upstream_discovery: Option<String>,
+
+    /// Optional balancing of the requests across the discovered upstream endpoints.
+    pub upstream_balancing: Option<String>,
+
+    /// Additional upstreams whose graphs are merged into the one of the upstream.
+    pub upstream_additional_urls: Vec<String>,
+
+    /// Optional base URL of the upstream graph-builder snapshots, from which pinned snapshots are fetched.
+    pub upstream_snapshots_url: Option<String>,
+
+    /// Optional maximum size of an upstream response, in bytes, instead of the plugin default.
+    pub upstream_max_response_bytes: Option<u64>,
+
+    /// Optional maximum time spent decoding an upstream response, in seconds.
+    pub upstream_max_decode_secs: Option<u64>,
+
     /// Listening address for the main service.
     #[default(IpAddr::V4(Ipv4Addr::LOCALHOST))]
     pub address: IpAddr,
@@ -33,6 +76,9 @@ pub struct AppSettings {
     #[default(8081)]
     pub port: u16,
 
+    /// Listening addresses for the main service, instead of `address` and `port` if not empty.
+    pub listen: Vec<SocketAddr>,
+
     /// Listening address for the status service.
     #[default(IpAddr::V4(Ipv4Addr::LOCALHOST))]
     pub status_address: IpAddr,
@@ -41,6 +87,15 @@ pub struct AppSettings {
     #[default(9081)]
     pub status_port: u16,
 
+    /// Listening addresses for the status service, instead of `status_address` and `status_port` if not empty.
+    pub status_listen: Vec<SocketAddr>,
+
+    /// Optional file holding the bearer token required to change feature flags.
+    pub status_admin_token_path: Option<PathBuf>,
+
+    /// Optional file holding the bearer token required by debug endpoints.
+    pub status_debug_token_path: Option<PathBuf>,
+
     /// Endpoints namespace for the main service.
     pub path_prefix: String,
 
@@ -50,6 +105,29 @@ pub struct AppSettings {
     /// Required client parameters for the main service.
     pub mandatory_client_parameters: HashSet<String>,
 
+    /// Constraints on the values of required client parameters, by parameter.
+    pub client_parameters: BTreeMap<String, commons::params::ParamConstraint>,
+
+    /// Default values of query parameters omitted by clients, by parameter.
+    pub default_params: BTreeMap<String, String>,
+
+    /// Optional graph-builder configuration, whose pipeline runs in-process
+    /// instead of fetching the upstream graph.
+    pub standalone_graph_builder_config_path: Option<PathBuf>,
+
+    /// Memory budget of the rendered graph variant store, in bytes; 0 disables it.
+    #[default(crate::variants::DEFAULT_MAX_BYTES)]
+    pub variants_max_bytes: u64,
+
+    /// Optional graph to apply the plugin chain to, instead of serving.
+    pub apply_graph_path: Option<PathBuf>,
+
+    /// Client parameters for the plugin chain in apply mode.
+    pub apply_params: Vec<(String, String)>,
+
+    /// Optional file to which the graph is written in apply mode, instead of stdout.
+    pub apply_output: Option<PathBuf>,
+
     /// Jaeger host and port for tracing support
     pub tracing_endpoint: Option<String>,
 
@@ -68,6 +146,122 @@ pub struct AppSettings {
     /// Actix-web server client timeout for first request, defaults to 5s: https://docs.rs/actix-web/latest/actix_web/struct.HttpServer.html#method.client_timeout
     #[default(Duration::new(5, 0))]
     pub client_timeout: Duration,
+    /// Actix-web number of workers, defaults to the number of physical cores: https://docs.rs/actix-web/latest/actix_web/struct.HttpServer.html#method.workers
+    pub workers: Option<usize>,
+    /// Actix-web per-worker maximum number of blocking threads, defaults to 512 divided by the number of workers: https://docs.rs/actix-web/latest/actix_web/struct.HttpServer.html#method.worker_max_blocking_threads
+    pub worker_max_blocking_threads: Option<usize>,
+
+    /// Optional path to the Ed25519 key used for signing graph responses.
+    pub signing_key_path: Option<PathBuf>,
+
+    /// Optional key identifier advertised in graph signatures.
+    pub signing_key_id: Option<String>,
+
+    /// Optional listening port for the gRPC service.
+    pub grpc_port: Option<u16>,
+
+    /// Interval between graph refreshes for events streams.
+    #[default(DEFAULT_EVENTS_POLL_INTERVAL)]
+    pub events_poll_interval: Duration,
+
+    /// Optional file to which graph requests are audited.
+    pub audit_file_path: Option<PathBuf>,
+
+    /// Optional URL to which graph requests are audited.
+    pub audit_url: Option<String>,
+
+    /// How client identifiers and addresses are audited.
+    pub audit_pii: PiiPolicy,
+
+    /// Optional base URL of a secondary deployment to which graph requests are mirrored.
+    pub shadow_url: Option<String>,
+
+    /// Percentage of the graph requests mirrored to the secondary deployment.
+    #[default(crate::shadow::DEFAULT_PERCENT)]
+    pub shadow_percent: f64,
+
+    /// Whether graph queries are counted by channel, version and architecture.
+    pub analytics_enabled: bool,
+
+    /// Interval between logged summaries of the graph queries.
+    #[default(crate::analytics::DEFAULT_SUMMARY_INTERVAL)]
+    pub analytics_summary_interval: Duration,
+
+    /// Maximum number of distinct (channel, version, arch) series counted.
+    #[default(crate::analytics::DEFAULT_MAX_SERIES)]
+    pub analytics_max_series: usize,
+
+    /// Limits enforced on requests to the main service.
+    pub request_limits: commons::limits::RequestLimits,
+
+    /// Maximum numbers of requests in flight to endpoints of the main service.
+    pub concurrency_limits: commons::limits::ConcurrencyLimits,
+
+    /// Static labels added to every exported metric, by name.
+    pub metrics_labels: BTreeMap<String, String>,
+
+    /// Aliases of request paths of the main service.
+    pub path_aliases: commons::aliases::PathAliases,
+
+    /// Mapping of the legacy query parameters and headers of earlier clients.
+    pub legacy_params: crate::legacy::LegacyParams,
+
+    /// Maximum depth of GraphQL queries.
+    #[default(DEFAULT_GRAPHQL_MAX_DEPTH)]
+    pub graphql_max_depth: usize,
+
+    /// Optional latency above which requests are logged.
+    pub slow_request_threshold: Option<Duration>,
+
+    /// Optional time after which graph requests fail.
+    pub request_timeout: Option<Duration>,
+
+    /// Caching policies of the main service endpoints.
+    pub cache_control: crate::cache_control::CacheControl,
+
+    /// Salt hashed with client identifiers when assigning them to cohorts.
+    pub cohort_salt: String,
+
+    /// Cohorts served their own plugin chain, in assignment order.
+    pub cohorts: Vec<CohortSettings>,
+
+    /// Clients served a fixed graph for a period, in precedence order.
+    pub pins: Vec<crate::pins::PinSettings>,
+
+    /// Graph variants rendered on startup before becoming ready, in order.
+    pub warmup_variants: Vec<crate::warmup::WarmupVariant>,
+
+    /// Feature flags overridden by the configuration, by name.
+    pub features: BTreeMap<String, bool>,
+}
+
+/// Runtime settings of a cohort.
+#[derive(Debug)]
+pub struct CohortSettings {
+    /// Cohort name, reported to clients.
+    pub name: String,
+
+    /// Percentage of the clients assigned to the cohort.
+    pub percent: f64,
+
+    /// Plugin settings of the cohort.
+    pub plugin_settings: Vec<Box<dyn PluginSettings>>,
+}
+
+impl CohortSettings {
+    /// Build the plugins of the cohort.
+    ///
+    /// Plugin metrics are not registered, as they would collide with those
+    /// of the main plugin chain.
+    pub fn build_plugins(&self) -> Fallible<Vec<BoxedPlugin>> {
+        catalog::build_plugins(&self.plugin_settings, None)
+            .context(format!("building plugins of cohort '{}'", self.name))
+    }
+
+    /// Return the digest of the plugin chain of the cohort.
+    pub fn plugin_chain_digest(&self) -> String {
+        crate::variants::chain_digest(&self.plugin_settings)
+    }
 }
 
 impl AppSettings {
@@ -81,17 +275,35 @@ impl AppSettings {
         // Source options.
         let cli_opts = cli::CliOptions::from_args();
         let file_opts = match &cli_opts.config_path {
-            Some(ref path) => Some(file::FileOptions::read_filepath(path)?),
+            Some(ref path) => {
+                Some(file::FileOptions::read_filepath(path).map_err(ConfigError::unreadable)?)
+            }
             None => None,
         };
 
         // Combine options into a single config.
         let mut cfg = defaults;
-        cfg.try_merge(cli_opts)?;
-        cfg.try_merge(file_opts)?;
+        cfg.try_merge(cli_opts).map_err(ConfigError::invalid)?;
+        cfg.try_merge(file_opts).map_err(ConfigError::invalid)?;
 
         // Validate and convert to settings.
-        Self::try_validate(cfg)
+        Self::try_validate(cfg).map_err(ConfigError::invalid)
+    }
+
+    /// Merge a TOML configuration with defaults, and transform it into
+    /// valid runtime settings.
+    pub fn try_from_toml(content: &str) -> Fallible<Self> {
+        use commons::MergeOptions;
+
+        let file_opts: file::FileOptions = toml::from_str(content)
+            .context("failed to parse TOML configuration")
+            .map_err(ConfigError::unreadable)?;
+
+        let mut cfg = Self::default();
+        cfg.try_merge(Some(file_opts))
+            .map_err(ConfigError::invalid)?;
+
+        Self::try_validate(cfg).map_err(ConfigError::invalid)
     }
 
     /// Validate and the configured plugins.
@@ -110,12 +322,197 @@ impl AppSettings {
         catalog::build_plugins(plugin_settings, registry)
     }
 
+    /// Return the digest of the configured plugin chain, defaults included.
+    pub fn plugin_chain_digest(&self) -> Fallible<String> {
+        if self.plugin_settings.is_empty() {
+            Ok(crate::variants::chain_digest(
+                &self.default_openshift_plugin_settings()?,
+            ))
+        } else {
+            Ok(crate::variants::chain_digest(&self.plugin_settings))
+        }
+    }
+
+    /// Return the sink for audit records, if audit logging is enabled.
+    pub fn audit_sink(&self) -> Fallible<Option<AuditSink>> {
+        match (&self.audit_file_path, &self.audit_url) {
+            (Some(_), Some(_)) => bail!("audit file and URL sinks are mutually exclusive"),
+            (Some(path), None) => Ok(Some(AuditSink::File(path.clone()))),
+            (None, Some(url)) => {
+                let url =
+                    reqwest::Url::parse(url).context(format!("invalid audit URL '{}'", url))?;
+                Ok(Some(AuditSink::Http(url)))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Return the base URL of the deployment graph requests are mirrored to, if shadowing is enabled.
+    pub fn shadow_target(&self) -> Fallible<Option<reqwest::Url>> {
+        let url = match &self.shadow_url {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+        if !(self.shadow_percent > 0.0 && self.shadow_percent <= 100.0) {
+            bail!("shadowing percentage must be greater than 0 and at most 100");
+        }
+        let url = reqwest::Url::parse(url).context(format!("invalid shadow URL '{}'", url))?;
+        Ok(Some(url))
+    }
+
+    /// Return the listening addresses of the main service.
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        commons::listen::listen_addrs(&self.listen, self.address, self.port)
+    }
+
+    /// Return the listening addresses of the status service.
+    pub fn status_listen_addrs(&self) -> Vec<SocketAddr> {
+        commons::listen::listen_addrs(&self.status_listen, self.status_address, self.status_port)
+    }
+
     /// Validate and build runtime settings.
     fn try_validate(self) -> Fallible<Self> {
-        if self.address == self.status_address && self.port == self.status_port {
+        let status_addrs = self.status_listen_addrs();
+        if self
+            .listen_addrs()
+            .iter()
+            .any(|addr| status_addrs.contains(addr))
+        {
             bail!("main and status service configured with the same address and port");
         }
 
+        if self
+            .listen_addrs()
+            .iter()
+            .any(|addr| self.grpc_port == Some(addr.port()))
+        {
+            bail!("main and gRPC service configured with the same port");
+        }
+
+        if self.upstream_client_cert_path.is_some() != self.upstream_client_key_path.is_some() {
+            bail!("upstream client certificate and key must be configured together");
+        }
+
+        if self.events_poll_interval.as_secs() == 0 {
+            bail!("unexpected 0s events poll interval");
+        }
+
+        if self.request_timeout == Some(Duration::ZERO) {
+            bail!("request timeout must be greater than 0");
+        }
+
+        if self
+            .warmup_variants
+            .iter()
+            .any(|variant| variant.channel.is_empty())
+        {
+            bail!("warm-up variants must have non-empty channels");
+        }
+
+        if self.request_limits.max_uri_length == 0
+            || self.request_limits.max_query_params == 0
+            || self.request_limits.max_headers_size == 0
+        {
+            bail!("request limits must be greater than 0");
+        }
+        self.concurrency_limits.validate()?;
+        commons::metrics::validate_labels(&self.metrics_labels)?;
+        self.path_aliases.validate()?;
+        self.legacy_params.validate()?;
+        if let Some(url) = &self.upstream_snapshots_url {
+            reqwest::Url::parse(url).context(format!("invalid snapshots URL '{}'", url))?;
+        }
+        let mut pin_names = std::collections::HashSet::new();
+        for pin in &self.pins {
+            pin.validate()?;
+            if !pin_names.insert(&pin.name) {
+                bail!("duplicate pin '{}'", pin.name);
+            }
+            if pin.snapshot.is_some() && self.upstream_snapshots_url.is_none() {
+                bail!(
+                    "pin '{}' requires the snapshots URL of the upstream",
+                    pin.name
+                );
+            }
+        }
+        commons::params::ParamConstraints::validate(
+            &self.client_parameters,
+            &self.mandatory_client_parameters,
+        )?;
+        if self.default_params.keys().any(String::is_empty) {
+            bail!("default_params must not have empty parameter names");
+        }
+        let default_query = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(&self.default_params)
+            .finish();
+        commons::params::ParamConstraints::try_new(&self.client_parameters)?
+            .ensure_query_values(&default_query)
+            .context("checking default_params")?;
+
+        if self.workers == Some(0) || self.worker_max_blocking_threads == Some(0) {
+            bail!("HTTP worker and blocking thread counts must be greater than 0");
+        }
+
+        if self.cache_control.refresh_interval.as_secs() == 0 {
+            bail!("unexpected 0s graph refresh interval");
+        }
+
+        if self.analytics_summary_interval.as_secs() == 0 {
+            bail!("unexpected 0s analytics summary interval");
+        }
+
+        if self.analytics_max_series == 0 {
+            bail!("analytics maximum number of series must be greater than 0");
+        }
+
+        if self.graphql_max_depth == 0 {
+            bail!("GraphQL maximum depth must be greater than 0");
+        }
+
+        commons::features::validate(crate::features::FEATURES, &self.features)?;
+
+        let mut cohort_names = HashSet::new();
+        let mut cohorts_percent = 0.0;
+        for cohort in &self.cohorts {
+            if cohort.name.is_empty()
+                || !cohort
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                bail!(
+                    "invalid cohort name '{}', expected alphanumeric characters, '-' or '_'",
+                    cohort.name
+                );
+            }
+            if cohort.name == crate::cohorts::DEFAULT_COHORT {
+                bail!("cohort name '{}' is reserved", cohort.name);
+            }
+            if !cohort_names.insert(&cohort.name) {
+                bail!("duplicate cohort name '{}'", cohort.name);
+            }
+            if !(cohort.percent > 0.0 && cohort.percent <= 100.0) {
+                bail!(
+                    "percentage of cohort '{}' must be greater than 0 and at most 100",
+                    cohort.name
+                );
+            }
+            if cohort.plugin_settings.is_empty() {
+                bail!("no policy configured for cohort '{}'", cohort.name);
+            }
+            cohorts_percent += cohort.percent;
+        }
+        if cohorts_percent > 100.0 {
+            bail!("cohorts cover more than 100% of the clients");
+        }
+
+        if self.upstream_max_decode_secs == Some(0) {
+            bail!("the upstream decode time limit must be greater than 0");
+        }
+
+        self.audit_sink()?;
+        self.shadow_target()?;
+
         // Deprecates options
         if self.upstream.to_string() != hyper::Uri::default().to_string() {
             warn!("the 'upstream' setting is deprecated and will eventually be removed.");
@@ -127,26 +524,81 @@ impl AppSettings {
     fn default_openshift_plugin_settings(&self) -> Fallible<Vec<Box<dyn PluginSettings>>> {
         use cincinnati::plugins::prelude::*;
 
-        Ok(vec![
-            plugin_config!(
-                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
-                ("upstream", &self.upstream.to_string())
-            )?,
-            plugin_config!(
-                ("name", ChannelFilterPlugin::PLUGIN_NAME),
-                ("upstream", &self.upstream.to_string()),
-                (
-                    "key_prefix",
-                    cincinnati::plugins::internal::metadata_fetch_quay::DEFAULT_QUAY_LABEL_FILTER
+        fn path_option<'a>(key: &'a str, path: &'a Option<PathBuf>) -> Option<(&'a str, &'a str)> {
+            path.as_ref()
+                .and_then(|path| path.to_str())
+                .map(|path| (key, path))
+        }
+
+        let mut graph_fetch: toml::value::Table = [
+            Some(("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME)),
+            Some(("upstream", self.upstream.to_string().as_str())),
+            path_option("bearer_token_path", &self.upstream_bearer_token_path),
+            path_option("client_cert_path", &self.upstream_client_cert_path),
+            path_option("client_key_path", &self.upstream_client_key_path),
+            path_option("ca_cert_path", &self.upstream_ca_cert_path),
+            self.upstream_discovery
+                .as_deref()
+                .map(|discovery| ("discovery", discovery)),
+            self.upstream_balancing
+                .as_deref()
+                .map(|balancing| ("balancing", balancing)),
+        ]
+        .iter()
+        .filter_map(|kv| kv.as_ref())
+        .map(|(k, v)| (k.to_string(), toml::Value::String(v.to_string())))
+        .collect();
+        if !self.upstream_additional_urls.is_empty() {
+            graph_fetch.insert(
+                "additional_upstreams".to_string(),
+                toml::Value::Array(
+                    self.upstream_additional_urls
+                        .iter()
+                        .cloned()
+                        .map(toml::Value::String)
+                        .collect(),
                 ),
-                ("key_suffix", "release.channels")
-            )?,
+            );
+        }
+        for (key, value) in &[
+            ("max_response_bytes", self.upstream_max_response_bytes),
+            ("max_decode_secs", self.upstream_max_decode_secs),
+        ] {
+            if let Some(value) = value {
+                let value = i64::try_from(*value).context(format!("invalid {}", key))?;
+                graph_fetch.insert(key.to_string(), toml::Value::Integer(value));
+            }
+        }
+
+        let mut channel_filter: toml::value::Table = [
+            ("name", ChannelFilterPlugin::PLUGIN_NAME),
+            ("upstream", self.upstream.to_string().as_str()),
+            ("key_prefix", self.profile.key_prefix.as_str()),
+            ("key_suffix", "release.channels"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), toml::Value::String(v.to_string())))
+        .collect();
+        if !self.profile.channels.is_empty() {
+            channel_filter.insert(
+                "allowed_channels".to_string(),
+                toml::Value::Array(
+                    self.profile
+                        .channels
+                        .iter()
+                        .cloned()
+                        .map(toml::Value::String)
+                        .collect(),
+                ),
+            );
+        }
+
+        Ok(vec![
+            cincinnati::plugins::catalog::deserialize_config(toml::Value::Table(graph_fetch))?,
+            cincinnati::plugins::catalog::deserialize_config(toml::Value::Table(channel_filter))?,
             plugin_config!(
                 ("name", ArchFilterPlugin::PLUGIN_NAME),
-                (
-                    "key_prefix",
-                    cincinnati::plugins::internal::arch_filter::DEFAULT_KEY_FILTER
-                ),
+                ("key_prefix", self.profile.key_prefix.as_str()),
                 (
                     "key_suffix",
                     cincinnati::plugins::internal::arch_filter::DEFAULT_ARCH_KEY
@@ -160,6 +612,11 @@ impl AppSettings {
                     cincinnati::plugins::internal::arch_filter::DEFAULT_DEFAULT_ARCH_THRESHOLD_VERSION
                 )
             )?,
+            plugin_config!(
+                ("name", EdgeSchedulePlugin::PLUGIN_NAME),
+                ("key_prefix", self.profile.key_prefix.as_str())
+            )?,
+            plugin_config!(("name", AcceptedRisksPlugin::PLUGIN_NAME))?,
         ])
     }
 }
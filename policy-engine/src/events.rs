@@ -0,0 +1,241 @@
+//! Server-Sent Events stream of graph updates.
+//!
+//! Clients subscribe with the same query parameters they would use for the
+//! graph endpoint. A single poller per distinct query re-renders the graph
+//! periodically and shares its ETag with all the streams of that query, which
+//! push an event carrying the new ETag whenever it changes.
+
+use crate::graph::{self, graph_etag};
+use crate::AppState;
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse};
+use commons::{api_response_error, Fallible, GraphError};
+use futures::stream::{self, StreamExt};
+use parking_lot::Mutex;
+use prometheus::{IntGauge, Registry};
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use tokio::sync::watch;
+
+/// Media type of Server-Sent Events streams.
+pub(crate) static EVENT_STREAM_CONTENT_TYPE: &str = "text/event-stream";
+
+/// SSE event name for graph changes.
+static GRAPH_EVENT: &str = "graph";
+
+lazy_static! {
+    static ref GRAPH_EVENTS_STREAMS: IntGauge = IntGauge::new(
+        "graph_events_active_streams",
+        "Number of open graph events streams"
+    )
+    .unwrap();
+}
+
+/// Key of a change source: the response media type and the rewritten query.
+type SourceKey = (String, String);
+
+/// Sources of graph changes, shared by the events streams of identical queries.
+///
+/// The poller of a source holds its only strong reference, and stops once the
+/// last stream subscribed to it is closed.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EventSources {
+    sources: Arc<Mutex<HashMap<SourceKey, Weak<watch::Sender<String>>>>>,
+}
+
+impl EventSources {
+    /// Subscribe to the ETag changes of the graph for `query`, starting its
+    /// poller from the given `etag` if no stream is subscribed to it yet.
+    fn subscribe(
+        &self,
+        app_data: &AppState,
+        query: String,
+        content_type: String,
+        etag: String,
+    ) -> watch::Receiver<String> {
+        let key = (content_type, query);
+        let mut sources = self.sources.lock();
+        if let Some(sender) = sources.get(&key).and_then(Weak::upgrade) {
+            return sender.subscribe();
+        }
+
+        let (sender, receiver) = watch::channel(etag);
+        let sender = Arc::new(sender);
+        sources.insert(key.clone(), Arc::downgrade(&sender));
+        actix_web::rt::spawn(poll_graph(self.clone(), app_data.clone(), key, sender));
+        receiver
+    }
+}
+
+/// Re-render the graph for a source key periodically, publishing its ETag
+/// changes until no stream is subscribed anymore.
+async fn poll_graph(
+    sources: EventSources,
+    app_data: AppState,
+    key: SourceKey,
+    sender: Arc<watch::Sender<String>>,
+) {
+    let (content_type, query) = &key;
+    loop {
+        actix_web::rt::time::sleep(app_data.events_poll_interval).await;
+
+        // Subscriptions are made under the same lock.
+        {
+            let mut sources = sources.sources.lock();
+            if sender.receiver_count() == 0 {
+                sources.remove(&key);
+                return;
+            }
+        }
+
+        let deadline = app_data.deadline(None);
+        match graph::render_query(query, content_type.clone(), &app_data, deadline).await {
+            Ok(rendered) => {
+                let etag = graph_etag(&rendered.graph_json);
+                sender.send_if_modified(|current| {
+                    if *current == etag {
+                        return false;
+                    }
+                    *current = etag;
+                    true
+                });
+            }
+            Err(e) => warn!("failed to refresh graph for events stream: {}", e),
+        }
+    }
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(GRAPH_EVENTS_STREAMS.clone()))?;
+    Ok(())
+}
+
+/// Keeps track of an open stream for metrics.
+struct StreamGuard;
+
+impl StreamGuard {
+    fn new() -> Self {
+        GRAPH_EVENTS_STREAMS.inc();
+        StreamGuard
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        GRAPH_EVENTS_STREAMS.dec();
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct GraphEvent<'a> {
+    etag: &'a str,
+}
+
+/// Format a graph-change event for the given ETag.
+fn graph_event(etag: &str) -> Bytes {
+    let data =
+        serde_json::to_string(&GraphEvent { etag }).expect("serialization of graph event failed");
+    Bytes::from(format!(
+        "event: {}\nid: {}\ndata: {}\n\n",
+        GRAPH_EVENT,
+        etag.trim_matches('"'),
+        data
+    ))
+}
+
+/// Comment line keeping idle connections open.
+fn keepalive() -> Bytes {
+    Bytes::from_static(b": keepalive\n\n")
+}
+
+/// Serve a stream of graph-change events.
+pub(crate) async fn index(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    // Render once upfront, so that invalid requests are rejected with a regular error.
+    let (query, content_type) =
+        graph::graph_query(&req, &app_data).map_err(|e| api_response_error(&req, e))?;
+    let deadline =
+        graph::request_deadline(&req, &app_data).map_err(|e| api_response_error(&req, e))?;
+    let rendered = graph::render_query(&query, content_type.clone(), &app_data, deadline)
+        .await
+        .map_err(|e| api_response_error(&req, e))?;
+    let etag = graph_etag(&rendered.graph_json);
+    let initial = graph_event(&etag);
+
+    let receiver = app_data
+        .event_sources
+        .subscribe(&app_data, query, content_type, etag.clone());
+    let interval = app_data.events_poll_interval;
+    let updates = stream::unfold(
+        (receiver, etag, StreamGuard::new()),
+        move |(mut receiver, etag, guard)| async move {
+            let (chunk, etag) =
+                match actix_web::rt::time::timeout(interval, receiver.changed()).await {
+                    Ok(Ok(())) => {
+                        let current = receiver.borrow_and_update().clone();
+                        if current == etag {
+                            (keepalive(), etag)
+                        } else {
+                            (graph_event(&current), current)
+                        }
+                    }
+                    // The poller is gone along with the worker running it.
+                    Ok(Err(_)) => return None,
+                    Err(_) => (keepalive(), etag),
+                };
+
+            Some((chunk, (receiver, etag, guard)))
+        },
+    );
+
+    let events = stream::once(async { initial })
+        .chain(updates)
+        .map(Ok::<_, actix_web::Error>);
+
+    Ok(HttpResponse::Ok()
+        .content_type(EVENT_STREAM_CONTENT_TYPE)
+        .insert_header((actix_web::http::header::CACHE_CONTROL, "no-cache"))
+        .streaming(events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_graph_event() {
+        let event = graph_event("\"0123abcd\"");
+        assert_eq!(
+            std::str::from_utf8(&event).unwrap(),
+            "event: graph\nid: 0123abcd\ndata: {\"etag\":\"\\\"0123abcd\\\"\"}\n\n"
+        );
+    }
+
+    #[test]
+    fn share_source_between_identical_queries() {
+        let state = AppState::default();
+        let sources = EventSources::default();
+
+        actix_web::rt::System::new().block_on(async {
+            let subscribe = |query: &str| {
+                sources.subscribe(
+                    &state,
+                    query.to_string(),
+                    graph::latest_content_type(),
+                    "\"0123abcd\"".to_string(),
+                )
+            };
+            let first = subscribe("channel=stable-4.1");
+            let second = subscribe("channel=stable-4.1");
+            let other = subscribe("channel=fast-4.1");
+
+            assert!(first.same_channel(&second));
+            assert!(!first.same_channel(&other));
+            assert_eq!(sources.sources.lock().len(), 2);
+            assert_eq!(*second.borrow(), "\"0123abcd\"");
+        });
+    }
+}
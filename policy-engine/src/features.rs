@@ -0,0 +1,13 @@
+//! Feature flags of the policy-engine.
+
+use commons::features::Feature;
+
+/// Serve clients assigned to a cohort the plugin chain of the cohort.
+pub static COHORT_SERVING: &str = "cohort-serving";
+
+/// Features gated by a flag.
+pub static FEATURES: &[Feature] = &[Feature {
+    name: COHORT_SERVING,
+    description: "Serve clients assigned to a cohort the plugin chain of the cohort",
+    default: true,
+}];
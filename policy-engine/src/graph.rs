@@ -1,28 +1,53 @@
 //! Cincinnati graph service.
 
+use crate::capabilities::{Capabilities, CAPABILITIES_HEADER};
+use crate::coalesce::Coalescer;
+use crate::cohorts;
+use crate::features;
+use crate::pins;
+use crate::signing;
+use crate::standalone;
+use crate::variants::VariantStore;
 use crate::AppState;
-use actix_web::http::header;
+use actix_web::http::{header, StatusCode};
+use actix_web::rt::{Arbiter, ArbiterHandle};
 use actix_web::web::Query;
 use actix_web::{HttpRequest, HttpResponse};
 use cincinnati::plugins::internal::versioned_graph::VersionedGraph;
+use cincinnati::plugins::prelude::CincinnatiGraphFetchPlugin;
 use cincinnati::plugins::{BoxedPlugin, InternalIO};
 use cincinnati::CONTENT_TYPE;
+use commons::latency::TimingBreakdown;
+use commons::metrics::{GuardedIntCounterVec, GuardedVec, DEFAULT_MAX_LABEL_SETS};
 use commons::tracing::get_tracer;
 use commons::{self, api_response_error, Fallible, GraphError};
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use opentelemetry::{
     trace::{mark_span_as_active, FutureExt, Tracer},
     Context as ot_context,
 };
 use prometheus::{histogram_opts, Histogram, IntCounterVec, Opts, Registry};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::sync::{Arc, OnceLock};
+
+/// Time after which the request counter of an unused path may be pruned.
+const INCOMING_REQS_IDLE_TIMEOUT: std::time::Duration =
+    std::time::Duration::from_secs(24 * 60 * 60);
 
 lazy_static! {
-    static ref GRAPH_INCOMING_REQS: IntCounterVec = IntCounterVec::new(
-        Opts::new("graph_incoming_requests_total",
-        "Total number of incoming HTTP client request"),
-        &["uri_path"]
+    static ref GRAPH_INCOMING_REQS: GuardedIntCounterVec = GuardedVec::new(
+        IntCounterVec::new(
+            Opts::new("graph_incoming_requests_total",
+            "Total number of incoming HTTP client request"),
+            &["uri_path"]
+        )
+        .unwrap(),
+        DEFAULT_MAX_LABEL_SETS
     )
-    .unwrap();
+    .idle_timeout(INCOMING_REQS_IDLE_TIMEOUT);
     // Histogram with custom bucket values for serving latency metric (in seconds), values are picked based on monthly data
     static ref GRAPH_SERVE_HIST: Histogram = Histogram::with_opts(histogram_opts!(
         "graph_serve_duration_seconds",
@@ -30,6 +55,14 @@ lazy_static! {
         vec![0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 5.0]
     ))
     .unwrap();
+    static ref GRAPH_DEADLINE_EXCEEDED: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "graph_deadline_exceeded_total",
+            "Total number of graph requests exceeding their deadline, by stage"
+        ),
+        &["stage"]
+    )
+    .unwrap();
 }
 
 /// Register relevant metrics to a prometheus registry.
@@ -37,6 +70,7 @@ pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
     commons::register_metrics(registry)?;
     registry.register(Box::new(GRAPH_INCOMING_REQS.clone()))?;
     registry.register(Box::new(GRAPH_SERVE_HIST.clone()))?;
+    registry.register(Box::new(GRAPH_DEADLINE_EXCEEDED.clone()))?;
     Ok(())
 }
 
@@ -45,7 +79,43 @@ pub(crate) async fn index(
     req: HttpRequest,
     app_data: actix_web::web::Data<AppState>,
 ) -> Result<HttpResponse, GraphError> {
-    _index(&req, app_data)
+    let result = _index(&req, app_data.clone())
+        .await
+        .map_err(|e| api_response_error(&req, e));
+
+    if let Some(audit) = &app_data.audit {
+        match &result {
+            Ok(response) => audit.record(&req, response.status(), response_etag(response)),
+            Err(e) => audit.record(&req, e.status_code(), None),
+        }
+    }
+    if let Some(shadow) = &app_data.shadow {
+        match &result {
+            Ok(response) => shadow.mirror(&req, response.status(), response_etag(response)),
+            Err(e) => shadow.mirror(&req, e.status_code(), None),
+        }
+    }
+    if let (Some(analytics), Ok(_)) = (&app_data.analytics, &result) {
+        analytics.record(&req);
+    }
+
+    result
+}
+
+/// Return the entity tag of a graph response, if any.
+fn response_etag(response: &HttpResponse) -> Option<&str> {
+    response
+        .headers()
+        .get(header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+}
+
+/// Serve detached signatures for Cincinnati graph requests.
+pub(crate) async fn signature(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    _signature(&req, app_data)
         .await
         .map_err(|e| api_response_error(&req, e))
 }
@@ -57,9 +127,264 @@ async fn _index(
     let span = get_tracer().start("index");
     let _active_span = mark_span_as_active(span);
 
+    let rendered = render_graph(req, &app_data).await?;
+
+    let etag = graph_etag(&rendered.graph_json);
+    let not_modified = matches_if_none_match(req, &etag);
+    let mut response = HttpResponse::Ok();
+    response.content_type(rendered.content_type);
+    if let Some(signer) = &app_data.signer {
+        response.insert_header((
+            signing::SIGNATURE_HEADER,
+            signer.sign_graph(&etag, rendered.graph_json.as_bytes()),
+        ));
+    }
+    response.insert_header((header::ETAG, etag));
+    commons::insert_stale_headers(&mut response, rendered.stale_since.as_deref());
+    insert_cohort_header(&mut response, &app_data, rendered.cohort.as_deref());
+    insert_pin_header(&mut response, rendered.pin.as_deref());
+    insert_default_params_header(&mut response, &rendered.default_params);
+    response.insert_header((CAPABILITIES_HEADER, rendered.capabilities.to_string()));
+    response.insert_header((header::VARY, header::ACCEPT_ENCODING.as_str()));
+    // Clients which already hold the graph are only sent its headers.
+    let body = if not_modified {
+        None
+    } else if accepts_gzip(req) {
+        response.insert_header((header::CONTENT_ENCODING, "gzip"));
+        Some(rendered.gzip_body().to_vec())
+    } else {
+        Some(rendered.graph_json.into_bytes())
+    };
+    response.extensions_mut().insert(rendered.plugin_timings);
+    let mut response = match body {
+        Some(body) => response.body(body),
+        None => response.status(StatusCode::NOT_MODIFIED).finish(),
+    };
+    app_data
+        .cache_control
+        .insert_headers(&app_data.cache_control.graph, response.headers_mut());
+    Ok(response)
+}
+
+async fn _signature(
+    req: &HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    let span = get_tracer().start("signature");
+    let _active_span = mark_span_as_active(span);
+
+    let signer = match &app_data.signer {
+        Some(signer) => signer.clone(),
+        None => {
+            return Err(GraphError::DoesNotExist(
+                "graph signing is not enabled".to_string(),
+            ))
+        }
+    };
+
+    // Clients sending the entity tag of the graph they were served get its
+    // signature, even if the graph they would be served now differs.
+    let if_match = req
+        .headers()
+        .get(header::IF_MATCH)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+    if let Some(jws) = if_match.as_deref().and_then(|etag| signer.cached(etag)) {
+        let mut response = HttpResponse::Ok();
+        response.content_type(signing::SIGNATURE_CONTENT_TYPE);
+        response.insert_header((header::ETAG, if_match.unwrap_or_default()));
+        let mut response = response.body(jws);
+        app_data
+            .cache_control
+            .insert_headers(&app_data.cache_control.graph, response.headers_mut());
+        return Ok(response);
+    }
+
+    let rendered = render_graph(req, &app_data).await?;
+    let etag = graph_etag(&rendered.graph_json);
+    if if_match.map_or(false, |requested| requested != etag) {
+        return Ok(HttpResponse::PreconditionFailed().finish());
+    }
+
+    let mut response = HttpResponse::Ok();
+    response.content_type(signing::SIGNATURE_CONTENT_TYPE);
+    insert_cohort_header(&mut response, &app_data, rendered.cohort.as_deref());
+    insert_pin_header(&mut response, rendered.pin.as_deref());
+    insert_default_params_header(&mut response, &rendered.default_params);
+    response.insert_header((CAPABILITIES_HEADER, rendered.capabilities.to_string()));
+    response.extensions_mut().insert(rendered.plugin_timings);
+    let jws = signer.sign_graph(&etag, rendered.graph_json.as_bytes());
+    response.insert_header((header::ETAG, etag));
+    let mut response = response.body(jws);
+    app_data
+        .cache_control
+        .insert_headers(&app_data.cache_control.graph, response.headers_mut());
+    Ok(response)
+}
+
+/// Report the cohort the graph was rendered for, if any cohort is configured.
+fn insert_cohort_header(
+    response: &mut actix_web::HttpResponseBuilder,
+    app_data: &AppState,
+    cohort: Option<&str>,
+) {
+    if !app_data.cohorts.is_empty() {
+        response.insert_header((
+            cohorts::COHORT_HEADER,
+            cohort.unwrap_or(cohorts::DEFAULT_COHORT),
+        ));
+    }
+}
+
+/// Report the pin whose graph is served, if any.
+fn insert_pin_header(response: &mut actix_web::HttpResponseBuilder, pin: Option<&str>) {
+    if let Some(pin) = pin {
+        response.insert_header((pins::PIN_HEADER, pin));
+    }
+}
+
+/// Report the default values applied to the query parameters omitted by the client, if any.
+fn insert_default_params_header(
+    response: &mut actix_web::HttpResponseBuilder,
+    default_params: &[(String, String)],
+) {
+    if !default_params.is_empty() {
+        let value = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(default_params)
+            .finish();
+        response.insert_header((DEFAULT_PARAMS_HEADER, value));
+    }
+}
+
+/// Graph rendered by the plugin chain.
+#[derive(Clone, Debug)]
+pub(crate) struct RenderedGraph {
+    /// Response media type.
+    pub(crate) content_type: String,
+    /// Serialized graph.
+    pub(crate) graph_json: String,
+    /// HTTP-date since which the graph is stale, if the upstream is failing.
+    pub(crate) stale_since: Option<String>,
+    /// Time spent in each plugin.
+    pub(crate) plugin_timings: TimingBreakdown,
+    /// Cohort whose plugin chain rendered the graph, if not the main one.
+    pub(crate) cohort: Option<String>,
+    /// Pin whose graph is served, if any.
+    pub(crate) pin: Option<String>,
+    /// Default values applied to the query parameters omitted by the client.
+    pub(crate) default_params: Vec<(String, String)>,
+    /// Capabilities negotiated with the client.
+    pub(crate) capabilities: Capabilities,
+    /// Gzip-compressed graph, compressed on first use and shared by the clones of the graph.
+    pub(crate) gzip_body: Arc<OnceLock<Vec<u8>>>,
+}
+
+impl RenderedGraph {
+    /// Return the gzip-compressed graph.
+    pub(crate) fn gzip_body(&self) -> &[u8] {
+        self.gzip_body.get_or_init(|| {
+            // Writing to memory cannot fail.
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder
+                .write_all(self.graph_json.as_bytes())
+                .expect("compressing graph in memory");
+            encoder.finish().expect("compressing graph in memory")
+        })
+    }
+}
+
+/// Whether the client accepts gzip-compressed responses.
+fn accepts_gzip(req: &HttpRequest) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT_ENCODING)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .map_or(false, |q| q == 0.0)
+            });
+            name.eq_ignore_ascii_case("gzip") && !refused
+        })
+}
+
+/// Whether the `If-None-Match` header of the request lists the entity tag `etag`.
+///
+/// Entity tags are compared weakly, as required for `If-None-Match`.
+fn matches_if_none_match(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get_all(header::IF_NONE_MATCH)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Response header listing the default values applied to omitted query parameters.
+pub(crate) static DEFAULT_PARAMS_HEADER: &str = "x-cincinnati-default-params";
+
+/// Query parameter carrying the client identifier.
+static CLIENT_ID_PARAM: &str = "id";
+
+/// Request header shortening the timeout of the request, in milliseconds.
+pub(crate) static REQUEST_TIMEOUT_HEADER: &str = "x-cincinnati-timeout-ms";
+
+/// Stage of the requests exceeding their deadline outside of the plugins.
+static RENDER_STAGE: &str = "render";
+
+/// In-flight plugin chain runs, keyed by their cohort and sorted plugin parameters.
+pub(crate) type RenderCoalescer =
+    Coalescer<(Option<String>, Vec<(String, String)>), Result<RenderedGraph, GraphError>>;
+
+/// Compute the entity tag of a serialized graph.
+pub(crate) fn graph_etag(graph_json: &str) -> String {
+    let digest = Sha256::digest(graph_json.as_bytes());
+    format!("\"{}\"", hex::encode(&digest[..16]))
+}
+
+/// Return the most recent graph media type, for clients without content negotiation.
+pub(crate) fn latest_content_type() -> String {
+    commons::CINCINNATI_VERSION
+        .iter()
+        .max_by_key(|(_, version)| **version)
+        .map(|(content_type, _)| content_type.to_string())
+        .unwrap_or_else(|| commons::MIN_CINCINNATI_VERSION.to_string())
+}
+
+/// Render the graph for the given request, recording request metrics.
+async fn render_graph(
+    req: &HttpRequest,
+    app_data: &actix_web::web::Data<AppState>,
+) -> Result<RenderedGraph, GraphError> {
     let path = req.uri().path();
     GRAPH_INCOMING_REQS.with_label_values(&[path]).inc();
 
+    let timer = GRAPH_SERVE_HIST.start_timer();
+    let rendered = run_graph_plugins(req, app_data).await;
+    timer.observe_duration();
+    rendered
+}
+
+/// Validate the given request and run the plugin chain for it.
+pub(crate) async fn run_graph_plugins(
+    req: &HttpRequest,
+    app_data: &actix_web::web::Data<AppState>,
+) -> Result<RenderedGraph, GraphError> {
+    let (query, content_type) = graph_query(req, app_data)?;
+    let deadline = request_deadline(req, app_data)?;
+    render_query(&query, content_type, app_data, deadline).await
+}
+
+/// Return the query string of the given request, with legacy parameters mapped
+/// to modern ones, along with the negotiated response media type.
+pub(crate) fn graph_query(
+    req: &HttpRequest,
+    app_data: &AppState,
+) -> Result<(String, String), GraphError> {
     let accept_default = header::HeaderValue::from_static(CONTENT_TYPE);
 
     let accept_versions: Vec<header::HeaderValue> = commons::CINCINNATI_VERSION
@@ -71,60 +396,402 @@ async fn _index(
     let content_type: String =
         commons::validate_content_type(req.headers(), accept_versions, accept_default)?;
 
+    // Map the legacy parameters of earlier clients to modern ones.
+    let query = app_data
+        .legacy_params
+        .rewrite(req.query_string(), req.headers())
+        .into_owned();
+
+    Ok((query, content_type))
+}
+
+/// Return the deadline of the given request, shortened by its
+/// `REQUEST_TIMEOUT_HEADER`, if any.
+pub(crate) fn request_deadline(
+    req: &HttpRequest,
+    app_data: &AppState,
+) -> Result<Option<std::time::SystemTime>, GraphError> {
+    let requested = req
+        .headers()
+        .get(REQUEST_TIMEOUT_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .filter(|millis| *millis > 0)
+                .map(std::time::Duration::from_millis)
+                .ok_or_else(|| {
+                    GraphError::InvalidParams(format!(
+                        "invalid {} header, expected a positive number of milliseconds",
+                        REQUEST_TIMEOUT_HEADER
+                    ))
+                })
+        })
+        .transpose()?;
+    Ok(app_data.deadline(requested))
+}
+
+/// Run the plugin chain for the given query string and response media type,
+/// failing once the deadline, if any, is exceeded.
+pub(crate) async fn render_query(
+    query: &str,
+    content_type: String,
+    app_data: &AppState,
+    deadline: Option<std::time::SystemTime>,
+) -> Result<RenderedGraph, GraphError> {
+    // Waits for shared runs of the plugin chain are bounded by the deadline of each request.
+    let rendered = commons::deadline::within(
+        deadline,
+        RENDER_STAGE,
+        _render_query(query, content_type, app_data, deadline),
+    )
+    .await;
+    if let Err(GraphError::DeadlineExceeded(stage)) = &rendered {
+        GRAPH_DEADLINE_EXCEEDED.with_label_values(&[stage]).inc();
+    }
+    rendered
+}
+
+async fn _render_query(
+    query: &str,
+    content_type: String,
+    app_data: &AppState,
+    deadline: Option<std::time::SystemTime>,
+) -> Result<RenderedGraph, GraphError> {
+    // Fill in the default values of the parameters omitted by the client.
+    let (query, default_params) = apply_default_params(&app_data.default_params, query);
+    let query = query.as_str();
+
     // Check for required client parameters.
     let mandatory_params = &app_data.mandatory_params;
-    commons::ensure_query_params(mandatory_params, req.query_string())?;
+    commons::ensure_query_params(mandatory_params, query)?;
+    app_data.param_constraints.ensure_query_values(query)?;
 
-    let mut plugin_params = Query::<HashMap<String, String>>::from_query(req.query_string())
+    let mut plugin_params = Query::<HashMap<String, String>>::from_query(query)
         .map(|query| query.into_inner())
         .map_err(|e| commons::GraphError::InvalidParams(e.to_string()))?;
+    let capabilities = Capabilities::negotiate(&mut plugin_params)?;
+
+    // Pinned clients are served the graph of their pin, processed by the main plugin chain.
+    let client_id = plugin_params.get(CLIENT_ID_PARAM).map(String::as_str);
+    if let Some(pin) = app_data
+        .pins
+        .assign(client_id, std::time::SystemTime::now())
+    {
+        let plugins = app_data.plugins;
+        cincinnati::plugins::validate_plugin_parameters(plugins.iter(), &plugin_params)?;
+        plugin_params.insert(String::from("content_type"), content_type);
+        insert_deadline_param(&mut plugin_params, deadline);
+
+        let key = render_key(&plugin_params);
+        let mut rendered = app_data
+            .pins
+            .render(pin, key, app_data.pipeline.as_ref(), plugins, plugin_params)
+            .await?;
+        rendered.pin = Some(pin.name().to_string());
+        rendered.default_params = default_params;
+        rendered.capabilities = capabilities;
+        return Ok(rendered);
+    }
+
+    // Clients assigned to a cohort are served its plugin chain, unless cohort serving is disabled.
+    let cohort = if app_data.features.is_enabled(features::COHORT_SERVING) {
+        app_data
+            .cohorts
+            .assign(plugin_params.get(CLIENT_ID_PARAM).map(String::as_str))
+    } else {
+        None
+    };
+    app_data.cohorts.record(cohort);
+    let plugins = cohort.map_or(app_data.plugins, |cohort| cohort.plugins);
+    let cohort = cohort.map(|cohort| cohort.name.clone());
+
+    // Check that plugin-specific parameters are addressed to configured plugins.
+    cincinnati::plugins::validate_plugin_parameters(plugins.iter(), &plugin_params)?;
 
     plugin_params.insert(String::from("content_type"), content_type);
+    insert_deadline_param(&mut plugin_params, deadline);
 
-    let timer = GRAPH_SERVE_HIST.start_timer();
+    // Identical concurrent requests share a single run of the plugin chain.
+    let key = render_key(&plugin_params);
 
+    // Requests relying on defaults share runs with those setting the same values explicitly,
+    // thus applied defaults are only recorded once the graph is rendered.
     let cx = ot_context::current();
-    let response = process_plugins(app_data.plugins.iter(), plugin_params)
-        .with_context(cx)
-        .await;
+    let pipeline = app_data.pipeline.as_ref();
+    let variants = &app_data.variants;
+    let mut rendered = app_data
+        .inflight_renders
+        .run((cohort.clone(), key.clone()), || {
+            async move {
+                let mut rendered =
+                    process_variant(variants, pipeline, plugins, &key, plugin_params).await?;
+                rendered.cohort = cohort;
+                Ok(rendered)
+            }
+            .with_context(cx)
+        })
+        .await?;
+    rendered.default_params = default_params;
+    rendered.capabilities = capabilities;
+    Ok(rendered)
+}
 
-    timer.observe_duration();
-    response
+/// Pass the deadline of the request to the plugins, if any.
+pub(crate) fn insert_deadline_param(
+    plugin_params: &mut HashMap<String, String>,
+    deadline: Option<std::time::SystemTime>,
+) {
+    if let Some(deadline) = deadline {
+        plugin_params.insert(
+            commons::GRAPH_DEADLINE_PARAM_KEY.to_string(),
+            commons::deadline::to_param(deadline),
+        );
+    }
+}
+
+/// Return the sorted plugin parameters identifying the rendered graph.
+///
+/// Client identifiers and deadlines do not affect the rendered graph, thus
+/// are not part of the key.
+fn render_key(plugin_params: &HashMap<String, String>) -> Vec<(String, String)> {
+    let mut key: Vec<(String, String)> = plugin_params
+        .iter()
+        .filter(|(k, _)| {
+            k.as_str() != CLIENT_ID_PARAM && k.as_str() != commons::GRAPH_DEADLINE_PARAM_KEY
+        })
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    key.sort();
+    key
+}
+
+/// Append to `query` the `defaults` of the parameters it omits, returning
+/// the resulting query and the applied defaults.
+pub(crate) fn apply_default_params(
+    defaults: &BTreeMap<String, String>,
+    query: &str,
+) -> (String, Vec<(String, String)>) {
+    if defaults.is_empty() {
+        return (query.to_string(), vec![]);
+    }
+
+    let present: HashSet<String> = url::form_urlencoded::parse(query.as_bytes())
+        .map(|(key, _)| key.into_owned())
+        .collect();
+    let applied: Vec<(String, String)> = defaults
+        .iter()
+        .filter(|(key, _)| !present.contains(*key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    if applied.is_empty() {
+        return (query.to_string(), applied);
+    }
+
+    let mut serializer = url::form_urlencoded::Serializer::for_suffix(query.to_string(), 0);
+    serializer.extend_pairs(&applied);
+    (serializer.finish(), applied)
+}
+
+/// Worker running the plugin chain for callers which require `Send` futures.
+///
+/// Plugin processing keeps tracing guards across await points, thus it cannot
+/// be moved between threads; the worker runs it on its own arbiter instead.
+#[derive(Clone, Debug)]
+pub(crate) struct RenderWorker {
+    arbiter: ArbiterHandle,
+}
+
+impl RenderWorker {
+    /// Start a new worker. This must be called from within a running system.
+    pub(crate) fn new() -> Self {
+        Self {
+            arbiter: Arbiter::new().handle(),
+        }
+    }
+
+    /// Run the plugin chain for the given query string and response media type.
+    pub(crate) async fn render(
+        &self,
+        query: String,
+        content_type: String,
+        app_data: AppState,
+    ) -> Result<RenderedGraph, GraphError> {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        let spawned = self.arbiter.spawn_fn(move || {
+            actix_web::rt::spawn(async move {
+                let deadline = app_data.deadline(None);
+                let rendered = render_query(&query, content_type, &app_data, deadline).await;
+                let _ = tx.send(rendered);
+            });
+        });
+        if !spawned {
+            return Err(GraphError::FailedPluginExecution(
+                "render worker is not running".to_string(),
+            ));
+        }
+
+        rx.await.map_err(|_| {
+            GraphError::FailedPluginExecution("graph rendering was cancelled".to_string())
+        })?
+    }
+}
+
+/// Plugins fetching the upstream graph, rather than applying policies to it.
+static UPSTREAM_PLUGINS: &[&str] = &[CincinnatiGraphFetchPlugin::PLUGIN_NAME];
+
+/// Whether `plugin` fetches the upstream graph.
+pub(crate) fn is_upstream_plugin(plugin: &BoxedPlugin) -> bool {
+    UPSTREAM_PLUGINS.contains(&plugin.get_name())
 }
 
-async fn process_plugins<P>(
+/// Run the plugin chain on the upstream graph.
+///
+/// In standalone mode, the graph built in-process is processed instead, thus
+/// the plugins fetching the upstream graph are skipped.
+pub(crate) async fn process_upstream_plugins<P>(
+    pipeline: Option<&standalone::Pipeline>,
     plugins: P,
+    mut plugin_params: HashMap<String, String>,
+) -> Result<RenderedGraph, GraphError>
+where
+    P: std::iter::Iterator<Item = &'static BoxedPlugin>,
+    P: 'static + Sync + Send,
+{
+    let pipeline = match pipeline {
+        Some(pipeline) => pipeline,
+        None => return process_plugins(plugins, Default::default(), plugin_params).await,
+    };
+
+    let (graph, stale_since) = pipeline.graph()?;
+    if let Some(stale_since) = stale_since {
+        plugin_params.insert(
+            commons::GRAPH_STALE_SINCE_PARAM_KEY.to_string(),
+            stale_since,
+        );
+    }
+    let plugins = plugins.filter(|plugin| !is_upstream_plugin(plugin));
+    process_plugins(plugins, graph, plugin_params).await
+}
+
+/// Run the plugin chain on the upstream graph, serving the variants already
+/// rendered from the same upstream graph from the variant store.
+///
+/// The plugins fetching the upstream graph always run, as the digest of the
+/// upstream graph they report is part of the content address of the variant.
+async fn process_variant(
+    variants: &VariantStore,
+    pipeline: Option<&standalone::Pipeline>,
+    plugins: &'static [BoxedPlugin],
+    key: &[(String, String)],
     plugin_params: HashMap<String, String>,
-) -> Result<HttpResponse, GraphError>
+) -> Result<RenderedGraph, GraphError> {
+    // Graphs built in-process have no digest to address their variants by.
+    if !variants.is_enabled() || pipeline.is_some() {
+        return process_upstream_plugins(pipeline, plugins.iter(), plugin_params).await;
+    }
+
+    let (mut upstream_io, upstream_timings) = cincinnati::plugins::process_timed(
+        plugins
+            .iter()
+            .take_while(|plugin| is_upstream_plugin(plugin)),
+        cincinnati::plugins::PluginIO::InternalIO(InternalIO {
+            graph: Default::default(),
+            parameters: plugin_params,
+        }),
+    )
+    .await
+    .map_err(GraphError::from_error)?;
+    let policy_plugins = plugins
+        .iter()
+        .skip_while(|plugin| is_upstream_plugin(plugin));
+
+    // Variants of graphs with schedules are rendered again whenever one of
+    // their blocked edges or risks activates or expires.
+    let mut key = key.to_vec();
+    let reached_bounds = cincinnati::plugins::internal::edge_schedule::reached_bounds(
+        &mut upstream_io.graph,
+        chrono::Utc::now(),
+    );
+    if reached_bounds > 0 {
+        key.push((
+            "schedule_bounds_reached".to_string(),
+            reached_bounds.to_string(),
+        ));
+    }
+
+    // Upstreams which do not report a digest, and unregistered chains, are
+    // rendered on every request.
+    let address = upstream_io
+        .parameters
+        .get(commons::GRAPH_UPSTREAM_DIGEST_PARAM_KEY)
+        .and_then(|digest| variants.address(plugins, digest, &key));
+    if let Some(mut rendered) = address.as_deref().and_then(|address| variants.get(address)) {
+        rendered.stale_since = upstream_io
+            .parameters
+            .get(commons::GRAPH_STALE_SINCE_PARAM_KEY)
+            .cloned();
+        rendered.plugin_timings = TimingBreakdown(upstream_timings);
+        return Ok(rendered);
+    }
+
+    let mut rendered =
+        process_plugins(policy_plugins, upstream_io.graph, upstream_io.parameters).await?;
+    let mut plugin_timings = upstream_timings;
+    plugin_timings.append(&mut rendered.plugin_timings.0);
+    rendered.plugin_timings = TimingBreakdown(plugin_timings);
+    if let Some(address) = address {
+        variants.insert(address, rendered.clone());
+    }
+    Ok(rendered)
+}
+
+/// Run the plugin chain on `graph`, the first plugin usually producing it.
+pub(crate) async fn process_plugins<P>(
+    plugins: P,
+    graph: cincinnati::Graph,
+    plugin_params: HashMap<String, String>,
+) -> Result<RenderedGraph, GraphError>
 where
     P: std::iter::Iterator<Item = &'static BoxedPlugin>,
     P: 'static + Sync + Send,
 {
-    let internal_io = cincinnati::plugins::process(
+    let (mut internal_io, plugin_timings) = cincinnati::plugins::process_timed(
         plugins,
         cincinnati::plugins::PluginIO::InternalIO(cincinnati::plugins::InternalIO {
-            graph: Default::default(),
+            graph,
             parameters: plugin_params,
         }),
     )
     .await
-    .map_err(|e| match e.downcast::<GraphError>() {
-        Ok(graph_error) => graph_error,
-        Err(other_error) => GraphError::FailedPluginExecution(other_error.to_string()),
-    })?;
+    .map_err(GraphError::from_error)?;
 
+    Capabilities::from_params(&internal_io.parameters).apply(&mut internal_io.graph);
     let versioned_graph = add_version_information(&internal_io);
 
     let graph_json = serde_json::to_string(&versioned_graph)
         .map_err(|e| GraphError::FailedJsonOut(e.to_string()))?;
 
     let content_type = match &internal_io.parameters.get("content_type") {
-        Some(version) => *version,
-        None => *commons::MIN_CINCINNATI_VERSION,
+        Some(version) => version.to_string(),
+        None => commons::MIN_CINCINNATI_VERSION.to_string(),
     };
-    Ok(HttpResponse::Ok()
-        .content_type(content_type)
-        .body(graph_json))
+    let stale_since = internal_io
+        .parameters
+        .get(commons::GRAPH_STALE_SINCE_PARAM_KEY)
+        .cloned();
+    Ok(RenderedGraph {
+        content_type,
+        graph_json,
+        stale_since,
+        plugin_timings: TimingBreakdown(plugin_timings),
+        cohort: None,
+        pin: None,
+        default_params: vec![],
+        capabilities: Default::default(),
+        gzip_body: Default::default(),
+    })
 }
 
 /// add version information to the graph json
@@ -175,6 +842,70 @@ pub(crate) mod tests {
         );
     }
 
+    #[test]
+    fn default_params() {
+        let defaults: std::collections::BTreeMap<String, String> = vec![
+            ("arch".to_string(), "amd64".to_string()),
+            ("channel".to_string(), "stable-4.19".to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            graph::apply_default_params(&defaults, "arch=arm64"),
+            (
+                "arch=arm64&channel=stable-4.19".to_string(),
+                vec![("channel".to_string(), "stable-4.19".to_string())]
+            )
+        );
+        assert_eq!(
+            graph::apply_default_params(&defaults, ""),
+            (
+                "arch=amd64&channel=stable-4.19".to_string(),
+                defaults.clone().into_iter().collect()
+            )
+        );
+        assert_eq!(
+            graph::apply_default_params(&defaults, "channel=fast-4.19&arch=multi"),
+            ("channel=fast-4.19&arch=multi".to_string(), vec![])
+        );
+    }
+
+    #[test]
+    fn invalid_mandatory_params() {
+        use commons::params::{ParamConstraint, ParamConstraints, ParamFormat};
+
+        let rt = common_init();
+        let constraint = ParamConstraint {
+            format: Some(ParamFormat::Uuid),
+            ..Default::default()
+        };
+        let state = AppState {
+            mandatory_params: vec!["id".to_string()].into_iter().collect(),
+            param_constraints: ParamConstraints::try_new(
+                &vec![("id".to_string(), constraint)].into_iter().collect(),
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        let app_data = actix_web::web::Data::new(state);
+
+        let http_req = actix_web::test::TestRequest::get()
+            .uri("/graph?id=not-a-uuid")
+            .insert_header((
+                http::header::ACCEPT,
+                http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+            ))
+            .to_http_request();
+        let graph_call = graph::index(http_req, app_data);
+        let resp = rt.block_on(graph_call).unwrap_err();
+
+        assert_eq!(
+            resp,
+            graph::GraphError::InvalidParams("unexpected value of id".to_string())
+        );
+    }
+
     #[test]
     fn failed_plugin_execution() -> Result<(), Error> {
         let rt = common_init();
@@ -379,8 +1110,10 @@ pub(crate) mod tests {
                     ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
                     ("upstream", "http://offline.url.test")
                 )?],
-                expected_result: TestResult::Error(commons::GraphError::FailedUpstreamFetch(
-                    "error sending request for url (http://offline.url.test/): error trying to connect".to_string(),
+                expected_result: TestResult::Error(commons::GraphError::Upstream(
+                    commons::UpstreamError::Unreachable(
+                        "error sending request for url (http://offline.url.test/): error trying to connect".to_string(),
+                    ),
                 )),
             },
             TestParams {
@@ -401,6 +1134,15 @@ pub(crate) mod tests {
                     "channel 'invalid:channel'".to_string(),
                 )),
             },
+            TestParams {
+                name: "unknown plugin parameter",
+                mandatory_params: &[],
+                passed_params: &[("plugin.channel-filter.unknown", "value")],
+                plugin_config: &[plugin_config!(("name", ChannelFilterPlugin::PLUGIN_NAME))?],
+                expected_result: TestResult::Error(commons::GraphError::InvalidParams(
+                    "unknown plugin parameters: plugin.channel-filter.unknown".to_string(),
+                )),
+            },
             TestParams {
                 name: "invalid channel name with equal sign",
                 mandatory_params: &["channel"],
@@ -422,4 +1164,510 @@ pub(crate) mod tests {
             .map_err(|e| format_err!("test '{}' failed: {}", test_param.name, e))
         })
     }
+
+    #[test]
+    fn serve_cohort_plugins() -> Result<(), Error> {
+        let rt = common_init();
+
+        let fetch = || {
+            plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", &format!("{}/cohorts", mockito::server_url()))
+            )
+        };
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[
+                fetch()?,
+                plugin_config!(("name", ChannelFilterPlugin::PLUGIN_NAME))?,
+            ],
+            None,
+        )?;
+        let canary_plugins = cincinnati::plugins::catalog::build_plugins(&[fetch()?], None)?;
+        let state = AppState {
+            plugins: Box::leak(Box::new(plugins)),
+            cohorts: crate::cohorts::Cohorts::new(
+                String::new(),
+                vec![(
+                    "canary".to_string(),
+                    100.0,
+                    &*Box::leak(canary_plugins.into_boxed_slice()),
+                )],
+            ),
+            ..Default::default()
+        };
+        let app_data = actix_web::web::Data::new(state);
+
+        let _m = mockito::mock("GET", "/cohorts")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"nodes":[
+                    {"version":"4.1.0","payload":"image/4.1.0","metadata":{
+                        "io.openshift.upgrades.graph.release.channels":"stable-4.1"}},
+                    {"version":"4.2.0","payload":"image/4.2.0","metadata":{}}
+                ],"edges":[[0,1]]}"#,
+            )
+            .create();
+
+        let cohort_nodes = |query: &str| -> Result<(String, usize), Error> {
+            let req = actix_web::test::TestRequest::get()
+                .uri(&format!("http://unused.test?{}", query))
+                .insert_header((
+                    http::header::ACCEPT,
+                    http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+                ))
+                .to_http_request();
+            let resp = rt.block_on(graph::index(req, app_data.clone()))?;
+            let cohort = resp
+                .headers()
+                .get(crate::cohorts::COHORT_HEADER)
+                .unwrap()
+                .to_str()?
+                .to_string();
+            let body = resp.into_body().try_into_bytes().unwrap();
+            let graph: serde_json::Value = serde_json::from_slice(&body)?;
+            Ok((cohort, graph["nodes"].as_array().unwrap().len()))
+        };
+
+        // The canary chain does not filter channels.
+        assert_eq!(
+            cohort_nodes("channel=stable-4.1&id=01234567-0123-0123-0123-0123456789ab")?,
+            ("canary".to_string(), 2)
+        );
+        assert_eq!(
+            cohort_nodes("channel=stable-4.1")?,
+            ("default".to_string(), 1)
+        );
+
+        // Disabling cohort serving serves everyone the default chain.
+        app_data
+            .features
+            .set(crate::features::COHORT_SERVING, false)?;
+        assert_eq!(
+            cohort_nodes("channel=stable-4.1&id=01234567-0123-0123-0123-0123456789ab")?,
+            ("default".to_string(), 1)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_not_modified() -> Result<(), Error> {
+        let rt = common_init();
+
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                (
+                    "upstream",
+                    &format!("{}/not-modified", mockito::server_url())
+                )
+            )?],
+            None,
+        )?;
+        let state = AppState {
+            plugins: Box::leak(Box::new(plugins)),
+            ..Default::default()
+        };
+        let app_data = actix_web::web::Data::new(state);
+
+        let _m = mockito::mock("GET", "/not-modified")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"nodes":[],"edges":[]}"#)
+            .create();
+
+        let fetch = |if_none_match: Option<&str>| -> Result<actix_web::HttpResponse, Error> {
+            let mut req = actix_web::test::TestRequest::get()
+                .uri("http://unused.test")
+                .insert_header((
+                    http::header::ACCEPT,
+                    http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+                ));
+            if let Some(etag) = if_none_match {
+                req = req.insert_header((http::header::IF_NONE_MATCH, etag.to_string()));
+            }
+            Ok(rt.block_on(graph::index(req.to_http_request(), app_data.clone()))?)
+        };
+
+        let resp = fetch(None)?;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let etag = resp
+            .headers()
+            .get(http::header::ETAG)
+            .unwrap()
+            .to_str()?
+            .to_string();
+
+        // Clients holding the served graph get its headers only.
+        for if_none_match in [
+            etag.clone(),
+            format!("W/{}", etag),
+            format!("\"other\", {}", etag),
+        ] {
+            let resp = fetch(Some(&if_none_match))?;
+            assert_eq!(resp.status(), http::StatusCode::NOT_MODIFIED);
+            assert_eq!(
+                resp.headers().get(http::header::ETAG).unwrap().to_str()?,
+                etag
+            );
+            assert!(resp.into_body().try_into_bytes().unwrap().is_empty());
+        }
+
+        // Clients holding another graph get the served one.
+        let resp = fetch(Some("\"other\""))?;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body = resp.into_body().try_into_bytes().unwrap();
+        let graph: serde_json::Value = serde_json::from_slice(&body)?;
+        assert!(graph["nodes"].as_array().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_negotiated_capabilities() -> Result<(), Error> {
+        use crate::capabilities::CAPABILITIES_HEADER;
+
+        let rt = common_init();
+
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                (
+                    "upstream",
+                    &format!("{}/capabilities", mockito::server_url())
+                )
+            )?],
+            None,
+        )?;
+        let state = AppState {
+            plugins: Box::leak(Box::new(plugins)),
+            ..Default::default()
+        };
+        let app_data = actix_web::web::Data::new(state);
+
+        let _m = mockito::mock("GET", "/capabilities")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(serde_json::to_string(
+                &cincinnati::testing::generate_graph(true, false),
+            )?)
+            .create();
+
+        let fetch = |query: &str| -> Result<(String, serde_json::Value), Error> {
+            let req = actix_web::test::TestRequest::get()
+                .uri(&format!("http://unused.test?{}", query))
+                .insert_header((
+                    http::header::ACCEPT,
+                    http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+                ))
+                .to_http_request();
+            let resp = rt.block_on(graph::index(req, app_data.clone()))?;
+            let capabilities = resp
+                .headers()
+                .get(CAPABILITIES_HEADER)
+                .unwrap()
+                .to_str()?
+                .to_string();
+            let body = resp.into_body().try_into_bytes().unwrap();
+            Ok((capabilities, serde_json::from_slice(&body)?))
+        };
+
+        // Clients listing no capabilities are served all of them.
+        let (capabilities, graph) = fetch("")?;
+        assert_eq!(capabilities, "conditional-edges,multi-arch");
+        assert!(graph.get("conditionalEdges").is_some());
+
+        let (capabilities, graph) = fetch("capabilities=conditional-edges,future-feature")?;
+        assert_eq!(capabilities, "conditional-edges");
+        assert!(graph.get("conditionalEdges").is_some());
+
+        // Clients without conditional edges are served the graph without them.
+        let (capabilities, graph) = fetch("capabilities=multi-arch")?;
+        assert_eq!(capabilities, "multi-arch");
+        assert!(graph.get("conditionalEdges").is_none());
+        assert_eq!(graph["nodes"].as_array().unwrap().len(), 3);
+
+        assert!(matches!(
+            fetch("arch=multi&capabilities=conditional-edges")
+                .unwrap_err()
+                .downcast::<graph::GraphError>()?,
+            graph::GraphError::InvalidParams(_)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_pinned_graphs() -> Result<(), Error> {
+        let rt = common_init();
+
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[
+                plugin_config!(
+                    ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                    ("upstream", &format!("{}/pins", mockito::server_url()))
+                )?,
+                plugin_config!(("name", ChannelFilterPlugin::PLUGIN_NAME))?,
+            ],
+            None,
+        )?;
+        let pin = |name: &str, id: &str, snapshot: Option<&str>| crate::pins::PinSettings {
+            name: name.to_string(),
+            ids: vec![id.to_string()].into_iter().collect(),
+            from: None,
+            until: std::time::SystemTime::now() + std::time::Duration::from_secs(3600),
+            snapshot: snapshot.map(str::to_string),
+        };
+        let state = AppState {
+            plugins: Box::leak(Box::new(plugins)),
+            pins: crate::pins::Pins::try_new(
+                &[
+                    pin("freeze", "cluster-a", None),
+                    pin(
+                        "incident",
+                        "cluster-c",
+                        Some("20261014T120000Z-3f2a9c1b0d4e"),
+                    ),
+                ],
+                Some(&format!("{}/pin-snapshots", mockito::server_url())),
+                Some(1024 * 1024),
+            )?,
+            ..Default::default()
+        };
+        let app_data = actix_web::web::Data::new(state);
+
+        let graph_body = |versions: &[&str]| {
+            let nodes: Vec<String> = versions
+                .iter()
+                .map(|version| {
+                    format!(
+                        r#"{{"version":"{0}","payload":"image/{0}","metadata":{{
+                            "io.openshift.upgrades.graph.release.channels":"stable-4.1"}}}}"#,
+                        version
+                    )
+                })
+                .collect();
+            format!(r#"{{"nodes":[{}],"edges":[]}}"#, nodes.join(","))
+        };
+        let mock_graph = |path: &str, versions: &[&str]| {
+            mockito::mock("GET", path)
+                .match_query(mockito::Matcher::Any)
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(graph_body(versions))
+                .create()
+        };
+
+        let pinned_nodes = |query: &str| -> Result<(Option<String>, usize), Error> {
+            let req = actix_web::test::TestRequest::get()
+                .uri(&format!("http://unused.test?{}", query))
+                .insert_header((
+                    http::header::ACCEPT,
+                    http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+                ))
+                .to_http_request();
+            let resp = rt.block_on(graph::index(req, app_data.clone()))?;
+            let pin = resp
+                .headers()
+                .get(crate::pins::PIN_HEADER)
+                .map(|pin| pin.to_str().map(str::to_string))
+                .transpose()?;
+            let body = resp.into_body().try_into_bytes().unwrap();
+            let graph: serde_json::Value = serde_json::from_slice(&body)?;
+            Ok((pin, graph["nodes"].as_array().unwrap().len()))
+        };
+
+        let upstream = mock_graph("/pins", &["4.1.0"]);
+        assert_eq!(
+            pinned_nodes("channel=stable-4.1&id=cluster-a")?,
+            (Some("freeze".to_string()), 1)
+        );
+
+        // Pinned clients keep being served the frozen graph once the upstream changes.
+        drop(upstream);
+        rt.block_on(cincinnati::plugins::internal::cincinnati_graph_fetch::clear_cached_graphs());
+        let _upstream = mock_graph("/pins", &["4.1.0", "4.1.1"]);
+        assert_eq!(pinned_nodes("channel=stable-4.1&id=cluster-b")?, (None, 2));
+        assert_eq!(
+            pinned_nodes("channel=stable-4.1&id=cluster-a")?,
+            (Some("freeze".to_string()), 1)
+        );
+
+        // Snapshots are processed by the policy plugins.
+        let _snapshot = mock_graph(
+            "/pin-snapshots/20261014T120000Z-3f2a9c1b0d4e",
+            &["4.1.0", "4.1.1", "4.1.2"],
+        );
+        assert_eq!(
+            pinned_nodes("channel=stable-4.1&id=cluster-c")?,
+            (Some("incident".to_string()), 3)
+        );
+        assert_eq!(
+            pinned_nodes("channel=fast-4.1&id=cluster-c")?,
+            (Some("incident".to_string()), 0)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn gzip_bodies() -> Result<(), Error> {
+        use std::io::Read;
+
+        let accepts = |accept_encoding: &str| {
+            let req = actix_web::test::TestRequest::get()
+                .insert_header((http::header::ACCEPT_ENCODING, accept_encoding))
+                .to_http_request();
+            graph::accepts_gzip(&req)
+        };
+        assert!(accepts("gzip"));
+        assert!(accepts("br;q=1.0, GZIP;q=0.5"));
+        assert!(!accepts("br"));
+        assert!(!accepts("gzip;q=0"));
+        assert!(!graph::accepts_gzip(
+            &actix_web::test::TestRequest::get().to_http_request()
+        ));
+
+        let rendered = graph::RenderedGraph {
+            content_type: cincinnati::CONTENT_TYPE.to_string(),
+            graph_json: r#"{"nodes":[],"edges":[]}"#.to_string(),
+            stale_since: None,
+            plugin_timings: commons::latency::TimingBreakdown(vec![]),
+            cohort: None,
+            pin: None,
+            default_params: vec![],
+            capabilities: Default::default(),
+            gzip_body: Default::default(),
+        };
+        let mut graph_json = String::new();
+        flate2::read::GzDecoder::new(rendered.clone().gzip_body())
+            .read_to_string(&mut graph_json)?;
+        assert_eq!(graph_json, rendered.graph_json);
+        // Clones share the compressed body.
+        assert!(rendered.gzip_body.get().is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn request_deadlines() -> Result<(), Error> {
+        let rt = common_init();
+        let state = AppState {
+            request_timeout: Some(std::time::Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let request = |timeout: Option<&'static str>| {
+            let mut req = actix_web::test::TestRequest::get().uri("http://unused.test");
+            if let Some(timeout) = timeout {
+                req = req.insert_header((graph::REQUEST_TIMEOUT_HEADER, timeout));
+            }
+            req.to_http_request()
+        };
+        let timeout = |req: &actix_web::HttpRequest| -> Result<std::time::Duration, Error> {
+            let deadline = graph::request_deadline(req, &state)?.unwrap();
+            Ok(deadline.duration_since(std::time::SystemTime::now())?)
+        };
+
+        assert!(timeout(&request(None))? > std::time::Duration::from_secs(5));
+        assert!(timeout(&request(Some("500")))? <= std::time::Duration::from_millis(500));
+        // Clients cannot extend the configured timeout.
+        assert!(timeout(&request(Some("60000")))? <= std::time::Duration::from_secs(10));
+        for invalid in &["0", "-1", "fast"] {
+            assert!(graph::request_deadline(&request(Some(*invalid)), &state).is_err());
+        }
+        assert_eq!(
+            graph::request_deadline(&request(None), &AppState::default())?,
+            None
+        );
+
+        // Requests past their deadline fail with the stage exceeding it.
+        let rendered = rt.block_on(graph::render_query(
+            "channel=stable-4.1",
+            cincinnati::CONTENT_TYPE.to_string(),
+            &state,
+            Some(std::time::SystemTime::now()),
+        ));
+        match rendered {
+            Err(graph::GraphError::DeadlineExceeded(stage)) => assert_eq!(stage, "render"),
+            other => bail!("expected DeadlineExceeded error, got: {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_stored_variants() -> Result<(), Error> {
+        let rt = common_init();
+
+        let plugin_settings = vec![
+            plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", &format!("{}/variants", mockito::server_url()))
+            )?,
+            plugin_config!(("name", ChannelFilterPlugin::PLUGIN_NAME))?,
+        ];
+        let plugins: &'static [BoxedPlugin] = Box::leak(
+            cincinnati::plugins::catalog::build_plugins(&plugin_settings, None)?.into_boxed_slice(),
+        );
+        let variants = crate::variants::VariantStore::new(crate::variants::DEFAULT_MAX_BYTES);
+        variants.register_chain(plugins, crate::variants::chain_digest(&plugin_settings));
+        let state = AppState {
+            plugins,
+            variants,
+            ..Default::default()
+        };
+        let _upstream = mockito::mock("GET", "/variants")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"nodes":[{"version":"4.1.0","payload":"image/4.1.0","metadata":{
+                    "io.openshift.upgrades.graph.release.channels":"stable-4.1"}}],"edges":[]}"#,
+            )
+            .create();
+
+        let render = |query: &str| {
+            rt.block_on(graph::render_query(
+                query,
+                cincinnati::CONTENT_TYPE.to_string(),
+                &state,
+                None,
+            ))
+            .map_err(|e| format_err!("{}", e))
+        };
+        let plugin_names = |rendered: &graph::RenderedGraph| -> Vec<&'static str> {
+            rendered
+                .plugin_timings
+                .0
+                .iter()
+                .map(|(name, _)| *name)
+                .collect()
+        };
+
+        let rendered = render("channel=stable-4.1")?;
+        assert_eq!(
+            plugin_names(&rendered),
+            vec![
+                CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+                ChannelFilterPlugin::PLUGIN_NAME
+            ]
+        );
+
+        // Only the upstream graph is fetched for variants already rendered.
+        let stored = render("channel=stable-4.1")?;
+        assert_eq!(
+            plugin_names(&stored),
+            vec![CincinnatiGraphFetchPlugin::PLUGIN_NAME]
+        );
+        assert_eq!(stored.graph_json, rendered.graph_json);
+
+        let other = render("channel=fast-4.1")?;
+        assert_eq!(plugin_names(&other).len(), 2);
+        assert_ne!(other.graph_json, rendered.graph_json);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,480 @@
+//! Paginated graph endpoints.
+//!
+//! The nodes and edges of the graph are served in pages, for clients which
+//! cannot hold the whole graph document in memory. The first page renders the
+//! graph for the client parameters and pins the result as a snapshot,
+//! identified by its ETag. The cursors of the following pages address that
+//! snapshot, so that all pages are consistent even if the graph changes in the
+//! meantime. Snapshots are only retained for the most recently rendered
+//! graphs, after which pagination has to be restarted.
+
+use crate::graph::{self, graph_etag, latest_content_type};
+use crate::AppState;
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use commons::tracing::get_tracer;
+use commons::{api_response_error, GraphError};
+use opentelemetry::trace::{mark_span_as_active, Tracer};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Query parameter carrying the cursor of the requested page.
+static CURSOR_PARAM: &str = "cursor";
+
+/// Query parameter carrying the maximum number of items per page.
+static LIMIT_PARAM: &str = "limit";
+
+/// Query parameter carrying the comma-separated node fields to serve.
+static FIELDS_PARAM: &str = "fields";
+
+/// Fields of graph nodes.
+static NODE_FIELDS: &[&str] = &["version", "payload", "metadata"];
+
+/// Default number of items per page.
+const DEFAULT_PAGE_LIMIT: usize = 1000;
+
+/// Maximum number of items per page.
+const MAX_PAGE_LIMIT: usize = 10000;
+
+/// Number of graph snapshots retained for pagination.
+const MAX_SNAPSHOTS: usize = 16;
+
+/// Paginated collection of the graph.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Collection {
+    Nodes,
+    Edges,
+}
+
+impl Collection {
+    fn name(self) -> &'static str {
+        match self {
+            Collection::Nodes => "nodes",
+            Collection::Edges => "edges",
+        }
+    }
+}
+
+/// Nodes and edges of a rendered graph.
+#[derive(Debug)]
+struct Snapshot {
+    etag: String,
+    nodes: Vec<serde_json::Value>,
+    edges: Vec<serde_json::Value>,
+    stale_since: Option<String>,
+}
+
+impl Snapshot {
+    fn try_new(rendered: &graph::RenderedGraph) -> Result<Self, GraphError> {
+        let mut document: serde_json::Value = serde_json::from_str(&rendered.graph_json)
+            .map_err(|e| GraphError::FailedJsonIn(e.to_string()))?;
+        let mut take = |collection: Collection| match document
+            .get_mut(collection.name())
+            .map(serde_json::Value::take)
+        {
+            Some(serde_json::Value::Array(items)) => Ok(items),
+            _ => Err(GraphError::FailedJsonIn(format!(
+                "missing {} in rendered graph",
+                collection.name()
+            ))),
+        };
+
+        Ok(Self {
+            etag: graph_etag(&rendered.graph_json),
+            nodes: take(Collection::Nodes)?,
+            edges: take(Collection::Edges)?,
+            stale_since: rendered.stale_since.clone(),
+        })
+    }
+
+    fn items(&self, collection: Collection) -> &[serde_json::Value] {
+        match collection {
+            Collection::Nodes => &self.nodes,
+            Collection::Edges => &self.edges,
+        }
+    }
+}
+
+/// Most recently rendered graph snapshots, by ETag.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Snapshots {
+    snapshots: Arc<Mutex<VecDeque<Arc<Snapshot>>>>,
+}
+
+impl Snapshots {
+    fn get(&self, etag: &str) -> Option<Arc<Snapshot>> {
+        self.snapshots
+            .lock()
+            .iter()
+            .find(|snapshot| snapshot.etag == etag)
+            .cloned()
+    }
+
+    /// Retain `snapshot`, evicting the oldest snapshots beyond the limit.
+    fn insert(&self, snapshot: Arc<Snapshot>) {
+        let mut snapshots = self.snapshots.lock();
+        snapshots.retain(|retained| retained.etag != snapshot.etag);
+        snapshots.push_back(snapshot);
+        while snapshots.len() > MAX_SNAPSHOTS {
+            snapshots.pop_front();
+        }
+    }
+}
+
+/// Position in a collection of a graph snapshot.
+#[derive(Debug, PartialEq, Eq)]
+struct Cursor {
+    collection: Collection,
+    etag: String,
+    offset: usize,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        base64::encode_config(
+            format!(
+                "{}:{}:{}",
+                self.collection.name(),
+                self.etag.trim_matches('"'),
+                self.offset
+            ),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    /// Decode a cursor, which must address `collection`.
+    fn decode(cursor: &str, collection: Collection) -> Result<Self, GraphError> {
+        let invalid = || GraphError::InvalidParams(format!("invalid {}", CURSOR_PARAM));
+        let decoded = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .ok_or_else(invalid)?;
+        let mut parts = decoded.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(etag), Some(offset)) if name == collection.name() => Ok(Self {
+                collection,
+                etag: format!("\"{}\"", etag),
+                offset: offset.parse().map_err(|_| invalid())?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Pagination parameters, split from the client parameters of the graph.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct PageQuery {
+    cursor: Option<Cursor>,
+    limit: usize,
+    fields: Option<Vec<String>>,
+    /// Query string of the graph, without the pagination parameters.
+    graph_query: String,
+}
+
+impl PageQuery {
+    fn parse(query: &str, collection: Collection) -> Result<Self, GraphError> {
+        let mut page = PageQuery {
+            limit: DEFAULT_PAGE_LIMIT,
+            ..Default::default()
+        };
+        let mut graph_query = url::form_urlencoded::Serializer::new(String::new());
+
+        for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            if key == CURSOR_PARAM {
+                page.cursor = Some(Cursor::decode(&value, collection)?);
+            } else if key == LIMIT_PARAM {
+                page.limit = match value.parse() {
+                    Ok(limit) if (1..=MAX_PAGE_LIMIT).contains(&limit) => limit,
+                    _ => {
+                        return Err(GraphError::InvalidParams(format!(
+                            "{} must be between 1 and {}",
+                            LIMIT_PARAM, MAX_PAGE_LIMIT
+                        )))
+                    }
+                };
+            } else if key == FIELDS_PARAM {
+                if collection != Collection::Nodes {
+                    return Err(GraphError::InvalidParams(format!(
+                        "{} can only be selected for nodes",
+                        FIELDS_PARAM
+                    )));
+                }
+                let fields: Vec<String> = value
+                    .split(',')
+                    .map(|field| field.trim().to_string())
+                    .collect();
+                if let Some(unknown) = fields.iter().find(|f| !NODE_FIELDS.contains(&f.as_str())) {
+                    return Err(GraphError::InvalidParams(format!(
+                        "unknown node field '{}'",
+                        unknown
+                    )));
+                }
+                page.fields = Some(fields);
+            } else {
+                graph_query.append_pair(&key, &value);
+            }
+        }
+
+        page.graph_query = graph_query.finish();
+        Ok(page)
+    }
+}
+
+/// Serve a page of the nodes of the graph.
+pub(crate) async fn nodes(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    _index(&req, &app_data, Collection::Nodes)
+        .await
+        .map_err(|e| api_response_error(&req, e))
+}
+
+/// Serve a page of the edges of the graph.
+pub(crate) async fn edges(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    _index(&req, &app_data, Collection::Edges)
+        .await
+        .map_err(|e| api_response_error(&req, e))
+}
+
+async fn _index(
+    req: &HttpRequest,
+    app_data: &AppState,
+    collection: Collection,
+) -> Result<HttpResponse, GraphError> {
+    let span = get_tracer().start("graph_pages");
+    let _active_span = mark_span_as_active(span);
+
+    let page_query = PageQuery::parse(req.query_string(), collection)?;
+
+    // Snapshots may also be addressed by `If-Match`, e.g. to fetch the edges of the
+    // snapshot whose nodes are being fetched.
+    let pinned = match &page_query.cursor {
+        Some(cursor) => Some((cursor.etag.clone(), cursor.offset)),
+        None => req
+            .headers()
+            .get(header::IF_MATCH)
+            .map(|etag| match etag.to_str() {
+                Ok(etag) => Ok((etag.to_string(), 0)),
+                Err(_) => Err(GraphError::InvalidParams(
+                    "invalid If-Match header".to_string(),
+                )),
+            })
+            .transpose()?,
+    };
+
+    // Snapshots were rendered for validated requests, thus are served directly.
+    let (snapshot, offset) = match pinned {
+        Some((etag, offset)) => {
+            let snapshot = app_data.snapshots.get(&etag).ok_or_else(|| {
+                GraphError::DoesNotExist(format!(
+                    "graph snapshot {} expired, pagination has to be restarted",
+                    etag
+                ))
+            })?;
+            (snapshot, offset)
+        }
+        None => {
+            let deadline = graph::request_deadline(req, app_data)?;
+            let rendered = graph::render_query(
+                &page_query.graph_query,
+                latest_content_type(),
+                app_data,
+                deadline,
+            )
+            .await?;
+            let snapshot = Arc::new(Snapshot::try_new(&rendered)?);
+            app_data.snapshots.insert(snapshot.clone());
+            (snapshot, 0)
+        }
+    };
+
+    let items = snapshot.items(collection);
+    let start = offset.min(items.len());
+    let end = (start + page_query.limit).min(items.len());
+    let mut page = serde_json::Map::new();
+    page.insert(
+        collection.name().to_string(),
+        items[start..end]
+            .iter()
+            .map(|item| select_fields(item, page_query.fields.as_deref()))
+            .collect(),
+    );
+    if end < items.len() {
+        let next = Cursor {
+            collection,
+            etag: snapshot.etag.clone(),
+            offset: end,
+        };
+        page.insert("next".to_string(), next.encode().into());
+    }
+
+    let mut response = HttpResponse::Ok();
+    response.insert_header((header::ETAG, snapshot.etag.as_str()));
+    commons::insert_stale_headers(&mut response, snapshot.stale_since.as_deref());
+    let mut response = response.json(page);
+    app_data
+        .cache_control
+        .insert_headers(&app_data.cache_control.graph, response.headers_mut());
+    Ok(response)
+}
+
+/// Keep the selected `fields` of a node, or all of them if none are selected.
+fn select_fields(node: &serde_json::Value, fields: Option<&[String]>) -> serde_json::Value {
+    match (node, fields) {
+        (serde_json::Value::Object(node), Some(fields)) => serde_json::Value::Object(
+            node.iter()
+                .filter(|(key, _)| fields.contains(key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        ),
+        (node, _) => node.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::tests::common_init;
+    use actix_web::body::MessageBody;
+    use cincinnati::plugins::prelude::*;
+
+    fn page(resp: HttpResponse) -> Result<serde_json::Value, Error> {
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        let body = resp.into_body().try_into_bytes().unwrap();
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    #[test]
+    fn cursor_roundtrip() -> Result<(), Error> {
+        let cursor = Cursor {
+            collection: Collection::Nodes,
+            etag: "\"0123abcd\"".to_string(),
+            offset: 42,
+        };
+        assert_eq!(Cursor::decode(&cursor.encode(), Collection::Nodes)?, cursor);
+
+        assert!(Cursor::decode(&cursor.encode(), Collection::Edges).is_err());
+        assert!(Cursor::decode("not a cursor", Collection::Nodes).is_err());
+        let encoded = base64::encode_config("nodes:0123abcd", base64::URL_SAFE_NO_PAD);
+        assert!(Cursor::decode(&encoded, Collection::Nodes).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_page_query() -> Result<(), Error> {
+        let page = PageQuery::parse(
+            "channel=stable-4.14&limit=10&fields=version",
+            Collection::Nodes,
+        )?;
+        assert_eq!(page.limit, 10);
+        assert_eq!(page.fields, Some(vec!["version".to_string()]));
+        assert_eq!(page.graph_query, "channel=stable-4.14");
+
+        for invalid in &["limit=0", "limit=100000", "limit=x", "fields=version,size"] {
+            assert!(
+                PageQuery::parse(invalid, Collection::Nodes).is_err(),
+                "{}",
+                invalid
+            );
+        }
+        assert!(PageQuery::parse("fields=version", Collection::Edges).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_graph_pages() -> Result<(), Error> {
+        let rt = common_init();
+
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                (
+                    "upstream",
+                    &format!("{}/graph-pages", mockito::server_url())
+                )
+            )?],
+            None,
+        )?;
+        let state = actix_web::web::Data::new(AppState {
+            plugins: Box::leak(Box::new(plugins)),
+            ..Default::default()
+        });
+
+        let _m = mockito::mock("GET", "/graph-pages")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"nodes":[
+                    {"version":"4.1.0","payload":"image/4.1.0","metadata":{}},
+                    {"version":"4.1.1","payload":"image/4.1.1","metadata":{}},
+                    {"version":"4.1.2","payload":"image/4.1.2","metadata":{}}
+                ],"edges":[[0,1],[1,2],[0,2]]}"#,
+            )
+            .create();
+
+        let get = |uri: &str| {
+            actix_web::test::TestRequest::get()
+                .uri(uri)
+                .to_http_request()
+        };
+
+        let resp = rt.block_on(nodes(
+            get("/v2/graph/nodes?limit=2&fields=version"),
+            state.clone(),
+        ))?;
+        let etag = resp.headers().get(header::ETAG).cloned().unwrap();
+        let first = page(resp)?;
+        assert_eq!(
+            first["nodes"],
+            serde_json::json!([{"version": "4.1.0"}, {"version": "4.1.1"}])
+        );
+
+        let next = first["next"].as_str().unwrap();
+        let resp = rt.block_on(nodes(
+            get(&format!(
+                "/v2/graph/nodes?limit=2&fields=version&cursor={}",
+                next
+            )),
+            state.clone(),
+        ))?;
+        assert_eq!(resp.headers().get(header::ETAG), Some(&etag));
+        let second = page(resp)?;
+        assert_eq!(second, serde_json::json!({"nodes": [{"version": "4.1.2"}]}));
+
+        // The edges of the same snapshot are addressed by its ETag.
+        let req = actix_web::test::TestRequest::get()
+            .uri("/v2/graph/edges")
+            .insert_header((header::IF_MATCH, etag))
+            .to_http_request();
+        let resp = rt.block_on(edges(req, state.clone()))?;
+        assert_eq!(
+            page(resp)?,
+            serde_json::json!({"edges": [[0, 1], [1, 2], [0, 2]]})
+        );
+
+        // Cursors of evicted snapshots cannot be followed.
+        let expired = Cursor {
+            collection: Collection::Edges,
+            etag: "\"0123abcd\"".to_string(),
+            offset: 0,
+        };
+        let resp = rt.block_on(edges(
+            get(&format!("/v2/graph/edges?cursor={}", expired.encode())),
+            state,
+        ));
+        assert_eq!(
+            resp.unwrap_err().status_code(),
+            actix_web::http::StatusCode::NOT_FOUND
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,411 @@
+//! GraphQL query endpoint for the update graph.
+//!
+//! The graph is rendered by the plugin chain exactly as for the `/graph`
+//! endpoint; filtering and field selection then happen server-side, so that
+//! clients only receive the slice of the graph they asked for.
+
+use crate::graph::{self, RenderWorker};
+use crate::AppState;
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, InputObject, Object, Schema, SimpleObject,
+};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+use commons::grpc::{self, proto};
+use commons::GraphError;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Metadata key listing the channels of a release, per the product profile.
+pub(crate) struct ChannelsKey(pub(crate) String);
+
+/// Schema served by the GraphQL endpoint.
+pub(crate) type GraphSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Build the GraphQL schema, rendering graphs with the given state and worker.
+///
+/// Release channels are read from the metadata under `channels_key`. Queries
+/// nested deeper than `max_depth` are rejected.
+pub(crate) fn build_schema(
+    state: AppState,
+    worker: RenderWorker,
+    channels_key: String,
+    max_depth: usize,
+) -> GraphSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(state)
+        .data(worker)
+        .data(ChannelsKey(channels_key))
+        .limit_depth(max_depth)
+        .finish()
+}
+
+/// Serve GraphQL queries.
+pub(crate) async fn index(
+    schema: actix_web::web::Data<GraphSchema>,
+    req: GraphQLRequest,
+) -> GraphQLResponse {
+    schema.execute(req.into_inner()).await.into()
+}
+
+fn graphql_error(e: GraphError) -> async_graphql::Error {
+    let kind = e.kind();
+    async_graphql::Error::new(e.value()).extend_with(|_, ext| ext.set("kind", kind))
+}
+
+/// A client parameter, equivalent to a query parameter of `/graph`.
+#[derive(InputObject)]
+struct Parameter {
+    key: String,
+    value: String,
+}
+
+/// A filter on release metadata; matches any value if `value` is unset.
+#[derive(InputObject)]
+struct MetadataFilter {
+    key: String,
+    value: Option<String>,
+}
+
+/// Root of all queries.
+pub(crate) struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The update graph for the given client parameters.
+    async fn graph(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] parameters: Vec<Parameter>,
+    ) -> async_graphql::Result<Graph> {
+        let state = ctx.data::<AppState>()?;
+        let worker = ctx.data::<RenderWorker>()?;
+
+        let parameters: HashMap<String, String> =
+            parameters.into_iter().map(|p| (p.key, p.value)).collect();
+        let rendered = worker
+            .render(
+                grpc::parameters_query(&parameters),
+                graph::latest_content_type(),
+                state.clone(),
+            )
+            .await
+            .map_err(graphql_error)?;
+        let graph = grpc::graph_from_json(&rendered.graph_json).map_err(graphql_error)?;
+
+        Ok(Graph(graph))
+    }
+}
+
+/// An update graph.
+struct Graph(proto::Graph);
+
+#[Object]
+impl Graph {
+    /// Version of the graph format.
+    async fn version(&self) -> u64 {
+        self.0.version
+    }
+
+    /// Releases in the graph, optionally filtered.
+    async fn nodes(
+        &self,
+        ctx: &Context<'_>,
+        version: Option<String>,
+        version_prefix: Option<String>,
+        channel: Option<String>,
+        #[graphql(default)] metadata: Vec<MetadataFilter>,
+        limit: Option<usize>,
+    ) -> async_graphql::Result<Vec<Node>> {
+        let channels_key = channels_key(ctx)?;
+        Ok(self
+            .0
+            .nodes
+            .iter()
+            .filter(|n| version.as_ref().map_or(true, |v| &n.version == v))
+            .filter(|n| {
+                version_prefix
+                    .as_ref()
+                    .map_or(true, |p| n.version.starts_with(p.as_str()))
+            })
+            .filter(|n| {
+                channel.as_ref().map_or(true, |c| {
+                    release_channels(n, channels_key).contains(c.as_str())
+                })
+            })
+            .filter(|n| {
+                metadata
+                    .iter()
+                    .all(|f| match (n.metadata.get(&f.key), &f.value) {
+                        (Some(actual), Some(expected)) => actual == expected,
+                        (Some(_), None) => true,
+                        (None, _) => false,
+                    })
+            })
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .map(Node)
+            .collect())
+    }
+
+    /// Unconditional update edges, optionally filtered by source or target version.
+    async fn edges(&self, from: Option<String>, to: Option<String>) -> Vec<Edge> {
+        self.0
+            .edges
+            .iter()
+            .filter_map(|e| {
+                let source = self.0.nodes.get(e.from as usize)?;
+                let target = self.0.nodes.get(e.to as usize)?;
+                Some(Edge {
+                    from: source.version.clone(),
+                    to: target.version.clone(),
+                })
+            })
+            .filter(|e| from.as_ref().map_or(true, |v| &e.from == v))
+            .filter(|e| to.as_ref().map_or(true, |v| &e.to == v))
+            .collect()
+    }
+
+    /// Conditional update edges, optionally filtered by source or target version.
+    async fn conditional_edges(
+        &self,
+        from: Option<String>,
+        to: Option<String>,
+    ) -> Vec<ConditionalEdge> {
+        self.0
+            .conditional_edges
+            .iter()
+            .filter_map(|ce| {
+                let edges: Vec<Edge> = ce
+                    .edges
+                    .iter()
+                    .filter(|e| from.as_ref().map_or(true, |v| &e.from == v))
+                    .filter(|e| to.as_ref().map_or(true, |v| &e.to == v))
+                    .map(|e| Edge {
+                        from: e.from.clone(),
+                        to: e.to.clone(),
+                    })
+                    .collect();
+                if edges.is_empty() {
+                    return None;
+                }
+                Some(ConditionalEdge {
+                    edges,
+                    risks: ce.risks.iter().map(Risk::from).collect(),
+                })
+            })
+            .collect()
+    }
+
+    /// Names of all channels releases belong to.
+    async fn channels(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<String>> {
+        let channels_key = channels_key(ctx)?;
+        Ok(self
+            .0
+            .nodes
+            .iter()
+            .flat_map(|n| {
+                release_channels(n, channels_key)
+                    .into_iter()
+                    .map(String::from)
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect())
+    }
+
+    /// All known risks, de-duplicated by name.
+    async fn risks(&self) -> Vec<Risk> {
+        self.0
+            .conditional_edges
+            .iter()
+            .flat_map(|ce| ce.risks.iter())
+            .map(|r| (r.name.clone(), Risk::from(r)))
+            .collect::<BTreeMap<_, _>>()
+            .into_values()
+            .collect()
+    }
+}
+
+fn channels_key<'a>(ctx: &Context<'a>) -> async_graphql::Result<&'a str> {
+    Ok(ctx.data::<ChannelsKey>()?.0.as_str())
+}
+
+fn release_channels<'a>(release: &'a proto::Release, channels_key: &str) -> BTreeSet<&'a str> {
+    release
+        .metadata
+        .get(channels_key)
+        .map(|channels| {
+            channels
+                .split(',')
+                .map(str::trim)
+                .filter(|c| !c.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A release.
+struct Node(proto::Release);
+
+#[Object]
+impl Node {
+    /// Version of the release.
+    async fn version(&self) -> &str {
+        &self.0.version
+    }
+
+    /// Pullspec of the release payload.
+    async fn payload(&self) -> &str {
+        &self.0.payload
+    }
+
+    /// Release metadata, optionally restricted to the given keys.
+    async fn metadata(&self, keys: Option<Vec<String>>) -> Vec<MetadataEntry> {
+        let mut entries: Vec<MetadataEntry> = self
+            .0
+            .metadata
+            .iter()
+            .filter(|(k, _)| keys.as_ref().map_or(true, |keys| keys.contains(k)))
+            .map(|(key, value)| MetadataEntry {
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    /// Channels the release belongs to.
+    async fn channels(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<String>> {
+        Ok(release_channels(&self.0, channels_key(ctx)?)
+            .into_iter()
+            .map(String::from)
+            .collect())
+    }
+}
+
+/// A release metadata entry.
+#[derive(SimpleObject, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct MetadataEntry {
+    key: String,
+    value: String,
+}
+
+/// An update edge, by release versions.
+#[derive(SimpleObject)]
+struct Edge {
+    from: String,
+    to: String,
+}
+
+/// Updates which are only recommended under certain conditions.
+#[derive(SimpleObject)]
+struct ConditionalEdge {
+    edges: Vec<Edge>,
+    risks: Vec<Risk>,
+}
+
+/// A risk affecting conditional updates.
+#[derive(SimpleObject)]
+struct Risk {
+    url: String,
+    name: String,
+    message: String,
+    matching_rules: Vec<ClusterCondition>,
+}
+
+impl From<&proto::ConditionalUpdateRisk> for Risk {
+    fn from(risk: &proto::ConditionalUpdateRisk) -> Self {
+        Risk {
+            url: risk.url.clone(),
+            name: risk.name.clone(),
+            message: risk.message.clone(),
+            matching_rules: risk
+                .matching_rules
+                .iter()
+                .map(|c| ClusterCondition {
+                    condition_type: c.r#type.clone(),
+                    promql: c.promql.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A condition identifying the clusters exposed to a risk.
+#[derive(SimpleObject)]
+struct ClusterCondition {
+    #[graphql(name = "type")]
+    condition_type: String,
+    promql: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::value;
+
+    static OKD_PREFIX: &str = "io.okd.upgrades.graph";
+
+    fn test_graph() -> Graph {
+        let release = |version: &str, channels: &str| proto::Release {
+            version: version.to_string(),
+            payload: format!("image:{}", version),
+            metadata: vec![(cincinnati::channels_key(OKD_PREFIX), channels.to_string())]
+                .into_iter()
+                .collect(),
+        };
+
+        Graph(proto::Graph {
+            version: 1,
+            nodes: vec![
+                release("4.1.0", "stable-4.1"),
+                release("4.1.1", "fast-4.1,stable-4.1"),
+                release("4.2.0", "fast-4.2"),
+            ],
+            edges: vec![
+                proto::Edge { from: 0, to: 1 },
+                proto::Edge { from: 1, to: 2 },
+            ],
+            conditional_edges: vec![],
+        })
+    }
+
+    struct TestRoot;
+
+    #[Object]
+    impl TestRoot {
+        async fn graph(&self) -> Graph {
+            test_graph()
+        }
+    }
+
+    #[test]
+    fn filter_and_select() {
+        let schema = Schema::build(TestRoot, EmptyMutation, EmptySubscription)
+            .data(ChannelsKey(cincinnati::channels_key(OKD_PREFIX)))
+            .finish();
+        let rt = crate::graph::tests::common_init();
+
+        let response = rt.block_on(schema.execute(
+            r#"{
+                graph {
+                    channels
+                    nodes(channel: "stable-4.1", versionPrefix: "4.1.1") { version }
+                    edges(from: "4.1.1") { from to }
+                }
+            }"#,
+        ));
+
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data,
+            value!({
+                "graph": {
+                    "channels": ["fast-4.1", "fast-4.2", "stable-4.1"],
+                    "nodes": [{"version": "4.1.1"}],
+                    "edges": [{"from": "4.1.1", "to": "4.2.0"}],
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,67 @@
+//! gRPC graph service.
+
+use crate::graph;
+use crate::AppState;
+use commons::grpc::{self, proto};
+use commons::GraphError;
+use std::collections::HashMap;
+use tonic::{Request, Response, Status};
+
+/// Graph service backed by the policy-engine plugin chain.
+#[derive(Clone, Debug)]
+pub(crate) struct GraphService {
+    state: AppState,
+    worker: graph::RenderWorker,
+}
+
+impl GraphService {
+    pub(crate) fn new(state: AppState, worker: graph::RenderWorker) -> Self {
+        Self { state, worker }
+    }
+
+    /// Run the plugin chain for the given client parameters.
+    async fn render(
+        &self,
+        parameters: &HashMap<String, String>,
+    ) -> Result<proto::Graph, GraphError> {
+        // gRPC clients always get the most recent graph format.
+        let content_type = graph::latest_content_type();
+        let query = grpc::parameters_query(parameters);
+        let rendered = self
+            .worker
+            .render(query, content_type, self.state.clone())
+            .await?;
+        grpc::graph_from_json(&rendered.graph_json)
+    }
+}
+
+#[tonic::async_trait]
+impl proto::graph_service_server::GraphService for GraphService {
+    async fn get_graph(
+        &self,
+        request: Request<proto::GetGraphRequest>,
+    ) -> Result<Response<proto::Graph>, Status> {
+        let graph = self.render(&request.get_ref().parameters).await?;
+        Ok(Response::new(graph))
+    }
+
+    type StreamGraphStream = grpc::GraphElementStream;
+
+    async fn stream_graph(
+        &self,
+        request: Request<proto::GetGraphRequest>,
+    ) -> Result<Response<Self::StreamGraphStream>, Status> {
+        let graph = self.render(&request.get_ref().parameters).await?;
+        Ok(Response::new(grpc::stream_graph_elements(graph)))
+    }
+
+    async fn get_next_updates(
+        &self,
+        request: Request<proto::GetNextUpdatesRequest>,
+    ) -> Result<Response<proto::NextUpdates>, Status> {
+        let request = request.get_ref();
+        let graph = self.render(&request.parameters).await?;
+        let updates = grpc::next_updates(graph, &request.version)?;
+        Ok(Response::new(updates))
+    }
+}
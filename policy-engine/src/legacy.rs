@@ -0,0 +1,198 @@
+//! Compatibility with the query conventions of earlier cluster-version operators.
+//!
+//! Earlier releases of the cluster-version operator spell some query
+//! parameters differently, or send them as request headers. Graph requests are
+//! rewritten according to the configured mapping before being validated and
+//! rendered, so that they are served exactly as if the modern parameters had
+//! been sent. Modern parameters sent along with their legacy spelling take
+//! precedence over it.
+
+use actix_web::http::header::{HeaderMap, HeaderName};
+use commons::prelude_errors::*;
+use prometheus::{IntCounterVec, Opts, Registry};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+
+lazy_static! {
+    static ref LEGACY_PARAMS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "graph_legacy_params_total",
+            "Total number of legacy query parameters and headers mapped to modern parameters, by legacy name"
+        ),
+        &["legacy"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(LEGACY_PARAMS.clone()))?;
+    Ok(())
+}
+
+/// Mapping of legacy query parameters and headers to modern query parameters.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LegacyParams {
+    /// Modern parameters, by legacy parameter.
+    pub params: BTreeMap<String, String>,
+    /// Modern parameters, by request header.
+    pub headers: BTreeMap<String, String>,
+}
+
+impl LegacyParams {
+    /// Check that the mapping is unambiguous.
+    pub fn validate(&self) -> Fallible<()> {
+        let modern: HashSet<&String> = self.params.values().chain(self.headers.values()).collect();
+
+        for (legacy, param) in &self.params {
+            ensure!(
+                !legacy.is_empty() && !param.is_empty(),
+                "legacy parameter mappings must not have empty names"
+            );
+            ensure!(
+                !modern.contains(legacy),
+                "legacy parameter '{}' is also mapped to",
+                legacy
+            );
+        }
+        for (name, param) in &self.headers {
+            HeaderName::from_str(name).context(format!("invalid legacy header '{}'", name))?;
+            ensure!(
+                !param.is_empty(),
+                "legacy header mappings must not have empty names"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Whether no legacy parameter or header is mapped.
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty() && self.headers.is_empty()
+    }
+
+    /// Rewrite `query` with the modern spelling of its legacy parameters, and
+    /// the parameters carried by legacy `headers`.
+    pub(crate) fn rewrite<'a>(&self, query: &'a str, headers: &HeaderMap) -> Cow<'a, str> {
+        if self.is_empty() {
+            return Cow::Borrowed(query);
+        }
+
+        let pairs: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes())
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        let mut present: HashSet<String> = pairs
+            .iter()
+            .filter(|(key, _)| !self.params.contains_key(key))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut rewritten = Vec::with_capacity(pairs.len());
+        let mut changed = false;
+        for (key, value) in pairs {
+            let param = match self.params.get(&key) {
+                Some(param) => param,
+                None => {
+                    rewritten.push((key, value));
+                    continue;
+                }
+            };
+            changed = true;
+            LEGACY_PARAMS.with_label_values(&[&key]).inc();
+            if present.insert(param.clone()) {
+                rewritten.push((param.clone(), value));
+            } else {
+                debug!("ignoring legacy parameter '{}', '{}' is set", key, param);
+            }
+        }
+
+        for (name, param) in &self.headers {
+            let value = match headers.get(name).and_then(|value| value.to_str().ok()) {
+                Some(value) => value,
+                None => continue,
+            };
+            LEGACY_PARAMS.with_label_values(&[name]).inc();
+            if present.insert(param.clone()) {
+                changed = true;
+                rewritten.push((param.clone(), value.to_string()));
+            } else {
+                debug!("ignoring legacy header '{}', '{}' is set", name, param);
+            }
+        }
+
+        if !changed {
+            return Cow::Borrowed(query);
+        }
+        Cow::Owned(
+            url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(&rewritten)
+                .finish(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::HeaderValue;
+
+    fn legacy() -> LegacyParams {
+        LegacyParams {
+            params: vec![("channel_name", "channel"), ("cluster_id", "id")]
+                .into_iter()
+                .map(|(legacy, param)| (legacy.to_string(), param.to_string()))
+                .collect(),
+            headers: vec![("x-cluster-arch".to_string(), "arch".to_string())]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn rewrite_legacy_params() {
+        let legacy = legacy();
+        let mut headers = HeaderMap::new();
+
+        assert!(matches!(
+            legacy.rewrite("channel=stable-4.14&id=1", &headers),
+            Cow::Borrowed("channel=stable-4.14&id=1")
+        ));
+        assert_eq!(
+            legacy.rewrite("channel_name=stable-4.14&cluster_id=1", &headers),
+            "channel=stable-4.14&id=1"
+        );
+        // Modern parameters take precedence.
+        assert_eq!(
+            legacy.rewrite("channel_name=fast-4.14&channel=stable-4.14", &headers),
+            "channel=stable-4.14"
+        );
+
+        headers.insert("x-cluster-arch", HeaderValue::from_static("arm64"));
+        assert_eq!(
+            legacy.rewrite("channel=stable-4.14", &headers),
+            "channel=stable-4.14&arch=arm64"
+        );
+        assert!(matches!(
+            legacy.rewrite("arch=amd64", &headers),
+            Cow::Borrowed("arch=amd64")
+        ));
+    }
+
+    #[test]
+    fn validate_mappings() {
+        assert!(legacy().validate().is_ok());
+
+        let mut chained = legacy();
+        chained
+            .params
+            .insert("channel_id".to_string(), "channel_name".to_string());
+        assert!(chained.validate().is_err());
+
+        let mut invalid = legacy();
+        invalid
+            .headers
+            .insert("x cluster".to_string(), "id".to_string());
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,633 @@
+//! Cincinnati backend: policy-engine server.
+//!
+//! The `policy-engine` binary assembles its settings and calls `run`, which
+//! can also be called from tests to serve a policy-engine in-process.
+
+#![deny(missing_docs)]
+
+#[macro_use]
+extern crate cincinnati;
+#[macro_use]
+extern crate commons;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate smart_default;
+#[macro_use]
+extern crate structopt;
+#[macro_use]
+extern crate custom_debug_derive;
+
+mod analytics;
+mod apply;
+mod audit;
+mod cache_control;
+mod capabilities;
+mod channel_groups;
+mod coalesce;
+mod cohorts;
+mod config;
+mod events;
+mod features;
+mod graph;
+mod graph_pages;
+mod graphql;
+mod grpc;
+mod legacy;
+mod openapi;
+mod pins;
+mod profile;
+mod shadow;
+mod signing;
+mod standalone;
+mod status;
+mod variants;
+mod warmup;
+
+pub use crate::config::AppSettings;
+
+use actix_cors::Cors;
+use actix_service::Service;
+use actix_web::http::StatusCode;
+use actix_web::{http, middleware, App, HttpRequest, HttpResponse, HttpServer};
+use cincinnati::plugins::BoxedPlugin;
+use commons::prelude_errors::*;
+use commons::tracing::{get_tracer, init_tracer, set_span_tags};
+use commons::{
+    format_request,
+    metrics::{self, HasRegistry},
+};
+use futures::future;
+use opentelemetry::{
+    trace::{mark_span_as_active, FutureExt, Tracer},
+    Context as ot_context,
+};
+use parking_lot::RwLock;
+use prometheus::{labels, opts, Counter, Registry};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[allow(dead_code)]
+/// Build info
+mod built_info {
+    include!(concat!(env!("OUT_DIR"), "/built.rs"));
+}
+
+/// Common prefix for policy-engine metrics.
+pub static METRICS_PREFIX: &str = "cincinnati_pe";
+
+lazy_static! {
+    static ref BUILD_INFO: Counter = Counter::with_opts(opts!(
+        "build_info",
+        "Build information",
+        labels! {
+            "git_commit" => built_info::GIT_COMMIT_HASH.unwrap_or("unknown"),
+        }
+    ))
+    .unwrap();
+}
+
+/// Serve the policy-engine with `settings`, until its servers stop.
+pub async fn run(settings: AppSettings) -> Result<(), Error> {
+    // Metrics service.
+    let registry: &'static Registry = Box::leak(Box::new(metrics::new_labeled_registry(
+        Some(METRICS_PREFIX.to_string()),
+        &settings.metrics_labels,
+    )?));
+    registry.register(Box::new(BUILD_INFO.clone()))?;
+
+    // Main service.
+    let plugins = settings.validate_and_build_plugins(Some(registry))?;
+
+    // Offline apply mode.
+    if settings.apply_graph_path.is_some() {
+        let plugins: &'static [BoxedPlugin] = Box::leak(plugins.into_boxed_slice());
+        return apply::run(&settings, plugins).await;
+    }
+
+    // Optional query analytics.
+    let analytics = if settings.analytics_enabled {
+        let analytics = analytics::QueryAnalytics::new(settings.analytics_max_series);
+        analytics.spawn_summary(settings.analytics_summary_interval)?;
+        Some(analytics)
+    } else {
+        None
+    };
+
+    // Rendered graph variants, by content address of the chain which rendered them.
+    let variants = variants::VariantStore::new(settings.variants_max_bytes);
+
+    // Optional cohorts, served their own plugin chain.
+    let cohorts = {
+        let mut cohorts = Vec::with_capacity(settings.cohorts.len());
+        for cohort in &settings.cohorts {
+            let plugins: &'static [BoxedPlugin] =
+                Box::leak(cohort.build_plugins()?.into_boxed_slice());
+            variants.register_chain(plugins, cohort.plugin_chain_digest());
+            cohorts.push((cohort.name.clone(), cohort.percent, plugins));
+        }
+        cohorts::Cohorts::new(settings.cohort_salt.clone(), cohorts)
+    };
+
+    // Optional embedded graph-builder, replacing the upstream in standalone mode.
+    let pipeline = settings
+        .standalone_graph_builder_config_path
+        .as_deref()
+        .map(standalone::Pipeline::spawn)
+        .transpose()?;
+
+    // Optional pins, serving fixed graphs to the listed clients.
+    let pins = pins::Pins::try_new(
+        &settings.pins,
+        settings.upstream_snapshots_url.as_deref(),
+        Some(settings.upstream_max_response_bytes.unwrap_or(
+            cincinnati::plugins::internal::cincinnati_graph_fetch::DEFAULT_MAX_RESPONSE_BYTES,
+        )),
+    )?;
+
+    // Feature flags, changeable through the status service.
+    let feature_flags =
+        commons::features::FeatureFlags::try_new(features::FEATURES, &settings.features)?;
+    let admin_token = settings
+        .status_admin_token_path
+        .as_deref()
+        .map(commons::auth::BearerToken::read)
+        .transpose()?;
+    let debug_token = settings
+        .status_debug_token_path
+        .as_deref()
+        .map(profile::DebugToken::read)
+        .transpose()?;
+
+    // Optional graph signing.
+    let signer = match &settings.signing_key_path {
+        Some(path) => Some(Arc::new(signing::GraphSigner::from_file(
+            path,
+            settings.signing_key_id.clone(),
+        )?)),
+        None => None,
+    };
+
+    // Optional audit log.
+    let audit = match settings.audit_sink()? {
+        Some(sink) => Some(audit::AuditLogger::try_new(sink, settings.audit_pii)?),
+        None => None,
+    };
+
+    // Optional request shadowing.
+    let shadow = match settings.shadow_target()? {
+        Some(url) => Some(shadow::Shadow::try_new(url, settings.shadow_percent)?),
+        None => None,
+    };
+
+    // Shared state.
+    let state = {
+        let mandatory_params = settings.mandatory_client_parameters.clone();
+        let path_prefix = settings.path_prefix.clone();
+        let plugins: &'static [BoxedPlugin] = Box::leak(plugins.into_boxed_slice());
+        variants.register_chain(plugins, settings.plugin_chain_digest()?);
+        let live = Arc::new(RwLock::new(false));
+        let ready = Arc::new(RwLock::new(false));
+
+        AppState {
+            events_poll_interval: settings.events_poll_interval,
+            audit,
+            shadow,
+            analytics,
+            cache_control: settings.cache_control.clone(),
+            cohorts,
+            pins,
+            pipeline: pipeline.clone(),
+            variants,
+            request_timeout: settings.request_timeout,
+            features: feature_flags.clone(),
+            param_constraints: commons::params::ParamConstraints::try_new(
+                &settings.client_parameters,
+            )?,
+            default_params: settings.default_params.clone(),
+            legacy_params: settings.legacy_params.clone(),
+            ..AppState::new(
+                mandatory_params,
+                path_prefix,
+                plugins,
+                live,
+                ready,
+                registry,
+                signer,
+            )
+        }
+    };
+
+    graph::register_metrics(state.registry())?;
+    legacy::register_metrics(state.registry())?;
+    events::register_metrics(state.registry())?;
+    commons::limits::register_metrics(state.registry())?;
+    commons::aliases::register_metrics(state.registry())?;
+    commons::latency::register_metrics(state.registry())?;
+    commons::metrics::register_metrics(state.registry())?;
+    audit::register_metrics(state.registry())?;
+    shadow::register_metrics(state.registry())?;
+    analytics::register_metrics(state.registry())?;
+    coalesce::register_metrics(state.registry())?;
+    cohorts::register_metrics(state.registry())?;
+    pins::register_metrics(state.registry())?;
+    variants::register_metrics(state.registry())?;
+    warmup::register_metrics(state.registry())?;
+    let metric_state = state.clone();
+    let mut metrics_server = HttpServer::new(move || {
+        let app = App::new()
+            .wrap(middleware::Compress::default())
+            .app_data(actix_web::web::Data::new(metric_state.clone()))
+            .app_data(actix_web::web::Data::new(feature_flags.clone()))
+            .service(
+                actix_web::web::resource("/metrics")
+                    .route(actix_web::web::get().to(metrics::serve::<AppState>)),
+            )
+            .service(
+                actix_web::web::resource("/livez")
+                    .route(actix_web::web::get().to(status::serve_liveness)),
+            )
+            .service(
+                actix_web::web::resource("/readyz")
+                    .route(actix_web::web::get().to(status::serve_readiness)),
+            )
+            .service(
+                actix_web::web::resource("/version")
+                    .route(actix_web::web::get().to(status::serve_version)),
+            )
+            .service(
+                actix_web::web::resource("/features")
+                    .route(actix_web::web::get().to(commons::features::serve_features)),
+            );
+        let app = match &pipeline {
+            Some(pipeline) => app.service(
+                actix_web::web::resource("/graph-builder/metrics")
+                    .app_data(actix_web::web::Data::new(pipeline.state().clone()))
+                    .route(actix_web::web::get().to(graph_builder::status::serve_metrics)),
+            ),
+            None => app,
+        };
+        let app = match &debug_token {
+            Some(token) => app.service(
+                actix_web::web::resource("/debug/profile")
+                    .app_data(actix_web::web::Data::new(token.clone()))
+                    .route(actix_web::web::get().to(profile::serve_profile)),
+            ),
+            None => app,
+        };
+        match &admin_token {
+            Some(token) => app.service(
+                actix_web::web::resource("/features/{name}")
+                    .app_data(actix_web::web::Data::new(token.clone()))
+                    .route(actix_web::web::put().to(commons::features::update_feature)),
+            ),
+            None => app,
+        }
+    });
+    let status_addrs = settings.status_listen_addrs();
+    for listener in commons::listen::bind_all(&status_addrs, settings.backlog)? {
+        metrics_server = metrics_server.listen(listener)?;
+    }
+    let metrics_server = metrics_server.run();
+
+    // Enable tracing
+    init_tracer("policy-engine", settings.tracing_endpoint.clone())?;
+    let render_worker = graph::RenderWorker::new();
+    let graphql_schema = graphql::build_schema(
+        state.clone(),
+        render_worker.clone(),
+        settings.profile.channels_key(),
+        settings.graphql_max_depth,
+    );
+    let main_state = state.clone();
+    let request_limits = settings.request_limits;
+    let concurrency_limiter = settings.concurrency_limits.limiter(&settings.path_prefix);
+    let path_aliases = settings.path_aliases.clone();
+    let status_listener = commons::StatusListener {
+        address: status_addrs[0].ip(),
+        port: status_addrs[0].port(),
+    };
+    let latency_recorder = commons::latency::LatencyRecorder {
+        slow_threshold: settings.slow_request_threshold,
+    };
+    let mut main_server = HttpServer::new(move || {
+        let app_prefix = main_state.path_prefix.clone();
+        let concurrency_limiter = concurrency_limiter.clone();
+        let path_aliases = path_aliases.clone();
+        App::new()
+            .wrap_fn(move |req, srv| concurrency_limiter.enforce(req, srv))
+            .wrap_fn(move |req, srv| path_aliases.enforce(req, srv))
+            .wrap_fn(move |req, srv| request_limits.enforce(req, srv))
+            .wrap_fn(move |req, srv| latency_recorder.observe(req, srv))
+            .wrap_fn(|req, srv| {
+                let mut span = get_tracer().start("request");
+                set_span_tags(req.path(), req.headers(), &mut span);
+                let _active_span = mark_span_as_active(span);
+                let cx = ot_context::current();
+                srv.call(req).with_context(cx)
+            })
+            .wrap(
+                Cors::default()
+                    .allow_any_origin()
+                    .allowed_methods(vec!["HEAD", "GET", "POST"])
+                    .allowed_header(http::header::CONTENT_TYPE)
+                    .allowed_header(graph::REQUEST_TIMEOUT_HEADER),
+            )
+            .app_data(actix_web::web::Data::<AppState>::new(main_state.clone()))
+            .app_data(actix_web::web::Data::new(graphql_schema.clone()))
+            .app_data(actix_web::web::Data::new(status_listener))
+            .service(
+                // keeping this for backward compatibility
+                actix_web::web::resource(&format!("{}/v1/graph", app_prefix))
+                    .route(actix_web::web::get().to(graph::index)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/graph", app_prefix))
+                    .route(actix_web::web::get().to(graph::index)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/v1/graph.sig", app_prefix))
+                    .route(actix_web::web::get().to(graph::signature)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/graph.sig", app_prefix))
+                    .route(actix_web::web::get().to(graph::signature)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/v1/graph/schema", app_prefix))
+                    .route(actix_web::web::get().to(openapi::graph_schema)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/v1/channel-groups", app_prefix))
+                    .route(actix_web::web::get().to(channel_groups::index)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/v1/graph/events", app_prefix))
+                    .route(actix_web::web::get().to(events::index)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/v2/graph/nodes", app_prefix))
+                    .route(actix_web::web::get().to(graph_pages::nodes)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/v2/graph/edges", app_prefix))
+                    .route(actix_web::web::get().to(graph_pages::edges)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/v1/graphql", app_prefix))
+                    .route(actix_web::web::get().to(graphql::index))
+                    .route(actix_web::web::post().to(graphql::index)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/openapi", app_prefix))
+                    .route(actix_web::web::get().to(openapi::index)),
+            )
+            .service(
+                actix_web::web::resource(&format!("{}/v1/openapi", app_prefix))
+                    .route(actix_web::web::get().to(openapi::index)),
+            )
+            .default_service(actix_web::web::route().to(default_response))
+    })
+    .backlog(settings.backlog)
+    .max_connections(settings.max_connections)
+    .max_connection_rate(settings.max_connection_rate)
+    .keep_alive(settings.keep_alive)
+    .client_request_timeout(settings.client_timeout);
+    if let Some(workers) = settings.workers {
+        main_server = main_server.workers(workers);
+    }
+    if let Some(threads) = settings.worker_max_blocking_threads {
+        main_server = main_server.worker_max_blocking_threads(threads);
+    }
+    let service_addrs = settings.listen_addrs();
+    for listener in commons::listen::bind_all(&service_addrs, settings.backlog)? {
+        main_server = main_server.listen(listener)?;
+    }
+    let main_server = main_server.run();
+
+    // Optional gRPC service.
+    if let Some(grpc_port) = settings.grpc_port {
+        // The gRPC service listens on the first address of the main service.
+        let grpc_addr = std::net::SocketAddr::new(service_addrs[0].ip(), grpc_port);
+        let grpc_service = grpc::GraphService::new(state.clone(), render_worker);
+        actix_web::rt::spawn(async move {
+            if let Err(e) = commons::grpc::serve(grpc_addr, grpc_service).await {
+                error!("gRPC service failed: {:?}", e);
+            }
+        });
+    }
+
+    // metrics endpoints has started running
+    *state.live.write() = true;
+
+    let http_req = actix_web::test::TestRequest::get()
+        .uri(&format!(
+            "{}?channel=stable-4.10",
+            "http://ready.probe/graph"
+        ))
+        .insert_header((
+            http::header::ACCEPT,
+            http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+        ))
+        .to_http_request();
+
+    info!("waiting for the application to be ready");
+
+    // Readiness probes are not client requests, keep them out of the audit log and shadowing.
+    let probe_state = AppState {
+        audit: None,
+        shadow: None,
+        ..state.clone()
+    };
+
+    // wait for the application to be initialized and the cache refreshed.
+    while *state.ready.read() == false {
+        let resp = graph::index(
+            http_req.clone(),
+            actix_web::web::Data::<AppState>::new(probe_state.clone()),
+        )
+        .await;
+        let status =
+            resp.unwrap_or_else(|err| HttpResponse::InternalServerError().body(err.to_string()));
+        if status.status().is_success() {
+            // Render the popular variants first, so that the first clients hit warm caches.
+            warmup::run(&probe_state, &settings.warmup_variants).await;
+            info!("application is ready");
+            *state.ready.write() = true;
+        } else {
+            thread::sleep(Duration::new(10, 0));
+        }
+    }
+
+    BUILD_INFO.inc();
+    future::try_join(metrics_server, main_server).await?;
+    Ok(())
+}
+
+// log errors in case an incorrect endpoint is called
+async fn default_response(req: HttpRequest) -> HttpResponse {
+    error!(
+        "Error serving request '{}' from '{}': Incorrect Endpoint",
+        format_request(&req),
+        req.peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "<not available>".into())
+    );
+    HttpResponse::new(StatusCode::NOT_FOUND)
+}
+
+/// Shared application configuration (cloned per-thread).
+#[derive(Clone, Debug)]
+pub struct AppState {
+    /// Query parameters that must be present in all client requests.
+    mandatory_params: HashSet<String>,
+    /// Constraints on the values of the mandatory query parameters.
+    param_constraints: commons::params::ParamConstraints,
+    /// Default values of the query parameters omitted by clients.
+    default_params: BTreeMap<String, String>,
+    /// Mapping of the legacy query parameters and headers of earlier clients.
+    legacy_params: legacy::LegacyParams,
+    /// Upstream cincinnati service.
+    path_prefix: String,
+    /// Policy plugins.
+    plugins: &'static [BoxedPlugin],
+    live: Arc<RwLock<bool>>,
+    ready: Arc<RwLock<bool>>,
+    registry: &'static Registry,
+    /// Optional signer for graph responses.
+    signer: Option<Arc<signing::GraphSigner>>,
+    /// Interval between graph refreshes for events streams.
+    events_poll_interval: Duration,
+    /// Optional audit log of graph requests.
+    audit: Option<audit::AuditLogger>,
+    /// Optional mirror of graph requests to a secondary deployment.
+    shadow: Option<shadow::Shadow>,
+    /// Optional aggregated analytics of graph queries.
+    analytics: Option<analytics::QueryAnalytics>,
+    /// Caching policies of the endpoints.
+    cache_control: cache_control::CacheControl,
+    /// Cohorts served their own plugin chain.
+    cohorts: cohorts::Cohorts,
+    /// Clients served a fixed graph for a period.
+    pins: pins::Pins,
+    /// Graph-builder pipeline running in-process, in standalone mode.
+    pipeline: Option<standalone::Pipeline>,
+    /// Rendered graph variants, by content address.
+    variants: variants::VariantStore,
+    /// Optional time after which graph requests fail.
+    request_timeout: Option<Duration>,
+    /// Plugin chain runs in flight, shared by identical concurrent requests.
+    inflight_renders: graph::RenderCoalescer,
+    /// Graph change sources shared by events streams.
+    event_sources: events::EventSources,
+    /// Graph snapshots pinned for pagination.
+    snapshots: graph_pages::Snapshots,
+    /// Feature flags.
+    features: commons::features::FeatureFlags,
+}
+
+impl AppState {
+    /// Creates a new State with the given arguments
+    pub fn new(
+        mandatory_params: HashSet<String>,
+        path_prefix: String,
+        plugins: &'static [BoxedPlugin],
+        live: Arc<RwLock<bool>>,
+        ready: Arc<RwLock<bool>>,
+        registry: &'static Registry,
+        signer: Option<Arc<signing::GraphSigner>>,
+    ) -> AppState {
+        AppState {
+            mandatory_params,
+            param_constraints: Default::default(),
+            default_params: Default::default(),
+            legacy_params: Default::default(),
+            path_prefix,
+            plugins,
+            live,
+            ready,
+            registry,
+            signer,
+            events_poll_interval: config::DEFAULT_EVENTS_POLL_INTERVAL,
+            audit: None,
+            shadow: None,
+            analytics: None,
+            cache_control: Default::default(),
+            cohorts: Default::default(),
+            pins: Default::default(),
+            pipeline: None,
+            variants: Default::default(),
+            request_timeout: None,
+            inflight_renders: Default::default(),
+            event_sources: Default::default(),
+            snapshots: Default::default(),
+            features: commons::features::FeatureFlags::new(features::FEATURES),
+        }
+    }
+
+    /// Return the deadline of a request starting now, the configured timeout
+    /// being shortened to the `requested` one, if any.
+    pub(crate) fn deadline(&self, requested: Option<Duration>) -> Option<std::time::SystemTime> {
+        let timeout = match (self.request_timeout, requested) {
+            (Some(configured), Some(requested)) => Some(configured.min(requested)),
+            (configured, requested) => configured.or(requested),
+        }?;
+        Some(std::time::SystemTime::now() + timeout)
+    }
+
+    /// Returns the boolean inside self.live
+    pub fn is_live(&self) -> bool {
+        *self.live.read()
+    }
+
+    /// Returns the boolean inside self.ready
+    pub fn is_ready(&self) -> bool {
+        *self.ready.read()
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        let registry: &'static Registry = Box::leak(Box::new(
+            metrics::new_registry(Some(METRICS_PREFIX.to_string())).unwrap(),
+        ));
+        AppState {
+            mandatory_params: Default::default(),
+            param_constraints: Default::default(),
+            default_params: Default::default(),
+            legacy_params: Default::default(),
+            path_prefix: Default::default(),
+            plugins: Default::default(),
+            live: Default::default(),
+            ready: Default::default(),
+            registry,
+            signer: None,
+            events_poll_interval: config::DEFAULT_EVENTS_POLL_INTERVAL,
+            audit: None,
+            shadow: None,
+            analytics: None,
+            cache_control: Default::default(),
+            cohorts: Default::default(),
+            pins: Default::default(),
+            pipeline: None,
+            variants: Default::default(),
+            request_timeout: None,
+            inflight_renders: Default::default(),
+            event_sources: Default::default(),
+            snapshots: Default::default(),
+            features: commons::features::FeatureFlags::new(features::FEATURES),
+        }
+    }
+}
+
+impl HasRegistry for AppState {
+    fn registry(&self) -> &'static Registry {
+        self.registry
+    }
+}
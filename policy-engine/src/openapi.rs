@@ -1,13 +1,22 @@
 use crate::AppState;
-use actix_web::{dev::Response, HttpResponse};
+use actix_web::{dev::Response, HttpRequest, HttpResponse};
+use commons::params::ParamConstraints;
 use commons::prelude_errors::*;
+use commons::StatusListener;
 use openapiv3::{OpenAPI, ReferenceOr};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
 /// Template for policy-engine OpenAPIv3 document.
 const SPEC: &str = include_str!("openapiv3.json");
 
-pub(crate) async fn index(app_data: actix_web::web::Data<AppState>) -> HttpResponse {
+/// Template for the OpenAPIv3 document of the status service endpoints.
+const STATUS_SPEC: &str = include_str!("openapiv3-status.json");
+
+pub(crate) async fn index(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+    status: actix_web::web::Data<StatusListener>,
+) -> HttpResponse {
     let path_prefix = &app_data.path_prefix;
 
     let mut spec_object: OpenAPI =
@@ -19,23 +28,94 @@ pub(crate) async fn index(app_data: actix_web::web::Data<AppState>) -> HttpRespo
             }
         };
 
-    // Add mandatory parameters to the `graph` endpoint.
-    if let Some(path) = spec_object.paths.paths.get_mut("/graph") {
-        add_mandatory_params(path, &app_data.mandatory_params);
+    // Add mandatory parameters to the `graph` endpoints.
+    for graph_path in &["/graph", "/v2/graph/nodes", "/v2/graph/edges"] {
+        if let Some(path) = spec_object.paths.paths.get_mut(*graph_path) {
+            add_mandatory_params(
+                path,
+                &app_data.mandatory_params,
+                &app_data.param_constraints,
+                &app_data.default_params,
+            );
+        }
+    }
+
+    if let Err(e) = add_response_schemas(&mut spec_object) {
+        error!("{:?}", e);
+        return HttpResponse::InternalServerError().body(e.to_string());
     }
 
     // Prefix all paths with `path_prefix`
     spec_object.paths = rewrite_paths(spec_object.paths, path_prefix);
 
-    serde_json::to_string(&spec_object)
+    // Status endpoints are served on their own listener, without prefix.
+    if let Err(e) = add_status_paths(&mut spec_object, &status.base_url(&req)) {
+        error!("{:?}", e);
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+
+    match serde_json::to_string(&spec_object)
         .context("Could not serialize OpenAPI object")
         .map(Response::from)
         .map(Response::map_into_boxed_body)
         .map(HttpResponse::from)
-        .unwrap_or_else(|e| {
+    {
+        Ok(mut response) => {
+            app_data
+                .cache_control
+                .insert_headers(&app_data.cache_control.openapi, response.headers_mut());
+            response
+        }
+        Err(e) => {
             error!("{:?}", e);
             HttpResponse::InternalServerError().body(e.to_string())
-        })
+        }
+    }
+}
+
+/// Serve the JSON Schema of the graph document.
+pub(crate) async fn graph_schema(app_data: actix_web::web::Data<AppState>) -> HttpResponse {
+    let mut response = HttpResponse::Ok().json(cincinnati::schema::graph_json_schema());
+    app_data
+        .cache_control
+        .insert_headers(&app_data.cache_control.openapi, response.headers_mut());
+    response
+}
+
+/// Add the response schemas, generated from their Rust types, to the components.
+fn add_response_schemas(spec_object: &mut OpenAPI) -> Fallible<()> {
+    let components = spec_object.components.get_or_insert_with(Default::default);
+    for (name, schema) in cincinnati::schema::openapi_schemas()? {
+        let schema = serde_json::from_value(schema)
+            .with_context(|| format!("Could not convert schema {}", name))?;
+        components.schemas.insert(name, schema);
+    }
+    Ok(())
+}
+
+/// Add the endpoints of the status service, served at `status_url`, and their schemas.
+fn add_status_paths(spec_object: &mut OpenAPI, status_url: &str) -> Fallible<()> {
+    let status: OpenAPI = serde_json::from_str(STATUS_SPEC)
+        .context("Could not deserialize status endpoints to OpenAPI object")?;
+    let server: openapiv3::Server = serde_json::from_value(serde_json::json!({
+        "url": status_url,
+        "description": "Status service"
+    }))?;
+
+    for (path, mut path_item) in status.paths.paths {
+        if let ReferenceOr::Item(item) = &mut path_item {
+            item.servers = vec![server.clone()];
+        }
+        spec_object.paths.paths.insert(path, path_item);
+    }
+    if let Some(components) = status.components {
+        spec_object
+            .components
+            .get_or_insert_with(Default::default)
+            .schemas
+            .extend(components.schemas);
+    }
+    Ok(())
 }
 
 fn rewrite_paths(paths: openapiv3::Paths, path_prefix: &str) -> openapiv3::Paths {
@@ -51,8 +131,14 @@ fn rewrite_paths(paths: openapiv3::Paths, path_prefix: &str) -> openapiv3::Paths
     new_paths
 }
 
-// Add mandatory parameters to the `graph` endpoint.
-fn add_mandatory_params(path: &mut ReferenceOr<openapiv3::PathItem>, reqs: &HashSet<String>) {
+// Add mandatory parameters, with the values they accept, to a `graph` endpoint.
+// Parameters with a default value are optional.
+fn add_mandatory_params(
+    path: &mut ReferenceOr<openapiv3::PathItem>,
+    reqs: &HashSet<String>,
+    constraints: &ParamConstraints,
+    defaults: &BTreeMap<String, String>,
+) {
     // Template for building an `openapiv3::Parameter`, which otherwise has private fields.
     static PARAM_TEMPLATE: &str = r#"
 {
@@ -77,6 +163,22 @@ fn add_mandatory_params(path: &mut ReferenceOr<openapiv3::PathItem>, reqs: &Hash
                         ..
                     } => {
                         p.name = key.clone();
+                        let mut schema = constraints.schema(key);
+                        if let Some(default) = defaults.get(key) {
+                            p.required = false;
+                            schema["default"] = default.as_str().into();
+                        }
+                        match serde_json::from_value(schema) {
+                            Ok(schema) => {
+                                p.format = openapiv3::ParameterSchemaOrContent::Schema(
+                                    ReferenceOr::Item(schema),
+                                );
+                            }
+                            Err(e) => {
+                                error!("invalid schema for parameter {}: {}", key, e);
+                                continue;
+                            }
+                        }
                     }
                     _ => {
                         error!("non-query parameters not allowed");
@@ -131,7 +233,12 @@ mod tests {
 
         {
             let graph_path = spec.paths.paths.get_mut("/graph").unwrap();
-            add_mandatory_params(graph_path, &params);
+            add_mandatory_params(
+                graph_path,
+                &params,
+                &Default::default(),
+                &Default::default(),
+            );
         }
         let output = serde_json::to_string(&spec).unwrap();
 
@@ -145,6 +252,64 @@ mod tests {
         }
     }
 
+    #[test]
+    fn graph_params_constraints() -> Fallible<()> {
+        use super::{add_mandatory_params, SPEC};
+        use commons::params::{ParamConstraint, ParamFormat};
+        use openapiv3::OpenAPI;
+
+        let params: HashSet<String> = vec!["id".to_string(), "channel".to_string()]
+            .into_iter()
+            .collect();
+        let defaults: BTreeMap<String, String> =
+            vec![("channel".to_string(), "stable-4.19".to_string())]
+                .into_iter()
+                .collect();
+        let constraints = ParamConstraints::try_new(
+            &vec![(
+                "id".to_string(),
+                ParamConstraint {
+                    format: Some(ParamFormat::Uuid),
+                    ..Default::default()
+                },
+            )]
+            .into_iter()
+            .collect(),
+        )?;
+        let mut spec: OpenAPI = serde_json::from_str(SPEC)?;
+        add_mandatory_params(
+            spec.paths.paths.get_mut("/graph").unwrap(),
+            &params,
+            &constraints,
+            &defaults,
+        );
+
+        let spec = serde_json::to_value(&spec)?;
+        let graph_params = spec["paths"]["/graph"]["parameters"].as_array().unwrap();
+        let id = graph_params
+            .iter()
+            .find(|p| p["name"] == "id")
+            .expect("missing id parameter");
+        assert_eq!(id["required"], true);
+        assert_eq!(
+            id["schema"],
+            serde_json::json!({ "type": "string", "format": "uuid" })
+        );
+
+        // Parameters with a default value are optional.
+        let channel = graph_params
+            .iter()
+            .find(|p| p["name"] == "channel")
+            .expect("missing channel parameter");
+        assert_ne!(channel["required"], true);
+        assert_eq!(
+            channel["schema"],
+            serde_json::json!({ "type": "string", "default": "stable-4.19" })
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn graph_params_integration() -> Result<(), Box<dyn std::error::Error>> {
         let runtime = common_init();
@@ -166,7 +331,12 @@ mod tests {
         });
         let resource =
             actix_web::web::resource(service_uri).route(actix_web::web::get().to(super::index));
-        let app = actix_web::App::new().service(resource);
+        let app = actix_web::App::new()
+            .app_data(actix_web::web::Data::new(StatusListener {
+                address: std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+                port: 9081,
+            }))
+            .service(resource);
 
         // call the service and get the response body
         let body_future: Box<dyn Future<Output = Result<_, Box<dyn Error>>> + Unpin> =
@@ -223,6 +393,21 @@ mod tests {
 
         assert_eq!(mandatory_params, v1_graph_mandatory_params_result,);
 
+        let schemas = &spec.components.ok_or("missing components")?.schemas;
+        assert!(schemas.contains_key(cincinnati::schema::GRAPH_SCHEMA_NAME));
+        assert!(schemas.contains_key(cincinnati::schema::ERROR_SCHEMA_NAME));
+        assert!(schemas.contains_key("FeatureState"));
+
+        // Status endpoints are advertised on the status listener, at the requested host.
+        match spec.paths.paths.get("/readyz") {
+            Some(ReferenceOr::Item(item)) => {
+                assert_eq!(item.servers.len(), 1);
+                assert_eq!(item.servers[0].url, "http://localhost:9081");
+            }
+            other => return Err(format!("unexpected /readyz endpoint {:?}", other).into()),
+        }
+        assert!(spec.paths.paths.contains_key("/version"));
+
         Ok(())
     }
 }
@@ -0,0 +1,377 @@
+//! Pins: clients served a fixed graph for a period.
+//!
+//! Customers in change-freeze windows must not see their update
+//! recommendations change. Clients whose identifier, the `id` query
+//! parameter, is listed by an active pin are served either a named
+//! graph-builder snapshot, or the graph frozen at their first request within
+//! the window, until the pin expires. Both are processed by the main policy
+//! chain, and the rendered graphs are kept in memory for the lifetime of the
+//! pin, up to `MAX_FROZEN_GRAPHS` parameter combinations per pin; further
+//! combinations are rendered afresh on each request.
+
+use crate::graph::{is_upstream_plugin, process_plugins, process_upstream_plugins, RenderedGraph};
+use crate::standalone::Pipeline;
+use cincinnati::plugins::internal::versioned_graph::VersionedGraph;
+use cincinnati::plugins::BoxedPlugin;
+use commons::prelude_errors::*;
+use commons::GraphError;
+use parking_lot::Mutex;
+use prometheus::{IntCounterVec, Registry};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Response header naming the pin a client is served.
+pub(crate) static PIN_HEADER: &str = "x-cincinnati-pin";
+
+/// Timeout of the requests fetching pinned snapshots.
+const SNAPSHOT_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum number of graphs frozen by a pin, one per parameter combination.
+const MAX_FROZEN_GRAPHS: usize = 1024;
+
+lazy_static! {
+    static ref PIN_REQS: IntCounterVec = IntCounterVec::new(
+        prometheus::Opts::new(
+            "graph_pinned_requests_total",
+            "Total number of graph requests served a pinned graph, by pin"
+        ),
+        &["pin"]
+    )
+    .unwrap();
+    static ref PIN_UNFROZEN: IntCounterVec = IntCounterVec::new(
+        prometheus::Opts::new(
+            "graph_pinned_unfrozen_total",
+            "Total number of pinned graphs not frozen as the pin holds the maximum of frozen graphs, by pin"
+        ),
+        &["pin"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(PIN_REQS.clone()))?;
+    registry.register(Box::new(PIN_UNFROZEN.clone()))?;
+    Ok(())
+}
+
+/// Settings of a pin.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PinSettings {
+    /// Pin name, reported to clients.
+    pub name: String,
+    /// Identifiers of the pinned clients.
+    pub ids: BTreeSet<String>,
+    /// Start of the pin, immediate if unset.
+    pub from: Option<SystemTime>,
+    /// Expiry of the pin.
+    pub until: SystemTime,
+    /// Identifier of the graph-builder snapshot served to the pinned clients,
+    /// the graph being frozen at their first request if unset.
+    pub snapshot: Option<String>,
+}
+
+impl PinSettings {
+    /// Check that the pin is well-formed.
+    pub fn validate(&self) -> Fallible<()> {
+        ensure!(!self.name.is_empty(), "pins must not have empty names");
+        ensure!(
+            !self.ids.is_empty() && !self.ids.iter().any(String::is_empty),
+            "pin '{}' must list non-empty client identifiers",
+            self.name
+        );
+        if let Some(from) = self.from {
+            ensure!(
+                from < self.until,
+                "pin '{}' must start before it expires",
+                self.name
+            );
+        }
+        if let Some(snapshot) = &self.snapshot {
+            ensure!(
+                !snapshot.is_empty() && !snapshot.contains('/'),
+                "invalid snapshot '{}' of pin '{}'",
+                snapshot,
+                self.name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A pin with the graphs served to its clients.
+#[derive(Debug)]
+pub(crate) struct Pin {
+    settings: PinSettings,
+    /// Upstream graph of the snapshot, once fetched.
+    snapshot_graph: Mutex<Option<cincinnati::Graph>>,
+    /// Graphs served to the pinned clients, by sorted plugin parameters.
+    frozen: Mutex<HashMap<Vec<(String, String)>, RenderedGraph>>,
+}
+
+impl Pin {
+    /// Pin name, reported to clients.
+    pub(crate) fn name(&self) -> &str {
+        &self.settings.name
+    }
+
+    fn is_active(&self, now: SystemTime) -> bool {
+        self.settings.from.map_or(true, |from| from <= now) && now < self.settings.until
+    }
+}
+
+/// Pins, in configuration order.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Pins {
+    pins: Arc<Vec<Pin>>,
+    snapshots_url: Option<String>,
+    client: reqwest::Client,
+    /// Maximum size of a fetched snapshot, in bytes.
+    max_snapshot_bytes: Option<u64>,
+}
+
+impl Pins {
+    /// Create pins, fetching their snapshots from `snapshots_url`, up to
+    /// `max_snapshot_bytes` each.
+    pub(crate) fn try_new(
+        settings: &[PinSettings],
+        snapshots_url: Option<&str>,
+        max_snapshot_bytes: Option<u64>,
+    ) -> Fallible<Self> {
+        if settings.iter().any(|pin| pin.snapshot.is_some()) {
+            ensure!(
+                snapshots_url.is_some(),
+                "pinning snapshots requires the snapshots URL of the upstream"
+            );
+        }
+        let client = commons::http::HttpClientBuilder::new()
+            .timeout(SNAPSHOT_FETCH_TIMEOUT)
+            .build()
+            .context("building snapshots client")?;
+        let pins = settings
+            .iter()
+            .map(|settings| Pin {
+                settings: settings.clone(),
+                snapshot_graph: Mutex::new(None),
+                frozen: Mutex::new(HashMap::new()),
+            })
+            .collect();
+        Ok(Self {
+            pins: Arc::new(pins),
+            snapshots_url: snapshots_url.map(|url| url.trim_end_matches('/').to_string()),
+            client,
+            max_snapshot_bytes,
+        })
+    }
+
+    /// Return the first pin of the client with the given identifier active at `now`, if any.
+    ///
+    /// Graphs of the expired pins of the client are released.
+    pub(crate) fn assign(&self, client_id: Option<&str>, now: SystemTime) -> Option<&Pin> {
+        let client_id = client_id.filter(|id| !id.is_empty())?;
+        self.pins
+            .iter()
+            .filter(|pin| pin.settings.ids.contains(client_id))
+            .find(|pin| {
+                if now >= pin.settings.until {
+                    pin.frozen.lock().clear();
+                    pin.snapshot_graph.lock().take();
+                }
+                pin.is_active(now)
+            })
+    }
+
+    /// Return the graph served to the clients of `pin` for the given plugin
+    /// parameters, rendering it with `plugins` on the first request.
+    pub(crate) async fn render(
+        &self,
+        pin: &Pin,
+        key: Vec<(String, String)>,
+        pipeline: Option<&Pipeline>,
+        plugins: &'static [BoxedPlugin],
+        plugin_params: HashMap<String, String>,
+    ) -> Result<RenderedGraph, GraphError> {
+        PIN_REQS.with_label_values(&[pin.name()]).inc();
+        if let Some(rendered) = pin.frozen.lock().get(&key) {
+            return Ok(rendered.clone());
+        }
+
+        let rendered = match &pin.settings.snapshot {
+            Some(snapshot) => {
+                let graph = self.snapshot_graph(pin, snapshot).await?;
+                let plugins = plugins.iter().filter(|plugin| !is_upstream_plugin(plugin));
+                process_plugins(plugins, graph, plugin_params).await?
+            }
+            None => process_upstream_plugins(pipeline, plugins.iter(), plugin_params).await?,
+        };
+
+        // Concurrent first requests are all served the graph frozen first.
+        let mut frozen = pin.frozen.lock();
+        if frozen.len() >= MAX_FROZEN_GRAPHS && !frozen.contains_key(&key) {
+            PIN_UNFROZEN.with_label_values(&[pin.name()]).inc();
+            return Ok(rendered);
+        }
+        Ok(frozen.entry(key).or_insert(rendered).clone())
+    }
+
+    /// Return the upstream graph of `snapshot`, fetching it on first use.
+    async fn snapshot_graph(
+        &self,
+        pin: &Pin,
+        snapshot: &str,
+    ) -> Result<cincinnati::Graph, GraphError> {
+        if let Some(graph) = pin.snapshot_graph.lock().as_ref() {
+            return Ok(graph.clone());
+        }
+
+        let base = self.snapshots_url.as_deref().ok_or_else(|| {
+            GraphError::FailedUpstreamRequest("no snapshots URL configured".to_string())
+        })?;
+        let url = format!("{}/{}", base, snapshot);
+        debug!("fetching snapshot of pin '{}' from {}", pin.name(), url);
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| GraphError::FailedUpstreamFetch(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(GraphError::FailedUpstreamFetch(format!(
+                "fetching snapshot '{}': {}",
+                snapshot,
+                response.status()
+            )));
+        }
+        let body = commons::download::read_body(response, self.max_snapshot_bytes)
+            .await
+            .map_err(|e| {
+                GraphError::FailedUpstreamFetch(format!("fetching snapshot '{}': {}", snapshot, e))
+            })?;
+        let versioned: VersionedGraph =
+            serde_json::from_slice(&body).map_err(|e| GraphError::FailedJsonIn(e.to_string()))?;
+
+        *pin.snapshot_graph.lock() = Some(versioned.graph.clone());
+        Ok(versioned.graph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin(name: &str, ids: &[&str], from: u64, until: u64) -> PinSettings {
+        PinSettings {
+            name: name.to_string(),
+            ids: ids.iter().map(|id| id.to_string()).collect(),
+            from: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(from)),
+            until: SystemTime::UNIX_EPOCH + Duration::from_secs(until),
+            snapshot: None,
+        }
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn assign_active_pins() -> Fallible<()> {
+        let pins = Pins::try_new(
+            &[
+                pin("freeze", &["cluster-a", "cluster-b"], 100, 200),
+                pin("later", &["cluster-a"], 150, 300),
+            ],
+            None,
+            None,
+        )?;
+
+        assert!(pins.assign(Some("cluster-a"), at(50)).is_none());
+        assert_eq!(
+            pins.assign(Some("cluster-a"), at(100)).unwrap().name(),
+            "freeze"
+        );
+        assert_eq!(
+            pins.assign(Some("cluster-b"), at(199)).unwrap().name(),
+            "freeze"
+        );
+        assert_eq!(
+            pins.assign(Some("cluster-a"), at(200)).unwrap().name(),
+            "later"
+        );
+        assert!(pins.assign(Some("cluster-b"), at(200)).is_none());
+        assert!(pins.assign(Some("cluster-c"), at(150)).is_none());
+        assert!(pins.assign(None, at(150)).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_frozen_graphs() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let pins = Pins::try_new(
+            &[pin("freeze", &["cluster-a"], 0, 4_000_000_000)],
+            None,
+            None,
+        )?;
+        let pin = pins.assign(Some("cluster-a"), SystemTime::now()).unwrap();
+        let plugins: &'static [BoxedPlugin] = &[];
+        let render = |channel: &str| {
+            let params: HashMap<String, String> =
+                vec![("channel".to_string(), channel.to_string())]
+                    .into_iter()
+                    .collect();
+            let key = params.clone().into_iter().collect();
+            rt.block_on(pins.render(pin, key, None, plugins, params))
+                .map_err(|e| format_err!("{}", e))
+        };
+
+        let frozen = render("stable-4.1")?;
+        let key = vec![("channel".to_string(), "stable-4.1".to_string())];
+        pin.frozen.lock().get_mut(&key).unwrap().graph_json = "frozen".to_string();
+
+        // Further requests are served the frozen graph, without running the plugins.
+        assert_eq!(render("stable-4.1")?.graph_json, "frozen");
+        assert_eq!(render("fast-4.1")?.graph_json, frozen.graph_json);
+
+        // Once the pin holds the maximum of frozen graphs, further parameter
+        // combinations are rendered without being frozen.
+        for i in pin.frozen.lock().len()..MAX_FROZEN_GRAPHS {
+            render(&format!("channel-{}", i))?;
+        }
+        assert_eq!(pin.frozen.lock().len(), MAX_FROZEN_GRAPHS);
+        render("candidate-4.1")?;
+        assert_eq!(pin.frozen.lock().len(), MAX_FROZEN_GRAPHS);
+        assert_eq!(render("stable-4.1")?.graph_json, "frozen");
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_pins() {
+        assert!(pin("freeze", &["cluster-a"], 100, 200).validate().is_ok());
+        for invalid in &[
+            pin("", &["cluster-a"], 100, 200),
+            pin("freeze", &[], 100, 200),
+            pin("freeze", &[""], 100, 200),
+            pin("freeze", &["cluster-a"], 200, 100),
+            PinSettings {
+                snapshot: Some("../index".to_string()),
+                ..pin("freeze", &["cluster-a"], 100, 200)
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{:?}", invalid);
+        }
+
+        let snapshot = PinSettings {
+            snapshot: Some("20261014T120000Z-3f2a9c1b0d4e".to_string()),
+            ..pin("freeze", &["cluster-a"], 100, 200)
+        };
+        assert!(Pins::try_new(&[snapshot.clone()], None, None).is_err());
+        assert!(Pins::try_new(
+            &[snapshot],
+            Some("http://graph-builder:9080/snapshots"),
+            Some(1_048_576)
+        )
+        .is_ok());
+    }
+}
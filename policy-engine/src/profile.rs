@@ -0,0 +1,322 @@
+//! Profiles of the plugin chain.
+//!
+//! Operators investigating the performance of graph requests can have the
+//! main plugin chain run once for given query parameters, e.g. `channel` and
+//! `arch`, and get the time spent and the memory allocated by each plugin.
+//! Profiles bypass the shared runs of identical requests, the variant store,
+//! cohorts and pins, so that every plugin of the chain actually runs.
+//!
+//! The chain runs on a thread of its own, whose allocations are counted if
+//! the binary counts allocations, see `commons::alloc`.
+
+use crate::graph;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use cincinnati::plugins::{BoxedPlugin, InternalIO, PluginIO};
+use commons::alloc::Allocations;
+use commons::prelude_errors::*;
+use commons::GraphError;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+/// Bearer token required by the debug endpoints.
+pub use commons::auth::BearerToken as DebugToken;
+
+/// Held while a profile runs, so that only one runs at a time.
+static RUNNING: Mutex<()> = parking_lot::const_mutex(());
+
+/// Profile of the run of a plugin.
+#[derive(Debug, Serialize)]
+pub struct PluginProfile {
+    /// Plugin name.
+    pub name: &'static str,
+    /// Time spent in the plugin, in seconds.
+    pub duration_secs: f64,
+    /// Allocations made by the plugin, if counted.
+    pub allocations: Option<Allocations>,
+    /// Number of releases of the graph returned by the plugin, if it succeeded.
+    pub releases: Option<u64>,
+    /// Number of edges of the graph returned by the plugin, if it succeeded.
+    pub edges: Option<u64>,
+    /// Error returned by the plugin, if it failed.
+    pub error: Option<String>,
+}
+
+/// Profile of a run of the plugin chain.
+#[derive(Debug, Serialize)]
+pub struct ChainProfile {
+    /// Parameters of the run, defaults included.
+    pub parameters: BTreeMap<String, String>,
+    /// Time spent in the plugin chain, in seconds.
+    pub duration_secs: f64,
+    /// Allocations made by the plugin chain, if counted.
+    pub allocations: Option<Allocations>,
+    /// Runs of the plugins, in order, up to the first failing one.
+    pub plugins: Vec<PluginProfile>,
+}
+
+/// Return the allocations made since `start`, if counted.
+fn allocated_since(start: Option<Allocations>) -> Option<Allocations> {
+    Some(Allocations::current()?.since(&start?))
+}
+
+/// Run `plugins` once on `graph` with `parameters`, profiling each plugin.
+pub(crate) async fn profile_chain<P>(
+    plugins: P,
+    graph: cincinnati::Graph,
+    parameters: HashMap<String, String>,
+) -> ChainProfile
+where
+    P: IntoIterator<Item = &'static BoxedPlugin>,
+{
+    let deadline = commons::deadline::from_params(&parameters);
+    let mut profile = ChainProfile {
+        parameters: parameters
+            .iter()
+            .filter(|(key, _)| key.as_str() != commons::GRAPH_DEADLINE_PARAM_KEY)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        duration_secs: 0.0,
+        allocations: None,
+        plugins: vec![],
+    };
+
+    let chain_start = Instant::now();
+    let chain_allocations = Allocations::current();
+    let mut io = PluginIO::InternalIO(InternalIO { graph, parameters });
+    for plugin in plugins {
+        let name = plugin.get_name();
+        let start = Instant::now();
+        let allocations = Allocations::current();
+        let result = commons::deadline::within(deadline, name, plugin.run(io)).await;
+        let mut run = PluginProfile {
+            name,
+            duration_secs: start.elapsed().as_secs_f64(),
+            allocations: allocated_since(allocations),
+            releases: None,
+            edges: None,
+            error: None,
+        };
+        match result {
+            Ok(next_io) => {
+                if let PluginIO::InternalIO(internal_io) = &next_io {
+                    run.releases = Some(internal_io.graph.releases_count());
+                    run.edges = Some(internal_io.graph.edges_count());
+                }
+                profile.plugins.push(run);
+                io = next_io;
+            }
+            Err(e) => {
+                run.error = Some(format!("{:#}", e));
+                profile.plugins.push(run);
+                break;
+            }
+        }
+    }
+    profile.duration_secs = chain_start.elapsed().as_secs_f64();
+    profile.allocations = allocated_since(chain_allocations);
+
+    profile
+}
+
+/// Return the plugins, the initial graph and the parameters of a run of the
+/// main plugin chain for `query`, validated as for graph requests.
+fn prepare(
+    query: &str,
+    app_data: &AppState,
+) -> Result<
+    (
+        Vec<&'static BoxedPlugin>,
+        cincinnati::Graph,
+        HashMap<String, String>,
+    ),
+    GraphError,
+> {
+    let (query, _) = graph::apply_default_params(&app_data.default_params, query);
+    commons::ensure_query_params(&app_data.mandatory_params, &query)?;
+    app_data.param_constraints.ensure_query_values(&query)?;
+
+    let mut parameters: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    cincinnati::plugins::validate_plugin_parameters(app_data.plugins.iter(), &parameters)?;
+    parameters.insert(String::from("content_type"), graph::latest_content_type());
+    graph::insert_deadline_param(&mut parameters, app_data.deadline(None));
+
+    // In standalone mode, the graph built in-process is processed instead of the upstream one.
+    match &app_data.pipeline {
+        Some(pipeline) => {
+            let (upstream, _) = pipeline.graph()?;
+            let plugins = app_data
+                .plugins
+                .iter()
+                .filter(|plugin| !graph::is_upstream_plugin(plugin))
+                .collect();
+            Ok((plugins, upstream, parameters))
+        }
+        None => Ok((
+            app_data.plugins.iter().collect(),
+            Default::default(),
+            parameters,
+        )),
+    }
+}
+
+/// Run the plugin chain on a runtime of its own, polled by the calling
+/// thread only, so that the allocations of the plugins are counted.
+fn run_profile(
+    plugins: Vec<&'static BoxedPlugin>,
+    graph: cincinnati::Graph,
+    parameters: HashMap<String, String>,
+) -> Fallible<ChainProfile> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    Ok(runtime.block_on(profile_chain(plugins, graph, parameters)))
+}
+
+/// Run the main plugin chain once for the query parameters of the request,
+/// serving the profile of each plugin as JSON.
+///
+/// Requests must present the configured `DebugToken` as a bearer token.
+pub async fn serve_profile(
+    req: HttpRequest,
+    token: web::Data<DebugToken>,
+    app_data: web::Data<AppState>,
+) -> HttpResponse {
+    if let Err(response) = token.check(&req) {
+        return response;
+    }
+
+    let (plugins, graph, parameters) = match prepare(req.query_string(), &app_data) {
+        Ok(run) => run,
+        Err(e) => return e.as_json_error(),
+    };
+    let result = web::block(move || {
+        let _running = match RUNNING.try_lock() {
+            Some(running) => running,
+            None => return Ok(None),
+        };
+        info!("plugin chain profile requested");
+        run_profile(plugins, graph, parameters).map(Some)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(profile))) => HttpResponse::Ok().json(profile),
+        Ok(Ok(None)) => HttpResponse::TooManyRequests().body("a profile is already running"),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(format!("{:#}", e)),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test::TestRequest;
+    use cincinnati::plugins::prelude::*;
+    use cincinnati::testing::generate_custom_graph;
+
+    fn channel_filter() -> Fallible<&'static [BoxedPlugin]> {
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(("name", ChannelFilterPlugin::PLUGIN_NAME))?],
+            None,
+        )?;
+        Ok(Box::leak(plugins.into_boxed_slice()))
+    }
+
+    #[test]
+    fn profile_plugins() -> Fallible<()> {
+        let rt = crate::graph::tests::common_init();
+        let plugins = channel_filter()?;
+        let channels = |channels: &str| {
+            vec![(
+                "io.openshift.upgrades.graph.release.channels".to_string(),
+                channels.to_string(),
+            )]
+            .into_iter()
+            .collect()
+        };
+        let graph = || {
+            generate_custom_graph(
+                "image",
+                vec![
+                    (0, channels("stable-4.1")),
+                    (1, channels("stable-4.1,fast-4.1")),
+                    (2, channels("fast-4.1")),
+                ],
+                None,
+            )
+        };
+
+        let parameters = vec![("channel".to_string(), "stable-4.1".to_string())]
+            .into_iter()
+            .collect();
+        let profile = rt.block_on(profile_chain(plugins, graph(), parameters));
+        assert_eq!(profile.parameters["channel"], "stable-4.1");
+        assert_eq!(profile.plugins.len(), 1);
+        let run = &profile.plugins[0];
+        assert_eq!(run.name, ChannelFilterPlugin::PLUGIN_NAME);
+        assert_eq!(run.error, None);
+        assert_eq!(run.releases, Some(2));
+        assert_eq!(run.edges, Some(1));
+        assert!(profile.duration_secs >= run.duration_secs);
+
+        let profile = rt.block_on(profile_chain(plugins, graph(), HashMap::new()));
+        assert_eq!(profile.plugins.len(), 1);
+        assert!(profile.plugins[0].error.is_some());
+        assert_eq!(profile.plugins[0].releases, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_profiles() -> Fallible<()> {
+        let rt = crate::graph::tests::common_init();
+        let token = web::Data::new(DebugToken::new("secret"));
+        let app_data = web::Data::new(AppState {
+            mandatory_params: vec!["channel".to_string()].into_iter().collect(),
+            plugins: channel_filter()?,
+            ..Default::default()
+        });
+
+        let profile = |query: &str, token_value: Option<&str>| {
+            let mut req = TestRequest::get().uri(&format!("/debug/profile?{}", query));
+            if let Some(value) = token_value {
+                req = req.insert_header((header::AUTHORIZATION, format!("Bearer {}", value)));
+            }
+            rt.block_on(serve_profile(
+                req.to_http_request(),
+                token.clone(),
+                app_data.clone(),
+            ))
+        };
+
+        assert_eq!(
+            profile("channel=stable-4.1", None).status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            profile("channel=stable-4.1", Some("wrong")).status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            profile("arch=amd64", Some("secret")).status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let resp = profile("channel=stable-4.1&arch=amd64", Some("secret"));
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = rt
+            .block_on(actix_web::body::to_bytes(resp.into_body()))
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(body["parameters"]["arch"], "amd64");
+        assert_eq!(body["plugins"][0]["name"], ChannelFilterPlugin::PLUGIN_NAME);
+        assert_eq!(body["plugins"][0]["releases"], 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+//! Shadowing of graph requests to a secondary deployment.
+//!
+//! A sample of the graph requests is mirrored by a background thread to a
+//! secondary policy-engine, e.g. a canary running a new plugin configuration,
+//! and the digest of its response is compared with the one of the response
+//! served to the client. Shadowing never delays nor alters client responses:
+//! mirrored requests are dropped when the background thread falls behind.
+
+use crate::graph;
+use actix_web::http::{header, StatusCode};
+use actix_web::HttpRequest;
+use commons::prelude_errors::*;
+use custom_debug_derive::Debug as CustomDebug;
+use prometheus::{IntCounterVec, Registry};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Request header marking mirrored requests, so that the secondary
+/// deployment can tell them apart from client requests.
+pub(crate) static SHADOW_HEADER: &str = "x-cincinnati-shadow";
+
+/// Default percentage of the graph requests mirrored.
+pub const DEFAULT_PERCENT: f64 = 1.0;
+
+/// Number of mirrored requests buffered before new ones are dropped.
+static QUEUE_CAPACITY: usize = 256;
+
+/// Timeout for a single mirrored request.
+static SHADOW_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcomes of mirrored requests.
+static OUTCOMES: &[&str] = &["match", "mismatch", "failed", "dropped"];
+
+lazy_static! {
+    static ref SHADOW_REQS: IntCounterVec = IntCounterVec::new(
+        prometheus::Opts::new(
+            "graph_shadow_requests_total",
+            "Total number of graph requests mirrored to the shadow deployment, by outcome"
+        ),
+        &["outcome"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    for outcome in OUTCOMES {
+        SHADOW_REQS.with_label_values(&[outcome]);
+    }
+    registry.register(Box::new(SHADOW_REQS.clone()))?;
+    Ok(())
+}
+
+/// A graph request to mirror, along with the response served to the client.
+#[derive(Debug, PartialEq, Eq)]
+struct ShadowRequest {
+    /// Path and query string of the request.
+    path_and_query: String,
+    /// Media type accepted by the client, if any.
+    accept: Option<String>,
+    /// Status code of the response served to the client.
+    status: u16,
+    /// Entity tag of the graph served to the client, if any.
+    etag: Option<String>,
+}
+
+/// Whether the `n`-th request is sampled, so that exactly `percent` of the
+/// requests are, spread evenly.
+fn is_sampled(n: u64, percent: f64) -> bool {
+    let rate = percent / 100.0;
+    ((n + 1) as f64 * rate).floor() > (n as f64 * rate).floor()
+}
+
+/// Handle to the background request mirror.
+#[derive(Clone, CustomDebug)]
+pub(crate) struct Shadow {
+    percent: f64,
+    #[debug(skip)]
+    requests: Arc<AtomicU64>,
+    #[debug(skip)]
+    tx: SyncSender<ShadowRequest>,
+}
+
+impl Shadow {
+    /// Start mirroring `percent` of the graph requests to the deployment at `url`.
+    pub(crate) fn try_new(url: reqwest::Url, percent: f64) -> Fallible<Self> {
+        let mirror = ShadowMirror::try_new(url)?;
+        let (tx, rx) = mpsc::sync_channel(QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("graph-shadow".to_string())
+            .spawn(move || mirror.run(rx))
+            .context("spawning graph request shadowing")?;
+
+        Ok(Self {
+            percent,
+            requests: Default::default(),
+            tx,
+        })
+    }
+
+    /// Mirror a graph request if sampled, dropping it if the mirror lags behind.
+    pub(crate) fn mirror(&self, req: &HttpRequest, status: StatusCode, etag: Option<&str>) {
+        if !is_sampled(self.requests.fetch_add(1, Ordering::Relaxed), self.percent) {
+            return;
+        }
+
+        let request = ShadowRequest {
+            path_and_query: req
+                .uri()
+                .path_and_query()
+                .map(|path_and_query| path_and_query.as_str())
+                .unwrap_or_else(|| req.path())
+                .to_string(),
+            accept: req
+                .headers()
+                .get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok())
+                .map(str::to_string),
+            status: status.as_u16(),
+            etag: etag.map(str::to_string),
+        };
+        match self.tx.try_send(request) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                SHADOW_REQS.with_label_values(&["dropped"]).inc();
+                debug!("graph shadowing queue is full, dropping request");
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                SHADOW_REQS.with_label_values(&["dropped"]).inc();
+                error!("graph shadowing is not running, dropping request");
+            }
+        }
+    }
+}
+
+/// Mirror sending requests to the secondary deployment.
+struct ShadowMirror {
+    url: reqwest::Url,
+    client: reqwest::Client,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl ShadowMirror {
+    fn try_new(url: reqwest::Url) -> Fallible<Self> {
+        let client = commons::http::HttpClientBuilder::new()
+            .timeout(SHADOW_TIMEOUT)
+            .build()
+            .context("building graph shadowing HTTP client")?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("building graph shadowing runtime")?;
+        Ok(Self {
+            url,
+            client,
+            runtime,
+        })
+    }
+
+    /// Mirror requests until all handles are dropped.
+    fn run(self, rx: Receiver<ShadowRequest>) {
+        for request in rx {
+            let outcome = match self.compare(&request) {
+                Ok(true) => "match",
+                Ok(false) => "mismatch",
+                Err(e) => {
+                    warn!("failed to mirror graph request: {:#}", e);
+                    "failed"
+                }
+            };
+            SHADOW_REQS.with_label_values(&[outcome]).inc();
+        }
+    }
+
+    /// Send `request` to the secondary deployment, returning whether its
+    /// response matches the one served to the client.
+    fn compare(&self, request: &ShadowRequest) -> Fallible<bool> {
+        let url = self
+            .url
+            .join(&request.path_and_query)
+            .context(format!("joining '{}'", request.path_and_query))?;
+        let mut builder = self.client.get(url).header(SHADOW_HEADER, "true");
+        if let Some(accept) = &request.accept {
+            builder = builder.header(reqwest::header::ACCEPT, accept);
+        }
+
+        let (status, body) = self.runtime.block_on(async {
+            let response = builder.send().await?;
+            let status = response.status().as_u16();
+            Ok::<_, reqwest::Error>((status, response.text().await?))
+        })?;
+        let etag = if (200..300).contains(&status) {
+            Some(graph::graph_etag(&body))
+        } else {
+            None
+        };
+
+        let matches = status == request.status && etag == request.etag;
+        if !matches {
+            debug!(
+                "shadow response to '{}' differs: status {} instead of {}, entity tag {:?} instead of {:?}",
+                request.path_and_query, status, request.status, etag, request.etag
+            );
+        }
+        Ok(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_requests() {
+        let sampled = |percent| (0..1000).filter(|n| is_sampled(*n, percent)).count();
+        assert_eq!(sampled(0.0), 0);
+        assert_eq!(sampled(1.0), 10);
+        assert_eq!(sampled(12.5), 125);
+        assert_eq!(sampled(100.0), 1000);
+    }
+
+    #[test]
+    fn compare_responses() -> Fallible<()> {
+        let body = r#"{"nodes":[],"edges":[]}"#;
+        let _secondary = mockito::mock("GET", "/shadow/graph?channel=stable-4.14")
+            .match_header(SHADOW_HEADER, "true")
+            .match_header("accept", "application/json")
+            .with_status(200)
+            .with_body(body)
+            .create();
+        let mirror = ShadowMirror::try_new(reqwest::Url::parse(&mockito::server_url())?)?;
+
+        let mut request = ShadowRequest {
+            path_and_query: "/shadow/graph?channel=stable-4.14".to_string(),
+            accept: Some("application/json".to_string()),
+            status: 200,
+            etag: Some(graph::graph_etag(body)),
+        };
+        assert!(mirror.compare(&request)?);
+
+        request.etag = Some(graph::graph_etag(r#"{"nodes":[],"edges":[],"x":1}"#));
+        assert!(!mirror.compare(&request)?);
+
+        request.status = 400;
+        request.etag = None;
+        assert!(!mirror.compare(&request)?);
+
+        request.path_and_query = "/shadow/graph?channel=unknown".to_string();
+        request.status = 501;
+        assert!(mirror.compare(&request)?);
+
+        Ok(())
+    }
+}
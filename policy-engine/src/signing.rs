@@ -0,0 +1,197 @@
+//! Detached JWS signing of graph responses.
+//!
+//! Signatures are produced according to RFC 7515 (JSON Web Signature) with
+//! detached content (RFC 7515, Appendix F): the compact serialization carries
+//! an empty payload segment, and the signed payload is the exact graph body
+//! served to the client.
+//!
+//! Signatures are kept by the entity tag of the signed graph, so that the
+//! signature of a graph already served is returned as is by the signature
+//! endpoint, instead of the signature of a graph rendered again, which may
+//! differ once the upstream graph changes.
+
+use commons::prelude_errors::*;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+
+/// Response header carrying the detached JWS for a graph response.
+pub static SIGNATURE_HEADER: &str = "x-graph-signature";
+
+/// Media type for the `graph.sig` endpoint.
+pub static SIGNATURE_CONTENT_TYPE: &str = "application/jose";
+
+/// Maximum number of signatures kept by entity tag.
+const MAX_CACHED_SIGNATURES: usize = 256;
+
+/// Signs graph documents with an Ed25519 key.
+pub struct GraphSigner {
+    keypair: Keypair,
+    key_id: Option<String>,
+    /// Base64url-encoded JWS protected header.
+    protected: String,
+    /// Signatures of the served graphs, by entity tag.
+    signatures: Mutex<SignatureCache>,
+}
+
+/// Signatures by entity tag, oldest evicted first.
+#[derive(Debug, Default)]
+struct SignatureCache {
+    by_etag: HashMap<String, String>,
+    order: VecDeque<String>,
+}
+
+impl std::fmt::Debug for GraphSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GraphSigner")
+            .field("key_id", &self.key_id)
+            .finish()
+    }
+}
+
+impl GraphSigner {
+    /// Create a signer from a base64-encoded 32 bytes Ed25519 secret key.
+    pub fn from_base64_key(encoded: &str, key_id: Option<String>) -> Fallible<Self> {
+        let bytes = base64::decode(encoded.trim()).context("decoding signing key")?;
+        let secret = SecretKey::from_bytes(&bytes)
+            .map_err(|e| format_err!("invalid Ed25519 signing key: {}", e))?;
+        let public = PublicKey::from(&secret);
+
+        let mut header = serde_json::json!({ "alg": "EdDSA" });
+        if let Some(kid) = &key_id {
+            header["kid"] = serde_json::Value::String(kid.clone());
+        }
+        let protected =
+            base64::encode_config(serde_json::to_vec(&header)?, base64::URL_SAFE_NO_PAD);
+
+        Ok(Self {
+            keypair: Keypair { secret, public },
+            key_id,
+            protected,
+            signatures: Default::default(),
+        })
+    }
+
+    /// Create a signer from a file containing a base64-encoded Ed25519 secret key.
+    pub fn from_file<P>(path: P, key_id: Option<String>) -> Fallible<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let encoded = std::fs::read_to_string(&path).context(format!(
+            "failed to read signing key {}",
+            path.as_ref().display()
+        ))?;
+        Self::from_base64_key(&encoded, key_id)
+    }
+
+    /// Return the compact detached JWS for the given payload.
+    pub fn sign(&self, payload: &[u8]) -> String {
+        let encoded_payload = base64::encode_config(payload, base64::URL_SAFE_NO_PAD);
+        let signing_input = format!("{}.{}", self.protected, encoded_payload);
+        let signature = self.keypair.sign(signing_input.as_bytes());
+
+        format!(
+            "{}..{}",
+            self.protected,
+            base64::encode_config(signature.to_bytes(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// Return the compact detached JWS for the graph `payload` tagged `etag`,
+    /// keeping it for `cached`.
+    pub fn sign_graph(&self, etag: &str, payload: &[u8]) -> String {
+        if let Some(jws) = self.cached(etag) {
+            return jws;
+        }
+
+        let jws = self.sign(payload);
+        let mut signatures = self.signatures.lock();
+        if signatures
+            .by_etag
+            .insert(etag.to_string(), jws.clone())
+            .is_none()
+        {
+            signatures.order.push_back(etag.to_string());
+        }
+        while signatures.order.len() > MAX_CACHED_SIGNATURES {
+            if let Some(oldest) = signatures.order.pop_front() {
+                signatures.by_etag.remove(&oldest);
+            }
+        }
+        jws
+    }
+
+    /// Return the signature of the graph tagged `etag`, if signed recently.
+    pub fn cached(&self, etag: &str) -> Option<String> {
+        self.signatures.lock().by_etag.get(etag).cloned()
+    }
+
+    /// Return the public half of the signing key.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.keypair.public
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signature, Verifier};
+    use std::convert::TryFrom;
+
+    static TEST_KEY: &str = "nWGxne/9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A=";
+
+    #[test]
+    fn sign_detached_roundtrip() -> Fallible<()> {
+        let signer = GraphSigner::from_base64_key(TEST_KEY, Some("test-key".to_string()))?;
+        let payload = br#"{"version":1,"nodes":[],"edges":[],"conditionalEdges":[]}"#;
+
+        let jws = signer.sign(payload);
+        let parts: Vec<&str> = jws.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        assert!(parts[1].is_empty(), "payload is not detached: {}", jws);
+
+        let header: serde_json::Value =
+            serde_json::from_slice(&base64::decode_config(parts[0], base64::URL_SAFE_NO_PAD)?)?;
+        assert_eq!(header["alg"], "EdDSA");
+        assert_eq!(header["kid"], "test-key");
+
+        let signing_input = format!(
+            "{}.{}",
+            parts[0],
+            base64::encode_config(&payload[..], base64::URL_SAFE_NO_PAD)
+        );
+        let signature = Signature::try_from(
+            base64::decode_config(parts[2], base64::URL_SAFE_NO_PAD)?.as_slice(),
+        )?;
+        signer
+            .public_key()
+            .verify(signing_input.as_bytes(), &signature)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn keep_signatures_by_etag() -> Fallible<()> {
+        let signer = GraphSigner::from_base64_key(TEST_KEY, None)?;
+        let jws = signer.sign_graph("\"a\"", b"graph a");
+        assert_eq!(jws, signer.sign(b"graph a"));
+        assert_eq!(signer.cached("\"a\"").as_deref(), Some(jws.as_str()));
+        assert_eq!(signer.cached("\"b\""), None);
+
+        // The oldest signatures are evicted first.
+        for i in 0..MAX_CACHED_SIGNATURES {
+            signer.sign_graph(&format!("\"{}\"", i), b"graph");
+        }
+        assert_eq!(signer.cached("\"a\""), None);
+        assert!(signer.cached("\"0\"").is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_key() {
+        GraphSigner::from_base64_key("not base64!", None).unwrap_err();
+        GraphSigner::from_base64_key("dG9vIHNob3J0", None).unwrap_err();
+    }
+}
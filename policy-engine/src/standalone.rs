@@ -0,0 +1,131 @@
+//! Standalone mode: the graph-builder pipeline embedded in the policy-engine.
+//!
+//! Small and disconnected installs may not want to run two services. In
+//! standalone mode, the scrape loop of a graph-builder runs in-process and
+//! its built graph is processed by the policy plugins straight from memory,
+//! instead of being fetched from an upstream over HTTP.
+
+use commons::prelude_errors::*;
+use commons::GraphError;
+use std::path::Path;
+
+/// Graph-builder pipeline running in-process.
+#[derive(Clone)]
+pub(crate) struct Pipeline(graph_builder::graph::State);
+
+impl std::fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("path_prefix", &self.0.path_prefix())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Pipeline {
+    /// Start the graph-builder pipeline configured by the TOML file at `path`.
+    pub(crate) fn spawn(path: &Path) -> Fallible<Self> {
+        let content = std::fs::read_to_string(path).context(format!(
+            "reading graph-builder configuration {}",
+            path.display()
+        ))?;
+        let settings = graph_builder::config::AppSettings::try_from_toml(&content).context(
+            format!("parsing graph-builder configuration {}", path.display()),
+        )?;
+        info!("embedded graph-builder settings:\n{:#?}", settings);
+
+        let state = graph_builder::server::spawn_pipeline(settings)?;
+        Ok(Self(state))
+    }
+
+    /// Return the last built graph, with the HTTP-date since which it is
+    /// stale if scrapes are failing.
+    pub(crate) fn graph(&self) -> Result<(cincinnati::Graph, Option<String>), GraphError> {
+        let graph = self
+            .0
+            .graph()
+            .map_err(|e| GraphError::FailedUpstreamFetch(e.to_string()))?
+            .ok_or_else(|| {
+                GraphError::FailedUpstreamFetch("no graph has been built yet".to_string())
+            })?;
+        Ok((graph, self.0.stale_since()))
+    }
+
+    /// State of the graph-builder, for its status endpoints.
+    pub(crate) fn state(&self) -> &graph_builder::graph::State {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::process_upstream_plugins;
+    use cincinnati::plugins::prelude::*;
+    use parking_lot::RwLock;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn pipeline(json: &str) -> Pipeline {
+        Pipeline(graph_builder::graph::State::new(
+            Arc::new(RwLock::new(json.to_string())),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            &[],
+            Box::leak(Box::new(prometheus::Registry::new())),
+            Default::default(),
+        ))
+    }
+
+    #[test]
+    fn process_embedded_graph() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let plugins: &'static [BoxedPlugin] = Box::leak(Box::new([
+            plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", "http://offline.url.test")
+            )?
+            .build_plugin(None)?,
+            plugin_config!(("name", ChannelFilterPlugin::PLUGIN_NAME))?.build_plugin(None)?,
+        ]));
+        let params: HashMap<String, String> = vec![
+            ("channel".to_string(), "stable-4.14".to_string()),
+            (
+                "content_type".to_string(),
+                cincinnati::CONTENT_TYPE.to_string(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        // Nothing is served until the first graph is built.
+        let pending = pipeline("");
+        assert!(rt
+            .block_on(process_upstream_plugins(
+                Some(&pending),
+                plugins.iter(),
+                params.clone()
+            ))
+            .is_err());
+
+        let built = pipeline(
+            r#"{"nodes":[
+                {"version":"4.14.0","payload":"image/4.14.0","metadata":{
+                    "io.openshift.upgrades.graph.release.channels":"stable-4.14"}},
+                {"version":"4.15.0","payload":"image/4.15.0","metadata":{}}
+            ],"edges":[[0,1]]}"#,
+        );
+        let rendered = rt
+            .block_on(process_upstream_plugins(
+                Some(&built),
+                plugins.iter(),
+                params,
+            ))
+            .map_err(|e| format_err!("{}", e))?;
+        let graph: serde_json::Value = serde_json::from_str(&rendered.graph_json)?;
+        assert_eq!(graph["nodes"].as_array().unwrap().len(), 1);
+        assert_eq!(rendered.stale_since, None);
+
+        Ok(())
+    }
+}
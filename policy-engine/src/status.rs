@@ -28,3 +28,19 @@ pub async fn serve_readiness(app_data: actix_web::web::Data<AppState>) -> HttpRe
         HttpResponse::ServiceUnavailable().finish()
     }
 }
+
+/// Version of the service.
+#[derive(Debug, Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    git_commit: Option<&'static str>,
+}
+
+/// Expose the version of the service, as JSON.
+pub async fn serve_version() -> HttpResponse {
+    HttpResponse::Ok().json(VersionInfo {
+        version: crate::built_info::PKG_VERSION,
+        git_commit: crate::built_info::GIT_COMMIT_HASH,
+    })
+}
@@ -0,0 +1,401 @@
+//! Content-addressed store of rendered graph variants.
+//!
+//! Each combination of plugin chain, upstream graph and plugin parameters,
+//! e.g. channel, architecture and cohort, renders an identical graph until
+//! the upstream graph changes. Rendered variants are stored under the digest
+//! of the plugin chain configuration, the digest of the upstream graph and
+//! the sorted plugin parameters, so that only the fetch of the upstream graph
+//! runs for the variants already rendered. Plugin chains are registered with
+//! the digest of their settings; variants of unregistered chains are not
+//! stored. Variants are evicted least recently used first once the store
+//! exceeds its memory budget, accounting for their compressed body once
+//! compressed, so that rarely used combinations do not accumulate while hot
+//! ones stay resident.
+
+use crate::graph::RenderedGraph;
+use cincinnati::plugins::catalog::PluginSettings;
+use cincinnati::plugins::BoxedPlugin;
+use commons::prelude_errors::*;
+use parking_lot::Mutex;
+use prometheus::{IntCounter, IntGauge, Registry};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+/// Default memory budget of the store, in bytes.
+pub const DEFAULT_MAX_BYTES: u64 = 256 * 1024 * 1024;
+
+lazy_static! {
+    static ref VARIANTS: IntGauge = IntGauge::new(
+        "graph_variants",
+        "Number of rendered graph variants in the store"
+    )
+    .unwrap();
+    static ref VARIANTS_BYTES: IntGauge = IntGauge::new(
+        "graph_variants_bytes",
+        "Size of the rendered graph variants in the store, in bytes"
+    )
+    .unwrap();
+    static ref VARIANT_HITS: IntCounter = IntCounter::new(
+        "graph_variant_hits_total",
+        "Total number of graph renders served from the variant store"
+    )
+    .unwrap();
+    static ref VARIANT_MISSES: IntCounter = IntCounter::new(
+        "graph_variant_misses_total",
+        "Total number of graph renders missing from the variant store"
+    )
+    .unwrap();
+    static ref VARIANT_EVICTIONS: IntCounter = IntCounter::new(
+        "graph_variant_evictions_total",
+        "Total number of graph variants evicted from the store to fit its memory budget"
+    )
+    .unwrap();
+}
+
+/// Return the digest of the configuration of a plugin chain, from the
+/// settings of its plugins, defaults included.
+pub(crate) fn chain_digest(plugin_settings: &[Box<dyn PluginSettings>]) -> String {
+    let mut hasher = Sha256::new();
+    for settings in plugin_settings {
+        hasher.update(format!("{:?}", settings).as_bytes());
+        hasher.update([0]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Return the accounted size of the variant `rendered` stored at `address`, in bytes.
+fn variant_size(address: &str, rendered: &RenderedGraph) -> u64 {
+    let gzip_len = rendered.gzip_body.get().map_or(0, Vec::len);
+    (address.len() + rendered.graph_json.len() + rendered.content_type.len() + gzip_len) as u64
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(VARIANTS.clone()))?;
+    registry.register(Box::new(VARIANTS_BYTES.clone()))?;
+    registry.register(Box::new(VARIANT_HITS.clone()))?;
+    registry.register(Box::new(VARIANT_MISSES.clone()))?;
+    registry.register(Box::new(VARIANT_EVICTIONS.clone()))?;
+    Ok(())
+}
+
+/// Stored variant.
+#[derive(Debug)]
+struct Entry {
+    rendered: RenderedGraph,
+    /// Accounted size, in bytes.
+    size: u64,
+    /// Tick of the last use.
+    tick: u64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Variants, by content address.
+    entries: HashMap<String, Entry>,
+    /// Content addresses, by tick of their last use.
+    lru: BTreeMap<u64, String>,
+    /// Usage clock.
+    tick: u64,
+    /// Accounted size of all variants, in bytes.
+    bytes: u64,
+    /// Digests of the plugin chains, by address of the chain.
+    chains: HashMap<usize, String>,
+}
+
+impl Inner {
+    fn touch(&mut self, address: &str) -> Option<&Entry> {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(address)?;
+        self.lru.remove(&entry.tick);
+        entry.tick = tick;
+        self.lru.insert(tick, address.to_string());
+        Some(entry)
+    }
+
+    /// Account for the body of the variant at `address` compressed since it
+    /// was stored, if any.
+    fn resize(&mut self, address: &str) {
+        let entry = match self.entries.get_mut(address) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let size = variant_size(address, &entry.rendered);
+        self.bytes = self.bytes - entry.size + size;
+        entry.size = size;
+    }
+
+    /// Evict the least recently used variants other than `keep` until the
+    /// store fits `max_bytes`.
+    fn evict(&mut self, max_bytes: u64, keep: &str) {
+        while self.bytes > max_bytes {
+            let oldest = match self.lru.values().find(|address| *address != keep) {
+                Some(oldest) => oldest.clone(),
+                None => break,
+            };
+            trace!("evicting graph variant {}", oldest);
+            self.remove(&oldest);
+            VARIANT_EVICTIONS.inc();
+        }
+    }
+
+    fn remove(&mut self, address: &str) -> Option<Entry> {
+        let entry = self.entries.remove(address)?;
+        self.lru.remove(&entry.tick);
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    fn update_metrics(&self) {
+        VARIANTS.set(i64::try_from(self.entries.len()).unwrap_or(i64::MAX));
+        VARIANTS_BYTES.set(i64::try_from(self.bytes).unwrap_or(i64::MAX));
+    }
+}
+
+/// Rendered graph variants, by content address.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct VariantStore {
+    /// Memory budget, in bytes; the store is disabled when 0.
+    max_bytes: u64,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl VariantStore {
+    /// Create a store holding up to `max_bytes` of rendered graphs.
+    pub(crate) fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            inner: Default::default(),
+        }
+    }
+
+    /// Whether variants are stored.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    /// Register the plugin chain `plugins`, configured by the settings with
+    /// the given digest, as returned by `chain_digest`.
+    pub(crate) fn register_chain(&self, plugins: &'static [BoxedPlugin], digest: String) {
+        self.inner
+            .lock()
+            .chains
+            .insert(plugins.as_ptr() as usize, digest);
+    }
+
+    /// Return the content address of the variant rendered by `plugins` from
+    /// the upstream graph with the given digest, for the sorted plugin
+    /// parameters, if the chain is registered.
+    pub(crate) fn address(
+        &self,
+        plugins: &'static [BoxedPlugin],
+        upstream_digest: &str,
+        params: &[(String, String)],
+    ) -> Option<String> {
+        let chain = self
+            .inner
+            .lock()
+            .chains
+            .get(&(plugins.as_ptr() as usize))?
+            .clone();
+
+        let mut hasher = Sha256::new();
+        for part in [chain.as_str(), upstream_digest] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        for (key, value) in params {
+            hasher.update(key.as_bytes());
+            hasher.update([b'=']);
+            hasher.update(value.as_bytes());
+            hasher.update([0]);
+        }
+        Some(hex::encode(hasher.finalize()))
+    }
+
+    /// Return the variant stored at `address`, if any, marking it used.
+    ///
+    /// The body of the variant compressed since the previous use is accounted
+    /// for, evicting the least recently used variants to fit the memory budget.
+    pub(crate) fn get(&self, address: &str) -> Option<RenderedGraph> {
+        let mut inner = self.inner.lock();
+        let rendered = inner.touch(address).map(|entry| entry.rendered.clone());
+        if rendered.is_some() {
+            inner.resize(address);
+            inner.evict(self.max_bytes, address);
+            inner.update_metrics();
+        }
+        drop(inner);
+        match rendered {
+            Some(_) => VARIANT_HITS.inc(),
+            None => VARIANT_MISSES.inc(),
+        }
+        rendered
+    }
+
+    /// Store the variant rendered at `address`, evicting the least recently
+    /// used ones to fit the memory budget.
+    ///
+    /// Variants larger than the whole budget are not stored.
+    pub(crate) fn insert(&self, address: String, rendered: RenderedGraph) {
+        let size = variant_size(&address, &rendered);
+        if size > self.max_bytes {
+            return;
+        }
+
+        let mut inner = self.inner.lock();
+        inner.remove(&address);
+        inner.evict(self.max_bytes.saturating_sub(size), &address);
+
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.lru.insert(tick, address.clone());
+        inner.bytes += size;
+        inner.entries.insert(
+            address,
+            Entry {
+                rendered,
+                size,
+                tick,
+            },
+        );
+        inner.update_metrics();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::plugins::prelude::*;
+    use commons::latency::TimingBreakdown;
+
+    fn rendered(graph_json: &str) -> RenderedGraph {
+        RenderedGraph {
+            content_type: String::new(),
+            graph_json: graph_json.to_string(),
+            stale_since: None,
+            plugin_timings: TimingBreakdown(vec![]),
+            cohort: None,
+            pin: None,
+            default_params: vec![],
+            capabilities: Default::default(),
+            gzip_body: Default::default(),
+        }
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        // Room for three variants of 1 byte with 1-byte addresses.
+        let store = VariantStore::new(6);
+        store.insert("a".to_string(), rendered("1"));
+        store.insert("b".to_string(), rendered("2"));
+        store.insert("c".to_string(), rendered("3"));
+        assert_eq!(store.get("a").unwrap().graph_json, "1");
+
+        store.insert("d".to_string(), rendered("4"));
+        assert!(store.get("b").is_none());
+        for (address, graph_json) in &[("a", "1"), ("c", "3"), ("d", "4")] {
+            assert_eq!(store.get(address).unwrap().graph_json, *graph_json);
+        }
+
+        // Replacing a variant does not evict others.
+        store.insert("a".to_string(), rendered("5"));
+        assert_eq!(store.get("a").unwrap().graph_json, "5");
+        assert!(store.get("c").is_some());
+        assert!(store.get("d").is_some());
+    }
+
+    #[test]
+    fn fit_memory_budget() {
+        let store = VariantStore::new(8);
+        store.insert("a".to_string(), rendered("123"));
+        store.insert("b".to_string(), rendered("123"));
+        assert!(store.get("a").is_some());
+
+        // Variants larger than the budget are not stored, nor evict others.
+        store.insert("c".to_string(), rendered("12345678"));
+        assert!(store.get("c").is_none());
+        assert!(store.get("a").is_some());
+
+        store.insert("c".to_string(), rendered("123456"));
+        assert!(store.get("a").is_none());
+        assert!(store.get("b").is_none());
+        assert!(store.get("c").is_some());
+        assert_eq!(store.inner.lock().bytes, 7);
+    }
+
+    #[test]
+    fn account_compressed_bodies() {
+        // Room for two uncompressed variants of 100 bytes with 1-byte addresses.
+        let store = VariantStore::new(220);
+        let graph_json = "0123456789".repeat(10);
+        store.insert("a".to_string(), rendered(&graph_json));
+        store.insert("b".to_string(), rendered(&graph_json));
+        assert_eq!(store.inner.lock().bytes, 202);
+
+        // Compressing a stored variant accounts for its compressed body on
+        // its next use, evicting the least recently used variants.
+        store.get("a").unwrap().gzip_body();
+        assert!(store.get("a").is_some());
+        assert!(store.get("b").is_none());
+        let gzip_len = store.get("a").unwrap().gzip_body().len() as u64;
+        assert_eq!(store.inner.lock().bytes, 101 + gzip_len);
+
+        // Compressed bodies are accounted for when stored.
+        let compressed = rendered(&graph_json);
+        compressed.gzip_body();
+        store.insert("c".to_string(), compressed);
+        assert!(store.get("a").is_none());
+        assert_eq!(store.inner.lock().bytes, 101 + gzip_len);
+    }
+
+    #[test]
+    fn address_by_content() -> Fallible<()> {
+        let store = VariantStore::new(DEFAULT_MAX_BYTES);
+        let plugins: &'static [BoxedPlugin] = &[];
+        let params = |channel: &str| vec![("channel".to_string(), channel.to_string())];
+
+        // Variants of unregistered chains are not stored.
+        assert_eq!(
+            store.address(plugins, "digest", &params("stable-4.14")),
+            None
+        );
+        store.register_chain(plugins, chain_digest(&[]));
+
+        let address = store.address(plugins, "digest", &params("stable-4.14"));
+        assert!(address.is_some());
+        assert_eq!(
+            address,
+            store.address(plugins, "digest", &params("stable-4.14"))
+        );
+        assert_ne!(
+            address,
+            store.address(plugins, "other", &params("stable-4.14"))
+        );
+        assert_ne!(
+            address,
+            store.address(plugins, "digest", &params("fast-4.14"))
+        );
+
+        // Chains are addressed by their settings, defaults included.
+        let channel_filter = |key_suffix: &str| {
+            plugin_config!(
+                ("name", ChannelFilterPlugin::PLUGIN_NAME),
+                ("key_suffix", key_suffix)
+            )
+        };
+        assert_eq!(
+            chain_digest(&[channel_filter("release.channels")?]),
+            chain_digest(&[plugin_config!(("name", ChannelFilterPlugin::PLUGIN_NAME))?])
+        );
+        assert_ne!(
+            chain_digest(&[channel_filter("release.channels")?]),
+            chain_digest(&[channel_filter("release.streams")?])
+        );
+
+        Ok(())
+    }
+}
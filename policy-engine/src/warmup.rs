@@ -0,0 +1,155 @@
+//! Warm-up of the caches before serving clients.
+//!
+//! After a rollout, the first wave of client requests would otherwise all
+//! hit cold caches: the upstream graph, the rendered graph variants and
+//! their compressed bodies. Once the first graph is available, and before
+//! the policy-engine reports being ready, the configured popular
+//! (channel, arch) variants are rendered and compressed, in order.
+//! Failures are logged and do not delay readiness further.
+
+use crate::graph;
+use crate::AppState;
+use commons::prelude_errors::*;
+use prometheus::{IntGauge, Registry};
+use std::time::Instant;
+
+lazy_static! {
+    static ref WARMUP_VARIANTS: IntGauge = IntGauge::new(
+        "graph_warmup_variants",
+        "Number of graph variants rendered by the warm-up on startup"
+    )
+    .unwrap();
+    static ref WARMUP_FAILURES: IntGauge = IntGauge::new(
+        "graph_warmup_failures",
+        "Number of graph variants which failed to render during the warm-up on startup"
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(WARMUP_VARIANTS.clone()))?;
+    registry.register(Box::new(WARMUP_FAILURES.clone()))?;
+    Ok(())
+}
+
+/// Graph variant rendered by the warm-up.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct WarmupVariant {
+    /// Channel of the variant.
+    pub channel: String,
+    /// Architecture of the variant, the default one if unset.
+    #[serde(default)]
+    pub arch: Option<String>,
+}
+
+impl WarmupVariant {
+    /// Return the query string of the variant.
+    fn query(&self) -> String {
+        let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+        serializer.append_pair("channel", &self.channel);
+        if let Some(arch) = &self.arch {
+            serializer.append_pair("arch", arch);
+        }
+        serializer.finish()
+    }
+}
+
+/// Render and compress `variants` in order, returning the number of variants rendered.
+pub(crate) async fn run(app_data: &AppState, variants: &[WarmupVariant]) -> usize {
+    if variants.is_empty() {
+        return 0;
+    }
+
+    info!("warming up {} graph variants", variants.len());
+    let start = Instant::now();
+    let mut rendered = 0;
+    let mut failed = 0;
+    for variant in variants {
+        let query = variant.query();
+        let deadline = app_data.deadline(None);
+        match graph::render_query(&query, graph::latest_content_type(), app_data, deadline).await {
+            Ok(graph) => {
+                graph.gzip_body();
+                rendered += 1;
+            }
+            Err(e) => {
+                warn!("failed to warm up graph variant '{}': {}", query, e);
+                failed += 1;
+            }
+        }
+    }
+    WARMUP_VARIANTS.set(rendered as i64);
+    WARMUP_FAILURES.set(failed as i64);
+    info!(
+        "warmed up {} graph variants in {:?}, {} failed",
+        rendered,
+        start.elapsed(),
+        failed
+    );
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::plugins::prelude::*;
+
+    #[test]
+    fn warm_up_variants() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let plugin_settings = vec![
+            plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", &format!("{}/warmup", mockito::server_url()))
+            )?,
+            plugin_config!(("name", ChannelFilterPlugin::PLUGIN_NAME))?,
+        ];
+        let plugins: &'static [BoxedPlugin] = Box::leak(
+            cincinnati::plugins::catalog::build_plugins(&plugin_settings, None)?.into_boxed_slice(),
+        );
+        let variants = crate::variants::VariantStore::new(crate::variants::DEFAULT_MAX_BYTES);
+        variants.register_chain(plugins, crate::variants::chain_digest(&plugin_settings));
+        let state = AppState {
+            plugins,
+            variants,
+            ..Default::default()
+        };
+        let _upstream = mockito::mock("GET", "/warmup")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"nodes":[{"version":"4.1.0","payload":"image/4.1.0","metadata":{
+                    "io.openshift.upgrades.graph.release.channels":"stable-4.1"}}],"edges":[]}"#,
+            )
+            .create();
+
+        let variant = |channel: &str| WarmupVariant {
+            channel: channel.to_string(),
+            arch: None,
+        };
+        assert_eq!(
+            rt.block_on(run(
+                &state,
+                &[variant("stable-4.1"), variant("not a channel")]
+            )),
+            1
+        );
+
+        // Warmed-up variants are served from the store, along with their compressed body.
+        let rendered = rt
+            .block_on(graph::render_query(
+                "channel=stable-4.1",
+                graph::latest_content_type(),
+                &state,
+                None,
+            ))
+            .map_err(|e| format_err!("{}", e))?;
+        assert_eq!(rendered.plugin_timings.0.len(), 1);
+        assert!(rendered.gzip_body.get().is_some());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+//! Discovery of the platform Prometheus API from inside a Kubernetes cluster.
+//!
+//! Pods run with a service account, whose token and CA certificates are
+//! mounted under `SERVICE_ACCOUNT_DIR`. On OpenShift, the platform Thanos
+//! querier is served at `DEFAULT_QUERIER_URL` with a certificate signed by
+//! the service CA, mounted as `service-ca.crt`, and accepts the token of
+//! service accounts allowed to view cluster metrics.
+//!
+//! Clients are switched to the discovered API by setting `IN_CLUSTER_ENV` to
+//! `true`. Projected service account tokens are rotated by the kubelet, so
+//! in-cluster clients read the token again for each request.
+
+use super::ClientBuilder;
+use anyhow::{bail, ensure, Context, Result as Fallible};
+use std::path::{Path, PathBuf};
+
+/// Directory where the service account credentials are mounted.
+pub static SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// URL of the platform Thanos querier service.
+pub static DEFAULT_QUERIER_URL: &str = "https://thanos-querier.openshift-monitoring.svc:9091";
+
+/// Environment variable set by Kubernetes in every container.
+static KUBERNETES_SERVICE_HOST_ENV: &str = "KUBERNETES_SERVICE_HOST";
+
+/// Environment variable switching clients to the in-cluster API when `true`.
+pub static IN_CLUSTER_ENV: &str = "PROM_IN_CLUSTER";
+
+/// File of the service account directory holding the token.
+static TOKEN_FILE: &str = "token";
+
+/// Files of the service account directory holding the CA certificates
+/// trusted for the querier, in order of preference.
+static CA_FILES: [&str; 2] = ["service-ca.crt", "ca.crt"];
+
+/// Connection settings of the platform Prometheus API.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InCluster {
+    /// Base URL of the API.
+    pub api_base: String,
+    /// File holding the service account token.
+    pub token_path: PathBuf,
+    /// CA certificates trusted for the API, if mounted.
+    pub ca_path: Option<PathBuf>,
+}
+
+impl InCluster {
+    /// Discover the platform Prometheus API, if running in a Kubernetes cluster.
+    pub fn discover() -> Fallible<Option<Self>> {
+        if std::env::var_os(KUBERNETES_SERVICE_HOST_ENV).is_none() {
+            return Ok(None);
+        }
+
+        Self::from_service_account(Path::new(SERVICE_ACCOUNT_DIR), DEFAULT_QUERIER_URL).map(Some)
+    }
+
+    /// Discover the platform Prometheus API if enabled by `IN_CLUSTER_ENV`.
+    ///
+    /// Fails if enabled outside of a Kubernetes cluster.
+    pub fn from_env() -> Fallible<Option<Self>> {
+        match std::env::var(IN_CLUSTER_ENV) {
+            Ok(value) if value == "true" => match Self::discover()? {
+                Some(in_cluster) => Ok(Some(in_cluster)),
+                None => bail!("{} is set outside of a Kubernetes cluster", IN_CLUSTER_ENV),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Build the connection settings of the API at `api_base` from the
+    /// service account credentials mounted in `dir`.
+    pub fn from_service_account(dir: &Path, api_base: &str) -> Fallible<Self> {
+        let token_path = dir.join(TOKEN_FILE);
+        read_token(&token_path)?;
+
+        let ca_path = CA_FILES
+            .iter()
+            .map(|file| dir.join(file))
+            .find(|path| path.is_file());
+
+        Ok(Self {
+            api_base: api_base.to_string(),
+            token_path,
+            ca_path,
+        })
+    }
+}
+
+/// Read the service account token from `token_path`.
+pub(crate) fn read_token(token_path: &Path) -> Fallible<String> {
+    let token = std::fs::read_to_string(token_path)
+        .context(format!("reading service account token {:?}", token_path))?;
+    let token = token.trim().to_string();
+    ensure!(
+        !token.is_empty(),
+        "empty service account token {:?}",
+        token_path
+    );
+    Ok(token)
+}
+
+impl ClientBuilder {
+    /// Configure the endpoint, token and CA certificates of the platform
+    /// Prometheus API, as discovered by `in_cluster`, reading the token for
+    /// each request.
+    pub fn in_cluster(self, in_cluster: InCluster) -> Fallible<Self> {
+        let hclient = commons::http::HttpClientBuilder::new()
+            .ca_bundle(in_cluster.ca_path)
+            .build_blocking()?;
+
+        Ok(self
+            .api_base(Some(in_cluster.api_base))
+            .access_token_path(Some(in_cluster.token_path))
+            .http_client(Some(hclient)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discover_from_service_account() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        assert!(InCluster::from_service_account(dir.path(), DEFAULT_QUERIER_URL).is_err());
+
+        std::fs::write(dir.path().join("token"), "sa-token\n")?;
+        std::fs::write(dir.path().join("ca.crt"), "")?;
+        let in_cluster = InCluster::from_service_account(dir.path(), DEFAULT_QUERIER_URL)?;
+        assert_eq!(in_cluster.api_base, DEFAULT_QUERIER_URL);
+        assert_eq!(in_cluster.token_path, dir.path().join("token"));
+        assert_eq!(in_cluster.ca_path, Some(dir.path().join("ca.crt")));
+
+        // The service CA signs the certificates of in-cluster services.
+        std::fs::write(dir.path().join("service-ca.crt"), "")?;
+        let in_cluster = InCluster::from_service_account(dir.path(), DEFAULT_QUERIER_URL)?;
+        assert_eq!(in_cluster.ca_path, Some(dir.path().join("service-ca.crt")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reread_rotated_tokens() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("token"), "sa-token\n")?;
+        let in_cluster = InCluster::from_service_account(dir.path(), DEFAULT_QUERIER_URL)?;
+        let client = super::super::Client::builder()
+            .in_cluster(in_cluster)?
+            .build()?;
+        let authorization = || -> Fallible<String> {
+            let request = client
+                .new_request(reqwest::Method::GET, "/api/v1/query", None)?
+                .build()?;
+            Ok(request.headers()["Authorization"].to_str()?.to_string())
+        };
+        assert_eq!(authorization()?, "Bearer sa-token");
+
+        std::fs::write(dir.path().join("token"), "rotated-token\n")?;
+        assert_eq!(authorization()?, "Bearer rotated-token");
+
+        std::fs::write(dir.path().join("token"), "")?;
+        assert!(authorization().is_err());
+
+        Ok(())
+    }
+}
@@ -2,7 +2,10 @@
 
 use anyhow::{bail, Result as Fallible};
 use reqwest;
+use std::path::PathBuf;
+use std::time::Instant;
 
+pub mod in_cluster;
 pub mod queries;
 
 /// Client to make outgoing API requests
@@ -14,9 +17,13 @@ pub struct Client {
     hclient: reqwest::blocking::Client,
     /// Authentication token.
     token: Option<String>,
+    /// File holding the authentication token, read for each request.
+    token_path: Option<PathBuf>,
     #[allow(dead_code)]
     /// Trust all certs
     danger_accept_invalid_certs: Option<bool>,
+    /// Budget the queries are acquired from, if any.
+    request_budget: Option<commons::budget::RequestBudget>,
 }
 
 impl Client {
@@ -26,18 +33,29 @@ impl Client {
     }
 
     /// Return a request builder with base URL and parameters set.
+    ///
+    /// The request is acquired from the budget of the client, if any, waiting
+    /// at most until `deadline`.
     pub(crate) fn new_request<S: AsRef<str>>(
         &self,
         method: reqwest::Method,
         url_suffix: S,
+        deadline: Option<Instant>,
     ) -> Fallible<reqwest::blocking::RequestBuilder> {
         let url = self.api_base.clone().join(url_suffix.as_ref())?;
         trace!("url: '{}'", url);
+        if let (Some(budget), Some(host)) = (&self.request_budget, url.host_str()) {
+            budget.acquire_blocking(host, deadline)?;
+        }
         let builder = {
             let plain = self.hclient.request(method, url);
-            match self.token {
+            let token = match &self.token_path {
+                Some(token_path) => Some(in_cluster::read_token(token_path)?),
+                None => self.token.clone(),
+            };
+            match token {
                 None => plain,
-                Some(ref token) => {
+                Some(token) => {
                     let bearer_token = format!("Bearer {}", token);
                     plain.header("Authorization", bearer_token)
                 }
@@ -53,7 +71,9 @@ pub struct ClientBuilder {
     api_base: Option<String>,
     hclient: Option<reqwest::blocking::Client>,
     token: Option<String>,
+    token_path: Option<PathBuf>,
     danger_accept_invalid_certs: Option<bool>,
+    request_budget: Option<commons::budget::RequestBudget>,
 }
 
 impl ClientBuilder {
@@ -71,6 +91,14 @@ impl ClientBuilder {
         builder
     }
 
+    /// Set (or reset) the file holding the access token, read for each
+    /// request instead of the access token.
+    pub fn access_token_path(self, token_path: Option<PathBuf>) -> Self {
+        let mut builder = self;
+        builder.token_path = token_path;
+        builder
+    }
+
     /// Set (or reset) the base API endpoint URL to use.
     pub fn api_base(self, api_base: Option<String>) -> Self {
         let mut builder = self;
@@ -85,13 +113,20 @@ impl ClientBuilder {
         builder
     }
 
+    /// Set (or reset) the budget the queries are acquired from.
+    pub fn request_budget(self, request_budget: Option<commons::budget::RequestBudget>) -> Self {
+        let mut builder = self;
+        builder.request_budget = request_budget;
+        builder
+    }
+
     /// Build a client with specified parameters.
     pub fn build(self) -> Fallible<Client> {
         let hclient = match self.hclient {
             Some(client) => client,
-            None => reqwest::blocking::ClientBuilder::new()
-                .danger_accept_invalid_certs(self.danger_accept_invalid_certs.unwrap_or_default())
-                .build()?,
+            None => commons::http::HttpClientBuilder::new()
+                .accept_invalid_certs(self.danger_accept_invalid_certs.unwrap_or_default())
+                .build_blocking()?,
         };
         let api_base = match self.api_base {
             Some(ref base) => reqwest::Url::parse(base)?,
@@ -102,6 +137,8 @@ impl ClientBuilder {
             danger_accept_invalid_certs: self.danger_accept_invalid_certs,
             hclient,
             token: self.token,
+            token_path: self.token_path,
+            request_budget: self.request_budget,
         };
 
         Ok(client)
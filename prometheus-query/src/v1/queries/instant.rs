@@ -1,9 +1,9 @@
 //! Impelement instant queries
 
 use super::*;
-use anyhow::Result as Fallible;
+use anyhow::{bail, Result as Fallible};
 use reqwest;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
 pub static INSTANT_QUERY_PATH_SUFFIX: &str = "/api/v1/query";
 
@@ -17,7 +17,35 @@ impl Client {
         time: Option<chrono::DateTime<chrono::Utc>>,
         timeout: Option<Duration>,
     ) -> Fallible<QueryResult> {
-        self.new_request(reqwest::Method::GET, INSTANT_QUERY_PATH_SUFFIX)
+        self.send_query(query, time, timeout, None)
+    }
+
+    /// Sends the given query to the remote API like `query`, bounded by `deadline`.
+    ///
+    /// The time left until the deadline bounds both the evaluation of the
+    /// query and the request, and queries past the deadline are not sent.
+    pub fn query_until(
+        &self,
+        query: String,
+        time: Option<chrono::DateTime<chrono::Utc>>,
+        deadline: SystemTime,
+    ) -> Fallible<QueryResult> {
+        let remaining = match deadline.duration_since(SystemTime::now()) {
+            Ok(remaining) if !remaining.is_zero() => remaining,
+            _ => bail!("deadline exceeded before sending query"),
+        };
+        self.send_query(query, time, Some(remaining), Some(remaining))
+    }
+
+    fn send_query(
+        &self,
+        query: String,
+        time: Option<chrono::DateTime<chrono::Utc>>,
+        timeout: Option<Duration>,
+        request_timeout: Option<Duration>,
+    ) -> Fallible<QueryResult> {
+        let deadline = request_timeout.map(|timeout| Instant::now() + timeout);
+        self.new_request(reqwest::Method::GET, INSTANT_QUERY_PATH_SUFFIX, deadline)
             .and_then(move |request_builder| {
                 let mut query = vec![("query", query)];
 
@@ -26,7 +54,12 @@ impl Client {
                 }
 
                 if let Some(timeout) = timeout {
-                    query.push(("timeout", format!("{}s", timeout.as_secs())));
+                    query.push(("timeout", format!("{}ms", timeout.as_millis())));
+                };
+
+                let request_builder = match request_timeout {
+                    Some(request_timeout) => request_builder.timeout(request_timeout),
+                    None => request_builder,
                 };
 
                 trace!("sending query '{:?}'", &query);
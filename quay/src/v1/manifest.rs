@@ -77,7 +77,7 @@ impl Client {
             }
         })?;
 
-        let resp = req.send().await?;
+        let resp = self.send(req).await?;
         let json = resp.json::<Labels>().await?;
 
         Ok(json.labels)
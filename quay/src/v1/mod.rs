@@ -1,5 +1,7 @@
-use anyhow::Result as Fallible;
+use anyhow::{Context, Result as Fallible};
 use reqwest;
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock};
 
 mod manifest;
 
@@ -16,7 +18,47 @@ pub struct Client {
     /// Asynchronous reqwest client.
     hclient: reqwest::Client,
     /// Authentication token.
-    token: Option<String>,
+    token: Token,
+}
+
+/// Authentication token of a client.
+#[derive(Clone, Debug)]
+enum Token {
+    /// Token set at build time.
+    Static(Option<String>),
+    /// Token read from a file, which is read again when the token is rejected.
+    File {
+        path: PathBuf,
+        token: Arc<RwLock<String>>,
+    },
+}
+
+impl Token {
+    /// Return the current token, if any.
+    fn current(&self) -> Option<String> {
+        match self {
+            Token::Static(token) => token.clone(),
+            Token::File { token, .. } => {
+                Some(token.read().unwrap_or_else(PoisonError::into_inner).clone())
+            }
+        }
+    }
+
+    /// Read the token file again after `rejected` was rejected, returning
+    /// the new token if it was rotated.
+    fn reload(&self, rejected: Option<&str>) -> Fallible<Option<String>> {
+        let (path, token) = match self {
+            Token::Static(_) => return Ok(None),
+            Token::File { path, token } => (path, token),
+        };
+
+        let reloaded = crate::read_credentials(path).context("could not reload credentials")?;
+        if Some(reloaded.as_str()) == rejected {
+            return Ok(None);
+        }
+        *token.write().unwrap_or_else(PoisonError::into_inner) = reloaded.clone();
+        Ok(Some(reloaded))
+    }
 }
 
 impl Client {
@@ -26,23 +68,42 @@ impl Client {
     }
 
     /// Return a request builder with base URL and parameters set.
+    ///
+    /// The request is authenticated when sent with `send`.
     pub(crate) fn new_request<S: AsRef<str>>(
         &self,
         method: reqwest::Method,
         url_suffix: S,
     ) -> Fallible<reqwest::RequestBuilder> {
         let url = self.api_base.clone().join(url_suffix.as_ref())?;
-        let builder = {
-            let plain = self.hclient.request(method, url);
-            match self.token {
-                None => plain,
-                Some(ref token) => {
-                    let bearer_token = format!("Bearer {}", token);
-                    plain.header("Authorization", bearer_token)
-                }
-            }
-        };
-        Ok(builder)
+        Ok(self.hclient.request(method, url))
+    }
+
+    /// Send a request, authenticated with the current token.
+    ///
+    /// If the request is unauthorized and the token is read from a file, the
+    /// file is read again and, if the token was rotated, the request is
+    /// retried once with the new token.
+    pub(crate) async fn send(&self, req: reqwest::RequestBuilder) -> Fallible<reqwest::Response> {
+        let retry = req.try_clone();
+        let token = self.token.current();
+        let resp = authenticate(req, token.as_deref()).send().await?;
+        if resp.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(resp);
+        }
+
+        match (retry, self.token.reload(token.as_deref())?) {
+            (Some(retry), Some(rotated)) => Ok(authenticate(retry, Some(&rotated)).send().await?),
+            _ => Ok(resp),
+        }
+    }
+}
+
+/// Add the bearer `token`, if any, to a request.
+fn authenticate(req: reqwest::RequestBuilder, token: Option<&str>) -> reqwest::RequestBuilder {
+    match token {
+        None => req,
+        Some(token) => req.header("Authorization", format!("Bearer {}", token)),
     }
 }
 
@@ -52,6 +113,7 @@ pub struct ClientBuilder {
     api_base: Option<String>,
     hclient: Option<reqwest::Client>,
     token: Option<String>,
+    token_path: Option<PathBuf>,
 }
 
 impl ClientBuilder {
@@ -69,6 +131,16 @@ impl ClientBuilder {
         builder
     }
 
+    /// Set (or reset) the path of a file to read the access token from.
+    ///
+    /// The file is read again whenever the token is rejected, so that it can
+    /// be rotated without restarting. It takes precedence over `access_token`.
+    pub fn access_token_path(self, token_path: Option<PathBuf>) -> Self {
+        let mut builder = self;
+        builder.token_path = token_path;
+        builder
+    }
+
     /// Set (or reset) the base API endpoint URL to use.
     pub fn api_base(self, api_base: Option<String>) -> Self {
         let mut builder = self;
@@ -86,10 +158,17 @@ impl ClientBuilder {
             Some(ref base) => reqwest::Url::parse(base)?,
             None => reqwest::Url::parse(DEFAULT_API_BASE)?,
         };
+        let token = match self.token_path {
+            Some(path) => Token::File {
+                token: Arc::new(RwLock::new(crate::read_credentials(&path)?)),
+                path,
+            },
+            None => Token::Static(self.token),
+        };
         let quay_client = Client {
             api_base,
             hclient,
-            token: self.token,
+            token,
         };
         Ok(quay_client)
     }
@@ -101,6 +180,7 @@ impl Default for ClientBuilder {
             api_base: Some(DEFAULT_API_BASE.to_string()),
             hclient: None,
             token: None,
+            token_path: None,
         }
     }
 }
@@ -49,7 +49,7 @@ impl Client {
                 .new_request(Method::GET, endpoint)?
                 .query(&[("onlyActiveTags", actives_only)]);
 
-            let resp = req.send().await?;
+            let resp = self.send(req).await?;
             let paginated_tags = resp.json::<PaginatedTags>().await?.tags;
             for tag in paginated_tags {
                 yield Ok(tag);